@@ -15,6 +15,7 @@ pub const PAGE_SIZE_BITS: usize = 0xc;
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT: usize = TRAMPOLINE - PAGE_SIZE;
 pub const USER_TRAP_BUFFER: usize = TRAP_CONTEXT - PAGE_SIZE;
+pub const CONSOLE_RING_BUFFER: usize = USER_TRAP_BUFFER - PAGE_SIZE;
 
 #[cfg(feature = "board_qemu")]
 pub const CLOCK_FREQ: usize = 12500000;
@@ -23,4 +24,10 @@ pub const CLOCK_FREQ: usize = 12500000;
 pub const CLOCK_FREQ: usize = 10_000_000;
 
 pub const CPU_NUM: usize = 4;
+/// How long a process may leave a device claimed, until it changes it
+/// with `sys_set_ext_int_watchdog`.
+pub const EXT_INT_WATCHDOG_US: usize = 1_000_000;
+/// How many devices a process may hold at once, until it lowers it with
+/// `sys_set_ext_int_limit`.
+pub const EXT_INT_CLAIM_LIMIT: usize = 3;
 pub const TRACE_SIZE: usize = 0x1000_0000; // 256M
@@ -1,4 +1,4 @@
-use crate::sbi::console_putchar;
+use crate::uart::{console_is_sync, console_write};
 use core::fmt::{self, Write};
 
 use alloc::sync::Arc;
@@ -9,9 +9,7 @@ struct Stderr;
 
 impl Write for Stderr {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        for c in s.chars() {
-            console_putchar(c as usize);
-        }
+        console_write(s.as_bytes());
         Ok(())
     }
 }
@@ -34,12 +32,20 @@ macro_rules! colorize {
     };
 }
 
+/// Formats `args` under one lock, so lines of several harts don't
+/// interleave. After a panic the lock isn't waited for, the hart that
+/// panicked may hold it.
+pub fn print(args: fmt::Arguments) {
+    if console_is_sync() {
+        let _ = Stderr.write_fmt(args);
+    } else {
+        STDERR.lock().write_fmt(args).unwrap();
+    }
+}
+
 /// Use colorize! to print with color
 pub fn print_colorized(args: fmt::Arguments, foreground_color: u8, background_color: u8) {
-    STDERR
-        .lock()
-        .write_fmt(colorize!(args, foreground_color, background_color))
-        .unwrap();
+    print(colorize!(args, foreground_color, background_color));
 }
 
 #[macro_export]
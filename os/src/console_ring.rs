@@ -0,0 +1,150 @@
+use crate::mm::PhysPageNum;
+use crate::trap::{push_trap_record, UserTrapRecord};
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use core::sync::atomic::{AtomicU32, Ordering};
+use lazy_static::*;
+use spin::Mutex;
+
+/// Serial 0 is the kernel console, it is never claimed by users.
+pub const CONSOLE_SERIAL_ID: usize = 0;
+pub const CONSOLE_RING_SIZE: usize = 2048;
+pub const MAX_CONSOLE_READERS: usize = 8;
+pub const MAX_CONSOLE_ERROR_LISTENERS: usize = 8;
+/// Tag of the software interrupt message sent to error listeners, the
+/// `LSR_*` error bits seen are in the low byte.
+pub const CONSOLE_ERROR_MSG: usize = 0x4c53_0000;
+
+/// A page shared with one reader, the user library has the same layout.
+/// `head` and `tail` run freely and wrap, the kernel only moves `head`
+/// and the reader only moves `tail`.
+#[repr(C)]
+pub struct ConsoleRing {
+    pub head: AtomicU32,
+    pub tail: AtomicU32,
+    /// Bytes lost because the ring was full.
+    pub dropped: AtomicU32,
+    /// Set by the reader before it sleeps, cleared by the kernel when it
+    /// rings it with a user external interrupt record.
+    pub doorbell: AtomicU32,
+    pub data: [u8; CONSOLE_RING_SIZE],
+}
+
+impl ConsoleRing {
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire) == self.tail.load(Ordering::Acquire)
+    }
+
+    fn push(&mut self, ch: u8) {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+        if head.wrapping_sub(tail) as usize >= CONSOLE_RING_SIZE {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.data[head as usize % CONSOLE_RING_SIZE] = ch;
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+    }
+}
+
+lazy_static! {
+    /// Pages of the processes reading the console through a ring.
+    static ref CONSOLE_READERS: Mutex<BTreeMap<usize, PhysPageNum>> =
+        Mutex::new(BTreeMap::new());
+    /// Processes told about line errors on the console.
+    static ref ERROR_LISTENERS: Mutex<BTreeSet<usize>> = Mutex::new(BTreeSet::new());
+}
+
+/// Registers the ring page of `pid`, it must already be mapped and zeroed.
+pub fn register(pid: usize, ppn: PhysPageNum) -> Result<(), isize> {
+    let mut readers = CONSOLE_READERS.lock();
+    if readers.contains_key(&pid) {
+        return Err(-1);
+    }
+    if readers.len() >= MAX_CONSOLE_READERS {
+        return Err(-2);
+    }
+    readers.insert(pid, ppn);
+    Ok(())
+}
+
+/// Stops filling the ring of `pid`. Must be called before its page is
+/// freed, on close, exec and exit.
+pub fn unregister(pid: usize) -> bool {
+    CONSOLE_READERS.lock().remove(&pid).is_some()
+}
+
+pub fn ring_of(pid: usize) -> Option<&'static ConsoleRing> {
+    CONSOLE_READERS
+        .lock()
+        .get(&pid)
+        .map(|ppn| &*ppn.get_mut::<ConsoleRing>())
+}
+
+/// Moves console input from the kernel buffer into every ring. Returns
+/// false and leaves the input to the kernel if nobody reads a ring.
+pub fn fill(rx_buffer: &mut VecDeque<u8>) -> bool {
+    let readers = CONSOLE_READERS.lock();
+    if readers.is_empty() {
+        return false;
+    }
+    for ch in rx_buffer.drain(..) {
+        for ppn in readers.values() {
+            ppn.get_mut::<ConsoleRing>().push(ch);
+        }
+    }
+    true
+}
+
+/// Rings the readers waiting on an empty ring that has input now.
+pub fn ring_doorbells(irq: u16) {
+    let mut waiting: heapless::Vec<usize, MAX_CONSOLE_READERS> = heapless::Vec::new();
+    for (pid, ppn) in CONSOLE_READERS.lock().iter() {
+        let ring = ppn.get_mut::<ConsoleRing>();
+        if !ring.is_empty() && ring.doorbell.swap(0, Ordering::AcqRel) != 0 {
+            let _ = waiting.push(*pid);
+        }
+    }
+    // not under the readers lock, exit takes it with the task locked
+    for pid in waiting {
+        let _ = push_trap_record(
+            pid,
+            UserTrapRecord {
+                // User External Interrupt
+                cause: 8,
+                message: irq as usize,
+            },
+        );
+    }
+}
+
+pub fn listen_errors(pid: usize) -> Result<(), isize> {
+    let mut listeners = ERROR_LISTENERS.lock();
+    if !listeners.contains(&pid) && listeners.len() >= MAX_CONSOLE_ERROR_LISTENERS {
+        return Err(-2);
+    }
+    listeners.insert(pid);
+    Ok(())
+}
+
+/// Must be called on exec and exit, the pid may be reused.
+pub fn unlisten_errors(pid: usize) -> bool {
+    ERROR_LISTENERS.lock().remove(&pid)
+}
+
+/// Sends every listener a software interrupt with the error bits of `lsr`,
+/// once per burst the driver drained.
+pub fn notify_line_errors(lsr: u8) {
+    let listeners: heapless::Vec<usize, MAX_CONSOLE_ERROR_LISTENERS> =
+        ERROR_LISTENERS.lock().iter().copied().collect();
+    // not under the listeners lock, exit takes it with the task locked
+    for pid in listeners {
+        let _ = push_trap_record(
+            pid,
+            UserTrapRecord {
+                // User Software Interrupt, from the kernel
+                cause: 0,
+                message: CONSOLE_ERROR_MSG | lsr as usize,
+            },
+        );
+    }
+}
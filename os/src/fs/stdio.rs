@@ -1,25 +1,34 @@
 use super::File;
+use crate::console_ring::CONSOLE_SERIAL_ID;
 use crate::mm::UserBuffer;
-use crate::print;
-use crate::uart::{serial_getchar, serial_putchar};
-use core::fmt::{self, Write};
+use crate::task::suspend_current_and_run_next;
+use crate::uart::{console_write, serial_getchar};
+use core::fmt;
 
 pub struct Stdin;
 
 pub struct Stdout;
 
 impl File for Stdin {
-    fn read(&self, mut user_buf: UserBuffer) -> Result<usize, isize> {
-        assert_eq!(user_buf.len(), 1);
-        // busy loop
-        if let Ok(ch) = serial_getchar(0) {
-            unsafe {
-                user_buf.buffers[0].as_mut_ptr().write_volatile(ch);
+    /// Waits for the first byte, yielding until the rx interrupt brings
+    /// one, then takes what else is buffered.
+    fn read(&self, user_buf: UserBuffer) -> Result<usize, isize> {
+        let mut read_cnt = 0;
+        for ptr in user_buf.into_iter() {
+            let ch = loop {
+                match serial_getchar(CONSOLE_SERIAL_ID) {
+                    Ok(ch) => break Some(ch),
+                    Err(_) if read_cnt > 0 => break None,
+                    Err(_) => suspend_current_and_run_next(),
+                }
+            };
+            match ch {
+                Some(ch) => unsafe { ptr.write_volatile(ch) },
+                None => break,
             }
-            Ok(1)
-        } else {
-            Err(-1)
+            read_cnt += 1;
         }
+        Ok(read_cnt)
     }
     fn write(&self, _user_buf: UserBuffer) -> Result<usize, isize> {
         panic!("Cannot write to stdin!");
@@ -32,24 +41,15 @@ impl File for Stdout {
     }
     fn write(&self, user_buf: UserBuffer) -> Result<usize, isize> {
         for buffer in user_buf.buffers.iter() {
-            print!("{}", core::str::from_utf8(*buffer).unwrap());
+            console_write(buffer);
         }
         Ok(user_buf.len())
     }
 }
 
-impl Write for Stdout {
-    fn write_str(&mut self, s: &str) -> fmt::Result {
-        for c in s.chars() {
-            let _ = serial_putchar(0, c as u8);
-        }
-        Ok(())
-    }
-}
-
 #[allow(dead_code)]
 pub fn print(args: fmt::Arguments) {
-    Stdout.write_fmt(args).unwrap();
+    crate::console::print(args);
 }
 
 #[macro_export]
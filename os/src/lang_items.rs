@@ -1,9 +1,11 @@
 use crate::task::hart_id;
+use crate::uart::{console_set_sync, console_wait_idle};
 use crate::{console::ANSICON, sbi::shutdown};
 use core::panic::PanicInfo;
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    console_set_sync();
     if let Some(location) = info.location() {
         println_colorized!(
             "[kernel {}] Panicked at {}:{} {}",
@@ -23,5 +25,6 @@ fn panic(info: &PanicInfo) -> ! {
             info.message().unwrap()
         );
     }
+    console_wait_idle();
     shutdown()
 }
@@ -19,6 +19,7 @@ use core::arch::{asm, global_asm};
 #[macro_use]
 mod console;
 mod config;
+mod console_ring;
 #[macro_use]
 mod fs;
 mod lang_items;
@@ -395,6 +395,13 @@ impl MemorySet {
         }
     }
 
+    /// Whether `start..start + len` is already mapped, e.g. inherited on fork.
+    pub fn is_mmio_mapped(&self, start: usize, len: usize) -> bool {
+        let start_va = VirtAddr::from(start).floor().into();
+        let end_va = VirtAddr::from(start + len).ceil().into();
+        self.is_mapped_area(start_va, end_va)
+    }
+
     #[allow(unused)]
     pub fn mmio_unmap(&mut self, start: usize, len: usize) -> Result<isize, isize> {
         let mut start_va: VirtAddr = VirtAddr::from(start);
@@ -1,6 +1,8 @@
+use crate::config::CPU_NUM;
 use crate::trace::{push_trace, S_EXT_INTR_ENTER, S_EXT_INTR_EXIT};
-use crate::trap::{push_trap_record, UserTrapRecord, USER_EXT_INT_MAP};
+use crate::trap::{forward_ext_int, USER_EXT_INT_MAP, WAITED_EXT_INT_MAP};
 use crate::uart;
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
 use rv_plic::{Priority, PLIC};
 
 #[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
@@ -61,25 +63,109 @@ pub fn init_hart(hart_id: usize) {
     Plic::set_threshold(get_context(hart_id, 'M'), Priority::never());
 }
 
+/// Reads the enable bit back, sources a context can't take read as 0.
+pub fn is_enabled(context: usize, irq: u16) -> bool {
+    const ENABLE_BASE: usize = PLIC_BASE + 0x2000;
+    const ENABLE_PER_CONTEXT: usize = 0x80;
+    let word = ENABLE_BASE + context * ENABLE_PER_CONTEXT + (irq as usize / 32) * 4;
+    let bits = unsafe { (word as *const u32).read_volatile() };
+    bits & (1 << (irq % 32)) != 0
+}
+
+pub const PLIC_MAX_PRIORITY: u32 = (1 << PLIC_PRIORITY_BIT) - 1;
+/// What `init` gives the serial sources.
+pub const PLIC_DEFAULT_PRIORITY: u32 = 1;
+
+pub fn set_source_priority(irq: u16, priority: u32) {
+    let reg = PLIC_BASE + irq as usize * 4;
+    unsafe { (reg as *mut u32).write_volatile(priority) }
+}
+
+pub fn set_context_threshold(context: usize, threshold: u32) {
+    const THRESHOLD_BASE: usize = PLIC_BASE + 0x20_0000;
+    const THRESHOLD_PER_CONTEXT: usize = 0x1000;
+    let reg = THRESHOLD_BASE + context * THRESHOLD_PER_CONTEXT;
+    unsafe { (reg as *mut u32).write_volatile(threshold) }
+}
+
+/// Routes `irq` to the S context of every hart, or masks it there.
+pub fn set_kernel_enable(irq: u16, enable: bool) {
+    for hart_id in 0..CPU_NUM {
+        if enable {
+            Plic::enable(get_context(hart_id, 'S'), irq);
+        } else {
+            Plic::disable(get_context(hart_id, 'S'), irq);
+        }
+    }
+}
+
+/// Sources with delivery counters, covers the serials of both boards.
+pub const MAX_COUNTED_IRQ: usize = 32;
+
+/// What the kernel did with one source. Claims and completes in U mode
+/// don't go through the kernel and are not counted.
+pub struct ExtIntCounters {
+    /// Pending, and enabled for the S context of a hart taking an
+    /// external interrupt. Every hart that sees it counts it.
+    pub fired: AtomicUsize,
+    /// Claimed in S mode.
+    pub claimed: AtomicUsize,
+    /// Forwarded to the owner's user trap queue.
+    pub delivered: AtomicUsize,
+    /// Completed in S mode after a claim.
+    pub completed: AtomicUsize,
+}
+
+const NO_COUNTS: ExtIntCounters = ExtIntCounters {
+    fired: AtomicUsize::new(0),
+    claimed: AtomicUsize::new(0),
+    delivered: AtomicUsize::new(0),
+    completed: AtomicUsize::new(0),
+};
+pub static EXT_INT_COUNTERS: [ExtIntCounters; MAX_COUNTED_IRQ] = [NO_COUNTS; MAX_COUNTED_IRQ];
+
+fn count(irq: u16, counter: impl Fn(&ExtIntCounters) -> &AtomicUsize) {
+    if let Some(counters) = EXT_INT_COUNTERS.get(irq as usize) {
+        counter(counters).fetch_add(1, Relaxed);
+    }
+}
+
+/// Counts the sources pending for `context` before it claims any.
+fn count_fired(context: usize) {
+    const PENDING_BASE: usize = PLIC_BASE + 0x1000;
+    for word in 0..MAX_COUNTED_IRQ / 32 {
+        let pending = unsafe { ((PENDING_BASE + word * 4) as *const u32).read_volatile() };
+        for bit in 0..32 {
+            let irq = (word * 32 + bit) as u16;
+            if pending & (1 << bit) != 0 && is_enabled(context, irq) {
+                count(irq, |c| &c.fired);
+            }
+        }
+    }
+}
+
 pub fn handle_external_interrupt(hart_id: usize) {
     let context = get_context(hart_id, 'S');
+    count_fired(context);
     while let Some(irq) = Plic::claim(context) {
         push_trace(S_EXT_INTR_ENTER + irq as usize);
+        count(irq, |c| &c.claimed);
+        if let Some(fired) = WAITED_EXT_INT_MAP.lock().get_mut(&irq) {
+            // the waiter drains the device, keep it masked until it waits again
+            *fired = true;
+            set_kernel_enable(irq, false);
+            Plic::complete(context, irq);
+            count(irq, |c| &c.completed);
+            push_trace(S_EXT_INTR_EXIT + irq as usize);
+            continue;
+        }
         let mut can_user_handle = false;
         let uei_map = USER_EXT_INT_MAP.lock();
         if let Some(pid) = uei_map.get(&irq).cloned() {
             trace!("[PLIC] irq {:?} mapped to pid {:?}", irq, pid);
             drop(uei_map); // avoid deadlock with sys_set_ext_int_enable
-            if push_trap_record(
-                pid,
-                UserTrapRecord {
-                    // User External Interrupt
-                    cause: 8,
-                    message: irq as usize,
-                },
-            )
-            .is_ok()
-            {
+            if forward_ext_int(pid, irq).is_ok() {
+                count(irq, |c| &c.delivered);
                 can_user_handle = true;
             }
             // prioritize_task(*pid);
@@ -101,6 +187,7 @@ pub fn handle_external_interrupt(hart_id: usize) {
                 }
             }
             Plic::complete(context, irq);
+            count(irq, |c| &c.completed);
         }
         push_trace(S_EXT_INTR_EXIT + irq as usize);
     }
@@ -1,3 +1,4 @@
+use alloc::vec::Vec;
 use core::cmp::min;
 
 use crate::fs::{make_pipe, File};
@@ -41,6 +42,47 @@ pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
     }
 }
 
+/// Most buffers one `sys_writev` takes.
+const IOV_MAX: usize = 16;
+
+/// `sys_write` of `iovcnt` buffers in one call to the file, `iov` points
+/// to pairs of a base address and a length. Returns the bytes written, -5
+/// if `iovcnt` is above `IOV_MAX`.
+pub fn sys_writev(fd: usize, iov: *const usize, iovcnt: usize) -> isize {
+    if iovcnt > IOV_MAX {
+        return -5;
+    }
+    let token = current_user_token();
+    let task = current_task().unwrap();
+    let inner = task.acquire_inner_lock();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    if let Some(file) = &inner.fd_table[fd] {
+        push_trace(TRACE_SYSCALL_WRITE_FIND_FD + fd);
+        let file = file.clone();
+        // release Task lock manually to avoid deadlock
+        drop(inner);
+        let mut buffers = Vec::new();
+        for i in 0..iovcnt {
+            let base = *translated_refmut(token, unsafe { iov.add(2 * i) as *mut usize });
+            let len = *translated_refmut(token, unsafe { iov.add(2 * i + 1) as *mut usize });
+            match translated_byte_buffer(token, base as *const u8, len) {
+                Ok(parts) => buffers.extend(parts),
+                Err(_) => return -3,
+            }
+        }
+        let res = match file.write(UserBuffer::new(buffers)) {
+            Ok(write_len) => write_len as isize,
+            Err(_) => -2,
+        };
+        push_trace((TRACE_SYSCALL_WRITE_RES as isize + res) as usize);
+        res
+    } else {
+        -4
+    }
+}
+
 pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
     if fd == 3 || fd == 4 || fd == 0 || fd == 1 {
         // debug!("sys_read {} {}", fd, len);
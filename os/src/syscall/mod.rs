@@ -2,6 +2,7 @@ const SYSCALL_CLOSE: usize = 57;
 const SYSCALL_PIPE: usize = 59;
 const SYSCALL_READ: usize = 63;
 const SYSCALL_WRITE: usize = 64;
+const SYSCALL_WRITEV: usize = 66;
 const SYSCALL_EXIT: usize = 93;
 const SYSCALL_YIELD: usize = 124;
 const SYSCALL_SET_PRIORITY: usize = 140;
@@ -22,6 +23,23 @@ const SYSCALL_SEND_MSG: usize = 601;
 const SYSCALL_SET_TIMER: usize = 602;
 const SYSCALL_CLAIM_EXT_INT: usize = 603;
 const SYSCALL_SET_EXT_INT_ENABLE: usize = 604;
+const SYSCALL_RELEASE_EXT_INT: usize = 605;
+const SYSCALL_ENUMERATE_SERIAL: usize = 606;
+const SYSCALL_WAIT_EXT_INT: usize = 607;
+const SYSCALL_SET_EXT_INT_AFFINITY: usize = 608;
+const SYSCALL_SET_EXT_INT_PRIORITY: usize = 609;
+const SYSCALL_SET_EXT_INT_THRESHOLD: usize = 610;
+const SYSCALL_TRANSFER_EXT_INT: usize = 611;
+const SYSCALL_OPEN_CONSOLE_RING: usize = 612;
+const SYSCALL_WAIT_CONSOLE_RING: usize = 613;
+const SYSCALL_CLOSE_CONSOLE_RING: usize = 614;
+const SYSCALL_GET_EXT_INT_STATS: usize = 615;
+const SYSCALL_DUMP_SERIAL_REGS: usize = 616;
+const SYSCALL_DROP_CAPS: usize = 617;
+const SYSCALL_SET_EXT_INT_WATCHDOG: usize = 618;
+const SYSCALL_SET_EXT_INT_LIMIT: usize = 619;
+const SYSCALL_LIST_EXT_INT: usize = 620;
+const SYSCALL_LISTEN_CONSOLE_ERRORS: usize = 621;
 
 mod fs;
 mod process;
@@ -38,6 +56,7 @@ pub fn syscall(syscall_id: usize, args: [usize; 3]) -> isize {
         SYSCALL_PIPE => sys_pipe(args[0] as *mut usize),
         SYSCALL_READ => sys_read(args[0], args[1] as *const u8, args[2]),
         SYSCALL_WRITE => sys_write(args[0], args[1] as *const u8, args[2]),
+        SYSCALL_WRITEV => sys_writev(args[0], args[1] as *const usize, args[2]),
         SYSCALL_EXIT => sys_exit(args[0] as i32),
         SYSCALL_YIELD => sys_yield(),
         SYSCALL_GET_TIME => sys_get_time(args[0], args[1]),
@@ -58,6 +77,23 @@ pub fn syscall(syscall_id: usize, args: [usize; 3]) -> isize {
         SYSCALL_SET_TIMER => sys_set_timer(args[0]),
         SYSCALL_CLAIM_EXT_INT => sys_claim_ext_int(args[0]),
         SYSCALL_SET_EXT_INT_ENABLE => sys_set_ext_int_enable(args[0], args[1]),
+        SYSCALL_RELEASE_EXT_INT => sys_release_ext_int(args[0]),
+        SYSCALL_ENUMERATE_SERIAL => sys_enumerate_serial(args[0] as *mut u8, args[1]),
+        SYSCALL_WAIT_EXT_INT => sys_wait_ext_int(args[0], args[1]),
+        SYSCALL_SET_EXT_INT_AFFINITY => sys_set_ext_int_affinity(args[0], args[1]),
+        SYSCALL_SET_EXT_INT_PRIORITY => sys_set_ext_int_priority(args[0], args[1]),
+        SYSCALL_SET_EXT_INT_THRESHOLD => sys_set_ext_int_threshold(args[0]),
+        SYSCALL_TRANSFER_EXT_INT => sys_transfer_ext_int(args[0], args[1]),
+        SYSCALL_OPEN_CONSOLE_RING => sys_open_console_ring(),
+        SYSCALL_WAIT_CONSOLE_RING => sys_wait_console_ring(args[0]),
+        SYSCALL_CLOSE_CONSOLE_RING => sys_close_console_ring(),
+        SYSCALL_GET_EXT_INT_STATS => sys_get_ext_int_stats(args[0], args[1] as *mut usize),
+        SYSCALL_DUMP_SERIAL_REGS => sys_dump_serial_regs(args[0], args[1] as *mut u8),
+        SYSCALL_DROP_CAPS => sys_drop_caps(args[0]),
+        SYSCALL_SET_EXT_INT_WATCHDOG => sys_set_ext_int_watchdog(args[0]),
+        SYSCALL_SET_EXT_INT_LIMIT => sys_set_ext_int_limit(args[0]),
+        SYSCALL_LIST_EXT_INT => sys_list_ext_int(args[0] as *mut usize, args[1]),
+        SYSCALL_LISTEN_CONSOLE_ERRORS => sys_listen_console_errors(args[0]),
         _ => panic!("Unsupported syscall_id: {}", syscall_id),
     };
     push_trace(TRACE_SYSCALL_S_EXIT + syscall_id);
@@ -7,7 +7,7 @@ use crate::task::{
     set_current_priority, suspend_current_and_run_next, WAIT_LOCK,
 };
 use crate::timer::get_time;
-use crate::trap::{push_trap_record, UserTrapRecord};
+use crate::trap::{push_trap_record, UserTrapRecord, IRQ_REVOKED_MSG};
 use alloc::vec::Vec;
 use core::mem::size_of;
 
@@ -197,6 +197,9 @@ pub fn sys_set_timer(time_us: usize) -> isize {
     0
 }
 
+/// Claims `device_id` for the caller and maps its registers. Returns where
+/// they are mapped, only in the caller, not in children it forks, so
+/// having the address is having the claim.
 pub fn sys_claim_ext_int(device_id: usize) -> isize {
     let device_id = device_id as u16;
     let current_task = current_task().unwrap();
@@ -204,20 +207,45 @@ pub fn sys_claim_ext_int(device_id: usize) -> isize {
     if !inner.is_user_trap_enabled() {
         return -1;
     }
+    use crate::uart;
+    if !uart::is_user_serial_irq(device_id) {
+        return -4;
+    }
+    if !uart::is_present(uart::irq_to_serial_id(device_id)) {
+        return -7;
+    }
     use crate::plic;
     use crate::trap::USER_EXT_INT_MAP;
+    let limit = inner.ext_int_limit;
     let user_trap_info = &mut inner.user_trap_info;
     match user_trap_info {
         Some(info) => {
             let mut map = USER_EXT_INT_MAP.lock();
-            if !map.contains_key(&device_id) {
-                let pid = current_task.getpid();
-                debug!(
-                    "[syscall claim] mapping device {} to pid {}",
-                    device_id, pid
+            if let Some(owner) = map.get(&device_id) {
+                warn!(
+                    "[syscall claim] device {} already held by pid {}!",
+                    device_id, owner
+                );
+                return -3;
+            }
+            if info.devices.len() >= limit {
+                warn!(
+                    "[syscall claim] pid {} holds {} devices already!",
+                    current_task.getpid(),
+                    limit
                 );
-                map.insert(device_id, pid);
-                info.devices.push((device_id, false));
+                return -8;
+            }
+            let pid = current_task.getpid();
+            debug!(
+                "[syscall claim] mapping device {} to pid {}",
+                device_id, pid
+            );
+            let first_device = info.devices.is_empty();
+            map.insert(device_id, pid);
+            info.reset_ledger(device_id);
+            info.devices.push((device_id, false));
+            if first_device {
                 for hart_id in 0..CPU_NUM {
                     let claim_addr = Plic::context_address(plic::get_context(hart_id, 'U'));
                     if inner
@@ -230,40 +258,222 @@ pub fn sys_claim_ext_int(device_id: usize) -> isize {
                     }
                 }
             }
-            use crate::uart;
-            match device_id {
-                #[cfg(feature = "board_qemu")]
-                13 | 14 | 15 => {
-                    let base_address = uart::get_base_addr_from_irq(device_id);
-                    match inner
-                        .memory_set
-                        .mmio_map(base_address, uart::SERIAL_ADDRESS_STRIDE, 0x3)
-                    {
-                        Ok(_) => base_address as isize,
-                        Err(_) => -2,
-                    }
+            let base_address = uart::get_base_addr_from_irq(device_id);
+            match inner
+                .memory_set
+                .mmio_map(base_address, uart::SERIAL_ADDRESS_STRIDE, 0x3)
+            {
+                Ok(_) => base_address as isize,
+                Err(_) => -2,
+            }
+        }
+        None => {
+            warn!("[syscall claim] user trap info is None!");
+            -5
+        }
+    }
+}
+
+pub fn sys_release_ext_int(device_id: usize) -> isize {
+    let device_id = device_id as u16;
+    let current_task = current_task().unwrap();
+    let mut inner = current_task.acquire_inner_lock();
+    if !inner.is_user_trap_enabled() {
+        return -1;
+    }
+    use crate::plic;
+    use crate::trap::{EXT_INT_AFFINITY_MAP, USER_EXT_INT_MAP};
+    use crate::uart;
+    let user_trap_info = &mut inner.user_trap_info;
+    match user_trap_info {
+        Some(info) => {
+            let mut map = USER_EXT_INT_MAP.lock();
+            match map.get(&device_id) {
+                Some(pid) if *pid == current_task.getpid() => {}
+                Some(pid) => {
+                    warn!(
+                        "[syscall release] device {} held by pid {}!",
+                        device_id, pid
+                    );
+                    return -3;
                 }
-                #[cfg(feature = "board_lrv")]
-                5 | 6 | 7 => {
-                    let base_address = uart::get_base_addr_from_irq(device_id);
-                    match inner
+                None => return -2,
+            }
+            map.remove(&device_id);
+            drop(map);
+            EXT_INT_AFFINITY_MAP.lock().remove(&device_id);
+            crate::plic::set_source_priority(device_id, crate::plic::PLIC_DEFAULT_PRIORITY);
+            info.devices.retain(|(dev_id, _)| *dev_id != device_id);
+            info.stalls.remove(&device_id);
+            for hart in 0..CPU_NUM {
+                Plic::disable(get_context(hart, 'U'), device_id);
+                Plic::enable(get_context(hart, 'S'), device_id);
+            }
+            Plic::complete(get_context(hart_id(), 'S'), device_id);
+            let last_device = info.devices.is_empty();
+            let base_address = uart::get_base_addr_from_irq(device_id);
+            if inner
+                .memory_set
+                .mmio_unmap(base_address, uart::SERIAL_ADDRESS_STRIDE)
+                .is_err()
+            {
+                warn!("[syscall release] unmap device {} failed!", device_id);
+            }
+            uart::reclaim(device_id);
+            if last_device {
+                for hart_id in 0..CPU_NUM {
+                    let claim_addr = Plic::context_address(plic::get_context(hart_id, 'U'));
+                    let _ = inner
                         .memory_set
-                        .mmio_map(base_address, uart::SERIAL_ADDRESS_STRIDE, 0x3)
-                    {
-                        Ok(_) => base_address as isize,
-                        Err(_) => -2,
-                    }
+                        .mmio_unmap(claim_addr, crate::config::PAGE_SIZE);
                 }
-                _ => -4,
             }
+            0
         }
         None => {
-            warn!("[syscall claim] user trap info is None!");
+            warn!("[syscall release] user trap info is None!");
             -5
         }
     }
 }
 
+/// Hands `device_id`, claimed by the caller, to process `pid` without
+/// resetting the port. The owner, the register mappings and the interrupt
+/// routing all move with the owner map and both processes locked, so if
+/// `pid` exits it either had the device already and the kernel reclaims it
+/// on exit, or the caller keeps it and gets -4. Priority and affinity stay.
+pub fn sys_transfer_ext_int(device_id: usize, pid: usize) -> isize {
+    use crate::plic;
+    use crate::task::find_task;
+    use crate::trap::{EXT_INT_AFFINITY_MAP, USER_EXT_INT_MAP};
+    use crate::uart;
+    let device_id = device_id as u16;
+    let current_task = current_task().unwrap();
+    let current_pid = current_task.getpid();
+    let target_task = match find_task(pid) {
+        Some(task) if pid != current_pid => task,
+        _ => return -4,
+    };
+    // lock the lower pid first, a transfer the other way may be under way
+    let (mut inner, mut target_inner) = if current_pid < pid {
+        let inner = current_task.acquire_inner_lock();
+        (inner, target_task.acquire_inner_lock())
+    } else {
+        let target_inner = target_task.acquire_inner_lock();
+        (current_task.acquire_inner_lock(), target_inner)
+    };
+    if !inner.is_user_trap_enabled() {
+        return -1;
+    }
+    if target_inner.is_zombie() || !target_inner.is_user_trap_enabled() {
+        return -4;
+    }
+    let mut map = USER_EXT_INT_MAP.lock();
+    match map.get(&device_id) {
+        Some(owner) if *owner == current_pid => {}
+        Some(owner) => {
+            warn!(
+                "[syscall transfer] device {} held by pid {}!",
+                device_id, owner
+            );
+            return -3;
+        }
+        None => return -2,
+    }
+    let inner = &mut *inner;
+    let target_inner = &mut *target_inner;
+    let (info, target_info) = match (&mut inner.user_trap_info, &mut target_inner.user_trap_info) {
+        (Some(info), Some(target_info)) => (info, target_info),
+        _ => return -5,
+    };
+    if target_info.devices.len() >= target_inner.ext_int_limit {
+        warn!(
+            "[syscall transfer] pid {} holds {} devices already!",
+            pid, target_inner.ext_int_limit
+        );
+        return -8;
+    }
+
+    // map into the target first, the caller keeps everything if that fails
+    let base_address = uart::get_base_addr_from_irq(device_id);
+    let mut regions = Vec::new();
+    if target_info.devices.is_empty() {
+        for hart_id in 0..CPU_NUM {
+            let claim_addr = Plic::context_address(plic::get_context(hart_id, 'U'));
+            regions.push((claim_addr, crate::config::PAGE_SIZE));
+        }
+    }
+    regions.push((base_address, uart::SERIAL_ADDRESS_STRIDE));
+    let mut mapped = Vec::new();
+    for (start, len) in regions {
+        if target_inner.memory_set.is_mmio_mapped(start, len) {
+            continue;
+        }
+        if target_inner.memory_set.mmio_map(start, len, 0x3).is_err() {
+            warn!(
+                "[syscall transfer] map {:#x} into pid {} failed!",
+                start, pid
+            );
+            for (start, len) in mapped {
+                let _ = target_inner.memory_set.mmio_unmap(start, len);
+            }
+            return -6;
+        }
+        mapped.push((start, len));
+    }
+
+    debug!(
+        "[syscall transfer] device {} from pid {} to pid {}",
+        device_id, current_pid, pid
+    );
+    let is_enabled = info
+        .devices
+        .iter()
+        .any(|(dev_id, en)| *dev_id == device_id && *en);
+    info.devices.retain(|(dev_id, _)| *dev_id != device_id);
+    info.stalls.remove(&device_id);
+    target_info.reset_ledger(device_id);
+    target_info.devices.push((device_id, is_enabled));
+    map.insert(device_id, pid);
+    // the target is not running here, let the kernel queue it until it is
+    for hart in 0..CPU_NUM {
+        Plic::disable(get_context(hart, 'U'), device_id);
+        Plic::disable(get_context(hart, 'S'), device_id);
+    }
+    if is_enabled {
+        let hart = EXT_INT_AFFINITY_MAP
+            .lock()
+            .get(&device_id)
+            .cloned()
+            .unwrap_or(hart_id());
+        Plic::enable(get_context(hart, 'S'), device_id);
+    }
+    drop(map);
+
+    if inner
+        .memory_set
+        .mmio_unmap(base_address, uart::SERIAL_ADDRESS_STRIDE)
+        .is_err()
+    {
+        warn!("[syscall transfer] unmap device {} failed!", device_id);
+    }
+    if info.devices.is_empty() {
+        for hart_id in 0..CPU_NUM {
+            let claim_addr = Plic::context_address(plic::get_context(hart_id, 'U'));
+            let _ = inner
+                .memory_set
+                .mmio_unmap(claim_addr, crate::config::PAGE_SIZE);
+        }
+    }
+    // a driver the caller still has on the device must not wait for it
+    let _ = info.push_trap_record(UserTrapRecord {
+        // User Software Interrupt, from the kernel
+        cause: 0,
+        message: IRQ_REVOKED_MSG | device_id as usize,
+    });
+    0
+}
+
 pub fn sys_set_ext_int_enable(device_id: usize, enable: usize) -> isize {
     debug!("[SET EXT INT] dev: {}, enable: {}", device_id, enable);
     let device_id = device_id as u16;
@@ -273,7 +483,7 @@ pub fn sys_set_ext_int_enable(device_id: usize, enable: usize) -> isize {
     if !inner.is_user_trap_enabled() {
         return -1;
     }
-    use crate::trap::USER_EXT_INT_MAP;
+    use crate::trap::{route_running_ext_int, EXT_INT_AFFINITY_MAP, USER_EXT_INT_MAP};
     let user_trap_info = &mut inner.user_trap_info;
     match user_trap_info {
         Some(info) => {
@@ -282,14 +492,12 @@ pub fn sys_set_ext_int_enable(device_id: usize, enable: usize) -> isize {
                     for (dev_id, en) in &mut info.devices {
                         if *dev_id == device_id {
                             *en = is_enable;
-                            if is_enable {
-                                Plic::enable(get_context(hart_id(), 'U'), device_id);
-                                for hart in 0..CPU_NUM {
-                                    Plic::disable(get_context(hart, 'S'), device_id);
-                                }
-                            } else {
-                                Plic::disable(get_context(hart_id(), 'U'), device_id);
-                            }
+                            route_running_ext_int(
+                                device_id,
+                                is_enable,
+                                hart_id(),
+                                EXT_INT_AFFINITY_MAP.lock().get(&device_id).cloned(),
+                            );
                         }
                     }
 
@@ -313,3 +521,425 @@ pub fn sys_set_ext_int_enable(device_id: usize, enable: usize) -> isize {
         }
     }
 }
+
+/// Pins the interrupt of `device_id`, claimed by the caller, to `hart`, or
+/// unpins it if `hart` is `usize::MAX`. It is then taken in U mode only
+/// while the caller runs on `hart`, otherwise the kernel on `hart` queues it.
+/// Returns -4 and leaves the routing alone if the PLIC can't enable the
+/// source for that hart.
+pub fn sys_set_ext_int_affinity(device_id: usize, hart: usize) -> isize {
+    use crate::plic::is_enabled;
+    use crate::trap::{route_running_ext_int, EXT_INT_AFFINITY_MAP, USER_EXT_INT_MAP};
+    let device_id = device_id as u16;
+    if hart >= CPU_NUM && hart != usize::MAX {
+        return -3;
+    }
+    let current_task = current_task().unwrap();
+    let inner = current_task.acquire_inner_lock();
+    let is_enable = match &inner.user_trap_info {
+        Some(info) => match info.devices.iter().find(|(dev_id, _)| *dev_id == device_id) {
+            Some((_, en)) => *en,
+            None => return -1,
+        },
+        None => return -5,
+    };
+    match USER_EXT_INT_MAP.lock().get(&device_id) {
+        Some(pid) if *pid == current_task.getpid() => {}
+        Some(_) => return -1,
+        None => return -2,
+    }
+    let mut affinity = EXT_INT_AFFINITY_MAP.lock();
+    if hart == usize::MAX {
+        affinity.remove(&device_id);
+    } else {
+        // probe both contexts, some PLICs hardwire enables for some sources
+        for mode in ['S', 'U'] {
+            let context = get_context(hart, mode);
+            let was_enabled = is_enabled(context, device_id);
+            Plic::enable(context, device_id);
+            let supported = is_enabled(context, device_id);
+            if !was_enabled {
+                Plic::disable(context, device_id);
+            }
+            if !supported {
+                warn!(
+                    "[syscall affinity] device {} can't be routed to hart {} {}",
+                    device_id, hart, mode
+                );
+                return -4;
+            }
+        }
+        affinity.insert(device_id, hart);
+    }
+    route_running_ext_int(
+        device_id,
+        is_enable,
+        hart_id(),
+        affinity.get(&device_id).cloned(),
+    );
+    0
+}
+
+/// Sets the PLIC priority of `device_id`, claimed by the caller, from 1 to
+/// `PLIC_MAX_PRIORITY`. It goes back to the default on release.
+pub fn sys_set_ext_int_priority(device_id: usize, priority: usize) -> isize {
+    use crate::plic::{set_source_priority, PLIC_MAX_PRIORITY};
+    use crate::trap::USER_EXT_INT_MAP;
+    let device_id = device_id as u16;
+    if priority == 0 || priority > PLIC_MAX_PRIORITY as usize {
+        return -3;
+    }
+    match USER_EXT_INT_MAP.lock().get(&device_id) {
+        Some(pid) if *pid == current_task().unwrap().getpid() => {}
+        Some(_) => return -1,
+        None => return -2,
+    }
+    set_source_priority(device_id, priority as u32);
+    0
+}
+
+/// Sets the threshold of the U context while the caller runs, interrupts
+/// at or below it are held back. 0 lets everything through.
+pub fn sys_set_ext_int_threshold(threshold: usize) -> isize {
+    use crate::plic::{set_context_threshold, PLIC_MAX_PRIORITY};
+    if threshold > PLIC_MAX_PRIORITY as usize {
+        return -3;
+    }
+    let current_task = current_task().unwrap();
+    let mut inner = current_task.acquire_inner_lock();
+    match &mut inner.user_trap_info {
+        Some(info) => {
+            info.threshold = threshold as u32;
+            set_context_threshold(get_context(hart_id(), 'U'), threshold as u32);
+            0
+        }
+        None => -5,
+    }
+}
+
+/// Blocks until `device_id`, claimed by the caller, raises its interrupt or
+/// `timeout_us` passes, 0 waits forever. While waiting, the interrupt goes
+/// to the kernel instead of the user trap queue and is masked once it fires,
+/// so the caller can drain the device before waiting again. On return,
+/// fired or not, it is enabled and routed as it was before the call.
+pub fn sys_wait_ext_int(device_id: usize, timeout_us: usize) -> isize {
+    use crate::plic::set_kernel_enable;
+    use crate::timer::get_time_us;
+    use crate::trap::{
+        route_running_ext_int, EXT_INT_AFFINITY_MAP, USER_EXT_INT_MAP, WAITED_EXT_INT_MAP,
+    };
+    let device_id = device_id as u16;
+    let deadline = get_time_us().saturating_add(timeout_us);
+    let mut was_enabled = false;
+    {
+        let current_task = current_task().unwrap();
+        let mut inner = current_task.acquire_inner_lock();
+        match USER_EXT_INT_MAP.lock().get(&device_id) {
+            Some(pid) if *pid == current_task.getpid() => {}
+            _ => return -1,
+        }
+        if let Some(info) = &mut inner.user_trap_info {
+            for (dev_id, en) in &mut info.devices {
+                if *dev_id == device_id {
+                    was_enabled = *en;
+                    *en = false;
+                }
+            }
+        }
+        WAITED_EXT_INT_MAP.lock().insert(device_id, false);
+        for hart in 0..CPU_NUM {
+            Plic::disable(get_context(hart, 'U'), device_id);
+        }
+        set_kernel_enable(device_id, true);
+    }
+    loop {
+        let fired = WAITED_EXT_INT_MAP.lock().get(&device_id).copied();
+        // no entry is an error below, not a wait forever
+        if fired != Some(false) || (timeout_us != 0 && get_time_us() >= deadline) {
+            break;
+        }
+        suspend_current_and_run_next();
+    }
+    // it may still fire until it is masked
+    set_kernel_enable(device_id, false);
+    let current_task = current_task().unwrap();
+    let mut inner = current_task.acquire_inner_lock();
+    let ret = match WAITED_EXT_INT_MAP.lock().remove(&device_id) {
+        Some(true) => 0,
+        Some(false) => -2,
+        None => -3,
+    };
+    if let Some(info) = &mut inner.user_trap_info {
+        for (dev_id, en) in &mut info.devices {
+            if *dev_id == device_id {
+                *en = was_enabled;
+                route_running_ext_int(
+                    device_id,
+                    was_enabled,
+                    hart_id(),
+                    EXT_INT_AFFINITY_MAP.lock().get(&device_id).cloned(),
+                );
+            }
+        }
+    }
+    ret
+}
+
+pub fn sys_enumerate_serial(buf: *mut u8, len: usize) -> isize {
+    use crate::trap::USER_EXT_INT_MAP;
+    use crate::uart::{
+        self, SerialPortInfo, SERIAL_PORT_ABSENT, SERIAL_PORT_CLAIMABLE, SERIAL_PORT_CLAIMED,
+    };
+    let pid = current_task().unwrap().getpid();
+    let map = USER_EXT_INT_MAP.lock();
+    let ports: Vec<SerialPortInfo> = (0..uart::SERIAL_NUM)
+        .map(|index| {
+            let irq = uart::serial_id_to_irq(index);
+            let phys_base = uart::get_base_addr_from_irq(irq);
+            let mut flags = 0;
+            if !uart::is_present(index) {
+                flags |= SERIAL_PORT_ABSENT;
+            } else if uart::is_user_serial_irq(irq) {
+                flags |= SERIAL_PORT_CLAIMABLE;
+            }
+            let owner = map.get(&irq).cloned();
+            if owner.is_some() {
+                flags |= SERIAL_PORT_CLAIMED;
+            }
+            SerialPortInfo {
+                index,
+                irq: irq as usize,
+                phys_base,
+                // claimed registers are mapped at their physical address
+                virt_base: if owner == Some(pid) { phys_base } else { 0 },
+                flags,
+                reg_shift: uart::REG_SHIFT,
+                reg_width: size_of::<uart::Register>(),
+            }
+        })
+        .collect();
+    drop(map);
+    let entry_num = ports.len().min(len);
+    let bytes = unsafe {
+        core::slice::from_raw_parts(
+            ports.as_ptr() as *const u8,
+            entry_num * size_of::<SerialPortInfo>(),
+        )
+    };
+    match mm::translated_byte_buffer(current_user_token(), buf, bytes.len()) {
+        Ok(buffers) => {
+            for (ptr, byte) in mm::UserBuffer::new(buffers).into_iter().zip(bytes) {
+                unsafe {
+                    ptr.write_volatile(*byte);
+                }
+            }
+            ports.len() as isize
+        }
+        Err(_) => -1,
+    }
+}
+
+/// Maps a ring that the console input is copied into, instead of going to
+/// the kernel stdin, and returns its address. With user traps on, setting
+/// its doorbell asks for a user external interrupt record with the console
+/// IRQ once input arrives.
+pub fn sys_open_console_ring() -> isize {
+    use crate::config::{CONSOLE_RING_BUFFER, PAGE_SIZE};
+    use crate::console_ring;
+    use crate::mm::{translate_writable_va, PhysAddr, PhysPageNum};
+    let current_task = current_task().unwrap();
+    let mut inner = current_task.acquire_inner_lock();
+    // R | W
+    if inner.mmap(CONSOLE_RING_BUFFER, PAGE_SIZE, 0b11).is_err() {
+        warn!("[syscall console ring] already open or map failed!");
+        return -1;
+    }
+    let phys_addr = translate_writable_va(inner.get_user_token(), CONSOLE_RING_BUFFER).unwrap();
+    let ppn = PhysPageNum::from(PhysAddr::from(phys_addr));
+    match console_ring::register(current_task.getpid(), ppn) {
+        Ok(()) => CONSOLE_RING_BUFFER as isize,
+        Err(errno) => {
+            let _ = inner.munmap(CONSOLE_RING_BUFFER, PAGE_SIZE);
+            errno
+        }
+    }
+}
+
+/// Blocks until the caller's console ring has input, or `timeout_us`
+/// passes, 0 waits forever. Returns -1 without a ring and -2 on timeout.
+pub fn sys_wait_console_ring(timeout_us: usize) -> isize {
+    use crate::console_ring;
+    use crate::timer::get_time_us;
+    let deadline = get_time_us().saturating_add(timeout_us);
+    let pid = current_task().unwrap().getpid();
+    loop {
+        match console_ring::ring_of(pid) {
+            Some(ring) if !ring.is_empty() => return 0,
+            Some(_) => {}
+            None => return -1,
+        }
+        if timeout_us != 0 && get_time_us() >= deadline {
+            return -2;
+        }
+        suspend_current_and_run_next();
+    }
+}
+
+/// Unmaps the caller's console ring, input goes to the kernel stdin again.
+pub fn sys_close_console_ring() -> isize {
+    use crate::config::{CONSOLE_RING_BUFFER, PAGE_SIZE};
+    use crate::console_ring;
+    let current_task = current_task().unwrap();
+    let mut inner = current_task.acquire_inner_lock();
+    if !console_ring::unregister(current_task.getpid()) {
+        return -1;
+    }
+    let _ = inner.munmap(CONSOLE_RING_BUFFER, PAGE_SIZE);
+    0
+}
+
+/// Writes the kernel's fired, claimed, delivered and completed counts of
+/// `irq` to `buf`, then the overruns, parity errors, framing errors and
+/// breaks the kernel driver saw if `irq` is a serial's, eight `usize`s.
+/// Compare with what the owner handled to find where interrupts go missing.
+pub fn sys_get_ext_int_stats(irq: usize, buf: *mut usize) -> isize {
+    use crate::mm::translated_refmut;
+    use crate::plic::EXT_INT_COUNTERS;
+    use crate::uart::{self, BUFFERED_SERIAL};
+    use core::sync::atomic::Ordering::Relaxed;
+    let counters = match EXT_INT_COUNTERS.get(irq) {
+        Some(counters) => counters,
+        None => return -1,
+    };
+    let line_errors = (0..uart::SERIAL_NUM)
+        .find(|&serial_id| uart::serial_id_to_irq(serial_id) as usize == irq)
+        .map_or([0; 4], |serial_id| {
+            BUFFERED_SERIAL[serial_id].lock().line_error_counts()
+        });
+    let token = current_user_token();
+    let counts = [
+        counters.fired.load(Relaxed),
+        counters.claimed.load(Relaxed),
+        counters.delivered.load(Relaxed),
+        counters.completed.load(Relaxed),
+        line_errors[0],
+        line_errors[1],
+        line_errors[2],
+        line_errors[3],
+    ];
+    for (i, count) in counts.iter().enumerate() {
+        *translated_refmut(token, unsafe { buf.add(i) }) = *count;
+    }
+    0
+}
+
+/// Copies a snapshot of the registers of serial `serial_id` to `buf`, even
+/// if another process holds it. Needs `CAP_SERIAL_DEBUG`, the LSR and MSR
+/// reads clear bits its driver may be waiting on.
+pub fn sys_dump_serial_regs(serial_id: usize, buf: *mut u8) -> isize {
+    use crate::task::CAP_SERIAL_DEBUG;
+    use crate::trap::USER_EXT_INT_MAP;
+    use crate::uart::{self, UartRegSnapshot, UART_SNAPSHOT_CLAIMED};
+    if current_task().unwrap().acquire_inner_lock().caps & CAP_SERIAL_DEBUG == 0 {
+        return -1;
+    }
+    if serial_id >= uart::SERIAL_NUM {
+        return -2;
+    }
+    let mut snapshot = uart::snapshot(serial_id);
+    if USER_EXT_INT_MAP
+        .lock()
+        .contains_key(&uart::serial_id_to_irq(serial_id))
+    {
+        snapshot.flags |= UART_SNAPSHOT_CLAIMED;
+    }
+    let bytes = unsafe {
+        core::slice::from_raw_parts(
+            &snapshot as *const _ as *const u8,
+            size_of::<UartRegSnapshot>(),
+        )
+    };
+    match mm::translated_byte_buffer(current_user_token(), buf, bytes.len()) {
+        Ok(buffers) => {
+            for (ptr, byte) in mm::UserBuffer::new(buffers).into_iter().zip(bytes) {
+                unsafe {
+                    ptr.write_volatile(*byte);
+                }
+            }
+            0
+        }
+        Err(_) => -3,
+    }
+}
+
+/// Drops the `CAP_*` bits in `caps` for good. Returns the ones left.
+pub fn sys_drop_caps(caps: usize) -> isize {
+    let task = current_task().unwrap();
+    let mut inner = task.acquire_inner_lock();
+    inner.caps &= !caps;
+    inner.caps as isize
+}
+
+/// Sets how long the caller may leave a device claimed, in us, before the
+/// kernel kills it with -4. 0 turns the watchdog off.
+pub fn sys_set_ext_int_watchdog(timeout_us: usize) -> isize {
+    let current_task = current_task().unwrap();
+    let mut inner = current_task.acquire_inner_lock();
+    match inner.user_trap_info.as_mut() {
+        Some(info) => {
+            info.watchdog_us = timeout_us;
+            info.stalls.clear();
+            0
+        }
+        None => -5,
+    }
+}
+
+/// Lowers how many devices the caller may hold at once to `limit`, it never
+/// goes up. Returns the limit in effect, `usize::MAX` only asks for it.
+pub fn sys_set_ext_int_limit(limit: usize) -> isize {
+    let current_task = current_task().unwrap();
+    let mut inner = current_task.acquire_inner_lock();
+    inner.ext_int_limit = inner.ext_int_limit.min(limit);
+    inner.ext_int_limit as isize
+}
+
+/// Writes the devices the caller holds to `buf`, at most `len` of them.
+/// Returns how many it holds.
+pub fn sys_list_ext_int(buf: *mut usize, len: usize) -> isize {
+    use crate::mm::translated_refmut;
+    let token = current_user_token();
+    let current_task = current_task().unwrap();
+    let inner = current_task.acquire_inner_lock();
+    let devices = match &inner.user_trap_info {
+        Some(info) => &info.devices,
+        None => return 0,
+    };
+    for (i, (device_id, _)) in devices.iter().take(len).enumerate() {
+        *translated_refmut(token, unsafe { buf.add(i) }) = *device_id as usize;
+    }
+    devices.len() as isize
+}
+
+/// Has the caller sent a user software interrupt whenever the kernel
+/// driver sees line errors on the console, or stops it if `enable` is 0.
+/// The message is `CONSOLE_ERROR_MSG` with the `LSR_*` error bits.
+pub fn sys_listen_console_errors(enable: usize) -> isize {
+    use crate::console_ring;
+    let current_task = current_task().unwrap();
+    let pid = current_task.getpid();
+    if enable == 0 {
+        return if console_ring::unlisten_errors(pid) {
+            0
+        } else {
+            -3
+        };
+    }
+    if !current_task.acquire_inner_lock().is_user_trap_enabled() {
+        return -1;
+    }
+    match console_ring::listen_errors(pid) {
+        Ok(()) => 0,
+        Err(errno) => errno,
+    }
+}
@@ -20,7 +20,7 @@ pub use processor::{
     current_task, current_trap_cx, current_user_token, hart_id, mmap, munmap, run_tasks, schedule,
     set_current_priority, take_current_task,
 };
-pub use task::{TaskControlBlock, TaskStatus};
+pub use task::{TaskControlBlock, TaskStatus, CAP_SERIAL_DEBUG};
 
 lazy_static! {
     pub static ref WAIT_LOCK: Mutex<()> = Mutex::new(());
@@ -51,15 +51,12 @@ pub fn exit_current_and_run_next(exit_code: i32) {
         "pid: {} exited with code {}, time intr: {}, cycle count: {}",
         task.pid.0, exit_code, inner.time_intr_count, inner.total_cpu_cycle_count
     );
-    if let Some(trap_info) = &inner.user_trap_info {
-        trap_info.remove_user_ext_int_map();
-        use riscv::register::sie;
-        unsafe {
-            sie::clear_uext();
-            sie::clear_usoft();
-            sie::clear_utimer();
-        }
-    }
+    // the ring page is freed with the rest of the user space below
+    crate::console_ring::unregister(task.pid.0);
+    crate::console_ring::unlisten_errors(task.pid.0);
+    // still under the lock, a transfer to us lands before this or sees the
+    // zombie
+    inner.release_user_trap();
 
     // Change status to Zombie
     inner.task_status = TaskStatus::Zombie;
@@ -2,19 +2,30 @@ use super::TaskContext;
 use super::{pid_alloc, KernelStack, PidHandle};
 use crate::fs::{File, MailBox, Serial, Socket, Stdin, Stdout};
 use crate::mm::{translate_writable_va, MemorySet, PhysAddr, PhysPageNum, VirtAddr, KERNEL_SPACE};
+use crate::plic::{self, Plic};
 use crate::task::pid::add_task_2_map;
 use crate::trap::{trap_handler, TrapContext, UserTrapInfo, UserTrapQueue};
 use crate::{
-    config::{PAGE_SIZE, TRAP_CONTEXT, USER_TRAP_BUFFER},
+    config::{
+        CPU_NUM, EXT_INT_CLAIM_LIMIT, EXT_INT_WATCHDOG_US, PAGE_SIZE, TRAP_CONTEXT,
+        USER_TRAP_BUFFER,
+    },
     loader::get_app_data_by_name,
     mm::translated_str,
 };
+use alloc::collections::BTreeMap;
 use alloc::sync::{Arc, Weak};
 use alloc::vec;
 use alloc::vec::Vec;
 use core::fmt::{self, Debug, Formatter};
 use spin::{Mutex, MutexGuard};
 
+/// May snapshot the registers of any serial, see `sys_dump_serial_regs`.
+pub const CAP_SERIAL_DEBUG: usize = 1 << 0;
+/// What initproc starts with. Children inherit their parent's set and can
+/// only drop from it.
+pub const CAP_ALL: usize = CAP_SERIAL_DEBUG;
+
 #[derive(Debug)]
 pub struct TaskControlBlock {
     // immutable
@@ -41,6 +52,11 @@ pub struct TaskControlBlockInner {
     pub time_intr_count: usize,
     pub total_cpu_cycle_count: usize,
     pub last_cpu_cycle: usize,
+    /// `CAP_*` bits.
+    pub caps: usize,
+    /// Most devices the process may hold at once. Children inherit it and
+    /// can only lower it.
+    pub ext_int_limit: usize,
 }
 
 impl Debug for TaskControlBlockInner {
@@ -121,6 +137,9 @@ impl TaskControlBlockInner {
                 self.user_trap_info = Some(UserTrapInfo {
                     user_trap_buffer_ppn: PhysPageNum::from(PhysAddr::from(phys_addr)),
                     devices: Vec::new(),
+                    threshold: 0,
+                    watchdog_us: EXT_INT_WATCHDOG_US,
+                    stalls: BTreeMap::new(),
                 });
                 let trap_queue = self.user_trap_info.as_mut().unwrap().get_trap_queue_mut();
                 *trap_queue = UserTrapQueue::new();
@@ -137,6 +156,21 @@ impl TaskControlBlockInner {
         Err(-1)
     }
 
+    /// Gives the claimed devices back to the kernel and turns the user
+    /// interrupts off, for exit and exec.
+    pub fn release_user_trap(&mut self) {
+        use riscv::register::sie;
+        if let Some(trap_info) = &mut self.user_trap_info {
+            trap_info.remove_user_ext_int_map();
+            trap_info.devices.clear();
+            unsafe {
+                sie::clear_uext();
+                sie::clear_usoft();
+                sie::clear_utimer();
+            }
+        }
+    }
+
     pub fn restore_user_trap_info(&mut self) {
         use riscv::register::{uip, uscratch};
         if self.is_user_trap_enabled() {
@@ -210,6 +244,8 @@ impl TaskControlBlock {
                 time_intr_count: 0,
                 total_cpu_cycle_count: 0,
                 last_cpu_cycle: 0,
+                caps: CAP_ALL,
+                ext_int_limit: EXT_INT_CLAIM_LIMIT,
             }),
         });
         add_task_2_map(task_control_block.getpid(), task_control_block.clone());
@@ -235,6 +271,11 @@ impl TaskControlBlock {
 
         // **** hold current PCB lock
         let mut inner = self.acquire_inner_lock();
+        crate::console_ring::unregister(self.pid.0);
+        crate::console_ring::unlisten_errors(self.pid.0);
+        // the new image knows nothing of what the old one claimed, and a
+        // transfer to us finds no trap info once we let go of the lock
+        inner.release_user_trap();
         inner.user_trap_info = None;
         // substitute memory_set
         inner.memory_set = memory_set;
@@ -256,7 +297,22 @@ impl TaskControlBlock {
         // ---- hold parent PCB lock
         let mut parent_inner = self.acquire_inner_lock();
         // copy user space(include trap context)
-        let memory_set = MemorySet::from_existed_user(&parent_inner.memory_set);
+        let mut memory_set = MemorySet::from_existed_user(&parent_inner.memory_set);
+        // the registers of claimed devices stay with the parent too
+        if let Some(trap_info) = parent_inner.user_trap_info.as_ref() {
+            for (device_id, _) in trap_info.devices.iter() {
+                let _ = memory_set.mmio_unmap(
+                    crate::uart::get_base_addr_from_irq(*device_id),
+                    crate::uart::SERIAL_ADDRESS_STRIDE,
+                );
+            }
+            if !trap_info.devices.is_empty() {
+                for hart_id in 0..CPU_NUM {
+                    let claim_addr = Plic::context_address(plic::get_context(hart_id, 'U'));
+                    let _ = memory_set.mmio_unmap(claim_addr, PAGE_SIZE);
+                }
+            }
+        }
         let trap_cx_ppn = memory_set
             .translate(VirtAddr::from(TRAP_CONTEXT).into())
             .unwrap()
@@ -281,6 +337,9 @@ impl TaskControlBlock {
         let mut user_trap_info: Option<UserTrapInfo> = None;
         if let Some(mut trap_info) = parent_inner.user_trap_info.clone() {
             debug!("[fork] copy parent trap info");
+            // claimed devices stay with the parent
+            trap_info.devices.clear();
+            trap_info.stalls.clear();
             trap_info.user_trap_buffer_ppn = memory_set
                 .translate(VirtAddr::from(USER_TRAP_BUFFER).into())
                 .unwrap()
@@ -307,6 +366,8 @@ impl TaskControlBlock {
                 time_intr_count: 0,
                 total_cpu_cycle_count: 0,
                 last_cpu_cycle: 0,
+                caps: parent_inner.caps,
+                ext_int_limit: parent_inner.ext_int_limit,
             }),
         });
         add_task_2_map(task_control_block.getpid(), task_control_block.clone());
@@ -378,6 +439,8 @@ impl TaskControlBlock {
                     time_intr_count: 0,
                     total_cpu_cycle_count: 0,
                     last_cpu_cycle: 0,
+                    caps: parent_inner.caps,
+                    ext_int_limit: parent_inner.ext_int_limit,
                 }),
             });
             add_task_2_map(task_control_block.getpid(), task_control_block.clone());
@@ -105,6 +105,7 @@ pub fn trap_handler() -> ! {
             exit_current_and_run_next(-3);
         }
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
+            check_ext_int_watchdog();
             // let current_time = time::read();
             let mut timer_map = TIMER_MAP[hart_id()].lock();
             while let Some((_, pid)) = timer_map.pop_first() {
@@ -159,6 +160,28 @@ pub fn trap_handler() -> ! {
     trap_return();
 }
 
+/// Kills the current process if it has left a device claimed for longer
+/// than its watchdog allows, see `UserTrapInfo::check_watchdog`. Exiting
+/// completes the device and hands it back to the kernel driver.
+fn check_ext_int_watchdog() {
+    let task = current_task().unwrap();
+    let mut inner = task.acquire_inner_lock();
+    let stalled = inner.user_trap_info.as_mut().and_then(|info| {
+        info.check_watchdog(get_time_us())
+            .map(|irq| (irq, info.watchdog_us))
+    });
+    drop(inner);
+    if let Some((irq, watchdog_us)) = stalled {
+        error!(
+            "[kernel] irq {} left claimed by pid {} for over {} us, killed",
+            irq, task.pid.0, watchdog_us
+        );
+        drop(task);
+        // wedged interrupt handler exit code
+        exit_current_and_run_next(-4);
+    }
+}
+
 #[no_mangle]
 pub fn trap_return() -> ! {
     unsafe {
@@ -227,5 +250,7 @@ pub extern "C" fn trap_from_kernel(cx: &mut TrapContext) {
 
 pub use context::TrapContext;
 pub use usertrap::{
-    push_trap_record, UserTrapError, UserTrapInfo, UserTrapQueue, UserTrapRecord, USER_EXT_INT_MAP,
+    forward_ext_int, push_trap_record, route_running_ext_int, UserTrapError, UserTrapInfo,
+    UserTrapQueue, UserTrapRecord, EXT_INT_AFFINITY_MAP, IRQ_REVOKED_MSG, USER_EXT_INT_MAP,
+    WAITED_EXT_INT_MAP,
 };
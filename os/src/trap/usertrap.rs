@@ -1,7 +1,9 @@
 const MAX_USER_TRAP_NUM: usize = 128;
 
-use crate::config::CPU_NUM;
-use crate::plic::Plic;
+use crate::config::{CPU_NUM, PAGE_SIZE};
+use crate::plic::{
+    set_context_threshold, set_source_priority, Plic, MAX_COUNTED_IRQ, PLIC_DEFAULT_PRIORITY,
+};
 use crate::sbi::send_ipi;
 use crate::task::hart_id;
 use crate::task::TaskStatus::Running;
@@ -12,6 +14,8 @@ use crate::trace::{
 use crate::{mm::PhysPageNum, plic::get_context};
 use alloc::{collections::BTreeMap, vec::Vec};
 use core::arch::asm;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
 use heapless::spsc::Queue;
 use lazy_static::*;
 use spin::Mutex;
@@ -21,8 +25,31 @@ pub type UserTrapQueue = Queue<UserTrapRecord, MAX_USER_TRAP_NUM>;
 pub struct UserTrapInfo {
     pub user_trap_buffer_ppn: PhysPageNum,
     pub devices: Vec<(u16, bool)>,
+    /// PLIC threshold of the U context while this process runs.
+    pub threshold: u32,
+    /// How long a device may stay claimed without a complete, 0 for no
+    /// limit, see `check_watchdog`.
+    pub watchdog_us: usize,
+    /// Devices found claimed, with the completes counted then and when.
+    pub stalls: BTreeMap<u16, (usize, usize)>,
+}
+
+/// Claims and completes of each source by one process, at the end of its
+/// user trap buffer. The kernel counts the claims it forwards, the process
+/// the ones it makes in U mode and all its completes.
+#[repr(C)]
+pub struct ExtIntLedger {
+    pub claimed: [AtomicUsize; MAX_COUNTED_IRQ],
+    pub completed: [AtomicUsize; MAX_COUNTED_IRQ],
 }
 
+const EXT_INT_LEDGER_OFFSET: usize = PAGE_SIZE - size_of::<ExtIntLedger>();
+
+/// Tag of the software interrupt message telling a process a device it
+/// had claimed is gone, the IRQ is in the low 16 bits. Its driver fails
+/// what is pending instead of waiting for interrupts that never come.
+pub const IRQ_REVOKED_MSG: usize = 0x5256_0000;
+
 #[repr(C)]
 #[derive(Copy, Clone)]
 pub struct UserTrapRecord {
@@ -48,19 +75,94 @@ impl UserTrapInfo {
         }
     }
 
+    /// Queues external interrupt `irq`, claimed in S mode, for the
+    /// process to complete.
+    pub fn forward_ext_int(&mut self, irq: u16) -> Result<(), UserTrapError> {
+        self.push_trap_record(UserTrapRecord {
+            // User External Interrupt
+            cause: 8,
+            message: irq as usize,
+        })?;
+        if let Some(claimed) = self.ledger().claimed.get(irq as usize) {
+            claimed.fetch_add(1, Relaxed);
+        }
+        Ok(())
+    }
+
+    pub fn ledger(&self) -> &'static ExtIntLedger {
+        let page = self.user_trap_buffer_ppn.get_bytes_array();
+        unsafe { &*(page[EXT_INT_LEDGER_OFFSET..].as_ptr() as *const ExtIntLedger) }
+    }
+
+    /// Starts the counts of a device over, before the process claims it.
+    pub fn reset_ledger(&mut self, irq: u16) {
+        let ledger = self.ledger();
+        if let (Some(claimed), Some(completed)) = (
+            ledger.claimed.get(irq as usize),
+            ledger.completed.get(irq as usize),
+        ) {
+            claimed.store(0, Relaxed);
+            completed.store(0, Relaxed);
+        }
+        self.stalls.remove(&irq);
+    }
+
+    /// Returns a device the process has had claimed for `watchdog_us`
+    /// without completing anything of it. A handler that left it so holds
+    /// the line, the PLIC sends the source nowhere until it is completed.
+    pub fn check_watchdog(&mut self, now_us: usize) -> Option<u16> {
+        if self.watchdog_us == 0 {
+            return None;
+        }
+        let ledger = self.ledger();
+        for &(irq, _) in self.devices.iter() {
+            let (claimed, completed) = match (
+                ledger.claimed.get(irq as usize),
+                ledger.completed.get(irq as usize),
+            ) {
+                (Some(claimed), Some(completed)) => {
+                    (claimed.load(Relaxed), completed.load(Relaxed))
+                }
+                _ => continue,
+            };
+            // a process that completes more than it claims only outruns us
+            if claimed.wrapping_sub(completed) as isize <= 0 {
+                self.stalls.remove(&irq);
+                continue;
+            }
+            match self.stalls.get(&irq) {
+                Some(&(seen, since)) if seen == completed => {
+                    if now_us - since >= self.watchdog_us {
+                        return Some(irq);
+                    }
+                }
+                _ => {
+                    self.stalls.insert(irq, (completed, now_us));
+                }
+            }
+        }
+        None
+    }
+
     pub fn enable_user_ext_int(&self) {
         // push_trace(ENABLE_USER_EXT_INT_ENTER);
 
-        let u_context = get_context(hart_id(), 'U');
+        let hart_id = hart_id();
+        if self.threshold != 0 {
+            set_context_threshold(get_context(hart_id, 'U'), self.threshold);
+        }
+        let waited = WAITED_EXT_INT_MAP.lock();
+        let affinity = EXT_INT_AFFINITY_MAP.lock();
         for (device_id, is_enabled) in &self.devices {
-            for hart_id in 0..CPU_NUM {
-                Plic::disable(get_context(hart_id, 'S'), *device_id);
-            }
-            if *is_enabled {
-                Plic::enable(u_context, *device_id);
-            } else {
-                Plic::disable(u_context, *device_id);
+            if waited.contains_key(device_id) {
+                continue;
             }
+            route_running_ext_int(
+                *device_id,
+                *is_enabled,
+                hart_id,
+                affinity.get(device_id).cloned(),
+            );
         }
         unsafe {
             asm!("fence iorw,iorw");
@@ -84,12 +186,25 @@ impl UserTrapInfo {
         // push_trace(DISABLE_USER_EXT_INT_ENTER);
 
         let hart_id = hart_id();
+        if self.threshold != 0 {
+            set_context_threshold(get_context(hart_id, 'U'), 0);
+        }
+        let waited = WAITED_EXT_INT_MAP.lock();
+        let affinity = EXT_INT_AFFINITY_MAP.lock();
         for (device_id, is_enabled) in &self.devices {
+            if waited.contains_key(device_id) {
+                continue;
+            }
             Plic::disable(get_context(hart_id, 'U'), *device_id);
+            // queued by the kernel on the pinned hart, or on this one
+            let target = affinity.get(device_id).cloned().unwrap_or(hart_id);
+            if target != hart_id {
+                Plic::disable(get_context(hart_id, 'S'), *device_id);
+            }
             if *is_enabled {
-                Plic::enable(get_context(hart_id, 'S'), *device_id);
+                Plic::enable(get_context(target, 'S'), *device_id);
             } else {
-                Plic::disable(get_context(hart_id, 'S'), *device_id);
+                Plic::disable(get_context(target, 'S'), *device_id);
             }
         }
         unsafe {
@@ -125,6 +240,17 @@ impl UserTrapInfo {
                 int_map.remove(device_id);
             }
         }
+        drop(int_map);
+        let mut affinity = EXT_INT_AFFINITY_MAP.lock();
+        for (device_id, _) in &self.devices {
+            affinity.remove(device_id);
+            set_source_priority(*device_id, PLIC_DEFAULT_PRIORITY);
+        }
+        drop(affinity);
+        for (device_id, _) in &self.devices {
+            warn!("[user trap] device {} still claimed on exit or exec", device_id);
+            crate::uart::reclaim(*device_id);
+        }
     }
 
     pub fn get_trap_queue(&self) -> &UserTrapQueue {
@@ -142,6 +268,39 @@ impl UserTrapInfo {
 
 lazy_static! {
     pub static ref USER_EXT_INT_MAP: Mutex<BTreeMap<u16, usize>> = Mutex::new(BTreeMap::new());
+    /// Devices a process is blocked on in `sys_wait_ext_int`, and whether
+    /// they fired since the wait began.
+    pub static ref WAITED_EXT_INT_MAP: Mutex<BTreeMap<u16, bool>> = Mutex::new(BTreeMap::new());
+    /// Harts that devices were pinned to with `sys_set_ext_int_affinity`.
+    pub static ref EXT_INT_AFFINITY_MAP: Mutex<BTreeMap<u16, usize>> = Mutex::new(BTreeMap::new());
+}
+
+/// Routes a device of the process running on `hart`. It is taken in U mode
+/// on `hart`, unless pinned to another hart, where the kernel queues it.
+pub fn route_running_ext_int(
+    device_id: u16,
+    is_enabled: bool,
+    hart: usize,
+    affinity: Option<usize>,
+) {
+    for hart_id in 0..CPU_NUM {
+        Plic::disable(get_context(hart_id, 'S'), device_id);
+    }
+    match affinity {
+        Some(target) if target != hart => {
+            Plic::disable(get_context(hart, 'U'), device_id);
+            if is_enabled {
+                Plic::enable(get_context(target, 'S'), device_id);
+            }
+        }
+        _ => {
+            if is_enabled {
+                Plic::enable(get_context(hart, 'U'), device_id);
+            } else {
+                Plic::disable(get_context(hart, 'U'), device_id);
+            }
+        }
+    }
 }
 
 pub fn push_trap_record(pid: usize, trap_record: UserTrapRecord) -> Result<(), UserTrapError> {
@@ -150,6 +309,19 @@ pub fn push_trap_record(pid: usize, trap_record: UserTrapRecord) -> Result<(), U
         "[push trap record] pid: {}, cause: {}, message: {}",
         pid, trap_record.cause, trap_record.message
     );
+    with_trap_info(pid, |trap_info| trap_info.push_trap_record(trap_record))
+}
+
+/// `UserTrapInfo::forward_ext_int` to process `pid`.
+pub fn forward_ext_int(pid: usize, irq: u16) -> Result<(), UserTrapError> {
+    push_trace(PUSH_TRAP_RECORD_ENTER + pid);
+    with_trap_info(pid, |trap_info| trap_info.forward_ext_int(irq))
+}
+
+fn with_trap_info(
+    pid: usize,
+    f: impl FnOnce(&mut UserTrapInfo) -> Result<(), UserTrapError>,
+) -> Result<(), UserTrapError> {
     if let Some(tcb) = crate::task::find_task(pid) {
         let mut tcb_inner = tcb.acquire_inner_lock();
         if !tcb_inner.is_user_trap_enabled() {
@@ -157,7 +329,7 @@ pub fn push_trap_record(pid: usize, trap_record: UserTrapRecord) -> Result<(), U
             // return Err(UserTrapError::TrapDisabled);
         }
         if let Some(trap_info) = &mut tcb_inner.user_trap_info {
-            let res = trap_info.push_trap_record(trap_record);
+            let res = f(trap_info);
             // if let Running(task_hart_id) = tcb_inner.task_status {
             //     if task_hart_id != hart_id() {
             //         let mask: usize = 1 << task_hart_id;
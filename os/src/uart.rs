@@ -1,5 +1,11 @@
+use crate::console_ring::CONSOLE_SERIAL_ID;
+use crate::sbi::console_putchar;
 use alloc::collections::VecDeque;
 use core::convert::Infallible;
+use core::sync::atomic::{
+    AtomicBool,
+    Ordering::{Acquire, Relaxed, Release},
+};
 use embedded_hal::serial::{Read, Write};
 use lazy_static::*;
 use spin::Mutex;
@@ -7,6 +13,9 @@ use spin::Mutex;
 pub const DEFAULT_TX_BUFFER_SIZE: usize = 1_000;
 pub const DEFAULT_RX_BUFFER_SIZE: usize = 1_000;
 
+mod reg_bits;
+use reg_bits::*;
+
 #[cfg(feature = "board_qemu")]
 mod serial_config {
     pub use uart8250::{InterruptType, MmioUart8250};
@@ -15,6 +24,9 @@ mod serial_config {
     pub const SERIAL_NUM: usize = 4;
     pub const SERIAL_BASE_ADDRESS: usize = 0x1000_2000;
     pub const SERIAL_ADDRESS_STRIDE: usize = 0x1000;
+    /// Byte wide registers, one after the other.
+    pub type Register = u8;
+    pub const REG_SHIFT: usize = 0;
     pub fn irq_to_serial_id(irq: u16) -> usize {
         match irq {
             12 => 0,
@@ -24,6 +36,15 @@ mod serial_config {
             _ => 0,
         }
     }
+
+    /// Serial 0 is the kernel console, the rest may be claimed by users.
+    pub fn is_user_serial_irq(irq: u16) -> bool {
+        matches!(irq, 13 | 14 | 15)
+    }
+
+    pub fn serial_id_to_irq(serial_id: usize) -> u16 {
+        serial_id as u16 + 12
+    }
 }
 
 #[cfg(feature = "board_lrv")]
@@ -34,6 +55,9 @@ mod serial_config {
     pub const SERIAL_NUM: usize = 4;
     pub const SERIAL_BASE_ADDRESS: usize = 0x6000_1000;
     pub const SERIAL_ADDRESS_STRIDE: usize = 0x1000;
+    /// AXI registers, a 32 bit word each.
+    pub type Register = u32;
+    pub const REG_SHIFT: usize = 2;
     pub fn irq_to_serial_id(irq: u16) -> usize {
         match irq {
             4 => 0,
@@ -43,6 +67,14 @@ mod serial_config {
             _ => 0,
         }
     }
+
+    pub fn is_user_serial_irq(irq: u16) -> bool {
+        matches!(irq, 5 | 6 | 7)
+    }
+
+    pub fn serial_id_to_irq(serial_id: usize) -> u16 {
+        serial_id as u16 + 4
+    }
 }
 
 pub use serial_config::*;
@@ -50,6 +82,58 @@ pub use serial_config::*;
 pub fn get_base_addr_from_irq(irq: u16) -> usize {
     SERIAL_BASE_ADDRESS + irq_to_serial_id(irq) * SERIAL_ADDRESS_STRIDE
 }
+
+pub const SERIAL_PORT_CLAIMABLE: usize = 1 << 0;
+pub const SERIAL_PORT_CLAIMED: usize = 1 << 1;
+/// `probe` found nothing at the port's address.
+pub const SERIAL_PORT_ABSENT: usize = 1 << 2;
+
+/// One entry of `sys_enumerate_serial`, shared with the user library.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SerialPortInfo {
+    pub index: usize,
+    pub irq: usize,
+    pub phys_base: usize,
+    /// Where the registers are mapped for the caller, 0 if not claimed by it.
+    pub virt_base: usize,
+    pub flags: usize,
+    /// Register `n` is at `n << reg_shift`, accessed `reg_width` bytes at
+    /// a time.
+    pub reg_shift: usize,
+    pub reg_width: usize,
+}
+
+/// IER was not read, LCR.DLAB was set and its offset holds the divisor.
+pub const UART_SNAPSHOT_IER_SKIPPED: usize = 1 << 0;
+/// Error bits of LSR were set, the read cleared them.
+pub const UART_SNAPSHOT_LSR_CLEARED: usize = 1 << 1;
+/// Delta bits of MSR were set, the read cleared them.
+pub const UART_SNAPSHOT_MSR_CLEARED: usize = 1 << 2;
+/// A user process holds the port.
+pub const UART_SNAPSHOT_CLAIMED: usize = 1 << 3;
+
+/// Result of `sys_dump_serial_regs`, shared with the user library.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UartRegSnapshot {
+    pub index: usize,
+    pub ier: usize,
+    pub lcr: usize,
+    pub mcr: usize,
+    pub lsr: usize,
+    pub msr: usize,
+    pub flags: usize,
+}
+
+/// The last interrupt `interrupt_handler` had nothing to do for, to be
+/// logged once the driver is unlocked: the console log goes through it.
+#[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
+pub enum Unhandled {
+    ModemStatus { msr: usize, lsr: usize, ier: usize },
+    Other(InterruptType),
+}
+
 pub struct BufferedSerial {
     pub hardware: SerialHardware,
     pub rx_buffer: VecDeque<u8>,
@@ -60,6 +144,14 @@ pub struct BufferedSerial {
     pub rx_intr_count: usize,
     pub tx_intr_count: usize,
     pub tx_fifo_count: usize,
+    /// Bytes received with the rx buffer full, and thrown away.
+    pub rx_dropped: usize,
+    pub overrun_count: usize,
+    pub parity_error_count: usize,
+    pub framing_error_count: usize,
+    pub break_count: usize,
+    /// `LSR_*` error bits seen since `take_line_errors`.
+    line_errors: u8,
     rx_intr_enabled: bool,
     tx_intr_enabled: bool,
 }
@@ -76,6 +168,12 @@ impl BufferedSerial {
             rx_intr_count: 0,
             tx_intr_count: 0,
             tx_fifo_count: 0,
+            rx_dropped: 0,
+            overrun_count: 0,
+            parity_error_count: 0,
+            framing_error_count: 0,
+            break_count: 0,
+            line_errors: 0,
             rx_intr_enabled: false,
             tx_intr_enabled: false,
         }
@@ -95,23 +193,33 @@ impl BufferedSerial {
     }
 
     #[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
-    pub fn interrupt_handler(&mut self) {
+    pub fn interrupt_handler(&mut self) -> Option<Unhandled> {
         let hardware = &self.hardware;
+        let mut unhandled = None;
         while let Some(int_type) = hardware.read_interrupt_type() {
             self.intr_count += 1;
             match int_type {
                 InterruptType::ReceivedDataAvailable | InterruptType::Timeout => {
                     // trace!("Received data available");
                     self.rx_intr_count += 1;
-                    while let Some(ch) = hardware.read_byte() {
-                        if self.rx_buffer.len() < DEFAULT_TX_BUFFER_SIZE {
+                    // a full buffer drops what comes in, the interrupt
+                    // stays on and nothing is left to overrun the FIFO
+                    loop {
+                        // the error bits are those of the byte read next,
+                        // and cleared by this read
+                        let lsr = hardware.read_lsr() as u8;
+                        if lsr & LSR_ERROR_BITS != 0 {
+                            self.count_line_errors(lsr);
+                        }
+                        let ch = match hardware.read_byte() {
+                            Some(ch) => ch,
+                            None => break,
+                        };
+                        if self.rx_buffer.len() < DEFAULT_RX_BUFFER_SIZE {
                             self.rx_buffer.push_back(ch);
                             self.rx_count += 1;
                         } else {
-                            // warn!("Serial rx buffer overflow!");
-                            hardware.disable_received_data_available_interrupt();
-                            self.rx_intr_enabled = false;
-                            break;
+                            self.rx_dropped += 1;
                         }
                     }
                 }
@@ -130,19 +238,84 @@ impl BufferedSerial {
                     }
                 }
                 InterruptType::ModemStatus => {
-                    let msr = hardware.read_msr();
-                    debug!(
-                        "MSR: {:#x}, LSR: {:#x}, IER: {:#x}",
-                        msr,
-                        hardware.read_lsr(),
-                        hardware.read_ier()
-                    );
+                    unhandled = Some(Unhandled::ModemStatus {
+                        msr: hardware.read_msr() as usize,
+                        lsr: hardware.read_lsr() as usize,
+                        ier: hardware.read_ier() as usize,
+                    });
                 }
                 _ => {
-                    warn!("[SERIAL] {:?} not supported!", int_type);
+                    unhandled = Some(Unhandled::Other(int_type));
                 }
             }
         }
+        unhandled
+    }
+
+    fn count_line_errors(&mut self, lsr: u8) {
+        self.overrun_count += (lsr & LSR_OE != 0) as usize;
+        self.parity_error_count += (lsr & LSR_PE != 0) as usize;
+        self.framing_error_count += (lsr & LSR_FE != 0) as usize;
+        self.break_count += (lsr & LSR_BI != 0) as usize;
+        self.line_errors |= lsr & LSR_ERROR_BITS;
+    }
+
+    /// The error bits seen since the last call.
+    pub fn take_line_errors(&mut self) -> u8 {
+        core::mem::take(&mut self.line_errors)
+    }
+
+    /// Overruns, parity errors, framing errors and breaks so far.
+    pub fn line_error_counts(&self) -> [usize; 4] {
+        [
+            self.overrun_count,
+            self.parity_error_count,
+            self.framing_error_count,
+            self.break_count,
+        ]
+    }
+
+    /// Queues as much of `bytes` as the tx buffer takes, and has the THR
+    /// empty interrupt send it. Returns how much was queued.
+    #[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
+    pub fn queue(&mut self, bytes: &[u8]) -> usize {
+        let len = bytes
+            .len()
+            .min(DEFAULT_TX_BUFFER_SIZE - self.tx_buffer.len());
+        if len == 0 {
+            return 0;
+        }
+        self.tx_buffer.extend(&bytes[..len]);
+        if !self.tx_intr_enabled {
+            self.hardware
+                .enable_transmitter_holding_register_empty_interrupt();
+            self.tx_intr_enabled = true;
+        }
+        len
+    }
+
+    /// Sends the tx buffer by polling, for when the interrupt can't be
+    /// waited for.
+    pub fn drain_sync(&mut self) {
+        self.tx_count += write_polled(&self.hardware, self.tx_buffer.drain(..));
+    }
+}
+
+/// Writes `bytes` by polling, a FIFO full each time LSR.THRE says it is
+/// empty. Returns how many were written.
+fn write_polled(hardware: &SerialHardware, mut bytes: impl Iterator<Item = u8>) -> usize {
+    let mut count = 0;
+    loop {
+        while hardware.read_lsr() as u8 & LSR_THRE == 0 {
+            core::hint::spin_loop();
+        }
+        for _ in 0..FIFO_DEPTH {
+            match bytes.next() {
+                Some(ch) => hardware.write_byte(ch),
+                None => return count,
+            }
+            count += 1;
+        }
     }
 }
 
@@ -151,22 +324,19 @@ impl Write<u8> for BufferedSerial {
 
     #[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
     fn try_write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
-        let serial = &mut self.hardware;
-        if self.tx_buffer.len() < DEFAULT_TX_BUFFER_SIZE {
-            self.tx_buffer.push_back(word);
-            if !self.tx_intr_enabled {
-                serial.enable_transmitter_holding_register_empty_interrupt();
-                self.tx_intr_enabled = true;
-            }
-        } else {
-            // warn!("Serial tx buffer overflow!");
+        if self.queue(&[word]) == 0 {
             return Err(nb::Error::WouldBlock);
         }
         Ok(())
     }
 
+    /// Done once the buffer is empty and the transmitter idle.
     fn try_flush(&mut self) -> nb::Result<(), Self::Error> {
-        todo!()
+        if self.tx_buffer.is_empty() && self.hardware.read_lsr() as u8 & LSR_TEMT != 0 {
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
     }
 }
 
@@ -215,15 +385,108 @@ lazy_static! {
         Arc::new(Mutex::new(MmioSerialAxiLite::new(0x6000_1000)));
 }
 
+fn kernel_baud_rate(serial_id: usize) -> usize {
+    if serial_id < 2 {
+        115200
+    } else {
+        6_250_000
+        // 1_250_000
+    }
+}
+
+/// Scratch register index.
+const SCR: usize = 7;
+/// Two patterns, so a bus that returns the last value driven on it can't
+/// pass for a register.
+const PROBE_PATTERNS: [u8; 2] = [0x55, 0xaa];
+
+/// Whether a UART answers at `base_address`: its scratch register keeps
+/// what is written to it. Some LRV bitstreams leave instances out, and
+/// writes to those go nowhere. The old scratch value is put back.
+pub fn probe(base_address: usize) -> bool {
+    let scr = (base_address + (SCR << REG_SHIFT)) as *mut Register;
+    unsafe {
+        let saved = scr.read_volatile();
+        let present = PROBE_PATTERNS.iter().all(|&pattern| {
+            scr.write_volatile(pattern as Register);
+            scr.read_volatile() as u8 == pattern
+        });
+        scr.write_volatile(saved);
+        present
+    }
+}
+
+const NOT_PRESENT: AtomicBool = AtomicBool::new(false);
+/// Probe results of `init`.
+static PRESENT: [AtomicBool; SERIAL_NUM] = [NOT_PRESENT; SERIAL_NUM];
+
+pub fn is_present(serial_id: usize) -> bool {
+    PRESENT[serial_id].load(Relaxed)
+}
+
+/// Set by `init` once it drives the console, output goes through the SBI
+/// before.
+static CONSOLE_READY: AtomicBool = AtomicBool::new(false);
+/// Set on panic, the console is written synchronously from then on.
+static CONSOLE_SYNC: AtomicBool = AtomicBool::new(false);
+
+/// Probes every serial and sets up the ones found. The rest are left
+/// alone, and can't be claimed.
 #[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
 pub fn init() {
-    for serial_id in 0..2 {
-        BUFFERED_SERIAL[serial_id].lock().hardware_init(115200);
+    for serial_id in 0..SERIAL_NUM {
+        if !probe(SERIAL_BASE_ADDRESS + serial_id * SERIAL_ADDRESS_STRIDE) {
+            warn!("[uart] serial {} not present", serial_id);
+            continue;
+        }
+        PRESENT[serial_id].store(true, Relaxed);
+        BUFFERED_SERIAL[serial_id]
+            .lock()
+            .hardware_init(kernel_baud_rate(serial_id));
     }
-    for serial_id in 2..SERIAL_NUM {
-        BUFFERED_SERIAL[serial_id].lock().hardware_init(6_250_000);
-        // BUFFERED_SERIAL[serial_id].lock().hardware_init(1_250_000);
+    CONSOLE_READY.store(is_present(CONSOLE_SERIAL_ID), Release);
+}
+
+/// Puts a serial given up by a user process back under the kernel driver.
+/// Whatever the user left in IER, FCR and MCR is reset, and the old driver
+/// state is thrown away.
+#[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
+pub fn reclaim(irq: u16) {
+    let serial_id = irq_to_serial_id(irq);
+    let mut serial = BUFFERED_SERIAL[serial_id].lock();
+    *serial = BufferedSerial::new(get_base_addr_from_irq(irq));
+    serial.hardware_init(kernel_baud_rate(serial_id));
+    info!("[uart] serial {} reclaimed by kernel", serial_id);
+}
+
+/// Reads the registers of a serial for debugging, whoever drives it. The
+/// ones without read side effects go first, then LSR and MSR, whose reads
+/// clear the bits `flags` reports. RBR and IIR are never read: that would
+/// take a received byte or a pending tx interrupt from the driver.
+#[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
+pub fn snapshot(serial_id: usize) -> UartRegSnapshot {
+    let serial = BUFFERED_SERIAL[serial_id].lock();
+    let hardware = &serial.hardware;
+    let mut snapshot = UartRegSnapshot {
+        index: serial_id,
+        ..Default::default()
+    };
+    snapshot.lcr = hardware.read_lcr() as usize;
+    if snapshot.lcr & LCR_DLAB as usize == 0 {
+        snapshot.ier = hardware.read_ier() as usize;
+    } else {
+        snapshot.flags |= UART_SNAPSHOT_IER_SKIPPED;
+    }
+    snapshot.mcr = hardware.read_mcr() as usize;
+    snapshot.lsr = hardware.read_lsr() as usize;
+    if snapshot.lsr & LSR_ERROR_BITS as usize != 0 {
+        snapshot.flags |= UART_SNAPSHOT_LSR_CLEARED;
     }
+    snapshot.msr = hardware.read_msr() as usize;
+    if snapshot.msr & MSR_DELTA_BITS as usize != 0 {
+        snapshot.flags |= UART_SNAPSHOT_MSR_CLEARED;
+    }
+    snapshot
 }
 
 #[cfg(feature = "board_lrv_seriallite")]
@@ -232,9 +495,29 @@ pub fn init() {
 }
 
 pub fn handle_interrupt(irq: u16) {
-    BUFFERED_SERIAL[irq_to_serial_id(irq)]
-        .lock()
-        .interrupt_handler();
+    use crate::console_ring;
+    let serial_id = irq_to_serial_id(irq);
+    let mut serial = BUFFERED_SERIAL[serial_id].lock();
+    let unhandled = serial.interrupt_handler();
+    let line_errors = serial.take_line_errors();
+    if serial_id == CONSOLE_SERIAL_ID && console_ring::fill(&mut serial.rx_buffer) {
+        drop(serial);
+        console_ring::ring_doorbells(irq);
+    } else {
+        drop(serial);
+    }
+    if serial_id == CONSOLE_SERIAL_ID && line_errors != 0 {
+        console_ring::notify_line_errors(line_errors);
+    }
+    match unhandled {
+        Some(Unhandled::ModemStatus { msr, lsr, ier }) => {
+            debug!("MSR: {:#x}, LSR: {:#x}, IER: {:#x}", msr, lsr, ier);
+        }
+        Some(Unhandled::Other(int_type)) => {
+            warn!("[SERIAL] {:?} not supported!", int_type);
+        }
+        None => {}
+    }
 }
 
 #[cfg(feature = "board_lrv_seriallite")]
@@ -274,3 +557,82 @@ pub fn serial_putchar(serial_id: usize, c: u8) -> nb::Result<(), Infallible> {
 pub fn serial_getchar(serial_id: usize) -> nb::Result<u8, Infallible> {
     BUFFERED_SERIAL[serial_id].lock().try_read()
 }
+
+/// Queues as much of `bytes` as the console tx buffer takes, the THR empty
+/// interrupt sends it. Never waits, returns how much was queued.
+#[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
+pub fn console_try_write(bytes: &[u8]) -> usize {
+    BUFFERED_SERIAL[CONSOLE_SERIAL_ID].lock().queue(bytes)
+}
+
+/// Writes all of `bytes` to the console, through `console_try_write`.
+/// What doesn't fit is made room for by sending the buffer by polling,
+/// so nothing is dropped even with interrupts off.
+#[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
+pub fn console_write(bytes: &[u8]) {
+    if !CONSOLE_READY.load(Acquire) {
+        bytes.iter().for_each(|&ch| console_putchar(ch as usize));
+        return;
+    }
+    if CONSOLE_SYNC.load(Relaxed) {
+        console_write_sync(bytes);
+        return;
+    }
+    let mut rest = bytes;
+    loop {
+        rest = &rest[console_try_write(rest)..];
+        if rest.is_empty() {
+            break;
+        }
+        BUFFERED_SERIAL[CONSOLE_SERIAL_ID].lock().drain_sync();
+    }
+}
+
+/// The escape hatch for panics: what is queued is sent now, and the
+/// console is written synchronously from then on.
+#[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
+pub fn console_set_sync() {
+    CONSOLE_SYNC.store(true, Relaxed);
+    console_write_sync(&[]);
+}
+
+pub fn console_is_sync() -> bool {
+    CONSOLE_SYNC.load(Relaxed)
+}
+
+/// Polls `bytes` out after the tx buffer. The hart that panicked may hold
+/// the lock, the registers are then written around the driver.
+#[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
+fn console_write_sync(bytes: &[u8]) {
+    if !CONSOLE_READY.load(Acquire) {
+        bytes.iter().for_each(|&ch| console_putchar(ch as usize));
+        return;
+    }
+    match BUFFERED_SERIAL[CONSOLE_SERIAL_ID].try_lock() {
+        Some(mut serial) => {
+            serial.drain_sync();
+            let count = write_polled(&serial.hardware, bytes.iter().copied());
+            serial.tx_count += count;
+        }
+        None => {
+            let hardware = SerialHardware::new(
+                SERIAL_BASE_ADDRESS + CONSOLE_SERIAL_ID * SERIAL_ADDRESS_STRIDE,
+            );
+            write_polled(&hardware, bytes.iter().copied());
+        }
+    }
+}
+
+/// Waits for the console transmitter to go idle, e.g. before a shutdown
+/// cuts off what is still in the FIFO.
+#[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
+pub fn console_wait_idle() {
+    if !CONSOLE_READY.load(Acquire) {
+        return;
+    }
+    let hardware =
+        SerialHardware::new(SERIAL_BASE_ADDRESS + CONSOLE_SERIAL_ID * SERIAL_ADDRESS_STRIDE);
+    while hardware.read_lsr() as u8 & LSR_TEMT == 0 {
+        core::hint::spin_loop();
+    }
+}
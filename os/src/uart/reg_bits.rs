@@ -0,0 +1,21 @@
+// The 16550 register bits the kernel driver uses. The user driver keeps
+// its own full set in user/src/user_uart/reg_bits.rs.
+
+pub const LCR_DLAB: u8 = 1 << 7;
+
+pub const LSR_OE: u8 = 1 << 1;
+pub const LSR_PE: u8 = 1 << 2;
+pub const LSR_FE: u8 = 1 << 3;
+pub const LSR_BI: u8 = 1 << 4;
+pub const LSR_THRE: u8 = 1 << 5;
+pub const LSR_TEMT: u8 = 1 << 6;
+pub const LSR_FIFO_ERROR: u8 = 1 << 7;
+/// OE, PE, FE, BI and the rx FIFO error bit.
+pub const LSR_ERROR_BITS: u8 = LSR_OE | LSR_PE | LSR_FE | LSR_BI | LSR_FIFO_ERROR;
+
+pub const MSR_DCTS: u8 = 1 << 0;
+pub const MSR_DDSR: u8 = 1 << 1;
+pub const MSR_TERI: u8 = 1 << 2;
+pub const MSR_DDCD: u8 = 1 << 3;
+/// DCTS, DDSR, TERI and DDCD.
+pub const MSR_DELTA_BITS: u8 = MSR_DCTS | MSR_DDSR | MSR_TERI | MSR_DDCD;
@@ -179,6 +179,8 @@ if __name__ == "__main__":
     u_trap = {}
     syscall = {}
     sercall = {}
+    # serial interrupts per hart, and hart switches between two of them
+    serintr = {}
     syscall2 = {}
     for hart in range(4):
         s_trap[hart] = defaultdict(list)
@@ -232,11 +234,29 @@ if __name__ == "__main__":
                 if p in accept_pid:
                     if event_subtype(e) == 2 or event_subtype(e) == 3:
                         sercall[p][extra(e)].append((e, c))
+                    if event_subtype(e) == 0:
+                        if p not in serintr:
+                            serintr[p] = {
+                                "harts": defaultdict(int),
+                                "last": None,
+                                "switches": 0,
+                            }
+                        intr = serintr[p]
+                        intr["harts"][hartid(e)] += 1
+                        if intr["last"] is not None and intr["last"] != hartid(e):
+                            intr["switches"] += 1
+                        intr["last"] = hartid(e)
 
         s_trap_stat = trap_rec_stat(s_trap, 2, 3)
         u_trap_stat = trap_rec_stat(u_trap, 8, 9)
         syscall_stat = syscall_stat(syscall, 0, 1)
         sercall_stat = serial_stat(sercall, 2, 3)
+        for p, intr in serintr.items():
+            print(
+                "pid: {}, serial interrupts per hart: {}, hart switches: {}".format(
+                    p, dict(intr["harts"]), intr["switches"]
+                )
+            )
 
         it1 = iter(syscall2[4])
         got = {"READ": False, "WRITE": False}
@@ -69,6 +69,20 @@ event_map = {
             0x7: {"name": "cts"},
             0x8: {"name": "tx"},
             0x9: {"name": "rx"},
+            0xA: {"name": "watchdog"},
+            0xB: {"name": "rx data"},
+            0xC: {"name": "rx timeout"},
+            0xD: {"name": "overrun"},
+            0xE: {"name": "intr bailout"},
+            0xF: {"name": "drop"},
+        },
+    },
+    0x5E1B: {
+        "name": "serial handler",
+        "sub_event": {
+            0x0: {"name": "rx hook enter"},
+            0x1: {"name": "rx hook exit"},
+            0x2: {"name": "intr over budget"},
         },
     },
     0x911C: {
@@ -90,6 +104,11 @@ event_map = {
             0x5: {"name": "write wake"},
             0x6: {"name": "intr poll"},
             0x7: {"name": "intr wake"},
+            0x8: {"name": "task poll enter"},
+            0x9: {"name": "task poll exit"},
+            0xA: {"name": "future poll enter"},
+            0xB: {"name": "future poll exit"},
+            0xC: {"name": "future ready"},
         },
     },
     0x315C: {
@@ -148,6 +167,19 @@ syscall_name = {
     602: "SET_TIMER",
     603: "CLAIM_EXT_INT",
     604: "SET_EXT_INT_ENABLE",
+    605: "RELEASE_EXT_INT",
+    606: "ENUMERATE_SERIAL",
+    607: "WAIT_EXT_INT",
+    608: "SET_EXT_INT_AFFINITY",
+    609: "SET_EXT_INT_PRIORITY",
+    610: "SET_EXT_INT_THRESHOLD",
+    611: "TRANSFER_EXT_INT",
+    612: "OPEN_CONSOLE_RING",
+    613: "WAIT_CONSOLE_RING",
+    614: "CLOSE_CONSOLE_RING",
+    615: "GET_EXT_INT_STATS",
+    616: "DUMP_SERIAL_REGS",
+    617: "DROP_CAPS",
 }
 
 serial_call_name = {
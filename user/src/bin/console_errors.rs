@@ -0,0 +1,89 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::console::{self, CONSOLE_ERROR_MSG};
+use user_lib::{
+    getpid, init_user_trap, irq, listen_console_errors, send_msg,
+    timer::now_us,
+    user_uart::{regs::*, serial, SerialEventBus, SerialEventKind},
+};
+
+/// How long to wait for the events of a message.
+const EVENT_TIMEOUT_US: usize = 1_000_000;
+
+fn check(name: &str, ok: bool) -> bool {
+    println!(
+        "[console errors] {}: {}",
+        name,
+        if ok { "ok" } else { "FAILED" }
+    );
+    ok
+}
+
+fn wait_events(bus: &SerialEventBus, count: usize) -> Vec<SerialEventKind> {
+    let mut kinds = Vec::new();
+    let start = now_us();
+    while kinds.len() < count && now_us() - start < EVENT_TIMEOUT_US {
+        if let Some(event) = bus.try_next() {
+            if event.port == 0 {
+                kinds.push(event.kind);
+            }
+        }
+    }
+    kinds
+}
+
+/// Line errors can't be made on the console here, so the kernel's message
+/// is sent by hand: the events it turns into, registration and the
+/// kernel's counters.
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut ok = check("needs user traps", console::error_events().is_err());
+    init_user_trap();
+    let bus = match console::error_events() {
+        Ok(bus) => bus,
+        Err(err) => {
+            println!("[console errors] listen failed: {}", err);
+            return -1;
+        }
+    };
+
+    send_msg(
+        getpid() as usize,
+        CONSOLE_ERROR_MSG | (LSR_OE | LSR_PE) as usize,
+    );
+    ok &= check(
+        "message turned into events",
+        wait_events(&bus, 2) == [SerialEventKind::Overrun, SerialEventKind::ParityError],
+    );
+    send_msg(
+        getpid() as usize,
+        CONSOLE_ERROR_MSG | (LSR_FE | LSR_BI) as usize,
+    );
+    ok &= check(
+        "framing error and break",
+        wait_events(&bus, 2) == [SerialEventKind::FramingError, SerialEventKind::Break],
+    );
+
+    console::stop_error_events();
+    ok &= check("stopped", listen_console_errors(false) == -3);
+    ok &= check("listening again", console::error_events().is_ok());
+    console::stop_error_events();
+
+    let irq = serial::port_info(0).map_or(0, |port| port.irq());
+    let stats = irq::kernel_stats(irq);
+    println!(
+        "[console errors] kernel saw {} overruns, {} parity errors, {} framing errors, {} breaks",
+        stats.overruns, stats.parity_errors, stats.framing_errors, stats.breaks
+    );
+    if ok {
+        0
+    } else {
+        -1
+    }
+}
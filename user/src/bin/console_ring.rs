@@ -0,0 +1,84 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::sync::Arc;
+use riscv::register::uie;
+use spin::Once;
+use user_lib::{executor::block_on, get_time, init_user_trap, user_uart::*};
+
+const LINE_LEN: usize = 64;
+const IDLE_TIMEOUT_US: usize = 50_000;
+
+static CONSOLE: Once<Arc<ConsoleAsync>> = Once::new();
+
+/// Read the console through the shared ring, once blocking and once with
+/// the doorbell, without claiming it.
+#[no_mangle]
+pub fn main() -> i32 {
+    init_user_trap();
+    let console = match ConsoleAsync::open() {
+        Ok(console) => Arc::new(console),
+        Err(err) => {
+            println!("[console ring] open failed: {}", err);
+            return -1;
+        }
+    };
+    CONSOLE.call_once(|| console.clone());
+    if ConsoleAsync::open().is_ok() {
+        println!("[console ring] opened twice");
+        return -1;
+    }
+
+    // nothing typed yet, this has to time out
+    let start = get_time();
+    let mut buf = [0u8; LINE_LEN];
+    let idle_len = console.read_blocking(&mut buf, IDLE_TIMEOUT_US);
+    println!(
+        "[console ring] idle read: {} bytes after {} ms",
+        idle_len,
+        get_time() - start
+    );
+
+    println!("[console ring] type a line");
+    let len = console.read_blocking(&mut buf, 0);
+    println!(
+        "[console ring] blocking read: {:?}",
+        core::str::from_utf8(&buf[..len])
+    );
+
+    println!("[console ring] type another line");
+    unsafe {
+        uie::set_usoft();
+    }
+    let mut lines = console.clone().lines::<LINE_LEN>();
+    let line = block_on(lines.next_line());
+    unsafe {
+        uie::clear_usoft();
+    }
+    println!(
+        "[console ring] async read: {:?}, doorbells {}, dropped {}",
+        line,
+        console
+            .doorbell_count
+            .load(core::sync::atomic::Ordering::Relaxed),
+        console.dropped()
+    );
+    if idle_len == 0 && len > 0 && line.is_ok() {
+        0
+    } else {
+        -1
+    }
+}
+
+#[no_mangle]
+pub fn ext_intr_handler(irq: u16, _is_from_kernel: bool) {
+    if let Some(console) = CONSOLE.get() {
+        if irq == console.irq() {
+            console.interrupt_handler();
+        }
+    }
+}
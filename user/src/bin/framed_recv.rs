@@ -0,0 +1,179 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use core::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use riscv::register::uie;
+use spin::Once;
+use user_lib::{
+    executor::{Executor, IdleStrategy},
+    init_user_trap, irq, set_ext_int_enable,
+    user_uart::{framed::*, xmodem::crc16, *},
+};
+
+// Wired to the port framed_send sends on, see uart_loopback.
+const PORT: usize = 3;
+const BAUD_RATE: usize = 115_200;
+/// The largest image taken.
+const BUFFER_SIZE: usize = 256 * 1024;
+/// What framed_send sends, checked if that is what arrives.
+const IMAGE_LEN: usize = 100_000;
+/// Largest payload framed_send puts in a frame.
+const CHUNK: usize = 512;
+/// Kind, sequence number, CRC-16.
+const OVERHEAD: usize = 5;
+
+const HEADER: u8 = b'H';
+const DATA: u8 = b'D';
+const ACK: u8 = b'A';
+
+static RESULT: Once<Result<usize, &'static str>> = Once::new();
+static DONE: AtomicBool = AtomicBool::new(false);
+
+/// Byte `index` of the test image of framed_send.
+fn pattern(index: usize) -> u8 {
+    (index.wrapping_mul(31) ^ (index >> 8)) as u8
+}
+
+async fn ack(framed: &mut FramedSerial, seq: u16) {
+    let mut frame = [ACK, 0, 0, 0, 0];
+    frame[1..3].copy_from_slice(&seq.to_le_bytes());
+    let crc = crc16(&frame[..3]);
+    frame[3..].copy_from_slice(&crc.to_be_bytes());
+    framed.send_frame(&frame).await.unwrap();
+}
+
+/// Receives frames into `buf` until the empty one that ends the image.
+/// Frames that fail their check are ignored, the sender sends them again,
+/// and ones already stored are acknowledged again.
+async fn receive(framed: &mut FramedSerial, buf: &mut [u8]) -> Result<usize, &'static str> {
+    let mut frame = [0u8; CHUNK + OVERHEAD];
+    let mut expected = 0u16;
+    let (mut len, mut image_len, mut image_crc) = (0, None, 0);
+    loop {
+        let frame_len = match framed.recv_frame(&mut frame).await {
+            Ok(frame_len) => frame_len,
+            Err(_) => continue,
+        };
+        if frame_len == 0 {
+            ack(framed, expected).await;
+            break;
+        }
+        if frame_len < OVERHEAD
+            || crc16(&frame[..frame_len - 2]).to_be_bytes() != frame[frame_len - 2..frame_len]
+        {
+            continue;
+        }
+        let seq = u16::from_le_bytes([frame[1], frame[2]]);
+        let payload = &frame[3..frame_len - 2];
+        if seq != expected {
+            if seq == expected.wrapping_sub(1) {
+                ack(framed, seq).await;
+            }
+            continue;
+        }
+        match frame[0] {
+            HEADER if seq == 0 && payload.len() == 6 => {
+                let announced =
+                    u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]) as usize;
+                if announced > buf.len() {
+                    return Err("image too large");
+                }
+                image_len = Some(announced);
+                image_crc = u16::from_le_bytes([payload[4], payload[5]]);
+            }
+            DATA if image_len.is_some() => {
+                if len + payload.len() > buf.len() {
+                    return Err("image too large");
+                }
+                buf[len..len + payload.len()].copy_from_slice(payload);
+                len += payload.len();
+            }
+            _ => continue,
+        }
+        ack(framed, seq).await;
+        expected = expected.wrapping_add(1);
+    }
+    if image_len != Some(len) {
+        return Err("image length differs from the header");
+    }
+    if crc16(&buf[..len]) != image_crc {
+        return Err("image crc differs from the header");
+    }
+    Ok(len)
+}
+
+/// Receives an image sent by framed_send into a buffer and checks it,
+/// against the test image too if it is the one framed_send sends.
+#[no_mangle]
+pub fn main() -> i32 {
+    init_user_trap();
+    let (claim, serial) = match SerialBuilder::new(PORT).baud(BAUD_RATE).build() {
+        Ok((claim, serial)) => (claim, serial.into_async().unwrap()),
+        Err(err) => {
+            println!("[framed recv] port {} failed: {:?}", PORT, err);
+            return -1;
+        }
+    };
+    serial::register(PORT, serial.clone()).unwrap();
+
+    static mut BUFFER: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE];
+    let buf = unsafe { &mut BUFFER };
+    println!(
+        "[framed recv] waiting on port {} for up to {} bytes",
+        PORT, BUFFER_SIZE
+    );
+    let exec = Executor::new(IdleStrategy::Yield);
+    let mut framed = FramedSerial::new(serial, CHUNK + OVERHEAD);
+    exec.spawn(async move {
+        let result = receive(&mut framed, buf).await;
+        RESULT.call_once(|| result);
+        DONE.store(true, Relaxed);
+    });
+    set_ext_int_enable(claim.irq() as usize, 1);
+    unsafe {
+        uie::set_uext();
+        uie::set_utimer();
+    }
+    exec.run_until(|| DONE.load(Relaxed));
+    unsafe {
+        uie::clear_uext();
+        uie::clear_utimer();
+    }
+
+    let len = match RESULT.get().unwrap() {
+        Ok(len) => *len,
+        Err(err) => {
+            println!("[framed recv] failed: {}", err);
+            return -1;
+        }
+    };
+    let image = unsafe { &BUFFER[..len] };
+    println!(
+        "[framed recv] {} bytes, crc {:#06x}, first bytes {:02x?}",
+        len,
+        crc16(image),
+        &image[..len.min(16)]
+    );
+    if len == IMAGE_LEN {
+        let bad = image
+            .iter()
+            .enumerate()
+            .filter(|&(i, &byte)| byte != pattern(i))
+            .count();
+        println!("[framed recv] test image, {} bad bytes", bad);
+        if bad > 0 {
+            return -1;
+        }
+    }
+    0
+}
+
+#[no_mangle]
+pub fn ext_intr_handler(irq: u16, _is_from_kernel: bool) {
+    serial::dispatch(irq);
+    irq::complete(irq);
+}
@@ -0,0 +1,154 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use core::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use riscv::register::uie;
+use spin::Once;
+use user_lib::{
+    executor::{Executor, IdleStrategy},
+    init_user_trap, irq, set_ext_int_enable,
+    timer::timeout,
+    user_uart::{framed::*, xmodem::crc16, *},
+};
+
+// Wired to the port framed_recv listens on, see uart_loopback.
+const PORT: usize = 2;
+const BAUD_RATE: usize = 115_200;
+/// Not a multiple of `CHUNK`, so the last frame is short.
+const IMAGE_LEN: usize = 100_000;
+const CHUNK: usize = 512;
+/// Kind, sequence number, CRC-16.
+const OVERHEAD: usize = 5;
+const MAX_RETRIES: usize = 10;
+const ANSWER_TIMEOUT_US: usize = 1_000_000;
+
+const HEADER: u8 = b'H';
+const DATA: u8 = b'D';
+const ACK: u8 = b'A';
+
+static RESULT: Once<Result<(), &'static str>> = Once::new();
+static DONE: AtomicBool = AtomicBool::new(false);
+
+/// Byte `index` of the test image, framed_recv checks for it.
+fn pattern(index: usize) -> u8 {
+    (index.wrapping_mul(31) ^ (index >> 8)) as u8
+}
+
+/// Sends `kind`, `seq` and `payload` in one frame until acknowledged. An
+/// empty frame, the end of the image, goes out without `kind` and `seq`.
+async fn exchange(
+    framed: &mut FramedSerial,
+    kind: u8,
+    seq: u16,
+    payload: &[u8],
+) -> Result<(), &'static str> {
+    let mut frame = [0u8; CHUNK + OVERHEAD];
+    let len = if kind == 0 {
+        0
+    } else {
+        frame[0] = kind;
+        frame[1..3].copy_from_slice(&seq.to_le_bytes());
+        frame[3..3 + payload.len()].copy_from_slice(payload);
+        let crc = crc16(&frame[..3 + payload.len()]);
+        frame[3 + payload.len()..5 + payload.len()].copy_from_slice(&crc.to_be_bytes());
+        payload.len() + OVERHEAD
+    };
+    let mut answer = [0u8; CHUNK + OVERHEAD];
+    for _ in 0..MAX_RETRIES {
+        framed
+            .send_frame(&frame[..len])
+            .await
+            .map_err(|_| "frame too long")?;
+        // a frame cut off by the timeout stays in `framed`
+        if let Ok(Ok(OVERHEAD)) = timeout(ANSWER_TIMEOUT_US, framed.recv_frame(&mut answer)).await {
+            if answer[0] == ACK
+                && answer[1..3] == seq.to_le_bytes()
+                && crc16(&answer[..3]).to_be_bytes() == answer[3..5]
+            {
+                return Ok(());
+            }
+        }
+    }
+    Err("no answer")
+}
+
+async fn send_image(framed: &mut FramedSerial, image: &[u8]) -> Result<(), &'static str> {
+    let mut header = [0u8; 6];
+    header[..4].copy_from_slice(&(image.len() as u32).to_le_bytes());
+    header[4..].copy_from_slice(&crc16(image).to_le_bytes());
+    exchange(framed, HEADER, 0, &header).await?;
+    let mut seq = 1;
+    for chunk in image.chunks(CHUNK) {
+        exchange(framed, DATA, seq, chunk).await?;
+        seq = seq.wrapping_add(1);
+    }
+    exchange(framed, 0, seq, &[]).await
+}
+
+/// Sends a test image of `IMAGE_LEN` bytes to framed_recv in COBS frames,
+/// each acknowledged before the next.
+#[no_mangle]
+pub fn main() -> i32 {
+    init_user_trap();
+    let (claim, serial) = match SerialBuilder::new(PORT).baud(BAUD_RATE).build() {
+        Ok((claim, serial)) => (claim, serial.into_async().unwrap()),
+        Err(err) => {
+            println!("[framed send] port {} failed: {:?}", PORT, err);
+            return -1;
+        }
+    };
+    serial::register(PORT, serial.clone()).unwrap();
+
+    // far bigger than the user heap
+    static mut IMAGE: [u8; IMAGE_LEN] = [0; IMAGE_LEN];
+    let image: &'static [u8] = unsafe {
+        for (i, byte) in IMAGE.iter_mut().enumerate() {
+            *byte = pattern(i);
+        }
+        &IMAGE
+    };
+    println!(
+        "[framed send] {} bytes, crc {:#06x}, on port {}",
+        IMAGE_LEN,
+        crc16(image),
+        PORT
+    );
+    let exec = Executor::new(IdleStrategy::Yield);
+    let mut framed = FramedSerial::new(serial, CHUNK + OVERHEAD);
+    exec.spawn(async move {
+        let result = send_image(&mut framed, image).await;
+        RESULT.call_once(|| result);
+        DONE.store(true, Relaxed);
+    });
+    set_ext_int_enable(claim.irq() as usize, 1);
+    unsafe {
+        uie::set_uext();
+        uie::set_utimer();
+    }
+    exec.run_until(|| DONE.load(Relaxed));
+    unsafe {
+        uie::clear_uext();
+        uie::clear_utimer();
+    }
+
+    match RESULT.get().unwrap() {
+        Ok(()) => {
+            println!("[framed send] done");
+            0
+        }
+        Err(err) => {
+            println!("[framed send] failed: {}", err);
+            -1
+        }
+    }
+}
+
+#[no_mangle]
+pub fn ext_intr_handler(irq: u16, _is_from_kernel: bool) {
+    serial::dispatch(irq);
+    irq::complete(irq);
+}
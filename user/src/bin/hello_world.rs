@@ -33,7 +33,7 @@ pub fn main() -> i32 {
 
 #[no_mangle]
 pub fn timer_intr_handler(time_us: usize) {
-    println!(
+    intr_println!(
         "[user trap default] user timer interrupt, time (us): {}",
         time_us
     );
@@ -0,0 +1,72 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed};
+use riscv::register::uie;
+use user_lib::{
+    console::{drain_intr_log, INTR_MSG_LEN},
+    init_user_trap, set_timer,
+    timer::now_us,
+    trap::in_interrupt,
+};
+
+const TIMER_AFTER_US: usize = 1_000;
+const WAIT_US: usize = 100_000;
+
+static FIRED: AtomicBool = AtomicBool::new(false);
+static SAW_INTERRUPT: AtomicBool = AtomicBool::new(false);
+static FIRED_AT: AtomicUsize = AtomicUsize::new(0);
+
+fn check(name: &str, ok: bool) -> bool {
+    println!("[intr log] {}: {}", name, if ok { "ok" } else { "FAILED" });
+    ok
+}
+
+/// Logs from a timer interrupt handler through `intr_println!`, then
+/// overflows the ring and cuts a long message. Each check drains before
+/// the `println!` reporting it, which would drain too.
+#[no_mangle]
+pub fn main() -> i32 {
+    init_user_trap();
+    unsafe {
+        uie::set_utimer();
+    }
+    set_timer((now_us() + TIMER_AFTER_US) as isize);
+    let start = now_us();
+    while !FIRED.load(Relaxed) && now_us() - start < WAIT_US {}
+    unsafe {
+        uie::clear_utimer();
+    }
+    let fired = FIRED.load(Relaxed);
+    let drained = drain_intr_log();
+    let mut passed = check(
+        "logged from the timer handler",
+        fired && SAW_INTERRUPT.load(Relaxed) && !in_interrupt() && drained == 1,
+    );
+
+    for i in 0..20 {
+        intr_println!("[intr log] message {}", i);
+    }
+    let drained = drain_intr_log();
+    passed &= check("full ring drops the rest", drained == 16);
+
+    let long = [b'x'; 2 * INTR_MSG_LEN];
+    intr_println!("{}", core::str::from_utf8(&long).unwrap());
+    passed &= check("long message cut", drain_intr_log() == 1);
+    if passed {
+        0
+    } else {
+        -1
+    }
+}
+
+#[no_mangle]
+pub fn timer_intr_handler(_time_us: usize) {
+    FIRED_AT.store(now_us(), Relaxed);
+    SAW_INTERRUPT.store(in_interrupt(), Relaxed);
+    intr_println!("[intr log] timer handler at {} us", FIRED_AT.load(Relaxed));
+    FIRED.store(true, Relaxed);
+}
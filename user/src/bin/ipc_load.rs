@@ -14,9 +14,8 @@ use rand_xorshift::XorShiftRng;
 use riscv::register::uie;
 use spin::Mutex;
 use user_lib::{
-    claim_ext_int, get_time, init_user_trap, mailread, mailwrite, send_msg, set_ext_int_enable,
-    set_timer, sleep,
-    trap::{get_context, hart_id, Plic},
+    claim_ext_int, get_time, init_user_trap, irq, mailread, mailwrite, send_msg,
+    set_ext_int_enable, set_timer, sleep,
 };
 
 static DST_PID: AtomicUsize = AtomicUsize::new(0);
@@ -210,7 +209,7 @@ fn uintc_test() -> (usize, usize, usize) {
 
         if HAS_INTR.load(Relaxed) {
             HAS_INTR.store(false, Relaxed);
-            Plic::complete(get_context(hart_id(), 'U'), uart_irqn);
+            irq::complete(uart_irqn);
         }
     }
     unsafe {
@@ -251,7 +250,7 @@ mod user_trap {
                 }
                 IS_INITIALIZED.store(true, Relaxed);
             } else {
-                println!("[uart load] Invalid config {:#x}!", msg);
+                intr_println!("[uart load] Invalid config {:#x}!", msg);
             }
         } else {
             let _ = MSG_QUEUE.enqueue(msg as u8);
@@ -268,7 +267,7 @@ mod user_trap {
         if irq == 0 {
             HAS_INTR.store(true, Relaxed);
         } else {
-            println!("[uart load] Unknown UEI!, irq: {}", irq);
+            intr_println!("[uart load] Unknown UEI!, irq: {}", irq);
         }
         // println!("[uart load] UEI fin");
     }
@@ -0,0 +1,111 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use user_lib::irq::{self, IrqError, IrqHandler};
+
+// Lines no device of the board uses, nothing is claimed or raised.
+const LINE_A: u16 = 60;
+const LINE_B: u16 = 61;
+const LINE_NONE: u16 = 62;
+
+#[derive(Default)]
+struct Counter {
+    count: AtomicUsize,
+    last_irq: AtomicUsize,
+}
+
+impl IrqHandler for Counter {
+    fn handle(&self, irq: u16) {
+        self.count.fetch_add(1, Relaxed);
+        self.last_irq.store(irq as usize, Relaxed);
+    }
+}
+
+fn check(name: &str, ok: bool) -> bool {
+    println!(
+        "[irq dispatch] {}: {}",
+        name,
+        if ok { "ok" } else { "FAILED" }
+    );
+    ok
+}
+
+/// An `AsyncSerial` on a `MockUart` registered like any other handler.
+#[cfg(feature = "mock_uart")]
+fn serial_handler() -> bool {
+    use user_lib::user_uart::MockUart;
+
+    let (mock, serial) = MockUart::async_serial(115_200);
+    let serial = Arc::new(serial);
+    let _registration = irq::register(LINE_NONE, serial.clone()).unwrap();
+    mock.inject_rx(b"irq");
+    let mut buf = [0u8; 4];
+    check(
+        "serial served through the table",
+        irq::dispatch(LINE_NONE) && serial.read_available(&mut buf) == 3 && &buf[..3] == b"irq",
+    )
+}
+
+#[cfg(not(feature = "mock_uart"))]
+fn serial_handler() -> bool {
+    println!("[irq dispatch] serial handler: built without the mock_uart feature, skipped");
+    true
+}
+
+/// Registers two handlers on different lines and dispatches to them by
+/// hand, as `ext_intr_handler` would.
+#[no_mangle]
+pub fn main() -> i32 {
+    let a = Arc::new(Counter::default());
+    let b = Arc::new(Counter::default());
+    let reg_a = irq::register(LINE_A, a.clone()).unwrap();
+    let reg_b = irq::register(LINE_B, b.clone()).unwrap();
+
+    let mut passed = check(
+        "second handler on a line refused",
+        irq::register(LINE_A, b.clone()).err() == Some(IrqError::AlreadyRegistered),
+    );
+    passed &= check(
+        "each line to its handler",
+        irq::dispatch(LINE_A)
+            && irq::dispatch(LINE_A)
+            && irq::dispatch(LINE_B)
+            && a.count.load(Relaxed) == 2
+            && b.count.load(Relaxed) == 1
+            && a.last_irq.load(Relaxed) == LINE_A as usize
+            && b.last_irq.load(Relaxed) == LINE_B as usize,
+    );
+
+    let unknown = irq::unknown_irqs();
+    passed &= check(
+        "unknown line counted",
+        !irq::dispatch(LINE_NONE) && irq::unknown_irqs() == unknown + 1,
+    );
+
+    drop(reg_a);
+    passed &= check(
+        "dropped registration unregisters",
+        !irq::dispatch(LINE_A) && a.count.load(Relaxed) == 2 && irq::unknown_irqs() == unknown + 2,
+    );
+    passed &= check(
+        "the other line stays",
+        reg_b.irq() == LINE_B && irq::dispatch(LINE_B) && b.count.load(Relaxed) == 2,
+    );
+    passed &= check(
+        "line free again",
+        irq::register(LINE_A, b.clone()).is_ok() && a.count.load(Relaxed) == 2,
+    );
+    passed &= serial_handler();
+
+    if passed {
+        0
+    } else {
+        -1
+    }
+}
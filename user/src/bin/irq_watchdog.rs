@@ -0,0 +1,121 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed};
+use riscv::register::uie;
+use user_lib::{
+    exit, fork, init_user_trap, irq, set_ext_int_enable, timer::now_us, user_uart::*, waitpid,
+};
+
+const PORT: usize = 1;
+const BAUD_RATE: usize = 115_200;
+const WATCHDOG_US: usize = 200_000;
+/// How long to wait for an interrupt before giving up on it.
+const INTERRUPT_TIMEOUT_US: usize = 2_000_000;
+/// What the kernel exits a process with when its watchdog fires.
+const WATCHDOG_EXIT_CODE: i32 = -4;
+
+static BASE: AtomicUsize = AtomicUsize::new(0);
+static IRQ: AtomicUsize = AtomicUsize::new(0);
+static STALL: AtomicBool = AtomicBool::new(false);
+static HANDLED: AtomicUsize = AtomicUsize::new(0);
+
+fn check(name: &str, ok: bool) -> bool {
+    println!(
+        "[irq watchdog] {}: {}",
+        name,
+        if ok { "ok" } else { "FAILED" }
+    );
+    ok
+}
+
+fn registers() -> UartMmio {
+    // from the claim held while interrupts are on
+    unsafe { UartMmio::new(BASE.load(Relaxed)) }
+}
+
+/// Raises a THR empty interrupt on the claimed port.
+fn raise(claim: &SerialClaim) {
+    let mut serial = BlockingSerial::from_claim(claim);
+    serial.hardware_init(BAUD_RATE);
+    // keep the registers as they are, the claim resets them on drop
+    core::mem::forget(serial);
+    BASE.store(claim.base_address(), Relaxed);
+    IRQ.store(claim.irq() as usize, Relaxed);
+    set_ext_int_enable(claim.irq() as usize, 1);
+    unsafe {
+        uie::set_uext();
+    }
+    registers().set_tx_interrupt(true);
+}
+
+fn wait_handled() -> bool {
+    let start = now_us();
+    while HANDLED.load(Relaxed) == 0 {
+        if now_us() - start >= INTERRUPT_TIMEOUT_US {
+            return false;
+        }
+    }
+    true
+}
+
+/// A child whose handler never returns is killed by the kernel, and the
+/// port works again for the next process to claim it. Both set a short
+/// watchdog, so a complete missed on the way out kills the parent too.
+#[no_mangle]
+pub fn main() -> i32 {
+    init_user_trap();
+    irq::set_watchdog(WATCHDOG_US);
+    let pid = fork();
+    if pid == 0 {
+        STALL.store(true, Relaxed);
+        let claim = SerialClaim::claim(PORT).unwrap();
+        raise(&claim);
+        // only if the interrupt never came
+        wait_handled();
+        exit(0);
+    }
+    let mut exit_code = 0;
+    waitpid(pid as usize, &mut exit_code);
+    let mut ok = check("stalled handler killed", exit_code == WATCHDOG_EXIT_CODE);
+
+    let claim = match SerialClaim::claim(PORT) {
+        Ok(claim) => claim,
+        Err(err) => {
+            println!("[irq watchdog] claim port {} failed: {:?}", PORT, err);
+            return -1;
+        }
+    };
+    raise(&claim);
+    ok &= check("line back", wait_handled());
+    let start = now_us();
+    while now_us() - start < 2 * WATCHDOG_US {}
+    ok &= check("completed handler left alone", HANDLED.load(Relaxed) == 1);
+    unsafe {
+        uie::clear_uext();
+    }
+    if ok {
+        0
+    } else {
+        -1
+    }
+}
+
+#[no_mangle]
+pub fn ext_intr_handler(irq: u16, _is_from_kernel: bool) {
+    let _complete = irq::complete_guard(irq);
+    if irq as usize != IRQ.load(Relaxed) {
+        return;
+    }
+    registers().set_tx_interrupt(false);
+    if STALL.load(Relaxed) {
+        // never gets out, the watchdog has to end it
+        loop {
+            core::hint::spin_loop();
+        }
+    }
+    HANDLED.fetch_add(1, Relaxed);
+}
@@ -0,0 +1,152 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::{sync::Arc, vec::Vec};
+use core::fmt::Write;
+use user_lib::{init_user_trap, timer::now_us, user_uart::*};
+
+// Wired together like in uart_loopback. The terminal runs on the first,
+// the second types into it.
+const TERMINAL_PORT: usize = 2;
+const PEER_PORT: usize = 3;
+const BAUD_RATE: usize = 115_200;
+const MODES: [Mode; 3] = [Mode::Polling, Mode::Buffered, Mode::Async];
+const TIMEOUT_US: usize = 1_000_000;
+/// How long the peer has to stay quiet before a case starts.
+const SETTLE_US: usize = 20_000;
+const LINE_SIZE: usize = 32;
+/// Typed by the peer: a typo fixed with DEL.
+const INPUT: &[u8] = b"hex\x7fy\r";
+const LINE: &[u8] = b"hey";
+/// What the peer gets back: the echo, then the reply. Less than a FIFO,
+/// so the polling driver never waits on CTS.
+const EXPECTED: &[u8] = b"hex\x08 \x08y\r\nok 3\r\n";
+
+fn report(role: &str, driver: &dyn SerialDriver) {
+    let stats = driver.stats();
+    println!(
+        "[serial driver console]   {} {:?}: rx {} tx {} intr {}",
+        role,
+        driver.mode(),
+        stats.rx_count,
+        stats.tx_count,
+        stats.intr_count
+    );
+}
+
+fn settle(peer: &mut dyn SerialDriver) {
+    let mut buf = [0u8; 16];
+    let mut quiet_since = now_us();
+    while now_us() - quiet_since < SETTLE_US {
+        peer.service();
+        if peer.read_nonblocking(&mut buf) > 0 {
+            quiet_since = now_us();
+        }
+    }
+}
+
+/// The peer types `INPUT`, the terminal answers with the length of the
+/// line it got. Both sides are pumped by hand, no interrupt is enabled.
+fn run_case(
+    terminal: &mut SerialTerminal<AnySerial, LINE_SIZE>,
+    peer: &mut dyn SerialDriver,
+) -> bool {
+    let deadline = now_us() + TIMEOUT_US;
+    if peer.write_nonblocking(INPUT) != INPUT.len() {
+        println!("[serial driver console]   input did not fit");
+        return false;
+    }
+    let line = loop {
+        peer.service();
+        if let Some(line) = terminal.poll_line() {
+            break line;
+        }
+        if now_us() >= deadline {
+            println!("[serial driver console]   no line");
+            return false;
+        }
+    };
+    let _ = writeln!(terminal, "ok {}", line.len());
+
+    let mut received = Vec::new();
+    let mut buf = [0u8; 16];
+    while received.len() < EXPECTED.len() && now_us() < deadline {
+        terminal.driver_mut().service();
+        peer.service();
+        let len = peer.read_nonblocking(&mut buf);
+        received.extend_from_slice(&buf[..len]);
+    }
+    if line != LINE {
+        println!("[serial driver console]   line {:?}", line);
+    }
+    if received != EXPECTED {
+        println!("[serial driver console]   peer got {:?}", received);
+    }
+    line == LINE && received == EXPECTED
+}
+
+/// Runs a `SerialTerminal` over each driver, typed into from `PEER_PORT`,
+/// and checks the line it reads and what it echoes and answers.
+#[no_mangle]
+pub fn main() -> i32 {
+    init_user_trap();
+    let claims = (
+        SerialClaim::claim(TERMINAL_PORT),
+        SerialClaim::claim(PEER_PORT),
+    );
+    let (terminal_claim, peer_claim) = match claims {
+        (Ok(terminal_claim), Ok(peer_claim)) => (terminal_claim, peer_claim),
+        (terminal_claim, peer_claim) => {
+            println!(
+                "[serial driver console] claim failed, port {}: {:?}, port {}: {:?}",
+                TERMINAL_PORT,
+                terminal_claim.err(),
+                PEER_PORT,
+                peer_claim.err()
+            );
+            return -1;
+        }
+    };
+    let mut peer: Arc<AsyncSerial> = SerialBuilder::new(PEER_PORT)
+        .baud(BAUD_RATE)
+        .build_on(&peer_claim)
+        .unwrap()
+        .into_async()
+        .unwrap();
+
+    let mut failed = 0;
+    for &mode in MODES.iter() {
+        let driver = SerialBuilder::new(TERMINAL_PORT)
+            .baud(BAUD_RATE)
+            .mode(mode)
+            .build_on(&terminal_claim)
+            .unwrap();
+        let mut terminal = SerialTerminal::<_, LINE_SIZE>::new(driver);
+        settle(&mut peer);
+        let ok = run_case(&mut terminal, &mut peer);
+        println!(
+            "[serial driver console] {:?}: {}",
+            mode,
+            if ok { "ok" } else { "FAILED" }
+        );
+        report("terminal", terminal.driver());
+        report("peer", &peer);
+        if !ok {
+            failed += 1;
+        }
+    }
+    println!(
+        "[serial driver console] {} of {} drivers failed",
+        failed,
+        MODES.len()
+    );
+    if failed == 0 {
+        0
+    } else {
+        -1
+    }
+}
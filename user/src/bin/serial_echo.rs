@@ -0,0 +1,157 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use riscv::register::uie;
+use user_lib::{
+    console::print,
+    executor::{Executor, IdleStrategy},
+    init_user_trap, irq, set_ext_int_enable,
+    timer::{sleep_us, timeout, TimedOut},
+    user_uart::*,
+};
+
+const PORT: usize = 1;
+const BAUD_RATE: usize = 115_200;
+const CHUNK_SIZE: usize = 64;
+const REPORT_PERIOD_US: usize = 5 * 1_000_000;
+/// Quiet time after which the echo task says the line went idle.
+const IDLE_US: usize = 2 * 1_000_000;
+/// Port the defmt frames go out on, with the `defmt` feature.
+#[cfg(feature = "defmt")]
+const DEFMT_PORT: usize = 2;
+/// Received anywhere in the stream, ends the program after it is echoed.
+const SENTINEL: &[u8] = b"\x1bquit\r";
+
+static DONE: AtomicBool = AtomicBool::new(false);
+static FLUSHED: AtomicBool = AtomicBool::new(false);
+
+/// Writes back every chunk it reads. While a write waits for room in the
+/// tx queue nothing is read, the rx queue fills and the driver stops
+/// taking bytes and drops RTS until it drains, so a fast sender is slowed
+/// down rather than losing data.
+async fn echo_task(mut reader: SerialReader, mut writer: SerialWriter) {
+    let mut buf = [0u8; CHUNK_SIZE];
+    // the last bytes seen, the sentinel may span two chunks
+    let mut tail = [0u8; SENTINEL.len()];
+    let mut idle = false;
+    loop {
+        let len = match timeout(IDLE_US, reader.read_some(&mut buf)).await {
+            Ok(len) => len,
+            Err(TimedOut) => {
+                if !idle {
+                    println!("[serial echo] idle");
+                    idle = true;
+                }
+                continue;
+            }
+        };
+        idle = false;
+        if writer.write(&buf[..len]).await.is_err() {
+            println!("[serial echo] port closed");
+            break;
+        }
+        for &ch in &buf[..len] {
+            tail.copy_within(1.., 0);
+            tail[SENTINEL.len() - 1] = ch;
+            if tail == SENTINEL {
+                // the echo of the sentinel may still be queued
+                FLUSHED.store(writer.flush(EXIT_DRAIN_TIMEOUT_US), Relaxed);
+                DONE.store(true, Relaxed);
+                return;
+            }
+        }
+    }
+}
+
+/// The line events of the last period, the rates come from a
+/// `StatsReporter`.
+async fn report_task(_serial: Arc<AsyncSerial>, bus: Arc<SerialEventBus>) {
+    loop {
+        sleep_us(REPORT_PERIOD_US).await;
+        while let Some(event) = bus.try_next() {
+            println!("[serial echo] {:?}", event.kind);
+        }
+        #[cfg(feature = "defmt")]
+        defmt::info!("{:?}", _serial.stats());
+    }
+}
+
+/// Echoes what arrives on `PORT` until `SENTINEL` comes in.
+#[no_mangle]
+pub fn main() -> i32 {
+    init_user_trap();
+    #[cfg(feature = "defmt")]
+    let _defmt_claim = match SerialBuilder::new(DEFMT_PORT)
+        .baud(BAUD_RATE)
+        .mode(Mode::Polling)
+        .build()
+    {
+        Ok((claim, serial)) => {
+            set_defmt_port(serial.into_polling());
+            claim
+        }
+        Err(err) => {
+            println!("[serial echo] defmt port {} failed: {:?}", DEFMT_PORT, err);
+            return -1;
+        }
+    };
+    let (claim, serial) = match SerialBuilder::new(PORT).baud(BAUD_RATE).build() {
+        Ok((claim, serial)) => (claim, serial.into_async().unwrap()),
+        Err(err) => {
+            println!("[serial echo] port {} failed: {:?}", PORT, err);
+            return -1;
+        }
+    };
+    let bus = Arc::new(SerialEventBus::new());
+    serial.attach_event_bus(bus.clone());
+    serial::register(PORT, serial.clone()).unwrap();
+
+    let (reader, writer) = serial.clone().split();
+    let exec = Executor::new(IdleStrategy::Yield);
+    exec.spawn(echo_task(reader, writer));
+    let report = exec.spawn(report_task(serial.clone(), bus));
+    let rates = StatsReporter::spawn(&exec, serial.clone(), REPORT_PERIOD_US, print);
+    set_ext_int_enable(claim.irq() as usize, 1);
+    unsafe {
+        uie::set_uext();
+        uie::set_usoft();
+        uie::set_utimer();
+    }
+    #[cfg(feature = "defmt")]
+    defmt::info!("echoing port {}", PORT);
+    println!(
+        "[serial echo] echoing port {}, send ESC \"quit\" CR to stop",
+        PORT
+    );
+    exec.run_until(|| DONE.load(Relaxed));
+    report.cancel();
+    rates.cancel();
+    unsafe {
+        uie::clear_uext();
+        uie::clear_usoft();
+        uie::clear_utimer();
+    }
+    let flushed = FLUSHED.load(Relaxed);
+    println!(
+        "[serial echo] done, tx flushed: {}, {:?}",
+        flushed,
+        serial.stats()
+    );
+    if flushed {
+        0
+    } else {
+        -1
+    }
+}
+
+#[no_mangle]
+pub fn ext_intr_handler(irq: u16, _is_from_kernel: bool) {
+    serial::dispatch(irq);
+    irq::complete(irq);
+}
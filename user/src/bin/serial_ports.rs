@@ -0,0 +1,58 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{init_user_trap, user_uart::*};
+
+/// List the serial ports and check the claim state the kernel reports.
+/// Ports the kernel found absent must not be claimable, and a claimed
+/// one must pass `probe`.
+#[no_mangle]
+pub fn main() -> i32 {
+    init_user_trap();
+    let ports = serial::enumerate();
+    for port in ports.iter() {
+        println!(
+            "[serial ports] #{} irq {} base {:#x} present {} claimable {} claimed {}",
+            port.index,
+            port.irq,
+            port.phys_base,
+            port.is_present(),
+            port.is_claimable(),
+            port.is_claimed()
+        );
+    }
+    for port in ports.iter().filter(|port| !port.is_present()) {
+        if SerialClaim::claim(port.index).err() != Some(ClaimError::Absent) {
+            println!("[serial ports] absent #{} could be claimed", port.index);
+            return -1;
+        }
+    }
+    let port = match ports
+        .iter()
+        .find(|port| port.is_claimable() && !port.is_claimed())
+    {
+        Some(port) => port.index,
+        None => {
+            println!("[serial ports] no free port");
+            return -1;
+        }
+    };
+    let claim = SerialClaim::claim(port).unwrap();
+    let info = serial::enumerate()[port];
+    println!(
+        "[serial ports] claimed #{}, mapped at {:#x}",
+        port, info.virt_base
+    );
+    let ok =
+        info.is_claimed() && info.virt_base == claim.base_address() && claim.registers().probe();
+    drop(claim);
+    let info = serial::enumerate()[port];
+    if ok && !info.is_claimed() && info.virt_base == 0 {
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,97 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed};
+use riscv::register::uie;
+use user_lib::{
+    init_user_trap, irq, set_ext_int_enable, set_timer,
+    timer::now_us,
+    trap::{nest_stats, set_nesting},
+    user_uart::*,
+};
+
+const PORT: usize = 1;
+const BAUD_RATE: usize = 115_200;
+/// How long the external handler keeps running.
+const HANDLER_US: usize = 10_000;
+/// When the timer is due, while the handler runs.
+const TIMER_AFTER_US: usize = 2_000;
+
+static BASE: AtomicUsize = AtomicUsize::new(0);
+static IRQ: AtomicUsize = AtomicUsize::new(0);
+static START: AtomicUsize = AtomicUsize::new(0);
+static FIRED_AT: AtomicUsize = AtomicUsize::new(0);
+static HANDLED: AtomicBool = AtomicBool::new(false);
+
+fn registers() -> UartMmio {
+    // from the claim `main` holds until the end
+    unsafe { UartMmio::new(BASE.load(Relaxed)) }
+}
+
+/// Raises a THR empty interrupt whose handler runs `HANDLER_US`, with the
+/// timer due in the middle of it, and returns how late the timer ran.
+fn run_phase(name: &str, max_depth: usize) -> usize {
+    set_nesting(max_depth, None);
+    FIRED_AT.store(0, Relaxed);
+    HANDLED.store(false, Relaxed);
+    let start = now_us();
+    START.store(start, Relaxed);
+    set_timer((start + TIMER_AFTER_US) as isize);
+    registers().set_tx_interrupt(true);
+    while !HANDLED.load(Relaxed) || FIRED_AT.load(Relaxed) == 0 {}
+    let late = FIRED_AT.load(Relaxed) - (start + TIMER_AFTER_US);
+    println!("[trap nesting] {}: timer {} us late", name, late);
+    late
+}
+
+/// Checks a timer interrupt due while a long external interrupt handler
+/// runs is taken in time once nesting is on.
+#[no_mangle]
+pub fn main() -> i32 {
+    init_user_trap();
+    let claim = SerialClaim::claim(PORT).unwrap();
+    let mut serial = BlockingSerial::from_claim(&claim);
+    serial.hardware_init(BAUD_RATE);
+    // keep the registers as they are, the claim resets them on drop
+    core::mem::forget(serial);
+    BASE.store(claim.base_address(), Relaxed);
+    IRQ.store(claim.irq() as usize, Relaxed);
+    set_ext_int_enable(claim.irq() as usize, 1);
+    unsafe {
+        uie::set_uext();
+        uie::set_utimer();
+    }
+
+    let plain = run_phase("no nesting", 0);
+    let nested = run_phase("nesting", 2);
+    set_nesting(0, None);
+    unsafe {
+        uie::clear_uext();
+        uie::clear_utimer();
+    }
+    let stats = nest_stats();
+    println!("[trap nesting] {:?}", stats);
+    if nested < plain && stats.max_depth >= 2 && stats.level_stack[0] > 0 {
+        0
+    } else {
+        -1
+    }
+}
+
+#[no_mangle]
+pub fn ext_intr_handler(irq: u16, _is_from_kernel: bool) {
+    if irq as usize == IRQ.load(Relaxed) {
+        registers().set_tx_interrupt(false);
+        while now_us() - START.load(Relaxed) < HANDLER_US {}
+        HANDLED.store(true, Relaxed);
+    }
+    irq::complete(irq);
+}
+
+#[no_mangle]
+pub fn timer_intr_handler(_time_us: usize) {
+    FIRED_AT.store(now_us(), Relaxed);
+}
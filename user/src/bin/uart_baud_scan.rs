@@ -0,0 +1,107 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+mock_uart_main!("uart baud scan", mock::run);
+
+/// Scans the baud rate of a peer on a `MockUart` that answers a probe
+/// only at its own rate, with nothing at one other rate and with garbage
+/// and an overrun at the rest. Checks that the scan stops at the peer's
+/// rate, that one over rates the peer does not use gives up with the rate
+/// put back, and that neither leaves bytes behind.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use alloc::sync::Arc;
+    use alloc::vec::Vec;
+    use core::cell::Cell;
+    use user_lib::executor::block_on_with;
+    use user_lib::user_uart::{regs::*, *};
+
+    const BAUD_RATE: usize = 115_200;
+    const PEER_RATE: usize = 57_600;
+    /// The peer hears nothing it can answer at this one.
+    const SILENT_RATE: usize = 9_600;
+    const CANDIDATES: &[usize] = &[9_600, 19_200, 38_400, 57_600, 115_200];
+    const WRONG_RATES: &[usize] = &[9_600, 19_200];
+    const PROBE: &[u8] = b"AT\r";
+    const EXPECT: &[u8] = b"OK\r\n";
+    /// Most of the answer, as a wrong rate may show it.
+    const GARBAGE: &[u8] = b"\x80O\xfeOK\r\xe0";
+    /// Ahead of the answer, overlapping its start.
+    const NOISE: &[u8] = b"\xff\x00OO";
+    const PER_RATE_TIMEOUT_US: usize = 20_000;
+
+    fn divisor_of(baud_rate: usize) -> u16 {
+        (100_000_000 / (16 * baud_rate)) as u16
+    }
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart baud scan");
+        let (mock, serial) = MockUart::async_serial(BAUD_RATE);
+        let serial = Arc::new(serial);
+        serial.interrupt_handler();
+
+        let probes = Cell::new(0);
+        let mut heard = Vec::new();
+        // the peer, answering each probe at the rate the mock is set to
+        let mut pump = || {
+            // CTS credit for the next FIFO
+            mock.inject_modem_status(MSR_CTS | MSR_DCTS);
+            serial.interrupt_handler();
+            heard.extend(mock.take_tx());
+            if !heard.ends_with(PROBE) {
+                return;
+            }
+            heard.clear();
+            probes.set(probes.get() + 1);
+            let divisor = mock.divisor();
+            if divisor == divisor_of(PEER_RATE) {
+                mock.inject_rx(NOISE);
+                mock.inject_rx(EXPECT);
+            } else if divisor != divisor_of(SILENT_RATE) {
+                mock.inject_rx(GARBAGE);
+                mock.inject_line_error(LSR_OE);
+                mock.inject_rx(GARBAGE);
+            }
+            serial.interrupt_handler();
+        };
+
+        let found = block_on_with(
+            serial.scan_baud(CANDIDATES, PROBE, EXPECT, PER_RATE_TIMEOUT_US),
+            &mut pump,
+        );
+        report.check(
+            "peer's rate found",
+            found == Some(PEER_RATE)
+                && serial.baud_rate() == PEER_RATE
+                && mock.divisor() == divisor_of(PEER_RATE),
+        );
+        report.check("stopped there", probes.get() == 4);
+        report.check(
+            "nothing left behind",
+            serial.rx_len() == 0 && mock.rx_left() == 0 && mock.take_tx().is_empty(),
+        );
+
+        probes.set(0);
+        let found = block_on_with(
+            serial.scan_baud(WRONG_RATES, PROBE, EXPECT, PER_RATE_TIMEOUT_US),
+            &mut pump,
+        );
+        report.check(
+            "no answer, rate put back",
+            found.is_none()
+                && probes.get() == WRONG_RATES.len()
+                && serial.baud_rate() == PEER_RATE
+                && mock.divisor() == divisor_of(PEER_RATE),
+        );
+        report.check(
+            "garbage dropped",
+            serial.rx_len() == 0 && mock.rx_left() == 0 && !serial.has_read_waker(),
+        );
+
+        report.exit_code()
+    }
+}
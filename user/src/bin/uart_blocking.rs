@@ -0,0 +1,62 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{get_time, init_user_trap, user_uart::*};
+
+const PORT: usize = 1;
+const BAUD_RATE: usize = 115_200;
+const IDLE_TIMEOUT_US: usize = 50_000;
+const LINE_TIMEOUT_US: usize = 10_000_000;
+
+/// Echo one line through `BlockingSerial`. No user interrupt is enabled,
+/// every wait sleeps in the kernel.
+#[no_mangle]
+pub fn main() -> i32 {
+    // claiming needs the trap buffer, the handlers are never used
+    init_user_trap();
+    let claim = match SerialClaim::claim(PORT) {
+        Ok(claim) => claim,
+        Err(err) => {
+            println!("[uart blocking] claim failed: {:?}", err);
+            return -1;
+        }
+    };
+    let mut serial = BlockingSerial::from_claim(&claim);
+    serial.hardware_init(BAUD_RATE);
+
+    // nothing is sent yet, so this has to time out
+    let start = get_time();
+    let mut buf = [0u8; 64];
+    let idle_len = serial.read(&mut buf, IDLE_TIMEOUT_US);
+    let idle_ms = get_time() - start;
+    println!(
+        "[uart blocking] idle read: {} bytes after {} ms",
+        idle_len, idle_ms
+    );
+    if idle_len != 0 || serial.timeout_count != 1 {
+        return -1;
+    }
+
+    serial.write(b"send a line\r\n");
+    let mut line_len = 0;
+    while line_len < buf.len() {
+        let len = serial.read(&mut buf[line_len..], LINE_TIMEOUT_US);
+        if len == 0 {
+            println!("[uart blocking] no line within timeout");
+            return -1;
+        }
+        line_len += len;
+        if buf[..line_len].contains(&b'\n') {
+            break;
+        }
+    }
+    serial.write(&buf[..line_len]);
+    println!(
+        "[uart blocking] rx {}, tx {}, waits {}, timeouts {}",
+        serial.rx_count, serial.tx_count, serial.wait_count, serial.timeout_count
+    );
+    0
+}
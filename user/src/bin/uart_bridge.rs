@@ -0,0 +1,253 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed};
+use riscv::register::uie;
+use spin::Once;
+use user_lib::{
+    console::print,
+    executor::{Executor, IdleStrategy},
+    init_user_trap, irq, set_ext_int_enable,
+    timer::sleep_us,
+    user_uart::*,
+};
+
+// Side A is meant for a terminal, side B for the device under test. The
+// rates may differ, the slower side then paces the faster one.
+const A_PORT: usize = 1;
+const A_BAUD_RATE: usize = 115_200;
+const B_PORT: usize = 2;
+const B_BAUD_RATE: usize = 115_200;
+const CHUNK_SIZE: usize = 64;
+/// A direction stops reading while the tx queue it feeds has less room
+/// than this.
+const LOW_WATER: usize = CHUNK_SIZE;
+const REPORT_PERIOD_US: usize = 10 * 1_000_000;
+
+/// Both drivers and their IRQs, for `ext_intr_handler`.
+static CONSOLE: Once<Arc<ConsoleAsync>> = Once::new();
+static DONE: AtomicBool = AtomicBool::new(false);
+
+/// Counters of one direction, A to B and B to A.
+struct Direction {
+    name: &'static str,
+    bytes: AtomicUsize,
+    /// Times reading stopped because the other side's tx queue was near
+    /// full.
+    pauses: AtomicUsize,
+    /// Overruns on the receiving port. With reads paused the rx queue
+    /// fills, the driver stops draining the FIFO and a sender that ignores
+    /// RTS overruns it. Every overrun loses at least one byte.
+    overruns: AtomicUsize,
+    line_errors: AtomicUsize,
+}
+
+impl Direction {
+    const fn new(name: &'static str) -> Self {
+        Direction {
+            name,
+            bytes: AtomicUsize::new(0),
+            pauses: AtomicUsize::new(0),
+            overruns: AtomicUsize::new(0),
+            line_errors: AtomicUsize::new(0),
+        }
+    }
+}
+
+static A_TO_B: Direction = Direction::new("A -> B");
+static B_TO_A: Direction = Direction::new("B -> A");
+
+/// Time `len` bytes take on the wire at `baud_rate`, 10 bits each.
+fn wire_time_us(len: usize, baud_rate: usize) -> usize {
+    len * 10 * 1_000_000 / baud_rate
+}
+
+/// Moves bytes from `reader` to `writer`. It only reads as much as the tx
+/// queue of `writer` can take, so nothing is ever held here: a write never
+/// waits and stopping the bridge only has to flush the tx queues. While
+/// that queue is near full it sleeps for the time a chunk takes to go out
+/// instead of reading, which leaves the bytes in the rx queue and, once
+/// that fills, in the sender.
+async fn forward(
+    dir: &'static Direction,
+    mut reader: SerialReader,
+    mut writer: SerialWriter,
+    tx_baud_rate: usize,
+) {
+    let mut buf = [0u8; CHUNK_SIZE];
+    loop {
+        let space = writer.space();
+        if space < LOW_WATER {
+            dir.pauses.fetch_add(1, Relaxed);
+            sleep_us(wire_time_us(CHUNK_SIZE, tx_baud_rate).max(1)).await;
+            continue;
+        }
+        let len = reader.read_some(&mut buf[..space.min(CHUNK_SIZE)]).await;
+        if writer.write(&buf[..len]).await.is_err() {
+            return;
+        }
+        dir.bytes.fetch_add(len, Relaxed);
+    }
+}
+
+/// Sorts line errors by the port they came in on.
+async fn event_task(bus: Arc<SerialEventBus>) {
+    loop {
+        let event = bus.next_event().await;
+        let dir = if event.port == A_PORT {
+            &A_TO_B
+        } else {
+            &B_TO_A
+        };
+        match event.kind {
+            SerialEventKind::Overrun => dir.overruns.fetch_add(1, Relaxed),
+            _ => dir.line_errors.fetch_add(1, Relaxed),
+        };
+    }
+}
+
+fn report(bus: &SerialEventBus) {
+    for dir in [&A_TO_B, &B_TO_A] {
+        println!(
+            "[uart bridge] {}: {} bytes, paused {} times, overruns {}, line errors {}",
+            dir.name,
+            dir.bytes.load(Relaxed),
+            dir.pauses.load(Relaxed),
+            dir.overruns.load(Relaxed),
+            dir.line_errors.load(Relaxed)
+        );
+    }
+    if bus.dropped() > 0 {
+        println!("[uart bridge] {} events not counted", bus.dropped());
+    }
+}
+
+/// Keys typed on the console: `s` prints the counters, `q` stops.
+async fn console_task(console: Arc<ConsoleAsync>, bus: Arc<SerialEventBus>) {
+    let mut buf = [0u8; 16];
+    loop {
+        let len = console.clone().read(&mut buf).await;
+        for &key in &buf[..len] {
+            match key {
+                b's' => report(&bus),
+                b'q' => {
+                    DONE.store(true, Relaxed);
+                    return;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn open(claim: &SerialClaim, baud_rate: usize) -> Arc<AsyncSerial> {
+    let serial = SerialBuilder::new(claim.port())
+        .baud(baud_rate)
+        .build_on(claim)
+        .unwrap();
+    serial.into_async().unwrap()
+}
+
+/// Forwards bytes both ways between `A_PORT` and `B_PORT` until `q` is
+/// typed on the console.
+#[no_mangle]
+pub fn main() -> i32 {
+    init_user_trap();
+    let (a_claim, b_claim) = match (SerialClaim::claim(A_PORT), SerialClaim::claim(B_PORT)) {
+        (Ok(a_claim), Ok(b_claim)) => (a_claim, b_claim),
+        (a_claim, b_claim) => {
+            println!(
+                "[uart bridge] claim failed, port {}: {:?}, port {}: {:?}",
+                A_PORT,
+                a_claim.err(),
+                B_PORT,
+                b_claim.err()
+            );
+            return -1;
+        }
+    };
+    let console = match ConsoleAsync::open() {
+        Ok(console) => Arc::new(console),
+        Err(err) => {
+            println!("[uart bridge] console open failed: {}", err);
+            return -1;
+        }
+    };
+    CONSOLE.call_once(|| console.clone());
+    let a = open(&a_claim, A_BAUD_RATE);
+    let b = open(&b_claim, B_BAUD_RATE);
+    let bus = Arc::new(SerialEventBus::new());
+    a.attach_event_bus(bus.clone());
+    b.attach_event_bus(bus.clone());
+    serial::register(A_PORT, a.clone()).unwrap();
+    serial::register(B_PORT, b.clone()).unwrap();
+
+    println!(
+        "[uart bridge] port {} at {} baud <-> port {} at {} baud, s: stats, q: quit",
+        A_PORT, A_BAUD_RATE, B_PORT, B_BAUD_RATE
+    );
+    if A_BAUD_RATE != B_BAUD_RATE {
+        // past this the faster side is only held back by RTS
+        println!(
+            "[uart bridge] rates differ, bursts over {} bytes toward the slower side need flow control",
+            DEFAULT_RX_BUFFER_SIZE + DEFAULT_TX_BUFFER_SIZE
+        );
+    }
+
+    let (a_reader, a_writer) = a.clone().split();
+    let (b_reader, b_writer) = b.clone().split();
+    let exec = Executor::new(IdleStrategy::Yield);
+    let tasks = [
+        exec.spawn(forward(&A_TO_B, a_reader, b_writer, B_BAUD_RATE)),
+        exec.spawn(forward(&B_TO_A, b_reader, a_writer, A_BAUD_RATE)),
+        exec.spawn(event_task(bus.clone())),
+        StatsReporter::spawn(&exec, a.clone(), REPORT_PERIOD_US, print),
+        StatsReporter::spawn(&exec, b.clone(), REPORT_PERIOD_US, print),
+    ];
+    exec.spawn(console_task(console.clone(), bus.clone()));
+    set_ext_int_enable(a_claim.irq() as usize, 1);
+    set_ext_int_enable(b_claim.irq() as usize, 1);
+    unsafe {
+        uie::set_uext();
+        uie::set_usoft();
+        uie::set_utimer();
+    }
+    exec.run_until(|| DONE.load(Relaxed));
+    for task in &tasks {
+        task.cancel();
+    }
+    unsafe {
+        uie::clear_uext();
+        uie::clear_usoft();
+        uie::clear_utimer();
+    }
+    a.remove_read();
+    b.remove_read();
+    console.remove_read();
+    // what was read is already queued on the other side
+    let flushed = a.flush(EXIT_DRAIN_TIMEOUT_US) & b.flush(EXIT_DRAIN_TIMEOUT_US);
+    report(&bus);
+    if flushed {
+        0
+    } else {
+        println!("[uart bridge] tx not drained in time");
+        -1
+    }
+}
+
+#[no_mangle]
+pub fn ext_intr_handler(irq: u16, _is_from_kernel: bool) {
+    if let Some(console) = CONSOLE.get() {
+        if irq == console.irq() {
+            console.interrupt_handler();
+            return;
+        }
+    }
+    serial::dispatch(irq);
+    irq::complete(irq);
+}
@@ -0,0 +1,99 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed};
+use heapless::spsc::Queue;
+use riscv::register::uie;
+use user_lib::{
+    executor::{Executor, IdleStrategy, JoinHandle},
+    init_user_trap,
+    timer::{now_us, sleep_us},
+    user_uart::*,
+};
+
+const PORT: usize = 1;
+const BAUD_RATE: usize = 115_200;
+const CANCEL_AFTER_US: usize = 100_000;
+/// A cancelled task must be gone within this long.
+const MAX_TEARDOWN_US: usize = 10_000;
+
+static READ_RETURNED: AtomicBool = AtomicBool::new(false);
+static CANCELLED_AT: AtomicUsize = AtomicUsize::new(0);
+
+/// Nothing is sent to the port, so the read never completes by itself.
+async fn blocked_read_task(serial: Arc<AsyncSerial>) {
+    let mut buf = [0u8; 16];
+    let _ = serial.read(&mut buf).await;
+    READ_RETURNED.store(true, Relaxed);
+}
+
+async fn cancel_task(reader: JoinHandle) {
+    sleep_us(CANCEL_AFTER_US).await;
+    CANCELLED_AT.store(now_us(), Relaxed);
+    reader.cancel();
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    println!("[uart cancel] cancel a blocked serial read");
+    let init_res = init_user_trap();
+    type RxBuffer = Queue<u8, DEFAULT_RX_BUFFER_SIZE>;
+    type TxBuffer = Queue<u8, DEFAULT_TX_BUFFER_SIZE>;
+    static mut DRIVER_RX_BUFFER: RxBuffer = RxBuffer::new();
+    static mut DRIVER_TX_BUFFER: TxBuffer = TxBuffer::new();
+    let (rx_pro, rx_con) = unsafe { DRIVER_RX_BUFFER.split() };
+    let (tx_pro, tx_con) = unsafe { DRIVER_TX_BUFFER.split() };
+    let claim = match SerialClaim::claim(PORT) {
+        Ok(claim) => claim,
+        Err(err) => {
+            println!("[uart cancel] claim port {} failed: {:?}", PORT, err);
+            return -1;
+        }
+    };
+    let serial = Arc::new(AsyncSerial::from_claim(
+        &claim, rx_pro, rx_con, tx_pro, tx_con,
+    ));
+    serial.hardware_init(BAUD_RATE);
+    println!("[uart cancel] trap init result: {:#x}", init_res);
+
+    let exec = Executor::new(IdleStrategy::Yield);
+    let reader = exec.spawn(blocked_read_task(serial.clone()));
+    let reader_done = reader.token();
+    exec.spawn(cancel_task(reader));
+
+    unsafe {
+        uie::set_usoft();
+        uie::set_utimer();
+    }
+    let mut torn_down_at = 0;
+    exec.run_until(|| {
+        if reader_done.is_cancelled() && !serial.has_read_waker() {
+            torn_down_at = now_us();
+            true
+        } else {
+            false
+        }
+    });
+    unsafe {
+        uie::clear_utimer();
+        uie::clear_usoft();
+    }
+
+    let teardown = torn_down_at - CANCELLED_AT.load(Relaxed);
+    println!(
+        "[uart cancel] read returned: {}, stale waker: {}, teardown: {} us",
+        READ_RETURNED.load(Relaxed),
+        serial.has_read_waker(),
+        teardown
+    );
+    if READ_RETURNED.load(Relaxed) || teardown > MAX_TEARDOWN_US {
+        -1
+    } else {
+        0
+    }
+}
@@ -0,0 +1,119 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use heapless::spsc::Queue;
+use user_lib::{executor::MAX_HART_NUM, fork, init_user_trap, user_uart::*, waitpid};
+
+const BAUD_RATE: usize = 115_200;
+const PORT: usize = 1;
+const PANIC_PORT: usize = 2;
+
+fn check(name: &str, ok: bool) -> bool {
+    println!(
+        "[uart claim] {}: {}",
+        name,
+        if ok { "ok" } else { "FAILED" }
+    );
+    ok
+}
+
+fn claim_then_bail(port: usize) -> Result<(), ClaimError> {
+    let _claim = SerialClaim::claim(port)?;
+    SerialClaim::claim(port)?;
+    Ok(())
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut ok = check(
+        "claim before init",
+        SerialClaim::claim(PORT).err() == Some(ClaimError::NotInitialized),
+    );
+    init_user_trap();
+
+    ok &= check(
+        "claim out of range",
+        SerialClaim::claim(serial::enumerate().len()).err() == Some(ClaimError::InvalidPort),
+    );
+    ok &= check(
+        "claim console",
+        SerialClaim::claim(0).err() == Some(ClaimError::InvalidPort),
+    );
+
+    {
+        let claim = match SerialClaim::claim(PORT) {
+            Ok(claim) => claim,
+            Err(err) => {
+                println!("[uart claim] claim port {} failed: {:?}", PORT, err);
+                return -1;
+            }
+        };
+        ok &= check(
+            "claim base address",
+            claim.base_address() == get_base_addr_from_irq(claim.irq()),
+        );
+        ok &= check(
+            "double claim",
+            SerialClaim::claim(PORT).err() == Some(ClaimError::AlreadyClaimed),
+        );
+        type RxBuffer = Queue<u8, DEFAULT_RX_BUFFER_SIZE>;
+        type TxBuffer = Queue<u8, DEFAULT_TX_BUFFER_SIZE>;
+        static mut DRIVER_RX_BUFFER: RxBuffer = RxBuffer::new();
+        static mut DRIVER_TX_BUFFER: TxBuffer = TxBuffer::new();
+        let (rx_pro, rx_con) = unsafe { DRIVER_RX_BUFFER.split() };
+        let (tx_pro, tx_con) = unsafe { DRIVER_TX_BUFFER.split() };
+        let serial = AsyncSerial::from_claim(&claim, rx_pro, rx_con, tx_pro, tx_con);
+        serial.hardware_init(BAUD_RATE);
+    }
+    ok &= check("reclaim after drop", SerialClaim::claim(PORT).is_ok());
+
+    ok &= check(
+        "release on early return",
+        claim_then_bail(PORT) == Err(ClaimError::AlreadyClaimed)
+            && SerialClaim::claim(PORT).is_ok(),
+    );
+
+    ok &= check(
+        "pin to missing hart",
+        SerialClaim::builder(PORT)
+            .affinity(MAX_HART_NUM)
+            .claim()
+            .err()
+            == Some(ClaimError::InvalidHart),
+    );
+    match SerialClaim::builder(PORT).affinity(1).claim() {
+        Ok(mut claim) => {
+            // unsupported routing falls back to unpinned
+            ok &= check("pin to hart 1", matches!(claim.affinity(), Some(1) | None));
+            ok &= check(
+                "unpin",
+                claim.set_affinity(None).is_ok() && claim.affinity().is_none(),
+            );
+        }
+        Err(err) => {
+            println!("[uart claim] pinned claim failed: {:?}", err);
+            ok = false;
+        }
+    }
+
+    let pid = fork();
+    if pid == 0 {
+        let _claim = SerialClaim::claim(PANIC_PORT).unwrap();
+        panic!("[uart claim] panic while holding port {}", PANIC_PORT);
+    }
+    let mut exit_code = 0;
+    waitpid(pid as usize, &mut exit_code);
+    ok &= check(
+        "release on panic",
+        exit_code == -1 && SerialClaim::claim(PANIC_PORT).is_ok(),
+    );
+
+    if ok {
+        0
+    } else {
+        -1
+    }
+}
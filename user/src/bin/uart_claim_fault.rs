@@ -0,0 +1,84 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, init_user_trap, user_uart::*, waitpid};
+
+const PORT: usize = 1;
+/// What the kernel exits a process with on a page fault.
+const FAULT_EXIT_CODE: i32 = -2;
+
+fn check(name: &str, ok: bool) -> bool {
+    println!(
+        "[uart claim fault] {}: {}",
+        name,
+        if ok { "ok" } else { "FAILED" }
+    );
+    ok
+}
+
+/// Runs `child` in a forked process and returns its exit code.
+fn in_child(child: impl FnOnce() -> i32) -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        exit(child());
+    }
+    let mut exit_code = 0;
+    waitpid(pid as usize, &mut exit_code);
+    exit_code
+}
+
+/// Reads LSR at `base` the way a driver built without a claim would.
+fn touch(base: usize) -> i32 {
+    // not claimed by this process, the point is that it faults
+    let lsr = unsafe { UartMmio::new(base) }.read_lsr();
+    println!("[uart claim fault] read LSR {:#x} without a claim", lsr);
+    0
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    init_user_trap();
+    let base = serial::port_info(PORT).unwrap().phys_base;
+
+    let mut ok = check("never claimed", in_child(|| touch(base)) == FAULT_EXIT_CODE);
+
+    match SerialClaim::claim(PORT) {
+        Ok(claim) => {
+            let base = claim.base_address();
+            ok &= check(
+                "claimed by the parent",
+                in_child(|| touch(base)) == FAULT_EXIT_CODE,
+            );
+        }
+        Err(err) => {
+            println!("[uart claim fault] claim port {} failed: {:?}", PORT, err);
+            ok = false;
+        }
+    }
+
+    ok &= check(
+        "after release",
+        in_child(|| {
+            let base = SerialClaim::claim(PORT).unwrap().base_address();
+            touch(base)
+        }) == FAULT_EXIT_CODE,
+    );
+
+    ok &= check(
+        "through the claim",
+        in_child(|| {
+            let claim = SerialClaim::claim(PORT).unwrap();
+            claim.registers().read_lsr();
+            0
+        }) == 0,
+    );
+
+    if ok {
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,128 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{exit, fork, init_user_trap, irq, timer::now_us, user_uart::*, waitpid, yield_};
+
+const PORT: usize = 1;
+const OTHER_PORT: usize = 2;
+/// How long the child at its limit stays around for the transfer.
+const HOLD_US: usize = 500_000;
+/// What the user library exits a process with on panic.
+const PANIC_EXIT_CODE: i32 = -1;
+
+fn check(name: &str, ok: bool) -> bool {
+    println!(
+        "[uart claim limit] {}: {}",
+        name,
+        if ok { "ok" } else { "FAILED" }
+    );
+    ok
+}
+
+/// Claims `OTHER_PORT`, which puts it at the inherited limit of 1, and
+/// holds it a while.
+fn child_at_limit() -> i32 {
+    if irq::claim_limit() != 1 {
+        return 1;
+    }
+    let _claim = match SerialClaim::claim(OTHER_PORT) {
+        Ok(claim) => claim,
+        Err(_) => return 2,
+    };
+    let start = now_us();
+    while now_us() - start < HOLD_US {
+        yield_();
+    }
+    0
+}
+
+/// Takes `PORT` over and dies holding it.
+fn child_dying() -> i32 {
+    let _claim = loop {
+        match SerialClaim::inherit(PORT) {
+            Ok(claim) => break claim,
+            Err(ClaimError::NotHeld) => yield_(),
+            Err(_) => return 1,
+        };
+    };
+    if SerialClaim::list() != [PORT] {
+        return 2;
+    }
+    panic!("[uart claim limit] dying with port {} held", PORT);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    init_user_trap();
+    let mut ok = check("nothing held", SerialClaim::list().is_empty());
+
+    let claim = match SerialClaim::claim(PORT) {
+        Ok(claim) => claim,
+        Err(err) => {
+            println!("[uart claim limit] claim port {} failed: {:?}", PORT, err);
+            return -1;
+        }
+    };
+    ok &= check("claim listed", SerialClaim::list() == [PORT]);
+
+    ok &= check("limit lowered", irq::set_claim_limit(1) == 1);
+    ok &= check("never raised", irq::set_claim_limit(3) == 1);
+    ok &= check(
+        "over the limit refused",
+        matches!(
+            SerialClaim::claim(OTHER_PORT),
+            Err(ClaimError::LimitReached)
+        ) && SerialClaim::list() == [PORT],
+    );
+
+    let pid = fork();
+    if pid == 0 {
+        exit(child_at_limit());
+    }
+    while !serial::enumerate()[OTHER_PORT].is_claimed() {
+        yield_();
+    }
+    let claim = match claim.transfer(pid as usize) {
+        Err((claim, ClaimError::LimitReached)) => {
+            ok &= check("target at its limit", SerialClaim::list() == [PORT]);
+            claim
+        }
+        res => {
+            println!("[uart claim limit] transfer to a full pid: {:?}", res);
+            return -1;
+        }
+    };
+    let mut exit_code = 0;
+    waitpid(pid as usize, &mut exit_code);
+    ok &= check("limit inherited", exit_code == 0);
+
+    let pid = fork();
+    if pid == 0 {
+        exit(child_dying());
+    }
+    if let Err((_, err)) = claim.transfer(pid as usize) {
+        println!("[uart claim limit] transfer failed: {:?}", err);
+        return -1;
+    }
+    ok &= check("transferred away", SerialClaim::list().is_empty());
+    waitpid(pid as usize, &mut exit_code);
+    ok &= check(
+        "released on abnormal exit",
+        exit_code == PANIC_EXIT_CODE && !serial::enumerate()[PORT].is_claimed(),
+    );
+    let again = SerialClaim::claim(PORT);
+    ok &= check(
+        "claimed again",
+        again.is_ok() && SerialClaim::list() == [PORT],
+    );
+    drop(again);
+
+    if ok {
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,266 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+mock_uart_main!("uart close race", mock::run, argv);
+
+#[cfg(feature = "mock_uart")]
+#[no_mangle]
+pub fn timer_intr_handler(_time_us: usize) {
+    mock::on_timer();
+}
+
+/// Closes an `AsyncSerial` on a `MockUart` at a random time while the user
+/// timer, as in `uart_stress`, injects bytes and calls the interrupt
+/// handler, a reader and a writer task pending. Checks that both fail with
+/// `SerialError::Closed`, that nothing serves the port once it is down,
+/// though the timer still raises its interrupt, and that the timer finds
+/// the driver gone once dropped.
+///
+/// `uart_close_race [seed] [rounds]`: the seed is printed, pass it back to
+/// repeat a run.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use alloc::format;
+    use alloc::sync::{Arc, Weak};
+    use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed};
+    use heapless::spsc::Queue;
+    use rand_core::{RngCore, SeedableRng};
+    use rand_xorshift::XorShiftRng;
+    use riscv::register::uie;
+    use spin::{Mutex, Once};
+    use user_lib::executor::{Executor, IdleStrategy};
+    use user_lib::set_timer;
+    use user_lib::timer::now_us;
+    use user_lib::uintr::critical_section;
+    use user_lib::user_uart::{regs::*, *};
+
+    type Serial = AsyncSerial<&'static MockUart>;
+    type RxBuffer = Queue<u8, DEFAULT_RX_BUFFER_SIZE>;
+    type TxBuffer = Queue<u8, DEFAULT_TX_BUFFER_SIZE>;
+
+    const BAUD_RATE: usize = 115_200;
+    const DEFAULT_ROUNDS: usize = 64;
+    /// The close comes up to this long after the tasks start.
+    const MAX_CLOSE_US: usize = 3_000;
+    /// How long the port is watched once down, and the dropped driver.
+    const QUIET_US: usize = 2_000;
+    /// Past this a round counts as stuck.
+    const DEADLINE_US: usize = 2_000_000;
+    const MAX_BURST: usize = 32;
+    const MIN_TICK_US: usize = 20;
+    const TICK_SPREAD_US: usize = 100;
+    /// Bytes the mock holds at most, the timer injects no more.
+    const RX_ROOM: usize = 1024;
+    const CHUNK: usize = 64;
+
+    static MOCK: Once<&'static MockUart> = Once::new();
+    /// Only locked with user interrupts masked. Weak, so the timer never
+    /// keeps the driver alive.
+    static SERIAL: Mutex<Option<Weak<Serial>>> = Mutex::new(None);
+    static RNG: Mutex<Option<XorShiftRng>> = Mutex::new(None);
+    static TICKS: AtomicUsize = AtomicUsize::new(0);
+    /// Ticks that found the driver dropped.
+    static LATE: AtomicUsize = AtomicUsize::new(0);
+    static STOP: AtomicBool = AtomicBool::new(false);
+    static READ_RESULT: Mutex<Option<Result<(), SerialError>>> = Mutex::new(None);
+    static WRITE_RESULT: Mutex<Option<Result<(), SerialError>>> = Mutex::new(None);
+    /// Not `MockUart::async_serial`: every round's driver is on the one
+    /// mock the timer knows, and leaked queues per round would not fit.
+    static mut DRIVER_RX_BUFFER: RxBuffer = RxBuffer::new();
+    static mut DRIVER_TX_BUFFER: TxBuffer = TxBuffer::new();
+
+    fn next_below(rng: &mut XorShiftRng, bound: usize) -> usize {
+        rng.next_u32() as usize % bound
+    }
+
+    fn arm(delay_us: usize) {
+        set_timer((now_us() + delay_us) as isize);
+    }
+
+    /// Bytes arrive and the IRQ is taken, in the middle of `close` too.
+    /// No CTS credit is given, so the writer stays pending.
+    pub fn on_timer() {
+        let mock = match MOCK.get() {
+            Some(mock) => *mock,
+            None => return,
+        };
+        TICKS.fetch_add(1, Relaxed);
+        let (burst, delay) = {
+            let mut rng = RNG.lock();
+            let rng = match rng.as_mut() {
+                Some(rng) => rng,
+                None => return,
+            };
+            (
+                next_below(rng, MAX_BURST + 1),
+                MIN_TICK_US + next_below(rng, TICK_SPREAD_US),
+            )
+        };
+        let len = burst.min(RX_ROOM.saturating_sub(mock.rx_left()));
+        mock.inject_rx(&[0x5a; MAX_BURST][..len]);
+        match SERIAL.lock().as_ref().and_then(Weak::upgrade) {
+            Some(serial) => serial.interrupt_handler(),
+            None => {
+                LATE.fetch_add(1, Relaxed);
+            }
+        }
+        if !STOP.load(Relaxed) {
+            arm(delay);
+        }
+    }
+
+    async fn reader(serial: Arc<Serial>) {
+        let mut buf = [0u8; CHUNK];
+        let result = loop {
+            if let Err(err) = serial.clone().read_checked(&mut buf).await {
+                break Err(err);
+            }
+        };
+        *READ_RESULT.lock() = Some(result);
+    }
+
+    async fn writer(serial: Arc<Serial>) {
+        let buf = [0xa5u8; CHUNK];
+        let result = loop {
+            if let Err(err) = serial.clone().write_checked(&buf).await {
+                break Err(err);
+            }
+        };
+        *WRITE_RESULT.lock() = Some(result);
+    }
+
+    fn spin_us(us: usize) {
+        let start = now_us();
+        while now_us() - start < us {}
+    }
+
+    /// One driver, closed `close_us` after its tasks start. Returns false
+    /// if a check failed.
+    fn round(mock: &'static MockUart, close_us: usize) -> bool {
+        *READ_RESULT.lock() = None;
+        *WRITE_RESULT.lock() = None;
+        // the driver of the last round is gone
+        let (rx_pro, rx_con, tx_pro, tx_con) = unsafe {
+            DRIVER_RX_BUFFER = RxBuffer::new();
+            DRIVER_TX_BUFFER = TxBuffer::new();
+            let (rx_pro, rx_con) = DRIVER_RX_BUFFER.split();
+            let (tx_pro, tx_con) = DRIVER_TX_BUFFER.split();
+            (rx_pro, rx_con, tx_pro, tx_con)
+        };
+        let serial = Arc::new(AsyncSerial::with_registers(
+            mock, rx_pro, rx_con, tx_pro, tx_con,
+        ));
+        serial.hardware_init(BAUD_RATE);
+        critical_section(|| *SERIAL.lock() = Some(Arc::downgrade(&serial)));
+
+        let executor = Executor::new(IdleStrategy::Spin);
+        executor.spawn(reader(serial.clone()));
+        executor.spawn(writer(serial.clone()));
+        let start = now_us();
+        let mut stuck = false;
+        executor.run_until(|| {
+            let elapsed = now_us() - start;
+            if elapsed >= close_us {
+                serial.close();
+            }
+            stuck = elapsed > DEADLINE_US;
+            let done = READ_RESULT.lock().is_some() && WRITE_RESULT.lock().is_some();
+            done || stuck
+        });
+        drop(executor);
+
+        // the port is down, the timer goes on raising its interrupt
+        let before = (serial.stats(), mock.rx_left());
+        spin_us(QUIET_US);
+        let after = (serial.stats(), mock.rx_left());
+        let mut passed = true;
+        let mut check = |name: &str, ok: bool| {
+            if !ok {
+                println!("[uart close race] {}: FAILED", name);
+            }
+            passed &= ok;
+        };
+        check("tasks finished", !stuck);
+        check(
+            "read failed with Closed",
+            matches!(*READ_RESULT.lock(), Some(Err(SerialError::Closed { .. }))),
+        );
+        check(
+            "write failed with Closed",
+            matches!(*WRITE_RESULT.lock(), Some(Err(SerialError::Closed { .. }))),
+        );
+        check(
+            "no waker left",
+            !serial.has_read_waker() && !serial.has_write_waker(),
+        );
+        check("IER off", mock.read_ier() == 0);
+        check(
+            "nothing served once down",
+            after.0.intr_count == before.0.intr_count
+                && after.0.rx_count == before.0.rx_count
+                && after.1 >= before.1,
+        );
+        check("last reference", Arc::strong_count(&serial) == 1);
+
+        let late = LATE.load(Relaxed);
+        drop(serial);
+        spin_us(QUIET_US);
+        check("dropped driver found gone", LATE.load(Relaxed) > late);
+        mock.take_tx();
+        passed
+    }
+
+    pub fn run(argv: &[&str]) -> i32 {
+        let seed = argv
+            .get(1)
+            .and_then(|arg| arg.parse().ok())
+            .unwrap_or(now_us() as u64);
+        let rounds = argv
+            .get(2)
+            .and_then(|arg| arg.parse().ok())
+            .unwrap_or(DEFAULT_ROUNDS);
+        println!("[uart close race] seed {}, {} rounds", seed, rounds);
+        let mock = MockUart::new();
+        mock.reserve(RX_ROOM, DEFAULT_TX_BUFFER_SIZE);
+        MOCK.call_once(|| mock);
+        let mut rng = XorShiftRng::seed_from_u64(seed);
+        *RNG.lock() = Some(XorShiftRng::seed_from_u64(rng.next_u64()));
+        arm(MIN_TICK_US);
+        unsafe {
+            uie::set_utimer();
+        }
+        let mut failed = 0;
+        for _ in 0..rounds {
+            if !round(mock, next_below(&mut rng, MAX_CLOSE_US)) {
+                failed += 1;
+            }
+        }
+        STOP.store(true, Relaxed);
+        unsafe {
+            uie::clear_utimer();
+        }
+
+        println!(
+            "[uart close race] {} ticks, {} after a drop",
+            TICKS.load(Relaxed),
+            LATE.load(Relaxed)
+        );
+        let mut report = MockReport::new("uart close race");
+        report.check(
+            &format!("{} of {} rounds clean", rounds - failed, rounds),
+            failed == 0,
+        );
+        let code = report.exit_code();
+        if code != 0 {
+            println!(
+                "[uart close race] rerun with `uart_close_race {} {}`",
+                seed, rounds
+            );
+        }
+        code
+    }
+}
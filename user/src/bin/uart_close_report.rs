@@ -0,0 +1,88 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+mock_uart_main!("uart close report", mock::run);
+
+/// Closes and drops drivers on a `MockUart` with bytes left in them.
+/// Checks that `close` reports a flush that timed out and what it did not
+/// send or nobody read, the held tx FIFO included, that a drop after
+/// `close` records nothing, and that dropping an open `AsyncSerial` or a
+/// `StaticBufferedSerial` records its leftovers in `last_drop_report`.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use user_lib::user_uart::*;
+
+    const BAUD_RATE: usize = 115_200;
+    const DATA: &[u8] = &[0x5a; 100];
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart close report");
+        let (mock, serial) = MockUart::async_serial(BAUD_RATE);
+        // nothing leaves the tx FIFO, the flush times out
+        mock.hold_tx(true);
+        mock.set_tx_level_register(true);
+        mock.inject_rx(b"abc");
+        serial.interrupt_handler();
+        let queued = serial.write_available(DATA);
+        let first = serial.close();
+        report.check(
+            "close reports the timeout and the leftovers",
+            first
+                == CloseReport {
+                    tx_unsent: queued,
+                    rx_unread: 3,
+                    flush_timed_out: true,
+                },
+        );
+        let second = serial.close();
+        report.check(
+            "closing again reports the queues only",
+            queued == DATA.len()
+                && second.tx_unsent < first.tx_unsent
+                && second.rx_unread == 3
+                && !second.flush_timed_out,
+        );
+        drop(serial);
+        report.check(
+            "closed, the drop records nothing",
+            last_drop_report().is_none(),
+        );
+
+        let (mock, serial) = MockUart::async_serial(BAUD_RATE);
+        mock.inject_rx(b"xy");
+        serial.interrupt_handler();
+        serial.write_available(b"bye");
+        drop(serial);
+        report.check(
+            "drop flushes and records what was unread",
+            last_drop_report()
+                == Some(CloseReport {
+                    tx_unsent: 0,
+                    rx_unread: 2,
+                    flush_timed_out: false,
+                })
+                && mock.take_tx().ends_with(b"bye"),
+        );
+
+        let mock = MockUart::new();
+        let mut serial = StaticBufferedSerial::<64, 32, _>::with_registers(mock);
+        serial.init(BAUD_RATE);
+        let queued = serial.write_nonblocking(b"lost");
+        drop(serial);
+        report.check(
+            "buffered drop records the tx buffer",
+            queued == 4
+                && last_drop_report()
+                    == Some(CloseReport {
+                        tx_unsent: 4,
+                        rx_unread: 0,
+                        flush_timed_out: false,
+                    }),
+        );
+
+        report.exit_code()
+    }
+}
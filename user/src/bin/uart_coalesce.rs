@@ -0,0 +1,120 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+mock_uart_main!("uart coalesce", mock::run);
+
+/// Runs rx interrupt coalescing on a `MockUart` with the user timer
+/// interrupt on: an rx interrupt turns ERBFI off, the timer drains what
+/// came in meanwhile and turns it on again once nothing more does.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use alloc::sync::Arc;
+    use riscv::register::uie;
+    use user_lib::init_user_trap;
+    use user_lib::timer::now_us;
+    use user_lib::user_uart::{regs::*, *};
+
+    const BAUD_RATE: usize = 3_000_000;
+    const DELAY_US: usize = 150;
+    const WAIT_US: usize = 100_000;
+
+    /// Spins until `done` or `WAIT_US` passed, returns `done()`.
+    fn wait(done: impl Fn() -> bool) -> bool {
+        let start = now_us();
+        while !done() && now_us() - start < WAIT_US {}
+        done()
+    }
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart coalesce");
+        init_user_trap();
+        let (mock, serial) = MockUart::async_serial_uninit();
+        mock.set_deep_fifo(true);
+        let serial = Arc::new(serial);
+        serial.hardware_init(BAUD_RATE);
+
+        // 64 bytes take 213 us at 3 Mbaud
+        let too_long = Coalescing {
+            trigger: FifoTrigger::ThirtyTwo,
+            delay_us: 1_000,
+            max_latency_us: 2_000,
+        };
+        report.check(
+            "delay overrunning the FIFO refused",
+            serial.set_coalescing(Some(too_long))
+                == Err(SerialBuildError::InvalidCoalescing {
+                    delay_us: 1_000,
+                    max_delay_us: 212,
+                })
+                && serial.coalescing().is_none(),
+        );
+        let coalescing = Coalescing {
+            trigger: FifoTrigger::ThirtyTwo,
+            delay_us: DELAY_US,
+            max_latency_us: 100,
+        };
+        report.check(
+            "delay over the latency bound refused",
+            serial.set_coalescing(Some(coalescing)).is_err(),
+        );
+        let coalescing = Coalescing {
+            max_latency_us: 500,
+            ..coalescing
+        };
+        report.check(
+            "coalescing on",
+            serial.set_coalescing(Some(coalescing)).is_ok()
+                && serial.coalescing() == Some(coalescing)
+                && serial.fifo_trigger() == FifoTrigger::ThirtyTwo,
+        );
+
+        unsafe {
+            uie::set_utimer();
+        }
+        let data: [u8; 72] = core::array::from_fn(|i| i as u8);
+        mock.inject_rx(&data[..32]);
+        serial.interrupt_handler();
+        let held_off = mock.read_ier() & IER_ERBFI == 0;
+        mock.inject_rx(&data[32..]);
+        // nothing raised while held off
+        serial.interrupt_handler();
+        let rx_intr_count = serial.stats().rx_intr_count;
+        report.check(
+            "rx interrupt holds ERBFI off",
+            held_off && rx_intr_count == 1,
+        );
+        report.check(
+            "timer drains the FIFO",
+            wait(|| serial.stats().rx_count == data.len()),
+        );
+        report.check(
+            "ERBFI back on once idle",
+            wait(|| mock.read_ier() & IER_ERBFI != 0) && serial.stats().rx_coalesce_count >= 2,
+        );
+        unsafe {
+            uie::clear_utimer();
+        }
+        let mut buf = [0u8; 80];
+        let len = serial.read_available(&mut buf);
+        report.check("bytes in order", buf[..len] == data[..]);
+
+        let stats = serial.stats();
+        println!(
+            "[uart coalesce] {} rx interrupts, {} timer expiries, {} per KiB",
+            stats.rx_intr_count + stats.rx_timeout_count,
+            stats.rx_coalesce_count,
+            stats.rx_intr_per_kb()
+        );
+        report.check(
+            "coalescing off",
+            serial.set_coalescing(None).is_ok()
+                && serial.coalescing().is_none()
+                && mock.read_ier() & IER_ERBFI != 0,
+        );
+        report.exit_code()
+    }
+}
@@ -0,0 +1,111 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+mock_uart_main!("uart diag tap", mock::run);
+
+/// Runs a `DiagnosticTap` on a `MockUart`: escapes taken out of the data,
+/// split over reads, held back and let out again, the tap turned off, and
+/// the dump written back.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use alloc::sync::Arc;
+    use alloc::vec::Vec;
+    use user_lib::executor::block_on_with;
+    use user_lib::timer::timeout;
+    use user_lib::user_uart::{regs::*, *};
+
+    const BAUD_RATE: usize = 115_200;
+    /// Long enough for a dump to go out.
+    const DUMP_US: usize = 200_000;
+
+    /// Reads until `len` bytes came or a read finds nothing for twice the
+    /// hold time.
+    fn read_all(tap: &mut DiagnosticTap<&'static MockUart>, len: usize) -> Vec<u8> {
+        let serial = tap.serial().clone();
+        let mut out = Vec::new();
+        let mut buf = [0u8; 4];
+        while out.len() < len {
+            let read = block_on_with(timeout(2 * ESCAPE_HOLD_US, tap.read(&mut buf)), || {
+                serial.interrupt_handler()
+            });
+            match read {
+                Ok(n) => out.extend_from_slice(&buf[..n]),
+                Err(_) => break,
+            }
+        }
+        out
+    }
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart diag tap");
+        let (mock, serial) = MockUart::async_serial(BAUD_RATE);
+        let serial = Arc::new(serial);
+        let mut tap = DiagnosticTap::new(serial.clone());
+
+        mock.inject_rx(b"ab");
+        mock.inject_rx(DEFAULT_ESCAPE);
+        mock.inject_rx(b"cd");
+        report.check("escape taken out", read_all(&mut tap, 4) == b"abcd");
+        report.check("dump requested", tap.dumps_requested() == 1);
+
+        mock.inject_rx(b"x");
+        mock.inject_rx(&DEFAULT_ESCAPE[..3]);
+        let first = read_all(&mut tap, 1);
+        mock.inject_rx(&DEFAULT_ESCAPE[3..]);
+        mock.inject_rx(b"y");
+        let second = read_all(&mut tap, 1);
+        report.check(
+            "split escape found",
+            first == b"x" && second == b"y" && tap.dumps_requested() == 2,
+        );
+
+        // starts like the escape, then goes on differently
+        mock.inject_rx(&DEFAULT_ESCAPE[..2]);
+        mock.inject_rx(b"z");
+        let mut expected = Vec::from(&DEFAULT_ESCAPE[..2]);
+        expected.push(b'z');
+        report.check("near miss let out", read_all(&mut tap, 3) == expected);
+
+        mock.inject_rx(&DEFAULT_ESCAPE[..4]);
+        report.check(
+            "held start let out after the hold time",
+            read_all(&mut tap, 4) == DEFAULT_ESCAPE[..4],
+        );
+
+        mock.inject_rx(&[0x01, 0x01]);
+        mock.inject_rx(&DEFAULT_ESCAPE[1..]);
+        report.check(
+            "escape after a repeated first byte",
+            read_all(&mut tap, 1) == [0x01] && tap.dumps_requested() == 3,
+        );
+
+        report.check("too long refused", tap.set_escape(Some(&[0; 9])).is_err());
+        report.check("off", tap.set_escape(None).is_ok());
+        mock.inject_rx(DEFAULT_ESCAPE);
+        report.check(
+            "passed through when off",
+            read_all(&mut tap, DEFAULT_ESCAPE.len()) == DEFAULT_ESCAPE
+                && tap.dumps_requested() == 3,
+        );
+
+        mock.take_tx();
+        let pump = || {
+            // CTS credit for the next FIFO
+            mock.inject_modem_status(MSR_CTS | MSR_DCTS);
+            serial.interrupt_handler()
+        };
+        let _ = block_on_with(timeout(DUMP_US, tap.dumper()), pump);
+        let dump = mock.take_tx();
+        let dump = core::str::from_utf8(&dump).unwrap_or("");
+        report.check(
+            "one dump for the escapes so far",
+            dump.matches("[diag] end").count() == 1 && dump.contains("[diag] SerialStats"),
+        );
+
+        report.exit_code()
+    }
+}
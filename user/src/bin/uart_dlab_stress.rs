@@ -0,0 +1,106 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+mock_uart_main!("uart dlab stress", mock::run);
+
+/// Changes the baud rate of a `MockUart` over and over while the user
+/// timer interrupt services the port. The mock aliases RBR/THR and IER to
+/// the divisor latches while LCR.DLAB is set, so a handler run inside the
+/// latch window would show up as a wrong divisor or a stuck DLAB.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use alloc::{boxed::Box, sync::Arc, task::Wake};
+    use core::future::Future;
+    use core::pin::Pin;
+    use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed};
+    use core::task::{Context, Waker};
+    use riscv::register::uie;
+    use spin::Mutex;
+    use user_lib::init_user_trap;
+    use user_lib::timer::{now_us, sleep_us, Sleep};
+    use user_lib::user_uart::{regs::*, *};
+
+    const RATES: [usize; 5] = [9600, 19200, 38400, 57600, 115_200];
+    const RUN_US: usize = 300_000;
+    const HAMMER_PERIOD_US: usize = 1_000;
+
+    /// Services the port from the timer interrupt, then sleeps again.
+    struct Hammer {
+        mock: &'static MockUart,
+        serial: Arc<AsyncSerial<&'static MockUart>>,
+        sleep: Mutex<Option<Pin<Box<Sleep>>>>,
+        hits: AtomicUsize,
+        stopped: AtomicBool,
+    }
+
+    impl Hammer {
+        fn arm(self: &Arc<Self>) {
+            let waker = Waker::from(self.clone());
+            let mut sleep = Box::pin(sleep_us(HAMMER_PERIOD_US));
+            let _ = sleep.as_mut().poll(&mut Context::from_waker(&waker));
+            *self.sleep.lock() = Some(sleep);
+        }
+    }
+
+    impl Wake for Hammer {
+        fn wake(self: Arc<Self>) {
+            if self.stopped.load(Relaxed) {
+                return;
+            }
+            self.mock.inject_rx(b"dlab");
+            self.serial.interrupt_handler();
+            let mut buf = [0u8; 8];
+            self.serial.read_available(&mut buf);
+            self.hits.fetch_add(1, Relaxed);
+            self.arm();
+        }
+    }
+
+    pub fn run() -> i32 {
+        let init_res = init_user_trap();
+        println!("[uart dlab stress] trap init result: {:#x}", init_res);
+        let (mock, serial) = MockUart::async_serial(115_200);
+        let serial = Arc::new(serial);
+
+        let hammer = Arc::new(Hammer {
+            mock,
+            serial: serial.clone(),
+            sleep: Mutex::new(None),
+            hits: AtomicUsize::new(0),
+            stopped: AtomicBool::new(false),
+        });
+        hammer.arm();
+        unsafe {
+            uie::set_utimer();
+        }
+        let start = now_us();
+        let mut changes = 0;
+        let mut corrupted = 0;
+        while now_us() - start < RUN_US {
+            let baud = RATES[changes % RATES.len()];
+            serial.set_baud(baud);
+            changes += 1;
+            if mock.divisor() as usize != 100_000_000 / (16 * baud) || mock.read_lcr() != LCR_8N1 {
+                corrupted += 1;
+            }
+        }
+        hammer.stopped.store(true, Relaxed);
+        unsafe {
+            uie::clear_utimer();
+        }
+        let hits = hammer.hits.load(Relaxed);
+        println!(
+            "[uart dlab stress] {} baud changes, {} handler runs, {} corrupted",
+            changes, hits, corrupted
+        );
+        if corrupted == 0 && hits > 0 && mock.read_ier() & IER_ERBFI != 0 {
+            0
+        } else {
+            -1
+        }
+    }
+}
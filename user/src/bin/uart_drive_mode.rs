@@ -0,0 +1,100 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+mock_uart_main!("uart drive mode", mock::run);
+
+/// Switches an `AsyncSerial` between `DriveMode::Interrupt` and
+/// `DriveMode::Polled` with a read pending and bytes queued, and checks
+/// nothing is lost or woken twice.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use alloc::{boxed::Box, sync::Arc};
+    use user_lib::user_uart::{regs::*, *};
+
+    const BAUD_RATE: usize = 115_200;
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart drive mode");
+        let (mock, serial) = MockUart::async_serial(BAUD_RATE);
+        let serial = Arc::new(serial);
+        serial.interrupt_handler();
+
+        // a read of 6 gets 3 bytes by interrupt
+        let waker = Arc::new(CountingWaker::default());
+        let mut buf = [0u8; 6];
+        let mut read = Box::pin(serial.clone().read(&mut buf));
+        report.check(
+            "read pending",
+            poll_once(read.as_mut(), &waker).is_pending(),
+        );
+        mock.inject_rx(b"abc");
+        serial.interrupt_handler();
+        report.check(
+            "read partial",
+            poll_once(read.as_mut(), &waker).is_pending(),
+        );
+
+        serial.set_mode(DriveMode::Polled);
+        report.check(
+            "polled: no interrupt enabled",
+            serial.drive_mode() == DriveMode::Polled && mock.read_ier() == 0,
+        );
+        // an interrupt raised before the switch is handled after it
+        let intr_count = serial.stats().intr_count;
+        mock.inject_rx(b"def");
+        mock.inject_iid(IID_RX_DATA);
+        serial.interrupt_handler();
+        report.check(
+            "polled: late interrupt ignored",
+            mock.rx_left() == 3 && serial.stats().intr_count == intr_count,
+        );
+        let wakes = waker.wakes();
+        serial.pump();
+        report.check(
+            "polled: pump wakes the read",
+            mock.rx_left() == 0 && waker.wakes() > wakes,
+        );
+        report.check(
+            "polled: read complete",
+            poll_once(read.as_mut(), &waker).is_ready(),
+        );
+        drop(read);
+        report.check("polled: read got all 6", &buf == b"abcdef");
+
+        mock.take_tx();
+        report.check(
+            "polled: write queued",
+            serial.write_available(b"xyz") == 3 && mock.read_ier() == 0,
+        );
+        serial.pump();
+        report.check("polled: pump sends", mock.take_tx() == b"xyz");
+
+        // bytes received and queued while polled stay for interrupt mode
+        mock.inject_rx(b"gh");
+        serial.pump();
+        mock.inject_rx(b"ij");
+        serial.set_mode(DriveMode::Interrupt);
+        report.check(
+            "interrupt: rx interrupt back on",
+            serial.drive_mode() == DriveMode::Interrupt && mock.read_ier() & IER_ERBFI != 0,
+        );
+        serial.interrupt_handler();
+        let mut rest = [0u8; 8];
+        let len = serial.read_available(&mut rest);
+        report.check("interrupt: queued and new bytes", &rest[..len] == b"ghij");
+
+        // and the other way round
+        mock.inject_rx(b"kl");
+        serial.interrupt_handler();
+        serial.set_mode(DriveMode::Polled);
+        let len = serial.read_available(&mut rest);
+        report.check("polled: bytes queued before", &rest[..len] == b"kl");
+        serial.set_mode(DriveMode::Interrupt);
+
+        report.exit_code()
+    }
+}
@@ -0,0 +1,611 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::{sync::Arc, vec, vec::Vec};
+use core::fmt::{self, Write as FmtWrite};
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed};
+use core::task::{Context, Poll};
+use embedded_hal::serial::{Read, Write};
+use heapless::spsc::Queue;
+use riscv::register::uie;
+use user_lib::{
+    console,
+    executor::{Executor, IdleStrategy},
+    get_time_us, init_user_trap,
+    timer::cycles,
+    user_uart::*,
+    write,
+};
+
+const BAUD_RATE: usize = 115_200;
+/// Print one CSV row per driver instead of the table, to keep the
+/// numbers of different commits side by side.
+const CSV_OUTPUT: bool = false;
+const WARMUP_BYTES: usize = 256;
+const THROUGHPUT_BYTES: usize = 8192;
+const ROUND_TRIPS: usize = 64;
+/// Slack on top of twice the time the bytes take on the wire.
+const TIMEOUT_MARGIN_US: usize = 1_000_000;
+/// The time is a syscall, only read it once in this many polls.
+const TIME_CHECK_PERIOD: usize = 1024;
+/// Lines the console logging scenario prints each way.
+const LOG_LINES: usize = 32;
+/// Writes of 3 to 10 bytes the small write scenario makes, a logger's
+/// pattern, and the tx coalescing delay it is run with.
+const SMALL_WRITES: usize = 256;
+const SMALL_WRITE_DELAY_US: usize = 500;
+
+type RxBuffer = Queue<u8, DEFAULT_RX_BUFFER_SIZE>;
+type TxBuffer = Queue<u8, DEFAULT_TX_BUFFER_SIZE>;
+const EMPTY_RX_BUFFER: RxBuffer = RxBuffer::new();
+const EMPTY_TX_BUFFER: TxBuffer = TxBuffer::new();
+static mut DRIVER_RX_BUFFERS: [RxBuffer; 2] = [EMPTY_RX_BUFFER; 2];
+static mut DRIVER_TX_BUFFERS: [TxBuffer; 2] = [EMPTY_TX_BUFFER; 2];
+
+#[derive(Clone, Copy)]
+enum Driver {
+    Polling,
+    Buffered,
+    Async,
+}
+
+const DRIVERS: [Driver; 3] = [Driver::Polling, Driver::Buffered, Driver::Async];
+
+impl Driver {
+    fn name(self) -> &'static str {
+        match self {
+            Driver::Polling => "polling",
+            Driver::Buffered => "buffered",
+            Driver::Async => "async",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Throughput {
+    us: usize,
+    cycles: usize,
+    /// Interrupt sources the driver handled, 0 for the polling one.
+    intrs: usize,
+    /// Queue high-water marks of the receiving and the sending port, 0
+    /// for the drivers that don't report them.
+    rx_high_water: usize,
+    tx_high_water: usize,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Latency {
+    total_us: usize,
+    max_us: usize,
+}
+
+fn now() -> usize {
+    get_time_us() as usize
+}
+
+fn timeout_us(len: usize) -> usize {
+    2 * len * 10 * 1_000_000 / BAUD_RATE + TIMEOUT_MARGIN_US
+}
+
+fn pattern(index: usize) -> u8 {
+    (index.wrapping_mul(31) ^ (index >> 8)) as u8
+}
+
+/// Checks the deadline every `TIME_CHECK_PERIOD` calls.
+struct Deadline {
+    at: usize,
+    polls: usize,
+}
+
+impl Deadline {
+    fn new(len: usize) -> Self {
+        Deadline {
+            at: now() + timeout_us(len),
+            polls: 0,
+        }
+    }
+
+    fn passed(&mut self) -> bool {
+        self.polls += 1;
+        self.polls % TIME_CHECK_PERIOD == 0 && now() >= self.at
+    }
+}
+
+/// Sends `len` bytes from `tx` to `rx` with nonblocking calls, running
+/// `service` on both in between. `None` on a lost or wrong byte.
+fn nb_throughput<S: Read<u8> + Write<u8>>(
+    tx: &mut S,
+    rx: &mut S,
+    len: usize,
+    service: fn(&mut S),
+    intrs: fn(&S) -> usize,
+) -> Option<Throughput> {
+    let intrs_before = intrs(tx) + intrs(rx);
+    let mut deadline = Deadline::new(len);
+    let (start_us, start_cycles) = (now(), cycles());
+    let (mut sent, mut received) = (0, 0);
+    while received < len {
+        while sent < len && tx.try_write(pattern(sent)).is_ok() {
+            sent += 1;
+        }
+        service(tx);
+        service(rx);
+        while let Ok(ch) = rx.try_read() {
+            if ch != pattern(received) {
+                return None;
+            }
+            received += 1;
+        }
+        if deadline.passed() {
+            return None;
+        }
+    }
+    Some(Throughput {
+        cycles: cycles().wrapping_sub(start_cycles),
+        us: now() - start_us,
+        intrs: intrs(tx) + intrs(rx) - intrs_before,
+        ..Default::default()
+    })
+}
+
+/// Sends a byte from `a` to `b`, which echoes it back, `ROUND_TRIPS`
+/// times.
+fn nb_latency<S: Read<u8> + Write<u8>>(
+    a: &mut S,
+    b: &mut S,
+    service: fn(&mut S),
+) -> Option<Latency> {
+    let mut latency = Latency::default();
+    let mut deadline = Deadline::new(2 * ROUND_TRIPS);
+    for round in 0..ROUND_TRIPS {
+        let start_us = now();
+        while a.try_write(pattern(round)).is_err() {
+            service(a);
+        }
+        let echoed = loop {
+            service(a);
+            service(b);
+            if let Ok(ch) = b.try_read() {
+                while b.try_write(ch).is_err() {
+                    service(b);
+                }
+            }
+            if let Ok(ch) = a.try_read() {
+                break ch;
+            }
+            if deadline.passed() {
+                return None;
+            }
+        };
+        let us = now() - start_us;
+        if echoed != pattern(round) {
+            return None;
+        }
+        latency.total_us += us;
+        latency.max_us = latency.max_us.max(us);
+    }
+    Some(latency)
+}
+
+fn open_async(claim: &SerialClaim, slot: usize) -> Arc<AsyncSerial> {
+    let (rx_pro, rx_con, tx_pro, tx_con) = unsafe {
+        DRIVER_RX_BUFFERS[slot] = RxBuffer::new();
+        DRIVER_TX_BUFFERS[slot] = TxBuffer::new();
+        let (rx_pro, rx_con) = DRIVER_RX_BUFFERS[slot].split();
+        let (tx_pro, tx_con) = DRIVER_TX_BUFFERS[slot].split();
+        (rx_pro, rx_con, tx_pro, tx_con)
+    };
+    let serial = Arc::new(AsyncSerial::from_claim(
+        claim, rx_pro, rx_con, tx_pro, tx_con,
+    ));
+    serial.hardware_init(BAUD_RATE);
+    serial
+}
+
+/// Runs `exec` until `done`, pumping both ports in between, as the
+/// interrupts are not enabled. Returns false on timeout.
+fn run_pumped(
+    exec: &Executor,
+    a: &AsyncSerial,
+    b: &AsyncSerial,
+    done: &AtomicBool,
+    mut deadline: Deadline,
+) -> bool {
+    let mut timed_out = false;
+    exec.run_until(|| {
+        a.pump();
+        b.pump();
+        timed_out = deadline.passed();
+        done.load(Relaxed) || timed_out
+    });
+    a.remove_read();
+    a.remove_write();
+    b.remove_read();
+    b.remove_write();
+    !timed_out
+}
+
+fn async_intrs(a: &AsyncSerial, b: &AsyncSerial) -> usize {
+    a.stats().intr_count + b.stats().intr_count
+}
+
+/// The tasks only take the time when they finish, the executor and the
+/// driver see nothing else of the measurement.
+fn async_throughput(
+    tx: &Arc<AsyncSerial>,
+    rx: &Arc<AsyncSerial>,
+    len: usize,
+) -> Option<Throughput> {
+    static DONE: AtomicBool = AtomicBool::new(false);
+    static MATCHED: AtomicBool = AtomicBool::new(false);
+    static END_US: AtomicUsize = AtomicUsize::new(0);
+    static END_CYCLES: AtomicUsize = AtomicUsize::new(0);
+    DONE.store(false, Relaxed);
+
+    let exec = Executor::new(IdleStrategy::Spin);
+    let writer = tx.clone();
+    exec.spawn(async move {
+        let data: Vec<u8> = (0..len).map(pattern).collect();
+        let _ = writer.write(&data).await;
+    });
+    let reader = rx.clone();
+    exec.spawn(async move {
+        let mut buf = vec![0u8; len];
+        let _ = reader.read(&mut buf).await;
+        END_CYCLES.store(cycles(), Relaxed);
+        END_US.store(now(), Relaxed);
+        MATCHED.store(
+            buf.iter().enumerate().all(|(i, &ch)| ch == pattern(i)),
+            Relaxed,
+        );
+        DONE.store(true, Relaxed);
+    });
+    AsyncSerial::reset_stats(tx);
+    AsyncSerial::reset_stats(rx);
+    let intrs_before = async_intrs(tx, rx);
+    let (start_us, start_cycles) = (now(), cycles());
+    if !run_pumped(&exec, tx, rx, &DONE, Deadline::new(len)) || !MATCHED.load(Relaxed) {
+        return None;
+    }
+    Some(Throughput {
+        us: END_US.load(Relaxed) - start_us,
+        cycles: END_CYCLES.load(Relaxed).wrapping_sub(start_cycles),
+        intrs: async_intrs(tx, rx) - intrs_before,
+        rx_high_water: rx.stats().rx_high_water,
+        tx_high_water: tx.stats().tx_high_water,
+    })
+}
+
+fn async_latency(a: &Arc<AsyncSerial>, b: &Arc<AsyncSerial>) -> Option<Latency> {
+    static DONE: AtomicBool = AtomicBool::new(false);
+    static MATCHED: AtomicBool = AtomicBool::new(true);
+    static TOTAL_US: AtomicUsize = AtomicUsize::new(0);
+    static MAX_US: AtomicUsize = AtomicUsize::new(0);
+    DONE.store(false, Relaxed);
+    MATCHED.store(true, Relaxed);
+    TOTAL_US.store(0, Relaxed);
+    MAX_US.store(0, Relaxed);
+
+    let exec = Executor::new(IdleStrategy::Spin);
+    let echo = b.clone();
+    exec.spawn(async move {
+        let mut ch = [0u8];
+        for _ in 0..ROUND_TRIPS {
+            if echo.clone().read(&mut ch).await.is_err() || echo.clone().write(&ch).await.is_err() {
+                break;
+            }
+        }
+    });
+    let ping = a.clone();
+    exec.spawn(async move {
+        let mut ch = [0u8];
+        for round in 0..ROUND_TRIPS {
+            let start_us = now();
+            let _ = ping.clone().write(&[pattern(round)]).await;
+            let _ = ping.clone().read(&mut ch).await;
+            let us = now() - start_us;
+            TOTAL_US.fetch_add(us, Relaxed);
+            MAX_US.fetch_max(us, Relaxed);
+            if ch[0] != pattern(round) {
+                MATCHED.store(false, Relaxed);
+            }
+        }
+        DONE.store(true, Relaxed);
+    });
+    if !run_pumped(&exec, a, b, &DONE, Deadline::new(2 * ROUND_TRIPS)) || !MATCHED.load(Relaxed) {
+        return None;
+    }
+    Some(Latency {
+        total_us: TOTAL_US.load(Relaxed),
+        max_us: MAX_US.load(Relaxed),
+    })
+}
+
+/// Lets the executor run the pumps once before going on.
+struct YieldOnce(bool);
+
+impl Future for YieldOnce {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+/// Sends `SMALL_WRITES` small writes from `tx` to `rx`, each left to the
+/// pumps before the next, with tx coalescing after `delay_us` or
+/// without. Returns the THR empty interrupts per KiB sent.
+fn async_small_writes(
+    tx: &Arc<AsyncSerial>,
+    rx: &Arc<AsyncSerial>,
+    delay_us: Option<usize>,
+) -> Option<usize> {
+    static DONE: AtomicBool = AtomicBool::new(false);
+    static MATCHED: AtomicBool = AtomicBool::new(false);
+    DONE.store(false, Relaxed);
+    tx.set_tx_coalescing(delay_us).ok()?;
+
+    let lens: Vec<usize> = (0..SMALL_WRITES)
+        .map(|i| 3 + pattern(i) as usize % 8)
+        .collect();
+    let len: usize = lens.iter().sum();
+    let exec = Executor::new(IdleStrategy::Spin);
+    let writer = tx.clone();
+    exec.spawn(async move {
+        let data: Vec<u8> = (0..len).map(pattern).collect();
+        let mut at = 0;
+        for write_len in lens {
+            let _ = writer.clone().write(&data[at..at + write_len]).await;
+            at += write_len;
+            YieldOnce(false).await;
+        }
+    });
+    let reader = rx.clone();
+    exec.spawn(async move {
+        let mut buf = vec![0u8; len];
+        let _ = reader.read(&mut buf).await;
+        MATCHED.store(
+            buf.iter().enumerate().all(|(i, &ch)| ch == pattern(i)),
+            Relaxed,
+        );
+        DONE.store(true, Relaxed);
+    });
+    AsyncSerial::reset_stats(tx);
+    // the coalescing timer
+    unsafe {
+        uie::set_utimer();
+    }
+    let done = run_pumped(&exec, tx, rx, &DONE, Deadline::new(len));
+    unsafe {
+        uie::clear_utimer();
+    }
+    tx.set_tx_coalescing(None).ok()?;
+    if !done || !MATCHED.load(Relaxed) {
+        return None;
+    }
+    Some(tx.stats().tx_intr_per_kb())
+}
+
+/// The small write scenario on the async driver, with and without tx
+/// coalescing.
+fn small_write_bench(a: &SerialClaim, b: &SerialClaim) -> bool {
+    let (tx, rx) = (open_async(a, 0), open_async(b, 1));
+    let mut passed = true;
+    for (name, delay_us) in [("direct", None), ("coalesced", Some(SMALL_WRITE_DELAY_US))] {
+        match async_small_writes(&tx, &rx, delay_us) {
+            Some(per_kb) => println!(
+                "[uart driver bench] small writes {:<10} {:>6} tx intrs/KiB",
+                name, per_kb
+            ),
+            None => {
+                println!("[uart driver bench] small writes {:<10} FAILED", name);
+                passed = false;
+            }
+        }
+    }
+    passed
+}
+
+/// Warms up, then measures throughput from `a` to `b` and the round trip
+/// latency of `a` through `b`.
+fn bench(driver: Driver, a: &SerialClaim, b: &SerialClaim) -> Option<(Throughput, Latency)> {
+    match driver {
+        Driver::Polling => {
+            let (mut tx, mut rx) = (PollingSerial::from_claim(a), PollingSerial::from_claim(b));
+            tx.hardware_init(BAUD_RATE);
+            rx.hardware_init(BAUD_RATE);
+            let service = |_: &mut PollingSerial| {};
+            let intrs = |_: &PollingSerial| 0;
+            nb_throughput(&mut tx, &mut rx, WARMUP_BYTES, service, intrs)?;
+            let throughput = nb_throughput(&mut tx, &mut rx, THROUGHPUT_BYTES, service, intrs)?;
+            Some((throughput, nb_latency(&mut tx, &mut rx, service)?))
+        }
+        Driver::Buffered => {
+            let (mut tx, mut rx) = (BufferedSerial::from_claim(a), BufferedSerial::from_claim(b));
+            tx.hardware_init(BAUD_RATE);
+            rx.hardware_init(BAUD_RATE);
+            let service = |serial: &mut BufferedSerial| serial.interrupt_handler();
+            let intrs = |serial: &BufferedSerial| serial.intr_count;
+            nb_throughput(&mut tx, &mut rx, WARMUP_BYTES, service, intrs)?;
+            let throughput = nb_throughput(&mut tx, &mut rx, THROUGHPUT_BYTES, service, intrs)?;
+            Some((throughput, nb_latency(&mut tx, &mut rx, service)?))
+        }
+        Driver::Async => {
+            let (tx, rx) = (open_async(a, 0), open_async(b, 1));
+            async_throughput(&tx, &rx, WARMUP_BYTES)?;
+            let throughput = async_throughput(&tx, &rx, THROUGHPUT_BYTES)?;
+            Some((throughput, async_latency(&tx, &rx)?))
+        }
+    }
+}
+
+fn print_result(driver: Driver, result: Option<(Throughput, Latency)>) {
+    let (throughput, latency) = match result {
+        Some(result) => result,
+        None if CSV_OUTPUT => {
+            println!("{},FAILED,,,,,,", driver.name());
+            return;
+        }
+        None => {
+            println!("{:<10} FAILED", driver.name());
+            return;
+        }
+    };
+    let bytes_per_sec = THROUGHPUT_BYTES * 1_000_000 / throughput.us.max(1);
+    let cycles_per_kb = throughput.cycles * 1024 / THROUGHPUT_BYTES;
+    let mean_us = latency.total_us / ROUND_TRIPS;
+    if CSV_OUTPUT {
+        println!(
+            "{:?},{},{},{},{},{},{},{}",
+            driver.name(),
+            bytes_per_sec,
+            mean_us,
+            latency.max_us,
+            cycles_per_kb,
+            throughput.intrs,
+            throughput.rx_high_water,
+            throughput.tx_high_water
+        );
+    } else {
+        println!(
+            "{:<10} {:>10} {:>10} {:>10} {:>12} {:>8} {:>8} {:>8}",
+            driver.name(),
+            bytes_per_sec,
+            mean_us,
+            latency.max_us,
+            cycles_per_kb,
+            throughput.intrs,
+            throughput.rx_high_water,
+            throughput.tx_high_water
+        );
+    }
+}
+
+/// One `write` per piece of the format, as `print!` did before it kept
+/// lines back.
+struct UnbufferedStdout;
+
+impl FmtWrite for UnbufferedStdout {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        write(1, s.as_bytes());
+        Ok(())
+    }
+}
+
+fn timed(f: impl FnOnce()) -> (usize, usize) {
+    let (start_us, start_cycles) = (now(), cycles());
+    f();
+    (now() - start_us, cycles() - start_cycles)
+}
+
+/// Logs `LOG_LINES` lines to the console unbuffered and through
+/// `println!`, which writes a line with one syscall.
+fn console_bench() {
+    let unbuffered = timed(|| {
+        for i in 0..LOG_LINES {
+            let _ = write!(
+                UnbufferedStdout,
+                "[console log] line {} of {}, pattern {:#04x}\r\n",
+                i,
+                LOG_LINES,
+                pattern(i)
+            );
+        }
+    });
+    let buffered = timed(|| {
+        for i in 0..LOG_LINES {
+            println!(
+                "[console log] line {} of {}, pattern {:#04x}",
+                i,
+                LOG_LINES,
+                pattern(i)
+            );
+        }
+        console::flush();
+    });
+    for (name, (us, cycles)) in [("unbuffered", unbuffered), ("println", buffered)] {
+        println!(
+            "[uart driver bench] console {:<10} {:>8} us {:>10} cycles/line",
+            name,
+            us,
+            cycles / LOG_LINES
+        );
+    }
+}
+
+/// Compares the three drivers between the last two claimable ports, which
+/// have to be wired together. Everything is polled, so the interrupt
+/// counts are the sources each handler found when it was run. Console
+/// logging is timed first, unbuffered against `println!`, and small
+/// writes with tx coalescing last.
+#[no_mangle]
+pub fn main() -> i32 {
+    console_bench();
+    init_user_trap();
+    let ports: Vec<usize> = serial::enumerate()
+        .iter()
+        .filter(|port| port.is_claimable() && !port.is_claimed())
+        .map(|port| port.index)
+        .collect();
+    if ports.len() < 2 {
+        println!(
+            "[uart driver bench] needs two free ports, found {}",
+            ports.len()
+        );
+        return -1;
+    }
+    let (a, b) = (ports[ports.len() - 2], ports[ports.len() - 1]);
+    let (a, b) = match (SerialClaim::claim(a), SerialClaim::claim(b)) {
+        (Ok(a), Ok(b)) => (a, b),
+        (a, b) => {
+            println!(
+                "[uart driver bench] claim failed: {:?} {:?}",
+                a.err(),
+                b.err()
+            );
+            return -1;
+        }
+    };
+    let results: Vec<_> = DRIVERS
+        .iter()
+        .map(|&driver| bench(driver, &a, &b))
+        .collect();
+
+    if CSV_OUTPUT {
+        println!(
+            "driver,bytes_per_sec,rtt_mean_us,rtt_max_us,cycles_per_kb,intrs,rx_high_water,tx_high_water"
+        );
+    } else {
+        println!(
+            "[uart driver bench] port {} -> port {}, {} bytes, {} round trips",
+            a.port(),
+            b.port(),
+            THROUGHPUT_BYTES,
+            ROUND_TRIPS
+        );
+        println!(
+            "{:<10} {:>10} {:>10} {:>10} {:>12} {:>8} {:>8} {:>8}",
+            "driver", "bytes/s", "rtt us", "max us", "cycles/KB", "intrs", "rx hw", "tx hw"
+        );
+    }
+    for (&driver, &result) in DRIVERS.iter().zip(results.iter()) {
+        print_result(driver, result);
+    }
+    let small_writes = small_write_bench(&a, &b);
+    if results.iter().all(Option::is_some) && small_writes {
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,119 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+mock_uart_main!("uart drop pending", mock::run);
+
+/// Drops read and write futures of `AsyncSerial` while they are pending
+/// and checks the next operation is not disturbed.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use alloc::{boxed::Box, sync::Arc, vec::Vec};
+    use user_lib::user_uart::*;
+
+    const BAUD_RATE: usize = 115_200;
+
+    type Serial = Arc<AsyncSerial<&'static MockUart>>;
+
+    /// First read wants 8 bytes and gets 3, then is dropped. The second
+    /// read wants 5, gets exactly the 5 injected after the drop, and the
+    /// first read's waker is never woken again.
+    fn read_dropped(report: &mut MockReport, mock: &'static MockUart, serial: &Serial) {
+        let first_waker = Arc::new(CountingWaker::default());
+        let mut first_buf = [0u8; 8];
+        let mut first = Box::pin(serial.clone().read(&mut first_buf));
+        report.check(
+            "first read pending",
+            poll_once(first.as_mut(), &first_waker).is_pending(),
+        );
+        mock.inject_rx(b"abc");
+        serial.interrupt_handler();
+        report.check(
+            "first read partial",
+            poll_once(first.as_mut(), &first_waker).is_pending(),
+        );
+        let wakes = first_waker.wakes();
+        drop(first);
+        report.check("drop unregisters the read waker", !serial.has_read_waker());
+
+        let second_waker = Arc::new(CountingWaker::default());
+        let mut second_buf = [0u8; 5];
+        let mut second = Box::pin(serial.clone().read(&mut second_buf));
+        report.check(
+            "second read starts empty",
+            poll_once(second.as_mut(), &second_waker).is_pending(),
+        );
+        mock.inject_rx(b"defgh");
+        serial.interrupt_handler();
+        report.check(
+            "second read woken",
+            second_waker.wakes() > 0 && first_waker.wakes() == wakes,
+        );
+        report.check(
+            "second read complete",
+            poll_once(second.as_mut(), &second_waker).is_ready(),
+        );
+        drop(second);
+        let mut rest = [0u8; 8];
+        report.check(
+            "second read gets the new bytes",
+            &second_buf == b"defgh" && serial.read_available(&mut rest) == 0,
+        );
+    }
+
+    /// A write bigger than the tx queue is dropped once the queue is full.
+    /// Exactly the queued bytes go out, once, and a second write follows
+    /// them.
+    fn write_dropped(report: &mut MockReport, mock: &'static MockUart, serial: &Serial) {
+        mock.take_tx();
+        let long: Vec<u8> = (0..DEFAULT_TX_BUFFER_SIZE + 100)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let first_waker = Arc::new(CountingWaker::default());
+        let mut first = Box::pin(serial.clone().write(&long));
+        report.check(
+            "first write pending",
+            poll_once(first.as_mut(), &first_waker).is_pending(),
+        );
+        let wakes = first_waker.wakes();
+        drop(first);
+        report.check(
+            "drop unregisters the write waker",
+            !serial.has_write_waker(),
+        );
+
+        let second_waker = Arc::new(CountingWaker::default());
+        report.check("flush", serial.flush(EXIT_DRAIN_TIMEOUT_US));
+        let queued = DEFAULT_TX_BUFFER_SIZE - 1;
+        let sent = mock.take_tx();
+        report.check(
+            "exactly the queued bytes sent",
+            sent.len() == queued && sent[..] == long[..queued],
+        );
+        let mut second = Box::pin(serial.clone().write(b"xyz"));
+        report.check(
+            "second write complete",
+            poll_once(second.as_mut(), &second_waker).is_ready(),
+        );
+        drop(second);
+        serial.flush(EXIT_DRAIN_TIMEOUT_US);
+        report.check(
+            "second write sent alone",
+            mock.take_tx() == b"xyz" && first_waker.wakes() == wakes,
+        );
+    }
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart drop pending");
+        let (mock, serial) = MockUart::async_serial(BAUD_RATE);
+        let serial = Arc::new(serial);
+        serial.interrupt_handler();
+
+        read_dropped(&mut report, mock, &serial);
+        write_dropped(&mut report, mock, &serial);
+        report.exit_code()
+    }
+}
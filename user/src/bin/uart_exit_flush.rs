@@ -0,0 +1,75 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::sync::Arc;
+use heapless::spsc::Queue;
+use user_lib::{
+    executor::block_on_with, exit, fork, get_time, init_user_trap, read, user_uart::*, waitpid,
+    yield_,
+};
+
+/// Serial 3 sends, serial 2 is wired to it and read through the kernel.
+const TX_PORT: usize = 3;
+const PEER_FD: usize = 3;
+/// What the kernel driver of serial 2 runs at.
+const BAUD_RATE: usize = 6_250_000;
+const MESSAGE: &[u8] = b"[uart exit flush] the last line before exit has to arrive whole, \
+    even though it is far longer than the FIFO and still queued when the process exits\r\n";
+const READ_TIMEOUT_MS: isize = 1000;
+
+/// Queues the message and exits at once, without dropping the driver.
+fn write_and_exit() -> ! {
+    init_user_trap();
+    let claim = SerialClaim::claim(TX_PORT).unwrap();
+    type RxBuffer = Queue<u8, DEFAULT_RX_BUFFER_SIZE>;
+    type TxBuffer = Queue<u8, DEFAULT_TX_BUFFER_SIZE>;
+    static mut DRIVER_RX_BUFFER: RxBuffer = RxBuffer::new();
+    static mut DRIVER_TX_BUFFER: TxBuffer = TxBuffer::new();
+    let (rx_pro, rx_con) = unsafe { DRIVER_RX_BUFFER.split() };
+    let (tx_pro, tx_con) = unsafe { DRIVER_TX_BUFFER.split() };
+    let serial = Arc::new(AsyncSerial::from_claim(
+        &claim, rx_pro, rx_con, tx_pro, tx_con,
+    ));
+    serial.hardware_init(BAUD_RATE);
+    // completes once queued, not once sent
+    let _ = block_on_with(serial.clone().write(MESSAGE), || {});
+    exit(0);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut buf = [0u8; 64];
+    while read(PEER_FD, &mut buf) > 0 {}
+    let pid = fork();
+    if pid == 0 {
+        write_and_exit();
+    }
+    let mut exit_code = 0;
+    waitpid(pid as usize, &mut exit_code);
+
+    let mut received = [0u8; MESSAGE.len()];
+    let mut len = 0;
+    let start = get_time();
+    while len < received.len() && get_time() - start < READ_TIMEOUT_MS {
+        match read(PEER_FD, &mut received[len..]) {
+            n if n > 0 => len += n as usize,
+            _ => {
+                yield_();
+            }
+        }
+    }
+    println!(
+        "[uart exit flush] peer received {}/{} bytes",
+        len,
+        MESSAGE.len()
+    );
+    if &received[..len] == MESSAGE {
+        0
+    } else {
+        -1
+    }
+}
@@ -133,14 +133,14 @@ mod user_console {
 
 mod user_trap {
     use user_lib::{
+        irq,
         trace::{push_trace, U_TRAP_HANDLER, U_TRAP_RETURN},
-        trap::{get_context, hart_id, Plic},
     };
 
     #[no_mangle]
     pub fn soft_intr_handler(pid: usize, msg: usize) {
         if msg == 15 {
-            println!("[uart ext] Received SIGTERM, exiting...");
+            intr_println!("[uart ext] Received SIGTERM, exiting...");
             user_lib::exit(15);
         } else {
             user_println!("[uart ext] Received message 0x{:x} from pid {}", msg, pid);
@@ -150,14 +150,14 @@ mod user_trap {
     #[no_mangle]
     pub fn ext_intr_handler(irq: u16, is_from_kernel: bool) {
         if is_from_kernel {
-            println!("[uart ext] Received UEI from kernel, irq: {}", irq);
+            intr_println!("[uart ext] Received UEI from kernel, irq: {}", irq);
         } else {
-            println!("[uart ext] user external interrupt, irq: {}", irq);
+            intr_println!("[uart ext] user external interrupt, irq: {}", irq);
         }
         if irq == crate::UART_IRQN {
             push_trace(U_TRAP_HANDLER | 8 | 128);
             crate::SERIAL.lock().interrupt_handler();
-            Plic::complete(get_context(hart_id(), 'U'), irq);
+            irq::complete(irq);
             push_trace(U_TRAP_RETURN | 8 | 128);
         }
     }
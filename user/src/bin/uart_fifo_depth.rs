@@ -0,0 +1,67 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+mock_uart_main!("uart fifo depth", mock::run);
+
+/// Has `hardware_init` find 16 and 64 byte FIFOs on a `MockUart` and
+/// checks a THR empty refills that many bytes, also after the depth is
+/// lowered with bytes in flight.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use user_lib::user_uart::{regs::*, *};
+
+    const BAUD_RATE: usize = 115_200;
+
+    /// Depth found and bytes sent on the first THR empty, with 100 queued.
+    fn refill(deep: bool) -> (usize, usize) {
+        let (mock, serial) = MockUart::async_serial_uninit();
+        mock.set_deep_fifo(deep);
+        serial.hardware_init(BAUD_RATE);
+        serial.interrupt_handler();
+        serial.write_available(&[b'x'; 100]);
+        serial.interrupt_handler();
+        let sent = mock.take_tx().len();
+        let fifo_depth = serial.stats().fifo_depth;
+        // nothing will take the rest
+        core::mem::forget(serial);
+        (fifo_depth, sent)
+    }
+
+    /// Lowers the depth to 16 with 64 bytes in flight, then gives CTS
+    /// credit. Bytes sent on that THR empty, the handler must not panic.
+    fn lowered() -> usize {
+        let (mock, serial) = MockUart::async_serial_uninit();
+        mock.set_deep_fifo(true);
+        serial.hardware_init(BAUD_RATE);
+        serial.interrupt_handler();
+        serial.write_available(&[b'x'; 100]);
+        serial.interrupt_handler();
+        mock.take_tx();
+        serial.set_fifo_depth(FIFO_DEPTH);
+        mock.inject_modem_status(MSR_CTS | MSR_DCTS);
+        serial.interrupt_handler();
+        let sent = mock.take_tx().len();
+        core::mem::forget(serial);
+        sent
+    }
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart fifo depth");
+        let (depth, sent) = refill(false);
+        report.check("16550: 16 bytes", depth == FIFO_DEPTH && sent == FIFO_DEPTH);
+        let (depth, sent) = refill(true);
+        report.check(
+            "16750: 64 bytes",
+            depth == DEEP_FIFO_DEPTH && sent == DEEP_FIFO_DEPTH,
+        );
+        let sent = lowered();
+        report.check(
+            "lowered in flight: at most 16 bytes",
+            0 < sent && sent <= FIFO_DEPTH,
+        );
+        report.exit_code()
+    }
+}
@@ -0,0 +1,96 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+mock_uart_main!("uart fifo reset", mock::run);
+
+/// Resets the FIFOs of a `MockUart` that misbehaves on a reset the way
+/// the LRV part does: a spurious interrupt of the reset FIFO, then bogus
+/// IIDs for a few reads. Checks that a bare FCR write leaves them in IIR,
+/// that `reset_rx_fifo` and `reset_tx_fifo` do not and put IER back, and
+/// that `AsyncSerial` comes up on such a part without an unknown IID.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use user_lib::user_uart::{regs::*, *};
+
+    const BAUD_RATE: usize = 115_200;
+    /// No 16550 has it, the driver counts it as unknown.
+    const IID_BOGUS: u8 = 0b0011;
+    const BOGUS_READS: usize = 2;
+    /// THREI stays off for the rx resets: putting it back with the tx
+    /// FIFO empty raises a THR empty, for real.
+    const IER_RX_ARMED: u8 = IER_ERBFI | IER_ELSI;
+    const IER_TX_ARMED: u8 = IER_ETBEI | IER_ELSI;
+
+    fn iid(mock: &'static MockUart) -> u8 {
+        mock.read_iir() & IIR_IID_MASK
+    }
+
+    /// A glitching mock with `ier` on and nothing pending.
+    fn armed(ier: u8) -> &'static MockUart {
+        let mock = MockUart::new();
+        mock.glitch_fifo_reset(IID_BOGUS, BOGUS_READS);
+        mock.write_fcr(FCR_FIFO_ENABLE);
+        mock.write_ier(ier);
+        // the THR empty setting ETBEI raised
+        while iid(mock) != IID_NO_INTERRUPT {}
+        mock
+    }
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart fifo reset");
+        let mock = armed(IER_RX_ARMED);
+        mock.write_fcr(FCR_FIFO_ENABLE | FCR_RX_RESET);
+        let stale = [iid(mock), iid(mock), iid(mock)];
+        report.check(
+            "bare FCR write leaves a spurious interrupt and bogus IIDs",
+            stale == [IID_RX_DATA, IID_BOGUS, IID_BOGUS],
+        );
+
+        let mock = armed(IER_RX_ARMED);
+        mock.inject_rx(b"stale");
+        mock.reset_rx_fifo(FCR_FIFO_ENABLE);
+        report.check(
+            "rx reset: FIFO empty, IIR clean, IER back",
+            mock.rx_left() == 0 && iid(mock) == IID_NO_INTERRUPT && mock.read_ier() == IER_RX_ARMED,
+        );
+
+        let mock = armed(IER_TX_ARMED);
+        mock.reset_tx_fifo(FCR_FIFO_ENABLE);
+        // THREI back on with the FIFO empty raises it once, for real
+        let after = [iid(mock), iid(mock)];
+        report.check(
+            "tx reset: only the THR empty of the empty FIFO, IER back",
+            after == [IID_THR_EMPTY, IID_NO_INTERRUPT] && mock.read_ier() == IER_TX_ARMED,
+        );
+
+        let mock = armed(IER_RX_ARMED);
+        mock.inject_line_error(LSR_OE);
+        mock.reset_rx_fifo(FCR_FIFO_ENABLE);
+        report.check(
+            "a pending line status survives the reset",
+            iid(mock) == IID_LINE_STATUS,
+        );
+
+        let (mock, serial) = MockUart::async_serial_uninit();
+        mock.glitch_fifo_reset(IID_BOGUS, BOGUS_READS);
+        serial.hardware_init(BAUD_RATE);
+        serial.interrupt_handler();
+        mock.inject_rx(b"ok");
+        serial.interrupt_handler();
+        let stats = serial.stats();
+        report.check(
+            "AsyncSerial init sees no unknown IID",
+            stats.unknown_iids == 0 && stats.irq_loop_bailouts == 0 && serial.rx_len() == 2,
+        );
+        drop(serial);
+        report.check(
+            "drop leaves IIR clean",
+            iid(mock) == IID_NO_INTERRUPT && mock.read_ier() == 0,
+        );
+
+        report.exit_code()
+    }
+}
@@ -0,0 +1,78 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+mock_uart_main!("uart fill buf", mock::run);
+
+/// Runs the two-phase read of `AsyncSerial` on a `MockUart`: chunks out
+/// of the staging buffer, partial consumes, reads around a chunk, and
+/// `Lines` on top of the driver.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use alloc::sync::Arc;
+    use user_lib::executor::block_on_with;
+    use user_lib::user_uart::*;
+
+    const BAUD_RATE: usize = 115_200;
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart fill buf");
+        let (mock, serial) = MockUart::async_serial(BAUD_RATE);
+        let serial = Arc::new(serial);
+        let pump = || serial.interrupt_handler();
+
+        mock.inject_rx(b"hello\nworld");
+        let chunk = block_on_with(serial.fill_buf(), pump);
+        report.check("chunk of what came in", &chunk[..] == b"hello\nworld");
+        let mut buf = [0u8; 16];
+        report.check(
+            "reads wait for the chunk",
+            serial.read_available(&mut buf) == 0 && serial.rx_len() == 11,
+        );
+        chunk.consume(6);
+        report.check("rest staged", serial.rx_len() == 5);
+
+        mock.inject_rx(b"!");
+        serial.interrupt_handler();
+        let len = serial.read_available(&mut buf);
+        report.check("staged bytes read first", &buf[..len] == b"world!");
+
+        mock.inject_rx(b"ab");
+        let chunk = block_on_with(serial.fill_buf(), pump);
+        drop(chunk);
+        let chunk = block_on_with(serial.fill_buf(), pump);
+        report.check("dropped chunk keeps its bytes", &chunk[..] == b"ab");
+        let len = chunk.len();
+        chunk.consume(len);
+        report.check("consumed", serial.rx_len() == 0);
+
+        // the second line runs past the end of the line buffer
+        let mut lines = serial.clone().lines::<16>();
+        mock.inject_rx(b"abcdefghij\nklmnopqrstu\n");
+        mock.inject_rx(b"0123456789abcdefXYZ\nend\n");
+        let results = block_on_with(
+            async {
+                [
+                    lines.next_line().await,
+                    lines.next_line().await,
+                    lines.next_line().await,
+                    lines.next_line().await,
+                ]
+            },
+            pump,
+        );
+        report.check(
+            "lines across the buffer end",
+            results[0].as_deref() == Ok("abcdefghij") && results[1].as_deref() == Ok("klmnopqrstu"),
+        );
+        report.check(
+            "too long line dropped",
+            results[2] == Err(LineError::TooLong) && results[3].as_deref() == Ok("end"),
+        );
+
+        report.exit_code()
+    }
+}
@@ -0,0 +1,65 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+mock_uart_main!("uart fmt", mock::run);
+
+/// Formats an `AsyncSerial` and a `PollingSerial` with `Debug` and
+/// `Display`, and checks neither clears a pending line status.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use alloc::format;
+    use alloc::sync::Arc;
+    use user_lib::user_uart::{regs::*, *};
+
+    const BAUD_RATE: usize = 115_200;
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart fmt");
+        let (mock, serial) = MockUart::async_serial(BAUD_RATE);
+        let serial = Arc::new(serial);
+        serial.interrupt_handler();
+
+        mock.inject_rx(b"hello");
+        serial.interrupt_handler();
+        mock.inject_line_error(LSR_OE);
+        serial.interrupt_handler();
+        let stats = serial.stats();
+        let line = format!("{}", serial);
+        let expected = format!(
+            "uart@{:#x} rx=5 tx=0 irq={} ovf=1",
+            mock.base_address(),
+            stats.intr_count
+        );
+        println!("[uart fmt] {}", line);
+        report.check("display", line == expected);
+
+        // a line status nobody has handled yet stays pending
+        mock.inject_line_error(LSR_PE);
+        let debug = format!("{:?}", serial);
+        println!("[uart fmt] {}", debug);
+        report.check(
+            "debug",
+            debug.contains("rx_queue: 5") && debug.contains("overrun_count: 1"),
+        );
+        report.check("debug reads no LSR", mock.read_lsr() & LSR_PE != 0);
+
+        let polling = PollingSerial::with_registers(mock);
+        let line = format!("{}", polling);
+        report.check(
+            "polling display",
+            line == format!("uart@{:#x} rx=0 tx=0", mock.base_address()),
+        );
+        report.check(
+            "polling debug",
+            format!("{:?}", polling).starts_with("PollingSerial { base_address: "),
+        );
+        // its drop resets the same mock
+        core::mem::forget(polling);
+
+        report.exit_code()
+    }
+}
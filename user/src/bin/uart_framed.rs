@@ -0,0 +1,144 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::user_uart::framed::*;
+
+const MAX_FRAME: usize = 600;
+
+fn check(name: &str, ok: bool) -> bool {
+    println!(
+        "[uart framed] {}: {}",
+        name,
+        if ok { "ok" } else { "FAILED" }
+    );
+    ok
+}
+
+/// Frames that trip up a byte stuffing scheme: the delimiters of both
+/// codecs, nothing at all, and COBS block boundaries.
+fn frames() -> Vec<Vec<u8>> {
+    let mut frames = Vec::new();
+    frames.push(b"hello".to_vec());
+    frames.push(Vec::new());
+    frames.push(alloc::vec![0; 3]);
+    frames.push(alloc::vec![0xc0, 0xdb, 0xdc, 0xdd, 0xc0]);
+    frames.push(alloc::vec![0x55; 254]);
+    frames.push((0..=255u8).cycle().skip(1).take(MAX_FRAME).collect());
+    frames.push(alloc::vec![0; MAX_FRAME]);
+    frames
+}
+
+/// Encodes and decodes every frame, checking the encoding holds no
+/// delimiter and fits `max_encoded_len`.
+fn round_trip<C: FrameCodec>(name: &str) -> bool {
+    let mut passed = true;
+    for frame in frames() {
+        let mut encoded = alloc::vec![0xaa; C::max_encoded_len(frame.len())];
+        let len = C::encode(&frame, &mut encoded);
+        let clean = len <= encoded.len() && !encoded[..len].contains(&C::DELIMITER);
+        let decoded = C::decode(&mut encoded[..len]);
+        passed &= clean && decoded == Ok(frame.len()) && encoded[..frame.len()] == frame[..];
+    }
+    passed &= C::decode(&mut [0xdb, 0x05, b'a']) == Err(FrameError::Corrupt);
+    check(name, passed)
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut passed = round_trip::<Cobs>("cobs round trip");
+    #[cfg(feature = "slip")]
+    {
+        passed &= round_trip::<Slip>("slip round trip");
+    }
+    passed &= loopback();
+    if passed {
+        0
+    } else {
+        -1
+    }
+}
+
+#[cfg(not(feature = "mock_uart"))]
+fn loopback() -> bool {
+    println!("[uart framed] loopback: built without the mock_uart feature, skipped");
+    true
+}
+
+/// Sends the frames over a `MockUart` whose tx is fed back to its rx a
+/// byte per interrupt, so every frame spans many reads, then garbage and
+/// a frame over the limit that the receiver has to get past.
+#[cfg(feature = "mock_uart")]
+fn loopback() -> bool {
+    use alloc::collections::VecDeque;
+    use alloc::sync::Arc;
+    use user_lib::executor::block_on_with;
+    use user_lib::user_uart::{regs::*, *};
+
+    let (mock, serial) = MockUart::async_serial(115_200);
+    let serial = Arc::new(serial);
+    serial.interrupt_handler();
+    let mut framed = FramedSerial::new(serial.clone(), MAX_FRAME);
+    let mut wire = VecDeque::new();
+    let mut pump = || {
+        // CTS credit for the next FIFO
+        mock.inject_modem_status(MSR_CTS | MSR_DCTS);
+        serial.interrupt_handler();
+        wire.extend(mock.take_tx());
+        if let Some(byte) = wire.pop_front() {
+            mock.inject_rx(&[byte]);
+        }
+        serial.interrupt_handler();
+    };
+
+    let mut buf = [0u8; MAX_FRAME];
+    let mut passed = true;
+    for frame in frames() {
+        let len = block_on_with(
+            async {
+                framed.send_frame(&frame).await.unwrap();
+                framed.recv_frame(&mut buf).await
+            },
+            &mut pump,
+        );
+        passed &= len == Ok(frame.len()) && buf[..frame.len()] == frame[..];
+    }
+    passed = check("loopback: every frame back", passed);
+
+    let over = alloc::vec![1; MAX_FRAME + 1];
+    passed &= check(
+        "loopback: sending over the limit refused",
+        block_on_with(framed.send_frame(&over), &mut pump) == Err(FrameError::TooLong),
+    );
+
+    // a bad code, a frame too long for the receiver, then a good one
+    let mut garbage = alloc::vec![0x05, b'a', 0];
+    garbage.extend(core::iter::repeat(1).take(2 * MAX_FRAME));
+    garbage.push(0);
+    mock.inject_rx(&garbage);
+    let results = block_on_with(
+        async {
+            let corrupt = framed.recv_frame(&mut buf).await;
+            let too_long = framed.recv_frame(&mut buf).await;
+            framed.send_frame(b"after").await.unwrap();
+            let after = framed.recv_frame(&mut buf).await;
+            (corrupt, too_long, after)
+        },
+        &mut pump,
+    );
+    passed &= check(
+        "loopback: bad frames reported, resynced",
+        results
+            == (
+                Err(FrameError::Corrupt),
+                Err(FrameError::TooLong),
+                Ok(b"after".len()),
+            )
+            && &buf[..5] == b"after",
+    );
+    passed
+}
@@ -0,0 +1,101 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+mock_uart_main!("uart handler budget", mock::run);
+
+/// Times `AsyncSerial::interrupt_handler` on a `MockUart` and runs it
+/// against a budget of one cycle, which every call is over. Checks the
+/// min/mean/max kept, that a budget that only watches leaves the work
+/// alone, and that one that cuts short leaves the next interrupt source
+/// raised for the next call.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use user_lib::user_uart::{regs::*, *};
+
+    const BAUD_RATE: usize = 115_200;
+    const CALLS: usize = 8;
+
+    fn iid(mock: &'static MockUart) -> u8 {
+        mock.read_iir() & IIR_IID_MASK
+    }
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart handler budget");
+        let (mock, serial) = MockUart::async_serial(BAUD_RATE);
+        serial.interrupt_handler();
+        serial.reset_stats();
+        let mut buf = [0u8; 16];
+
+        for _ in 0..CALLS {
+            mock.inject_rx(b"tick");
+            serial.interrupt_handler();
+        }
+        let handler = serial.stats().handler;
+        report.check(
+            "every call timed",
+            handler.calls == CALLS
+                && 0 < handler.min
+                && handler.min <= handler.mean()
+                && handler.mean() <= handler.max
+                && handler.over_budget == 0,
+        );
+        println!(
+            "[uart handler budget] cycles min {}, mean {}, max {}",
+            handler.min,
+            handler.mean(),
+            handler.max
+        );
+        serial.read_available(&mut [0u8; 4 * CALLS]);
+
+        serial.set_handler_budget(Some(HandlerBudget {
+            cycles: 1,
+            cut_short: false,
+        }));
+        serial.reset_stats();
+        mock.inject_rx(b"ab");
+        mock.inject_modem_status(MSR_CTS | MSR_DCTS);
+        serial.interrupt_handler();
+        let handler = serial.stats().handler;
+        report.check(
+            "watched: over, all served",
+            handler.over_budget == 1
+                && handler.cut_short == 0
+                && serial.rx_len() == 2
+                && iid(mock) == IID_NO_INTERRUPT,
+        );
+        serial.read_available(&mut buf);
+
+        serial.set_handler_budget(Some(HandlerBudget {
+            cycles: 1,
+            cut_short: true,
+        }));
+        mock.inject_rx(b"cd");
+        mock.inject_modem_status(MSR_DCTS);
+        serial.interrupt_handler();
+        report.check(
+            "cut short after the rx data",
+            serial.stats().handler.cut_short == 1
+                && serial.rx_len() == 2
+                && iid(mock) == IID_MODEM_STATUS,
+        );
+        serial.interrupt_handler();
+        let handler = serial.stats().handler;
+        report.check(
+            "the next call goes on",
+            handler.calls == 3 && handler.over_budget == 3 && iid(mock) == IID_NO_INTERRUPT,
+        );
+
+        serial.set_handler_budget(None);
+        serial.reset_stats();
+        let handler = serial.stats().handler;
+        report.check(
+            "off, stats reset",
+            serial.handler_budget().is_none() && handler.calls == 0 && handler.min == 0,
+        );
+
+        report.exit_code()
+    }
+}
@@ -0,0 +1,116 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::sync::Arc;
+use heapless::spsc::Queue;
+use user_lib::{executor::block_on_with, fork, init_user_trap, user_uart::*, waitpid, yield_};
+
+const PORT: usize = 1;
+const BAUD_RATE: usize = 115_200;
+
+type RxBuffer = Queue<u8, DEFAULT_RX_BUFFER_SIZE>;
+type TxBuffer = Queue<u8, DEFAULT_TX_BUFFER_SIZE>;
+
+/// Takes the port over from the parent and writes through it without
+/// resetting anything.
+fn worker() -> i32 {
+    let claim = loop {
+        match SerialClaim::inherit(PORT) {
+            Ok(claim) => break claim,
+            Err(ClaimError::NotHeld) => {
+                yield_();
+            }
+            Err(err) => {
+                println!("[uart handoff] inherit failed: {:?}", err);
+                return -1;
+            }
+        }
+    };
+    let block = claim.registers();
+    // hardware_init in the parent asserted RTS, a reset would drop it
+    let rts = block.mcr.read().rts().is_asserted();
+    let ier = block.ier().read().bits();
+    println!(
+        "[uart handoff] worker inherited port {}, rts {}, ier {:#x}",
+        claim.port(),
+        rts,
+        ier
+    );
+
+    static mut RX_BUFFER: RxBuffer = RxBuffer::new();
+    static mut TX_BUFFER: TxBuffer = TxBuffer::new();
+    let (rx_pro, rx_con) = unsafe { RX_BUFFER.split() };
+    let (tx_pro, tx_con) = unsafe { TX_BUFFER.split() };
+    let serial = match AsyncSerial::from_claimed_running(&claim, rx_pro, rx_con, tx_pro, tx_con) {
+        Some(serial) => Arc::new(serial),
+        None => {
+            println!("[uart handoff] port was not running");
+            return -1;
+        }
+    };
+    let pump_serial = serial.clone();
+    let _ = block_on_with(serial.clone().write(b"worker took over\r\n"), || {
+        pump_serial.pump()
+    });
+    serial.remove_write();
+    if rts && ier != 0 {
+        0
+    } else {
+        -1
+    }
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    init_user_trap();
+    let claim = SerialClaim::claim(PORT).unwrap();
+    static mut RX_BUFFER: RxBuffer = RxBuffer::new();
+    static mut TX_BUFFER: TxBuffer = TxBuffer::new();
+    let (rx_pro, rx_con) = unsafe { RX_BUFFER.split() };
+    let (tx_pro, tx_con) = unsafe { TX_BUFFER.split() };
+    let serial = AsyncSerial::from_claim(&claim, rx_pro, rx_con, tx_pro, tx_con);
+    serial.hardware_init(BAUD_RATE);
+
+    // a target that is gone must leave the claim with us
+    let gone = fork();
+    if gone == 0 {
+        return 0;
+    }
+    let mut exit_code = 0;
+    waitpid(gone as usize, &mut exit_code);
+    let claim = match claim.transfer(gone as usize) {
+        Err((claim, ClaimError::InvalidTarget)) => claim,
+        res => {
+            println!("[uart handoff] transfer to exited pid: {:?}", res);
+            return -1;
+        }
+    };
+
+    let pid = fork();
+    if pid == 0 {
+        return worker();
+    }
+    // its drop would reset the port under the worker
+    core::mem::forget(serial);
+    if let Err((_, err)) = claim.transfer(pid as usize) {
+        println!("[uart handoff] transfer failed: {:?}", err);
+        return -1;
+    }
+    let held_after_transfer = serial::enumerate()[PORT].virt_base != 0;
+    waitpid(pid as usize, &mut exit_code);
+    // the worker released it on exit, so it is back with the kernel
+    let reclaimed = !serial::enumerate()[PORT].is_claimed();
+    println!(
+        "[uart handoff] worker exited with {}, reclaimed {}",
+        exit_code, reclaimed
+    );
+    if exit_code == 0 && !held_after_transfer && reclaimed {
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,271 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::{sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed};
+use heapless::spsc::Queue;
+use riscv::register::uie;
+use spin::Mutex;
+use user_lib::{
+    executor::{Executor, IdleStrategy},
+    get_time_us, init_user_trap, irq, set_ext_int_enable,
+    timer::{cycles, timeout},
+    user_uart::*,
+};
+
+const BAUD_RATE: usize = 115_200;
+const WARMUP_ROUNDS: usize = 16;
+const ROUNDS: usize = 1000;
+/// A byte takes under 100 us on the wire, this only catches a lost one.
+const ECHO_TIMEOUT_US: usize = 100_000;
+
+type RxBuffer = Queue<u8, DEFAULT_RX_BUFFER_SIZE>;
+type TxBuffer = Queue<u8, DEFAULT_TX_BUFFER_SIZE>;
+const EMPTY_RX_BUFFER: RxBuffer = RxBuffer::new();
+const EMPTY_TX_BUFFER: TxBuffer = TxBuffer::new();
+static mut DRIVER_RX_BUFFERS: [RxBuffer; 2] = [EMPTY_RX_BUFFER; 2];
+static mut DRIVER_TX_BUFFERS: [TxBuffer; 2] = [EMPTY_TX_BUFFER; 2];
+
+/// A probe is one byte, its tag, the round number. The cycles it was
+/// handed to the driver at and reached the echoing task at are kept under
+/// the tag, so an echo is only ever timed against the probe it answers.
+const NO_STAMP: AtomicUsize = AtomicUsize::new(0);
+static SENT: [AtomicUsize; 256] = [NO_STAMP; 256];
+static DELIVERED: [AtomicUsize; 256] = [NO_STAMP; 256];
+
+static DONE: AtomicBool = AtomicBool::new(false);
+static USER_SAMPLES: Mutex<Option<Samples>> = Mutex::new(None);
+
+/// Round trips and one way delays of the probes, in cycles.
+#[derive(Default)]
+struct Samples {
+    round_trip: Vec<usize>,
+    one_way: Vec<usize>,
+}
+
+impl Samples {
+    /// Times the echo of probe `tag`, which just came back.
+    fn record(&mut self, tag: u8) {
+        let now = cycles();
+        let sent = SENT[tag as usize].load(Relaxed);
+        self.round_trip.push(now.wrapping_sub(sent));
+        self.one_way
+            .push(DELIVERED[tag as usize].load(Relaxed).wrapping_sub(sent));
+    }
+}
+
+/// p50, p99 and max, sorts `samples`.
+fn percentiles(samples: &mut [usize]) -> (usize, usize, usize) {
+    samples.sort_unstable();
+    let at = |per_cent: usize| samples[(samples.len() - 1) * per_cent / 100];
+    (at(50), at(99), at(100))
+}
+
+/// Mode A: the kernel takes the interrupt and wakes the process blocked
+/// in `wait_ext_int`, through `BlockingSerial`.
+fn kernel_mode(a: &SerialClaim, b: &SerialClaim) -> Option<Samples> {
+    let (mut ping, mut echo) = (BlockingSerial::from_claim(a), BlockingSerial::from_claim(b));
+    for serial in [&mut ping, &mut echo] {
+        serial.hardware_init(BAUD_RATE);
+        serial.set_fifo_trigger(FifoTrigger::One).ok()?;
+    }
+    let mut samples = Samples::default();
+    let mut ch = [0u8];
+    for round in 0..WARMUP_ROUNDS + ROUNDS {
+        let tag = round as u8;
+        SENT[tag as usize].store(cycles(), Relaxed);
+        ping.write(&[tag]);
+        if echo.read(&mut ch, ECHO_TIMEOUT_US) != 1 || ch[0] != tag {
+            return None;
+        }
+        DELIVERED[tag as usize].store(cycles(), Relaxed);
+        echo.write(&ch);
+        if ping.read(&mut ch, ECHO_TIMEOUT_US) != 1 || ch[0] != tag {
+            return None;
+        }
+        if round >= WARMUP_ROUNDS {
+            samples.record(tag);
+        }
+    }
+    Some(samples)
+}
+
+fn open_async(claim: &SerialClaim, slot: usize) -> Arc<AsyncSerial> {
+    let (rx_pro, rx_con, tx_pro, tx_con) = unsafe {
+        DRIVER_RX_BUFFERS[slot] = RxBuffer::new();
+        DRIVER_TX_BUFFERS[slot] = TxBuffer::new();
+        let (rx_pro, rx_con) = DRIVER_RX_BUFFERS[slot].split();
+        let (tx_pro, tx_con) = DRIVER_TX_BUFFERS[slot].split();
+        (rx_pro, rx_con, tx_pro, tx_con)
+    };
+    let serial = Arc::new(AsyncSerial::from_claim(
+        claim, rx_pro, rx_con, tx_pro, tx_con,
+    ));
+    serial.hardware_init(BAUD_RATE);
+    serial
+}
+
+async fn echo_task(serial: Arc<AsyncSerial>) {
+    let mut ch = [0u8];
+    for _ in 0..WARMUP_ROUNDS + ROUNDS {
+        if !matches!(
+            timeout(ECHO_TIMEOUT_US, serial.clone().read(&mut ch)).await,
+            Ok(Ok(()))
+        ) {
+            return;
+        }
+        DELIVERED[ch[0] as usize].store(cycles(), Relaxed);
+        if serial.clone().write(&ch).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn ping_task(serial: Arc<AsyncSerial>) {
+    let mut samples = Samples::default();
+    let mut ch = [0u8];
+    let mut echoed = true;
+    for round in 0..WARMUP_ROUNDS + ROUNDS {
+        let tag = round as u8;
+        SENT[tag as usize].store(cycles(), Relaxed);
+        echoed = serial.clone().write(&[tag]).await.is_ok()
+            && matches!(
+                timeout(ECHO_TIMEOUT_US, serial.clone().read(&mut ch)).await,
+                Ok(Ok(()))
+            )
+            && ch[0] == tag;
+        if !echoed {
+            break;
+        }
+        if round >= WARMUP_ROUNDS {
+            samples.record(tag);
+        }
+    }
+    *USER_SAMPLES.lock() = echoed.then(|| samples);
+    DONE.store(true, Relaxed);
+}
+
+/// Mode B: the interrupt goes straight to the process, where
+/// `AsyncSerial` takes it and wakes the waiting task. The executor yields
+/// the hart while nothing is ready, as the process of mode A gives it up
+/// while it waits.
+fn user_mode(a: &SerialClaim, b: &SerialClaim) -> Option<Samples> {
+    let (ping, echo) = (open_async(a, 0), open_async(b, 1));
+    for (claim, serial) in [(a, &ping), (b, &echo)] {
+        serial.set_fifo_trigger(FifoTrigger::One).ok()?;
+        serial::register(claim.port(), serial.clone()).ok()?;
+        set_ext_int_enable(claim.irq() as usize, 1);
+    }
+    let exec = Executor::new(IdleStrategy::Yield);
+    exec.spawn(echo_task(echo));
+    exec.spawn(ping_task(ping));
+    unsafe {
+        uie::set_uext();
+        uie::set_utimer();
+    }
+    exec.run_until(|| DONE.load(Relaxed));
+    unsafe {
+        uie::clear_uext();
+        uie::clear_utimer();
+    }
+    for claim in [a, b] {
+        set_ext_int_enable(claim.irq() as usize, 0);
+    }
+    USER_SAMPLES.lock().take()
+}
+
+/// Prints the distributions of a mode, returns its p50 round trip.
+fn report(mode: &str, samples: &mut Samples) -> usize {
+    let (rtt_p50, rtt_p99, rtt_max) = percentiles(&mut samples.round_trip);
+    let (way_p50, way_p99, way_max) = percentiles(&mut samples.one_way);
+    println!(
+        "{:<8} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10}",
+        mode, rtt_p50, rtt_p99, rtt_max, way_p50, way_p99, way_max
+    );
+    rtt_p50
+}
+
+/// Echoes one byte at a time between the last two claimable ports, which
+/// have to be wired together, with the interrupts taken by the kernel and
+/// then by the process. Both modes run the ports at a trigger level of one
+/// byte and take the same time stamps, so the wire time is the same and
+/// only the interrupt path differs. Prints cycles: the round trip, and
+/// the one way delay from the probe being written to the echoing side
+/// having it.
+#[no_mangle]
+pub fn main() -> i32 {
+    init_user_trap();
+    let ports: Vec<usize> = serial::enumerate()
+        .iter()
+        .filter(|port| port.is_claimable() && !port.is_claimed())
+        .map(|port| port.index)
+        .collect();
+    if ports.len() < 2 {
+        println!(
+            "[uart irq latency] needs two free ports, found {}",
+            ports.len()
+        );
+        return -1;
+    }
+    let (a, b) = (ports[ports.len() - 2], ports[ports.len() - 1]);
+    let (a, b) = match (SerialClaim::claim(a), SerialClaim::claim(b)) {
+        (Ok(a), Ok(b)) => (a, b),
+        (a, b) => {
+            println!(
+                "[uart irq latency] claim failed: {:?} {:?}",
+                a.err(),
+                b.err()
+            );
+            return -1;
+        }
+    };
+
+    let start_us = get_time_us();
+    let kernel = kernel_mode(&a, &b);
+    let kernel_us = get_time_us() - start_us;
+    let start_us = get_time_us();
+    let user = user_mode(&a, &b);
+    let user_us = get_time_us() - start_us;
+
+    println!(
+        "[uart irq latency] port {} <-> port {}, {} rounds of one byte at {} baud",
+        a.port(),
+        b.port(),
+        ROUNDS,
+        BAUD_RATE
+    );
+    println!(
+        "{:<8} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10}",
+        "mode", "rtt p50", "rtt p99", "rtt max", "way p50", "way p99", "way max"
+    );
+    let mut p50s = [0; 2];
+    for (p50, (mode, samples)) in p50s.iter_mut().zip([("kernel", kernel), ("user", user)]) {
+        match samples {
+            Some(mut samples) => *p50 = report(mode, &mut samples),
+            None => println!("{:<8} FAILED, an echo was lost or wrong", mode),
+        }
+    }
+    println!(
+        "[uart irq latency] kernel mode took {} us, user mode {} us",
+        kernel_us, user_us
+    );
+    let [kernel_p50, user_p50] = p50s;
+    if kernel_p50 == 0 || user_p50 == 0 {
+        return -1;
+    }
+    println!(
+        "[uart irq latency] user handled p50 round trip is {}% of kernel handled",
+        user_p50 * 100 / kernel_p50
+    );
+    0
+}
+
+#[no_mangle]
+pub fn ext_intr_handler(irq: u16, _is_from_kernel: bool) {
+    serial::dispatch(irq);
+    irq::complete(irq);
+}
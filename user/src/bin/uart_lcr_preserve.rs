@@ -0,0 +1,60 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+mock_uart_main!("uart lcr preserve", mock::run);
+
+/// Changes the baud rate of drivers on a `MockUart` and checks only the
+/// divisor moves: LCR keeps its 8N1 line configuration with DLAB clear.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use alloc::sync::Arc;
+    use user_lib::user_uart::{regs::*, *};
+
+    const RATES: [usize; 3] = [9600, 57600, 115_200];
+
+    fn divisor_of(baud_rate: usize) -> u16 {
+        (100_000_000 / (16 * baud_rate)) as u16
+    }
+
+    fn async_serial(report: &mut MockReport) {
+        let (mock, serial) = MockUart::async_serial(115_200);
+        let serial = Arc::new(serial);
+        for &baud in RATES.iter() {
+            serial.set_baud(baud);
+            report.check(
+                "async: divisor set, LCR kept",
+                mock.divisor() == divisor_of(baud)
+                    && mock.read_lcr() == LCR_8N1
+                    && serial.line_config_intact(),
+            );
+        }
+        mock.write_lcr(0);
+        report.check("async: clobbered LCR noticed", !serial.line_config_intact());
+    }
+
+    fn polling_serial(report: &mut MockReport) {
+        let mock = MockUart::new();
+        let mut serial = PollingSerial::with_registers(mock);
+        serial.hardware_init(115_200);
+        for &baud in RATES.iter() {
+            serial.set_baud(baud);
+            report.check(
+                "polling: divisor set, LCR kept",
+                mock.divisor() == divisor_of(baud)
+                    && mock.read_lcr() == LCR_8N1
+                    && serial.line_config_intact(),
+            );
+        }
+    }
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart lcr preserve");
+        async_serial(&mut report);
+        polling_serial(&mut report);
+        report.exit_code()
+    }
+}
@@ -0,0 +1,63 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::sync::Arc;
+use heapless::spsc::Queue;
+use user_lib::{
+    executor::block_on_with,
+    future::traced,
+    init_user_trap,
+    trace::{FUTURE_SERIAL_READ, FUTURE_SERIAL_WRITE},
+    user_uart::*,
+};
+
+const PORT: usize = 1;
+const BAUD_RATE: usize = 115_200;
+const LINE_NUM: usize = 4;
+const MAX_LINE_LEN: usize = 64;
+
+/// Echo a few lines back, driving the serial by polling.
+#[no_mangle]
+pub fn main() -> i32 {
+    println!("[uart lines] send {} lines", LINE_NUM);
+    init_user_trap();
+    type RxBuffer = Queue<u8, DEFAULT_RX_BUFFER_SIZE>;
+    type TxBuffer = Queue<u8, DEFAULT_TX_BUFFER_SIZE>;
+    static mut DRIVER_RX_BUFFER: RxBuffer = RxBuffer::new();
+    static mut DRIVER_TX_BUFFER: TxBuffer = TxBuffer::new();
+    let (rx_pro, rx_con) = unsafe { DRIVER_RX_BUFFER.split() };
+    let (tx_pro, tx_con) = unsafe { DRIVER_TX_BUFFER.split() };
+    let claim = match SerialClaim::claim(PORT) {
+        Ok(claim) => claim,
+        Err(err) => {
+            println!("[uart lines] claim port {} failed: {:?}", PORT, err);
+            return -1;
+        }
+    };
+    let serial = Arc::new(AsyncSerial::from_claim(
+        &claim, rx_pro, rx_con, tx_pro, tx_con,
+    ));
+    serial.hardware_init(BAUD_RATE);
+
+    let mut lines = serial.clone().lines::<MAX_LINE_LEN>();
+    for _ in 0..LINE_NUM {
+        let line = traced(FUTURE_SERIAL_READ, lines.next_line());
+        match block_on_with(line, || serial.pump()) {
+            Ok(line) => {
+                let echo = traced(FUTURE_SERIAL_WRITE, serial.clone().write(line.as_bytes()));
+                let _ = block_on_with(echo, || serial.pump());
+                let _ = block_on_with(serial.clone().write(b"\r\n"), || serial.pump());
+            }
+            Err(err) => println!("[uart lines] {:?}", err),
+        }
+    }
+    let (_, rest) = lines.into_remainder();
+    println!("[uart lines] {} bytes left unread", rest.len());
+    serial.remove_read();
+    serial.remove_write();
+    0
+}
@@ -15,7 +15,6 @@ use core::{
     task::{Context, Poll, Waker},
 };
 use embedded_hal::serial::{Read, Write};
-use executor::Executor;
 use futures::{SinkExt, StreamExt};
 use heapless::spsc::Queue;
 use lazy_static::*;
@@ -26,14 +25,15 @@ use riscv::register::uie;
 use spin::Mutex;
 use user_lib::{
     claim_ext_int,
-    future::GetWakerFuture,
+    executor::Executor,
+    future::{traced, GetWakerFuture},
     get_time, init_user_trap, read, set_ext_int_enable, set_timer, sleep,
     trace::{
         push_trace, ASYNC_INTR_POLL, ASYNC_INTR_WAKE, ASYNC_READ_SPAWN, ASYNC_WRITE_SPAWN,
-        PLIC_COMPLETE_ENTER, PLIC_COMPLETE_EXIT, SERIAL_CALL_ENTER, SERIAL_CALL_EXIT,
-        SERIAL_TEST_ENTER, SERIAL_TEST_EXIT, U_TRAP_RETURN,
+        FUTURE_SERIAL_READ, FUTURE_SERIAL_WRITE, PLIC_COMPLETE_ENTER, PLIC_COMPLETE_EXIT,
+        SERIAL_CALL_ENTER, SERIAL_CALL_EXIT, SERIAL_TEST_ENTER, SERIAL_TEST_EXIT, U_TRAP_RETURN,
     },
-    trap::{get_context, hart_id, Plic},
+    trap::{get_context, hart_id},
     user_uart::*,
     write,
 };
@@ -233,7 +233,8 @@ fn user_polling_test() -> (usize, usize, usize) {
     let uart_irqn = UART_IRQN.load(Relaxed);
     let serial_number = irq_to_serial_id(uart_irqn);
     let claim_res = claim_ext_int(uart_irqn as usize);
-    let mut serial = PollingSerial::new(get_base_addr_from_irq(UART_IRQN.load(Relaxed)));
+    // where the claim mapped the registers
+    let mut serial = unsafe { PollingSerial::new(claim_res as usize) };
     serial.hardware_init(BAUD_RATE);
     const BATCH_SIZE: u8 = 0;
 
@@ -319,7 +320,8 @@ fn user_polling_test() -> (usize, usize, usize) {
 fn user_flow_control_test() -> (usize, usize, usize) {
     let uart_irqn = UART_IRQN.load(Relaxed);
     let claim_res = claim_ext_int(uart_irqn as usize);
-    let mut serial = PollingSerial::new(get_base_addr_from_irq(UART_IRQN.load(Relaxed)));
+    // where the claim mapped the registers
+    let mut serial = unsafe { PollingSerial::new(claim_res as usize) };
     serial.hardware_init(BAUD_RATE);
     println!("[uart load] Polling mode, claim result: {:#x}", claim_res);
     let mut error_count: usize = 0;
@@ -398,7 +400,8 @@ fn user_flow_control_test() -> (usize, usize, usize) {
 fn user_full_load_test() -> (usize, usize, usize) {
     let uart_irqn = UART_IRQN.load(Relaxed);
     let claim_res = claim_ext_int(uart_irqn as usize);
-    let mut serial = PollingSerial::new(get_base_addr_from_irq(UART_IRQN.load(Relaxed)));
+    // where the claim mapped the registers
+    let mut serial = unsafe { PollingSerial::new(claim_res as usize) };
     serial.hardware_init(BAUD_RATE);
     println!("[uart load] Polling mode, claim result: {:#x}", claim_res);
     let mut error_count: usize = 0;
@@ -462,7 +465,8 @@ fn user_full_load_test() -> (usize, usize, usize) {
 fn user_short_buf_test() -> (usize, usize, usize) {
     let uart_irqn = UART_IRQN.load(Relaxed);
     let claim_res = claim_ext_int(uart_irqn as usize);
-    let mut serial = PollingSerial::new(get_base_addr_from_irq(UART_IRQN.load(Relaxed)));
+    // where the claim mapped the registers
+    let mut serial = unsafe { PollingSerial::new(claim_res as usize) };
     serial.hardware_init(BAUD_RATE);
     println!("[uart load] Polling mode, claim result: {:#x}", claim_res);
     let mut error_count: usize = 0;
@@ -591,7 +595,7 @@ fn user_intr_test() -> (usize, usize, usize) {
             loop {
                 let ctx = get_context(hart_id(), 'U');
                 push_trace(PLIC_COMPLETE_ENTER | ctx);
-                Plic::complete(ctx, uart_irqn);
+                user_lib::irq::complete_on(ctx, uart_irqn);
                 let ctx2 = get_context(hart_id(), 'U');
                 push_trace(PLIC_COMPLETE_EXIT | ctx2);
                 if ctx == ctx2 {
@@ -626,7 +630,7 @@ async fn read_task(serial: Arc<AsyncSerial>) {
     let uart_irqn = UART_IRQN.load(Relaxed);
 
     let mut rx_buf = [0; HALF_FIFO_DEPTH];
-    serial.read(&mut rx_buf).await;
+    let _ = traced(FUTURE_SERIAL_READ, serial.read(&mut rx_buf)).await;
     let mut rx_rng = RX_RNG.lock();
     let mut expect_rx = rx_rng.next_u32();
 
@@ -654,7 +658,7 @@ async fn read_task(serial: Arc<AsyncSerial>) {
 async fn write_task(serial: Arc<AsyncSerial>) {
     let mut tx_rng = TX_RNG.lock();
     let tx_buf: [u8; HALF_FIFO_DEPTH] = array_init::array_init(|_| tx_rng.next_u32() as _);
-    serial.write(&tx_buf).await;
+    let _ = traced(FUTURE_SERIAL_WRITE, serial.write(&tx_buf)).await;
     WRITE_DONE.store(true, Relaxed);
 }
 
@@ -680,7 +684,7 @@ impl Future for IntrHandlerFuture {
             loop {
                 let ctx = get_context(hart_id(), 'U');
                 push_trace(PLIC_COMPLETE_ENTER | ctx);
-                Plic::complete(ctx, self.irqn);
+                user_lib::irq::complete_on(ctx, self.irqn);
                 let ctx2 = get_context(hart_id(), 'U');
                 push_trace(PLIC_COMPLETE_EXIT | ctx2);
                 if ctx == ctx2 {
@@ -720,13 +724,9 @@ fn user_async_test() -> (usize, usize, usize) {
     let (rx_pro, rx_con) = unsafe { DRIVER_RX_BUFFER.split() };
     let (tx_pro, tx_con) = unsafe { DRIVER_TX_BUFFER.split() };
 
-    let serial = Arc::new(AsyncSerial::new(
-        get_base_addr_from_irq(uart_irqn),
-        rx_pro,
-        rx_con,
-        tx_pro,
-        tx_con,
-    ));
+    // where the claim mapped the registers
+    let serial =
+        Arc::new(unsafe { AsyncSerial::new(claim_res as usize, rx_pro, rx_con, tx_pro, tx_con) });
     serial.hardware_init(BAUD_RATE);
     let en_res = set_ext_int_enable(uart_irqn as usize, 1);
     println!(
@@ -736,7 +736,7 @@ fn user_async_test() -> (usize, usize, usize) {
     let mut err_pos = -1;
     let (mut read_task_cnt, mut write_task_cnt) = (0, 0);
     let exec = Executor::default();
-    exec.spawn(intr_handler_task(serial.clone(), uart_irqn));
+    exec.spawn_high(intr_handler_task(serial.clone(), uart_irqn));
 
     let time_us = get_time() * 1000;
     set_timer(time_us + TEST_TIME_US);
@@ -819,11 +819,12 @@ fn user_async_test() -> (usize, usize, usize) {
         Arc::strong_count(&serial)
     );
     println!(
-        "[uart {}] Async, Intr count: {}, Tx: {}, Rx: {}, err pos: {}",
+        "[uart {}] Async, Intr count: {}, Tx: {}, Rx: {}, cycles: {}, err pos: {}",
         serial_number,
         serial.intr_count.load(Relaxed),
         serial.tx_intr_count.load(Relaxed),
         serial.rx_intr_count.load(Relaxed),
+        serial.intr_cycles.load(Relaxed),
         err_pos,
     );
     (
@@ -893,7 +894,7 @@ impl Future for UnbufferedIntrHandler {
             loop {
                 let ctx = get_context(hart_id(), 'U');
                 push_trace(PLIC_COMPLETE_ENTER | ctx);
-                Plic::complete(ctx, self.irqn);
+                user_lib::irq::complete_on(ctx, self.irqn);
                 let ctx2 = get_context(hart_id(), 'U');
                 push_trace(PLIC_COMPLETE_EXIT | ctx2);
                 if ctx == ctx2 {
@@ -927,9 +928,8 @@ fn user_unbuffered_async_test() -> (usize, usize, usize) {
 
     let claim_res = claim_ext_int(uart_irqn as usize);
 
-    let serial = Arc::new(AsyncUnbufferedSerial::new(get_base_addr_from_irq(
-        uart_irqn,
-    )));
+    // where the claim mapped the registers
+    let serial = Arc::new(unsafe { AsyncUnbufferedSerial::new(claim_res as usize) });
     serial.hardware_init(BAUD_RATE);
     let en_res = set_ext_int_enable(uart_irqn as usize, 1);
     println!(
@@ -937,7 +937,7 @@ fn user_unbuffered_async_test() -> (usize, usize, usize) {
         serial_number, claim_res, en_res
     );
     let exec = Executor::default();
-    exec.spawn(unbuffered_intr_handler_task(serial.clone(), uart_irqn));
+    exec.spawn_high(unbuffered_intr_handler_task(serial.clone(), uart_irqn));
 
     // if serial_number & 1 == 1 {
     exec.spawn(unbuffered_write_task(serial.clone()));
@@ -1050,11 +1050,11 @@ mod user_trap {
                 #[cfg(feature = "board_lrv")]
                 UART_IRQN.store(7, Relaxed);
             } else {
-                println!("[uart load] UART config invalid!");
+                intr_println!("[uart load] UART config invalid!");
             }
             IS_INITIALIZED.store(true, Relaxed);
         } else {
-            println!("[uart load] Invalid config {:#x}!", msg);
+            intr_println!("[uart load] Invalid config {:#x}!", msg);
         }
         // push_trace(U_TRAP_RETURN | 0 | 128);
     }
@@ -1083,7 +1083,7 @@ mod user_trap {
                 }
             }
         } else {
-            println!("[uart load] Unknown UEI!, irq: {}", irq);
+            intr_println!("[uart load] Unknown UEI!, irq: {}", irq);
         }
         // println!("[uart load] UEI fin");
     }
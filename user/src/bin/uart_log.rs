@@ -0,0 +1,71 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+/// Logs through `init_serial_logger` on `PORT`, floods it to see records
+/// dropped rather than waited for, and services the port with trace on to
+/// see its own diagnostics counted rather than sent. Needs the `log`
+/// feature, skipped without it.
+#[cfg(not(feature = "log"))]
+#[no_mangle]
+pub fn main() -> i32 {
+    println!("[uart log] built without the log feature, skipped");
+    0
+}
+
+#[cfg(feature = "log")]
+#[no_mangle]
+pub fn main() -> i32 {
+    logged::run()
+}
+
+#[cfg(feature = "log")]
+mod logged {
+    use log::LevelFilter;
+    use user_lib::{init_user_trap, user_uart::*};
+
+    const PORT: usize = 1;
+    const BAUD_RATE: usize = 115_200;
+    /// Far more than the tx queue holds.
+    const FLOOD: usize = 2 * DEFAULT_TX_BUFFER_SIZE / 16;
+
+    pub fn run() -> i32 {
+        init_user_trap();
+        let (_claim, serial) = match SerialBuilder::new(PORT).baud(BAUD_RATE).build() {
+            Ok((claim, serial)) => (claim, serial.into_async().unwrap()),
+            Err(err) => {
+                println!("[uart log] port {} failed: {:?}", PORT, err);
+                return -1;
+            }
+        };
+        if init_serial_logger(serial.clone(), LevelFilter::Trace).is_err() {
+            println!("[uart log] a logger is set already");
+            return -1;
+        }
+        log::info!("logging on port {}", PORT);
+
+        // nothing is pumped, the tx queue only fills
+        for i in 0..FLOOD {
+            log::info!("record {}", i);
+        }
+        let dropped = log_dropped();
+        // every interrupt handled traces about this very port
+        let suppressed = log_suppressed();
+        serial.pump();
+        let traced = log_suppressed() - suppressed;
+        serial.flush(EXIT_DRAIN_TIMEOUT_US);
+
+        println!(
+            "[uart log] {} of {} records dropped, {} diagnostics of the log port counted",
+            dropped, FLOOD, traced
+        );
+        if dropped > 0 && traced > 0 {
+            0
+        } else {
+            -1
+        }
+    }
+}
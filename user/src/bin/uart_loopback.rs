@@ -0,0 +1,183 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use user_lib::{init_user_trap, timer::now_us, user_uart::*};
+
+// The last two ports are wired together by `SERIAL_FLAGS` in os/justfile.
+// On the LRV board, connect them with a loopback cable.
+const TX_PORT: usize = 2;
+const RX_PORT: usize = 3;
+const BAUD_RATE: usize = 115_200;
+const SIZES: [usize; 4] = [
+    1,
+    FIFO_DEPTH,
+    DEFAULT_RX_BUFFER_SIZE,
+    10 * DEFAULT_RX_BUFFER_SIZE,
+];
+/// Slack on top of twice the time the bytes take on the wire.
+const TIMEOUT_MARGIN_US: usize = 1_000_000;
+/// How long the receiving port has to stay quiet before a case starts.
+const SETTLE_US: usize = 20_000;
+const CHUNK: usize = 64;
+
+#[derive(Clone, Copy, Debug)]
+enum Driver {
+    Buffered,
+    Async,
+}
+
+const MATRIX: [(Driver, Driver); 4] = [
+    (Driver::Buffered, Driver::Buffered),
+    (Driver::Async, Driver::Async),
+    (Driver::Buffered, Driver::Async),
+    (Driver::Async, Driver::Buffered),
+];
+
+fn report(role: &str, serial: &dyn SerialDriver) {
+    let stats = serial.stats();
+    println!(
+        "[uart loopback]   {} {:?}: rx {} tx {} intr {} (rx {} tx {})",
+        role,
+        serial.mode(),
+        stats.rx_count,
+        stats.tx_count,
+        stats.intr_count,
+        stats.rx_intr_count,
+        stats.tx_intr_count
+    );
+}
+
+fn open(driver: Driver, claim: &SerialClaim) -> AnySerial {
+    let mode = match driver {
+        Driver::Buffered => Mode::Buffered,
+        Driver::Async => Mode::Async,
+    };
+    // an async driver gets fresh queues, a failed case may have left bytes
+    // behind
+    SerialBuilder::new(claim.port())
+        .baud(BAUD_RATE)
+        .mode(mode)
+        .build_on(claim)
+        .unwrap()
+}
+
+/// Byte `index` of case `case`. Differs between cases, so a byte left
+/// over from the one before does not pass for a good one.
+fn pattern(case: usize, index: usize) -> u8 {
+    (index.wrapping_mul(31) ^ (index >> 8) ^ case.wrapping_mul(0x5b)) as u8
+}
+
+/// Drops what the receiving port still gets until it stays quiet.
+fn settle(rx: &mut dyn SerialDriver) {
+    let mut buf = [0u8; CHUNK];
+    let mut quiet_since = now_us();
+    while now_us() - quiet_since < SETTLE_US {
+        rx.service();
+        if rx.read_nonblocking(&mut buf) > 0 {
+            quiet_since = now_us();
+        }
+    }
+}
+
+/// Sends `len` bytes from `tx` to `rx`. Returns whether every byte came
+/// through, in order.
+fn run_case(case: usize, tx: &mut dyn SerialDriver, rx: &mut dyn SerialDriver, len: usize) -> bool {
+    let deadline = now_us() + 2 * len * 10 * 1_000_000 / BAUD_RATE + TIMEOUT_MARGIN_US;
+    let mut chunk = [0u8; CHUNK];
+    let mut sent = 0;
+    let mut received = 0;
+    let mut mismatches = 0;
+    let mut first_mismatch = None;
+    while received < len && now_us() < deadline {
+        if sent < len {
+            let end = len.min(sent + CHUNK);
+            for (i, ch) in chunk[..end - sent].iter_mut().enumerate() {
+                *ch = pattern(case, sent + i);
+            }
+            sent += tx.write_nonblocking(&chunk[..end - sent]);
+        }
+        tx.service();
+        rx.service();
+        let n = rx.read_nonblocking(&mut chunk);
+        for (i, &ch) in chunk[..n].iter().enumerate() {
+            let index = received + i;
+            if index >= len || ch != pattern(case, index) {
+                mismatches += 1;
+                first_mismatch.get_or_insert(index);
+            }
+        }
+        received += n;
+    }
+    if let Some(index) = first_mismatch {
+        println!(
+            "[uart loopback]   {} bad bytes, first at {}",
+            mismatches, index
+        );
+    }
+    if received < len {
+        println!(
+            "[uart loopback]   timed out, sent {} received {} of {}",
+            sent, received, len
+        );
+    }
+    received == len && mismatches == 0
+}
+
+/// Sends a pattern from `TX_PORT` to `RX_PORT` for every pair of drivers
+/// and size, and checks it arrives byte for byte.
+#[no_mangle]
+pub fn main() -> i32 {
+    init_user_trap();
+    let claims = (SerialClaim::claim(TX_PORT), SerialClaim::claim(RX_PORT));
+    let (tx_claim, rx_claim) = match claims {
+        (Ok(tx_claim), Ok(rx_claim)) => (tx_claim, rx_claim),
+        (tx_claim, rx_claim) => {
+            println!(
+                "[uart loopback] claim failed, port {}: {:?}, port {}: {:?}",
+                TX_PORT,
+                tx_claim.err(),
+                RX_PORT,
+                rx_claim.err()
+            );
+            return -1;
+        }
+    };
+
+    let mut failed = 0;
+    let mut case = 0;
+    for &(tx_driver, rx_driver) in MATRIX.iter() {
+        for &len in SIZES.iter() {
+            let mut tx = open(tx_driver, &tx_claim);
+            let mut rx = open(rx_driver, &rx_claim);
+            settle(&mut rx);
+            let ok = run_case(case, &mut tx, &mut rx, len);
+            println!(
+                "[uart loopback] {:?} -> {:?}, {} bytes: {}",
+                tx_driver,
+                rx_driver,
+                len,
+                if ok { "ok" } else { "FAILED" }
+            );
+            report("tx", &tx);
+            report("rx", &rx);
+            if !ok {
+                failed += 1;
+            }
+            case += 1;
+        }
+    }
+    println!(
+        "[uart loopback] {} of {} cases failed",
+        failed,
+        MATRIX.len() * SIZES.len()
+    );
+    if failed == 0 {
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,123 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+mock_uart_main!("uart mock", mock::run);
+
+/// Drives every branch of `AsyncSerial::interrupt_handler` with scripted
+/// register traffic.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use alloc::sync::Arc;
+    use embedded_hal::serial::{Read, Write};
+    use user_lib::user_uart::regs::*;
+    use user_lib::user_uart::*;
+
+    const BAUD_RATE: usize = 115_200;
+    // IIR IDs: RS-485 is one the driver does not handle, 0b1111 none the
+    // register block knows
+    const IID_RS485: u8 = 0b0011;
+    const IID_INVALID: u8 = 0b1111;
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart mock");
+        let (mock, serial) = MockUart::async_serial(BAUD_RATE);
+        let serial = Arc::new(serial);
+        let bus = Arc::new(SerialEventBus::new());
+        serial.attach_event_bus(bus.clone());
+        // takes the THR empty interrupt of enabling ETBEI, with nothing queued
+        serial.interrupt_handler();
+        report.check(
+            "empty tx queue disables THREI",
+            mock.read_ier() & IER_ETBEI == 0 && mock.take_tx().is_empty(),
+        );
+        report.check(
+            "init",
+            mock.divisor() == (100_000_000 / (16 * BAUD_RATE)) as u16
+                && mock.read_lcr() == LCR_8N1
+                && mock.read_mcr() & MCR_RTS != 0
+                && mock.read_ier() & (IER_ELSI | IER_EDSSI) == IER_ELSI | IER_EDSSI,
+        );
+
+        mock.inject_rx(b"hello");
+        serial.interrupt_handler();
+        let mut buf = [0u8; 16];
+        let len = serial.read_available(&mut buf);
+        report.check("rx data", &buf[..len] == b"hello" && mock.rx_left() == 0);
+
+        serial.write_available(b"hi");
+        serial.interrupt_handler();
+        report.check(
+            "tx data",
+            mock.take_tx() == b"hi" && mock.read_ier() & IER_ETBEI == 0,
+        );
+
+        let stats = serial.stats();
+        mock.inject_iid(IID_RS485);
+        mock.inject_iid(IID_INVALID);
+        mock.inject_rx(b"x");
+        serial.interrupt_handler();
+        report.check(
+            "unknown IIDs end the loop",
+            serial.stats().intr_count == stats.intr_count + 1 && mock.rx_left() == 1,
+        );
+        serial.interrupt_handler();
+        serial.interrupt_handler();
+        serial.read_available(&mut buf);
+        let after = serial.stats();
+        report.check(
+            "unknown IIDs kept",
+            after.irq_loop_bailouts == stats.irq_loop_bailouts + 2
+                && after.unknown_iids == stats.unknown_iids + 2
+                && serial.unknown_iids().ends_with(&[IID_RS485, IID_INVALID])
+                && mock.rx_left() == 0,
+        );
+
+        mock.inject_line_error(LSR_BI | LSR_FIFO_ERROR);
+        serial.interrupt_handler();
+        report.check(
+            "line status",
+            bus.try_next().map(|event| event.kind) == Some(SerialEventKind::Break),
+        );
+
+        mock.inject_modem_status(MSR_DSR | MSR_DDSR);
+        serial.interrupt_handler();
+        report.check(
+            "modem status",
+            bus.try_next().map(|event| event.kind) == Some(SerialEventKind::DsrChanged(true))
+                && mock.read_msr() & MSR_DELTA_BITS == 0,
+        );
+
+        // the rx queue holds one byte less than its size
+        let overflow = [b'o'; DEFAULT_RX_BUFFER_SIZE + 8];
+        mock.inject_rx(&overflow);
+        serial.interrupt_handler();
+        report.check(
+            "rx overflow disables RDAI",
+            mock.read_ier() & IER_ERBFI == 0 && mock.rx_left() == 9,
+        );
+        while serial.read_available(&mut buf) > 0 {}
+
+        // the tx queue takes one byte less than its size
+        let long = [b'l'; DEFAULT_TX_BUFFER_SIZE + 8];
+        let queued = serial.write_available(&long);
+        report.check("tx queue full", queued == DEFAULT_TX_BUFFER_SIZE - 1);
+
+        // the polling driver runs on the same registers
+        let polled = MockUart::new();
+        let mut polling = PollingSerial::with_registers(polled);
+        polling.hardware_init(BAUD_RATE);
+        polled.inject_rx(b"ok");
+        let rx = [polling.try_read(), polling.try_read()];
+        let _ = polling.try_write(b'!');
+        report.check(
+            "polling",
+            rx == [Ok(b'o'), Ok(b'k')] && polling.try_read().is_err() && polled.take_tx() == b"!",
+        );
+
+        report.exit_code()
+    }
+}
@@ -0,0 +1,90 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::{sync::Arc, vec::Vec};
+use core::pin::Pin;
+use heapless::spsc::Queue;
+use user_lib::{
+    executor::block_on_with,
+    future::{FutureSet, FutureSetState},
+    init_user_trap,
+    user_uart::*,
+};
+
+/// Port 0 is the kernel console.
+const PORTS: [usize; PORT_NUM] = [1, 2, 3];
+const PORT_NUM: usize = 3;
+const BAUD_RATE: usize = 115_200;
+const LINE_LEN: usize = 32;
+
+type RxBuffer = Queue<u8, DEFAULT_RX_BUFFER_SIZE>;
+type TxBuffer = Queue<u8, DEFAULT_TX_BUFFER_SIZE>;
+const EMPTY_RX_BUFFER: RxBuffer = RxBuffer::new();
+const EMPTY_TX_BUFFER: TxBuffer = TxBuffer::new();
+static mut DRIVER_RX_BUFFERS: [RxBuffer; PORT_NUM] = [EMPTY_RX_BUFFER; PORT_NUM];
+static mut DRIVER_TX_BUFFERS: [TxBuffer; PORT_NUM] = [EMPTY_TX_BUFFER; PORT_NUM];
+
+static SET_STATE: FutureSetState = FutureSetState::new();
+
+/// One `read_until` per port, all in flight at once in a `FutureSet`.
+#[no_mangle]
+pub fn main() -> i32 {
+    println!(
+        "[uart multi read] send one line to each of {} ports",
+        PORT_NUM
+    );
+    init_user_trap();
+    let mut claims = Vec::new();
+    for &port in PORTS.iter() {
+        match SerialClaim::claim(port) {
+            Ok(claim) => claims.push(claim),
+            Err(err) => {
+                println!("[uart multi read] claim port {} failed: {:?}", port, err);
+                return -1;
+            }
+        }
+    }
+    let serials: Vec<Arc<AsyncSerial>> = claims
+        .iter()
+        .enumerate()
+        .map(|(i, claim)| {
+            let (rx_pro, rx_con) = unsafe { DRIVER_RX_BUFFERS[i].split() };
+            let (tx_pro, tx_con) = unsafe { DRIVER_TX_BUFFERS[i].split() };
+            let serial = Arc::new(AsyncSerial::from_claim(
+                claim, rx_pro, rx_con, tx_pro, tx_con,
+            ));
+            serial.hardware_init(BAUD_RATE);
+            serial
+        })
+        .collect();
+
+    let mut bufs = [[0u8; LINE_LEN]; PORT_NUM];
+    let mut set = FutureSet::<_, PORT_NUM>::new(&SET_STATE);
+    let mut set = Pin::new(&mut set);
+    for (serial, buf) in serials.iter().zip(bufs.iter_mut()) {
+        if set
+            .as_mut()
+            .push(serial.clone().read_until(b'\n', buf))
+            .is_err()
+        {
+            println!("[uart multi read] future set is full");
+            return -1;
+        }
+    }
+    let pump = || serials.iter().for_each(|serial| serial.pump());
+    while let Some((slot, len)) = block_on_with(set.as_mut().next_ready(), pump) {
+        println!(
+            "[uart multi read] port {} finished with {} bytes",
+            serials[slot].port(),
+            len
+        );
+    }
+    for serial in serials.iter() {
+        serial.remove_read();
+    }
+    0
+}
@@ -0,0 +1,207 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::collections::VecDeque;
+use core::cell::RefCell;
+use core::convert::Infallible;
+use embedded_hal::serial::{Read, Write};
+use user_lib::user_uart::{spin_up_to, ReadExact, WriteAll};
+
+/// A byte queue holding `capacity` bytes, written and read like a driver.
+/// Used through `&Pipe`, so an `on_full` or `on_empty` can play the
+/// interrupt handler on the other end.
+struct Pipe {
+    queue: RefCell<VecDeque<u8>>,
+    capacity: usize,
+}
+
+impl Pipe {
+    fn new(capacity: usize) -> Self {
+        Pipe {
+            queue: RefCell::new(VecDeque::new()),
+            capacity,
+        }
+    }
+
+    fn fill(&self, data: &[u8]) {
+        self.queue.borrow_mut().extend(data.iter());
+    }
+
+    fn take(&self, len: usize) -> VecDeque<u8> {
+        let mut queue = self.queue.borrow_mut();
+        let len = len.min(queue.len());
+        queue.drain(..len).collect()
+    }
+}
+
+impl Write<u8> for &Pipe {
+    type Error = Infallible;
+
+    fn try_write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+        let mut queue = self.queue.borrow_mut();
+        if queue.len() == self.capacity {
+            return Err(nb::Error::WouldBlock);
+        }
+        queue.push_back(word);
+        Ok(())
+    }
+
+    fn try_flush(&mut self) -> nb::Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl Read<u8> for &Pipe {
+    type Error = Infallible;
+
+    fn try_read(&mut self) -> nb::Result<u8, Self::Error> {
+        self.queue
+            .borrow_mut()
+            .pop_front()
+            .ok_or(nb::Error::WouldBlock)
+    }
+}
+
+fn check(name: &str, ok: bool) -> bool {
+    println!(
+        "[uart nb io] {}: {}",
+        name,
+        if ok { "ok" } else { "FAILED" }
+    );
+    ok
+}
+
+fn write_boundaries() -> bool {
+    let data = *b"abcdef";
+    let mut calls = 0;
+    let pipe = Pipe::new(4);
+    let mut passed = check(
+        "fits exactly, on_full not called",
+        (&pipe).write_all(&data[..4], || {
+            calls += 1;
+            true
+        }) == 4
+            && calls == 0,
+    );
+    passed &= check(
+        "full, on_full gives up",
+        (&pipe).write_all(&data[4..], || {
+            calls += 1;
+            false
+        }) == 0
+            && calls == 1,
+    );
+    passed &= check(
+        "empty buffer while full",
+        (&pipe).write_all(&[], || {
+            calls += 1;
+            false
+        }) == 0
+            && calls == 1,
+    );
+
+    let pipe = Pipe::new(4);
+    let mut sent = VecDeque::new();
+    let len = (&pipe).write_all(&data, || {
+        sent.extend(pipe.take(2));
+        true
+    });
+    sent.extend(pipe.take(usize::MAX));
+    passed &= check(
+        "on_full makes room, all written in order",
+        len == data.len() && sent.iter().eq(data.iter()),
+    );
+
+    let pipe = Pipe::new(0);
+    passed &= check(
+        "bounded spin gives up",
+        (&pipe).write_all(&data, spin_up_to(3)) == 0,
+    );
+    passed
+}
+
+fn read_boundaries() -> bool {
+    let mut buf = [0u8; 4];
+    let mut calls = 0;
+    let pipe = Pipe::new(8);
+    pipe.fill(b"wxyz");
+    let mut passed = check(
+        "exactly enough, on_empty not called",
+        (&pipe).read_exact(&mut buf, || {
+            calls += 1;
+            true
+        }) == 4
+            && &buf == b"wxyz"
+            && calls == 0,
+    );
+    passed &= check(
+        "empty, on_empty gives up",
+        (&pipe).read_exact(&mut buf, || {
+            calls += 1;
+            false
+        }) == 0
+            && calls == 1,
+    );
+    pipe.fill(b"ab");
+    let len = (&pipe).read_exact(&mut buf, || {
+        pipe.fill(b"cd");
+        true
+    });
+    passed &= check("on_empty fills the rest", len == 4 && &buf == b"abcd");
+    passed
+}
+
+/// A `PollingSerial` on a `MockUart`: 16 bytes fill the FIFO estimate, a
+/// CTS edge takes a pulse width back.
+#[cfg(feature = "mock_uart")]
+fn polling_serial() -> bool {
+    use user_lib::user_uart::{regs::*, *};
+
+    let mock = MockUart::new();
+    let mut serial = PollingSerial::with_registers(mock);
+    serial.hardware_init(115_200);
+    let data: [u8; 40] = core::array::from_fn(|i| b'a' + i as u8 % 26);
+    let mut stalls = 0;
+    let len = serial.write_all(&data, || {
+        stalls += 1;
+        mock.inject_modem_status(MSR_CTS | MSR_DCTS);
+        true
+    });
+    let mut passed = check(
+        "polling: written across full FIFOs",
+        len == data.len() && stalls > 0 && mock.take_tx() == data,
+    );
+    mock.inject_rx(b"12345");
+    let mut buf = [0u8; 8];
+    let len = serial.read_exact(&mut buf, || {
+        mock.inject_rx(b"678");
+        true
+    });
+    passed &= check(
+        "polling: read across an empty FIFO",
+        len == 8 && &buf == b"12345678",
+    );
+    passed
+}
+
+#[cfg(not(feature = "mock_uart"))]
+fn polling_serial() -> bool {
+    println!("[uart nb io] polling: built without the mock_uart feature, skipped");
+    true
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut passed = write_boundaries();
+    passed &= read_boundaries();
+    passed &= polling_serial();
+    if passed {
+        0
+    } else {
+        -1
+    }
+}
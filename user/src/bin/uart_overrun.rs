@@ -0,0 +1,73 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+mock_uart_main!("uart overrun", mock::run);
+
+/// Raises receiver overruns on a `MockUart`, through a line status
+/// interrupt and behind an rx interrupt, and checks each is counted and
+/// reported by `read_available_checked` between the bytes before and
+/// after it.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use alloc::sync::Arc;
+    use user_lib::user_uart::{regs::*, *};
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart overrun");
+        let (mock, serial) = MockUart::async_serial(115_200);
+        let serial = Arc::new(serial);
+        serial.interrupt_handler();
+        let mut buf = [0u8; 8];
+
+        // IIR reports the line status first
+        mock.inject_rx(b"abc");
+        mock.inject_line_error(LSR_OE);
+        serial.interrupt_handler();
+        mock.inject_rx(b"de");
+        serial.interrupt_handler();
+        report.check(
+            "line status overrun counted",
+            serial.stats().overrun_count == 1,
+        );
+        report.check(
+            "bytes before the gap",
+            serial.read_available_checked(&mut buf) == Ok(3) && &buf[..3] == b"abc",
+        );
+        report.check(
+            "overrun at the gap, once",
+            serial.read_available_checked(&mut buf) == Err(SerialError::Overrun),
+        );
+        report.check(
+            "bytes after the gap",
+            serial.read_available_checked(&mut buf) == Ok(2)
+                && &buf[..2] == b"de"
+                && serial.read_available_checked(&mut buf) == Ok(0),
+        );
+
+        // only the rx loop's LSR reads see this one
+        mock.inject_rx(b"fgh");
+        mock.inject_line_error(LSR_OE);
+        mock.inject_iid(IID_RX_DATA);
+        serial.interrupt_handler();
+        report.check(
+            "overrun seen by the rx loop counted",
+            serial.stats().overrun_count == 2,
+        );
+        mock.inject_rx(b"ij");
+        serial.interrupt_handler();
+        report.check(
+            "plain read goes past the gap",
+            serial.read_available(&mut buf) == 5,
+        );
+        report.check(
+            "passed gap reported next",
+            serial.read_available_checked(&mut buf) == Err(SerialError::Overrun)
+                && serial.read_available_checked(&mut buf) == Ok(0),
+        );
+        report.exit_code()
+    }
+}
@@ -0,0 +1,52 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::sync::Arc;
+use heapless::spsc::Queue;
+use user_lib::{executor::block_on_with, fork, init_user_trap, user_uart::*, waitpid};
+
+const PORT: usize = 3;
+const BAUD_RATE: usize = 115_200;
+const MESSAGE: &[u8] = b"[uart panic dump] still queued when the task panics\r\n";
+
+/// Panics with bytes queued on serial 3. The dump of its driver should show
+/// up on the kernel console, after the panic message.
+fn panic_mid_transfer() -> ! {
+    init_user_trap();
+    let claim = SerialClaim::claim(PORT).unwrap();
+    type RxBuffer = Queue<u8, DEFAULT_RX_BUFFER_SIZE>;
+    type TxBuffer = Queue<u8, DEFAULT_TX_BUFFER_SIZE>;
+    static mut DRIVER_RX_BUFFER: RxBuffer = RxBuffer::new();
+    static mut DRIVER_TX_BUFFER: TxBuffer = TxBuffer::new();
+    let (rx_pro, rx_con) = unsafe { DRIVER_RX_BUFFER.split() };
+    let (tx_pro, tx_con) = unsafe { DRIVER_TX_BUFFER.split() };
+    let serial = Arc::new(AsyncSerial::from_claim(
+        &claim, rx_pro, rx_con, tx_pro, tx_con,
+    ));
+    serial.hardware_init(BAUD_RATE);
+    for _ in 0..8 {
+        let _ = block_on_with(serial.clone().write(MESSAGE), || {});
+    }
+    panic!("on purpose, {} bytes sent", serial.stats().tx_count);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let pid = fork();
+    if pid == 0 {
+        panic_mid_transfer();
+    }
+    let mut exit_code = 0;
+    waitpid(pid as usize, &mut exit_code);
+    // the panic handler exits with -1
+    println!("[uart panic dump] child exited with {}", exit_code);
+    if exit_code == -1 {
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,141 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use riscv::register::{time, uie};
+use user_lib::{init_user_trap, irq, set_ext_int_enable, set_ext_int_priority, user_uart::*};
+
+/// The port that should win, it has the higher IRQ number so it loses ties.
+const REALTIME_PORT: usize = 2;
+const BUSY_PORT: usize = 1;
+const UNCLAIMED_PORT: usize = 3;
+const BAUD_RATE: usize = 115_200;
+const ROUND_NUM: usize = 64;
+const BUCKET_NUM: usize = 8;
+const BUCKET_TICKS: usize = 16;
+
+const ZERO: AtomicUsize = AtomicUsize::new(0);
+const EMPTY_HISTOGRAM: [AtomicUsize; BUCKET_NUM] = [ZERO; BUCKET_NUM];
+/// Per port: base address, IRQ and latency histogram of the current phase.
+static BASES: [AtomicUsize; 2] = [ZERO; 2];
+static IRQS: [AtomicUsize; 2] = [ZERO; 2];
+static HISTOGRAMS: [[AtomicUsize; BUCKET_NUM]; 2] = [EMPTY_HISTOGRAM; 2];
+static LATENCY_SUMS: [AtomicUsize; 2] = [ZERO; 2];
+static REALTIME_FIRST: AtomicUsize = ZERO;
+static ROUND_START: AtomicUsize = ZERO;
+static SERVED: AtomicUsize = ZERO;
+
+fn registers(index: usize) -> UartMmio {
+    // from the claims `main` holds until the end
+    unsafe { UartMmio::new(BASES[index].load(Relaxed)) }
+}
+
+/// Raises a THR empty interrupt on both ports at once, with user external
+/// interrupts held off so they are both pending when the handler runs.
+fn run_round() {
+    unsafe {
+        uie::clear_uext();
+    }
+    SERVED.store(0, Relaxed);
+    ROUND_START.store(time::read(), Relaxed);
+    for index in 0..2 {
+        registers(index).set_tx_interrupt(true);
+    }
+    unsafe {
+        uie::set_uext();
+    }
+    while SERVED.load(Relaxed) < 2 {}
+}
+
+/// Runs a phase and returns the realtime port's mean latency in ticks.
+fn run_phase(name: &str) -> usize {
+    for index in 0..2 {
+        LATENCY_SUMS[index].store(0, Relaxed);
+        HISTOGRAMS[index]
+            .iter()
+            .for_each(|bucket| bucket.store(0, Relaxed));
+    }
+    REALTIME_FIRST.store(0, Relaxed);
+    for _ in 0..ROUND_NUM {
+        run_round();
+    }
+    for (index, port) in [REALTIME_PORT, BUSY_PORT].iter().enumerate() {
+        let histogram: [usize; BUCKET_NUM] =
+            core::array::from_fn(|bucket| HISTOGRAMS[index][bucket].load(Relaxed));
+        println!(
+            "[uart priority] {}: port {} mean {} ticks, histogram {:?}",
+            name,
+            port,
+            LATENCY_SUMS[index].load(Relaxed) / ROUND_NUM,
+            histogram
+        );
+    }
+    println!(
+        "[uart priority] {}: realtime port served first {}/{}",
+        name,
+        REALTIME_FIRST.load(Relaxed),
+        ROUND_NUM
+    );
+    LATENCY_SUMS[0].load(Relaxed) / ROUND_NUM
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    init_user_trap();
+    let claims = [
+        SerialClaim::claim(REALTIME_PORT).unwrap(),
+        SerialClaim::claim(BUSY_PORT).unwrap(),
+    ];
+    let serials = [
+        BlockingSerial::from_claim(&claims[0]),
+        BlockingSerial::from_claim(&claims[1]),
+    ];
+    for (index, (claim, mut serial)) in claims.iter().zip(serials).enumerate() {
+        serial.hardware_init(BAUD_RATE);
+        // keep the registers as they are, the claim resets them on drop
+        core::mem::forget(serial);
+        BASES[index].store(claim.base_address(), Relaxed);
+        IRQS[index].store(claim.irq() as usize, Relaxed);
+        set_ext_int_enable(claim.irq() as usize, 1);
+    }
+
+    let mut ok = true;
+    let realtime = &claims[0];
+    ok &= realtime.set_priority(0) == Err(ClaimError::InvalidPriority);
+    ok &= realtime.set_priority(MAX_IRQ_PRIORITY + 1) == Err(ClaimError::InvalidPriority);
+    ok &= realtime.set_threshold(MAX_IRQ_PRIORITY + 1) == Err(ClaimError::InvalidPriority);
+    let unclaimed_irq = serial::port_info(UNCLAIMED_PORT).unwrap().irq;
+    ok &= set_ext_int_priority(unclaimed_irq, MAX_IRQ_PRIORITY) < 0;
+    println!("[uart priority] bounds and ownership checks: {}", ok);
+
+    let equal = run_phase("equal priority");
+    realtime.set_priority(MAX_IRQ_PRIORITY).unwrap();
+    let raised = run_phase("raised priority");
+    unsafe {
+        uie::clear_uext();
+    }
+    if ok && raised < equal && REALTIME_FIRST.load(Relaxed) == ROUND_NUM {
+        0
+    } else {
+        -1
+    }
+}
+
+#[no_mangle]
+pub fn ext_intr_handler(irq: u16, _is_from_kernel: bool) {
+    let latency = time::read() - ROUND_START.load(Relaxed);
+    if let Some(index) = (0..2).find(|&index| IRQS[index].load(Relaxed) == irq as usize) {
+        registers(index).set_tx_interrupt(false);
+        let bucket = (latency / BUCKET_TICKS).min(BUCKET_NUM - 1);
+        HISTOGRAMS[index][bucket].fetch_add(1, Relaxed);
+        LATENCY_SUMS[index].fetch_add(latency, Relaxed);
+        if index == 0 && SERVED.load(Relaxed) == 0 {
+            REALTIME_FIRST.fetch_add(1, Relaxed);
+        }
+        SERVED.fetch_add(1, Relaxed);
+    }
+    irq::complete(irq);
+}
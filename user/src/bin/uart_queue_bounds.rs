@@ -0,0 +1,198 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+mock_uart_main!("uart queue bounds", mock::run);
+
+/// Moves every size from 0 to a little over the free room through the rx
+/// and tx queues of `AsyncSerial`, for a few amounts of free room, and
+/// checks nothing is lost, doubled or signalled ready too early.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use alloc::{boxed::Box, sync::Arc, vec, vec::Vec};
+    use embedded_hal::serial::Read;
+    use user_lib::user_uart::*;
+
+    const BAUD_RATE: usize = 115_200;
+    /// The queues are sized at build time. A smaller queue is emulated by
+    /// filling it up to this much free room before each case.
+    const ROOM: [usize; 6] = [1, 2, 3, FIFO_DEPTH - 1, FIFO_DEPTH, FIFO_DEPTH + 1];
+    /// An spsc queue holds one byte less than its size.
+    const RX_USABLE: usize = DEFAULT_RX_BUFFER_SIZE - 1;
+    const TX_USABLE: usize = DEFAULT_TX_BUFFER_SIZE - 1;
+    const FILLER: u8 = 0xee;
+    /// The user heap is 32 KiB, keep the big buffers static.
+    static FILLER_BYTES: [u8; DEFAULT_RX_BUFFER_SIZE] = [FILLER; DEFAULT_RX_BUFFER_SIZE];
+
+    type Serial = Arc<AsyncSerial<&'static MockUart>>;
+
+    /// Never `FILLER`, so filler and data can't pass for each other.
+    fn pattern(case: usize, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| ((case * 7 + i * 13) % 255) as u8)
+            .collect()
+    }
+
+    fn fail(path: &str, room: usize, len: usize, what: &str) -> bool {
+        println!(
+            "[uart queue bounds] {} room {} size {}: {}",
+            path, room, len, what
+        );
+        false
+    }
+
+    /// Queues `len` bytes with a write future while the tx queue has
+    /// `room` free, then sends what was queued.
+    fn tx_case(
+        mock: &'static MockUart,
+        serial: &Serial,
+        case: usize,
+        room: usize,
+        len: usize,
+    ) -> bool {
+        let waker = Arc::new(CountingWaker::default());
+        let filler = &FILLER_BYTES[..TX_USABLE - room];
+        if serial.write_available(filler) != filler.len() || serial.tx_space() != room {
+            return fail("tx", room, len, "filling the queue");
+        }
+        let data = pattern(case, len);
+        let fits = len.min(room);
+        let mut write = Box::pin(serial.clone().write(&data));
+        let ready = poll_once(write.as_mut(), &waker).is_ready();
+        if ready != (len <= room) {
+            return fail("tx", room, len, "write ready at the wrong time");
+        }
+        if serial.tx_space() != room - fits {
+            return fail("tx", room, len, "wrong room left");
+        }
+        serial.flush(EXIT_DRAIN_TIMEOUT_US);
+        let sent = mock.take_tx();
+        if sent.len() != filler.len() + fits
+            || sent[..filler.len()] != filler[..]
+            || sent[filler.len()..] != data[..fits]
+        {
+            return fail("tx", room, len, "first part lost or doubled");
+        }
+        if !ready {
+            if poll_once(write.as_mut(), &waker).is_pending() {
+                return fail("tx", room, len, "write pending with room");
+            }
+            serial.flush(EXIT_DRAIN_TIMEOUT_US);
+            if mock.take_tx() != data[fits..] {
+                return fail("tx", room, len, "rest lost or doubled");
+            }
+        }
+        serial.tx_space() == TX_USABLE || fail("tx", room, len, "queue not empty")
+    }
+
+    /// Receives `len` bytes while the rx queue has `room` free, then reads
+    /// them with a read future.
+    fn rx_case(
+        mock: &'static MockUart,
+        serial: &Serial,
+        case: usize,
+        room: usize,
+        len: usize,
+    ) -> bool {
+        let waker = Arc::new(CountingWaker::default());
+        // a read that finds its bytes queued leaves RDAI as it was, one
+        // that goes pending turns it back on
+        let mut prime = [0u8];
+        if poll_once(Box::pin(serial.clone().read(&mut prime)).as_mut(), &waker).is_ready() {
+            return fail("rx", room, len, "read ready on an empty queue");
+        }
+        let filler = &FILLER_BYTES[..RX_USABLE - room];
+        mock.inject_rx(filler);
+        serial.interrupt_handler();
+        let data = pattern(case, len);
+        mock.inject_rx(&data);
+        serial.interrupt_handler();
+        let fits = len.min(room);
+        if mock.rx_left() != len - fits {
+            return fail("rx", room, len, "queue took the wrong amount");
+        }
+        let mut buf = [0u8; 64];
+        let mut filler_left = filler.len();
+        loop {
+            let got = serial.read_available(&mut buf[..filler_left.min(64)]);
+            if got == 0 || buf[..got].iter().any(|&byte| byte != FILLER) {
+                break;
+            }
+            filler_left -= got;
+        }
+        if filler_left != 0 {
+            return fail("rx", room, len, "filler lost");
+        }
+        let mut read_buf = vec![0u8; len];
+        let mut read = Box::pin(serial.clone().read(&mut read_buf));
+        let ready = poll_once(read.as_mut(), &waker).is_ready();
+        if ready != (fits == len) {
+            return fail("rx", room, len, "read ready at the wrong time");
+        }
+        if !ready {
+            // the read turned RDAI back on
+            serial.interrupt_handler();
+            if poll_once(read.as_mut(), &waker).is_pending() {
+                return fail("rx", room, len, "read pending with the bytes in");
+            }
+        }
+        drop(read);
+        if read_buf != data {
+            return fail("rx", room, len, "bytes lost or doubled");
+        }
+        serial.read_available(&mut buf) == 0 && mock.rx_left() == 0
+            || fail("rx", room, len, "bytes left over")
+    }
+
+    /// The polling driver reads what is in the FIFO, then `WouldBlock`.
+    fn polling_case(len: usize) -> bool {
+        let mock = MockUart::new();
+        let mut polling = PollingSerial::with_registers(mock);
+        polling.hardware_init(BAUD_RATE);
+        let data = pattern(len, len);
+        mock.inject_rx(&data);
+        let read: Vec<u8> = core::iter::from_fn(|| polling.try_read().ok()).collect();
+        read == data && polling.try_read() == Err(nb::Error::WouldBlock)
+            || fail(
+                "polling",
+                FIFO_DEPTH,
+                len,
+                "lost, doubled or not WouldBlock",
+            )
+    }
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart queue bounds");
+        let (mock, serial) = MockUart::async_serial(BAUD_RATE);
+        let serial = Arc::new(serial);
+        serial.interrupt_handler();
+
+        let mut cases = 0;
+        let mut failed = 0;
+        for &room in ROOM.iter() {
+            for len in 0..=room + 2 {
+                for ok in [
+                    tx_case(mock, &serial, cases, room, len),
+                    rx_case(mock, &serial, cases, room, len),
+                ] {
+                    cases += 1;
+                    if !ok {
+                        failed += 1;
+                    }
+                }
+            }
+        }
+        for len in 0..=FIFO_DEPTH + 2 {
+            cases += 1;
+            if !polling_case(len) {
+                failed += 1;
+            }
+        }
+        println!("[uart queue bounds] {} of {} cases failed", failed, cases);
+        report.check("every case", failed == 0);
+        report.exit_code()
+    }
+}
@@ -0,0 +1,72 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+mock_uart_main!("uart readiness", mock::run);
+
+/// `readable` and `writable` on a `MockUart`: ready at once while the
+/// condition holds, taking and queueing nothing, and woken once it comes
+/// about.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use alloc::{boxed::Box, sync::Arc};
+    use user_lib::user_uart::{regs::*, *};
+
+    const BAUD_RATE: usize = 115_200;
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart readiness");
+        let (mock, serial) = MockUart::async_serial(BAUD_RATE);
+        let serial = Arc::new(serial);
+        serial.interrupt_handler();
+        mock.take_tx();
+        let waker = Arc::new(CountingWaker::default());
+
+        let mut readable = Box::pin(serial.readable());
+        report.check(
+            "empty rx queue pending",
+            poll_once(readable.as_mut(), &waker).is_pending(),
+        );
+        mock.inject_rx(b"abc");
+        serial.interrupt_handler();
+        report.check(
+            "woken by the bytes",
+            waker.wakes() == 1 && poll_once(readable.as_mut(), &waker).is_ready(),
+        );
+        drop(readable);
+        let again =
+            (0..3).all(|_| poll_once(Box::pin(serial.readable()).as_mut(), &waker).is_ready());
+        report.check(
+            "level triggered, nothing taken",
+            again && serial.rx_len() == 3 && !serial.has_read_waker(),
+        );
+        let mut buf = [0u8; 8];
+        serial.read_available(&mut buf);
+
+        report.check(
+            "tx queue with room ready",
+            poll_once(Box::pin(serial.writable()).as_mut(), &waker).is_ready()
+                && mock.take_tx().is_empty(),
+        );
+        // nothing goes out until the handler runs
+        serial.write_available(&[b'w'; DEFAULT_TX_BUFFER_SIZE]);
+        let mut writable = Box::pin(serial.writable());
+        let wakes = waker.wakes();
+        report.check(
+            "full tx queue pending",
+            serial.tx_space() == 0 && poll_once(writable.as_mut(), &waker).is_pending(),
+        );
+        mock.inject_modem_status(MSR_CTS | MSR_DCTS);
+        serial.interrupt_handler();
+        report.check(
+            "woken once bytes go out",
+            waker.wakes() > wakes && poll_once(writable.as_mut(), &waker).is_ready(),
+        );
+        drop(writable);
+        report.check("no waker left", !serial.has_write_waker());
+        report.exit_code()
+    }
+}
@@ -0,0 +1,107 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::sync::Arc;
+use heapless::spsc::Queue;
+use user_lib::{
+    exec, executor::block_on_with, fork, get_time, init_user_trap, set_ext_int_enable,
+    user_uart::*, waitpid, write, yield_,
+};
+
+/// Serial 2, which the kernel also exposes to every process as fd 3.
+const PORT: usize = 2;
+const SERIAL_FD: usize = 3;
+const USER_BAUD_RATE: usize = 115_200;
+/// More than the kernel tx buffer holds, so it only goes through if the
+/// kernel driver is draining the port again.
+const KERNEL_WRITE_LEN: usize = 4096;
+const KERNEL_WRITE_TIMEOUT_MS: isize = 1000;
+
+/// Leaves the port with user baud rate, IER/FCR/MCR settings and the
+/// interrupt routed to this process, the claim never dropped.
+fn claim_and_set_up() {
+    let claim = SerialClaim::claim(PORT).unwrap();
+    type RxBuffer = Queue<u8, DEFAULT_RX_BUFFER_SIZE>;
+    type TxBuffer = Queue<u8, DEFAULT_TX_BUFFER_SIZE>;
+    static mut DRIVER_RX_BUFFER: RxBuffer = RxBuffer::new();
+    static mut DRIVER_TX_BUFFER: TxBuffer = TxBuffer::new();
+    let (rx_pro, rx_con) = unsafe { DRIVER_RX_BUFFER.split() };
+    let (tx_pro, tx_con) = unsafe { DRIVER_TX_BUFFER.split() };
+    let serial = Arc::new(AsyncSerial::from_claim(
+        &claim, rx_pro, rx_con, tx_pro, tx_con,
+    ));
+    serial.hardware_init(USER_BAUD_RATE);
+    serial.rts(true);
+    set_ext_int_enable(claim.irq() as usize, 1);
+    let _ = block_on_with(serial.clone().write(&[b'u'; 64]), || serial.pump());
+    core::mem::forget(serial);
+    core::mem::forget(claim);
+}
+
+/// Dies holding the port.
+fn claim_and_crash() -> ! {
+    claim_and_set_up();
+    panic!("[uart reclaim] crash while holding port {}", PORT);
+}
+
+/// Execs a program that knows nothing of the port it holds.
+fn claim_and_exec() -> ! {
+    claim_and_set_up();
+    exec("hello_world_simple\0", &[core::ptr::null()]);
+    panic!("[uart reclaim] exec failed");
+}
+
+/// Runs `child` in a child process, then checks the kernel driver drains
+/// the port again and that it can be claimed.
+fn reclaimed(case: &str, child: fn() -> !) -> bool {
+    let pid = fork();
+    if pid == 0 {
+        child();
+    }
+    let mut exit_code = 0;
+    waitpid(pid as usize, &mut exit_code);
+    println!("[uart reclaim] {}: child exited with {}", case, exit_code);
+
+    let buf = [b'k'; 64];
+    let mut written = 0;
+    let start = get_time();
+    while written < KERNEL_WRITE_LEN && get_time() - start < KERNEL_WRITE_TIMEOUT_MS {
+        match write(SERIAL_FD, &buf) {
+            len if len > 0 => written += len as usize,
+            _ => {
+                yield_();
+            }
+        }
+    }
+    println!(
+        "[uart reclaim] {}: kernel driver wrote {}/{} bytes",
+        case, written, KERNEL_WRITE_LEN
+    );
+    if written < KERNEL_WRITE_LEN {
+        return false;
+    }
+    // dropped at once, the port goes back to the kernel for the next case
+    match SerialClaim::claim(PORT) {
+        Ok(_) => true,
+        Err(err) => {
+            println!("[uart reclaim] {}: port still held: {:?}", case, err);
+            false
+        }
+    }
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    init_user_trap();
+    let crashed = reclaimed("crash", claim_and_crash);
+    let execed = reclaimed("exec", claim_and_exec);
+    if crashed && execed {
+        0
+    } else {
+        -1
+    }
+}
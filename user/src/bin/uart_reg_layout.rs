@@ -0,0 +1,85 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::user_uart::{regs::*, *};
+
+/// Stands in for a 16550 with 8 byte spacing and 32 bit accesses.
+static mut WIDE: [u32; 16] = [0; 16];
+/// And for one with 4 byte spacing and byte accesses.
+static mut SPARSE: [u8; 32] = [0; 32];
+
+fn check(name: &str, ok: bool) -> bool {
+    println!(
+        "[uart reg layout] {}: {}",
+        name,
+        if ok { "ok" } else { "FAILED" }
+    );
+    ok
+}
+
+/// Drives `UartMmio` with layouts other than the board's over plain
+/// memory, and checks every register lands where the layout puts it.
+#[no_mangle]
+pub fn main() -> i32 {
+    let wide = RegLayout {
+        reg_shift: 3,
+        width: AccessWidth::Word,
+    };
+    // memory standing in for the registers
+    let regs = unsafe { UartMmio::with_layout(WIDE.as_ptr() as usize, wide) };
+    regs.write_ier(IER_ERBFI);
+    regs.write_lcr(LCR_8N1);
+    regs.write_mcr(MCR_RTS);
+    regs.write_fcr(FCR_FIFO_ENABLE);
+    regs.write_thr(b'x');
+    let mut passed = check(
+        "wide: register n at word 2n",
+        unsafe {
+            WIDE[0] == b'x' as u32
+                && WIDE[2] == IER_ERBFI as u32
+                && WIDE[4] == FCR_FIFO_ENABLE as u32
+                && WIDE[6] == LCR_8N1 as u32
+                && WIDE[8] == MCR_RTS as u32
+        } && regs.read_ier() == IER_ERBFI,
+    );
+    unsafe {
+        WIDE[10] = (LSR_THRE | LSR_TEMT) as u32;
+        WIDE[12] = MSR_CTS as u32;
+    }
+    passed &= check(
+        "wide: status reads",
+        regs.read_lsr() == LSR_THRE | LSR_TEMT && regs.read_msr() == MSR_CTS,
+    );
+    regs.write_divisor(0x1234);
+    passed &= check(
+        "wide: divisor latch",
+        unsafe { WIDE[0] == 0x34 && WIDE[2] == 0x12 } && regs.read_lcr() == LCR_8N1,
+    );
+    passed &= check("wide: probe", regs.probe());
+
+    let sparse = RegLayout {
+        reg_shift: 2,
+        width: AccessWidth::Byte,
+    };
+    let regs = unsafe { UartMmio::with_layout(SPARSE.as_ptr() as usize, sparse) };
+    regs.write_lcr(LCR_8N1);
+    regs.write_mcr(MCR_DTR);
+    passed &= check("sparse: register n at byte 4n", unsafe {
+        SPARSE[12] == LCR_8N1 && SPARSE[16] == MCR_DTR && SPARSE[13] == 0
+    });
+    passed &= check(
+        "kernel layout",
+        RegLayout::from_raw(3, 4) == Some(wide)
+            && RegLayout::from_raw(0, 0) == Some(RegLayout::BOARD)
+            && RegLayout::from_raw(0, 2).is_none(),
+    );
+
+    if passed {
+        0
+    } else {
+        -1
+    }
+}
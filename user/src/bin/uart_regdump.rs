@@ -0,0 +1,53 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    drop_caps, exit, fork,
+    user_uart::serial::{debug_dump, enumerate},
+    waitpid, CAP_SERIAL_DEBUG,
+};
+
+/// Dumps the registers of every serial, then drops the capability and
+/// checks that neither this process nor a child can dump any more.
+#[no_mangle]
+pub fn main() -> i32 {
+    let ports = enumerate();
+    for port in ports.iter() {
+        match debug_dump(port.index) {
+            Ok(snapshot) => println!("[uart regdump] {}", snapshot),
+            Err(err) => {
+                println!("[uart regdump] serial {}: error {}", port.index, err);
+                return -1;
+            }
+        }
+    }
+    let missing = debug_dump(ports.len());
+
+    let left = drop_caps(CAP_SERIAL_DEBUG);
+    let dropped = debug_dump(0);
+    let pid = fork();
+    if pid == 0 {
+        exit(if debug_dump(0) == Err(-1) { 0 } else { -1 });
+    }
+    let mut child_code = 0;
+    waitpid(pid as usize, &mut child_code);
+    println!(
+        "[uart regdump] missing port {:?}, caps left {:#x}, after drop {:?}, child {}",
+        missing.err(),
+        left,
+        dropped.err(),
+        child_code
+    );
+    if missing == Err(-2)
+        && left as usize & CAP_SERIAL_DEBUG == 0
+        && dropped == Err(-1)
+        && child_code == 0
+    {
+        0
+    } else {
+        -1
+    }
+}
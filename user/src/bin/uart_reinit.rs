@@ -0,0 +1,90 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+mock_uart_main!("uart reinit", mock::run);
+
+/// Sets a `MockUart` up in two steps, then runs `hardware_init` again with
+/// reads pending: queued bytes go out, received ones are dropped, a
+/// `read_checked` fails with `SerialError::Reinit` and a plain read goes
+/// on.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use alloc::{boxed::Box, sync::Arc};
+    use core::task::Poll;
+    use user_lib::user_uart::{regs::*, *};
+
+    const BAUD_RATE: usize = 115_200;
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart reinit");
+        let (mock, serial) = MockUart::async_serial_uninit();
+        let serial = Arc::new(serial);
+
+        serial.init_line(BAUD_RATE);
+        report.check(
+            "line set up, interrupts off",
+            serial.is_initialized() && mock.divisor() != 0 && mock.read_ier() == 0,
+        );
+        serial.enable_interrupts();
+        report.check(
+            "interrupts on",
+            mock.read_ier() & (IER_ERBFI | IER_ETBEI) == IER_ERBFI | IER_ETBEI,
+        );
+        serial.interrupt_handler();
+        mock.take_tx();
+
+        let waker = Arc::new(CountingWaker::default());
+        let mut buf = [0u8; 4];
+        let mut checked = Box::pin(serial.clone().read_checked(&mut buf));
+        report.check(
+            "checked read pending",
+            poll_once(checked.as_mut(), &waker).is_pending(),
+        );
+        // received but not read yet, and queued but not sent yet
+        mock.inject_rx(b"xy");
+        serial.interrupt_handler();
+        serial.write_available(b"queued");
+        let wakes = waker.wakes();
+        serial.hardware_init(BAUD_RATE);
+        report.check("pending read woken", waker.wakes() > wakes);
+        report.check(
+            "checked read fails",
+            poll_once(checked.as_mut(), &waker) == Poll::Ready(Err(SerialError::Reinit)),
+        );
+        drop(checked);
+        report.check("its waker taken back", !serial.has_read_waker());
+        let mut rest = [0u8; 8];
+        report.check(
+            "rx dropped, tx sent",
+            serial.read_available(&mut rest) == 0 && mock.take_tx() == b"queued",
+        );
+        report.check("rx interrupt back on", mock.read_ier() & IER_ERBFI != 0);
+
+        let waker = Arc::new(CountingWaker::default());
+        let mut buf = [0u8; 2];
+        let mut plain = Box::pin(serial.clone().read(&mut buf));
+        report.check(
+            "plain read pending",
+            poll_once(plain.as_mut(), &waker).is_pending(),
+        );
+        serial.hardware_init(BAUD_RATE);
+        report.check(
+            "plain read goes on",
+            poll_once(plain.as_mut(), &waker).is_pending(),
+        );
+        mock.inject_rx(b"cd");
+        serial.interrupt_handler();
+        report.check(
+            "plain read done",
+            poll_once(plain.as_mut(), &waker).is_ready(),
+        );
+        drop(plain);
+        report.check("with the new bytes", &buf == b"cd");
+
+        report.exit_code()
+    }
+}
@@ -0,0 +1,125 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+mock_uart_main!("uart resync", mock::run);
+
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use alloc::sync::Arc;
+    use alloc::vec::Vec;
+    use user_lib::executor::block_on_with;
+    use user_lib::user_uart::{framed::*, xmodem::crc16, *};
+
+    const DELIMITER: u8 = 0x7e;
+
+    /// Framing of their own: payload, CRC-16, then the delimiter.
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        let mut frame = payload.to_vec();
+        frame.extend_from_slice(&crc16(payload).to_be_bytes());
+        frame
+    }
+
+    fn crc_ok(frame: &[u8]) -> bool {
+        let len = frame.len();
+        len >= 2 && crc16(&frame[..len - 2]).to_be_bytes() == frame[len - 2..]
+    }
+
+    /// Checks `Resync::recover` on a `MockUart` fed line noise, a frame
+    /// holding the delimiter and good frames, then the frame counters of a
+    /// `FramedSerial` checking CRCs.
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart resync");
+        let (mock, serial) = MockUart::async_serial(115_200);
+        let serial = Arc::new(serial);
+        serial.interrupt_handler();
+        AsyncSerial::reset_stats(&serial);
+        let mut pump = || serial.interrupt_handler();
+        let cfg = ResyncConfig {
+            delimiter: DELIMITER,
+            check: crc_ok,
+            max_frame: 64,
+        };
+
+        // the rest of a bad frame, noise, a frame holding the delimiter, which
+        // only ever comes in pieces, then two good ones
+        let mut stream = alloc::vec![9, 9, 9, DELIMITER, 5, 6, 7, DELIMITER];
+        stream.extend(frame(&[1, DELIMITER, 2]));
+        stream.push(DELIMITER);
+        stream.extend(frame(b"first"));
+        stream.push(DELIMITER);
+        stream.extend(frame(b"second"));
+        stream.push(DELIMITER);
+        mock.inject_rx(&stream);
+        let skipped = block_on_with(Resync::recover(&serial, &cfg), &mut pump);
+        let mut next = [0u8; 9];
+        let _ = block_on_with(serial.clone().read(&mut next), &mut pump);
+        let frames = serial.stats().frames;
+        report.check(
+            "recover skips to a checked boundary",
+            skipped == 14
+                && next[..8] == frame(b"second")[..]
+                && next[8] == DELIMITER
+                && frames.frames_ok == 1
+                && frames.crc_failures == 3
+                && frames.resync_events == 1
+                && frames.bytes_skipped == 14,
+        );
+
+        // runs over `max_frame` are not taken for a frame
+        AsyncSerial::reset_stats(&serial);
+        let mut stream = alloc::vec![DELIMITER];
+        stream.extend(core::iter::repeat(3).take(100));
+        stream.push(DELIMITER);
+        stream.extend(frame(b"good"));
+        stream.push(DELIMITER);
+        mock.inject_rx(&stream);
+        let skipped = block_on_with(Resync::recover(&serial, &cfg), &mut pump);
+        let frames = serial.stats().frames;
+        report.check(
+            "recover drops overlong runs unchecked",
+            skipped == 102 && frames.crc_failures == 0 && frames.bytes_skipped == 102,
+        );
+
+        // COBS frames with a CRC inside, one of them damaged on the way
+        AsyncSerial::reset_stats(&serial);
+        let mut framed = FramedSerial::new(serial.clone(), 32);
+        framed.set_check(crc_ok);
+        let mut encoded = [0u8; 40];
+        let mut stream = Vec::new();
+        for (i, payload) in [&b"one"[..], b"two", b"three"].iter().enumerate() {
+            let mut good = frame(payload);
+            if i == 1 {
+                good[0] ^= 0x20;
+            }
+            let len = Cobs::encode(&good, &mut encoded);
+            stream.extend_from_slice(&encoded[..len]);
+            stream.push(Cobs::DELIMITER);
+        }
+        mock.inject_rx(&stream);
+        let mut buf = [0u8; 32];
+        let results = block_on_with(
+            async {
+                [
+                    framed.recv_frame(&mut buf).await,
+                    framed.recv_frame(&mut buf).await,
+                    framed.recv_frame(&mut buf).await,
+                ]
+            },
+            &mut pump,
+        );
+        let frames = framed.stats();
+        report.check(
+            "framed counts CRC failures",
+            results == [Ok(5), Err(FrameError::Check), Ok(7)]
+                && frames.frames_ok == 2
+                && frames.crc_failures == 1
+                && frames.resync_events == 1
+                && frames.bytes_skipped == 7,
+        );
+        report.exit_code()
+    }
+}
@@ -0,0 +1,123 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+mock_uart_main!("uart revoke", mock::run);
+
+/// Revokes an `AsyncSerial` on a `MockUart` with a read and a write
+/// pending, as the kernel does once the claim is handed on. Checks that
+/// both fail with `SerialError::Closed` and their partial counts, that
+/// the handler leaves the IRQ table, that an event is posted, and that no
+/// register is written, the real ones being unmapped by then.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use alloc::sync::Arc;
+    use spin::Mutex;
+    use user_lib::executor::{block_on, Executor, IdleStrategy};
+    use user_lib::irq::{self, IRQ_REVOKED_MSG};
+    use user_lib::user_uart::{regs::*, *};
+
+    type Serial = AsyncSerial<&'static MockUart>;
+
+    const BAUD_RATE: usize = 115_200;
+    /// No port has it, `irq::dispatch` only finds the mock here.
+    const IRQ: u16 = 200;
+    const READ_LEN: usize = 8;
+    const ARRIVED: &[u8] = b"abc";
+    /// More than the tx queue holds, and no CTS credit to send any.
+    const WRITE_LEN: usize = DEFAULT_TX_BUFFER_SIZE + 64;
+
+    static READ_RESULT: Mutex<Option<Result<(), SerialError>>> = Mutex::new(None);
+    static WRITE_RESULT: Mutex<Option<Result<(), SerialError>>> = Mutex::new(None);
+
+    async fn reader(serial: Arc<Serial>) {
+        let mut buf = [0u8; READ_LEN];
+        let result = serial.read(&mut buf).await;
+        *READ_RESULT.lock() = Some(result);
+    }
+
+    async fn writer(serial: Arc<Serial>) {
+        static DATA: [u8; WRITE_LEN] = [0xa5; WRITE_LEN];
+        let result = serial.write(&DATA).await;
+        *WRITE_RESULT.lock() = Some(result);
+    }
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart revoke");
+        let (mock, serial) = MockUart::async_serial(BAUD_RATE);
+        let serial = Arc::new(serial);
+        let bus = Arc::new(SerialEventBus::new());
+        serial.attach_event_bus(bus.clone());
+        report.check("IRQ registered", serial.register_irq(IRQ).is_ok());
+
+        let executor = Executor::new(IdleStrategy::Spin);
+        executor.spawn(reader(serial.clone()));
+        executor.spawn(writer(serial.clone()));
+        executor.run_until(|| serial.has_read_waker() && serial.has_write_waker());
+        mock.inject_rx(ARRIVED);
+        irq::dispatch(IRQ);
+        executor.run_until(|| serial.rx_len() == 0);
+        report.check(
+            "both pending",
+            READ_RESULT.lock().is_none() && WRITE_RESULT.lock().is_none(),
+        );
+
+        report.check(
+            "other messages left alone",
+            !irq::handle_revoke_interrupt(IRQ as usize),
+        );
+        mock.take_tx();
+        let ier = mock.read_ier();
+        report.check(
+            "revoke message taken",
+            irq::handle_revoke_interrupt(IRQ_REVOKED_MSG | IRQ as usize),
+        );
+        executor.run_until(|| READ_RESULT.lock().is_some() && WRITE_RESULT.lock().is_some());
+        report.check(
+            "read failed with what it got",
+            *READ_RESULT.lock()
+                == Some(Err(SerialError::Closed {
+                    done: ARRIVED.len(),
+                })),
+        );
+        let written = match *WRITE_RESULT.lock() {
+            Some(Err(SerialError::Closed { done })) => done,
+            _ => 0,
+        };
+        report.check(
+            "write failed with what it queued",
+            written > 0 && written < WRITE_LEN,
+        );
+        report.check(
+            "revoked, no waker left",
+            serial.is_closed()
+                && serial.is_revoked()
+                && !serial.has_read_waker()
+                && !serial.has_write_waker(),
+        );
+        report.check("handler unregistered", !irq::dispatch(IRQ));
+        report.check(
+            "event posted",
+            core::iter::from_fn(|| bus.try_next())
+                .any(|event| event.kind == SerialEventKind::Revoked),
+        );
+
+        let mut buf = [0u8; READ_LEN];
+        let later = block_on(serial.clone().read(&mut buf));
+        report.check(
+            "later reads fail at once",
+            later == Err(SerialError::Closed { done: 0 }),
+        );
+        drop(executor);
+        drop(serial);
+        report.check(
+            "no register written, dropped too",
+            mock.read_ier() == ier && mock.take_tx().is_empty(),
+        );
+
+        report.exit_code()
+    }
+}
@@ -0,0 +1,107 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use riscv::register::uie;
+use user_lib::{init_user_trap, irq, set_ext_int_enable, timer::cycles, user_uart::*};
+
+const PORTS: [usize; 3] = [1, 2, 3];
+const BAUD_RATE: usize = 115_200;
+const ROUND_NUM: usize = 96;
+/// How long each handler keeps its port, so waiting behind it shows.
+const HANDLER_CYCLES: usize = 20_000;
+/// Mean latencies of the ports may differ by this factor at most.
+const MAX_SPREAD: usize = 2;
+
+const ZERO: AtomicUsize = AtomicUsize::new(0);
+static BASES: [AtomicUsize; 3] = [ZERO; 3];
+static IRQS: [AtomicUsize; 3] = [ZERO; 3];
+static FIRST: [AtomicUsize; 3] = [ZERO; 3];
+static SERVED: AtomicUsize = ZERO;
+
+fn registers(index: usize) -> UartMmio {
+    // from the claims `main` holds until the end
+    unsafe { UartMmio::new(BASES[index].load(Relaxed)) }
+}
+
+/// Raises a THR empty interrupt on every port at once, with user external
+/// interrupts held off so they are all pending when the handler runs.
+fn run_round() {
+    unsafe {
+        uie::clear_uext();
+    }
+    SERVED.store(0, Relaxed);
+    for index in 0..PORTS.len() {
+        registers(index).set_tx_interrupt(true);
+    }
+    unsafe {
+        uie::set_uext();
+    }
+    while SERVED.load(Relaxed) < PORTS.len() {}
+}
+
+/// Keeps three ports of one priority busy together and checks each gets
+/// served first in turn, and waits about as long as the others.
+#[no_mangle]
+pub fn main() -> i32 {
+    init_user_trap();
+    let claims = PORTS.map(|port| SerialClaim::claim(port).unwrap());
+    for (index, claim) in claims.iter().enumerate() {
+        let mut serial = BlockingSerial::from_claim(claim);
+        serial.hardware_init(BAUD_RATE);
+        // keep the registers as they are, the claim resets them on drop
+        core::mem::forget(serial);
+        claim.set_priority(1).unwrap();
+        BASES[index].store(claim.base_address(), Relaxed);
+        IRQS[index].store(claim.irq() as usize, Relaxed);
+        set_ext_int_enable(claim.irq() as usize, 1);
+    }
+
+    serial::reset_service_latency();
+    for _ in 0..ROUND_NUM {
+        run_round();
+    }
+    unsafe {
+        uie::clear_uext();
+    }
+
+    let mut means = [0; 3];
+    for (index, &port) in PORTS.iter().enumerate() {
+        let latency = serial::service_latency(port);
+        means[index] = latency.mean_cycles();
+        println!(
+            "[uart round robin] port {}: served first {}/{}, latency mean {} max {} cycles",
+            port,
+            FIRST[index].load(Relaxed),
+            ROUND_NUM,
+            latency.mean_cycles(),
+            latency.max_cycles
+        );
+    }
+    let (min, max) = (*means.iter().min().unwrap(), *means.iter().max().unwrap());
+    let turns = FIRST
+        .iter()
+        .all(|first| first.load(Relaxed) >= ROUND_NUM / PORTS.len() / 2);
+    if turns && max <= min.max(1) * MAX_SPREAD {
+        0
+    } else {
+        -1
+    }
+}
+
+#[no_mangle]
+pub fn ext_intr_handler(irq: u16, _is_from_kernel: bool) {
+    if let Some(index) = (0..PORTS.len()).find(|&index| IRQS[index].load(Relaxed) == irq as usize) {
+        registers(index).set_tx_interrupt(false);
+        if SERVED.load(Relaxed) == 0 {
+            FIRST[index].fetch_add(1, Relaxed);
+        }
+        let start = cycles();
+        while cycles().wrapping_sub(start) < HANDLER_CYCLES {}
+        SERVED.fetch_add(1, Relaxed);
+    }
+    irq::complete(irq);
+}
@@ -0,0 +1,84 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+mock_uart_main!("uart rx hook", mock::run);
+
+/// Answers a poll token from an rx hook on a `MockUart`. Checks that the
+/// hook sees every byte, that the token it takes never reaches a read,
+/// that its reply goes out in the same `interrupt_handler` call, ahead of
+/// what was queued before, and that without the hook the token is read
+/// like any byte.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+    use user_lib::user_uart::{regs::*, *};
+
+    const BAUD_RATE: usize = 115_200;
+    const POLL_TOKEN: u8 = 0x05;
+    const ACK: u8 = 0x06;
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    fn answer_poll(ch: u8) -> HookAction {
+        CALLS.fetch_add(1, Relaxed);
+        if ch == POLL_TOKEN {
+            HookAction::CONSUME.with_reply(ACK)
+        } else {
+            HookAction::PASS
+        }
+    }
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart rx hook");
+        let (mock, serial) = MockUart::async_serial(BAUD_RATE);
+        serial.interrupt_handler();
+        // CTS credit for the next FIFO
+        let credit = || {
+            mock.inject_modem_status(MSR_CTS | MSR_DCTS);
+            serial.interrupt_handler();
+        };
+        let mut buf = [0u8; 16];
+
+        serial.set_rx_hook(Some(answer_poll));
+        credit();
+        mock.inject_rx(&[b'a', b'b', POLL_TOKEN, b'c']);
+        serial.interrupt_handler();
+        let len = serial.read_available(&mut buf);
+        report.check(
+            "token taken, the rest read",
+            &buf[..len] == b"abc" && CALLS.load(Relaxed) == 4,
+        );
+        report.check("answered in the same call", mock.take_tx() == [ACK]);
+
+        // the rx data interrupt comes first, the reply is in the lane by
+        // the time the THR empty one sends the queue
+        credit();
+        serial.write_available(b"queued");
+        mock.inject_rx(&[POLL_TOKEN]);
+        serial.interrupt_handler();
+        report.check(
+            "reply ahead of the tx queue",
+            mock.take_tx() == b"\x06queued",
+        );
+        let stats = serial.stats();
+        report.check(
+            "calls counted",
+            stats.rx_hook_count == 5 && stats.rx_hook_drops == 0,
+        );
+
+        serial.set_rx_hook(None);
+        credit();
+        mock.inject_rx(&[POLL_TOKEN]);
+        serial.interrupt_handler();
+        let len = serial.read_available(&mut buf);
+        report.check(
+            "without the hook the token is read",
+            buf[..len] == [POLL_TOKEN] && mock.take_tx().is_empty() && CALLS.load(Relaxed) == 5,
+        );
+
+        report.exit_code()
+    }
+}
@@ -0,0 +1,132 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+mock_uart_main!("uart rx ring", mock::run);
+
+/// Runs an `AsyncSerial` on a one page `RxRing` over a `MockUart`: chunks
+/// read in place, the wrap, the ring filling up, and plain reads mixed
+/// with chunks.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use alloc::vec::Vec;
+    use user_lib::trap::PAGE_SIZE;
+    use user_lib::user_uart::{regs::*, *};
+
+    const BAUD_RATE: usize = 115_200;
+
+    /// Byte `index` of what is injected.
+    fn pattern(index: usize) -> u8 {
+        (index.wrapping_mul(7) ^ (index >> 8)) as u8
+    }
+
+    /// Injects the next `len` bytes of the pattern from `*sent` on.
+    fn inject(mock: &MockUart, sent: &mut usize, len: usize) {
+        let bytes: Vec<u8> = (*sent..*sent + len).map(pattern).collect();
+        mock.inject_rx(&bytes);
+        *sent += len;
+    }
+
+    fn matches(data: &[u8], from: usize) -> bool {
+        data.iter()
+            .enumerate()
+            .all(|(i, &byte)| byte == pattern(from + i))
+    }
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart rx ring");
+        static mut RING_PAGES: RingPages<1> = RingPages::new();
+        let (mock, serial) = MockUart::async_serial(BAUD_RATE);
+        report.check("no chunk without a ring", serial.read_chunk().is_none());
+        serial.attach_rx_ring(RxRing::new(unsafe { &mut RING_PAGES }));
+
+        let mut sent = 0;
+        let mut taken = 0;
+        inject(mock, &mut sent, 3000);
+        serial.interrupt_handler();
+        let chunk = serial.read_chunk().unwrap();
+        report.check(
+            "chunk in place",
+            chunk.len() == 3000 && matches(&chunk, taken),
+        );
+        report.check(
+            "one chunk at a time",
+            serial.read_chunk().is_none() && serial.read_available(&mut [0u8; 8]) == 0,
+        );
+        chunk.consume(2000);
+        taken += 2000;
+        report.check("rest left", serial.rx_len() == 1000);
+
+        // 2000 more run past the end of the page
+        inject(mock, &mut sent, 2000);
+        serial.interrupt_handler();
+        let chunk = serial.read_chunk().unwrap();
+        let to_end = PAGE_SIZE - taken;
+        report.check(
+            "chunk stops at the wrap",
+            chunk.len() == to_end && matches(&chunk, taken),
+        );
+        let len = chunk.len();
+        chunk.consume(len);
+        taken += len;
+        let chunk = serial.read_chunk().unwrap();
+        report.check(
+            "rest from the start",
+            chunk.len() == sent - taken && matches(&chunk, taken),
+        );
+        drop(chunk);
+        report.check(
+            "dropped chunk keeps its bytes",
+            serial.rx_len() == sent - taken,
+        );
+
+        let mut buf = [0u8; 600];
+        let len = serial.read_available(&mut buf);
+        report.check("plain read", len == 600 && matches(&buf, taken));
+        taken += len;
+        let len = serial.read_chunk().unwrap().len();
+        report.check("chunk after a read", len == sent - taken);
+
+        // more than fits, the rest waits in the FIFO
+        let room = PAGE_SIZE - (sent - taken);
+        inject(mock, &mut sent, room + 500);
+        serial.interrupt_handler();
+        report.check(
+            "full ring stops rx interrupts",
+            serial.rx_len() == PAGE_SIZE
+                && mock.rx_left() == 500
+                && mock.read_ier() & IER_ERBFI == 0,
+        );
+        let chunk = serial.read_chunk().unwrap();
+        let len = chunk.len();
+        chunk.consume(len);
+        taken += len;
+        report.check("consume turns them on", mock.read_ier() & IER_ERBFI != 0);
+        serial.interrupt_handler();
+        let mut all = Vec::new();
+        let mut buf = [0u8; 1000];
+        loop {
+            let len = serial.read_available(&mut buf);
+            if len == 0 {
+                break;
+            }
+            all.extend_from_slice(&buf[..len]);
+        }
+        report.check(
+            "nothing lost",
+            mock.rx_left() == 0 && all.len() == sent - taken && matches(&all, taken),
+        );
+
+        let stats = serial.stats();
+        println!(
+            "[uart rx ring] {} bytes, high water {}, {} rx interrupts per KiB",
+            stats.rx_count,
+            stats.rx_high_water,
+            stats.rx_intr_per_kb()
+        );
+        report.exit_code()
+    }
+}
@@ -0,0 +1,90 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+mock_uart_main!("uart rx timing", mock::run);
+
+/// Records rx drains of a `MockUart`: nothing while off, then bursts with
+/// a gap between them, a character timeout and more drains than the
+/// record keeps.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use alloc::sync::Arc;
+    use user_lib::timer::cycles;
+    use user_lib::user_uart::{regs::*, *};
+
+    const BAUD_RATE: usize = 115_200;
+    /// Between the request and the reply to it.
+    const GAP_CYCLES: usize = 50_000;
+
+    fn spin(cycle_num: usize) {
+        let start = cycles();
+        while cycles().wrapping_sub(start) < cycle_num {}
+    }
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart rx timing");
+        let (mock, serial) = MockUart::async_serial(BAUD_RATE);
+        let serial = Arc::new(serial);
+        serial.interrupt_handler();
+        let mut buf = [0u8; 64];
+
+        mock.inject_rx(b"unseen");
+        serial.interrupt_handler();
+        serial.read_available(&mut buf);
+        report.check("off by default", serial.rx_timing_events().is_empty());
+
+        // a request goes out, the reply comes in two bursts, the second
+        // drained on a character timeout
+        serial.set_rx_timing(true);
+        let sent = cycles();
+        spin(GAP_CYCLES);
+        mock.inject_rx(b"reply");
+        serial.interrupt_handler();
+        spin(GAP_CYCLES);
+        mock.inject_rx(b"end");
+        mock.inject_iid(IID_CHAR_TIMEOUT);
+        serial.interrupt_handler();
+        serial.read_available(&mut buf);
+        let events = serial.rx_timing_events();
+        report.check(
+            "one event per drain",
+            events.len() == 2
+                && (events[0].len, events[0].char_timeout) == (5, false)
+                && (events[1].len, events[1].char_timeout) == (3, true),
+        );
+        if events.len() == 2 {
+            let latency = events[0].cycles.wrapping_sub(sent);
+            let gap = events[1].cycles.wrapping_sub(events[0].cycles);
+            println!(
+                "[uart rx timing] reply after {} cycles, bursts {} cycles apart",
+                latency, gap
+            );
+            report.check("gaps measured", latency >= GAP_CYCLES && gap >= GAP_CYCLES);
+        }
+        report.check("taken once", serial.rx_timing_events().is_empty());
+
+        for byte in 0..RX_TIMING_LEN as u8 + 8 {
+            mock.inject_rx(&[byte]);
+            serial.interrupt_handler();
+            serial.read_available(&mut buf);
+        }
+        let events = serial.rx_timing_events();
+        report.check(
+            "oldest dropped",
+            events.len() == RX_TIMING_LEN
+                && events
+                    .windows(2)
+                    .all(|pair| pair[0].cycles <= pair[1].cycles),
+        );
+
+        serial.set_rx_timing(false);
+        mock.inject_rx(b"off");
+        serial.interrupt_handler();
+        report.check("off again", serial.rx_timing_events().is_empty());
+        report.exit_code()
+    }
+}
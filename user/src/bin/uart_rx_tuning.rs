@@ -0,0 +1,100 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+mock_uart_main!("uart rx tuning", mock::run);
+
+/// Counts data available and character timeout interrupts of a `MockUart`
+/// apart, and has `RxTriggerTuner` lower the rx trigger level on timeouts
+/// and raise it on a high interrupt rate.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use alloc::sync::Arc;
+    use user_lib::timer::now_us;
+    use user_lib::user_uart::{regs::*, *};
+
+    const BAUD_RATE: usize = 115_200;
+
+    /// Lets a 1 us sampling window pass.
+    fn next_window() {
+        let start = now_us();
+        while now_us() == start {}
+    }
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart rx tuning");
+        let (mock, serial) = MockUart::async_serial(BAUD_RATE);
+        let serial = Arc::new(serial);
+        serial.interrupt_handler();
+
+        // short bursts, mostly picked up by the timeout
+        let mut tuner = RxTriggerTuner::new(
+            serial.clone(),
+            RxTuning {
+                window_us: 1,
+                max_timeout_percent: 50,
+                max_intr_rate: usize::MAX,
+                min_intr_count: 4,
+            },
+        );
+        for _ in 0..4 {
+            mock.inject_iid(IID_CHAR_TIMEOUT);
+            mock.inject_rx(b"ab");
+            serial.interrupt_handler();
+        }
+        for _ in 0..2 {
+            mock.inject_rx(b"cd");
+            serial.interrupt_handler();
+        }
+        let stats = serial.stats();
+        report.check(
+            "counted apart",
+            stats.rx_timeout_count == 4 && stats.rx_intr_count == 2 && stats.rx_count == 12,
+        );
+        next_window();
+        report.check(
+            "timeouts lower the level",
+            tuner.tune() == Some(FifoTrigger::Eight)
+                && serial.fifo_trigger() == FifoTrigger::Eight
+                && mock.fcr() == FCR_FIFO_ENABLE | FCR_RX_TRIGGER_8,
+        );
+
+        // any rate is too high
+        let mut tuner = RxTriggerTuner::new(
+            serial.clone(),
+            RxTuning {
+                window_us: 1,
+                max_timeout_percent: 100,
+                max_intr_rate: 0,
+                min_intr_count: 4,
+            },
+        );
+        for _ in 0..4 {
+            mock.inject_rx(b"ef");
+            serial.interrupt_handler();
+        }
+        next_window();
+        report.check(
+            "rate raises the level",
+            tuner.tune() == Some(FifoTrigger::Fourteen),
+        );
+        for _ in 0..4 {
+            mock.inject_rx(b"gh");
+            serial.interrupt_handler();
+        }
+        next_window();
+        report.check("stays at the top", tuner.tune().is_none());
+        next_window();
+        report.check("too few to tell", tuner.tune().is_none());
+        report.check(
+            "no 16 byte level with 16 byte FIFOs",
+            serial.set_fifo_trigger(FifoTrigger::Sixteen).is_err()
+                && serial.fifo_trigger() == FifoTrigger::Fourteen,
+        );
+
+        report.exit_code()
+    }
+}
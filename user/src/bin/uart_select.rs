@@ -0,0 +1,109 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+mock_uart_main!("uart select", mock::run);
+
+/// Waits on three `MockUart` ports at once: data there before the call,
+/// turns taken between busy ports, a wakeup from a port that had nothing,
+/// two selects on the same port and a set that changes.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use alloc::sync::Arc;
+    use core::pin::Pin;
+    use core::task::Poll;
+    use user_lib::user_uart::*;
+
+    const BAUD_RATE: usize = 115_200;
+
+    type Serial = Arc<AsyncSerial<&'static MockUart>>;
+
+    /// A port on a mock of its own, with queues of its own.
+    fn mock_port() -> (&'static MockUart, Serial) {
+        let (mock, serial) = MockUart::async_serial(BAUD_RATE);
+        let serial = Arc::new(serial);
+        serial.interrupt_handler();
+        (mock, serial)
+    }
+
+    fn select_now(ports: &[Serial]) -> Poll<usize> {
+        let waker = Arc::new(CountingWaker::default());
+        let mut select = select_readable(ports);
+        poll_once(unsafe { Pin::new_unchecked(&mut select) }, &waker)
+    }
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart select");
+        let (mock_a, a) = mock_port();
+        let (mock_b, b) = mock_port();
+        let (_, c) = mock_port();
+        let ports = [a.clone(), b.clone(), c.clone()];
+        let mut buf = [0u8; 16];
+
+        mock_b.inject_rx(b"b");
+        b.interrupt_handler();
+        report.check("ready at the call", select_now(&ports) == Poll::Ready(1));
+
+        // both keep data, the pick moves on every time
+        mock_a.inject_rx(b"a");
+        a.interrupt_handler();
+        let picks = [select_now(&ports), select_now(&ports), select_now(&ports)];
+        report.check(
+            "turns taken",
+            picks == [Poll::Ready(0), Poll::Ready(1), Poll::Ready(0)],
+        );
+        a.read_available(&mut buf);
+        b.read_available(&mut buf);
+
+        let waker = Arc::new(CountingWaker::default());
+        let mut select = select_readable(&ports);
+        let mut select = unsafe { Pin::new_unchecked(&mut select) };
+        let pending = poll_once(select.as_mut(), &waker).is_pending();
+        mock_a.inject_rx(b"late");
+        a.interrupt_handler();
+        let woken = waker.wakes() > 0;
+        report.check(
+            "woken by a quiet port",
+            pending && woken && poll_once(select.as_mut(), &waker) == Poll::Ready(0),
+        );
+        report.check(
+            "wakers taken back",
+            a.read_available(&mut buf) == 4 && !a.has_read_waker(),
+        );
+
+        // two selects, as from two tasks, both waiting on `b`
+        let other = [a.clone(), b.clone()];
+        let wakers = [
+            Arc::new(CountingWaker::default()),
+            Arc::new(CountingWaker::default()),
+        ];
+        let mut first = select_readable(&ports[1..]);
+        let mut first = unsafe { Pin::new_unchecked(&mut first) };
+        let mut second = select_readable(&other);
+        let mut second = unsafe { Pin::new_unchecked(&mut second) };
+        let pending = poll_once(first.as_mut(), &wakers[0]).is_pending()
+            && poll_once(second.as_mut(), &wakers[1]).is_pending();
+        mock_b.inject_rx(b"b");
+        b.interrupt_handler();
+        report.check(
+            "both selects woken",
+            pending
+                && wakers.iter().all(|waker| waker.wakes() == 1)
+                && poll_once(first.as_mut(), &wakers[0]) == Poll::Ready(0)
+                && poll_once(second.as_mut(), &wakers[1]) == Poll::Ready(1),
+        );
+        b.read_available(&mut buf);
+
+        // fewer ports, in another order
+        mock_b.inject_rx(b"b");
+        b.interrupt_handler();
+        report.check(
+            "set changed",
+            select_now(&[c.clone(), b.clone()]) == Poll::Ready(1),
+        );
+        report.exit_code()
+    }
+}
@@ -0,0 +1,115 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+mock_uart_main!("uart shared read", mock::run);
+
+/// Two reads of one `MockUart` port waiting at once, as from two tasks:
+/// both woken, the bytes handed out between them without loss, a dropped
+/// read taking out only its own waker, and one reader too many.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use alloc::{boxed::Box, sync::Arc, vec::Vec};
+    use user_lib::user_uart::*;
+
+    const BAUD_RATE: usize = 115_200;
+    const READ_LEN: usize = 4;
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart shared read");
+        let (mock, serial) = MockUart::async_serial(BAUD_RATE);
+        let serial = Arc::new(serial);
+        serial.interrupt_handler();
+
+        // two tasks, one byte at a time, each polled only when woken
+        let wakers = [
+            Arc::new(CountingWaker::default()),
+            Arc::new(CountingWaker::default()),
+        ];
+        let mut bufs = [[0u8; READ_LEN]; 2];
+        let (first_buf, second_buf) = bufs.split_at_mut(1);
+        let mut reads = [
+            Some(Box::pin(serial.clone().read(&mut first_buf[0]))),
+            Some(Box::pin(serial.clone().read(&mut second_buf[0]))),
+        ];
+        let mut seen = [0; 2];
+        report.check(
+            "both wait",
+            reads.iter_mut().zip(wakers.iter()).all(|(read, waker)| {
+                poll_once(read.as_mut().unwrap().as_mut(), waker).is_pending()
+            }),
+        );
+        let mut woken_both = false;
+        for byte in 0..2 * READ_LEN as u8 {
+            mock.inject_rx(&[byte]);
+            serial.interrupt_handler();
+            if byte == 0 {
+                woken_both = wakers.iter().all(|waker| waker.wakes() == 1);
+            }
+            for i in 0..2 {
+                let wakes = wakers[i].wakes();
+                if wakes == seen[i] {
+                    continue;
+                }
+                seen[i] = wakes;
+                if let Some(read) = reads[i].as_mut() {
+                    if poll_once(read.as_mut(), &wakers[i]).is_ready() {
+                        reads[i] = None;
+                    }
+                }
+            }
+        }
+        report.check("a byte wakes both", woken_both);
+        let done = reads.iter().all(|read| read.is_none());
+        drop(reads);
+        let mut got: Vec<u8> = bufs.iter().flatten().copied().collect();
+        got.sort_unstable();
+        report.check(
+            "bytes handed out, nobody hangs",
+            done && got.iter().enumerate().all(|(i, &byte)| byte == i as u8),
+        );
+        report.check("no waker left", !serial.has_read_waker());
+
+        let first_waker = Arc::new(CountingWaker::default());
+        let second_waker = Arc::new(CountingWaker::default());
+        let mut first_buf = [0u8; 1];
+        let mut second_buf = [0u8; 1];
+        let mut first = Box::pin(serial.clone().read(&mut first_buf));
+        let mut second = Box::pin(serial.clone().read(&mut second_buf));
+        let _ = poll_once(first.as_mut(), &first_waker);
+        let _ = poll_once(second.as_mut(), &second_waker);
+        drop(first);
+        mock.inject_rx(b"x");
+        serial.interrupt_handler();
+        report.check(
+            "drop takes out only its own",
+            first_waker.wakes() == 0
+                && second_waker.wakes() == 1
+                && poll_once(second.as_mut(), &second_waker).is_ready(),
+        );
+        drop(second);
+
+        // the oldest is pushed out, and woken to poll again
+        let wakers: Vec<_> = (0..=MAX_WAITERS)
+            .map(|_| Arc::new(CountingWaker::default()))
+            .collect();
+        let mut bufs = [[0u8; 1]; MAX_WAITERS + 1];
+        let mut reads: Vec<_> = bufs
+            .iter_mut()
+            .map(|buf| Box::pin(serial.clone().read(buf)))
+            .collect();
+        for (read, waker) in reads.iter_mut().zip(wakers.iter()) {
+            let _ = poll_once(read.as_mut(), waker);
+        }
+        report.check(
+            "one reader too many",
+            wakers[0].wakes() == 1 && wakers[1..].iter().all(|waker| waker.wakes() == 0),
+        );
+        drop(reads);
+        report.check("all taken out", !serial.has_read_waker());
+        report.exit_code()
+    }
+}
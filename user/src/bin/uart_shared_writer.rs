@@ -0,0 +1,215 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::{boxed::Box, format, sync::Arc};
+use core::{
+    future::{poll_fn, Future},
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed},
+    task::{Context, Poll, Waker},
+};
+use heapless::spsc::Queue;
+use lazy_static::*;
+use riscv::register::uie;
+use spin::Mutex;
+use user_lib::{
+    executor::{block_on_with, cross_hart_wakes, HartExecutors, IdleStrategy},
+    future::{traced, GetWakerFuture},
+    init_user_trap, irq, set_ext_int_enable,
+    sync::Mutex as AsyncMutex,
+    trace::FUTURE_SERIAL_WRITE,
+    user_uart::*,
+};
+
+#[cfg(feature = "board_qemu")]
+const UART_IRQN: u16 = 13;
+#[cfg(feature = "board_lrv")]
+const UART_IRQN: u16 = 5;
+const BAUD_RATE: usize = 115_200;
+const FRAME_NUM: usize = 8;
+const WRITER_NUM: usize = 2;
+const HART_NUM: usize = 2;
+/// Hart of the interrupt task, the PLIC is asked to deliver there too.
+const INTR_HART: usize = 0;
+
+static HAS_INTR: AtomicBool = AtomicBool::new(false);
+static WRITERS_DONE: AtomicUsize = AtomicUsize::new(0);
+/// Times a writer moved to another hart, see `migrate`.
+static HOPS: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static! {
+    static ref INTR_TASK_WAKER: Mutex<Option<Waker>> = Mutex::new(None);
+}
+
+type SharedWriter = Arc<AsyncMutex<Arc<AsyncSerial>>>;
+type BoxedTask = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Runs `task` on `hart` until it is woken, then hands it to the executor
+/// of the next hart as it is: the write pending, its waiter entry and the
+/// lock guard go along, and the next poll registers the new waker.
+fn migrate(execs: &'static HartExecutors, hart: usize, mut task: BoxedTask) {
+    execs.executor(hart).spawn(async move {
+        let mut polled = false;
+        let done = poll_fn(|cx| {
+            if polled {
+                // woken, the next poll is on the next hart
+                return Poll::Ready(false);
+            }
+            polled = true;
+            task.as_mut().poll(cx).map(|()| true)
+        })
+        .await;
+        if !done {
+            HOPS.fetch_add(1, Relaxed);
+            migrate(execs, (hart + 1) % execs.hart_num(), task);
+        }
+    });
+}
+
+/// Each frame is written in two halves with the lock held, so without the
+/// mutex the frames of the two writers would interleave on the wire.
+async fn writer_task(id: usize, writer: SharedWriter) {
+    for seq in 0..FRAME_NUM {
+        let header = format!("<{}:{}|", id, seq);
+        let payload = format!("frame from writer {}>\r\n", id);
+        let serial = writer.lock().await;
+        let sent = traced(FUTURE_SERIAL_WRITE, serial.clone().write(header.as_bytes())).await;
+        if sent.is_err() {
+            break;
+        }
+        let sent = traced(
+            FUTURE_SERIAL_WRITE,
+            serial.clone().write(payload.as_bytes()),
+        )
+        .await;
+        if sent.is_err() {
+            break;
+        }
+    }
+    WRITERS_DONE.fetch_add(1, Relaxed);
+}
+
+struct IntrHandlerFuture {
+    driver: Arc<AsyncSerial>,
+    irqn: u16,
+}
+
+impl Future for IntrHandlerFuture {
+    type Output = ();
+    fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if HAS_INTR.load(Relaxed) {
+            self.driver.interrupt_handler();
+            HAS_INTR.store(false, Relaxed);
+            irq::complete(self.irqn);
+        }
+        Poll::Pending
+    }
+}
+
+async fn intr_handler_task(serial: Arc<AsyncSerial>, irqn: u16) {
+    let raw_waker = GetWakerFuture.await;
+    INTR_TASK_WAKER.lock().replace(raw_waker);
+    IntrHandlerFuture {
+        driver: serial,
+        irqn,
+    }
+    .await
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    println!("[uart shared writer] two tasks sharing one AsyncSerial writer, moving between harts");
+    let init_res = init_user_trap();
+    let claim = match SerialClaim::builder(irq_to_serial_id(UART_IRQN))
+        .affinity(INTR_HART)
+        .claim()
+    {
+        Ok(claim) => claim,
+        Err(err) => {
+            println!("[uart shared writer] claim failed: {:?}", err);
+            return -1;
+        }
+    };
+
+    type RxBuffer = Queue<u8, DEFAULT_RX_BUFFER_SIZE>;
+    type TxBuffer = Queue<u8, DEFAULT_TX_BUFFER_SIZE>;
+    static mut DRIVER_RX_BUFFER: RxBuffer = RxBuffer::new();
+    static mut DRIVER_TX_BUFFER: TxBuffer = TxBuffer::new();
+    let (rx_pro, rx_con) = unsafe { DRIVER_RX_BUFFER.split() };
+    let (tx_pro, tx_con) = unsafe { DRIVER_TX_BUFFER.split() };
+    let serial = Arc::new(AsyncSerial::from_claim(
+        &claim, rx_pro, rx_con, tx_pro, tx_con,
+    ));
+    serial.hardware_init(BAUD_RATE);
+    let en_res = set_ext_int_enable(UART_IRQN as usize, 1);
+    println!(
+        "[uart shared writer] init result: {:#x}, irq affinity: {:?}, enable res: {:#x}",
+        init_res,
+        claim.affinity(),
+        en_res
+    );
+
+    let writer: SharedWriter = Arc::new(AsyncMutex::new(serial.clone()));
+    // writer `id` starts on hart `id` and moves on at every wake
+    let execs: &'static HartExecutors =
+        Box::leak(Box::new(HartExecutors::new(HART_NUM, IdleStrategy::Yield)));
+    execs
+        .executor(INTR_HART)
+        .spawn_high(intr_handler_task(serial.clone(), UART_IRQN));
+    for id in 0..WRITER_NUM {
+        migrate(
+            execs,
+            id % HART_NUM,
+            Box::pin(writer_task(id, writer.clone())),
+        );
+    }
+
+    unsafe {
+        uie::set_uext();
+        uie::set_usoft();
+    }
+    execs.run_until(|| WRITERS_DONE.load(Relaxed) == WRITER_NUM);
+    unsafe {
+        uie::clear_uext();
+        uie::clear_usoft();
+    }
+
+    // the external interrupt is off now, drive the trailer by polling
+    let pump_serial = serial.clone();
+    let _ = block_on_with(serial.clone().write(b"<done>\r\n"), || pump_serial.pump());
+    serial.remove_write();
+    INTR_TASK_WAKER.lock().take();
+    let stats = serial.stats();
+    println!(
+        "[uart shared writer] {} frames written, tx count: {}, cross hart wakes: {} (serial) {} (all)",
+        FRAME_NUM * WRITER_NUM,
+        stats.tx_count,
+        stats.cross_hart_wakes,
+        cross_hart_wakes()
+    );
+    println!(
+        "[uart shared writer] interrupts handled per hart: {:?}, {} hart switches",
+        stats.intr_harts, stats.intr_hart_switches
+    );
+    println!(
+        "[uart shared writer] writers moved between harts {} times",
+        HOPS.load(Relaxed)
+    );
+    0
+}
+
+#[no_mangle]
+pub fn ext_intr_handler(irq: u16, _is_from_kernel: bool) {
+    if irq == UART_IRQN && !HAS_INTR.load(Relaxed) {
+        HAS_INTR.store(true, Relaxed);
+        if let Some(guard) = INTR_TASK_WAKER.try_lock() {
+            if let Some(waker) = guard.as_ref() {
+                waker.wake_by_ref();
+            }
+        }
+    }
+}
@@ -0,0 +1,415 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::sync::Arc;
+use core::future::ready;
+use user_lib::executor::block_on;
+use user_lib::future::{select2, Either};
+use user_lib::user_uart::*;
+
+/// Streams a CRC-32 checked payload between two `AsyncSerial`s while the
+/// reader takes only a few bytes per period, slower than the line. With
+/// RTS/CTS or XON/XOFF flow control on, nothing may be lost however slow
+/// the reader. With the sender ignoring the receiver, the rx queue fills
+/// and the FIFO overflows.
+///
+/// Built with the `mock_uart` feature it runs on two `MockUart`s wired
+/// together, time counted in character times. Without it, it runs on the
+/// loopback pair, see `uart_loopback`, where the RTS/CTS credit can't be
+/// turned off, so only the cases with flow control on run.
+#[cfg(feature = "mock_uart")]
+#[no_mangle]
+pub fn main() -> i32 {
+    mock::run()
+}
+
+#[cfg(not(feature = "mock_uart"))]
+#[no_mangle]
+pub fn main() -> i32 {
+    ports::run()
+}
+
+const PAYLOAD_LEN: usize = 12 * 1024;
+/// The payload and its CRC.
+const STREAM_LEN: usize = PAYLOAD_LEN + 4;
+/// Bytes the reader takes every `READ_PERIOD` character times: a quarter
+/// of the line rate down to a sixteenth.
+const READ_CHUNKS: [usize; 3] = [16, 8, 4];
+const READ_PERIOD: usize = 64;
+/// Once everything was sent, character times without a byte before the
+/// rest counts as lost.
+const QUIET: usize = 8 * 1024;
+const SOFT_FLOW: SoftFlow = SoftFlow {
+    high_water: 2048,
+    low_water: 1024,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Flow {
+    /// RTS pulses of the receiver give the sender credit through its CTS.
+    Hardware,
+    /// XOFF at `SOFT_FLOW.high_water`, XON at its `low_water`.
+    Software,
+}
+
+/// Moves the bytes between the two ports.
+trait Link {
+    /// Lets one character time pass.
+    fn tick(&mut self);
+}
+
+/// CRC-32 (IEEE), bit by bit.
+fn crc32_update(crc: u32, byte: u8) -> u32 {
+    let mut crc = crc ^ byte as u32;
+    for _ in 0..8 {
+        let mask = (crc & 1).wrapping_neg();
+        crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+    }
+    crc
+}
+
+fn pattern(index: usize) -> u8 {
+    (index ^ (index >> 8) ^ (index >> 13)) as u8
+}
+
+/// The payload's CRC, as it goes out after it.
+fn trailer() -> [u8; 4] {
+    let crc = (0..PAYLOAD_LEN).fold(!0, |crc, i| crc32_update(crc, pattern(i)));
+    (!crc).to_le_bytes()
+}
+
+/// Checks the received stream without keeping it: the CRC of all but the
+/// last four bytes has to be those four.
+struct StreamCheck {
+    crc: u32,
+    tail: [u8; 4],
+    len: usize,
+}
+
+impl StreamCheck {
+    fn new() -> Self {
+        StreamCheck {
+            crc: !0,
+            tail: [0; 4],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        let at = self.len % 4;
+        if self.len >= 4 {
+            self.crc = crc32_update(self.crc, self.tail[at]);
+        }
+        self.tail[at] = byte;
+        self.len += 1;
+    }
+
+    fn ok(&self) -> bool {
+        let at = self.len % 4;
+        let trailer = [
+            self.tail[at],
+            self.tail[(at + 1) % 4],
+            self.tail[(at + 2) % 4],
+            self.tail[(at + 3) % 4],
+        ];
+        self.len == STREAM_LEN && u32::from_le_bytes(trailer) == !self.crc
+    }
+}
+
+struct Outcome {
+    received: usize,
+    crc_ok: bool,
+    overruns: usize,
+    rx_full_stops: usize,
+    timed_out: bool,
+}
+
+/// Sends the stream from `tx` to `rx`, reading `chunk` bytes every
+/// `READ_PERIOD` character times.
+fn run_case<R: UartRegisters>(
+    tx: &Arc<AsyncSerial<R>>,
+    rx: &Arc<AsyncSerial<R>>,
+    link: &mut impl Link,
+    chunk: usize,
+) -> Outcome {
+    let deadline = 2 * STREAM_LEN * READ_PERIOD / chunk + QUIET;
+    let trailer = trailer();
+    let mut out = [0u8; 64];
+    let mut buf = [0u8; 16];
+    let mut check = StreamCheck::new();
+    let mut sent = 0;
+    let mut quiet = 0;
+    let mut tick = 0;
+    while check.len < STREAM_LEN && quiet < QUIET && tick < deadline {
+        let end = STREAM_LEN.min(sent + out.len());
+        for (i, byte) in out[..end - sent].iter_mut().enumerate() {
+            let index = sent + i;
+            *byte = if index < PAYLOAD_LEN {
+                pattern(index)
+            } else {
+                trailer[index - PAYLOAD_LEN]
+            };
+        }
+        sent += tx.write_available(&out[..end - sent]);
+        link.tick();
+        tick += 1;
+        if sent == STREAM_LEN {
+            quiet += 1;
+        }
+        if tick % READ_PERIOD != 0 {
+            continue;
+        }
+        // one poll: takes what is there and turns the rx interrupt back on
+        let read = block_on(select2(
+            rx.clone().read_some_checked(&mut buf[..chunk]),
+            ready(()),
+        ));
+        if let Either::Left(Ok(len)) = read {
+            buf[..len].iter().for_each(|&byte| check.push(byte));
+            if len > 0 {
+                quiet = 0;
+            }
+        }
+    }
+    let stats = rx.stats();
+    Outcome {
+        received: check.len,
+        crc_ok: check.ok(),
+        overruns: stats.overrun_count,
+        rx_full_stops: stats.rx_full_stops,
+        timed_out: tick >= deadline,
+    }
+}
+
+/// Prints the case and whether it came out as it should: everything in
+/// with flow control on, bytes lost to overruns with it off.
+fn judge(flow: Flow, on: bool, chunk: usize, outcome: &Outcome) -> bool {
+    let ok = if on {
+        outcome.received == STREAM_LEN && outcome.crc_ok && outcome.overruns == 0
+    } else {
+        !outcome.crc_ok && outcome.overruns > 0
+    };
+    println!(
+        "[uart slow reader] {:?} flow {}, {} bytes per {}: received {} of {}{}, {} overruns, {} rx full stops{}: {}",
+        flow,
+        if on { "on" } else { "off" },
+        chunk,
+        READ_PERIOD,
+        outcome.received,
+        STREAM_LEN,
+        if outcome.crc_ok { ", CRC ok" } else { "" },
+        outcome.overruns,
+        outcome.rx_full_stops,
+        if outcome.timed_out { ", timed out" } else { "" },
+        if ok { "ok" } else { "FAILED" }
+    );
+    ok
+}
+
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use super::*;
+    use alloc::collections::VecDeque;
+    use user_lib::user_uart::regs::*;
+
+    type Serial = AsyncSerial<&'static MockUart>;
+
+    const BAUD_RATE: usize = 115_200;
+
+    /// The sender's tx FIFO goes out a byte per character time into the
+    /// receiver's rx FIFO, which loses what comes while it is full. The
+    /// XON and XOFF the other way go at once.
+    struct MockLink {
+        tx: Arc<Serial>,
+        rx: Arc<Serial>,
+        tx_mock: &'static MockUart,
+        rx_mock: &'static MockUart,
+        /// The receiver's RTS drives the sender's CTS, or else the sender
+        /// gets credit every character time.
+        rts_wired: bool,
+        /// What the sender's tx FIFO holds, oldest first.
+        tx_fifo: VecDeque<u8>,
+        rts_edges: usize,
+        cts: bool,
+    }
+
+    impl Link for MockLink {
+        fn tick(&mut self) {
+            let mut buf = [0u8; 32];
+            loop {
+                let len = self.tx_mock.drain_tx(&mut buf);
+                if len == 0 {
+                    break;
+                }
+                self.tx_fifo.extend(&buf[..len]);
+            }
+            self.tx_mock.shift_tx(1);
+            if self.tx_fifo.len() > self.tx_mock.tx_fifo_level() {
+                let byte = self.tx_fifo.pop_front().unwrap();
+                if self.rx_mock.rx_left() < FIFO_DEPTH {
+                    self.rx_mock.inject_rx(&[byte]);
+                } else {
+                    self.rx_mock.inject_line_error(LSR_OE);
+                }
+            }
+            loop {
+                let len = self.rx_mock.drain_tx(&mut buf);
+                if len == 0 {
+                    break;
+                }
+                self.tx_mock.inject_rx(&buf[..len]);
+            }
+            // nothing paces the way back
+            self.rx_mock.inject_modem_status(MSR_CTS | MSR_DCTS);
+            if self.rts_wired {
+                // one edge at a time, the sender reads MSR once per interrupt
+                let edges = self.rx_mock.rts_edges();
+                while self.rts_edges < edges {
+                    self.rts_edges += 1;
+                    self.cts = !self.cts;
+                    let cts = if self.cts { MSR_CTS } else { 0 };
+                    self.tx_mock.inject_modem_status(cts | MSR_DCTS);
+                    self.tx.interrupt_handler();
+                }
+            } else {
+                self.tx_mock.inject_modem_status(MSR_CTS | MSR_DCTS);
+            }
+            self.rx.interrupt_handler();
+            self.tx.interrupt_handler();
+        }
+    }
+
+    /// A driver on a fresh mock.
+    fn serial() -> (Arc<Serial>, &'static MockUart) {
+        let (mock, serial) = MockUart::async_serial(BAUD_RATE);
+        let serial = Arc::new(serial);
+        serial.interrupt_handler();
+        (serial, mock)
+    }
+
+    fn case(flow: Flow, on: bool, chunk: usize) -> bool {
+        let ((tx, tx_mock), (rx, rx_mock)) = (serial(), serial());
+        tx_mock.hold_tx(true);
+        if flow == Flow::Software {
+            // the receiver asks either way, the sender only listens if on
+            rx.set_soft_flow(Some(SOFT_FLOW)).unwrap();
+            if on {
+                tx.set_soft_flow(Some(SOFT_FLOW)).unwrap();
+            }
+        }
+        let mut link = MockLink {
+            tx: tx.clone(),
+            rx: rx.clone(),
+            tx_mock,
+            rx_mock,
+            rts_wired: flow == Flow::Hardware && on,
+            tx_fifo: VecDeque::new(),
+            rts_edges: rx_mock.rts_edges(),
+            // as the driver starts out
+            cts: true,
+        };
+        let outcome = run_case(&tx, &rx, &mut link, chunk);
+        judge(flow, on, chunk, &outcome)
+    }
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart slow reader");
+        let mut failed = 0;
+        for flow in [Flow::Hardware, Flow::Software] {
+            for on in [true, false] {
+                for chunk in READ_CHUNKS {
+                    if !case(flow, on, chunk) {
+                        failed += 1;
+                    }
+                }
+            }
+        }
+        report.check("every case", failed == 0);
+        report.exit_code()
+    }
+}
+
+#[cfg(not(feature = "mock_uart"))]
+mod ports {
+    use super::*;
+    use user_lib::init_user_trap;
+    use user_lib::timer::now_us;
+
+    // wired together as for `uart_loopback`
+    const TX_PORT: usize = 2;
+    const RX_PORT: usize = 3;
+    const BAUD_RATE: usize = 115_200;
+    const CHAR_US: usize = 10 * 1_000_000 / BAUD_RATE;
+
+    /// The wire is real, a tick only waits out a character time, serving
+    /// both ports meanwhile.
+    struct PortLink {
+        tx: Arc<AsyncSerial>,
+        rx: Arc<AsyncSerial>,
+        next_us: usize,
+    }
+
+    impl Link for PortLink {
+        fn tick(&mut self) {
+            while now_us() < self.next_us {
+                self.tx.pump();
+                self.rx.pump();
+            }
+            self.next_us += CHAR_US;
+        }
+    }
+
+    fn open(claim: &SerialClaim) -> Arc<AsyncSerial> {
+        // fresh queues for every case
+        SerialBuilder::new(claim.port())
+            .baud(BAUD_RATE)
+            .build_on(claim)
+            .unwrap()
+            .into_async()
+            .unwrap()
+    }
+
+    pub fn run() -> i32 {
+        init_user_trap();
+        let claims = (SerialClaim::claim(TX_PORT), SerialClaim::claim(RX_PORT));
+        let (tx_claim, rx_claim) = match claims {
+            (Ok(tx_claim), Ok(rx_claim)) => (tx_claim, rx_claim),
+            (tx_claim, rx_claim) => {
+                println!(
+                    "[uart slow reader] claim failed, port {}: {:?}, port {}: {:?}",
+                    TX_PORT,
+                    tx_claim.err(),
+                    RX_PORT,
+                    rx_claim.err()
+                );
+                return -1;
+            }
+        };
+        let mut passed = true;
+        for flow in [Flow::Hardware, Flow::Software] {
+            for chunk in READ_CHUNKS {
+                let (tx, rx) = (open(&tx_claim), open(&rx_claim));
+                if flow == Flow::Software {
+                    tx.set_soft_flow(Some(SOFT_FLOW)).unwrap();
+                    rx.set_soft_flow(Some(SOFT_FLOW)).unwrap();
+                }
+                let mut link = PortLink {
+                    tx: tx.clone(),
+                    rx: rx.clone(),
+                    next_us: now_us(),
+                };
+                let outcome = run_case(&tx, &rx, &mut link, chunk);
+                passed &= judge(flow, true, chunk, &outcome);
+            }
+        }
+        if passed {
+            0
+        } else {
+            -1
+        }
+    }
+}
@@ -0,0 +1,337 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::{sync::Arc, vec::Vec};
+use core::convert::TryInto;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed};
+use riscv::register::uie;
+use user_lib::{
+    executor::{Executor, IdleStrategy},
+    init_user_trap, irq, set_ext_int_enable,
+    timer::{now_us, sleep_us},
+    trace::{last_trace_events, TraceEvent},
+    user_uart::*,
+};
+
+// Wired together like in uart_loopback.
+const TX_PORT: usize = 2;
+const RX_PORT: usize = 3;
+#[cfg(feature = "board_qemu")]
+const BAUD_RATE: usize = 115_200;
+#[cfg(feature = "board_lrv")]
+const BAUD_RATE: usize = 1_500_000;
+const SOAK_TIME_US: usize = 4 * 3600 * 1_000_000;
+const REPORT_PERIOD_US: usize = 10 * 1_000_000;
+/// Frames handed to one `write`.
+const FRAMES_PER_WRITE: usize = 16;
+const TRACE_DUMP_EVENTS: usize = 32;
+
+/// Sync word, sequence number, payload, CRC-32 of sequence and payload.
+/// Numbers are little endian.
+const SYNC: [u8; 2] = [0xa5, 0x5a];
+const PAYLOAD_LEN: usize = 8;
+const FRAME_LEN: usize = SYNC.len() + 4 + PAYLOAD_LEN + 4;
+
+static DONE: AtomicBool = AtomicBool::new(false);
+
+// Receiver counters. usize is 64 bits here, none wraps in a soak.
+static FRAMES_SENT: AtomicUsize = AtomicUsize::new(0);
+static FRAMES_OK: AtomicUsize = AtomicUsize::new(0);
+static FRAMES_LOST: AtomicUsize = AtomicUsize::new(0);
+static GAPS: AtomicUsize = AtomicUsize::new(0);
+static OUT_OF_ORDER: AtomicUsize = AtomicUsize::new(0);
+static CRC_ERRORS: AtomicUsize = AtomicUsize::new(0);
+static SKIPPED_BYTES: AtomicUsize = AtomicUsize::new(0);
+static OVERRUNS: AtomicUsize = AtomicUsize::new(0);
+/// Gaps no overrun was reported before, the loss went unnoticed.
+static SILENT_GAPS: AtomicUsize = AtomicUsize::new(0);
+static LINE_ERRORS: AtomicUsize = AtomicUsize::new(0);
+
+const ZERO: AtomicUsize = AtomicUsize::new(0);
+/// IRQs of `TX_PORT` and `RX_PORT`.
+static IRQS: [AtomicUsize; 2] = [ZERO; 2];
+/// Per port, interrupts `ext_intr_handler` got forwarded by the kernel.
+static FORWARDED: [AtomicUsize; 2] = [ZERO; 2];
+/// Forwarded interrupts the kernel counted and the handler hasn't seen
+/// yet. The kernel leaves a forwarded source claimed until the handler
+/// completes it, so one at most.
+const IN_FLIGHT: usize = 1;
+
+/// CRC-32 (IEEE), bit by bit, the frames are short.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn encode_frame(seq: u32, frame: &mut [u8]) {
+    frame[..2].copy_from_slice(&SYNC);
+    frame[2..6].copy_from_slice(&seq.to_le_bytes());
+    for (i, byte) in frame[6..6 + PAYLOAD_LEN].iter_mut().enumerate() {
+        *byte = (seq as u8).wrapping_mul(13).wrapping_add(i as u8);
+    }
+    let crc = crc32(&frame[2..6 + PAYLOAD_LEN]);
+    frame[6 + PAYLOAD_LEN..].copy_from_slice(&crc.to_le_bytes());
+}
+
+/// The sequence number of a frame with a good sync word and CRC.
+fn decode_frame(frame: &[u8]) -> Option<u32> {
+    let crc = u32::from_le_bytes(frame[6 + PAYLOAD_LEN..].try_into().unwrap());
+    if frame[..2] != SYNC || crc32(&frame[2..6 + PAYLOAD_LEN]) != crc {
+        return None;
+    }
+    Some(u32::from_le_bytes(frame[2..6].try_into().unwrap()))
+}
+
+fn dump_trace() {
+    let mut events = [TraceEvent::default(); TRACE_DUMP_EVENTS];
+    let len = last_trace_events(&mut events);
+    if len == 0 {
+        println!("[uart soak]   no trace events, build with tracing on the LRV board");
+    }
+    for event in &events[..len] {
+        println!("[uart soak]   {:#012x} at {}", event.event_id, event.cycle);
+    }
+}
+
+async fn send_task(serial: Arc<AsyncSerial>) {
+    let mut frames = [0u8; FRAME_LEN * FRAMES_PER_WRITE];
+    let mut seq = 0u32;
+    while !DONE.load(Relaxed) {
+        for frame in frames.chunks_mut(FRAME_LEN) {
+            encode_frame(seq, frame);
+            seq = seq.wrapping_add(1);
+        }
+        if serial.clone().write(&frames).await.is_err() {
+            break;
+        }
+        FRAMES_SENT.fetch_add(FRAMES_PER_WRITE, Relaxed);
+    }
+}
+
+/// Validates what arrives a frame at a time. Bytes that don't make a good
+/// frame are skipped one by one until the frames line up again. An
+/// overrun drops the partial frame before it, and must come before every
+/// gap in the sequence numbers.
+async fn receive_task(serial: Arc<AsyncSerial>) {
+    let mut window: Vec<u8> = Vec::with_capacity(2 * FRAME_LEN);
+    let mut expected: Option<u32> = None;
+    let mut in_sync = true;
+    let mut overrun = false;
+    let mut chunk = [0u8; FRAME_LEN];
+    while !DONE.load(Relaxed) {
+        match serial.clone().read_some_checked(&mut chunk).await {
+            Ok(len) => window.extend_from_slice(&chunk[..len]),
+            Err(SerialError::Overrun) => {
+                SKIPPED_BYTES.fetch_add(window.len(), Relaxed);
+                window.clear();
+                // what follows starts mid frame
+                in_sync = false;
+                overrun = true;
+                continue;
+            }
+            Err(_) => break,
+        }
+        while window.len() >= FRAME_LEN {
+            let seq = match decode_frame(&window[..FRAME_LEN]) {
+                Some(seq) => seq,
+                None => {
+                    if in_sync {
+                        CRC_ERRORS.fetch_add(1, Relaxed);
+                        in_sync = false;
+                    }
+                    SKIPPED_BYTES.fetch_add(1, Relaxed);
+                    window.remove(0);
+                    continue;
+                }
+            };
+            window.drain(..FRAME_LEN);
+            in_sync = true;
+            FRAMES_OK.fetch_add(1, Relaxed);
+            // the sequence number wraps, compare the distance
+            let gap = expected.map_or(0, |expected| seq.wrapping_sub(expected));
+            if gap != 0 && gap < 1 << 31 {
+                FRAMES_LOST.fetch_add(gap as usize, Relaxed);
+                GAPS.fetch_add(1, Relaxed);
+                if !overrun {
+                    SILENT_GAPS.fetch_add(1, Relaxed);
+                }
+                println!(
+                    "[uart soak] gap: expected {:#x}, got {:#x}, {} frames lost",
+                    expected.unwrap(),
+                    seq,
+                    gap
+                );
+                dump_trace();
+            } else if gap != 0 {
+                OUT_OF_ORDER.fetch_add(1, Relaxed);
+            }
+            expected = Some(seq.wrapping_add(1));
+            overrun = false;
+        }
+    }
+}
+
+fn report(elapsed_us: usize, bus: &SerialEventBus) {
+    while let Some(event) = bus.try_next() {
+        match event.kind {
+            SerialEventKind::Overrun => OVERRUNS.fetch_add(1, Relaxed),
+            _ => LINE_ERRORS.fetch_add(1, Relaxed),
+        };
+    }
+    println!(
+        "[uart soak] {} s: sent {} ok {} lost {} in {} gaps ({} silent), out of order {}, crc errors {}, skipped {} bytes, overruns {}, line errors {}",
+        elapsed_us / 1_000_000,
+        FRAMES_SENT.load(Relaxed),
+        FRAMES_OK.load(Relaxed),
+        FRAMES_LOST.load(Relaxed),
+        GAPS.load(Relaxed),
+        SILENT_GAPS.load(Relaxed),
+        OUT_OF_ORDER.load(Relaxed),
+        CRC_ERRORS.load(Relaxed),
+        SKIPPED_BYTES.load(Relaxed),
+        OVERRUNS.load(Relaxed),
+        LINE_ERRORS.load(Relaxed)
+    );
+    for (i, &port) in [TX_PORT, RX_PORT].iter().enumerate() {
+        let serial = serial::get(port).unwrap();
+        println!("[uart soak]   port {}: {:?}", serial.port(), serial.stats());
+        println!(
+            "[uart soak]   port {}: kernel {:?}, forwarded handled {}, intr count {}",
+            port,
+            irq::kernel_stats(IRQS[i].load(Relaxed) as u16),
+            FORWARDED[i].load(Relaxed),
+            serial.intr_count.load(Relaxed)
+        );
+    }
+}
+
+/// Forwarded interrupts the kernel counted for port `i` that never reached
+/// `ext_intr_handler`, beyond the one that may still be queued.
+fn lost_forwards(i: usize) -> usize {
+    let delivered = irq::kernel_stats(IRQS[i].load(Relaxed) as u16).delivered;
+    let lost = delivered
+        .saturating_sub(FORWARDED[i].load(Relaxed))
+        .saturating_sub(IN_FLIGHT);
+    if lost > 0 {
+        println!(
+            "[uart soak] port {}: kernel forwarded {}, handler got {}",
+            [TX_PORT, RX_PORT][i],
+            delivered,
+            FORWARDED[i].load(Relaxed)
+        );
+    }
+    lost
+}
+
+async fn report_task(bus: Arc<SerialEventBus>) {
+    let mut elapsed = 0;
+    let mut last = now_us();
+    loop {
+        sleep_us(REPORT_PERIOD_US).await;
+        // the clock wraps every 2^16 s, count a period across the wrap
+        let now = now_us();
+        elapsed += now.checked_sub(last).unwrap_or(REPORT_PERIOD_US);
+        last = now;
+        report(elapsed, &bus);
+        if elapsed >= SOAK_TIME_US {
+            DONE.store(true, Relaxed);
+            break;
+        }
+    }
+}
+
+fn open(claim: &SerialClaim, baud_rate: usize) -> Arc<AsyncSerial> {
+    let serial = SerialBuilder::new(claim.port())
+        .baud(baud_rate)
+        .build_on(claim)
+        .unwrap();
+    serial.into_async().unwrap()
+}
+
+/// Streams sequence numbered frames from `TX_PORT` to `RX_PORT` for
+/// `SOAK_TIME_US`, reporting every `REPORT_PERIOD_US`. Exits nonzero if a
+/// frame was lost or corrupted, or an interrupt the kernel forwarded never
+/// reached the handler.
+#[no_mangle]
+pub fn main() -> i32 {
+    init_user_trap();
+    let (tx_claim, rx_claim) = match (SerialClaim::claim(TX_PORT), SerialClaim::claim(RX_PORT)) {
+        (Ok(tx_claim), Ok(rx_claim)) => (tx_claim, rx_claim),
+        (tx_claim, rx_claim) => {
+            println!(
+                "[uart soak] claim failed: {:?} {:?}",
+                tx_claim.err(),
+                rx_claim.err()
+            );
+            return -1;
+        }
+    };
+    let (tx, rx) = (open(&tx_claim, BAUD_RATE), open(&rx_claim, BAUD_RATE));
+    IRQS[0].store(tx_claim.irq() as usize, Relaxed);
+    IRQS[1].store(rx_claim.irq() as usize, Relaxed);
+    let bus = Arc::new(SerialEventBus::new());
+    rx.attach_event_bus(bus.clone());
+    serial::register(TX_PORT, tx.clone()).unwrap();
+    serial::register(RX_PORT, rx.clone()).unwrap();
+    println!(
+        "[uart soak] port {} -> port {} at {} baud for {} s",
+        TX_PORT,
+        RX_PORT,
+        BAUD_RATE,
+        SOAK_TIME_US / 1_000_000
+    );
+
+    let exec = Executor::new(IdleStrategy::Yield);
+    exec.spawn(receive_task(rx.clone()));
+    exec.spawn(send_task(tx.clone()));
+    exec.spawn(report_task(bus));
+    set_ext_int_enable(tx_claim.irq() as usize, 1);
+    set_ext_int_enable(rx_claim.irq() as usize, 1);
+    unsafe {
+        uie::set_uext();
+        uie::set_usoft();
+        uie::set_utimer();
+    }
+    exec.run_until(|| DONE.load(Relaxed));
+    unsafe {
+        uie::clear_uext();
+        uie::clear_usoft();
+        uie::clear_utimer();
+    }
+    tx.remove_write();
+    rx.remove_read();
+
+    let failures = FRAMES_LOST.load(Relaxed)
+        + OUT_OF_ORDER.load(Relaxed)
+        + CRC_ERRORS.load(Relaxed)
+        + OVERRUNS.load(Relaxed)
+        + lost_forwards(0)
+        + lost_forwards(1);
+    if failures == 0 && FRAMES_OK.load(Relaxed) > 0 {
+        0
+    } else {
+        -1
+    }
+}
+
+#[no_mangle]
+pub fn ext_intr_handler(irq: u16, is_from_kernel: bool) {
+    if is_from_kernel {
+        if let Some(i) = IRQS.iter().position(|i| i.load(Relaxed) == irq as usize) {
+            FORWARDED[i].fetch_add(1, Relaxed);
+        }
+    }
+    serial::dispatch(irq);
+    irq::complete(irq);
+}
@@ -0,0 +1,101 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+mock_uart_main!("uart soft flow", mock::run);
+
+/// Runs XON/XOFF flow control against a `MockUart` standing in for the
+/// peer: XOFF and XON sent at the watermarks, ahead of data held back,
+/// and the transmitter paused by the peer's XOFF until its XON.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use alloc::sync::Arc;
+    use user_lib::user_uart::*;
+
+    const BAUD_RATE: usize = 115_200;
+    const FLOW: SoftFlow = SoftFlow {
+        high_water: 16,
+        low_water: 4,
+    };
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart soft flow");
+        let (mock, serial) = MockUart::async_serial(BAUD_RATE);
+        let serial = Arc::new(serial);
+        serial.interrupt_handler();
+        let mut buf = [0u8; 64];
+        // what the peer gets after the next interrupt
+        let sent = || {
+            serial.interrupt_handler();
+            mock.take_tx()
+        };
+
+        report.check(
+            "watermarks checked",
+            serial.set_soft_flow(Some(SoftFlow {
+                high_water: 4,
+                low_water: 4,
+            })) == Err(SerialBuildError::InvalidWatermarks {
+                high_water: 4,
+                low_water: 4,
+            }),
+        );
+        serial.set_soft_flow(Some(FLOW)).unwrap();
+        mock.take_tx();
+
+        mock.inject_rx(&[b'x'; 20]);
+        report.check("XOFF at the high watermark", sent() == [XOFF]);
+        serial.read_available(&mut buf[..17]);
+        report.check("XON at the low watermark", sent() == [XON]);
+        serial.read_available(&mut buf);
+
+        mock.inject_rx(&[b'a', XOFF, b'b']);
+        sent();
+        let len = serial.read_available(&mut buf);
+        report.check(
+            "XOFF taken out of the data",
+            &buf[..len] == b"ab" && serial.tx_paused(),
+        );
+        serial.write_available(b"data");
+        report.check("paused by the peer", sent().is_empty() && !serial.flush(0));
+
+        mock.inject_rx(&[b'y'; 16]);
+        let xoff = sent();
+        serial.read_available(&mut buf);
+        let xon = sent();
+        report.check(
+            "XON and XOFF still go out while paused",
+            xoff == [XOFF] && xon == [XON],
+        );
+
+        mock.inject_rx(&[XON]);
+        let resumed = sent();
+        report.check(
+            "resumed by the peer",
+            resumed == b"data" && !serial.tx_paused(),
+        );
+        let stats = serial.stats().soft_flow;
+        report.check(
+            "counted",
+            (
+                stats.xoff_sent,
+                stats.xon_sent,
+                stats.xoff_received,
+                stats.xon_received,
+            ) == (2, 2, 1, 1),
+        );
+
+        serial.set_soft_flow(None).unwrap();
+        mock.inject_rx(&[XOFF, b'z']);
+        sent();
+        let len = serial.read_available(&mut buf);
+        report.check(
+            "off again",
+            buf[..len] == [XOFF, b'z'] && !serial.tx_paused(),
+        );
+        report.exit_code()
+    }
+}
@@ -0,0 +1,128 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+mock_uart_main!("uart static buffered", mock::run);
+
+/// Runs the same checks against `StaticBufferedSerial` and
+/// `HeapBufferedSerial` on a `MockUart`: tx through the THR empty
+/// interrupt, rx, a full rx buffer holding the rest in the FIFO, reads
+/// across the wrap and unknown IIDs.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use alloc::{format, vec::Vec};
+    use embedded_hal::serial::Read;
+    use user_lib::user_uart::regs::*;
+    use user_lib::user_uart::*;
+
+    const BAUD_RATE: usize = 115_200;
+    const IID_RS485: u8 = 0b0011;
+
+    fn suite<RX, TX>(
+        report: &mut MockReport,
+        storage: &str,
+        serial: &mut RingBufferedSerial<RX, TX, &'static MockUart>,
+        mock: &'static MockUart,
+    ) where
+        RX: AsRef<[u8]> + AsMut<[u8]>,
+        TX: AsRef<[u8]> + AsMut<[u8]>,
+    {
+        serial.init(BAUD_RATE);
+        report.check(
+            &format!("{} rx interrupt on", storage),
+            mock.read_ier() & IER_ERBFI != 0 && mock.read_ier() & IER_ETBEI == 0,
+        );
+
+        report.check(
+            &format!("{} queued", storage),
+            serial.write_nonblocking(b"hello") == 5,
+        );
+        serial.service();
+        report.check(
+            &format!("{} sent from the interrupt", storage),
+            mock.take_tx() == b"hello" && serial.flush_nonblocking(),
+        );
+        serial.service();
+        report.check(
+            &format!("{} THREI off once empty", storage),
+            mock.read_ier() & IER_ETBEI == 0,
+        );
+
+        let tx_capacity = serial.tx_capacity();
+        let data: Vec<u8> = (0..tx_capacity + 1).map(|i| i as u8).collect();
+        let queued = serial.write_nonblocking(&data);
+        while !serial.flush_nonblocking() {}
+        report.check(
+            &format!("{} tx buffer full", storage),
+            queued == tx_capacity && mock.take_tx() == data[..tx_capacity],
+        );
+
+        mock.inject_rx(b"abc");
+        serial.service();
+        let mut buf = [0u8; 8];
+        let len = serial.read_nonblocking(&mut buf);
+        report.check(&format!("{} received", storage), &buf[..len] == b"abc");
+
+        let rx_capacity = serial.rx_capacity();
+        let data: Vec<u8> = (0..rx_capacity + 3).map(|i| (i % 251) as u8).collect();
+        mock.inject_rx(&data);
+        serial.service();
+        report.check(
+            &format!("{} full buffer leaves the rest in the FIFO", storage),
+            serial.rx_len() == rx_capacity
+                && mock.rx_left() == 3
+                && mock.read_ier() & IER_ERBFI == 0,
+        );
+        // a partial consume makes room, and the ring wraps on refill
+        serial.consume(5);
+        serial.service();
+        report.check(
+            &format!("{} rx interrupt back on", storage),
+            mock.rx_left() == 0 && serial.rx_len() == rx_capacity - 2,
+        );
+        report.check(
+            &format!("{} slice ends at the wrap", storage),
+            serial.buffered_slice().len() == rx_capacity - 5
+                && serial.buffered_slice() == &data[5..rx_capacity],
+        );
+        let mut received = Vec::new();
+        let mut buf = [0u8; 64];
+        loop {
+            let len = serial.read_nonblocking(&mut buf);
+            if len == 0 {
+                break;
+            }
+            received.extend_from_slice(&buf[..len]);
+        }
+        report.check(
+            &format!("{} read across the wrap", storage),
+            received == data[5..],
+        );
+
+        mock.inject_iid(IID_RS485);
+        mock.inject_rx(b"x");
+        serial.service();
+        let stopped = serial.rx_len() == 0 && mock.rx_left() == 1;
+        serial.service();
+        report.check(
+            &format!("{} unknown IID ends the call", storage),
+            stopped && serial.try_read() == Ok(b'x'),
+        );
+    }
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart static buffered");
+        let mock = MockUart::new();
+        let mut serial = StaticBufferedSerial::<64, 32, _>::with_registers(mock);
+        suite(&mut report, "static", &mut serial, mock);
+
+        let mock = MockUart::new();
+        let mut serial = HeapBufferedSerial::with_registers(mock);
+        suite(&mut report, "heap", &mut serial, mock);
+
+        report.exit_code()
+    }
+}
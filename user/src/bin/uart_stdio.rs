@@ -0,0 +1,62 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::sync::Arc;
+use heapless::spsc::Queue;
+use riscv::register::uie;
+use user_lib::{console::getchar, init_user_trap, read, set_ext_int_enable, user_uart::*};
+
+/// Serial 2, with a terminal attached.
+const PORT: usize = 2;
+const BAUD_RATE: usize = 115_200;
+const LINE_LEN: usize = 64;
+
+/// Talk to the terminal on serial 2 with plain `print!` and `read`, then
+/// give the port back and fall back to the kernel console.
+#[no_mangle]
+pub fn main() -> i32 {
+    init_user_trap();
+    let claim = SerialClaim::claim(PORT).unwrap();
+    type RxBuffer = Queue<u8, DEFAULT_RX_BUFFER_SIZE>;
+    type TxBuffer = Queue<u8, DEFAULT_TX_BUFFER_SIZE>;
+    static mut DRIVER_RX_BUFFER: RxBuffer = RxBuffer::new();
+    static mut DRIVER_TX_BUFFER: TxBuffer = TxBuffer::new();
+    let (rx_pro, rx_con) = unsafe { DRIVER_RX_BUFFER.split() };
+    let (tx_pro, tx_con) = unsafe { DRIVER_TX_BUFFER.split() };
+    let serial = Arc::new(AsyncSerial::from_claim(
+        &claim, rx_pro, rx_con, tx_pro, tx_con,
+    ));
+    serial.hardware_init(BAUD_RATE);
+    set_ext_int_enable(claim.irq() as usize, 1);
+    unsafe {
+        uie::set_uext();
+    }
+
+    redirect_stdio(serial, StdioMode::Line);
+    print!("name? ");
+    let mut name = [0u8; LINE_LEN];
+    let len = read(0, &mut name) as usize;
+    let name = core::str::from_utf8(&name[..len]).unwrap_or("?").trim_end();
+    println!("hello, {}! press a key", name);
+    let serial = restore_stdio().unwrap();
+
+    redirect_stdio(serial, StdioMode::Raw);
+    let key = getchar();
+    println!("got {:#x}", key);
+
+    unsafe {
+        uie::clear_uext();
+    }
+    drop(claim);
+    println!(
+        "[uart stdio] back on the kernel console, name {:?}, key {:#x}, dropped {}",
+        name,
+        key,
+        stdio_dropped()
+    );
+    0
+}
@@ -0,0 +1,293 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+mock_uart_main!("uart stress", mock::run, argv);
+
+#[cfg(feature = "mock_uart")]
+#[no_mangle]
+pub fn timer_intr_handler(_time_us: usize) {
+    mock::on_timer();
+}
+
+/// Runs a reader and a writer task of `AsyncSerial` on a `MockUart` while
+/// the user timer, at random intervals, injects random bursts and calls
+/// the interrupt handler, so the handler lands at arbitrary points of the
+/// futures. The main loop calls the handler too now and then, for the
+/// timer to find the port being served. Checks that every byte arrives
+/// once and in order both ways, that the tasks finish, and that no waker
+/// is left registered.
+///
+/// `uart_stress [seed] [bytes]`: the seed is printed, pass it back to
+/// repeat a run.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use alloc::sync::Arc;
+    use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed};
+    use rand_core::{RngCore, SeedableRng};
+    use rand_xorshift::XorShiftRng;
+    use riscv::register::uie;
+    use spin::{Mutex, Once};
+    use user_lib::executor::{Executor, IdleStrategy};
+    use user_lib::set_timer;
+    use user_lib::timer::now_us;
+    use user_lib::user_uart::{regs::*, *};
+
+    type Serial = AsyncSerial<&'static MockUart>;
+
+    const BAUD_RATE: usize = 115_200;
+    /// Bytes each way unless given.
+    const DEFAULT_BYTES: usize = 1 << 20;
+    /// Past this the tasks count as stuck.
+    const DEADLINE_US: usize = 300_000_000;
+    /// Most bytes one timer interrupt injects.
+    const MAX_BURST: usize = 48;
+    /// Timer interrupts come `MIN_TICK_US` plus up to `TICK_SPREAD_US`
+    /// apart.
+    const MIN_TICK_US: usize = 20;
+    const TICK_SPREAD_US: usize = 200;
+    /// Bytes the mock holds each way. The handler injects no more and the
+    /// tasks drain the tx side well before, so it never allocates.
+    const RX_ROOM: usize = 2048;
+    const TX_ROOM: usize = 4096;
+    /// Most bytes a task reads or writes at once.
+    const MAX_CHUNK: usize = 64;
+    const NO_MISMATCH: usize = usize::MAX;
+
+    static SERIAL: Once<Arc<Serial>> = Once::new();
+    static MOCK: Once<&'static MockUart> = Once::new();
+    /// Only taken by the timer handler.
+    static INJECTOR: Mutex<Option<Injector>> = Mutex::new(None);
+    static TOTAL: AtomicUsize = AtomicUsize::new(0);
+    static DONE: AtomicBool = AtomicBool::new(false);
+    static IN_PUMP: AtomicBool = AtomicBool::new(false);
+    static TICKS: AtomicUsize = AtomicUsize::new(0);
+    static CONTENDED: AtomicUsize = AtomicUsize::new(0);
+    static RECEIVED: AtomicUsize = AtomicUsize::new(0);
+    static WRITTEN: AtomicUsize = AtomicUsize::new(0);
+    /// Stream position of the first wrong byte.
+    static RX_MISMATCH: AtomicUsize = AtomicUsize::new(NO_MISMATCH);
+    static TX_MISMATCH: AtomicUsize = AtomicUsize::new(NO_MISMATCH);
+
+    struct Injector {
+        rng: XorShiftRng,
+        next: usize,
+    }
+
+    /// Byte `i` of the rx stream. Not periodic at 256, so a slip by a few
+    /// bytes shows.
+    fn rx_pattern(i: usize) -> u8 {
+        (i ^ (i >> 8) ^ (i >> 16)) as u8
+    }
+
+    fn tx_pattern(i: usize) -> u8 {
+        rx_pattern(i).wrapping_mul(31).wrapping_add(7)
+    }
+
+    fn next_below(rng: &mut XorShiftRng, bound: usize) -> usize {
+        rng.next_u32() as usize % bound
+    }
+
+    fn arm(delay_us: usize) {
+        set_timer((now_us() + delay_us) as isize);
+    }
+
+    /// The second thread of control: bytes arrive and the IRQ is taken,
+    /// in between any two instructions of the tasks.
+    pub fn on_timer() {
+        let (serial, mock) = match (SERIAL.get(), MOCK.get()) {
+            (Some(serial), Some(mock)) => (serial, *mock),
+            _ => return,
+        };
+        TICKS.fetch_add(1, Relaxed);
+        if IN_PUMP.load(Relaxed) {
+            CONTENDED.fetch_add(1, Relaxed);
+        }
+        let delay = {
+            let mut injector = INJECTOR.lock();
+            let injector = match injector.as_mut() {
+                Some(injector) => injector,
+                None => return,
+            };
+            let room = RX_ROOM.saturating_sub(mock.rx_left());
+            let len = next_below(&mut injector.rng, MAX_BURST + 1)
+                .min(room)
+                .min(TOTAL.load(Relaxed) - injector.next);
+            let mut burst = [0u8; MAX_BURST];
+            for (i, byte) in burst[..len].iter_mut().enumerate() {
+                *byte = rx_pattern(injector.next + i);
+            }
+            mock.inject_rx(&burst[..len]);
+            injector.next += len;
+            // CTS credit for the next FIFO, as the peer would give it
+            mock.inject_modem_status(MSR_CTS | MSR_DCTS);
+            // now and then the IRQ is taken twice, or left to the next tick
+            match next_below(&mut injector.rng, 16) {
+                0 => {}
+                1 => {
+                    serial.interrupt_handler();
+                    serial.interrupt_handler();
+                }
+                _ => serial.interrupt_handler(),
+            }
+            MIN_TICK_US + next_below(&mut injector.rng, TICK_SPREAD_US)
+        };
+        if !DONE.load(Relaxed) {
+            arm(delay);
+        }
+    }
+
+    async fn reader(serial: Arc<Serial>, seed: u64) {
+        let mut rng = XorShiftRng::seed_from_u64(seed);
+        let total = TOTAL.load(Relaxed);
+        let mut received = 0;
+        let mut buf = [0u8; MAX_CHUNK];
+        while received < total && RX_MISMATCH.load(Relaxed) == NO_MISMATCH {
+            // whole reads and partial consumes of a chunk, in turns
+            let len = if rng.next_u32() % 2 == 0 {
+                let len = (1 + next_below(&mut rng, MAX_CHUNK)).min(total - received);
+                if serial.clone().read(&mut buf[..len]).await.is_err() {
+                    return;
+                }
+                len
+            } else {
+                let chunk = serial.fill_buf().await;
+                let len = (1 + next_below(&mut rng, chunk.len())).min(MAX_CHUNK);
+                buf[..len].copy_from_slice(&chunk[..len]);
+                chunk.consume(len);
+                len
+            };
+            if let Some(at) = (0..len).find(|&i| buf[i] != rx_pattern(received + i)) {
+                RX_MISMATCH.store(received + at, Relaxed);
+            }
+            received += len;
+            RECEIVED.store(received, Relaxed);
+        }
+    }
+
+    async fn writer(serial: Arc<Serial>, seed: u64) {
+        let mut rng = XorShiftRng::seed_from_u64(seed);
+        let total = TOTAL.load(Relaxed);
+        let mut written = 0;
+        let mut buf = [0u8; MAX_CHUNK];
+        while written < total {
+            let len = (1 + next_below(&mut rng, MAX_CHUNK)).min(total - written);
+            for (i, byte) in buf[..len].iter_mut().enumerate() {
+                *byte = tx_pattern(written + i);
+            }
+            if serial.clone().write(&buf[..len]).await.is_err() {
+                return;
+            }
+            written += len;
+            WRITTEN.store(written, Relaxed);
+        }
+    }
+
+    /// Checks what went out since the last call, `checked` counts on.
+    fn check_tx(mock: &MockUart, checked: &mut usize) {
+        let mut buf = [0u8; 256];
+        loop {
+            let len = mock.drain_tx(&mut buf);
+            if len == 0 {
+                break;
+            }
+            if let Some(at) = (0..len).find(|&i| buf[i] != tx_pattern(*checked + i)) {
+                let _ = TX_MISMATCH.compare_exchange(NO_MISMATCH, *checked + at, Relaxed, Relaxed);
+            }
+            *checked += len;
+        }
+    }
+
+    pub fn run(argv: &[&str]) -> i32 {
+        let seed = argv
+            .get(1)
+            .and_then(|arg| arg.parse().ok())
+            .unwrap_or(now_us() as u64);
+        let total = argv
+            .get(2)
+            .and_then(|arg| arg.parse().ok())
+            .unwrap_or(DEFAULT_BYTES);
+        println!("[uart stress] seed {}, {} bytes each way", seed, total);
+        TOTAL.store(total, Relaxed);
+
+        let (mock, serial) = MockUart::async_serial(BAUD_RATE);
+        mock.reserve(RX_ROOM, TX_ROOM);
+        let serial = Arc::new(serial);
+        let mut rng = XorShiftRng::seed_from_u64(seed);
+        *INJECTOR.lock() = Some(Injector {
+            rng: XorShiftRng::seed_from_u64(rng.next_u64()),
+            next: 0,
+        });
+        MOCK.call_once(|| mock);
+        SERIAL.call_once(|| serial.clone());
+
+        let executor = Executor::new(IdleStrategy::Spin);
+        executor.spawn(reader(serial.clone(), rng.next_u64()));
+        executor.spawn(writer(serial.clone(), rng.next_u64()));
+        arm(MIN_TICK_US);
+        unsafe {
+            uie::set_utimer();
+        }
+        let start = now_us();
+        let mut tx_checked = 0;
+        let mut stuck = false;
+        executor.run_until(|| {
+            if rng.next_u32() % 8 == 0 {
+                IN_PUMP.store(true, Relaxed);
+                serial.interrupt_handler();
+                IN_PUMP.store(false, Relaxed);
+            }
+            check_tx(mock, &mut tx_checked);
+            stuck = now_us() - start > DEADLINE_US;
+            let done = RECEIVED.load(Relaxed) >= total && tx_checked >= total;
+            let failed = RX_MISMATCH.load(Relaxed) != NO_MISMATCH
+                || TX_MISMATCH.load(Relaxed) != NO_MISMATCH;
+            done || failed || stuck
+        });
+        DONE.store(true, Relaxed);
+        unsafe {
+            uie::clear_utimer();
+        }
+
+        let stats = serial.stats();
+        println!(
+            "[uart stress] {} ticks, {} on a port being served, {} us",
+            TICKS.load(Relaxed),
+            CONTENDED.load(Relaxed),
+            now_us() - start
+        );
+        let mut report = MockReport::new("uart stress");
+        report.check("tasks finished", !stuck);
+        report.check(
+            "rx in order, none lost or repeated",
+            RX_MISMATCH.load(Relaxed) == NO_MISMATCH
+                && RECEIVED.load(Relaxed) == total
+                && stats.rx_count == total
+                && serial.rx_len() == 0,
+        );
+        report.check(
+            "tx in order, none lost or repeated",
+            TX_MISMATCH.load(Relaxed) == NO_MISMATCH
+                && WRITTEN.load(Relaxed) == total
+                && tx_checked == total,
+        );
+        report.check(
+            "no waker left",
+            !serial.has_read_waker() && !serial.has_write_waker(),
+        );
+        let code = report.exit_code();
+        if code != 0 {
+            println!(
+                "[uart stress] failed at rx {} / tx {}, rerun with `uart_stress {} {}`",
+                RECEIVED.load(Relaxed),
+                tx_checked,
+                seed,
+                total
+            );
+        }
+        code
+    }
+}
@@ -0,0 +1,118 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+mock_uart_main!("uart suspend", mock::run);
+
+/// Suspends drivers on a `MockUart`, has the mock lose its registers as a
+/// gated clock would, and resumes them. The configuration must come back,
+/// and a read and a write pending across the cycle must finish after it.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use alloc::{boxed::Box, sync::Arc, vec::Vec};
+    use embedded_hal::serial::Write;
+    use user_lib::user_uart::{regs::*, *};
+
+    const BAUD_RATE: usize = 115_200;
+
+    /// The registers `restore_config` programs, without the reset bits it
+    /// sets in FCR.
+    fn config_of(mock: &'static MockUart) -> (u16, u8, u8, u8) {
+        let fcr = mock.fcr() & !(FCR_RX_RESET | FCR_TX_RESET);
+        (mock.divisor(), mock.read_lcr(), fcr, mock.read_mcr())
+    }
+
+    fn async_cycle(report: &mut MockReport) {
+        let waker = Arc::new(CountingWaker::default());
+        let (mock, serial) = MockUart::async_serial(BAUD_RATE);
+        let serial = Arc::new(serial);
+        let _ = serial.set_fifo_trigger(FifoTrigger::Eight);
+        serial.interrupt_handler();
+        mock.take_tx();
+        let before = config_of(mock);
+
+        // more than the tx queue holds, so the write stays pending
+        let long: Vec<u8> = (0..DEFAULT_TX_BUFFER_SIZE + 100)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let mut write = Box::pin(serial.clone().write(&long));
+        let mut buf = [0u8; 4];
+        let mut read = Box::pin(serial.clone().read(&mut buf));
+        report.check(
+            "both pending",
+            poll_once(write.as_mut(), &waker).is_pending()
+                && poll_once(read.as_mut(), &waker).is_pending(),
+        );
+
+        report.check("suspend", serial.suspend() && serial.is_suspended());
+        report.check("interrupts off", mock.read_ier() == 0);
+        mock.power_off();
+        serial.pump();
+        report.check(
+            "registers left alone",
+            poll_once(write.as_mut(), &waker).is_pending()
+                && poll_once(read.as_mut(), &waker).is_pending()
+                && mock.read_ier() == 0
+                && mock.take_tx().is_empty(),
+        );
+
+        serial.resume();
+        report.check(
+            "configuration back",
+            !serial.is_suspended() && config_of(mock) == before,
+        );
+        report.check(
+            "rx and THR empty interrupts back",
+            mock.read_ier() & (IER_ERBFI | IER_ETBEI) == IER_ERBFI | IER_ETBEI,
+        );
+
+        let mut written = false;
+        for _ in 0..long.len() {
+            // the peer's CTS pulse hands back FIFO credit
+            mock.inject_modem_status(MSR_CTS | MSR_DCTS);
+            serial.interrupt_handler();
+            if poll_once(write.as_mut(), &waker).is_ready() {
+                written = true;
+                break;
+            }
+        }
+        drop(write);
+        serial.flush(EXIT_DRAIN_TIMEOUT_US);
+        report.check("write finishes", written && mock.take_tx() == long);
+
+        mock.inject_rx(b"wxyz");
+        serial.interrupt_handler();
+        report.check("read finishes", poll_once(read.as_mut(), &waker).is_ready());
+        drop(read);
+        report.check("with the bytes", &buf == b"wxyz");
+    }
+
+    fn polling_cycle(report: &mut MockReport) {
+        let mock = MockUart::new();
+        let mut serial = PollingSerial::with_registers(mock);
+        serial.hardware_init(BAUD_RATE);
+        let before = config_of(mock);
+        report.check("polling suspend", serial.suspend());
+        mock.power_off();
+        report.check(
+            "polling write blocks",
+            serial.try_write(b'a') == Err(nb::Error::WouldBlock),
+        );
+        serial.resume();
+        report.check(
+            "polling configuration back",
+            config_of(mock) == before && serial.try_write(b'b').is_ok(),
+        );
+        report.check("polling sends after", mock.take_tx() == b"b");
+    }
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart suspend");
+        async_cycle(&mut report);
+        polling_cycle(&mut report);
+        report.exit_code()
+    }
+}
@@ -0,0 +1,64 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::sync::Arc;
+use heapless::spsc::Queue;
+use user_lib::{executor::block_on_with, init_user_trap, timer::now_us, user_uart::*};
+
+const PORT: usize = 1;
+const BAUD_RATE: usize = 115_200;
+const BYTES_PER_TICK: usize = 16;
+const TICK_US: usize = 16_000;
+const BUCKET_SIZE: usize = 64;
+const MESSAGE_LEN: usize = 1024;
+
+/// Write 1 KiB through a ~1 KB/s throttle and check how long it took.
+#[no_mangle]
+pub fn main() -> i32 {
+    println!(
+        "[uart throttle] {} bytes at {} bytes / {} us",
+        MESSAGE_LEN, BYTES_PER_TICK, TICK_US
+    );
+    init_user_trap();
+    type RxBuffer = Queue<u8, DEFAULT_RX_BUFFER_SIZE>;
+    type TxBuffer = Queue<u8, DEFAULT_TX_BUFFER_SIZE>;
+    static mut DRIVER_RX_BUFFER: RxBuffer = RxBuffer::new();
+    static mut DRIVER_TX_BUFFER: TxBuffer = TxBuffer::new();
+    let (rx_pro, rx_con) = unsafe { DRIVER_RX_BUFFER.split() };
+    let (tx_pro, tx_con) = unsafe { DRIVER_TX_BUFFER.split() };
+    let claim = match SerialClaim::claim(PORT) {
+        Ok(claim) => claim,
+        Err(err) => {
+            println!("[uart throttle] claim port {} failed: {:?}", PORT, err);
+            return -1;
+        }
+    };
+    let serial = Arc::new(AsyncSerial::from_claim(
+        &claim, rx_pro, rx_con, tx_pro, tx_con,
+    ));
+    serial.hardware_init(BAUD_RATE);
+
+    let throttle = Throttle::new(serial.clone(), BYTES_PER_TICK, TICK_US, BUCKET_SIZE);
+    let message = [b'.'; MESSAGE_LEN];
+    let start = now_us();
+    let _ = block_on_with(throttle.write(&message), || serial.pump());
+    let elapsed = now_us() - start;
+    // the first bucket goes out at once, the rest is paced
+    let expected = (MESSAGE_LEN - BUCKET_SIZE) / BYTES_PER_TICK * TICK_US;
+    println!(
+        "\r\n[uart throttle] took {} us, expected at least {} us, bucket level {}",
+        elapsed,
+        expected,
+        throttle.level()
+    );
+    serial.remove_write();
+    if elapsed + TICK_US >= expected {
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,184 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+mock_uart_main!("uart trace golden", mock::run);
+
+/// Runs a scripted scenario through `AsyncSerial` on a `MockUart` with
+/// the trace captured: rx interrupts, tx refills, an overrun and a modem
+/// status change. Checks that every `SERIAL_INTR_ENTER` has its
+/// `SERIAL_INTR_EXIT` with the same IID, that the interrupts come in the
+/// scripted order with their events inside them, and that no event is
+/// one the host tools do not decode.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use alloc::sync::Arc;
+    use alloc::vec::Vec;
+    use user_lib::trace::*;
+    use user_lib::user_uart::{regs::*, *};
+
+    const BAUD_RATE: usize = 115_200;
+    const RX_INTERRUPTS: usize = 3;
+    const TX_REFILLS: usize = 3;
+    /// Bytes of each refill. All refills fit the CTS credit of one FIFO.
+    const REFILL_LEN: usize = 5;
+
+    /// The events as `trace/event_def.py` decodes them, group and
+    /// sub-event. Moving a code breaks the host tools, change both.
+    const GOLDEN: &[(&str, usize, usize)] = &[
+        ("SERIAL_INTR_ENTER", SERIAL_INTR_ENTER, 0x5e1a_0000),
+        ("SERIAL_INTR_EXIT", SERIAL_INTR_EXIT, 0x5e1a_1000),
+        ("SERIAL_CALL_ENTER", SERIAL_CALL_ENTER, 0x5e1a_2000),
+        ("SERIAL_CALL_EXIT", SERIAL_CALL_EXIT, 0x5e1a_3000),
+        ("SERIAL_TEST_ENTER", SERIAL_TEST_ENTER, 0x5e1a_4000),
+        ("SERIAL_TEST_EXIT", SERIAL_TEST_EXIT, 0x5e1a_5000),
+        ("SERIAL_RTS", SERIAL_RTS, 0x5e1a_6000),
+        ("SERIAL_CTS", SERIAL_CTS, 0x5e1a_7000),
+        ("SERIAL_TX", SERIAL_TX, 0x5e1a_8000),
+        ("SERIAL_RX", SERIAL_RX, 0x5e1a_9000),
+        ("SERIAL_WATCHDOG", SERIAL_WATCHDOG, 0x5e1a_a000),
+        ("SERIAL_RX_DATA", SERIAL_RX_DATA, 0x5e1a_b000),
+        ("SERIAL_RX_TIMEOUT", SERIAL_RX_TIMEOUT, 0x5e1a_c000),
+        ("SERIAL_OVERRUN", SERIAL_OVERRUN, 0x5e1a_d000),
+        ("SERIAL_INTR_BAILOUT", SERIAL_INTR_BAILOUT, 0x5e1a_e000),
+        ("SERIAL_DROP", SERIAL_DROP, 0x5e1a_f000),
+        ("SERIAL_RX_HOOK_ENTER", SERIAL_RX_HOOK_ENTER, 0x5e1b_0000),
+        ("SERIAL_RX_HOOK_EXIT", SERIAL_RX_HOOK_EXIT, 0x5e1b_1000),
+        (
+            "SERIAL_INTR_OVER_BUDGET",
+            SERIAL_INTR_OVER_BUDGET,
+            0x5e1b_2000,
+        ),
+        ("ASYNC_READ_WAKE", ASYNC_READ_WAKE, 0xa57c_2000),
+        ("ASYNC_WRITE_WAKE", ASYNC_WRITE_WAKE, 0xa57c_5000),
+        // the driver's warnings go out by syscall
+        ("TRACE_SYSCALL_ENTER", TRACE_SYSCALL_ENTER, 0x575c_0000),
+        ("TRACE_SYSCALL_EXIT", TRACE_SYSCALL_EXIT, 0x575c_1000),
+    ];
+
+    /// Group and sub-event, the argument in bits 11:0 masked off.
+    fn code(event: usize) -> usize {
+        event & !0xfff
+    }
+
+    /// The IID of the interrupt an event has to come inside of, if any.
+    fn served_in(code: usize) -> Option<u8> {
+        match code {
+            SERIAL_RX | SERIAL_RX_DATA => Some(IID_RX_DATA),
+            SERIAL_TX => Some(IID_THR_EMPTY),
+            SERIAL_OVERRUN => Some(IID_LINE_STATUS),
+            _ => None,
+        }
+    }
+
+    /// What a walk over the capture found.
+    struct Walk {
+        /// The IIDs of the enter events, in order.
+        iids: Vec<u8>,
+        paired: bool,
+        placed: bool,
+        unknown: Vec<usize>,
+    }
+
+    fn walk(events: &[usize]) -> Walk {
+        let mut walk = Walk {
+            iids: Vec::new(),
+            paired: true,
+            placed: true,
+            unknown: Vec::new(),
+        };
+        let mut open = None;
+        for &event in events {
+            let iid = (event & 0xfff) as u8;
+            match code(event) {
+                SERIAL_INTR_ENTER => {
+                    walk.paired &= open.is_none() && event & 0xff0 == 0;
+                    walk.iids.push(iid);
+                    open = Some(iid);
+                }
+                SERIAL_INTR_EXIT => {
+                    walk.paired &= open == Some(iid);
+                    open = None;
+                }
+                other if !GOLDEN.iter().any(|&(_, golden, _)| golden == other) => {
+                    walk.unknown.push(event)
+                }
+                other => {
+                    if let Some(iid) = served_in(other) {
+                        walk.placed &= open == Some(iid);
+                    }
+                }
+            }
+        }
+        walk.paired &= open.is_none();
+        walk
+    }
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart trace golden");
+        let (mock, serial) = MockUart::async_serial(BAUD_RATE);
+        let serial = Arc::new(serial);
+        // whatever enabling the interrupts raised
+        serial.interrupt_handler();
+        mock.take_tx();
+
+        let moved: Vec<&str> = GOLDEN
+            .iter()
+            .filter(|&&(_, code, golden)| code != golden)
+            .map(|&(name, ..)| name)
+            .collect();
+        for name in &moved {
+            println!("[uart trace golden] {} moved", name);
+        }
+        report.check(
+            "event codes as the host tools decode them",
+            moved.is_empty(),
+        );
+
+        start_capture();
+        for i in 0..RX_INTERRUPTS {
+            mock.inject_rx(&[b'a' + i as u8; 4]);
+            serial.interrupt_handler();
+        }
+        for i in 0..TX_REFILLS {
+            serial.write_available(&[b'0' + i as u8; REFILL_LEN]);
+            serial.interrupt_handler();
+        }
+        mock.inject_line_error(LSR_OE);
+        serial.interrupt_handler();
+        mock.inject_modem_status(MSR_DSR | MSR_DDSR);
+        serial.interrupt_handler();
+        let events = stop_capture();
+
+        report.check("capture kept every event", events.is_some());
+        let events = events.unwrap_or_default();
+        let walk = walk(&events);
+        let mut expected = Vec::new();
+        expected.extend([IID_RX_DATA; RX_INTERRUPTS]);
+        expected.extend([IID_THR_EMPTY; TX_REFILLS]);
+        expected.push(IID_LINE_STATUS);
+        expected.push(IID_MODEM_STATUS);
+        report.check("enter and exit paired, same IID", walk.paired);
+        report.check("interrupts in the scripted order", walk.iids == expected);
+        report.check("events inside their interrupt", walk.placed);
+        report.check("no unknown codes", walk.unknown.is_empty());
+        report.check("no bailout", serial.stats().irq_loop_bailouts == 0);
+        report.check(
+            "the scenario went through",
+            serial.rx_len() == 4 * RX_INTERRUPTS
+                && mock.take_tx().len() == REFILL_LEN * TX_REFILLS
+                && serial.stats().overrun_count == 1,
+        );
+
+        let code = report.exit_code();
+        if code != 0 {
+            for event in &events {
+                println!("[uart trace golden] {:#010x}", event);
+            }
+        }
+        code
+    }
+}
@@ -0,0 +1,117 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+mock_uart_main!("uart tx coalesce", mock::run);
+
+/// Runs tx interrupt coalescing on a `MockUart` with the user timer
+/// interrupt on: small writes wait with THREI off until the timer turns
+/// it on, a FIFO's worth turns it on at once, and `flush` does not wait.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use alloc::sync::Arc;
+    use riscv::register::uie;
+    use user_lib::init_user_trap;
+    use user_lib::timer::now_us;
+    use user_lib::user_uart::{regs::*, *};
+
+    const BAUD_RATE: usize = 115_200;
+    const DELAY_US: usize = 300;
+    const WAIT_US: usize = 100_000;
+    const FLUSH_TIMEOUT_US: usize = 10_000;
+
+    /// Spins until `done` or `WAIT_US` passed, returns `done()`.
+    fn wait(done: impl Fn() -> bool) -> bool {
+        let start = now_us();
+        while !done() && now_us() - start < WAIT_US {}
+        done()
+    }
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart tx coalesce");
+        init_user_trap();
+        let (mock, serial) = MockUart::async_serial(BAUD_RATE);
+        let serial = Arc::new(serial);
+        serial.interrupt_handler();
+        let thrie_on = || mock.read_ier() & IER_ETBEI != 0;
+        // CTS credit for the next FIFO
+        let credit = || {
+            mock.inject_modem_status(MSR_CTS | MSR_DCTS);
+            serial.interrupt_handler();
+        };
+
+        report.check(
+            "delays of 0 and over the bound refused",
+            serial.set_tx_coalescing(Some(0)).is_err()
+                && serial.set_tx_coalescing(Some(MAX_TX_DELAY_US + 1))
+                    == Err(SerialBuildError::InvalidCoalescing {
+                        delay_us: MAX_TX_DELAY_US + 1,
+                        max_delay_us: MAX_TX_DELAY_US,
+                    })
+                && serial.tx_coalescing().is_none(),
+        );
+        report.check(
+            "coalescing on",
+            serial.set_tx_coalescing(Some(DELAY_US)).is_ok()
+                && serial.tx_coalescing() == Some(DELAY_US),
+        );
+
+        unsafe {
+            uie::set_utimer();
+        }
+        AsyncSerial::reset_stats(&serial);
+        serial.write_available(b"abc");
+        serial.write_available(b"defg");
+        serial.interrupt_handler();
+        report.check(
+            "small writes wait with THREI off",
+            !thrie_on() && mock.take_tx().is_empty(),
+        );
+        report.check("timer turns THREI on", wait(thrie_on));
+        serial.interrupt_handler();
+        report.check(
+            "sent together",
+            mock.take_tx() == b"abcdefg" && serial.stats().tx_coalesce_count == 1,
+        );
+
+        credit();
+        serial.write_available(b"0123");
+        serial.write_available(b"456789abcdef");
+        report.check("a FIFO's worth does not wait", thrie_on());
+        serial.interrupt_handler();
+        report.check(
+            "sent without the timer",
+            mock.take_tx() == b"0123456789abcdef" && serial.stats().tx_coalesce_count == 1,
+        );
+
+        credit();
+        serial.write_available(b"xyz");
+        let held = !thrie_on();
+        report.check(
+            "flush does not wait for the timer",
+            held && serial.flush(FLUSH_TIMEOUT_US) && mock.take_tx() == b"xyz",
+        );
+
+        report.check("coalescing off", serial.set_tx_coalescing(None).is_ok());
+        credit();
+        serial.write_available(b"!");
+        report.check("a write turns THREI on at once", thrie_on());
+        serial.interrupt_handler();
+        report.check("sent", mock.take_tx() == b"!");
+        unsafe {
+            uie::clear_utimer();
+        }
+
+        let stats = serial.stats();
+        println!(
+            "[uart tx coalesce] {} tx interrupts, {} timer expiries, {} per KiB",
+            stats.tx_intr_count,
+            stats.tx_coalesce_count,
+            stats.tx_intr_per_kb()
+        );
+        report.exit_code()
+    }
+}
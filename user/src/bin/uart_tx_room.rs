@@ -0,0 +1,70 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+mock_uart_main!("uart tx room", mock::run);
+
+/// Takes a THR empty interrupt with the tx FIFO of a `MockUart` part
+/// full, as the LRV part can report it, and checks the refill fits what
+/// is left: a single byte without a FIFO level register, the free room
+/// with one.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use alloc::vec::Vec;
+    use user_lib::user_uart::{regs::*, *};
+
+    const BAUD_RATE: usize = 115_200;
+    /// Bytes already in the tx FIFO when the interrupt is taken.
+    const FILLED: usize = 10;
+
+    /// Returns the bytes sent by the first refill, and whether all of
+    /// `data` then went out in order without a lost write.
+    fn refill(level_register: bool, data: &[u8]) -> (usize, bool) {
+        let (mock, serial) = MockUart::async_serial(BAUD_RATE);
+        serial.interrupt_handler();
+
+        mock.hold_tx(true);
+        mock.set_tx_level_register(level_register);
+        for _ in 0..FILLED {
+            mock.write_thr(b'.');
+        }
+        mock.take_tx();
+        serial.write_available(data);
+        mock.inject_iid(IID_THR_EMPTY);
+        serial.interrupt_handler();
+        let mut sent: Vec<u8> = mock.take_tx();
+        let first = sent.len();
+        for _ in 0..data.len() {
+            if sent.len() == data.len() {
+                break;
+            }
+            mock.shift_tx(FIFO_DEPTH);
+            // CTS credit for the next FIFO
+            mock.inject_modem_status(MSR_CTS | MSR_DCTS);
+            serial.interrupt_handler();
+            sent.extend(mock.take_tx());
+        }
+        let ok = mock.tx_overruns() == 0 && sent == data;
+        // nothing will take the rest
+        core::mem::forget(serial);
+        (first, ok)
+    }
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart tx room");
+        let data: [u8; 40] = core::array::from_fn(|i| b'a' + i as u8 % 26);
+        let (first, ok) = refill(false, &data);
+        report.check("no level register: a single byte", first == 1);
+        report.check("no level register: no overrun, in order", ok);
+        let (first, ok) = refill(true, &data);
+        report.check(
+            "level register: the free room",
+            first == FIFO_DEPTH - FILLED,
+        );
+        report.check("level register: no overrun, in order", ok);
+        report.exit_code()
+    }
+}
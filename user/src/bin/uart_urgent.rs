@@ -0,0 +1,97 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+mock_uart_main!("uart urgent", mock::run);
+
+/// Sends urgent bytes on a `MockUart` with the tx queue backed up: ahead
+/// of the queue, a full lane, ahead of the data a CTS edge lets go, and
+/// behind an XOFF of flow control.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use alloc::sync::Arc;
+    use alloc::vec::Vec;
+    use user_lib::user_uart::{regs::*, *};
+
+    const BAUD_RATE: usize = 115_200;
+    const NAK: u8 = 0x15;
+    const CAN: u8 = 0x18;
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart urgent");
+        let (mock, serial) = MockUart::async_serial(BAUD_RATE);
+        let serial = Arc::new(serial);
+        serial.interrupt_handler();
+        mock.take_tx();
+        let fifo_depth = serial.fifo_depth();
+        // what the peer gets after the next interrupt, also kept in `all`
+        let mut all = Vec::new();
+        let mut sent = || {
+            serial.interrupt_handler();
+            let tx = mock.take_tx();
+            all.extend_from_slice(&tx);
+            tx
+        };
+
+        let queued = serial.write_available(&[b'd'; 64]);
+        let urgent = serial.write_urgent(NAK);
+        let first = sent();
+        report.check(
+            "ahead of the queue",
+            urgent.is_ok()
+                && first.len() == fifo_depth
+                && first[0] == NAK
+                && first[1..].iter().all(|&ch| ch == b'd'),
+        );
+
+        // the FIFO counts as full until CTS says the peer took some
+        let mut fills = 0;
+        while serial.write_urgent(CAN).is_ok() {
+            fills += 1;
+        }
+        report.check(
+            "lane holds URGENT_LANE_LEN",
+            fills == URGENT_LANE_LEN && serial.write_urgent(CAN) == Err(Busy),
+        );
+        mock.inject_modem_status(MSR_CTS | MSR_DCTS);
+        let next = sent();
+        report.check(
+            "first after a CTS edge",
+            next.len() > URGENT_LANE_LEN
+                && next[..URGENT_LANE_LEN].iter().all(|&ch| ch == CAN)
+                && next[URGENT_LANE_LEN..].iter().all(|&ch| ch == b'd'),
+        );
+
+        serial
+            .set_soft_flow(Some(SoftFlow {
+                high_water: 8,
+                low_water: 2,
+            }))
+            .unwrap();
+        serial.write_urgent(NAK).unwrap();
+        mock.inject_rx(&[b'x'; 8]);
+        mock.inject_modem_status(MSR_DCTS);
+        let next = sent();
+        report.check(
+            "behind an XOFF",
+            next.len() >= 2 && next[..2] == [XOFF, NAK],
+        );
+
+        serial.write_urgent(NAK).unwrap();
+        let flushed = serial.flush(100_000);
+        let rest = mock.take_tx();
+        report.check(
+            "flush sends it first",
+            flushed && rest.first() == Some(&NAK),
+        );
+        all.extend_from_slice(&rest);
+        report.check(
+            "queue unharmed",
+            all.iter().filter(|&&ch| ch == b'd').count() == queued,
+        );
+        report.exit_code()
+    }
+}
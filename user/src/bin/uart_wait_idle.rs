@@ -0,0 +1,82 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+mock_uart_main!("uart wait idle", mock::run);
+
+/// Waits for a quiet line on a `MockUart`: one quiet from the start, one
+/// with bytes streaming in that nobody drains, and one after a character
+/// timeout.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use alloc::sync::Arc;
+    use user_lib::executor::block_on_with;
+    use user_lib::timer::now_us;
+    use user_lib::user_uart::{regs::*, *};
+
+    const BAUD_RATE: usize = 115_200;
+    const CHAR_US: usize = 10_000_000 / BAUD_RATE;
+    /// Modbus RTU's 3.5, rounded up.
+    const IDLE_CHARS: u32 = 4;
+    const WINDOW_US: usize = IDLE_CHARS as usize * CHAR_US;
+    /// Closer together than the window, so the line never goes idle.
+    const STREAM_GAP_US: usize = WINDOW_US / 2;
+    const STREAM_LEN: usize = 20;
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart wait idle");
+        let (mock, serial) = MockUart::async_serial(BAUD_RATE);
+        let serial = Arc::new(serial);
+        serial.interrupt_handler();
+
+        let start = now_us();
+        block_on_with(serial.wait_idle(IDLE_CHARS), || {});
+        report.check("quiet line", now_us() - start < WINDOW_US);
+
+        // bytes left in the FIFO as if below the trigger level, the wait
+        // has to find them itself
+        let start = now_us();
+        let mut sent = 0;
+        let mut last_sent = start;
+        block_on_with(serial.wait_idle(IDLE_CHARS), || {
+            if sent < STREAM_LEN && now_us() >= start + sent * STREAM_GAP_US {
+                mock.inject_rx(&[sent as u8]);
+                last_sent = now_us();
+                sent += 1;
+            }
+        });
+        let quiet = now_us() - last_sent;
+        report.check(
+            "window starts over with every byte",
+            sent == STREAM_LEN
+                && mock.rx_left() == 0
+                && quiet >= WINDOW_US
+                && quiet < 3 * WINDOW_US,
+        );
+        let mut buf = [0u8; STREAM_LEN];
+        report.check(
+            "streamed bytes kept",
+            serial.read_available(&mut buf) == STREAM_LEN
+                && buf.iter().enumerate().all(|(i, &byte)| byte == i as u8),
+        );
+
+        // a character timeout comes four character times after the last
+        // byte, so that much of the window is over already
+        mock.inject_rx(b"tail");
+        mock.inject_iid(IID_CHAR_TIMEOUT);
+        serial.interrupt_handler();
+        let start = now_us();
+        block_on_with(serial.wait_idle(IDLE_CHARS), || {});
+        let timed_out = now_us() - start;
+        block_on_with(serial.wait_idle(2 * IDLE_CHARS), || {});
+        let longer = now_us() - start;
+        report.check(
+            "character timeout counts as quiet",
+            timed_out < WINDOW_US / 2 && longer >= WINDOW_US / 2 && longer < 2 * WINDOW_US,
+        );
+        report.exit_code()
+    }
+}
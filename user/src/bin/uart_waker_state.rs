@@ -0,0 +1,93 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+mock_uart_main!("uart waker state", mock::run);
+
+/// Follows the read waker state of an `AsyncSerial` on a `MockUart`
+/// through a wait, a wake and the poll after it. Checks that a woken task
+/// that has not polled yet reads as `Woken`, not as nobody waiting, that
+/// the bytes after that wake nobody again, and that every transition and
+/// wake with nobody registered is counted, and that another task
+/// registering while the woken one has not polled is no repoll.
+#[cfg(feature = "mock_uart")]
+mod mock {
+    use alloc::{boxed::Box, sync::Arc};
+    use user_lib::user_uart::*;
+
+    const BAUD_RATE: usize = 115_200;
+
+    pub fn run() -> i32 {
+        let mut report = MockReport::new("uart waker state");
+        let (mock, serial) = MockUart::async_serial(BAUD_RATE);
+        serial.interrupt_handler();
+        serial.reset_stats();
+        let waker = Arc::new(CountingWaker::default());
+        let mut buf = [0u8; 8];
+
+        report.check(
+            "nobody waits",
+            serial.read_waker_state() == WakerState::Empty,
+        );
+        let mut readable = Box::pin(serial.readable());
+        report.check(
+            "registered",
+            poll_once(readable.as_mut(), &waker).is_pending()
+                && serial.read_waker_state() == WakerState::Registered,
+        );
+        mock.inject_rx(b"ab");
+        serial.interrupt_handler();
+        report.check(
+            "woken, not yet polled",
+            waker.wakes() == 1
+                && !serial.has_read_waker()
+                && serial.read_waker_state() == WakerState::Woken,
+        );
+        mock.inject_rx(b"cd");
+        serial.interrupt_handler();
+        report.check(
+            "no second wake",
+            waker.wakes() == 1 && serial.read_waker_state() == WakerState::Woken,
+        );
+        report.check(
+            "polled, nobody waits",
+            poll_once(readable.as_mut(), &waker).is_ready()
+                && serial.read_waker_state() == WakerState::Empty,
+        );
+        drop(readable);
+        serial.read_available(&mut buf);
+
+        mock.inject_rx(b"ef");
+        serial.interrupt_handler();
+        let stats = serial.stats().read_waker;
+        report.check(
+            "transitions counted",
+            stats.registered == 1
+                && stats.woken == 1
+                && stats.repolled == 1
+                && stats.left == 1
+                && stats.redundant_wakes == 1
+                && stats.idle_wakes == 1,
+        );
+        serial.read_available(&mut buf);
+
+        // woken, but another task comes first
+        serial.reset_stats();
+        let mut first = Box::pin(serial.readable());
+        let _ = poll_once(first.as_mut(), &waker);
+        mock.inject_rx(b"gh");
+        serial.interrupt_handler();
+        let mut other = Box::pin(serial.readable());
+        let other_ready = poll_once(other.as_mut(), &waker).is_ready();
+        let stats = serial.stats().read_waker;
+        report.check(
+            "another task is not a repoll",
+            other_ready && stats.registered == 2 && stats.repolled == 0,
+        );
+
+        report.exit_code()
+    }
+}
@@ -0,0 +1,82 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use heapless::spsc::Queue;
+use riscv::register::uie;
+use user_lib::{
+    executor::{Executor, IdleStrategy},
+    init_user_trap, irq,
+    user_uart::*,
+};
+
+const PORT: usize = 1;
+const BAUD_RATE: usize = 115_200;
+const WATCHDOG_PERIOD_US: usize = 10_000;
+const WATCHDOG_THRESHOLD_US: usize = 20_000;
+const MESSAGE: &[u8] = b"ping";
+
+static READ_DONE: AtomicBool = AtomicBool::new(false);
+
+async fn echo_task(serial: Arc<AsyncSerial>) {
+    let _ = serial.clone().write(MESSAGE).await;
+    let mut buf = [0u8; MESSAGE.len()];
+    let _ = serial.read(&mut buf).await;
+    println!("[uart watchdog] read back {:?}", core::str::from_utf8(&buf));
+    READ_DONE.store(buf == MESSAGE, Relaxed);
+}
+
+/// The port's interrupt is never enabled, as if every delivery was lost.
+/// Only the watchdog can complete the read of the looped back bytes.
+#[no_mangle]
+pub fn main() -> i32 {
+    init_user_trap();
+    let claim = SerialClaim::claim(PORT).unwrap();
+    type RxBuffer = Queue<u8, DEFAULT_RX_BUFFER_SIZE>;
+    type TxBuffer = Queue<u8, DEFAULT_TX_BUFFER_SIZE>;
+    static mut DRIVER_RX_BUFFER: RxBuffer = RxBuffer::new();
+    static mut DRIVER_TX_BUFFER: TxBuffer = TxBuffer::new();
+    let (rx_pro, rx_con) = unsafe { DRIVER_RX_BUFFER.split() };
+    let (tx_pro, tx_con) = unsafe { DRIVER_TX_BUFFER.split() };
+    let serial = Arc::new(AsyncSerial::from_claim(
+        &claim, rx_pro, rx_con, tx_pro, tx_con,
+    ));
+    serial.hardware_init(BAUD_RATE);
+    let block = claim.registers();
+    block.mcr.modify(|_, w| w.loop_().loop_back());
+
+    let exec = Executor::new(IdleStrategy::Yield);
+    exec.spawn(echo_task(serial.clone()));
+    exec.spawn(
+        serial
+            .clone()
+            .watchdog_task(WATCHDOG_PERIOD_US, WATCHDOG_THRESHOLD_US),
+    );
+    unsafe {
+        uie::set_usoft();
+        uie::set_utimer();
+    }
+    exec.run_until(|| READ_DONE.load(Relaxed));
+    unsafe {
+        uie::clear_utimer();
+        uie::clear_usoft();
+    }
+    block.mcr.modify(|_, w| w.loop_().normal());
+
+    let stats = serial.stats();
+    let kernel = irq::kernel_stats(claim.irq());
+    println!(
+        "[uart watchdog] missed interrupts suspected {}, user intr count {}, kernel {:?}",
+        stats.missed_intr_count, stats.intr_count, kernel
+    );
+    if stats.missed_intr_count > 0 && kernel.delivered == 0 {
+        0
+    } else {
+        -1
+    }
+}
@@ -0,0 +1,72 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use riscv::register::uie;
+use user_lib::{
+    get_time, init_user_trap, set_timer,
+    timer::now_us,
+    uintr::{critical_section, is_enabled},
+};
+
+const SECTION_MS: isize = 5;
+const WAIT_MS: isize = 50;
+
+static TIMER_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+fn spin_ms(ms: isize, until: impl Fn() -> bool) {
+    let start = get_time();
+    while get_time() - start < ms && !until() {}
+}
+
+/// A timer due inside a critical section is only taken once it ends, and
+/// nested sections leave user interrupts as they found them.
+#[no_mangle]
+pub fn main() -> i32 {
+    init_user_trap();
+    unsafe {
+        uie::set_utimer();
+    }
+    if !is_enabled() {
+        println!("[uintr critical] user interrupts are off");
+        return -1;
+    }
+
+    let nested_ok = critical_section(|| {
+        let inner = critical_section(is_enabled);
+        !inner && !is_enabled()
+    });
+    println!(
+        "[uintr critical] nested: {}, enabled after: {}",
+        nested_ok,
+        is_enabled()
+    );
+
+    let in_section = critical_section(|| {
+        set_timer((now_us() + 1000) as isize);
+        spin_ms(SECTION_MS, || false);
+        TIMER_COUNT.load(Relaxed)
+    });
+    spin_ms(WAIT_MS, || TIMER_COUNT.load(Relaxed) > 0);
+    unsafe {
+        uie::clear_utimer();
+    }
+    let after = TIMER_COUNT.load(Relaxed);
+    println!(
+        "[uintr critical] timer interrupts in section {}, after {}",
+        in_section, after
+    );
+    if nested_ok && is_enabled() && in_section == 0 && after == 1 {
+        0
+    } else {
+        -1
+    }
+}
+
+#[no_mangle]
+pub fn timer_intr_handler(_time_us: usize) {
+    TIMER_COUNT.fetch_add(1, Relaxed);
+}
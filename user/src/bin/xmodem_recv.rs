@@ -0,0 +1,152 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use riscv::register::uie;
+use spin::Once;
+use user_lib::{
+    executor::{Executor, IdleStrategy},
+    init_user_trap, irq, set_ext_int_enable,
+    sync::CancellationToken,
+    user_uart::{xmodem::*, *},
+};
+
+// Wired to the port xmodem_send sends on, see uart_loopback.
+const PORT: usize = 3;
+const BAUD_RATE: usize = 115_200;
+/// The largest image taken.
+const BUFFER_SIZE: usize = 256 * 1024;
+/// What xmodem_send sends, checked if that is what arrives.
+const IMAGE_LEN: usize = 100_000;
+/// Pages of the rx ring, a few blocks' worth.
+const RING_PAGES: usize = 4;
+
+static CONSOLE: Once<Arc<ConsoleAsync>> = Once::new();
+static RESULT: Once<Result<usize, XmodemError>> = Once::new();
+static DONE: AtomicBool = AtomicBool::new(false);
+
+/// Byte `index` of the test image of xmodem_send.
+fn pattern(index: usize) -> u8 {
+    (index.wrapping_mul(31) ^ (index >> 8)) as u8
+}
+
+/// Cancels the transfer when `q` or Ctrl-C is typed.
+async fn console_task(console: Arc<ConsoleAsync>, token: CancellationToken) {
+    let mut buf = [0u8; 16];
+    loop {
+        let len = console.clone().read(&mut buf).await;
+        if buf[..len].iter().any(|&key| key == b'q' || key == 0x03) {
+            token.cancel();
+            return;
+        }
+    }
+}
+
+/// Receives an image into a buffer and checks it, against the test image
+/// too if it is the one xmodem_send sends.
+#[no_mangle]
+pub fn main() -> i32 {
+    init_user_trap();
+    let (claim, serial) = match SerialBuilder::new(PORT).baud(BAUD_RATE).build() {
+        Ok((claim, serial)) => (claim, serial.into_async().unwrap()),
+        Err(err) => {
+            println!("[xmodem recv] port {} failed: {:?}", PORT, err);
+            return -1;
+        }
+    };
+    // blocks are copied out of the ring whole, not dequeued byte by byte
+    static mut RX_RING: RingPages<RING_PAGES> = RingPages::new();
+    serial.attach_rx_ring(RxRing::new(unsafe { &mut RX_RING }));
+    serial::register(PORT, serial.clone()).unwrap();
+
+    static mut BUFFER: [u8; BUFFER_SIZE] = [0; BUFFER_SIZE];
+    let buf = unsafe { &mut BUFFER };
+    println!(
+        "[xmodem recv] waiting on port {} for up to {} bytes, q to cancel",
+        PORT, BUFFER_SIZE
+    );
+    let token = CancellationToken::new();
+    let stats_serial = serial.clone();
+    let exec = Executor::new(IdleStrategy::Yield);
+    let transfer_token = token.clone();
+    exec.spawn(async move {
+        let result = receive(&serial, buf, &transfer_token).await;
+        RESULT.call_once(|| result);
+        DONE.store(true, Relaxed);
+    });
+    // without the console the transfer just can't be cancelled
+    let console = ConsoleAsync::open().ok().map(Arc::new);
+    let console_handle = console.clone().map(|console| {
+        CONSOLE.call_once(|| console.clone());
+        exec.spawn(console_task(console, token))
+    });
+    set_ext_int_enable(claim.irq() as usize, 1);
+    unsafe {
+        uie::set_uext();
+        uie::set_usoft();
+        uie::set_utimer();
+    }
+    exec.run_until(|| DONE.load(Relaxed));
+    if let Some(handle) = console_handle {
+        handle.cancel();
+    }
+    unsafe {
+        uie::clear_uext();
+        uie::clear_usoft();
+        uie::clear_utimer();
+    }
+    if let Some(console) = console {
+        console.remove_read();
+    }
+
+    let stats = stats_serial.stats();
+    println!(
+        "[xmodem recv] rx high water {}, {} rx interrupts per KiB",
+        stats.rx_high_water,
+        stats.rx_intr_per_kb()
+    );
+
+    let len = match RESULT.get().unwrap() {
+        Ok(len) => *len,
+        Err(err) => {
+            println!("[xmodem recv] failed: {:?}", err);
+            return -1;
+        }
+    };
+    let image = unsafe { &BUFFER[..len] };
+    println!(
+        "[xmodem recv] {} bytes, crc {:#06x}, first bytes {:02x?}",
+        len,
+        crc16(image),
+        &image[..len.min(16)]
+    );
+    if len == IMAGE_LEN {
+        let bad = image
+            .iter()
+            .enumerate()
+            .filter(|&(i, &byte)| byte != pattern(i))
+            .count();
+        println!("[xmodem recv] test image, {} bad bytes", bad);
+        if bad > 0 {
+            return -1;
+        }
+    }
+    0
+}
+
+#[no_mangle]
+pub fn ext_intr_handler(irq: u16, _is_from_kernel: bool) {
+    if let Some(console) = CONSOLE.get() {
+        if irq == console.irq() {
+            console.interrupt_handler();
+            return;
+        }
+    }
+    serial::dispatch(irq);
+    irq::complete(irq);
+}
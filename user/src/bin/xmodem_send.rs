@@ -0,0 +1,129 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use riscv::register::uie;
+use spin::Once;
+use user_lib::{
+    executor::{Executor, IdleStrategy},
+    init_user_trap, irq, set_ext_int_enable,
+    sync::CancellationToken,
+    user_uart::{xmodem::*, *},
+};
+
+// Wired to the port xmodem_recv listens on, see uart_loopback.
+const PORT: usize = 2;
+const BAUD_RATE: usize = 115_200;
+/// Not a multiple of the block size, so the last block is padded.
+const IMAGE_LEN: usize = 100_000;
+const BLOCK_SIZE: usize = LARGE_BLOCK;
+
+static CONSOLE: Once<Arc<ConsoleAsync>> = Once::new();
+static RESULT: Once<Result<(), XmodemError>> = Once::new();
+static DONE: AtomicBool = AtomicBool::new(false);
+
+/// Byte `index` of the test image, xmodem_recv checks for it.
+fn pattern(index: usize) -> u8 {
+    (index.wrapping_mul(31) ^ (index >> 8)) as u8
+}
+
+/// Cancels the transfer when `q` or Ctrl-C is typed.
+async fn console_task(console: Arc<ConsoleAsync>, token: CancellationToken) {
+    let mut buf = [0u8; 16];
+    loop {
+        let len = console.clone().read(&mut buf).await;
+        if buf[..len].iter().any(|&key| key == b'q' || key == 0x03) {
+            token.cancel();
+            return;
+        }
+    }
+}
+
+/// Sends a test image of `IMAGE_LEN` bytes to xmodem_recv.
+#[no_mangle]
+pub fn main() -> i32 {
+    init_user_trap();
+    let (claim, serial) = match SerialBuilder::new(PORT).baud(BAUD_RATE).build() {
+        Ok((claim, serial)) => (claim, serial.into_async().unwrap()),
+        Err(err) => {
+            println!("[xmodem send] port {} failed: {:?}", PORT, err);
+            return -1;
+        }
+    };
+    serial::register(PORT, serial.clone()).unwrap();
+
+    // far bigger than the user heap
+    static mut IMAGE: [u8; IMAGE_LEN] = [0; IMAGE_LEN];
+    let image: &'static [u8] = unsafe {
+        for (i, byte) in IMAGE.iter_mut().enumerate() {
+            *byte = pattern(i);
+        }
+        &IMAGE
+    };
+    println!(
+        "[xmodem send] {} bytes, crc {:#06x}, on port {}, q to cancel",
+        IMAGE_LEN,
+        crc16(image),
+        PORT
+    );
+    let token = CancellationToken::new();
+    let exec = Executor::new(IdleStrategy::Yield);
+    let transfer_token = token.clone();
+    exec.spawn(async move {
+        let result = send(&serial, image, BLOCK_SIZE, &transfer_token).await;
+        RESULT.call_once(|| result);
+        DONE.store(true, Relaxed);
+    });
+    // without the console the transfer just can't be cancelled
+    let console = ConsoleAsync::open().ok().map(Arc::new);
+    let console_handle = console.clone().map(|console| {
+        CONSOLE.call_once(|| console.clone());
+        exec.spawn(console_task(console, token))
+    });
+    set_ext_int_enable(claim.irq() as usize, 1);
+    unsafe {
+        uie::set_uext();
+        uie::set_usoft();
+        uie::set_utimer();
+    }
+    exec.run_until(|| DONE.load(Relaxed));
+    if let Some(handle) = console_handle {
+        handle.cancel();
+    }
+    unsafe {
+        uie::clear_uext();
+        uie::clear_usoft();
+        uie::clear_utimer();
+    }
+    if let Some(console) = console {
+        console.remove_read();
+    }
+
+    match RESULT.get().unwrap() {
+        Ok(()) => {
+            println!("[xmodem send] done");
+            0
+        }
+        Err(err) => {
+            println!("[xmodem send] failed: {:?}", err);
+            -1
+        }
+    }
+}
+
+#[no_mangle]
+pub fn ext_intr_handler(irq: u16, _is_from_kernel: bool) {
+    if let Some(console) = CONSOLE.get() {
+        if irq == console.irq() {
+            console.interrupt_handler();
+            return;
+        }
+    }
+    serial::dispatch(irq);
+    irq::complete(irq);
+}
@@ -1,9 +1,15 @@
+use crate::timer::now_us;
+use crate::user_uart::{regs::*, SerialEvent, SerialEventBus, SerialEventKind};
+use alloc::sync::Arc;
 use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed};
+use heapless::mpmc::Q16;
+use spin::Once;
 
 const STDIN: usize = 0;
 const STDOUT: usize = 1;
 
-use super::{read, write};
+use super::{listen_console_errors, read, write, writev};
 
 struct Stdout;
 
@@ -14,8 +20,196 @@ impl Write for Stdout {
     }
 }
 
+/// Bytes `print!` holds back, see `ConsoleWriter`.
+pub const CONSOLE_BUFFER_LEN: usize = 128;
+
+/// Writes to `fd` a line at a time: keeps up to `N` bytes and writes them
+/// with one syscall at the end of a line, or once more won't fit, together
+/// with the lines that come in.
+pub struct ConsoleWriter<const N: usize> {
+    fd: usize,
+    len: usize,
+    buf: [u8; N],
+}
+
+impl<const N: usize> ConsoleWriter<N> {
+    pub const fn new(fd: usize) -> Self {
+        ConsoleWriter {
+            fd,
+            len: 0,
+            buf: [0; N],
+        }
+    }
+
+    /// Bytes held back.
+    pub fn held(&self) -> usize {
+        self.len
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        let line_end = bytes
+            .iter()
+            .rposition(|&ch| ch == b'\n')
+            .map_or(0, |pos| pos + 1);
+        let (lines, tail) = bytes.split_at(line_end);
+        if lines.is_empty() && self.len + tail.len() <= N {
+            self.hold(tail);
+        } else if tail.len() <= N {
+            self.send(lines);
+            self.hold(tail);
+        } else {
+            self.send(bytes);
+        }
+    }
+
+    pub fn flush(&mut self) {
+        self.send(&[]);
+    }
+
+    fn hold(&mut self, bytes: &[u8]) {
+        self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+        self.len += bytes.len();
+    }
+
+    /// Writes what is held, then `bytes`.
+    fn send(&mut self, bytes: &[u8]) {
+        if self.len + bytes.len() != 0 {
+            writev(self.fd, &[&self.buf[..self.len], bytes]);
+            self.len = 0;
+        }
+    }
+}
+
+impl<const N: usize> Write for ConsoleWriter<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
+static CONSOLE: spin::Mutex<ConsoleWriter<CONSOLE_BUFFER_LEN>> =
+    spin::Mutex::new(ConsoleWriter::new(STDOUT));
+
+/// `None` if the code this interrupted, or the one that panicked, may hold
+/// the console.
+fn console() -> Option<spin::MutexGuard<'static, ConsoleWriter<CONSOLE_BUFFER_LEN>>> {
+    if crate::trap::in_interrupt() || crate::lang_items::panicking() {
+        CONSOLE.try_lock()
+    } else {
+        Some(CONSOLE.lock())
+    }
+}
+
+/// Lines go out whole, what is left of a line waits for the rest or for
+/// `flush`.
 pub fn print(args: fmt::Arguments) {
-    Stdout.write_fmt(args).unwrap();
+    // the console may be a serial driver the interrupted task holds
+    #[cfg(feature = "console_check")]
+    debug_assert!(
+        !crate::trap::in_interrupt(),
+        "print! in interrupt context, use intr_println!"
+    );
+    match console() {
+        Some(mut out) => {
+            drain_into(&mut out);
+            let _ = out.write_fmt(args);
+        }
+        None => {
+            let _ = Stdout.write_fmt(args);
+        }
+    }
+}
+
+/// Writes what `print!` holds back, with the interrupt log before it.
+/// `exit`, `fork`, reads of stdin and the panic handler call it.
+pub fn flush() {
+    drain_intr_log();
+}
+
+/// Longest message `intr_print!` keeps, the rest is cut off.
+pub const INTR_MSG_LEN: usize = 96;
+
+/// A message formatted on the stack of an interrupt handler.
+struct IntrMsg {
+    len: usize,
+    cut: bool,
+    buf: [u8; INTR_MSG_LEN],
+}
+
+impl Write for IntrMsg {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut len = s.len().min(INTR_MSG_LEN - self.len);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.cut |= len < s.len();
+        self.buf[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+/// Messages from interrupt context waiting for `drain_intr_log`. Lock
+/// free, so a handler can push while the task it interrupted drains.
+static INTR_LOG: Q16<IntrMsg> = Q16::new();
+static INTR_LOG_DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// `print` for interrupt context: takes no lock and makes no syscall, the
+/// message is queued for `drain_intr_log`. Dropped and counted if the
+/// queue is full.
+pub fn intr_print(args: fmt::Arguments) {
+    let mut msg = IntrMsg {
+        len: 0,
+        cut: false,
+        buf: [0; INTR_MSG_LEN],
+    };
+    let _ = msg.write_fmt(args);
+    if INTR_LOG.enqueue(msg).is_err() {
+        INTR_LOG_DROPPED.fetch_add(1, Relaxed);
+    }
+}
+
+/// Prints the messages queued from interrupt context, and how many were
+/// dropped since the last drain, then what `print!` holds back. `print!`
+/// drains first, so they come out in order with the rest. Does nothing in
+/// interrupt context. Returns the messages printed.
+pub fn drain_intr_log() -> usize {
+    match console() {
+        Some(mut out) => {
+            let count = drain_into(&mut out);
+            out.flush();
+            count
+        }
+        None => 0,
+    }
+}
+
+fn drain_into<const N: usize>(out: &mut ConsoleWriter<N>) -> usize {
+    if crate::trap::in_interrupt() {
+        return 0;
+    }
+    let mut count = 0;
+    while let Some(msg) = INTR_LOG.dequeue() {
+        out.write_bytes(&msg.buf[..msg.len]);
+        if msg.cut {
+            out.write_bytes(b"..\r\n");
+        }
+        count += 1;
+    }
+    let dropped = INTR_LOG_DROPPED.swap(0, Relaxed);
+    if dropped != 0 {
+        let _ = write!(out, "[intr log] {} messages dropped\r\n", dropped);
+    }
+    count
+}
+
+/// Drains the interrupt log every `period_us`, for a program that may not
+/// print for a while. Spawn it at low priority, it never completes.
+pub async fn intr_log_task(period_us: usize) {
+    loop {
+        drain_intr_log();
+        crate::timer::sleep_us(period_us).await;
+    }
 }
 
 #[macro_export]
@@ -32,6 +226,80 @@ macro_rules! println {
     }
 }
 
+#[macro_export]
+macro_rules! intr_print {
+    ($fmt: literal $(, $($arg: tt)+)?) => {
+        $crate::console::intr_print(format_args!($fmt $(, $($arg)+)?));
+    }
+}
+
+#[macro_export]
+macro_rules! intr_println {
+    ($fmt: literal $(, $($arg: tt)+)?) => {
+        $crate::console::intr_print(format_args!(concat!($fmt, "\r\n") $(, $($arg)+)?));
+    }
+}
+
+/// Tag of the kernel's software interrupt message on console line errors,
+/// the `LSR_*` error bits are in the low byte.
+pub const CONSOLE_ERROR_MSG: usize = 0x4c53_0000;
+
+static ERROR_EVENTS: Once<Arc<SerialEventBus>> = Once::new();
+static ERROR_LISTENING: AtomicBool = AtomicBool::new(false);
+
+/// Line errors the kernel driver sees on the console, as events of port 0.
+/// Without them a reader of the shared console only sees bytes missing.
+/// The first call has the kernel send them, `init_user_trap` must have been
+/// called. They come with a software interrupt: a `soft_intr_handler` of
+/// the program's own must pass its messages to `handle_error_interrupt`.
+pub fn error_events() -> Result<Arc<SerialEventBus>, isize> {
+    let bus = ERROR_EVENTS.call_once(|| Arc::new(SerialEventBus::new()));
+    if !ERROR_LISTENING.swap(true, Relaxed) {
+        let ret = listen_console_errors(true);
+        if ret < 0 {
+            ERROR_LISTENING.store(false, Relaxed);
+            return Err(ret);
+        }
+    }
+    Ok(bus.clone())
+}
+
+/// Stops the events of `error_events`, the bus stays and keeps the ones
+/// not taken yet.
+pub fn stop_error_events() {
+    if ERROR_LISTENING.swap(false, Relaxed) {
+        listen_console_errors(false);
+    }
+}
+
+/// Posts the events of a console error message. Returns false for other
+/// messages.
+pub fn handle_error_interrupt(msg: usize) -> bool {
+    if msg & !0xff != CONSOLE_ERROR_MSG {
+        return false;
+    }
+    let lsr = msg as u8;
+    if let Some(bus) = ERROR_EVENTS.get() {
+        let kinds = [
+            (LSR_OE, SerialEventKind::Overrun),
+            (LSR_PE, SerialEventKind::ParityError),
+            (LSR_FE, SerialEventKind::FramingError),
+            (LSR_BI, SerialEventKind::Break),
+        ];
+        let timestamp = now_us();
+        for (bit, kind) in kinds {
+            if lsr & bit != 0 {
+                bus.push(SerialEvent {
+                    port: 0,
+                    kind,
+                    timestamp,
+                });
+            }
+        }
+    }
+    true
+}
+
 pub fn getchar() -> u8 {
     let mut c = [0u8; 1];
     let mut res = -1;
@@ -0,0 +1,598 @@
+use crate::sync::CancellationToken;
+use crate::trace::{push_trace, ASYNC_TASK_POLL_ENTER, ASYNC_TASK_POLL_EXIT};
+use crate::trap::hart_id;
+use crate::uintr;
+use crate::{getpid, send_msg, yield_};
+use alloc::{boxed::Box, collections::BTreeMap, collections::VecDeque, sync::Arc, vec::Vec};
+use core::arch::asm;
+use core::future::Future;
+use core::mem::ManuallyDrop;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use heapless::mpmc::Q64;
+use lazy_static::*;
+use spin::Mutex;
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+type ReadyQueue = Mutex<ReadyQueues>;
+
+/// After this many high priority polls in a row, one low priority task is
+/// polled if any is ready, so bulk work is slowed down but never starved.
+pub const DEFAULT_HIGH_BURST: usize = 8;
+
+/// Scheduling class of a task. Ready high priority tasks are polled before
+/// low priority ones, see `DEFAULT_HIGH_BURST`. There is no poll budget: a
+/// task runs until it returns `Pending`, so a CPU heavy low priority task
+/// must still yield now and then for a high priority one to get in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Priority {
+    High,
+    Low,
+}
+
+impl Default for Priority {
+    fn default() -> Self {
+        Priority::Low
+    }
+}
+
+struct ReadyQueues {
+    high: VecDeque<Arc<Task>>,
+    low: VecDeque<Arc<Task>>,
+    high_burst: usize,
+    /// High priority polls since the last low priority one.
+    high_streak: usize,
+}
+
+impl ReadyQueues {
+    fn push(&mut self, task: Arc<Task>) {
+        match task.priority {
+            Priority::High => self.high.push_back(task),
+            Priority::Low => self.low.push_back(task),
+        }
+    }
+
+    fn pop(&mut self) -> Option<Arc<Task>> {
+        if self.high_streak >= self.high_burst || self.high.is_empty() {
+            if let Some(task) = self.low.pop_front() {
+                self.high_streak = 0;
+                return Some(task);
+            }
+        }
+        let task = self.high.pop_front()?;
+        self.high_streak += 1;
+        Some(task)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.high.is_empty() && self.low.is_empty()
+    }
+
+    fn clear(&mut self) {
+        self.high.clear();
+        self.low.clear();
+    }
+}
+
+pub const MAX_HART_NUM: usize = 4;
+/// Hart of the tasks of an executor that is not pinned to a hart.
+const ANY_HART: usize = usize::MAX;
+
+/// Set by every wake, one flag per hart. The idle hook clears it with user
+/// interrupts disabled before deciding to sleep, so a wake that lands
+/// between the empty-queue check and the WFI is never lost.
+static WAKE_PENDING: [AtomicBool; MAX_HART_NUM] = [
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+    AtomicBool::new(false),
+];
+
+/// Message of the soft interrupt sent to kick an idle hart, the low bits
+/// hold the hart.
+pub const CROSS_HART_WAKE_MSG: usize = 0x5a4e_0000;
+static CROSS_HART_WAKES: AtomicUsize = AtomicUsize::new(0);
+
+static NEXT_TASK_ID: AtomicUsize = AtomicUsize::new(0);
+
+const NO_TASK: usize = usize::MAX;
+static CURRENT_TASK: AtomicUsize = AtomicUsize::new(NO_TASK);
+
+/// Task IDs whose wake could not be delivered directly because a lock was
+/// held, one queue per hart. Pushing never blocks, so interrupt context can
+/// always use it. The executor drains them at the top of its loop.
+static DEFERRED_WAKES: [Q64<usize>; MAX_HART_NUM] =
+    [Q64::new(), Q64::new(), Q64::new(), Q64::new()];
+/// Set when a deferred wake list was full, every live task gets polled then.
+static DEFERRED_OVERFLOW: AtomicBool = AtomicBool::new(false);
+
+/// ID of the task being polled, if any.
+pub fn current_task_id() -> Option<usize> {
+    match CURRENT_TASK.load(Ordering::Relaxed) {
+        NO_TASK => None,
+        id => Some(id),
+    }
+}
+
+/// Wake a task by ID without taking any lock. Safe to call from user
+/// interrupt handlers.
+pub fn defer_wake(task_id: usize) {
+    let queue = &DEFERRED_WAKES[hart_id() % MAX_HART_NUM];
+    if queue.enqueue(task_id).is_err() {
+        DEFERRED_OVERFLOW.store(true, Ordering::Release);
+    }
+    // the hart of the task is unknown here
+    signal_hart(ANY_HART);
+}
+
+/// Mark `hart` as having work. If it is another hart, also send it a soft
+/// interrupt so an executor idling there returns.
+fn signal_hart(hart: usize) {
+    if hart == ANY_HART {
+        for pending in WAKE_PENDING.iter() {
+            pending.store(true, Ordering::Release);
+        }
+        return;
+    }
+    WAKE_PENDING[hart % MAX_HART_NUM].store(true, Ordering::Release);
+    if hart != hart_id() {
+        CROSS_HART_WAKES.fetch_add(1, Ordering::Relaxed);
+        send_msg(getpid() as usize, CROSS_HART_WAKE_MSG | hart);
+    }
+}
+
+/// Wakes that targeted a task pinned to another hart than the waker's.
+pub fn cross_hart_wakes() -> usize {
+    CROSS_HART_WAKES.load(Ordering::Relaxed)
+}
+
+/// Consume the soft interrupt sent by `signal_hart`. Returns false for other
+/// messages. The flag is already set, the interrupt only ends an idle wait.
+pub fn handle_soft_interrupt(msg: usize) -> bool {
+    msg & !0xffff == CROSS_HART_WAKE_MSG
+}
+
+fn drain_deferred_wakes() {
+    if DEFERRED_OVERFLOW.swap(false, Ordering::AcqRel) {
+        for queue in DEFERRED_WAKES.iter() {
+            while queue.dequeue().is_some() {}
+        }
+        let tasks: Vec<Arc<Task>> = TASKS.lock().values().cloned().collect();
+        for task in tasks {
+            task.schedule();
+        }
+        return;
+    }
+    for queue in DEFERRED_WAKES.iter() {
+        while let Some(task_id) = queue.dequeue() {
+            let task = TASKS.lock().get(&task_id).cloned();
+            if let Some(task) = task {
+                task.schedule();
+            }
+        }
+    }
+}
+
+lazy_static! {
+    /// Live tasks of all executors, indexed by task ID.
+    static ref TASKS: Mutex<BTreeMap<usize, Arc<Task>>> = Mutex::new(BTreeMap::new());
+}
+
+/// Counters of one task. `poll_cycles` is measured with the cycle values
+/// returned by `push_trace`, so it stays 0 unless tracing is compiled in.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TaskStats {
+    pub id: usize,
+    pub priority: Priority,
+    pub polls: usize,
+    pub wakes: usize,
+    pub poll_cycles: usize,
+}
+
+/// Snapshot the counters of every live task.
+pub fn task_stats() -> Vec<TaskStats> {
+    TASKS.lock().values().map(|task| task.stats()).collect()
+}
+
+/// What the executor does when no task is runnable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IdleStrategy {
+    /// Busy loop, the old behaviour.
+    Spin,
+    /// Stall the hart with `wfi` until a user interrupt is pending.
+    /// Only use it when the hart allows `wfi` in U mode and the interrupt
+    /// that will wake us is enabled in `uie`.
+    Wfi,
+    /// Give the hart back to the kernel with `sys_yield`.
+    Yield,
+}
+
+struct Task {
+    id: usize,
+    priority: Priority,
+    hart: usize,
+    future: Mutex<Option<BoxFuture>>,
+    queue: Arc<ReadyQueue>,
+    queued: AtomicBool,
+    polls: AtomicUsize,
+    wakes: AtomicUsize,
+    poll_cycles: AtomicUsize,
+}
+
+impl Task {
+    fn stats(&self) -> TaskStats {
+        TaskStats {
+            id: self.id,
+            priority: self.priority,
+            polls: self.polls.load(Ordering::Relaxed),
+            wakes: self.wakes.load(Ordering::Relaxed),
+            poll_cycles: self.poll_cycles.load(Ordering::Relaxed),
+        }
+    }
+
+    fn wake(self: &Arc<Self>) {
+        self.wakes.fetch_add(1, Ordering::Relaxed);
+        self.schedule();
+    }
+
+    fn schedule(self: &Arc<Self>) {
+        if !self.queued.swap(true, Ordering::AcqRel) {
+            match self.queue.try_lock() {
+                Some(mut queue) => queue.push(self.clone()),
+                None => {
+                    // the ready queue is held by the interrupted executor
+                    self.queued.store(false, Ordering::Release);
+                    defer_wake(self.id);
+                    return;
+                }
+            }
+        }
+        signal_hart(self.hart);
+    }
+
+    fn waker(self: &Arc<Self>) -> Waker {
+        let ptr = Arc::into_raw(self.clone()) as *const ();
+        unsafe { Waker::from_raw(RawWaker::new(ptr, &TASK_WAKER_VTABLE)) }
+    }
+}
+
+// The waker data is an `Arc<Task>` pointer: clone is a refcount bump, wake
+// by reference touches no refcount at all.
+static TASK_WAKER_VTABLE: RawWakerVTable =
+    RawWakerVTable::new(clone_waker, wake, wake_by_ref, drop_waker);
+
+unsafe fn clone_waker(ptr: *const ()) -> RawWaker {
+    Arc::increment_strong_count(ptr as *const Task);
+    RawWaker::new(ptr, &TASK_WAKER_VTABLE)
+}
+
+unsafe fn wake(ptr: *const ()) {
+    let task = Arc::from_raw(ptr as *const Task);
+    task.wake();
+}
+
+unsafe fn wake_by_ref(ptr: *const ()) {
+    let task = ManuallyDrop::new(Arc::from_raw(ptr as *const Task));
+    task.wake();
+}
+
+unsafe fn drop_waker(ptr: *const ()) {
+    drop(Arc::from_raw(ptr as *const Task));
+}
+
+/// Handle of a spawned task. Dropping it detaches the task.
+pub struct JoinHandle {
+    id: usize,
+    token: CancellationToken,
+    finished: Arc<AtomicBool>,
+}
+
+impl JoinHandle {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Drop the task's future at its next poll, which the cancel wakes.
+    /// Every await point of the task is a cancellation point.
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    /// The token tripped by `cancel`, to hand to work the task starts.
+    pub fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    /// The task completed or was torn down after a cancel.
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Acquire)
+    }
+}
+
+pub struct Executor {
+    queue: Arc<ReadyQueue>,
+    idle_strategy: IdleStrategy,
+    hart: usize,
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Executor::new(IdleStrategy::Spin)
+    }
+}
+
+impl Executor {
+    pub fn new(idle_strategy: IdleStrategy) -> Self {
+        Self::with_hart(idle_strategy, ANY_HART)
+    }
+
+    /// An executor whose tasks are pinned to `hart`: it must only be run
+    /// there, and a wake from another hart sends that hart a soft interrupt.
+    pub fn for_hart(hart: usize, idle_strategy: IdleStrategy) -> Self {
+        assert!(hart < MAX_HART_NUM);
+        Self::with_hart(idle_strategy, hart)
+    }
+
+    fn with_hart(idle_strategy: IdleStrategy, hart: usize) -> Self {
+        Executor {
+            queue: Arc::new(Mutex::new(ReadyQueues {
+                high: VecDeque::new(),
+                low: VecDeque::new(),
+                high_burst: DEFAULT_HIGH_BURST,
+                high_streak: 0,
+            })),
+            idle_strategy,
+            hart,
+        }
+    }
+
+    /// Poll up to `high_burst` high priority tasks in a row before letting a
+    /// low priority one run. At least 1.
+    pub fn set_high_burst(&self, high_burst: usize) {
+        self.queue.lock().high_burst = high_burst.max(1);
+    }
+
+    /// Spawn a low priority task.
+    pub fn spawn(&self, future: impl Future<Output = ()> + Send + 'static) -> JoinHandle {
+        self.spawn_with_priority(future, Priority::Low)
+    }
+
+    /// Spawn a task that is polled ahead of the low priority ones, e.g. one
+    /// that drains a device queue. Wakes keep the priority of the task, so
+    /// a wake from an interrupt handler lands in the high queue.
+    pub fn spawn_high(&self, future: impl Future<Output = ()> + Send + 'static) -> JoinHandle {
+        self.spawn_with_priority(future, Priority::High)
+    }
+
+    pub fn spawn_with_priority(
+        &self,
+        future: impl Future<Output = ()> + Send + 'static,
+        priority: Priority,
+    ) -> JoinHandle {
+        let token = CancellationToken::new();
+        let finished = Arc::new(AtomicBool::new(false));
+        let future = {
+            let token = token.clone();
+            let finished = finished.clone();
+            async move {
+                token.run_until_cancelled(future).await;
+                finished.store(true, Ordering::Release);
+            }
+        };
+        let task = Arc::new(Task {
+            id: NEXT_TASK_ID.fetch_add(1, Ordering::Relaxed),
+            priority,
+            hart: self.hart,
+            future: Mutex::new(Some(Box::pin(future))),
+            queue: self.queue.clone(),
+            queued: AtomicBool::new(false),
+            polls: AtomicUsize::new(0),
+            wakes: AtomicUsize::new(0),
+            poll_cycles: AtomicUsize::new(0),
+        });
+        TASKS.lock().insert(task.id, task.clone());
+        task.schedule();
+        JoinHandle {
+            id: task.id,
+            token,
+            finished,
+        }
+    }
+
+    /// Poll ready tasks until the ready queue is empty.
+    pub fn run_until_idle(&self) {
+        loop {
+            drain_deferred_wakes();
+            let task = match self.queue.lock().pop() {
+                Some(task) => task,
+                None => break,
+            };
+            task.queued.store(false, Ordering::Release);
+            let mut future_slot = task.future.lock();
+            if let Some(mut future) = future_slot.take() {
+                let waker = task.waker();
+                let mut cx = Context::from_waker(&waker);
+                task.polls.fetch_add(1, Ordering::Relaxed);
+                CURRENT_TASK.store(task.id, Ordering::Relaxed);
+                let enter = push_trace(ASYNC_TASK_POLL_ENTER | (task.id & 0xfff));
+                let res = future.as_mut().poll(&mut cx);
+                let exit = push_trace(ASYNC_TASK_POLL_EXIT | (task.id & 0xfff));
+                CURRENT_TASK.store(NO_TASK, Ordering::Relaxed);
+                task.poll_cycles
+                    .fetch_add(exit.wrapping_sub(enter), Ordering::Relaxed);
+                if res.is_pending() {
+                    future_slot.replace(future);
+                } else {
+                    TASKS.lock().remove(&task.id);
+                }
+            }
+        }
+    }
+
+    /// Run tasks, idling in between, until `done` returns true.
+    pub fn run_until(&self, mut done: impl FnMut() -> bool) {
+        loop {
+            self.run_until_idle();
+            if done() {
+                break;
+            }
+            self.idle();
+        }
+    }
+
+    /// The idle hook. Returns once a wake may have happened.
+    pub fn idle(&self) {
+        if self.idle_strategy == IdleStrategy::Spin {
+            return;
+        }
+        let hart = match self.hart {
+            ANY_HART => hart_id(),
+            hart => hart,
+        };
+        let uie = uintr::disable();
+        if WAKE_PENDING[hart % MAX_HART_NUM].swap(false, Ordering::AcqRel)
+            || !self.queue.lock().is_empty()
+        {
+            uintr::restore(uie);
+            return;
+        }
+        match self.idle_strategy {
+            // A pending interrupt ends the wfi even with ustatus.UIE cleared,
+            // it is then taken as soon as UIE is restored.
+            IdleStrategy::Wfi => unsafe { asm!("wfi") },
+            IdleStrategy::Yield => {
+                uintr::restore(uie);
+                yield_();
+                return;
+            }
+            IdleStrategy::Spin => {}
+        }
+        uintr::restore(uie);
+    }
+
+    /// Snapshot the counters of the live tasks spawned on this executor.
+    pub fn task_stats(&self) -> Vec<TaskStats> {
+        TASKS
+            .lock()
+            .values()
+            .filter(|task| Arc::ptr_eq(&task.queue, &self.queue))
+            .map(|task| task.stats())
+            .collect()
+    }
+}
+
+impl Drop for Executor {
+    fn drop(&mut self) {
+        // queued tasks hold the queue itself, break the cycle
+        self.queue.lock().clear();
+        TASKS
+            .lock()
+            .retain(|_, task| !Arc::ptr_eq(&task.queue, &self.queue));
+    }
+}
+
+/// One pinned executor per hart. `run_until` runs the executor of the hart
+/// it finds itself on, so tasks pinned to a hart only make progress while
+/// the process is scheduled there. The kernel runs a process on one hart at
+/// a time, so pinning decides where a task runs, not how many run at once.
+pub struct HartExecutors {
+    executors: Vec<Executor>,
+}
+
+impl HartExecutors {
+    pub fn new(hart_num: usize, idle_strategy: IdleStrategy) -> Self {
+        assert!(hart_num > 0 && hart_num <= MAX_HART_NUM);
+        HartExecutors {
+            executors: (0..hart_num)
+                .map(|hart| Executor::for_hart(hart, idle_strategy))
+                .collect(),
+        }
+    }
+
+    /// The executor pinned to `hart`, spawn on it to pin a task there.
+    pub fn executor(&self, hart: usize) -> &Executor {
+        &self.executors[hart]
+    }
+
+    pub fn hart_num(&self) -> usize {
+        self.executors.len()
+    }
+
+    /// Run the executor of the current hart until `done` returns true.
+    pub fn run_until(&self, mut done: impl FnMut() -> bool) {
+        loop {
+            let executor = &self.executors[hart_id() % self.executors.len()];
+            executor.run_until_idle();
+            if done() {
+                break;
+            }
+            if self.executors.iter().any(|e| !e.queue.lock().is_empty()) {
+                // work is pinned elsewhere, let the kernel move us
+                yield_();
+            } else {
+                executor.idle();
+            }
+        }
+    }
+}
+
+/// Set by the waker of the future driven by `block_on`.
+static BLOCK_ON_WOKEN: AtomicBool = AtomicBool::new(false);
+static BLOCK_ON_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+static BLOCK_ON_WAKER_VTABLE: RawWakerVTable = RawWakerVTable::new(
+    |_| RawWaker::new(core::ptr::null(), &BLOCK_ON_WAKER_VTABLE),
+    block_on_wake,
+    block_on_wake,
+    |_| {},
+);
+
+fn block_on_wake(_: *const ()) {
+    BLOCK_ON_WOKEN.store(true, Ordering::Release);
+    signal_hart(ANY_HART);
+}
+
+/// Run `future` to completion on the calling context, for `main` and other
+/// code outside of any executor. See `block_on_with`.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    block_on_with(future, || {})
+}
+
+/// Run `future` to completion on the calling context. Between pending polls
+/// it waits with `wfi` if user external interrupts are already enabled,
+/// otherwise it calls `pump`, e.g. `AsyncSerial::pump`, to make progress.
+///
+/// Must not be called from inside a task or another `block_on`, it would
+/// stall the executor polling it. Panics if called re-entrantly.
+pub fn block_on_with<F: Future>(future: F, mut pump: impl FnMut()) -> F::Output {
+    if BLOCK_ON_ACTIVE.swap(true, Ordering::AcqRel) || current_task_id().is_some() {
+        panic!("[executor] block_on called from inside a task or block_on");
+    }
+    let mut future = future;
+    let mut future = unsafe { Pin::new_unchecked(&mut future) };
+    let waker =
+        unsafe { Waker::from_raw(RawWaker::new(core::ptr::null(), &BLOCK_ON_WAKER_VTABLE)) };
+    let mut cx = Context::from_waker(&waker);
+    BLOCK_ON_WOKEN.store(true, Ordering::Release);
+    let output = loop {
+        if BLOCK_ON_WOKEN.swap(false, Ordering::AcqRel) {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                break output;
+            }
+        }
+        if uintr::is_ext_enabled() {
+            let uie = uintr::disable();
+            if !BLOCK_ON_WOKEN.load(Ordering::Acquire) {
+                unsafe { asm!("wfi") };
+            }
+            uintr::restore(uie);
+        } else {
+            pump();
+            // nothing else can wake the future, poll it again
+            BLOCK_ON_WOKEN.store(true, Ordering::Release);
+        }
+    };
+    BLOCK_ON_ACTIVE.store(false, Ordering::Release);
+    output
+}
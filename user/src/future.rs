@@ -1,7 +1,8 @@
 use core::{
     future::Future,
     pin::Pin,
-    task::{Context, Poll, Waker},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
 };
 
 pub struct GetWakerFuture;
@@ -14,3 +15,248 @@ impl Future for GetWakerFuture {
         Poll::Ready(waker)
     }
 }
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Either<A, B> {
+    Left(A),
+    Right(B),
+}
+
+/// Poll both futures and resolve with the output of the first one ready,
+/// `a` is polled first. The other one is dropped with the `Select2`.
+pub fn select2<A: Future, B: Future>(a: A, b: B) -> Select2<A, B> {
+    Select2 { a, b }
+}
+
+pub struct Select2<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A: Future, B: Future> Future for Select2<A, B> {
+    type Output = Either<A::Output, B::Output>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // both fields are structurally pinned, they are never moved out
+        let this = unsafe { self.get_unchecked_mut() };
+        if let Poll::Ready(output) = unsafe { Pin::new_unchecked(&mut this.a) }.poll(cx) {
+            return Poll::Ready(Either::Left(output));
+        }
+        if let Poll::Ready(output) = unsafe { Pin::new_unchecked(&mut this.b) }.poll(cx) {
+            return Poll::Ready(Either::Right(output));
+        }
+        Poll::Pending
+    }
+}
+
+/// Wrap `future` so that every poll emits `ASYNC_FUTURE_POLL_ENTER` and
+/// `ASYNC_FUTURE_POLL_EXIT`, plus `ASYNC_FUTURE_READY` on completion. The low
+/// 12 bits carry `code` (4 bits) and a per-instance sequence number (8 bits).
+/// Without tracing compiled in, the future is returned unchanged.
+#[cfg(all(feature = "board_lrv", feature = "trace"))]
+pub fn traced<F: Future>(code: usize, future: F) -> Traced<F> {
+    use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+    static NEXT_SEQ: AtomicUsize = AtomicUsize::new(0);
+    Traced {
+        future,
+        tag: (code & 0xf) << 8 | (NEXT_SEQ.fetch_add(1, Relaxed) & 0xff),
+    }
+}
+
+#[cfg(not(all(feature = "board_lrv", feature = "trace")))]
+#[inline(always)]
+pub fn traced<F: Future>(_code: usize, future: F) -> Traced<F> {
+    future
+}
+
+#[cfg(not(all(feature = "board_lrv", feature = "trace")))]
+pub type Traced<F> = F;
+
+#[cfg(all(feature = "board_lrv", feature = "trace"))]
+pub struct Traced<F> {
+    future: F,
+    tag: usize,
+}
+
+#[cfg(all(feature = "board_lrv", feature = "trace"))]
+impl<F: Future> Future for Traced<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        use crate::trace::{
+            push_trace, ASYNC_FUTURE_POLL_ENTER, ASYNC_FUTURE_POLL_EXIT, ASYNC_FUTURE_READY,
+        };
+        let tag = self.tag;
+        // `future` is structurally pinned, it is never moved out of `self`
+        let future = unsafe { self.map_unchecked_mut(|traced| &mut traced.future) };
+        push_trace(ASYNC_FUTURE_POLL_ENTER | tag);
+        let res = future.poll(cx);
+        push_trace(ASYNC_FUTURE_POLL_EXIT | tag);
+        if res.is_ready() {
+            push_trace(ASYNC_FUTURE_READY | tag);
+        }
+        res
+    }
+}
+
+/// Waker state of a `FutureSet`. It must be `'static` because the per-slot
+/// wakers point into it and may outlive the set, declare it as a `static`.
+/// One state serves one set at a time.
+#[repr(C, align(64))]
+pub struct FutureSetState {
+    /// Bit `i` is set when the future in slot `i` was woken.
+    ready: AtomicU64,
+    parent: spin::Mutex<Option<Waker>>,
+    in_use: AtomicBool,
+}
+
+impl FutureSetState {
+    pub const fn new() -> Self {
+        FutureSetState {
+            ready: AtomicU64::new(0),
+            parent: spin::Mutex::new(None),
+            in_use: AtomicBool::new(false),
+        }
+    }
+
+    /// The state is 64-byte aligned, so the slot index fits in the low bits
+    /// of the waker data pointer.
+    fn slot_waker(&'static self, index: usize) -> Waker {
+        let ptr = (self as *const Self as usize | index) as *const ();
+        unsafe { Waker::from_raw(RawWaker::new(ptr, &SLOT_WAKER_VTABLE)) }
+    }
+}
+
+static SLOT_WAKER_VTABLE: RawWakerVTable =
+    RawWakerVTable::new(clone_slot_waker, wake_slot, wake_slot, |_| {});
+
+unsafe fn clone_slot_waker(ptr: *const ()) -> RawWaker {
+    RawWaker::new(ptr, &SLOT_WAKER_VTABLE)
+}
+
+unsafe fn wake_slot(ptr: *const ()) {
+    let state = &*((ptr as usize & !63) as *const FutureSetState);
+    state
+        .ready
+        .fetch_or(1 << (ptr as usize & 63), Ordering::AcqRel);
+    // if the parent waker is being replaced, the set scans the ready bits
+    // right after, so the wake is not lost
+    if let Some(parent) = state.parent.try_lock() {
+        if let Some(waker) = parent.as_ref() {
+            waker.wake_by_ref();
+        }
+    }
+}
+
+/// Up to `N` (at most 64) futures polled concurrently without allocating.
+/// Each slot has its own waker, so only the futures that were woken are
+/// polled again. Outputs come out of `next_ready` in completion order.
+pub struct FutureSet<F: Future, const N: usize> {
+    state: &'static FutureSetState,
+    futures: [Option<F>; N],
+    len: usize,
+}
+
+impl<F: Future, const N: usize> FutureSet<F, N> {
+    /// Panics if `state` is used by another set.
+    pub fn new(state: &'static FutureSetState) -> Self {
+        assert!(N <= 64);
+        assert!(
+            !state.in_use.swap(true, Ordering::AcqRel),
+            "FutureSetState is already in use"
+        );
+        state.ready.store(0, Ordering::Release);
+        FutureSet {
+            state,
+            futures: core::array::from_fn(|_| None),
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Add `future` and return its slot, or give it back if the set is full.
+    pub fn push(self: Pin<&mut Self>, future: F) -> Result<usize, F> {
+        // futures are never moved out of their slot, only dropped in place
+        let this = unsafe { self.get_unchecked_mut() };
+        match this.futures.iter().position(|slot| slot.is_none()) {
+            Some(index) => {
+                this.futures[index] = Some(future);
+                this.len += 1;
+                this.state.ready.fetch_or(1 << index, Ordering::AcqRel);
+                Ok(index)
+            }
+            None => Err(future),
+        }
+    }
+
+    /// Wait for the next future to complete, returning its slot and output.
+    /// Yields `None` once the set is empty.
+    pub fn next_ready(self: Pin<&mut Self>) -> NextReady<'_, F, N> {
+        NextReady { set: self }
+    }
+
+    pub fn poll_next_ready(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<(usize, F::Output)>> {
+        let this = unsafe { self.get_unchecked_mut() };
+        if this.len == 0 {
+            return Poll::Ready(None);
+        }
+        {
+            let mut parent = this.state.parent.lock();
+            match parent.as_ref() {
+                Some(waker) if waker.will_wake(cx.waker()) => {}
+                _ => *parent = Some(cx.waker().clone()),
+            }
+        }
+        let mut ready = this.state.ready.swap(0, Ordering::AcqRel);
+        while ready != 0 {
+            let index = ready.trailing_zeros() as usize;
+            ready &= ready - 1;
+            let slot = match this.futures.get_mut(index) {
+                Some(slot) => slot,
+                None => continue,
+            };
+            let future = match slot.as_mut() {
+                Some(future) => unsafe { Pin::new_unchecked(future) },
+                None => continue,
+            };
+            let waker = this.state.slot_waker(index);
+            if let Poll::Ready(output) = future.poll(&mut Context::from_waker(&waker)) {
+                *slot = None;
+                this.len -= 1;
+                // leave the rest for the next call
+                this.state.ready.fetch_or(ready, Ordering::AcqRel);
+                return Poll::Ready(Some((index, output)));
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl<F: Future, const N: usize> Drop for FutureSet<F, N> {
+    fn drop(&mut self) {
+        self.state.parent.lock().take();
+        self.state.in_use.store(false, Ordering::Release);
+    }
+}
+
+pub struct NextReady<'a, F: Future, const N: usize> {
+    set: Pin<&'a mut FutureSet<F, N>>,
+}
+
+impl<F: Future, const N: usize> Future for NextReady<'_, F, N> {
+    type Output = Option<(usize, F::Output)>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.set.as_mut().poll_next_ready(cx)
+    }
+}
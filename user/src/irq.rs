@@ -0,0 +1,285 @@
+use crate::trap::{get_context, hart_id, Plic, PAGE_SIZE, USER_TRAP_BUFFER};
+use crate::uintr::critical_section;
+use crate::user_uart::MAX_IRQ_PRIORITY;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use spin::Mutex;
+
+/// Serves the interrupts of one user-level device. Object safe, the
+/// dispatch table holds `Arc<dyn IrqHandler>`.
+pub trait IrqHandler: Send + Sync {
+    /// Called in interrupt context, before the IRQ is completed.
+    fn handle(&self, irq: u16);
+
+    /// The kernel took `irq` back, see `handle_revoke_interrupt`. Called
+    /// in interrupt context, the registers of the device are unmapped.
+    fn revoke(&self, _irq: u16) {}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IrqError {
+    /// A handler is registered for the IRQ already.
+    AlreadyRegistered,
+}
+
+/// Only locked with user interrupts masked, dispatch takes it in the
+/// interrupt handler.
+static HANDLERS: Mutex<BTreeMap<u16, Arc<dyn IrqHandler>>> = Mutex::new(BTreeMap::new());
+static UNKNOWN_IRQS: AtomicUsize = AtomicUsize::new(0);
+
+/// Tag of the kernel's software interrupt message when it takes a claimed
+/// IRQ back, e.g. once `transfer_ext_int` handed it on. The IRQ is in the
+/// low 16 bits.
+pub const IRQ_REVOKED_MSG: usize = 0x5256_0000;
+
+/// Keeps a handler registered, dropping it unregisters the handler.
+#[must_use = "the handler is unregistered when this is dropped"]
+pub struct IrqRegistration {
+    irq: u16,
+}
+
+impl IrqRegistration {
+    pub fn irq(&self) -> u16 {
+        self.irq
+    }
+}
+
+impl Drop for IrqRegistration {
+    fn drop(&mut self) {
+        let handler = critical_section(|| HANDLERS.lock().remove(&self.irq));
+        // a handler running on another hart keeps its own reference
+        drop(handler);
+    }
+}
+
+/// Has the default `ext_intr_handler` run `handler` for `irq`. One handler
+/// per IRQ.
+pub fn register(irq: u16, handler: Arc<dyn IrqHandler>) -> Result<IrqRegistration, IrqError> {
+    critical_section(|| {
+        let mut handlers = HANDLERS.lock();
+        if handlers.contains_key(&irq) {
+            return Err(IrqError::AlreadyRegistered);
+        }
+        handlers.insert(irq, handler);
+        Ok(IrqRegistration { irq })
+    })
+}
+
+/// Runs the handler registered for `irq`, for `ext_intr_handler`. Returns
+/// false, and counts the IRQ as unknown, if there is none.
+pub fn dispatch(irq: u16) -> bool {
+    let handler = critical_section(|| HANDLERS.lock().get(&irq).cloned());
+    match handler {
+        Some(handler) => {
+            handler.handle(irq);
+            true
+        }
+        None => {
+            UNKNOWN_IRQS.fetch_add(1, Relaxed);
+            false
+        }
+    }
+}
+
+/// Has the handler of a revoked IRQ, and the driver `serial::register`
+/// shares for it, fail what is pending, for `soft_intr_handler`. Returns
+/// false if `msg` is not `IRQ_REVOKED_MSG`.
+pub fn handle_revoke_interrupt(msg: usize) -> bool {
+    if msg & !0xffff != IRQ_REVOKED_MSG {
+        return false;
+    }
+    let irq = msg as u16;
+    // the handler takes itself out of the table
+    let handler = critical_section(|| HANDLERS.lock().get(&irq).cloned());
+    if let Some(handler) = handler {
+        handler.revoke(irq);
+    }
+    crate::user_uart::serial::revoke(irq);
+    true
+}
+
+/// IRQs `dispatch` found no handler for.
+pub fn unknown_irqs() -> usize {
+    UNKNOWN_IRQS.load(Relaxed)
+}
+
+/// What the kernel did with one interrupt source, in the order an
+/// interrupt goes through it. Claims and completes in U mode are not
+/// counted, and a source forwarded to user mode is completed there.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IrqKernelStats {
+    /// Pending when a hart took an S external interrupt, every hart that
+    /// saw it counts it.
+    pub fired: usize,
+    /// Claimed in S mode.
+    pub claimed: usize,
+    /// Forwarded to the owner's user trap queue, `ext_intr_handler` gets
+    /// these with `is_from_kernel` set.
+    pub delivered: usize,
+    /// Completed in S mode.
+    pub completed: usize,
+    /// Line errors the kernel driver saw, for a serial while nobody claimed
+    /// it.
+    pub overruns: usize,
+    pub parity_errors: usize,
+    pub framing_errors: usize,
+    pub breaks: usize,
+}
+
+/// The kernel's counters of `irq`, all zero for a source it doesn't count.
+pub fn kernel_stats(irq: u16) -> IrqKernelStats {
+    let mut counts = [0; 8];
+    if crate::syscall::sys_get_ext_int_stats(irq as usize, &mut counts) < 0 {
+        return IrqKernelStats::default();
+    }
+    IrqKernelStats {
+        fired: counts[0],
+        claimed: counts[1],
+        delivered: counts[2],
+        completed: counts[3],
+        overruns: counts[4],
+        parity_errors: counts[5],
+        framing_errors: counts[6],
+        breaks: counts[7],
+    }
+}
+
+/// Sources the claim ledger covers, as many as the kernel counts.
+const LEDGER_IRQS: usize = 32;
+
+/// The kernel's `ExtIntLedger`, at the end of the user trap buffer. The
+/// kernel counts the claims it forwards, we count ours and all completes,
+/// and the watchdog kills the process if a source stays claimed.
+#[repr(C)]
+struct ClaimLedger {
+    claimed: [AtomicUsize; LEDGER_IRQS],
+    completed: [AtomicUsize; LEDGER_IRQS],
+}
+
+fn ledger() -> &'static ClaimLedger {
+    // mapped by `init_user_trap`, there are no interrupts to count before
+    unsafe { &*((USER_TRAP_BUFFER + PAGE_SIZE - size_of::<ClaimLedger>()) as *const ClaimLedger) }
+}
+
+pub(crate) fn count_claim(irq: u16) {
+    if let Some(claimed) = ledger().claimed.get(irq as usize) {
+        claimed.fetch_add(1, Relaxed);
+    }
+}
+
+/// Completes `irq` on the U context of this hart. Handlers complete
+/// through this or `complete_guard`, a bare `Plic::complete` looks to the
+/// watchdog like a source that was never completed.
+pub fn complete(irq: u16) {
+    complete_on(get_context(hart_id(), 'U'), irq);
+}
+
+/// `complete` on a context got before, for code that checks it didn't
+/// move to another hart meanwhile.
+pub fn complete_on(context: usize, irq: u16) {
+    Plic::complete(context, irq);
+    if let Some(completed) = ledger().completed.get(irq as usize) {
+        completed.fetch_add(1, Relaxed);
+    }
+}
+
+/// Completes its IRQ when dropped, see `complete_guard`.
+#[must_use = "the IRQ is completed when this is dropped"]
+pub struct CompleteGuard {
+    irq: u16,
+}
+
+impl Drop for CompleteGuard {
+    fn drop(&mut self) {
+        complete(self.irq);
+    }
+}
+
+/// Taken at the start of a handler, so no early return leaves `irq`
+/// claimed.
+pub fn complete_guard(irq: u16) -> CompleteGuard {
+    CompleteGuard { irq }
+}
+
+/// How long a source may stay claimed before the kernel kills the process
+/// with -4, the line is dead to everyone until it is completed. 0 turns
+/// the watchdog off, the kernel starts it at a second.
+pub fn set_watchdog(timeout_us: usize) -> isize {
+    crate::syscall::sys_set_ext_int_watchdog(timeout_us)
+}
+
+/// Lowers how many sources this process and the children it forks from
+/// now on may hold at once, it never goes back up. Returns the limit in
+/// effect.
+pub fn set_claim_limit(limit: usize) -> usize {
+    crate::syscall::sys_set_ext_int_limit(limit) as usize
+}
+
+pub fn claim_limit() -> usize {
+    crate::syscall::sys_set_ext_int_limit(usize::MAX) as usize
+}
+
+/// The sources this process holds, claimed or handed over to it.
+pub fn claimed() -> Vec<u16> {
+    // one source can't be held twice
+    let mut irqs = [0; LEDGER_IRQS];
+    let count = crate::syscall::sys_list_ext_int(&mut irqs).max(0) as usize;
+    irqs[..count.min(LEDGER_IRQS)]
+        .iter()
+        .map(|&irq| irq as u16)
+        .collect()
+}
+
+/// Most sources the user trap handler claims before serving them.
+pub const MAX_BURST: usize = 8;
+
+/// Priorities set through `set_ext_int_priority`, the PLIC's can't be read
+/// back from user mode. Only locked with user interrupts masked.
+static PRIORITIES: Mutex<BTreeMap<u16, usize>> = Mutex::new(BTreeMap::new());
+const NONE_SERVED: AtomicUsize = AtomicUsize::new(0);
+/// Per priority, the IRQ served first in the last burst that had one.
+static FIRST_SERVED: [AtomicUsize; MAX_IRQ_PRIORITY + 1] = [NONE_SERVED; MAX_IRQ_PRIORITY + 1];
+
+pub(crate) fn note_priority(irq: u16, priority: usize) {
+    critical_section(|| PRIORITIES.lock().insert(irq, priority));
+}
+
+/// The PLIC default is back once the device is released.
+pub(crate) fn forget_priority(irq: u16) {
+    critical_section(|| PRIORITIES.lock().remove(&irq));
+}
+
+/// Orders a burst of `(irq, claimed_at)` claimed together. Higher
+/// priorities still come first, but sources of one priority take turns:
+/// by IRQ, starting after the one served first last time. The PLIC hands
+/// out ties lowest IRQ first, so without this the lowest port always
+/// went first and the others waited for it under load.
+pub fn round_robin(burst: &mut [(u16, usize)]) {
+    assert!(burst.len() <= MAX_BURST);
+    let mut order = [(Reverse(0), false, 0, 0); MAX_BURST];
+    let order = &mut order[..burst.len()];
+    critical_section(|| {
+        let priorities = PRIORITIES.lock();
+        for (entry, &(irq, claimed_at)) in order.iter_mut().zip(burst.iter()) {
+            let priority = priorities
+                .get(&irq)
+                .map_or(0, |&priority| priority.min(MAX_IRQ_PRIORITY));
+            // up to the one served first last time go to the back
+            let after = FIRST_SERVED[priority].load(Relaxed);
+            *entry = (Reverse(priority), irq as usize <= after, irq, claimed_at);
+        }
+    });
+    order.sort_unstable();
+    let mut level = None;
+    for (slot, &(Reverse(priority), _, irq, claimed_at)) in burst.iter_mut().zip(order.iter()) {
+        if level != Some(priority) {
+            FIRST_SERVED[priority].store(irq as usize, Relaxed);
+            level = Some(priority);
+        }
+        *slot = (irq, claimed_at);
+    }
+}
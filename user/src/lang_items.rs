@@ -1,17 +1,40 @@
-use super::exit;
+use super::{exit, sys_exit};
+use crate::uintr;
+use crate::user_uart::{dump_on_panic, PanicWriter};
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering::Relaxed};
 
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn panicking() -> bool {
+    PANICKING.load(Relaxed)
+}
+
+/// Prints through `PanicWriter` rather than `println!`, stdio may be
+/// redirected to the driver that panicked. Then dumps the serial drivers.
 #[panic_handler]
 fn panic_handler(panic_info: &core::panic::PanicInfo) -> ! {
+    // no handler may run on top of the state being dumped
+    uintr::disable();
+    if PANICKING.swap(true, Relaxed) {
+        // panicked again while dumping, or in the exit drain
+        sys_exit(-1);
+    }
+    // what was printed before comes first, unless the panic is in the middle of it
+    crate::console::flush();
+    let mut out = PanicWriter::new();
     let err = panic_info.message().unwrap();
-    if let Some(location) = panic_info.location() {
-        println!(
+    let _ = if let Some(location) = panic_info.location() {
+        writeln!(
+            out,
             "Panicked at {}:{}, {}",
             location.file(),
             location.line(),
             err
-        );
+        )
     } else {
-        println!("Panicked: {}", err);
-    }
+        writeln!(out, "Panicked: {}", err)
+    };
+    let _ = dump_on_panic(&mut out);
     exit(-1);
 }
@@ -5,11 +5,16 @@
 
 #[macro_use]
 pub mod console;
+pub mod executor;
 pub mod future;
+pub mod irq;
 mod lang_items;
 mod syscall;
+pub mod sync;
+pub mod timer;
 pub mod trace;
 pub mod trap;
+pub mod uintr;
 pub mod user_uart;
 
 extern crate alloc;
@@ -22,7 +27,11 @@ use syscall::*;
 
 pub use trap::{UserTrapContext, UserTrapQueue, UserTrapRecord};
 
+#[cfg(not(feature = "mock_uart"))]
 const USER_HEAP_SIZE: usize = 32768;
+// every `MockUart::async_serial` leaks a driver's queues
+#[cfg(feature = "mock_uart")]
+const USER_HEAP_SIZE: usize = 0x10_0000;
 
 static mut HEAP_SPACE: [u8; USER_HEAP_SIZE] = [0; USER_HEAP_SIZE];
 
@@ -98,14 +107,59 @@ pub fn pipe(pipe_fd: &mut [usize]) -> isize {
     sys_pipe(pipe_fd)
 }
 
+/// fd 0 reads the port set with `user_uart::redirect_stdio`, if any,
+/// after a prompt `print!` holds back goes out.
 pub fn read(fd: usize, buf: &mut [u8]) -> isize {
+    if fd == 0 {
+        console::flush();
+        if let Some(ret) = user_uart::stdio_read(buf) {
+            return ret;
+        }
+    }
     sys_read(fd, buf)
 }
 
+/// fd 1 and 2 write to the port set with `user_uart::redirect_stdio`, if
+/// any.
 pub fn write(fd: usize, buf: &[u8]) -> isize {
+    if fd == 1 || fd == 2 {
+        if let Some(ret) = user_uart::stdio_write(buf) {
+            return ret;
+        }
+    }
     sys_write(fd, buf)
 }
+
+/// Most buffers one `writev` takes.
+pub const IOV_MAX: usize = 16;
+
+/// `write` of several buffers in one syscall. Returns the bytes written,
+/// -5 for more than `IOV_MAX` buffers.
+pub fn writev(fd: usize, bufs: &[&[u8]]) -> isize {
+    if bufs.len() > IOV_MAX {
+        return -5;
+    }
+    if fd == 1 || fd == 2 {
+        if let Some((first, rest)) = bufs.split_first() {
+            if let Some(mut written) = user_uart::stdio_write(first) {
+                for buf in rest {
+                    written += user_uart::stdio_write(buf).unwrap_or(0);
+                }
+                return written;
+            }
+        }
+    }
+    let mut iov = [[0usize; 2]; IOV_MAX];
+    for (iov, buf) in iov.iter_mut().zip(bufs) {
+        *iov = [buf.as_ptr() as usize, buf.len()];
+    }
+    sys_writev(fd, &iov[..bufs.len()])
+}
+/// Prints what the console still holds and lets the claimed serial ports
+/// send what is still queued first, see `user_uart::drain_all`.
 pub fn exit(exit_code: i32) -> ! {
+    console::flush();
+    user_uart::drain_all();
     sys_exit(exit_code);
 }
 pub fn yield_() -> isize {
@@ -135,7 +189,7 @@ pub fn get_time() -> isize {
 pub fn get_time_us() -> isize {
     let time = TimeVal::new();
     match sys_get_time(&time, 0) {
-        0 => ((time.sec & 0xffff) * 1000_0000 + time.usec) as isize,
+        0 => ((time.sec & 0xffff) * 1_000_000 + time.usec) as isize,
         _ => -1,
     }
 }
@@ -143,7 +197,9 @@ pub fn get_time_us() -> isize {
 pub fn getpid() -> isize {
     sys_getpid()
 }
+/// Prints what the console holds first, or both processes would.
 pub fn fork() -> isize {
+    console::flush();
     sys_fork()
 }
 pub fn exec(path: &str, args: &[*const u8]) -> isize {
@@ -213,3 +269,84 @@ pub fn claim_ext_int(device_id: usize) -> isize {
 pub fn set_ext_int_enable(device_id: usize, enable: usize) -> isize {
     sys_set_ext_int_enable(device_id, enable)
 }
+
+pub fn release_ext_int(device_id: usize) -> isize {
+    irq::forget_priority(device_id as u16);
+    sys_release_ext_int(device_id)
+}
+
+/// Pins a claimed device's interrupt to `hart`, `usize::MAX` unpins it.
+pub fn set_ext_int_affinity(device_id: usize, hart: usize) -> isize {
+    sys_set_ext_int_affinity(device_id, hart)
+}
+
+pub fn set_ext_int_priority(device_id: usize, priority: usize) -> isize {
+    let ret = sys_set_ext_int_priority(device_id, priority);
+    if ret == 0 {
+        // for the order of `irq::round_robin`
+        irq::note_priority(device_id as u16, priority);
+    }
+    ret
+}
+
+pub fn set_ext_int_threshold(threshold: usize) -> isize {
+    sys_set_ext_int_threshold(threshold)
+}
+
+/// Hands a claimed device to process `pid`, its registers are left as they
+/// are.
+pub fn transfer_ext_int(device_id: usize, pid: usize) -> isize {
+    irq::forget_priority(device_id as u16);
+    sys_transfer_ext_int(device_id, pid)
+}
+
+/// Maps a ring the console input is copied into and returns its address.
+pub fn open_console_ring() -> isize {
+    sys_open_console_ring()
+}
+
+/// Blocks until the console ring has input, 0 when it has and -2 after
+/// `timeout_us` (0 waits forever).
+pub fn wait_console_ring(timeout_us: usize) -> isize {
+    sys_wait_console_ring(timeout_us)
+}
+
+/// Has the kernel send a software interrupt on console line errors, see
+/// `console::error_events`.
+pub fn listen_console_errors(enable: bool) -> isize {
+    sys_listen_console_errors(enable as usize)
+}
+
+pub fn close_console_ring() -> isize {
+    sys_close_console_ring()
+}
+
+/// May read the registers of any serial, see `user_uart::serial::debug_dump`.
+pub const CAP_SERIAL_DEBUG: usize = 1 << 0;
+
+/// Gives up the `CAP_*` bits in `caps` for this process and the ones it
+/// forks from now on. Returns the bits left.
+pub fn drop_caps(caps: usize) -> isize {
+    sys_drop_caps(caps)
+}
+
+/// Snapshots the registers of serial `serial_id`, 0 on success, -1 without
+/// `CAP_SERIAL_DEBUG` and -2 if there is no such serial.
+pub fn dump_serial_regs(
+    serial_id: usize,
+    snapshot: &mut user_uart::serial::UartRegSnapshot,
+) -> isize {
+    sys_dump_serial_regs(serial_id, snapshot)
+}
+
+/// Blocks until a claimed device interrupts, 0 on interrupt and -2 after
+/// `timeout_us` (0 waits forever).
+pub fn wait_ext_int(device_id: usize, timeout_us: usize) -> isize {
+    sys_wait_ext_int(device_id, timeout_us)
+}
+
+/// Fills `ports` and returns how many ports the kernel has, which may be
+/// more than fit.
+pub fn enumerate_serial(ports: &mut [user_uart::serial::SerialPortInfo]) -> isize {
+    sys_enumerate_serial(ports)
+}
@@ -0,0 +1,295 @@
+use crate::future::{select2, Either};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use core::cell::UnsafeCell;
+use core::future::Future;
+use core::ops::{Deref, DerefMut};
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::task::{Context, Poll, Waker};
+
+/// An async mutex. Waiting tasks are parked on a FIFO list and the lock is
+/// handed over to the first waiter on release, so a later `lock()` can never
+/// barge ahead of a task that is already waiting.
+pub struct Mutex<T: ?Sized> {
+    state: spin::Mutex<MutexState>,
+    value: UnsafeCell<T>,
+}
+
+struct MutexState {
+    locked: bool,
+    next_ticket: usize,
+    waiters: VecDeque<Waiter>,
+}
+
+struct Waiter {
+    ticket: usize,
+    waker: Option<Waker>,
+    /// The lock has been handed over to this waiter but it has not been polled yet.
+    granted: bool,
+}
+
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub fn new(value: T) -> Self {
+        Mutex {
+            state: spin::Mutex::new(MutexState {
+                locked: false,
+                next_ticket: 0,
+                waiters: VecDeque::new(),
+            }),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    pub fn lock(&self) -> MutexLockFuture<'_, T> {
+        MutexLockFuture {
+            mutex: self,
+            ticket: None,
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        let mut state = self.state.lock();
+        if !state.locked && state.waiters.is_empty() {
+            state.locked = true;
+            Some(MutexGuard { mutex: self })
+        } else {
+            None
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.state.lock().locked
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    /// Pass the lock to the first waiter, or mark it free if nobody is waiting.
+    fn unlock(&self) {
+        let waker = {
+            let mut state = self.state.lock();
+            match state.waiters.front_mut() {
+                Some(waiter) => {
+                    waiter.granted = true;
+                    waiter.waker.take()
+                }
+                None => {
+                    state.locked = false;
+                    None
+                }
+            }
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Mutex::new(T::default())
+    }
+}
+
+pub struct MutexLockFuture<'a, T: ?Sized> {
+    mutex: &'a Mutex<T>,
+    ticket: Option<usize>,
+}
+
+impl<'a, T: ?Sized> Future for MutexLockFuture<'a, T> {
+    type Output = MutexGuard<'a, T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mutex = self.mutex;
+        let mut state = mutex.state.lock();
+        match self.ticket {
+            None => {
+                if !state.locked && state.waiters.is_empty() {
+                    state.locked = true;
+                    return Poll::Ready(MutexGuard { mutex });
+                }
+                let ticket = state.next_ticket;
+                state.next_ticket = state.next_ticket.wrapping_add(1);
+                state.waiters.push_back(Waiter {
+                    ticket,
+                    waker: Some(cx.waker().clone()),
+                    granted: false,
+                });
+                drop(state);
+                self.ticket = Some(ticket);
+                Poll::Pending
+            }
+            Some(ticket) => {
+                let pos = state
+                    .waiters
+                    .iter()
+                    .position(|w| w.ticket == ticket)
+                    .unwrap();
+                if state.waiters[pos].granted {
+                    state.waiters.remove(pos);
+                    drop(state);
+                    self.ticket = None;
+                    Poll::Ready(MutexGuard { mutex })
+                } else {
+                    let waiter = &mut state.waiters[pos];
+                    match waiter.waker.as_ref() {
+                        Some(waker) if waker.will_wake(cx.waker()) => {}
+                        _ => waiter.waker = Some(cx.waker().clone()),
+                    }
+                    Poll::Pending
+                }
+            }
+        }
+    }
+}
+
+impl<T: ?Sized> Drop for MutexLockFuture<'_, T> {
+    fn drop(&mut self) {
+        if let Some(ticket) = self.ticket {
+            let mut state = self.mutex.state.lock();
+            if let Some(pos) = state.waiters.iter().position(|w| w.ticket == ticket) {
+                let waiter = state.waiters.remove(pos).unwrap();
+                drop(state);
+                // the lock was already handed to us, pass it on
+                if waiter.granted {
+                    self.mutex.unlock();
+                }
+            }
+        }
+    }
+}
+
+pub struct MutexGuard<'a, T: ?Sized> {
+    mutex: &'a Mutex<T>,
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for MutexGuard<'_, T> {}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+
+/// Error of an operation that was cut short by a `CancellationToken`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cancelled;
+
+/// A cancellation flag shared by clones. Cancelling wakes every task that
+/// waits in `cancelled()`. Tasks observe it at their await points, see
+/// `run_until_cancelled`.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<CancelInner>,
+}
+
+#[derive(Default)]
+struct CancelInner {
+    cancelled: AtomicBool,
+    next_waiter: AtomicUsize,
+    waiters: spin::Mutex<BTreeMap<usize, Waker>>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        if !self.inner.cancelled.swap(true, Ordering::AcqRel) {
+            let waiters = core::mem::take(&mut *self.inner.waiters.lock());
+            for waker in waiters.into_values() {
+                waker.wake();
+            }
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Completes once the token is cancelled.
+    pub fn cancelled(&self) -> CancelledFuture<'_> {
+        CancelledFuture {
+            token: self,
+            waiter: None,
+        }
+    }
+
+    /// Run `future` until it completes or the token is cancelled, whichever
+    /// comes first. On cancellation `future` is dropped, which is where the
+    /// serial and timer futures unregister their wakers.
+    pub async fn run_until_cancelled<F: Future>(&self, future: F) -> Option<F::Output> {
+        match select2(future, self.cancelled()).await {
+            Either::Left(output) => Some(output),
+            Either::Right(()) => None,
+        }
+    }
+}
+
+pub struct CancelledFuture<'a> {
+    token: &'a CancellationToken,
+    waiter: Option<usize>,
+}
+
+impl Future for CancelledFuture<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let inner = &self.token.inner;
+        if inner.cancelled.load(Ordering::Acquire) {
+            return Poll::Ready(());
+        }
+        let id = match self.waiter {
+            Some(id) => id,
+            None => inner.next_waiter.fetch_add(1, Ordering::Relaxed),
+        };
+        let mut waiters = inner.waiters.lock();
+        // check again under the lock, `cancel` drains the list after setting the flag
+        if inner.cancelled.load(Ordering::Acquire) {
+            return Poll::Ready(());
+        }
+        match waiters.get(&id) {
+            Some(waker) if waker.will_wake(cx.waker()) => {}
+            _ => {
+                waiters.insert(id, cx.waker().clone());
+            }
+        }
+        drop(waiters);
+        self.waiter = Some(id);
+        Poll::Pending
+    }
+}
+
+impl Drop for CancelledFuture<'_> {
+    fn drop(&mut self) {
+        if let Some(id) = self.waiter {
+            self.token.inner.waiters.lock().remove(&id);
+        }
+    }
+}
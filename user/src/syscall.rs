@@ -1,5 +1,6 @@
 use crate::{
     trace::{push_trace, TRACE_SYSCALL_ENTER, TRACE_SYSCALL_EXIT},
+    user_uart::serial::{SerialPortInfo, UartRegSnapshot},
     TimeVal,
 };
 use core::arch::asm;
@@ -10,6 +11,7 @@ const SYSCALL_CLOSE: usize = 57;
 const SYSCALL_PIPE: usize = 59;
 const SYSCALL_READ: usize = 63;
 const SYSCALL_WRITE: usize = 64;
+const SYSCALL_WRITEV: usize = 66;
 const SYSCALL_EXIT: usize = 93;
 const SYSCALL_YIELD: usize = 124;
 const SYSCALL_GET_TIME: usize = 169;
@@ -26,6 +28,23 @@ const SYSCALL_SEND_MSG: usize = 601;
 const SYSCALL_SET_TIMER: usize = 602;
 const SYSCALL_CLAIM_EXT_INT: usize = 603;
 const SYSCALL_SET_EXT_INT_ENABLE: usize = 604;
+const SYSCALL_RELEASE_EXT_INT: usize = 605;
+const SYSCALL_ENUMERATE_SERIAL: usize = 606;
+const SYSCALL_WAIT_EXT_INT: usize = 607;
+const SYSCALL_SET_EXT_INT_AFFINITY: usize = 608;
+const SYSCALL_SET_EXT_INT_PRIORITY: usize = 609;
+const SYSCALL_SET_EXT_INT_THRESHOLD: usize = 610;
+const SYSCALL_TRANSFER_EXT_INT: usize = 611;
+const SYSCALL_OPEN_CONSOLE_RING: usize = 612;
+const SYSCALL_WAIT_CONSOLE_RING: usize = 613;
+const SYSCALL_CLOSE_CONSOLE_RING: usize = 614;
+const SYSCALL_GET_EXT_INT_STATS: usize = 615;
+const SYSCALL_DUMP_SERIAL_REGS: usize = 616;
+const SYSCALL_DROP_CAPS: usize = 617;
+const SYSCALL_SET_EXT_INT_WATCHDOG: usize = 618;
+const SYSCALL_SET_EXT_INT_LIMIT: usize = 619;
+const SYSCALL_LIST_EXT_INT: usize = 620;
+const SYSCALL_LISTEN_CONSOLE_ERRORS: usize = 621;
 
 fn syscall(id: usize, args: [usize; 3]) -> isize {
     let mut ret: isize;
@@ -65,6 +84,11 @@ pub fn sys_write(fd: usize, buffer: &[u8]) -> isize {
     syscall(SYSCALL_WRITE, [fd, buffer.as_ptr() as usize, buffer.len()])
 }
 
+/// Each of `iov` is a base address and a length.
+pub fn sys_writev(fd: usize, iov: &[[usize; 2]]) -> isize {
+    syscall(SYSCALL_WRITEV, [fd, iov.as_ptr() as usize, iov.len()])
+}
+
 pub fn sys_exit(exit_code: i32) -> ! {
     syscall(SYSCALL_EXIT, [exit_code as usize, 0, 0]);
     panic!("sys_exit never returns!");
@@ -133,3 +157,83 @@ pub fn sys_claim_ext_int(device_id: usize) -> isize {
 pub fn sys_set_ext_int_enable(device_id: usize, enable: usize) -> isize {
     syscall(SYSCALL_SET_EXT_INT_ENABLE, [device_id as usize, enable, 0])
 }
+
+pub fn sys_release_ext_int(device_id: usize) -> isize {
+    syscall(SYSCALL_RELEASE_EXT_INT, [device_id, 0, 0])
+}
+
+pub fn sys_enumerate_serial(ports: &mut [SerialPortInfo]) -> isize {
+    syscall(
+        SYSCALL_ENUMERATE_SERIAL,
+        [ports.as_mut_ptr() as usize, ports.len(), 0],
+    )
+}
+
+pub fn sys_wait_ext_int(device_id: usize, timeout_us: usize) -> isize {
+    syscall(SYSCALL_WAIT_EXT_INT, [device_id, timeout_us, 0])
+}
+
+pub fn sys_set_ext_int_affinity(device_id: usize, hart: usize) -> isize {
+    syscall(SYSCALL_SET_EXT_INT_AFFINITY, [device_id, hart, 0])
+}
+
+pub fn sys_set_ext_int_priority(device_id: usize, priority: usize) -> isize {
+    syscall(SYSCALL_SET_EXT_INT_PRIORITY, [device_id, priority, 0])
+}
+
+pub fn sys_set_ext_int_threshold(threshold: usize) -> isize {
+    syscall(SYSCALL_SET_EXT_INT_THRESHOLD, [threshold, 0, 0])
+}
+
+pub fn sys_transfer_ext_int(device_id: usize, pid: usize) -> isize {
+    syscall(SYSCALL_TRANSFER_EXT_INT, [device_id, pid, 0])
+}
+
+pub fn sys_open_console_ring() -> isize {
+    syscall(SYSCALL_OPEN_CONSOLE_RING, [0, 0, 0])
+}
+
+pub fn sys_wait_console_ring(timeout_us: usize) -> isize {
+    syscall(SYSCALL_WAIT_CONSOLE_RING, [timeout_us, 0, 0])
+}
+
+pub fn sys_close_console_ring() -> isize {
+    syscall(SYSCALL_CLOSE_CONSOLE_RING, [0, 0, 0])
+}
+
+pub fn sys_get_ext_int_stats(irq: usize, counts: &mut [usize; 8]) -> isize {
+    syscall(
+        SYSCALL_GET_EXT_INT_STATS,
+        [irq, counts.as_mut_ptr() as usize, 0],
+    )
+}
+
+pub fn sys_dump_serial_regs(serial_id: usize, snapshot: &mut UartRegSnapshot) -> isize {
+    syscall(
+        SYSCALL_DUMP_SERIAL_REGS,
+        [serial_id, snapshot as *mut _ as usize, 0],
+    )
+}
+
+pub fn sys_drop_caps(caps: usize) -> isize {
+    syscall(SYSCALL_DROP_CAPS, [caps, 0, 0])
+}
+
+pub fn sys_set_ext_int_watchdog(timeout_us: usize) -> isize {
+    syscall(SYSCALL_SET_EXT_INT_WATCHDOG, [timeout_us, 0, 0])
+}
+
+pub fn sys_set_ext_int_limit(limit: usize) -> isize {
+    syscall(SYSCALL_SET_EXT_INT_LIMIT, [limit, 0, 0])
+}
+
+pub fn sys_list_ext_int(irqs: &mut [usize]) -> isize {
+    syscall(
+        SYSCALL_LIST_EXT_INT,
+        [irqs.as_mut_ptr() as usize, irqs.len(), 0],
+    )
+}
+
+pub fn sys_listen_console_errors(enable: usize) -> isize {
+    syscall(SYSCALL_LISTEN_CONSOLE_ERRORS, [enable, 0, 0])
+}
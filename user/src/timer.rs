@@ -0,0 +1,145 @@
+use crate::future::{select2, Either};
+use crate::uintr::critical_section;
+use crate::{get_time_us, set_timer};
+use alloc::collections::BTreeMap;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::task::{Context, Poll, Waker};
+use lazy_static::*;
+use spin::Mutex;
+
+static NEXT_SLEEP_ID: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static! {
+    /// Pending sleeps ordered by (deadline, id). Only locked with user
+    /// interrupts masked, the timer handler takes it too.
+    static ref SLEEPERS: Mutex<BTreeMap<(usize, usize), Waker>> = Mutex::new(BTreeMap::new());
+}
+
+/// Current time in microseconds, on the clock `set_timer` uses.
+pub fn now_us() -> usize {
+    get_time_us() as usize
+}
+
+/// The hart's cycle counter, for spans measured on one hart.
+#[inline]
+pub fn cycles() -> usize {
+    let cycles;
+    unsafe { core::arch::asm!("rdcycle {}", out(reg) cycles) };
+    cycles
+}
+
+/// Wake the sleeps that are due and arm the user timer for the next one.
+/// Call it from `timer_intr_handler`. Returns false if nothing was due.
+pub fn on_timer_interrupt() -> bool {
+    let now = now_us();
+    let mut woken = false;
+    loop {
+        // the due waker, or the next deadline
+        let due = critical_section(|| {
+            let mut sleepers = SLEEPERS.lock();
+            match sleepers.keys().next().copied() {
+                Some(key) if key.0 <= now => Ok(sleepers.remove(&key).unwrap()),
+                next => Err(next.map(|key| key.0)),
+            }
+        });
+        // wake and arm outside the section, both may make a syscall
+        match due {
+            Ok(waker) => {
+                waker.wake();
+                woken = true;
+            }
+            Err(next) => {
+                if let Some(deadline) = next {
+                    set_timer(deadline as isize);
+                }
+                break;
+            }
+        }
+    }
+    woken
+}
+
+/// Completes once `now_us()` reaches the deadline. Needs the user timer
+/// interrupt (`uie::set_utimer`) to be woken, or a caller that keeps
+/// polling such as `block_on_with`.
+pub struct Sleep {
+    deadline: usize,
+    id: usize,
+    registered: bool,
+}
+
+impl Sleep {
+    pub fn until(deadline_us: usize) -> Self {
+        Sleep {
+            deadline: deadline_us,
+            id: NEXT_SLEEP_ID.fetch_add(1, Ordering::Relaxed),
+            registered: false,
+        }
+    }
+
+    pub fn deadline(&self) -> usize {
+        self.deadline
+    }
+}
+
+pub fn sleep_us(period_us: usize) -> Sleep {
+    Sleep::until(now_us() + period_us)
+}
+
+/// `timeout` gave up before the future completed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimedOut;
+
+/// Runs `future` for at most `period_us`. The future is dropped if the
+/// time runs out first. Woken like `Sleep`.
+pub async fn timeout<F: Future>(period_us: usize, future: F) -> Result<F::Output, TimedOut> {
+    match select2(future, sleep_us(period_us)).await {
+        Either::Left(output) => Ok(output),
+        Either::Right(()) => Err(TimedOut),
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let key = (self.deadline, self.id);
+        if now_us() >= self.deadline {
+            if self.registered {
+                critical_section(|| SLEEPERS.lock().remove(&key));
+                self.registered = false;
+            }
+            return Poll::Ready(());
+        }
+        let arm = critical_section(|| {
+            let mut sleepers = SLEEPERS.lock();
+            match sleepers.get(&key) {
+                Some(waker) if waker.will_wake(cx.waker()) => false,
+                Some(_) => {
+                    sleepers.insert(key, cx.waker().clone());
+                    false
+                }
+                None => {
+                    sleepers.insert(key, cx.waker().clone());
+                    sleepers.keys().next() == Some(&key)
+                }
+            }
+        });
+        self.registered = true;
+        // only a new earliest deadline needs the timer armed
+        if arm {
+            set_timer(self.deadline as isize);
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if self.registered {
+            critical_section(|| SLEEPERS.lock().remove(&(self.deadline, self.id)));
+        }
+    }
+}
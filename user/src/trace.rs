@@ -25,6 +25,10 @@ pub const U_TRAP_RETURN: usize = 0xc7ab_9000;
 pub const U_EXT_HANDLER: usize = 0xc7ab_a000;
 pub const U_SOFT_HANDLER: usize = 0xc7ab_b000;
 pub const U_TIMER_HANDLER: usize = 0xc7ab_c000;
+/// A user trap handler started at nesting depth 11:0.
+pub const U_TRAP_NESTED: usize = 0xc7ab_d000;
+/// Cycles a user trap handler kept interrupts masked, 11:0, saturated.
+pub const U_TRAP_HELD_OFF: usize = 0xc7ab_e000;
 
 // syscall
 pub const TRACE_SYSCALL_ENTER: usize = 0x575c_0000;
@@ -47,6 +51,29 @@ pub const SERIAL_RTS: usize = 0x5e1a_6000;
 pub const SERIAL_CTS: usize = 0x5e1a_7000;
 pub const SERIAL_TX: usize = 0x5e1a_8000;
 pub const SERIAL_RX: usize = 0x5e1a_9000;
+pub const SERIAL_WATCHDOG: usize = 0x5e1a_a000;
+// rx interrupts by cause, the bytes drained in bits 11:0
+pub const SERIAL_RX_DATA: usize = 0x5e1a_b000;
+pub const SERIAL_RX_TIMEOUT: usize = 0x5e1a_c000;
+/// LSR.OE seen, the port's overrun count in bits 11:0.
+pub const SERIAL_OVERRUN: usize = 0x5e1a_d000;
+/// `interrupt_handler` gave up with the IRQ raised, the last IID in bits
+/// 3:0 and `SERIAL_INTR_BAILOUT_UNKNOWN` if the driver does not know it.
+pub const SERIAL_INTR_BAILOUT: usize = 0x5e1a_e000;
+pub const SERIAL_INTR_BAILOUT_UNKNOWN: usize = 0x10;
+/// A driver tore its port down in `Drop`, the tx bytes it lost in bits
+/// 11:0, see `user_uart::last_drop_report`.
+pub const SERIAL_DROP: usize = 0x5e1a_f000;
+// `AsyncSerial::set_rx_hook` calls, the byte in bits 7:0 on enter and
+// whether the hook took it in bit 0 on exit
+pub const SERIAL_RX_HOOK_ENTER: usize = 0x5e1b_0000;
+pub const SERIAL_RX_HOOK_EXIT: usize = 0x5e1b_1000;
+/// An `interrupt_handler` call went over its budget, the cycles it took
+/// in budgets in bits 11:0, see `AsyncSerial::set_handler_budget`.
+pub const SERIAL_INTR_OVER_BUDGET: usize = 0x5e1b_2000;
+
+// defmt frames kept for export, the byte in bits 7:0
+pub const DEFMT_BYTE: usize = 0xdef7_0000;
 
 // PLIC
 pub const PLIC_CLAIM: usize = 0x911c_0000;
@@ -62,6 +89,15 @@ pub const ASYNC_WRITE_POLL: usize = 0xa57c_4000;
 pub const ASYNC_WRITE_WAKE: usize = 0xa57c_5000;
 pub const ASYNC_INTR_POLL: usize = 0xa57c_6000;
 pub const ASYNC_INTR_WAKE: usize = 0xa57c_7000;
+pub const ASYNC_TASK_POLL_ENTER: usize = 0xa57c_8000;
+pub const ASYNC_TASK_POLL_EXIT: usize = 0xa57c_9000;
+pub const ASYNC_FUTURE_POLL_ENTER: usize = 0xa57c_a000;
+pub const ASYNC_FUTURE_POLL_EXIT: usize = 0xa57c_b000;
+pub const ASYNC_FUTURE_READY: usize = 0xa57c_c000;
+
+// codes of futures wrapped by `future::traced`, bits 11:8 of the event
+pub const FUTURE_SERIAL_READ: usize = 0x1;
+pub const FUTURE_SERIAL_WRITE: usize = 0x2;
 
 // misc
 pub const TRACE_TEST: usize = 0x315c_0000;
@@ -99,6 +135,8 @@ pub fn push_trace(event_id: usize) -> usize {
         eid_ext = out(reg) _,
         )
     }
+    #[cfg(feature = "mock_uart")]
+    capture::record(event_id);
     cycle
 }
 
@@ -118,3 +156,77 @@ pub fn clear_trace() {
         )
     }
 }
+
+/// One entry of the trace queue. `event_id` carries the hart in bits 35:32
+/// and the pid in bits 39:36.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TraceEvent {
+    pub event_id: usize,
+    pub cycle: usize,
+}
+
+/// Copies the last events pushed, by any hart or process, oldest first.
+/// Returns how many were copied, always 0 without tracing.
+pub fn last_trace_events(buf: &mut [TraceEvent]) -> usize {
+    if !cfg!(all(feature = "board_lrv", feature = "trace")) {
+        return 0;
+    }
+    let head = (MEMORY_END + 16) as *const TraceEvent;
+    unsafe {
+        let tail = (MEMORY_END as *const *const TraceEvent).read_volatile();
+        let len = buf.len().min(tail.offset_from(head).max(0) as usize);
+        for (i, event) in buf[..len].iter_mut().enumerate() {
+            *event = tail.sub(len - i).read_volatile();
+        }
+        len
+    }
+}
+
+#[cfg(feature = "mock_uart")]
+pub use capture::{start_capture, stop_capture, CAPTURE_LEN};
+
+/// Nothing records the trace on the mock UART, its tests check what the
+/// driver pushes through a capture instead. Event ids only, no cycles.
+#[cfg(feature = "mock_uart")]
+mod capture {
+    use alloc::vec::Vec;
+    use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering::*};
+
+    /// Events a capture keeps, the ones after it are only counted.
+    pub const CAPTURE_LEN: usize = 1024;
+
+    const NO_EVENT: AtomicUsize = AtomicUsize::new(0);
+    static EVENTS: [AtomicUsize; CAPTURE_LEN] = [NO_EVENT; CAPTURE_LEN];
+    static PUSHED: AtomicUsize = AtomicUsize::new(0);
+    static CAPTURING: AtomicBool = AtomicBool::new(false);
+
+    pub(super) fn record(event_id: usize) {
+        if !CAPTURING.load(Relaxed) {
+            return;
+        }
+        let at = PUSHED.fetch_add(1, Relaxed);
+        if let Some(slot) = EVENTS.get(at) {
+            slot.store(event_id, Relaxed);
+        }
+    }
+
+    /// Starts keeping the events the process pushes, from interrupt
+    /// handlers too. Drops what an earlier capture kept.
+    pub fn start_capture() {
+        CAPTURING.store(false, SeqCst);
+        PUSHED.store(0, SeqCst);
+        CAPTURING.store(true, SeqCst);
+    }
+
+    /// The events pushed since `start_capture`, oldest first, or `None` if
+    /// more than `CAPTURE_LEN` came.
+    pub fn stop_capture() -> Option<Vec<usize>> {
+        CAPTURING.store(false, SeqCst);
+        let len = PUSHED.load(SeqCst);
+        if len > CAPTURE_LEN {
+            return None;
+        }
+        Some(EVENTS[..len].iter().map(|event| event.load(Relaxed)).collect())
+    }
+}
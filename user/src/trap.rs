@@ -1,4 +1,9 @@
+use crate::executor::MAX_HART_NUM;
+use crate::irq::MAX_BURST;
+use crate::timer::cycles;
+use crate::uintr;
 use core::arch::{asm, global_asm};
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
 use heapless::spsc::Queue;
 use riscv::register::{ucause, uepc, uip, ustatus::Ustatus, utval};
 
@@ -11,7 +16,8 @@ const MAX_USER_TRAP_NUM: usize = 128;
 use rv_plic::PLIC;
 
 use crate::trace::{
-    push_trace, PLIC_CLAIM, TRAP_QUEUE_ENTER, TRAP_QUEUE_EXIT, U_TRAP_HANDLER, U_TRAP_RETURN,
+    push_trace, PLIC_CLAIM, TRAP_QUEUE_ENTER, TRAP_QUEUE_EXIT, U_TRAP_HANDLER, U_TRAP_HELD_OFF,
+    U_TRAP_NESTED, U_TRAP_RETURN,
 };
 pub const PLIC_BASE: usize = 0xc00_0000;
 pub const PLIC_PRIORITY_BIT: usize = 3;
@@ -57,6 +63,135 @@ pub struct UserTrapRecord {
 pub type UserTrapQueue = Queue<UserTrapRecord, MAX_USER_TRAP_NUM>;
 global_asm!(include_str!("trap.asm"));
 
+/// Deepest `set_nesting` allows.
+pub const MAX_NEST_DEPTH: usize = 4;
+
+/// Classes of user interrupt by nesting priority. A handler can only be
+/// interrupted by a higher class.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TrapClass {
+    External = 1,
+    /// Also carries the external interrupts the kernel forwards.
+    Soft = 2,
+    Timer = 3,
+}
+
+/// The uie bits of the classes above `class`.
+fn higher_uie_bits(class: usize) -> usize {
+    const USIE: usize = 1 << 0;
+    const UTIE: usize = 1 << 4;
+    match class {
+        0 | 1 => USIE | UTIE,
+        2 => UTIE,
+        _ => 0,
+    }
+}
+
+/// Handlers nested this deep run with interrupts masked, 0 turns nesting
+/// off.
+static NEST_LIMIT: AtomicUsize = AtomicUsize::new(0);
+/// Only classes above it interrupt a handler.
+static NEST_THRESHOLD: AtomicUsize = AtomicUsize::new(0);
+const ZERO: AtomicUsize = AtomicUsize::new(0);
+const ZERO_LEVELS: [AtomicUsize; MAX_NEST_DEPTH] = [ZERO; MAX_NEST_DEPTH];
+static NEST_DEPTH: [AtomicUsize; MAX_HART_NUM] = [ZERO; MAX_HART_NUM];
+/// sp of each running level, per hart.
+static NEST_SP: [[AtomicUsize; MAX_NEST_DEPTH]; MAX_HART_NUM] = [ZERO_LEVELS; MAX_HART_NUM];
+static MAX_DEPTH_SEEN: AtomicUsize = AtomicUsize::new(0);
+static MAX_HELD_OFF: AtomicUsize = AtomicUsize::new(0);
+static LEVEL_STACK: [AtomicUsize; MAX_NEST_DEPTH] = ZERO_LEVELS;
+
+/// Lets a user interrupt handler be interrupted by a higher `TrapClass`
+/// above `threshold`, up to `max_depth` handlers deep, so a timer
+/// interrupt is not held off for a whole serial interrupt. The external
+/// source being handled stays claimed and can't come in again, other
+/// external sources wait for the handler. Nesting is off by default,
+/// `max_depth` 0 turns it off again.
+///
+/// Everything a handler shares with a higher class must then be taken in
+/// a `critical_section` or with `try_lock`.
+pub fn set_nesting(max_depth: usize, threshold: Option<TrapClass>) {
+    NEST_THRESHOLD.store(threshold.map_or(0, |class| class as usize), Relaxed);
+    NEST_LIMIT.store(max_depth.min(MAX_NEST_DEPTH), Relaxed);
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NestStats {
+    /// Handlers found running at once on a hart.
+    pub max_depth: usize,
+    /// Longest a handler kept the other classes masked, in cycles. Handlers
+    /// that can't nest count whole.
+    pub max_held_off_cycles: usize,
+    /// Stack a handler at each depth used, from the first, measured when
+    /// the next one came in. Trap frames are included.
+    pub level_stack: [usize; MAX_NEST_DEPTH],
+}
+
+pub fn nest_stats() -> NestStats {
+    NestStats {
+        max_depth: MAX_DEPTH_SEEN.load(Relaxed),
+        max_held_off_cycles: MAX_HELD_OFF.load(Relaxed),
+        level_stack: core::array::from_fn(|level| LEVEL_STACK[level].load(Relaxed)),
+    }
+}
+
+#[inline]
+fn stack_pointer() -> usize {
+    let sp: usize;
+    unsafe {
+        asm!("mv {}, sp", out(reg) sp);
+    }
+    sp
+}
+
+/// Runs the handler `f` of a `class` interrupt, with the higher classes
+/// unmasked if nesting allows.
+fn nested<R>(class: TrapClass, f: impl FnOnce() -> R) -> R {
+    let entered = cycles();
+    let hart = hart_id() % MAX_HART_NUM;
+    let depth = NEST_DEPTH[hart].fetch_add(1, Relaxed) + 1;
+    MAX_DEPTH_SEEN.fetch_max(depth, Relaxed);
+    push_trace(U_TRAP_NESTED | depth);
+    let sp = stack_pointer();
+    if depth <= MAX_NEST_DEPTH {
+        NEST_SP[hart][depth - 1].store(sp, Relaxed);
+    }
+    if depth >= 2 && depth - 2 < MAX_NEST_DEPTH {
+        let used = NEST_SP[hart][depth - 2].load(Relaxed).saturating_sub(sp);
+        LEVEL_STACK[depth - 2].fetch_max(used, Relaxed);
+    }
+
+    let uie = uintr::swap_uie(0);
+    // only the classes the program enabled
+    let unmask = uie & higher_uie_bits((class as usize).max(NEST_THRESHOLD.load(Relaxed)));
+    let ret = if depth < NEST_LIMIT.load(Relaxed) && unmask != 0 {
+        uintr::swap_uie(unmask);
+        held_off(cycles().wrapping_sub(entered));
+        uintr::enable();
+        let ret = f();
+        uintr::disable();
+        uintr::swap_uie(uie);
+        ret
+    } else {
+        uintr::swap_uie(uie);
+        let ret = f();
+        held_off(cycles().wrapping_sub(entered));
+        ret
+    };
+    NEST_DEPTH[hart].fetch_sub(1, Relaxed);
+    ret
+}
+
+/// Whether a user interrupt handler is running on this hart.
+pub fn in_interrupt() -> bool {
+    NEST_DEPTH[hart_id() % MAX_HART_NUM].load(Relaxed) != 0
+}
+
+fn held_off(cycles: usize) {
+    MAX_HELD_OFF.fetch_max(cycles, Relaxed);
+    push_trace(U_TRAP_HELD_OFF | cycles.min(0xfff));
+}
+
 #[linkage = "weak"]
 #[no_mangle]
 pub fn user_trap_handler(cx: &mut UserTrapContext) -> &mut UserTrapContext {
@@ -74,39 +209,64 @@ pub fn user_trap_handler(cx: &mut UserTrapContext) -> &mut UserTrapContext {
             unsafe {
                 uip::clear_usoft();
             }
-            while let Some(trap_record) = trap_queue.dequeue() {
-                let cause = trap_record.cause;
-                let msg = trap_record.message;
-                if cause & 0xF == 0 {
-                    // "real" soft interrupt
-                    let pid = cause >> 4;
-                    soft_intr_handler(pid, msg);
-                } else if ucause::Interrupt::from(cause) == ucause::Interrupt::UserExternal {
-                    let irq = trap_record.message as u16;
-                    // push_trace(U_TRAP_HANDLER | 8 | 128);
-                    ext_intr_handler(irq, true);
-                } else if ucause::Interrupt::from(cause) == ucause::Interrupt::UserTimer {
-                    timer_intr_handler(msg);
+            nested(TrapClass::Soft, || {
+                while let Some(trap_record) = trap_queue.dequeue() {
+                    let cause = trap_record.cause;
+                    let msg = trap_record.message;
+                    if cause & 0xF == 0 {
+                        // "real" soft interrupt
+                        let pid = cause >> 4;
+                        soft_intr_handler(pid, msg);
+                    } else if ucause::Interrupt::from(cause) == ucause::Interrupt::UserExternal {
+                        let irq = trap_record.message as u16;
+                        // push_trace(U_TRAP_HANDLER | 8 | 128);
+                        ext_intr_handler(irq, true);
+                    } else if ucause::Interrupt::from(cause) == ucause::Interrupt::UserTimer {
+                        timer_intr_handler(msg);
+                    }
                 }
-            }
+            });
             // push_trace(TRAP_QUEUE_EXIT);
         }
         ucause::Trap::Interrupt(ucause::Interrupt::UserExternal) => {
-            while let Some(irq) = Plic::claim(get_context(hart_id(), 'U')) {
-                // push_trace(U_TRAP_HANDLER | 8 | 128);
-                push_trace(PLIC_CLAIM | get_context(hart_id(), 'U'));
-                ext_intr_handler(irq, false);
+            let context = get_context(hart_id(), 'U');
+            loop {
+                // everything pending, for the sources to take turns
+                let mut burst = heapless::Vec::<(u16, usize), MAX_BURST>::new();
+                while !burst.is_full() {
+                    match Plic::claim(context) {
+                        Some(irq) => {
+                            // push_trace(U_TRAP_HANDLER | 8 | 128);
+                            push_trace(PLIC_CLAIM | context);
+                            crate::irq::count_claim(irq);
+                            let _ = burst.push((irq, cycles()));
+                        }
+                        None => break,
+                    }
+                }
+                if burst.is_empty() {
+                    break;
+                }
+                crate::irq::round_robin(&mut burst);
+                // each source stays claimed until its handler completes it
+                for &(irq, claimed_at) in burst.iter() {
+                    nested(TrapClass::External, || {
+                        let latency = cycles().wrapping_sub(claimed_at);
+                        crate::user_uart::serial::record_service_latency(irq, latency);
+                        ext_intr_handler(irq, false)
+                    });
+                }
             }
             // println!("[user trap] user external finished");
         }
         ucause::Trap::Interrupt(ucause::Interrupt::UserTimer) => {
-            timer_intr_handler(0);
+            nested(TrapClass::Timer, || timer_intr_handler(0));
             unsafe {
                 uip::clear_utimer();
             }
         }
         _ => {
-            println!(
+            intr_println!(
                 "Unsupported trap {:?}, utval = {:#x}, uepc = {:#x}!",
                 ucause.cause(),
                 utval,
@@ -121,26 +281,41 @@ pub fn user_trap_handler(cx: &mut UserTrapContext) -> &mut UserTrapContext {
 #[linkage = "weak"]
 #[no_mangle]
 pub fn ext_intr_handler(irq: u16, is_from_kernel: bool) {
-    println!(
-        "[user trap default] user external interrupt, irq: {}, is_from_kernel: {}",
-        irq, is_from_kernel
-    );
+    let _complete = crate::irq::complete_guard(irq);
+    if !crate::user_uart::stdio_interrupt(irq)
+        && !crate::user_uart::serial::dispatch(irq)
+        && !crate::irq::dispatch(irq)
+    {
+        intr_println!(
+            "[user trap default] user external interrupt, irq: {}, is_from_kernel: {}",
+            irq,
+            is_from_kernel
+        );
+    }
 }
 
 #[linkage = "weak"]
 #[no_mangle]
 pub fn soft_intr_handler(pid: usize, msg: usize) {
-    println!(
-        "[user trap default] user software interrupt, pid: {}, msg: {:#x}",
-        pid, msg
-    );
+    if !crate::executor::handle_soft_interrupt(msg)
+        && !crate::console::handle_error_interrupt(msg)
+        && !crate::irq::handle_revoke_interrupt(msg)
+    {
+        intr_println!(
+            "[user trap default] user software interrupt, pid: {}, msg: {:#x}",
+            pid,
+            msg
+        );
+    }
 }
 
 #[linkage = "weak"]
 #[no_mangle]
 pub fn timer_intr_handler(time_us: usize) {
-    println!(
-        "[user trap default] user timer interrupt, time (us): {}",
-        time_us
-    );
+    if !crate::timer::on_timer_interrupt() {
+        intr_println!(
+            "[user trap default] user timer interrupt, time (us): {}",
+            time_us
+        );
+    }
 }
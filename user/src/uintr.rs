@@ -0,0 +1,84 @@
+use core::arch::asm;
+use core::sync::atomic::{compiler_fence, Ordering};
+
+/// Runs `f` with ustatus.UIE cleared, so no user interrupt handler of this
+/// hart can run in between. Sections nest: the bit is restored to what it
+/// was on entry, not set unconditionally. Other harts are not stopped, a
+/// lock shared with them is still needed, but a handler can then take it
+/// with `lock()` instead of `try_lock()` and a fallback.
+///
+/// Interrupts that come in meanwhile stay pending and are taken when the
+/// bit is restored, so the section length adds to their latency. Keep it
+/// to a few hundred instructions and never wait or make a syscall in it.
+/// The serial driver raises its rx interrupt two bytes before the FIFO is
+/// full, about 170 us at 115200 baud, and a section plus the handler must
+/// fit in that to not lose input.
+#[inline]
+pub fn critical_section<R>(f: impl FnOnce() -> R) -> R {
+    let uie = disable();
+    compiler_fence(Ordering::SeqCst);
+    let ret = f();
+    compiler_fence(Ordering::SeqCst);
+    restore(uie);
+    ret
+}
+
+/// ustatus.UIE is set.
+#[inline]
+pub fn is_enabled() -> bool {
+    let ustatus: usize;
+    unsafe {
+        asm!("csrr {}, ustatus", out(reg) ustatus);
+    }
+    ustatus & 1 != 0
+}
+
+/// Both ustatus.UIE and uie.UEIE are set, so a user external interrupt
+/// can be taken right now.
+#[inline]
+pub fn is_ext_enabled() -> bool {
+    let uie: usize;
+    unsafe {
+        asm!("csrr {}, uie", out(reg) uie);
+    }
+    is_enabled() && uie & (1 << 8) != 0
+}
+
+/// Clears ustatus.UIE and returns whether it was set.
+#[inline]
+pub(crate) fn disable() -> bool {
+    let ustatus: usize;
+    unsafe {
+        asm!("csrrci {}, ustatus, 1", out(reg) ustatus);
+    }
+    ustatus & 1 != 0
+}
+
+/// Sets ustatus.UIE again if `disable` found it set.
+#[inline]
+pub(crate) fn restore(uie: bool) {
+    if uie {
+        unsafe {
+            asm!("csrsi ustatus, 1");
+        }
+    }
+}
+
+/// Sets ustatus.UIE.
+#[inline]
+pub(crate) fn enable() {
+    unsafe {
+        asm!("csrsi ustatus, 1");
+    }
+}
+
+/// Writes the uie CSR, which enables each class of user interrupt, and
+/// returns what it held.
+#[inline]
+pub(crate) fn swap_uie(uie: usize) -> usize {
+    let old: usize;
+    unsafe {
+        asm!("csrrw {}, uie, {}", out(reg) old, in(reg) uie);
+    }
+    old
+}
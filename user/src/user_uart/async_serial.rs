@@ -0,0 +1,3403 @@
+use super::builder::QueueSlot;
+use super::coalesce::{CoalesceTimer, TxCoalesceTimer};
+use super::framed::{FrameCounters, FrameStats};
+use super::panic_dump::{register_panic_dump, PanicDump, QueueLen};
+use super::regs::*;
+use super::rx_ring::RxRing;
+use super::soft_flow::{SoftFlow, SoftFlowState, SoftFlowStats, XOFF, XON};
+use super::*;
+use crate::executor::MAX_HART_NUM;
+use crate::irq::{IrqError, IrqHandler, IrqRegistration};
+use crate::sync::{CancellationToken, Cancelled};
+use crate::timer::{cycles, now_us};
+use crate::timer::{sleep_us, timeout, Sleep};
+use crate::trace::{
+    ASYNC_READ_WAKE, ASYNC_WRITE_WAKE, SERIAL_DROP, SERIAL_INTR_BAILOUT,
+    SERIAL_INTR_BAILOUT_UNKNOWN, SERIAL_INTR_OVER_BUDGET, SERIAL_OVERRUN, SERIAL_RX_DATA,
+    SERIAL_RX_HOOK_ENTER, SERIAL_RX_HOOK_EXIT, SERIAL_RX_TIMEOUT, SERIAL_WATCHDOG,
+};
+use crate::trap::hart_id;
+use crate::uintr::critical_section;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::{AtomicU8, Ordering::SeqCst};
+use heapless::{spsc, Deque};
+use spin::{MutexGuard, Once};
+
+type RxProducer = spsc::Producer<'static, u8, DEFAULT_RX_BUFFER_SIZE>;
+type RxConsumer = spsc::Consumer<'static, u8, DEFAULT_RX_BUFFER_SIZE>;
+type TxProducer = spsc::Producer<'static, u8, DEFAULT_TX_BUFFER_SIZE>;
+type TxConsumer = spsc::Consumer<'static, u8, DEFAULT_TX_BUFFER_SIZE>;
+
+/// IIR reads per `interrupt_handler` call. A source still pending after
+/// them keeps the IRQ raised and is served by the next call.
+const MAX_INTR_ROUNDS: usize = 8;
+/// Unknown IIDs kept for `unknown_iids`, the oldest go first.
+pub const UNKNOWN_IID_LOG: usize = 4;
+/// Overruns kept for `read_available_checked`, later ones merge into the
+/// last.
+const MAX_OVERRUN_MARKS: usize = 8;
+/// Rx drains kept for `rx_timing_events`, the oldest go first.
+pub const RX_TIMING_LEN: usize = 32;
+/// Bytes `write_urgent` holds ahead of the tx queue.
+pub const URGENT_LANE_LEN: usize = 4;
+/// Most bytes a chunk of `fill_buf` lends without an rx ring.
+pub const RX_STAGE_LEN: usize = 256;
+/// Tasks that can wait on reads, or writes, of one port. More keep
+/// pushing each other out, which works but has them polled in turns.
+pub const MAX_WAITERS: usize = 8;
+
+/// Interrupt driven 16550 driver. Runs on the PAC registers of a mapped
+/// port by default, or on any other `UartRegisters`.
+///
+/// Send and Sync by its fields, without an `unsafe impl`: the registers
+/// are `Send + Sync` by the `UartRegisters` bound, the queue halves are
+/// only used under their spin mutexes, and the waiters keep their wakers
+/// under one too, with the hart each was registered on, which the next
+/// poll updates. So the driver, its read and write futures and a task
+/// pending on them may move to another hart; `assert_cross_hart` fails
+/// the build if a field breaks that.
+pub struct AsyncSerial<R: UartRegisters = UartMmio> {
+    regs: R,
+    rx_pro: Mutex<RxProducer>,
+    /// Also held by readers copying out of `rx_ring`.
+    rx_con: Mutex<RxConsumer>,
+    /// Takes the received bytes in place of `rx_pro` once attached.
+    rx_ring: Once<RxRing>,
+    /// Bytes `fill_buf` moved out of the rx queue, locked while a chunk
+    /// lends them.
+    rx_stage: Mutex<RxStage>,
+    /// What `rx_stage` holds, for `rx_len` to see without the lock.
+    rx_staged: AtomicUsize,
+    tx_pro: Mutex<TxProducer>,
+    tx: Arc<TxDrain<R>>,
+    pub rx_count: AtomicUsize,
+    pub tx_count: AtomicUsize,
+    pub intr_count: AtomicUsize,
+    /// Received data available interrupts, the rx FIFO reached its
+    /// trigger level.
+    pub rx_intr_count: AtomicUsize,
+    /// Character timeout interrupts, bytes sat below the trigger level
+    /// for four character times.
+    pub rx_timeout_count: AtomicUsize,
+    /// Coalescing timer expiries, each one drains the rx FIFO.
+    pub rx_coalesce_count: AtomicUsize,
+    pub tx_intr_count: AtomicUsize,
+    /// Tx coalescing timer expiries, each one turns THREI on.
+    pub tx_coalesce_count: AtomicUsize,
+    /// Cycles spent in `interrupt_handler`, only counted with tracing on.
+    pub intr_cycles: AtomicUsize,
+    intr_harts: [AtomicUsize; MAX_HART_NUM],
+    /// Hart of the last `interrupt_handler` call, `usize::MAX` before one.
+    last_intr_hart: AtomicUsize,
+    intr_hart_switches: AtomicUsize,
+    /// `interrupt_handler` calls that gave up with the IRQ still raised.
+    irq_loop_bailouts: AtomicUsize,
+    /// Cycles of the `interrupt_handler` calls, see `HandlerCycles`.
+    handler_calls: AtomicUsize,
+    handler_cycles: AtomicUsize,
+    handler_min: AtomicUsize,
+    handler_max: AtomicUsize,
+    handler_over_budget: AtomicUsize,
+    handler_cut_short: AtomicUsize,
+    /// `HandlerBudget::cycles`, 0 for none.
+    handler_budget: AtomicUsize,
+    handler_budget_cuts: AtomicBool,
+    /// IIDs the driver does not know, a ring indexed by the count of them.
+    unknown_iids: [AtomicU8; UNKNOWN_IID_LOG],
+    unknown_iid_count: AtomicUsize,
+    rx_full_stops: AtomicUsize,
+    /// See `set_rx_hook`.
+    rx_hook: Mutex<Option<fn(u8) -> HookAction>>,
+    rx_hook_count: AtomicUsize,
+    rx_hook_drops: AtomicUsize,
+    rx_fifo_count: AtomicUsize,
+    tx_fifo_count: AtomicIsize,
+    /// FCR bits of the rx trigger level, FCR is write only.
+    rx_trigger: AtomicU8,
+    /// The line configuration LCR should hold, DLAB clear.
+    lcr: AtomicU8,
+    /// As last programmed, 0 if not known.
+    baud_rate: AtomicUsize,
+    /// The rx and tx interrupts the driver wants. IER follows them only
+    /// in `DriveMode::Interrupt`.
+    pub(super) rx_intr_enabled: AtomicBool,
+    pub(super) tx_intr_enabled: AtomicBool,
+    polled: AtomicBool,
+    /// Set with the rx interrupts held off for coalescing, IER.ERBFI is
+    /// clear while `rx_intr_enabled` may be set.
+    rx_coalescing: AtomicBool,
+    /// Only locked with user interrupts masked, the coalescing timer takes
+    /// it in the timer interrupt.
+    coalesce: Mutex<Option<(Coalescing, Waker)>>,
+    coalesce_sleep: Mutex<Option<Pin<Box<Sleep>>>>,
+    /// Set while bytes wait for the tx coalescing timer with THREI off.
+    tx_holding: AtomicBool,
+    /// The tx coalescing delay, locked like `coalesce`.
+    tx_coalesce: Mutex<Option<(usize, Waker)>>,
+    tx_coalesce_sleep: Mutex<Option<Pin<Box<Sleep>>>>,
+    /// Held while the port is serviced and while the mode changes, so a
+    /// handler already running finishes before a switch.
+    service: Mutex<()>,
+    /// Held for IER accesses made outside `service`, and in the divisor
+    /// latch window, where IER's address is DLH.
+    ier_lock: Mutex<()>,
+    prev_cts: AtomicBool,
+    read_waker: WakerList,
+    write_waker: WakerList,
+    cross_hart_wakes: AtomicUsize,
+    event_bus: Once<Arc<SerialEventBus>>,
+    /// When a read or write last went pending with no interrupt handled
+    /// since, 0 if none did.
+    pending_since: AtomicUsize,
+    /// Times `watchdog` found a future parked for too long and ran the
+    /// interrupt handler itself.
+    pub missed_intr_count: AtomicUsize,
+    /// Receiver overruns LSR reported.
+    pub overrun_count: AtomicUsize,
+    /// Most bytes the rx and tx queues held since `reset_stats`.
+    pub rx_high_water: AtomicUsize,
+    pub tx_high_water: AtomicUsize,
+    /// Kept by the framing running on the port, if any.
+    pub(super) frames: FrameCounters,
+    /// When the last byte came in, in us, for `wait_idle`.
+    last_rx_us: AtomicUsize,
+    /// Set by `set_rx_timing`, the cycle counter is only read then.
+    rx_timing_on: AtomicBool,
+    /// Only locked with user interrupts masked.
+    rx_timing: Mutex<VecDeque<RxTiming>>,
+    soft_flow: SoftFlowState,
+    /// Bytes put into and taken out of the rx queue, both wrap. The
+    /// positions in `overrun_marks` count in the first.
+    rx_queued: AtomicUsize,
+    rx_taken: AtomicUsize,
+    /// Set on LSR.OE, the next `receive` marks the gap.
+    overrun_seen: AtomicBool,
+    /// Where in the rx stream bytes went missing, oldest first. Only
+    /// locked with user interrupts masked.
+    overrun_marks: Mutex<VecDeque<usize>>,
+    panic_registered: AtomicBool,
+    /// Set by `init_line`, a second call quiesces first.
+    initialized: AtomicBool,
+    /// Bumped by every re-init, futures older than it fail with
+    /// `SerialError::Reinit`.
+    epoch: AtomicUsize,
+    /// Set by `close` and `revoke`, for good.
+    closed: AtomicBool,
+    /// Set by `revoke`: the registers are unmapped, nothing touches them.
+    revoked: AtomicBool,
+    /// Entry in the `irq` dispatch table, see `register_irq`.
+    irq_registration: Mutex<Option<IrqRegistration>>,
+    /// Saved by `suspend` with whether the port was polled, `polled` is
+    /// set meanwhile so nothing touches the registers.
+    suspended: Mutex<Option<(SerialConfig, bool)>>,
+    /// Bytes the rx and tx queues may hold, at most one less than their
+    /// size, see `SerialBuilder`.
+    rx_capacity: usize,
+    tx_capacity: usize,
+    /// Queues taken from the builder's pool, given back after the rest of
+    /// the driver is dropped.
+    queues: Option<QueueSlot>,
+}
+
+impl AsyncSerial {
+    /// Builds the driver on the registers at `base_address`, for bring-up
+    /// without a claim. `from_claim` is the checked way.
+    ///
+    /// # Safety
+    ///
+    /// As for `UartMmio::new`.
+    pub unsafe fn new(
+        base_address: usize,
+        rx_pro: RxProducer,
+        rx_con: RxConsumer,
+        tx_pro: TxProducer,
+        tx_con: TxConsumer,
+    ) -> Self {
+        Self::with_registers(UartMmio::new(base_address), rx_pro, rx_con, tx_pro, tx_con)
+    }
+
+    /// Builds the driver on a port claimed by this process.
+    pub fn from_claim(
+        claim: &SerialClaim,
+        rx_pro: RxProducer,
+        rx_con: RxConsumer,
+        tx_pro: TxProducer,
+        tx_con: TxConsumer,
+    ) -> Self {
+        Self::with_registers(claim.registers(), rx_pro, rx_con, tx_pro, tx_con)
+    }
+
+    /// Builds the driver on a port that is already set up, e.g. inherited
+    /// with `SerialClaim::inherit`, without `hardware_init`. The interrupt
+    /// state is read back from IER instead. Returns `None` if LCR still has
+    /// the divisor latch selected, then the port was never set up.
+    pub fn from_claimed_running(
+        claim: &SerialClaim,
+        rx_pro: RxProducer,
+        rx_con: RxConsumer,
+        tx_pro: TxProducer,
+        tx_con: TxConsumer,
+    ) -> Option<Self> {
+        let serial = Self::from_claim(claim, rx_pro, rx_con, tx_pro, tx_con);
+        let block = serial.hardware();
+        if block.read_lcr() & LCR_DLAB != 0 {
+            // don't let drop reset a port we did not take over
+            core::mem::forget(serial);
+            return None;
+        }
+        let ier = block.read_ier();
+        serial.rx_intr_enabled.store(ier & IER_ERBFI != 0, Relaxed);
+        serial.tx_intr_enabled.store(ier & IER_ETBEI != 0, Relaxed);
+        serial.lcr.store(block.read_lcr(), Relaxed);
+        let divisor = serial.divisor_window(|block| block.read_divisor()) as usize;
+        if divisor != 0 {
+            serial
+                .baud_rate
+                .store(100_000_000 / (16 * divisor), Relaxed);
+        }
+        serial.initialized.store(true, Relaxed);
+        // MSR is left alone, reading it would clear deltas still pending.
+        // IIR would tell a deep FIFO but could take a pending THR empty,
+        // the default depth is safe either way.
+        Some(serial)
+    }
+}
+
+impl<R: UartRegisters> AsyncSerial<R> {
+    /// Builds the driver on `regs`, e.g. a `MockUart`.
+    pub fn with_registers(
+        regs: R,
+        rx_pro: RxProducer,
+        rx_con: RxConsumer,
+        tx_pro: TxProducer,
+        tx_con: TxConsumer,
+    ) -> Self {
+        let tx = Arc::new(TxDrain {
+            regs,
+            queue: Mutex::new(tx_con),
+            fifo_depth: AtomicUsize::new(FIFO_DEPTH),
+            urgent: Mutex::new(UrgentLane::new()),
+        });
+        register_tx_drain(tx.clone());
+        AsyncSerial {
+            regs,
+            rx_pro: Mutex::new(rx_pro),
+            rx_con: Mutex::new(rx_con),
+            rx_ring: Once::new(),
+            rx_stage: Mutex::new(RxStage::new()),
+            rx_staged: AtomicUsize::new(0),
+            tx_pro: Mutex::new(tx_pro),
+            tx,
+            rx_count: AtomicUsize::new(0),
+            tx_count: AtomicUsize::new(0),
+            intr_count: AtomicUsize::new(0),
+            rx_intr_count: AtomicUsize::new(0),
+            rx_timeout_count: AtomicUsize::new(0),
+            rx_coalesce_count: AtomicUsize::new(0),
+            tx_intr_count: AtomicUsize::new(0),
+            tx_coalesce_count: AtomicUsize::new(0),
+            intr_cycles: AtomicUsize::new(0),
+            intr_harts: Default::default(),
+            last_intr_hart: AtomicUsize::new(usize::MAX),
+            intr_hart_switches: AtomicUsize::new(0),
+            irq_loop_bailouts: AtomicUsize::new(0),
+            handler_calls: AtomicUsize::new(0),
+            handler_cycles: AtomicUsize::new(0),
+            handler_min: AtomicUsize::new(usize::MAX),
+            handler_max: AtomicUsize::new(0),
+            handler_over_budget: AtomicUsize::new(0),
+            handler_cut_short: AtomicUsize::new(0),
+            handler_budget: AtomicUsize::new(0),
+            handler_budget_cuts: AtomicBool::new(false),
+            unknown_iids: Default::default(),
+            unknown_iid_count: AtomicUsize::new(0),
+            rx_full_stops: AtomicUsize::new(0),
+            rx_hook: Mutex::new(None),
+            rx_hook_count: AtomicUsize::new(0),
+            rx_hook_drops: AtomicUsize::new(0),
+            rx_fifo_count: AtomicUsize::new(0),
+            tx_fifo_count: AtomicIsize::new(0),
+            rx_trigger: AtomicU8::new(FCR_RX_TRIGGER_14),
+            lcr: AtomicU8::new(LCR_8N1),
+            baud_rate: AtomicUsize::new(0),
+            rx_intr_enabled: AtomicBool::new(false),
+            tx_intr_enabled: AtomicBool::new(false),
+            polled: AtomicBool::new(false),
+            rx_coalescing: AtomicBool::new(false),
+            coalesce: Mutex::new(None),
+            coalesce_sleep: Mutex::new(None),
+            tx_holding: AtomicBool::new(false),
+            tx_coalesce: Mutex::new(None),
+            tx_coalesce_sleep: Mutex::new(None),
+            service: Mutex::new(()),
+            ier_lock: Mutex::new(()),
+            prev_cts: AtomicBool::new(true),
+            read_waker: WakerList::new(),
+            write_waker: WakerList::new(),
+            cross_hart_wakes: AtomicUsize::new(0),
+            event_bus: Once::new(),
+            pending_since: AtomicUsize::new(0),
+            missed_intr_count: AtomicUsize::new(0),
+            overrun_count: AtomicUsize::new(0),
+            rx_high_water: AtomicUsize::new(0),
+            tx_high_water: AtomicUsize::new(0),
+            frames: FrameCounters::new(),
+            last_rx_us: AtomicUsize::new(0),
+            rx_timing_on: AtomicBool::new(false),
+            rx_timing: Mutex::new(VecDeque::new()),
+            soft_flow: SoftFlowState::new(),
+            rx_queued: AtomicUsize::new(0),
+            rx_taken: AtomicUsize::new(0),
+            overrun_seen: AtomicBool::new(false),
+            overrun_marks: Mutex::new(VecDeque::new()),
+            panic_registered: AtomicBool::new(false),
+            initialized: AtomicBool::new(false),
+            epoch: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+            revoked: AtomicBool::new(false),
+            irq_registration: Mutex::new(None),
+            suspended: Mutex::new(None),
+            rx_capacity: MAX_RX_CAPACITY,
+            tx_capacity: MAX_TX_CAPACITY,
+            queues: None,
+        }
+    }
+
+    pub(super) fn limit_queues(&mut self, rx_capacity: usize, tx_capacity: usize) {
+        self.rx_capacity = rx_capacity.min(MAX_RX_CAPACITY);
+        self.tx_capacity = tx_capacity.min(MAX_TX_CAPACITY);
+    }
+
+    pub(super) fn own_queues(&mut self, queues: QueueSlot) {
+        self.queues = Some(queues);
+    }
+
+    pub fn base_address(&self) -> usize {
+        self.regs.base_address()
+    }
+
+    /// Index of this port in `serial::enumerate()`.
+    pub fn port(&self) -> usize {
+        serial::port_info_by_base(self.regs.base_address()).map_or(0, |port| port.index)
+    }
+
+    /// Publish line and modem events of this port to `bus`. Several ports
+    /// can share one bus. Only the first call has an effect.
+    pub fn attach_event_bus(&self, bus: Arc<SerialEventBus>) {
+        self.event_bus.call_once(|| bus);
+    }
+
+    /// Has the interrupt handler put received bytes straight into `ring`
+    /// from now on, instead of the rx queue, see `read_chunk`. The reads
+    /// and `read_available` take from the ring then, once the queue is
+    /// empty. Only the first call has an effect.
+    pub fn attach_rx_ring(&self, ring: RxRing) {
+        self.rx_ring.call_once(|| ring);
+    }
+
+    /// The received bytes in the rx ring, to be parsed in place. A chunk
+    /// ends where the ring wraps, the bytes after come with the next one.
+    /// Nothing is taken until `RxChunk::consume`. `None` without a ring,
+    /// or while another chunk is out; an empty chunk is waited on with
+    /// `readable`.
+    pub fn read_chunk(&self) -> Option<RxChunk<'_, R>> {
+        let ring = self.rx_ring.get()?;
+        if !critical_section(|| {
+            let _rx = self.rx_con.lock();
+            ring.take_chunk()
+        }) {
+            return None;
+        }
+        Some(RxChunk {
+            driver: self,
+            source: ChunkSource::Ring {
+                ring,
+                // ours until `return_chunk`
+                data: unsafe { ring.filled() },
+            },
+        })
+    }
+
+    /// Waits for received bytes and lends them in place, the `fill_buf`
+    /// of `std::io::BufRead` with `RxChunk::consume` as its `consume`.
+    /// They come straight out of the rx ring if one is attached, else the
+    /// rx queue is moved into a staging buffer of `RX_STAGE_LEN` bytes
+    /// first, so parsers need no buffer of their own. Reads see the
+    /// staged bytes before the queue. While another chunk is out, this
+    /// waits for it to go and reads get nothing.
+    pub fn fill_buf(&self) -> FillBuf<'_, R> {
+        FillBuf {
+            driver: self,
+            waiter: 0,
+        }
+    }
+
+    /// A chunk of what is staged, moving the rx queue into the stage if
+    /// that is empty. `None` while another chunk is out.
+    fn stage_chunk(&self) -> Option<RxChunk<'_, R>> {
+        let mut stage = self.rx_stage.try_lock()?;
+        if stage.start == stage.end {
+            let len = self.read_queue(&mut stage.buf);
+            stage.start = 0;
+            stage.end = len;
+            self.rx_staged.store(len, Relaxed);
+        }
+        Some(RxChunk {
+            driver: self,
+            source: ChunkSource::Stage(stage),
+        })
+    }
+
+    /// Copies out of the stage, `None` while a chunk has it.
+    fn read_staged(&self, buf: &mut [u8]) -> Option<usize> {
+        let mut stage = self.rx_stage.try_lock()?;
+        let len = buf.len().min(stage.end - stage.start);
+        buf[..len].copy_from_slice(&stage.buf[stage.start..stage.start + len]);
+        stage.start += len;
+        self.rx_staged.fetch_sub(len, Relaxed);
+        Some(len)
+    }
+
+    /// Copies out of the rx ring, nothing while a chunk is out. Called
+    /// holding `rx_con`.
+    fn read_ring(&self, buf: &mut [u8]) -> usize {
+        match self.rx_ring.get() {
+            Some(ring) if !ring.chunk_out() => ring.read_into(buf),
+            _ => 0,
+        }
+    }
+
+    fn post_event(&self, kind: SerialEventKind) {
+        if let Some(bus) = self.event_bus.get() {
+            bus.push(SerialEvent {
+                port: self.port(),
+                kind,
+                timestamp: now_us(),
+            });
+        }
+    }
+
+    fn hardware(&self) -> &R {
+        &self.regs
+    }
+
+    fn with_ier<T>(&self, f: impl FnOnce(&R) -> T) -> T {
+        critical_section(|| {
+            let _ier = self.ier_lock.lock();
+            f(self.hardware())
+        })
+    }
+
+    /// Runs `f` with the port's interrupts masked and nothing else of the
+    /// driver touching RBR/THR or IER, so `f` may set LCR.DLAB: with it set
+    /// their addresses are the divisor latches. IER is restored after.
+    /// Called holding `service` with user interrupts masked, which keeps
+    /// the interrupt handler out.
+    fn divisor_window<T>(&self, f: impl FnOnce(&R) -> T) -> T {
+        // the tx drain writes THR
+        let _tx = self.tx.queue.lock();
+        let _ier = self.ier_lock.lock();
+        let block = self.hardware();
+        let ier = block.read_ier();
+        block.write_ier(0);
+        let ret = f(block);
+        block.write_ier(ier);
+        ret
+    }
+
+    /// Changes the baud rate of a running port. Bytes still in the tx FIFO
+    /// go out at the new rate.
+    pub fn set_baud(&self, baud_rate: usize) {
+        let divisor = 100_000_000 / (16 * baud_rate);
+        critical_section(|| {
+            let _service = self.service.lock();
+            self.divisor_window(|block| block.write_divisor(divisor as u16));
+        });
+        self.baud_rate.store(baud_rate, Relaxed);
+    }
+
+    /// As last programmed, 0 before `hardware_init`.
+    pub fn baud_rate(&self) -> usize {
+        self.baud_rate.load(Relaxed)
+    }
+
+    /// Finds the rate of a peer that answers `probe` with `expect`: tries
+    /// the `candidates` in order, giving each `per_rate_timeout_us` for
+    /// the probe to go out and the answer to come back, and returns the
+    /// first that answers, the port left at that rate. Returns `None`, the
+    /// rate it had put back, if none does or the port closes.
+    ///
+    /// Before each try what is queued is sent at the rate before, then
+    /// both queues and FIFOs are emptied, so nothing of a try is seen by
+    /// the next. At a wrong rate the peer's answer comes in as garbage,
+    /// overruns and line errors included; the answer is looked for in
+    /// everything received, whatever came before it. Nothing else may
+    /// read or write the port meanwhile.
+    pub async fn scan_baud(
+        self: &Arc<Self>,
+        candidates: &[usize],
+        probe: &[u8],
+        expect: &[u8],
+        per_rate_timeout_us: usize,
+    ) -> Option<usize> {
+        let original = self.baud_rate();
+        for &baud_rate in candidates {
+            if self.is_closed() {
+                break;
+            }
+            self.switch_baud(baud_rate);
+            let answer = timeout(per_rate_timeout_us, self.probe_baud(probe, expect)).await;
+            // the timeout dropped the read, not its waker
+            self.remove_read();
+            if answer == Ok(true) {
+                return Some(baud_rate);
+            }
+        }
+        if !self.is_closed() {
+            self.switch_baud(original);
+        }
+        None
+    }
+
+    /// Sends what is queued at the current rate, then moves to `baud_rate`
+    /// with the queues and FIFOs empty.
+    fn switch_baud(&self, baud_rate: usize) {
+        self.flush(EXIT_DRAIN_TIMEOUT_US);
+        self.set_baud(baud_rate);
+        critical_section(|| {
+            let _service = self.service.lock();
+            self.drop_queued();
+            let fcr = self.fcr();
+            self.with_ier(|block| block.reset_fifos(fcr, FCR_RX_RESET | FCR_TX_RESET));
+        });
+    }
+
+    /// Sends `probe` and reads until the last bytes received are `expect`.
+    /// False if the port closed first.
+    async fn probe_baud(self: &Arc<Self>, probe: &[u8], expect: &[u8]) -> bool {
+        if self.clone().write(probe).await.is_err() {
+            return false;
+        }
+        if expect.is_empty() {
+            return true;
+        }
+        let mut window = VecDeque::with_capacity(expect.len());
+        let mut buf = [0u8; 16];
+        loop {
+            let len = match self.clone().read_some_checked(&mut buf).await {
+                Ok(len) => len,
+                // the garbage of a wrong rate, look on
+                Err(SerialError::Overrun) => continue,
+                Err(_) => return false,
+            };
+            for &ch in &buf[..len] {
+                if window.len() == expect.len() {
+                    window.pop_front();
+                }
+                window.push_back(ch);
+                if window.iter().eq(expect.iter()) {
+                    return true;
+                }
+            }
+        }
+    }
+
+    // The flag is stored before the mode is checked, and `set_mode` does
+    // it the other way round, so one of the two writes IER.
+    pub(super) fn enable_rdai(&self) {
+        self.rx_intr_enabled.store(true, SeqCst);
+        // held off, the coalescing timer turns it on again
+        if !self.polled.load(SeqCst) && !self.rx_coalescing.load(SeqCst) && !self.is_closed() {
+            self.with_ier(|block| block.set_rx_interrupt(true));
+        }
+    }
+
+    fn disable_rdai(&self) {
+        self.rx_intr_enabled.store(false, SeqCst);
+        if !self.polled.load(SeqCst) {
+            self.with_ier(|block| block.set_rx_interrupt(false));
+        }
+    }
+
+    pub(super) fn enable_threi(&self) {
+        self.tx_intr_enabled.store(true, SeqCst);
+        if !self.polled.load(SeqCst) && !self.is_closed() {
+            self.with_ier(|block| block.set_tx_interrupt(true));
+        }
+    }
+
+    fn disable_threi(&self) {
+        self.tx_intr_enabled.store(false, SeqCst);
+        if !self.polled.load(SeqCst) {
+            self.with_ier(|block| block.set_tx_interrupt(false));
+        }
+    }
+
+    /// IER for the current mode and wanted interrupts: none when polled.
+    fn write_ier(&self) {
+        let ier = if self.polled.load(SeqCst) {
+            0
+        } else {
+            let rx = self.rx_intr_enabled.load(SeqCst);
+            let tx = self.tx_intr_enabled.load(SeqCst);
+            with_bits(
+                with_bits(IER_ELSI | IER_EDSSI, IER_ERBFI, rx),
+                IER_ETBEI,
+                tx,
+            )
+        };
+        self.with_ier(|block| block.write_ier(ier));
+    }
+
+    /// Switches between being serviced by `interrupt_handler` and by
+    /// `pump` calls. In `Polled` the port raises no interrupt at all, so
+    /// user interrupts can stay enabled for other devices. The queues and
+    /// the registered wakers carry over both ways.
+    pub fn set_mode(&self, mode: DriveMode) {
+        critical_section(|| {
+            // waits out a handler running on another hart
+            let _service = self.service.lock();
+            if let Some((_, polled)) = self.suspended.lock().as_mut() {
+                // `resume` switches
+                *polled = mode == DriveMode::Polled;
+                return;
+            }
+            self.polled.store(mode == DriveMode::Polled, SeqCst);
+            self.write_ier();
+        });
+    }
+
+    pub fn drive_mode(&self) -> DriveMode {
+        let suspended = critical_section(|| self.suspended.lock().map(|(_, polled)| polled));
+        if suspended.unwrap_or_else(|| self.polled.load(Relaxed)) {
+            DriveMode::Polled
+        } else {
+            DriveMode::Interrupt
+        }
+    }
+
+    #[inline]
+    pub fn rts(&self, is_asserted: bool) {
+        self.hardware()
+            .modify_mcr(|mcr| with_bits(mcr, MCR_RTS, is_asserted))
+    }
+
+    #[inline]
+    pub fn cts(&self) -> bool {
+        self.hardware().read_msr() & MSR_CTS != 0
+    }
+
+    #[inline]
+    pub fn dcts(&self) -> bool {
+        self.hardware().read_msr() & MSR_DCTS != 0
+    }
+
+    fn try_recv(&self) -> Option<u8> {
+        let block = self.hardware();
+        let lsr = block.read_lsr();
+        // reading LSR clears its error bits, an overrun seen only here
+        // would go unnoticed otherwise
+        if lsr & (LSR_FIFO_ERROR | LSR_OE) != 0 {
+            self.line_status(lsr);
+        }
+        if lsr & LSR_DR != 0 {
+            let ch = block.read_rbr();
+            push_trace(SERIAL_RX | ch as usize);
+            Some(ch)
+        } else {
+            None
+        }
+    }
+
+    fn send(&self, ch: u8) {
+        let block = self.hardware();
+        push_trace(SERIAL_TX | ch as usize);
+        block.write_thr(ch);
+    }
+
+    // The queue locks are only taken with user interrupts masked, so these
+    // work from an interrupt handler too and never print.
+    pub(super) fn try_read(&self) -> Option<u8> {
+        let mut ch = [0u8];
+        if self.read_staged(&mut ch)? == 1 {
+            return Some(ch[0]);
+        }
+        let (ch, left) = critical_section(|| {
+            let mut rx = self.rx_con.lock();
+            let ch = match rx.dequeue() {
+                Some(ch) => ch,
+                None => {
+                    let mut ch = [0u8];
+                    if self.read_ring(&mut ch) == 0 {
+                        return None;
+                    }
+                    ch[0]
+                }
+            };
+            self.rx_taken.fetch_add(1, Relaxed);
+            Some((ch, self.queued_len(&rx)))
+        })?;
+        self.release_peer(left);
+        Some(ch)
+    }
+
+    pub(super) fn try_write(&self, ch: u8) -> Result<(), u8> {
+        critical_section(|| {
+            let mut tx = self.tx_pro.lock();
+            if tx.len() < self.tx_capacity {
+                tx.enqueue(ch)?;
+                self.tx_high_water.fetch_max(tx.len(), Relaxed);
+                Ok(())
+            } else {
+                Err(ch)
+            }
+        })
+    }
+
+    /// Moves what is in the rx queue into `buf` without waiting.
+    pub fn read_available(&self, buf: &mut [u8]) -> usize {
+        let staged = match self.read_staged(buf) {
+            Some(len) => len,
+            None => return 0,
+        };
+        staged + self.read_queue(&mut buf[staged..])
+    }
+
+    /// `read_available` past the stage.
+    fn read_queue(&self, buf: &mut [u8]) -> usize {
+        let (len, left) = critical_section(|| {
+            let mut rx = self.rx_con.lock();
+            let mut len = 0;
+            while len < buf.len() {
+                match rx.dequeue() {
+                    Some(ch) => buf[len] = ch,
+                    None => break,
+                }
+                len += 1;
+            }
+            len += self.read_ring(&mut buf[len..]);
+            self.rx_taken.fetch_add(len, Relaxed);
+            (len, self.queued_len(&rx))
+        });
+        self.release_peer(left);
+        len
+    }
+
+    /// Like `read_available`, but stops where bytes went missing to a
+    /// receiver overrun and returns `SerialError::Overrun` there, once,
+    /// before the bytes after it. Framed protocols can resynchronize
+    /// instead of parsing across the gap.
+    ///
+    /// The gap is placed after the bytes the rx FIFO held when LSR.OE was
+    /// seen, which is where the 16550 drops them. An overrun plain reads
+    /// went past is reported by the next call.
+    pub fn read_available_checked(&self, buf: &mut [u8]) -> Result<usize, SerialError> {
+        // staged bytes are before any gap the marks know of
+        match self.read_staged(buf) {
+            Some(0) => {}
+            Some(len) => return Ok(len),
+            None => return Ok(0),
+        }
+        let (len, left) = critical_section(|| {
+            let mut marks = self.overrun_marks.lock();
+            let taken = self.rx_taken.load(Relaxed);
+            let before_mark = match marks.front() {
+                Some(&mark) if mark.wrapping_sub(taken) as isize <= 0 => {
+                    marks.pop_front();
+                    return Err(SerialError::Overrun);
+                }
+                Some(&mark) => mark.wrapping_sub(taken),
+                None => usize::MAX,
+            };
+            let mut rx = self.rx_con.lock();
+            let end = buf.len().min(before_mark);
+            let mut len = 0;
+            while len < end {
+                match rx.dequeue() {
+                    Some(ch) => buf[len] = ch,
+                    None => break,
+                }
+                len += 1;
+            }
+            len += self.read_ring(&mut buf[len..end]);
+            self.rx_taken.fetch_add(len, Relaxed);
+            Ok((len, self.queued_len(&rx)))
+        })?;
+        self.release_peer(left);
+        Ok(len)
+    }
+
+    /// Sends XON if we stopped the peer and a reader has made room.
+    fn release_peer(&self, left: usize) {
+        if self.soft_flow.drained(left) {
+            self.send_flow(XON);
+        }
+    }
+
+    /// Has `byte` sent ahead of everything in the tx queue, within one
+    /// FIFO refill. Safe to call from an interrupt handler. Fails if
+    /// `URGENT_LANE_LEN` urgent bytes wait already.
+    ///
+    /// Urgent bytes go out in the order written, but may land in the
+    /// middle of a frame queued with `write`. They go out while the peer
+    /// paused us with XOFF too, and after an XON or XOFF of our own that
+    /// waits.
+    pub fn write_urgent(&self, byte: u8) -> Result<(), Busy> {
+        critical_section(|| self.tx.urgent.lock().bytes.push_back(byte)).map_err(|_| Busy)?;
+        self.toggle_threi();
+        Ok(())
+    }
+
+    /// Has `hook` see every byte received, or stops with `None`. It runs in
+    /// the interrupt handler, after XON/XOFF are taken out and before the
+    /// byte is queued, and its `HookAction` can drop the byte and have one
+    /// sent through the urgent lane. Taken by the THR empty interrupt the
+    /// same handler call raises, if the FIFO has room, the reply beats any
+    /// task the byte would wake. A reply that finds the lane full is lost,
+    /// `SerialStats::rx_hook_drops`.
+    ///
+    /// A hook runs with user interrupts masked, on whatever hart takes the
+    /// IRQ, so it must be short, must not block, allocate, print or touch
+    /// this driver, and keeps its state in atomics. Every call is traced
+    /// between `SERIAL_RX_HOOK_ENTER` and `SERIAL_RX_HOOK_EXIT`, so a slow
+    /// one shows in the trace.
+    pub fn set_rx_hook(&self, hook: Option<fn(u8) -> HookAction>) {
+        critical_section(|| *self.rx_hook.lock() = hook);
+    }
+
+    /// Puts XON or XOFF in the urgent lane, in place of one not sent yet.
+    fn send_flow(&self, ch: u8) {
+        critical_section(|| self.tx.urgent.lock().flow = Some(ch));
+        self.toggle_threi();
+    }
+
+    /// Waits for at least one byte, or an overrun, see
+    /// `read_available_checked`.
+    pub async fn read_some_checked(self: Arc<Self>, buf: &mut [u8]) -> Result<usize, SerialError> {
+        core::future::poll_fn(|cx| self.poll_read_checked(cx, buf)).await
+    }
+
+    fn poll_read_checked(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, SerialError>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        // register first, so a byte arriving after the check below still wakes us
+        self.set_read_waker(cx.waker(), None);
+        match self.read_available_checked(buf) {
+            Ok(0) if self.is_closed() => {
+                self.pending_since.store(0, Relaxed);
+                Poll::Ready(Err(SerialError::Closed { done: 0 }))
+            }
+            Ok(0) => {
+                if !self.rx_intr_enabled.load(Relaxed) {
+                    self.enable_rdai();
+                }
+                self.mark_pending();
+                Poll::Pending
+            }
+            res => {
+                self.pending_since.store(0, Relaxed);
+                Poll::Ready(res)
+            }
+        }
+    }
+
+    /// Marks the gap of an overrun seen since the last call at what the
+    /// rx queue got so far.
+    fn mark_overrun(&self) {
+        if !self.overrun_seen.swap(false, SeqCst) {
+            return;
+        }
+        let mark = self.rx_queued.load(Relaxed);
+        critical_section(|| {
+            let mut marks = self.overrun_marks.lock();
+            if marks.back() == Some(&mark) {
+                // nothing received since the last one
+            } else if marks.len() < MAX_OVERRUN_MARKS {
+                marks.push_back(mark);
+            } else if let Some(last) = marks.back_mut() {
+                *last = mark;
+            }
+        });
+    }
+
+    /// Queues as much of `buf` as fits and starts sending it, without
+    /// waiting. Safe to call from an interrupt handler. Returns how many
+    /// bytes were queued.
+    pub fn write_available(&self, buf: &[u8]) -> usize {
+        let len = critical_section(|| {
+            let mut tx = self.tx_pro.lock();
+            let space = self.tx_capacity.saturating_sub(tx.len());
+            let len = buf
+                .iter()
+                .take(space)
+                .take_while(|&&ch| tx.enqueue(ch).is_ok())
+                .count();
+            self.tx_high_water.fetch_max(tx.len(), Relaxed);
+            len
+        });
+        if len > 0 {
+            self.kick_tx();
+        }
+        len
+    }
+
+    /// Bytes in the rx queue, what `read_available` would take now.
+    pub fn rx_len(&self) -> usize {
+        critical_section(|| self.queued_len(&self.rx_con.lock()))
+    }
+
+    /// What the rx queue, ring and stage hold.
+    fn queued_len(&self, rx: &RxConsumer) -> usize {
+        rx.len() + self.rx_ring.get().map_or(0, |ring| ring.len()) + self.rx_staged.load(Relaxed)
+    }
+
+    /// Free room in the tx queue, what `write_available` would take now.
+    pub fn tx_space(&self) -> usize {
+        critical_section(|| {
+            let tx = self.tx_pro.lock();
+            self.tx_capacity.saturating_sub(tx.len())
+        })
+    }
+
+    /// Sets up the port and enables its interrupts, `init_line` and then
+    /// `enable_interrupts`.
+    pub fn hardware_init(&self, baud_rate: usize) {
+        self.init_line(baud_rate);
+        self.enable_interrupts();
+    }
+
+    /// Programs baud rate, line format and FIFOs, leaving every interrupt
+    /// off until `enable_interrupts`, e.g. for when the executor is ready.
+    ///
+    /// On a port set up already it first sends what is queued, drops what
+    /// was received and fails the pending `read_checked` and
+    /// `write_checked` with `SerialError::Reinit`. Plain reads and writes go on with the
+    /// re-initialized port.
+    pub fn init_line(&self, baud_rate: usize) {
+        if let Some((_, polled)) = critical_section(|| self.suspended.lock().take()) {
+            self.polled.store(polled, SeqCst);
+        }
+        if self.initialized.swap(true, SeqCst) {
+            self.quiesce();
+        }
+        self.rx_intr_enabled.store(false, SeqCst);
+        self.tx_intr_enabled.store(false, SeqCst);
+        self.rx_coalescing.store(false, SeqCst);
+        self.stop_tx_hold();
+        critical_section(|| {
+            let _service = self.service.lock();
+            let block = self.hardware();
+            let _unused = block.read_msr();
+            let _unused = block.read_lsr();
+            self.with_ier(|block| block.write_ier(0));
+            self.divisor_window(|block| {
+                block.write_lcr(0);
+                // No modem control
+                block.write_mcr(0);
+                block.write_fcr(0);
+
+                block.write_divisor((100_000_000 / (16 * baud_rate)) as u16);
+                self.baud_rate.store(baud_rate, Relaxed);
+                // word length 8 bits, no parity, 1 stop bit
+                block.write_lcr(LCR_8N1);
+                self.lcr.store(LCR_8N1, Relaxed);
+                // Enable FIFO, 64 bytes deep if it can be
+                self.tx.fifo_depth.store(block.detect_fifo_depth(), Relaxed);
+                block.write_fcr(FCR_FIFO_ENABLE | FCR_RX_TRIGGER_14);
+            });
+            self.rx_trigger.store(FCR_RX_TRIGGER_14, Relaxed);
+            self.rts(true);
+            let _unused = self.dcts();
+        });
+    }
+
+    /// Enables line status, modem status, rx data and THR empty interrupts.
+    pub fn enable_interrupts(&self) {
+        self.rx_intr_enabled.store(true, SeqCst);
+        self.tx_intr_enabled.store(true, SeqCst);
+        self.write_ier();
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        self.initialized.load(Relaxed)
+    }
+
+    /// Stops the port for its clock to be gated: turns its interrupts off,
+    /// waits up to `EXIT_DRAIN_TIMEOUT_US` for the transmitter to be idle
+    /// and saves the configuration. Until `resume` the driver leaves the
+    /// registers alone, reads and writes stay pending. Returns false if
+    /// the transmitter did not go idle, what it still held is lost.
+    pub fn suspend(&self) -> bool {
+        critical_section(|| {
+            let _service = self.service.lock();
+            let mut suspended = self.suspended.lock();
+            if suspended.is_some() {
+                return true;
+            }
+            let polled = self.polled.swap(true, SeqCst);
+            self.with_ier(|block| block.write_ier(0));
+            let idle = self.hardware().wait_tx_idle(EXIT_DRAIN_TIMEOUT_US);
+            let fcr = self.fcr();
+            let config = self.divisor_window(|block| block.save_config(fcr));
+            *suspended = Some((config, polled));
+            idle
+        })
+    }
+
+    /// Programs the configuration `suspend` saved back and turns the
+    /// interrupts the driver wants on again, THR empty too if bytes were
+    /// queued meanwhile. Pending reads and writes go on from there.
+    pub fn resume(&self) {
+        critical_section(|| {
+            let _service = self.service.lock();
+            let (config, polled) = match self.suspended.lock().take() {
+                Some(suspended) => suspended,
+                None => return,
+            };
+            self.divisor_window(|block| block.restore_config(&config));
+            // the FIFOs start out empty
+            self.rx_fifo_count.store(0, Relaxed);
+            self.tx_fifo_count.store(0, Relaxed);
+            if self.tx.queue.lock().len() > 0 || !self.tx.urgent.lock().is_empty() {
+                self.tx_intr_enabled.store(true, SeqCst);
+            }
+            self.polled.store(polled, SeqCst);
+            self.write_ier();
+        });
+    }
+
+    /// What FCR was last set to, without the reset bits.
+    fn fcr(&self) -> u8 {
+        let fifo64 = if self.fifo_depth() == DEEP_FIFO_DEPTH {
+            FCR_FIFO64
+        } else {
+            0
+        };
+        FCR_FIFO_ENABLE | fifo64 | self.rx_trigger.load(Relaxed)
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        critical_section(|| self.suspended.lock().is_some())
+    }
+
+    /// Has `irq::dispatch` run this driver for `irq` until `close`. The
+    /// table holds a reference, the driver is not dropped before.
+    pub fn register_irq(self: &Arc<Self>, irq: u16) -> Result<(), IrqError>
+    where
+        R: 'static,
+    {
+        let handler: Arc<dyn IrqHandler> = self.clone();
+        let registration = crate::irq::register(irq, handler)?;
+        critical_section(|| *self.irq_registration.lock() = Some(registration));
+        Ok(())
+    }
+
+    /// Takes the port down for good, dropping the driver calls it. In this
+    /// order: no interrupt is routed here any more, a handler running on
+    /// another hart is waited out, the pending reads and writes fail with
+    /// `SerialError::Closed` and are woken, and then what is queued is
+    /// sent and the registers are reset. Must not be called from this
+    /// port's interrupt handler.
+    ///
+    /// A driver shared with `serial::register` stays in that registry, its
+    /// handler returns at once.
+    ///
+    /// Returns what was left behind. Closing a port closed or revoked
+    /// already sends nothing and reports what it still holds.
+    pub fn close(&self) -> CloseReport {
+        if self.closed.swap(true, SeqCst) {
+            return self.close_report(false);
+        }
+        // the handler, `pump` and the coalescing timers check `closed`
+        let registration = critical_section(|| self.irq_registration.lock().take());
+        drop(registration);
+        critical_section(|| {
+            // waits out a handler running on another hart, it holds
+            // `service` and went past the check
+            let _service = self.service.lock();
+            self.with_ier(|block| block.write_ier(0));
+        });
+        self.fail_pending();
+
+        // the reset below empties the tx FIFO, let what is queued out first
+        let flushed = self.flush(EXIT_DRAIN_TIMEOUT_US);
+        let block = self.hardware();
+        let mut report = self.close_report(!flushed);
+        report.tx_unsent += block.read_tx_fifo_level().unwrap_or(0);
+        let _unused = block.read_msr();
+        let _unused = block.read_lsr();
+        self.rts(false);
+        // reset Rx & Tx FIFO, disable FIFO
+        block.reset_fifos(0, FCR_RX_RESET | FCR_TX_RESET);
+        report
+    }
+
+    /// What the queues hold, touching no register.
+    fn close_report(&self, flush_timed_out: bool) -> CloseReport {
+        critical_section(|| CloseReport {
+            tx_unsent: self.tx.queue.lock().len() + self.tx.urgent.lock().bytes.len(),
+            rx_unread: self.queued_len(&self.rx_con.lock()),
+            flush_timed_out,
+        })
+    }
+
+    /// The kernel took the port back, see `irq::handle_revoke_interrupt`.
+    /// Its registers are unmapped already, so this closes the port as
+    /// `close` does but touches none of them, and what is queued is lost.
+    /// Fine in an interrupt handler.
+    pub fn revoke(&self) {
+        self.revoked.store(true, SeqCst);
+        if self.closed.swap(true, SeqCst) {
+            return;
+        }
+        let registration = critical_section(|| self.irq_registration.lock().take());
+        drop(registration);
+        self.fail_pending();
+        self.post_event(SerialEventKind::Revoked);
+    }
+
+    /// Stops the coalescing timers and wakes the pending reads and writes,
+    /// they find the port closed.
+    fn fail_pending(&self) {
+        let sleep = critical_section(|| self.coalesce_sleep.lock().take());
+        drop(sleep);
+        self.stop_tx_hold();
+        self.wake(&self.read_waker, ASYNC_READ_WAKE);
+        self.wake(&self.write_waker, ASYNC_WRITE_WAKE);
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(SeqCst)
+    }
+
+    pub fn is_revoked(&self) -> bool {
+        self.revoked.load(SeqCst)
+    }
+
+    /// Stops the port for `init_line` to program it again, see there.
+    fn quiesce(&self) {
+        self.flush(EXIT_DRAIN_TIMEOUT_US);
+        critical_section(|| {
+            // waits out a handler running on another hart
+            let _service = self.service.lock();
+            self.with_ier(|block| block.write_ier(0));
+            self.rx_intr_enabled.store(false, SeqCst);
+            self.tx_intr_enabled.store(false, SeqCst);
+            self.drop_queued();
+            self.pending_since.store(0, Relaxed);
+            self.epoch.fetch_add(1, SeqCst);
+        });
+        self.wake(&self.read_waker, ASYNC_READ_WAKE);
+        self.wake(&self.write_waker, ASYNC_WRITE_WAKE);
+    }
+
+    /// Drops what was received and what is still to send. Called holding
+    /// `service` with user interrupts masked.
+    fn drop_queued(&self) {
+        let mut rx = self.rx_con.lock();
+        while rx.dequeue().is_some() {}
+        if let Some(ring) = self.rx_ring.get() {
+            ring.discard();
+        }
+        if let Some(mut stage) = self.rx_stage.try_lock() {
+            stage.start = stage.end;
+            self.rx_staged.store(0, Relaxed);
+        }
+        // the gaps are gone with the bytes
+        self.rx_taken.store(self.rx_queued.load(Relaxed), Relaxed);
+        self.overrun_marks.lock().clear();
+        self.overrun_seen.store(false, SeqCst);
+        // what a flush left, e.g. held back by CTS
+        let mut tx = self.tx.queue.lock();
+        while tx.dequeue().is_some() {}
+        let mut urgent = self.tx.urgent.lock();
+        urgent.flow = None;
+        urgent.bytes.clear();
+        self.rx_fifo_count.store(0, Relaxed);
+        self.tx_fifo_count.store(0, Relaxed);
+    }
+
+    /// Bytes sent per THR empty interrupt, as found by `hardware_init`.
+    pub fn fifo_depth(&self) -> usize {
+        self.tx.fifo_depth.load(Relaxed)
+    }
+
+    /// LCR holds the line configuration the driver set, e.g. after a
+    /// baud rate change.
+    pub fn line_config_intact(&self) -> bool {
+        self.hardware().read_lcr() == self.lcr.load(Relaxed)
+    }
+
+    /// Overrides the detected depth, for a UART with 64 byte FIFOs that
+    /// IIR does not report. The FIFOs must be set up that deep already.
+    pub fn set_fifo_depth(&self, fifo_depth: usize) {
+        self.tx.fifo_depth.store(fifo_depth, Relaxed);
+        // bytes in flight past a lower depth are gone by the next THRE
+        self.tx_fifo_count.fetch_min(fifo_depth as isize, Relaxed);
+    }
+
+    /// The rx trigger level last set, `Fourteen` (or `FiftySix`) after
+    /// `hardware_init`.
+    pub fn fifo_trigger(&self) -> FifoTrigger {
+        FifoTrigger::from_fcr_bits(self.rx_trigger.load(Relaxed), self.fifo_depth())
+    }
+
+    /// Sets the rx trigger level, it takes effect with the next byte
+    /// received. Fails if the level does not exist with FIFOs this deep.
+    pub fn set_fifo_trigger(&self, trigger: FifoTrigger) -> Result<(), SerialBuildError> {
+        let fifo_depth = self.fifo_depth();
+        let bits = trigger
+            .fcr_bits(fifo_depth)
+            .ok_or(SerialBuildError::UnsupportedTrigger {
+                trigger,
+                fifo_depth,
+            })?;
+        self.rx_trigger.store(bits, Relaxed);
+        // no reset bits, the FIFOs keep what they hold
+        self.hardware().write_fcr(FCR_FIFO_ENABLE | bits);
+        Ok(())
+    }
+
+    /// Turns rx interrupt coalescing on, or off with `None`, see
+    /// `Coalescing`. Fails, changing nothing, if the delay is over
+    /// `Coalescing::max_delay_us` at the current baud rate or the trigger
+    /// level does not exist. Turning it off keeps the trigger level.
+    pub fn set_coalescing(
+        self: &Arc<Self>,
+        coalescing: Option<Coalescing>,
+    ) -> Result<(), SerialBuildError> {
+        let coalesce = match coalescing {
+            Some(config) => {
+                let max_delay_us =
+                    config.max_delay_us(self.baud_rate.load(Relaxed), self.fifo_depth());
+                if config.delay_us == 0 || config.delay_us > max_delay_us {
+                    return Err(SerialBuildError::InvalidCoalescing {
+                        delay_us: config.delay_us,
+                        max_delay_us,
+                    });
+                }
+                self.set_fifo_trigger(config.trigger)?;
+                let timer = Arc::new(CoalesceTimer(Arc::downgrade(self)));
+                Some((config, Waker::from(timer)))
+            }
+            None => None,
+        };
+        critical_section(|| *self.coalesce.lock() = coalesce);
+        if coalescing.is_none() {
+            let sleep = critical_section(|| self.coalesce_sleep.lock().take());
+            drop(sleep);
+            self.release_rx();
+        }
+        Ok(())
+    }
+
+    /// Turns tx interrupt coalescing on, or off with `None`. With a delay,
+    /// bytes written while THREI is off wait up to `delay_us` for more to
+    /// come before THREI goes on, or less once a FIFO's worth is queued,
+    /// so a burst of small writes costs one THR empty interrupt instead
+    /// of one each. `flush` sends at once. Fails, changing nothing, if the
+    /// delay is 0 or over `MAX_TX_DELAY_US`. Needs the user timer
+    /// interrupt on.
+    pub fn set_tx_coalescing(
+        self: &Arc<Self>,
+        delay_us: Option<usize>,
+    ) -> Result<(), SerialBuildError> {
+        let coalesce = match delay_us {
+            Some(delay_us) => {
+                if delay_us == 0 || delay_us > MAX_TX_DELAY_US {
+                    return Err(SerialBuildError::InvalidCoalescing {
+                        delay_us,
+                        max_delay_us: MAX_TX_DELAY_US,
+                    });
+                }
+                let timer = Arc::new(TxCoalesceTimer(Arc::downgrade(self)));
+                Some((delay_us, Waker::from(timer)))
+            }
+            None => None,
+        };
+        critical_section(|| *self.tx_coalesce.lock() = coalesce);
+        if delay_us.is_none() {
+            self.release_tx();
+        }
+        Ok(())
+    }
+
+    pub fn tx_coalescing(&self) -> Option<usize> {
+        critical_section(|| {
+            self.tx_coalesce
+                .lock()
+                .as_ref()
+                .map(|(delay_us, _)| *delay_us)
+        })
+    }
+
+    /// Starts sending bytes just queued: turns THREI on, unless the FIFO
+    /// has no CTS credit left, or tx coalescing holds them back for a
+    /// while.
+    fn kick_tx(&self) {
+        if self.tx_fifo_count.load(Relaxed) >= self.fifo_depth() as _ {
+            // the next CTS edge raises THREI
+            return;
+        }
+        if self.tx_intr_enabled.load(SeqCst) || !self.hold_tx() {
+            self.toggle_threi();
+        }
+    }
+
+    /// Returns whether the bytes queued wait for the tx coalescing timer,
+    /// arming it if they are the first. Not once a FIFO's worth waits.
+    fn hold_tx(&self) -> bool {
+        let (delay_us, timer) = match critical_section(|| self.tx_coalesce.lock().clone()) {
+            Some(coalesce) => coalesce,
+            None => return false,
+        };
+        let queued = critical_section(|| self.tx_pro.lock().len());
+        if queued >= self.fifo_depth() {
+            self.stop_tx_hold();
+            return false;
+        }
+        if self.tx_holding.swap(true, SeqCst) {
+            return true;
+        }
+        // polled outside the critical section, it may set the timer
+        let mut sleep = Box::pin(sleep_us(delay_us));
+        if sleep
+            .as_mut()
+            .poll(&mut Context::from_waker(&timer))
+            .is_ready()
+        {
+            self.tx_holding.store(false, SeqCst);
+            return false;
+        }
+        critical_section(|| *self.tx_coalesce_sleep.lock() = Some(sleep));
+        true
+    }
+
+    /// Ends holding the tx bytes back without turning THREI on. Returns
+    /// whether they were held.
+    fn stop_tx_hold(&self) -> bool {
+        let sleep = critical_section(|| self.tx_coalesce_sleep.lock().take());
+        drop(sleep);
+        self.tx_holding.swap(false, SeqCst)
+    }
+
+    /// Ends holding the tx bytes back and turns THREI on for them.
+    fn release_tx(&self) {
+        if self.stop_tx_hold() {
+            self.toggle_threi();
+        }
+    }
+
+    /// The tx coalescing delay is over, called from the timer interrupt.
+    pub(super) fn tx_coalesce_expired(&self) {
+        if self.tx_holding.load(SeqCst) && !self.is_closed() {
+            self.tx_coalesce_count.fetch_add(1, Relaxed);
+            self.release_tx();
+        }
+    }
+
+    /// Turns XON/XOFF flow control on, or off with `None`. Either way
+    /// both sides start over unpaused. The watermarks count rx queue
+    /// bytes, `low_water` below `high_water`, which is at most what the
+    /// queue holds.
+    pub fn set_soft_flow(&self, flow: Option<SoftFlow>) -> Result<(), SerialBuildError> {
+        if let Some(flow) = flow {
+            if flow.low_water >= flow.high_water || flow.high_water > self.rx_capacity {
+                return Err(SerialBuildError::InvalidWatermarks {
+                    high_water: flow.high_water,
+                    low_water: flow.low_water,
+                });
+            }
+        }
+        if self.soft_flow.set(flow) {
+            self.send_flow(XON);
+        }
+        // sends what a pause held back
+        self.toggle_threi();
+        Ok(())
+    }
+
+    pub fn soft_flow(&self) -> Option<SoftFlow> {
+        self.soft_flow.get()
+    }
+
+    /// Whether the peer sent XOFF and nothing goes out until its XON.
+    pub fn tx_paused(&self) -> bool {
+        self.soft_flow.paused()
+    }
+
+    pub fn coalescing(&self) -> Option<Coalescing> {
+        critical_section(|| self.coalesce.lock().as_ref().map(|(config, _)| *config))
+    }
+
+    /// Holds the rx interrupts off after one was served, if coalescing,
+    /// and arms the timer that drains the FIFO in their place.
+    fn hold_off_rx(&self) {
+        if critical_section(|| self.coalesce.lock().is_none())
+            || !self.rx_intr_enabled.load(SeqCst)
+            || self.rx_coalescing.swap(true, SeqCst)
+        {
+            // off, the rx queue is full, or held off already
+            return;
+        }
+        self.with_ier(|block| block.set_rx_interrupt(false));
+        if !self.arm_coalesce_timer() {
+            self.release_rx();
+        }
+    }
+
+    /// Returns false if the timer is not armed: coalescing was turned off,
+    /// or the delay is over already.
+    fn arm_coalesce_timer(&self) -> bool {
+        let (config, timer) = match critical_section(|| self.coalesce.lock().clone()) {
+            Some(coalesce) => coalesce,
+            None => return false,
+        };
+        // polled outside the critical section, it may set the timer
+        let mut sleep = Box::pin(sleep_us(config.delay_us));
+        if sleep
+            .as_mut()
+            .poll(&mut Context::from_waker(&timer))
+            .is_ready()
+        {
+            return false;
+        }
+        critical_section(|| *self.coalesce_sleep.lock() = Some(sleep));
+        true
+    }
+
+    /// Ends holding the rx interrupts off, IER.ERBFI follows
+    /// `rx_intr_enabled` again.
+    fn release_rx(&self) {
+        if self.rx_coalescing.swap(false, SeqCst)
+            && self.rx_intr_enabled.load(SeqCst)
+            && !self.polled.load(SeqCst)
+        {
+            self.with_ier(|block| block.set_rx_interrupt(true));
+        }
+    }
+
+    /// The coalescing delay is over, called from the timer interrupt.
+    /// Drains the rx FIFO and holds the rx interrupts off for another
+    /// delay if that found bytes, else turns them on again.
+    pub(super) fn coalesce_expired(&self) {
+        let sleep = critical_section(|| self.coalesce_sleep.lock().take());
+        drop(sleep);
+        if !self.rx_coalescing.load(SeqCst) || self.is_closed() {
+            return;
+        }
+        self.rx_coalesce_count.fetch_add(1, Relaxed);
+        match self.service.try_lock() {
+            Some(_service) if !self.polled.load(SeqCst) => {
+                let len = self.receive(false);
+                if len == 0 || !self.rx_intr_enabled.load(SeqCst) || !self.arm_coalesce_timer() {
+                    self.release_rx();
+                }
+            }
+            // a handler this interrupted, or one on another hart, reads
+            // IIR again and finds the rx interrupt
+            _ => self.release_rx(),
+        }
+    }
+
+    #[inline]
+    fn toggle_threi(&self) {
+        self.disable_threi();
+        self.enable_threi();
+    }
+
+    #[inline]
+    fn start_tx(&self) {
+        let mut tx_count = 0;
+        let fifo_depth = self.fifo_depth() as isize;
+        // the depth may have been lowered under the handler
+        let mut tx_fifo_count = self.tx_fifo_count.load(Relaxed).min(fifo_depth);
+        let block = self.hardware();
+        let lsr = block.read_lsr();
+        if lsr & (LSR_FIFO_ERROR | LSR_OE) != 0 {
+            self.line_status(lsr);
+        }
+        // THREI stays on if the FIFO takes less, it comes again once empty
+        let room = block.tx_fifo_room(lsr, fifo_depth as usize);
+        // the urgent lane goes first, also while the peer paused us
+        critical_section(|| {
+            let mut urgent = self.tx.urgent.lock();
+            while tx_fifo_count < fifo_depth && tx_count < room {
+                let ch = match urgent.pop() {
+                    Some(ch) => ch,
+                    None => break,
+                };
+                self.send(ch);
+                tx_count += 1;
+                tx_fifo_count += 1;
+            }
+        });
+        let mut con = self.tx.queue.lock();
+
+        if self.soft_flow.paused() {
+            // its XON turns THREI on again
+            self.disable_threi();
+        } else {
+            while tx_fifo_count < fifo_depth && tx_count < room {
+                if let Some(ch) = con.dequeue() {
+                    self.send(ch);
+                    tx_count += 1;
+                    tx_fifo_count += 1;
+                } else {
+                    self.disable_threi();
+                    break;
+                }
+            }
+        }
+
+        if tx_fifo_count >= fifo_depth {
+            self.disable_threi();
+        }
+
+        self.tx_count.fetch_add(tx_count, Relaxed);
+        self.tx_fifo_count.store(tx_fifo_count, Relaxed);
+    }
+
+    fn wake(&self, list: &WakerList, trace_event: usize) {
+        let remote = list.wake(trace_event);
+        self.cross_hart_wakes.fetch_add(remote, Relaxed);
+    }
+
+    pub fn stats(&self) -> SerialStats {
+        SerialStats {
+            rx_count: self.rx_count.load(Relaxed),
+            tx_count: self.tx_count.load(Relaxed),
+            intr_count: self.intr_count.load(Relaxed),
+            rx_intr_count: self.rx_intr_count.load(Relaxed),
+            rx_timeout_count: self.rx_timeout_count.load(Relaxed),
+            rx_coalesce_count: self.rx_coalesce_count.load(Relaxed),
+            tx_intr_count: self.tx_intr_count.load(Relaxed),
+            tx_coalesce_count: self.tx_coalesce_count.load(Relaxed),
+            intr_cycles: self.intr_cycles.load(Relaxed),
+            cross_hart_wakes: self.cross_hart_wakes.load(Relaxed),
+            intr_harts: core::array::from_fn(|hart| self.intr_harts[hart].load(Relaxed)),
+            intr_hart_switches: self.intr_hart_switches.load(Relaxed),
+            irq_loop_bailouts: self.irq_loop_bailouts.load(Relaxed),
+            handler: self.handler_cycles(),
+            unknown_iids: self.unknown_iid_count.load(Relaxed),
+            rx_full_stops: self.rx_full_stops.load(Relaxed),
+            rx_hook_count: self.rx_hook_count.load(Relaxed),
+            rx_hook_drops: self.rx_hook_drops.load(Relaxed),
+            read_waker: self.read_waker.stats(),
+            write_waker: self.write_waker.stats(),
+            missed_intr_count: self.missed_intr_count.load(Relaxed),
+            overrun_count: self.overrun_count.load(Relaxed),
+            rx_high_water: self.rx_high_water.load(Relaxed),
+            tx_high_water: self.tx_high_water.load(Relaxed),
+            fifo_depth: self.fifo_depth(),
+            frames: self.frames.stats(),
+            soft_flow: critical_section(|| {
+                let urgent = self.tx.urgent.lock();
+                SoftFlowStats {
+                    xoff_sent: urgent.xoff_sent,
+                    xon_sent: urgent.xon_sent,
+                    ..self.soft_flow.stats()
+                }
+            }),
+        }
+    }
+
+    /// Zeroes the counters of `stats`. The high-water marks start over
+    /// from what the queues hold now.
+    pub fn reset_stats(&self) {
+        let counters = [
+            &self.rx_count,
+            &self.tx_count,
+            &self.intr_count,
+            &self.rx_intr_count,
+            &self.rx_timeout_count,
+            &self.rx_coalesce_count,
+            &self.tx_intr_count,
+            &self.tx_coalesce_count,
+            &self.intr_cycles,
+            &self.cross_hart_wakes,
+            &self.intr_hart_switches,
+            &self.irq_loop_bailouts,
+            &self.handler_calls,
+            &self.handler_cycles,
+            &self.handler_max,
+            &self.handler_over_budget,
+            &self.handler_cut_short,
+            &self.unknown_iid_count,
+            &self.rx_full_stops,
+            &self.rx_hook_count,
+            &self.rx_hook_drops,
+            &self.missed_intr_count,
+            &self.overrun_count,
+        ];
+        for counter in counters.iter().copied().chain(self.intr_harts.iter()) {
+            counter.store(0, Relaxed);
+        }
+        self.handler_min.store(usize::MAX, Relaxed);
+        self.frames.reset();
+        self.read_waker.reset_stats();
+        self.write_waker.reset_stats();
+        self.soft_flow.reset_stats();
+        critical_section(|| {
+            let mut urgent = self.tx.urgent.lock();
+            urgent.xoff_sent = 0;
+            urgent.xon_sent = 0;
+        });
+        let (rx_len, tx_len) = critical_section(|| {
+            (
+                self.queued_len(&self.rx_con.lock()),
+                self.tx_pro.lock().len(),
+            )
+        });
+        self.rx_high_water.store(rx_len, Relaxed);
+        self.tx_high_water.store(tx_len, Relaxed);
+    }
+
+    /// Serves the port's pending interrupt sources, a bounded amount of
+    /// work per call.
+    ///
+    /// Safe to run nested in a user interrupt handler, see
+    /// `trap::set_nesting`: a call that finds the port being served, by a
+    /// handler it interrupted or on another hart, leaves it to that one,
+    /// which reads IIR again before it returns. So the rx queue producer
+    /// is only ever used by one call at a time.
+    #[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
+    pub fn interrupt_handler(&self) {
+        let start = cycles();
+        let _service = match self.service.try_lock() {
+            Some(service) => service,
+            None => return,
+        };
+        if self.is_closed() {
+            // raised before `close` turned IER off
+            return;
+        }
+        if self.polled.load(SeqCst) {
+            // raised just before `set_mode`, `pump` takes over
+            return;
+        }
+        let hart = hart_id();
+        self.intr_harts[hart % MAX_HART_NUM].fetch_add(1, Relaxed);
+        let last = self.last_intr_hart.swap(hart, Relaxed);
+        if last != hart && last != usize::MAX {
+            self.intr_hart_switches.fetch_add(1, Relaxed);
+        }
+        self.pending_since.store(0, Relaxed);
+        self.serve_iir(start);
+        self.record_handler_cycles(cycles().wrapping_sub(start));
+    }
+
+    /// The IIR loop of an `interrupt_handler` call that began at `start`
+    /// cycles.
+    fn serve_iir(&self, start: usize) {
+        let budget = self.handler_budget.load(Relaxed);
+        let cut_short = budget != 0 && self.handler_budget_cuts.load(Relaxed);
+        let block = self.hardware();
+        // the IID of the last round, with `SERIAL_INTR_BAILOUT_UNKNOWN` set
+        // if the driver does not know it
+        let mut last_iid = 0;
+        for _ in 0..MAX_INTR_ROUNDS {
+            let int_type = block.read_iir() & IIR_IID_MASK;
+            if int_type == IID_NO_INTERRUPT {
+                return;
+            }
+            last_iid = int_type as usize;
+            let intr_id: usize = int_type as _;
+            let enter = push_trace(SERIAL_INTR_ENTER + intr_id);
+            self.intr_count.fetch_add(1, Relaxed);
+            serial_trace!(
+                self.base_address(),
+                "[uart] {:#x}: IID {:#x}, IER {:#x}",
+                self.base_address(),
+                int_type,
+                block.read_ier()
+            );
+            match int_type {
+                IID_RX_DATA => {
+                    self.rx_intr_count.fetch_add(1, Relaxed);
+                    let len = self.receive(false);
+                    push_trace(SERIAL_RX_DATA | len.min(0xfff));
+                    self.hold_off_rx();
+                }
+                IID_CHAR_TIMEOUT => {
+                    self.rx_timeout_count.fetch_add(1, Relaxed);
+                    let len = self.receive(true);
+                    if len > 0 {
+                        // raised four character times after the last byte
+                        let quiet_us = 4 * self.char_time_us();
+                        self.last_rx_us
+                            .store(now_us().saturating_sub(quiet_us), Relaxed);
+                    }
+                    push_trace(SERIAL_RX_TIMEOUT | len.min(0xfff));
+                    self.hold_off_rx();
+                }
+                IID_THR_EMPTY => {
+                    self.tx_intr_count.fetch_add(1, Relaxed);
+                    self.start_tx();
+                }
+                IID_LINE_STATUS => self.line_status(block.read_lsr()),
+                IID_MODEM_STATUS => {
+                    // reading MSR clears the delta bits, read it only once
+                    let msr = self.hardware().read_msr();
+                    if msr & (MSR_DCTS | MSR_DDSR | MSR_DDCD | MSR_TERI) != 0 {
+                        self.modem_status(msr);
+                    } else {
+                        let block = self.hardware();
+                        serial_warn!(
+                            self.base_address(),
+                            "[USER SERIAL] EDSSI, MSR: {:#x}, LSR: {:#x}, IER: {:#x}",
+                            msr,
+                            block.read_lsr(),
+                            block.read_ier()
+                        );
+                    }
+                }
+                _ => {
+                    // no print, a stuck IIR would flood the console from here
+                    self.note_unknown_iid(int_type);
+                    last_iid |= SERIAL_INTR_BAILOUT_UNKNOWN;
+                }
+            }
+            let exit = push_trace(SERIAL_INTR_EXIT + intr_id);
+            self.intr_cycles
+                .fetch_add(exit.wrapping_sub(enter), Relaxed);
+            if last_iid & SERIAL_INTR_BAILOUT_UNKNOWN != 0 {
+                // reading IIR again does not clear it
+                break;
+            }
+            if cut_short && cycles().wrapping_sub(start) > budget {
+                // as at the round bound, what is left raises the IRQ again
+                self.handler_cut_short.fetch_add(1, Relaxed);
+                return;
+            }
+        }
+        self.irq_loop_bailouts.fetch_add(1, Relaxed);
+        push_trace(SERIAL_INTR_BAILOUT | last_iid);
+    }
+
+    fn record_handler_cycles(&self, elapsed: usize) {
+        self.handler_calls.fetch_add(1, Relaxed);
+        self.handler_cycles.fetch_add(elapsed, Relaxed);
+        self.handler_min.fetch_min(elapsed, Relaxed);
+        self.handler_max.fetch_max(elapsed, Relaxed);
+        let budget = self.handler_budget.load(Relaxed);
+        if budget != 0 && elapsed > budget {
+            self.handler_over_budget.fetch_add(1, Relaxed);
+            push_trace(SERIAL_INTR_OVER_BUDGET | (elapsed / budget).min(0xfff));
+        }
+    }
+
+    fn handler_cycles(&self) -> HandlerCycles {
+        let calls = self.handler_calls.load(Relaxed);
+        HandlerCycles {
+            calls,
+            min: if calls == 0 {
+                0
+            } else {
+                self.handler_min.load(Relaxed)
+            },
+            max: self.handler_max.load(Relaxed),
+            total: self.handler_cycles.load(Relaxed),
+            over_budget: self.handler_over_budget.load(Relaxed),
+            cut_short: self.handler_cut_short.load(Relaxed),
+        }
+    }
+
+    /// Sets the cycles one `interrupt_handler` call should stay within, or
+    /// none. Calls over it are counted in `SerialStats::handler` and traced
+    /// as `SERIAL_INTR_OVER_BUDGET`; with `cut_short` they also stop
+    /// reading IIR, as at the bound on rounds. A budget of 0 cycles is
+    /// none.
+    pub fn set_handler_budget(&self, budget: Option<HandlerBudget>) {
+        let budget = budget.unwrap_or(HandlerBudget {
+            cycles: 0,
+            cut_short: false,
+        });
+        self.handler_budget_cuts.store(budget.cut_short, Relaxed);
+        self.handler_budget.store(budget.cycles, Relaxed);
+    }
+
+    pub fn handler_budget(&self) -> Option<HandlerBudget> {
+        match self.handler_budget.load(Relaxed) {
+            0 => None,
+            cycles => Some(HandlerBudget {
+                cycles,
+                cut_short: self.handler_budget_cuts.load(Relaxed),
+            }),
+        }
+    }
+
+    fn note_unknown_iid(&self, iid: u8) {
+        let count = self.unknown_iid_count.fetch_add(1, Relaxed);
+        self.unknown_iids[count % UNKNOWN_IID_LOG].store(iid, Relaxed);
+    }
+
+    /// The last `UNKNOWN_IID_LOG` IIDs `interrupt_handler` read and does
+    /// not know, oldest first. Their count is in `SerialStats::unknown_iids`.
+    pub fn unknown_iids(&self) -> Vec<u8> {
+        let count = self.unknown_iid_count.load(Relaxed);
+        (count.saturating_sub(UNKNOWN_IID_LOG)..count)
+            .map(|i| self.unknown_iids[i % UNKNOWN_IID_LOG].load(Relaxed))
+            .collect()
+    }
+
+    /// Calls the rx hook on `ch` and puts its reply in the urgent lane.
+    /// Returns whether it took the byte and whether it replied; THREI is
+    /// left for the caller.
+    fn run_rx_hook(&self, hook: fn(u8) -> HookAction, ch: u8) -> (bool, bool) {
+        push_trace(SERIAL_RX_HOOK_ENTER | ch as usize);
+        let action = hook(ch);
+        push_trace(SERIAL_RX_HOOK_EXIT | action.consume as usize);
+        self.rx_hook_count.fetch_add(1, Relaxed);
+        let replied = match action.reply {
+            Some(reply) => {
+                let queued = critical_section(|| self.tx.urgent.lock().bytes.push_back(reply));
+                if queued.is_err() {
+                    self.rx_hook_drops.fetch_add(1, Relaxed);
+                }
+                queued.is_ok()
+            }
+            None => false,
+        };
+        (action.consume, replied)
+    }
+
+    /// Drains the rx FIFO into the rx queue until the queue is full.
+    /// Returns the bytes drained.
+    fn receive(&self, char_timeout: bool) -> usize {
+        use core::sync::atomic::Ordering::{Acquire, Release};
+
+        let at = self.rx_timing_on.load(Relaxed).then(cycles);
+        let mut rx_count = 0;
+        let mut rx_fifo_count = self.rx_fifo_count.load(Acquire);
+        let mut resumed = false;
+        let mut replied = false;
+        let hook = critical_section(|| *self.rx_hook.lock());
+        let mut pro = self.rx_pro.lock();
+        let mut ring = self.rx_ring.get().map(RxRing::writer);
+        let capacity = self
+            .rx_ring
+            .get()
+            .map_or(self.rx_capacity, RxRing::capacity);
+        while let Some(ch) = self.try_recv() {
+            rx_fifo_count += 1;
+            rx_count += 1;
+            if rx_fifo_count == RTS_PULSE_WIDTH {
+                push_trace(SERIAL_RTS);
+                self.rts(false);
+            } else if rx_fifo_count == RTS_PULSE_WIDTH * 2 {
+                push_trace(SERIAL_RTS | 1);
+                self.rts(true);
+                rx_fifo_count = 0;
+            }
+            if self.soft_flow.filter(ch) {
+                resumed |= ch == XON;
+                continue;
+            }
+            if let Some(hook) = hook {
+                let (consumed, reply) = self.run_rx_hook(hook, ch);
+                replied |= reply;
+                if consumed {
+                    continue;
+                }
+            }
+            let queued = match ring.as_mut() {
+                Some(ring) => ring.push(ch),
+                None => pro.enqueue(ch).is_ok(),
+            };
+            if !queued {
+                serial_warn!(
+                    self.base_address(),
+                    "[USER UART] Serial rx buffer overflow!"
+                );
+            } else {
+                self.rx_queued.fetch_add(1, Relaxed);
+            }
+            let len = ring.as_ref().map_or(pro.len(), |ring| ring.len());
+            if len >= capacity {
+                self.rx_full_stops.fetch_add(1, Relaxed);
+                self.disable_rdai();
+                break;
+            }
+        }
+        let len = ring.as_ref().map_or(pro.len(), |ring| ring.len());
+        self.rx_high_water.fetch_max(len, Relaxed);
+        let stop_peer = self.soft_flow.filled(len);
+        // publishes what went into the ring
+        drop(ring);
+        drop(pro);
+        if stop_peer {
+            self.send_flow(XOFF);
+        } else if resumed || replied {
+            self.toggle_threi();
+        }
+        if rx_count > 0 {
+            self.last_rx_us.store(now_us(), Relaxed);
+            if let Some(cycles) = at {
+                self.record_rx_timing(RxTiming {
+                    cycles,
+                    len: rx_count,
+                    char_timeout,
+                });
+            }
+        }
+        self.mark_overrun();
+        self.rx_fifo_count.store(rx_fifo_count, Release);
+        self.rx_count.fetch_add(rx_count, Relaxed);
+        self.wake(&self.read_waker, ASYNC_READ_WAKE);
+        rx_count
+    }
+
+    fn record_rx_timing(&self, timing: RxTiming) {
+        critical_section(|| {
+            let mut ring = self.rx_timing.lock();
+            if ring.len() == RX_TIMING_LEN {
+                ring.pop_front();
+            }
+            ring.push_back(timing);
+        });
+    }
+
+    /// Has the rx path record when it drains the FIFO and how much, for
+    /// `rx_timing_events`. Off by default, so the cycle counter is not
+    /// read for nothing. Turning it on starts with an empty record.
+    pub fn set_rx_timing(&self, on: bool) {
+        critical_section(|| {
+            self.rx_timing_on.store(on, Relaxed);
+            self.rx_timing.lock().clear();
+        });
+    }
+
+    /// Takes the drains recorded since the last call, oldest first. Only
+    /// the last `RX_TIMING_LEN` are kept.
+    pub fn rx_timing_events(&self) -> Vec<RxTiming> {
+        critical_section(|| self.rx_timing.lock().drain(..).collect())
+    }
+
+    fn line_status(&self, lsr: u8) {
+        if lsr & LSR_FIFO_ERROR != 0 {
+            if lsr & LSR_BI != 0 {
+                serial_warn!(self.base_address(), "[uart] lsr.BI!");
+                self.post_event(SerialEventKind::Break);
+            }
+            if lsr & LSR_FE != 0 {
+                serial_warn!(self.base_address(), "[uart] lsr.FE!");
+                self.post_event(SerialEventKind::FramingError);
+            }
+            if lsr & LSR_PE != 0 {
+                serial_warn!(self.base_address(), "[uart] lsr.PE!");
+                self.post_event(SerialEventKind::ParityError);
+            }
+        }
+        if lsr & LSR_OE != 0 {
+            let count = self.overrun_count.fetch_add(1, Relaxed) + 1;
+            push_trace(SERIAL_OVERRUN | count.min(0xfff));
+            self.overrun_seen.store(true, SeqCst);
+            self.hardware().modify_mcr(|mcr| mcr & !MCR_RTS);
+            serial_warn!(self.base_address(), "[uart] lsr.OE!");
+            self.post_event(SerialEventKind::Overrun);
+        }
+    }
+
+    /// `msr` has at least one delta bit set.
+    fn modem_status(&self, msr: u8) {
+        if msr & MSR_DDSR != 0 {
+            self.post_event(SerialEventKind::DsrChanged(msr & MSR_DSR != 0));
+        }
+        if msr & MSR_DDCD != 0 {
+            self.post_event(SerialEventKind::CarrierChanged(msr & MSR_DCD != 0));
+        }
+        if msr & MSR_TERI != 0 {
+            self.post_event(SerialEventKind::Ring);
+        }
+        if msr & MSR_DCTS != 0 {
+            let cts = msr & MSR_CTS != 0;
+            if cts == self.prev_cts.load(Relaxed) {
+                push_trace(SERIAL_CTS | (RTS_PULSE_WIDTH * 2));
+                self.tx_fifo_count
+                    .fetch_add(-(RTS_PULSE_WIDTH as isize * 2), Relaxed);
+            } else {
+                push_trace(SERIAL_CTS | RTS_PULSE_WIDTH);
+                self.tx_fifo_count
+                    .fetch_add(-(RTS_PULSE_WIDTH as isize), Relaxed);
+            }
+            self.prev_cts.store(cts, Relaxed);
+            self.toggle_threi();
+            self.wake(&self.write_waker, ASYNC_WRITE_WAKE);
+        }
+    }
+
+    /// What `interrupt_handler` does, found from the status registers
+    /// instead of IIR, which reports nothing with IER clear. The wanted
+    /// interrupts still decide which side is served.
+    fn poll_hardware(&self) {
+        let _service = self.service.lock();
+        let block = self.hardware();
+        let lsr = block.read_lsr();
+        if lsr & (LSR_FIFO_ERROR | LSR_OE) != 0 {
+            self.line_status(lsr);
+        }
+        if lsr & LSR_DR != 0 && self.rx_intr_enabled.load(SeqCst) {
+            self.receive(false);
+        }
+        let msr = block.read_msr();
+        if msr & (MSR_DCTS | MSR_DDSR | MSR_DDCD | MSR_TERI) != 0 {
+            self.modem_status(msr);
+        }
+        if lsr & LSR_THRE != 0 && self.tx_intr_enabled.load(SeqCst) {
+            self.start_tx();
+        }
+    }
+
+    /// Resolves once no byte has come in for `char_times` character times,
+    /// e.g. the 3.5, so 4, that end a Modbus RTU frame. Every byte that
+    /// comes in meanwhile starts the window over. Woken like `Sleep`.
+    ///
+    /// Bytes below the rx trigger level wait in the FIFO until a character
+    /// timeout, so whenever the window seems over the FIFO is drained here
+    /// first, and anything found starts it over.
+    pub async fn wait_idle(&self, char_times: u32) {
+        let window_us = char_times as usize * self.char_time_us();
+        loop {
+            let deadline = self.last_rx_us.load(Relaxed) + window_us;
+            if now_us() < deadline {
+                Sleep::until(deadline).await;
+            } else if !self.drain_rx_fifo() {
+                return;
+            }
+        }
+    }
+
+    /// How long a byte takes on the line at 8N1, 0 if the baud rate is
+    /// not known.
+    fn char_time_us(&self) -> usize {
+        10_000_000usize
+            .checked_div(self.baud_rate.load(Relaxed))
+            .unwrap_or(0)
+    }
+
+    /// Moves what the rx FIFO holds into the rx queue, unless that is full
+    /// or the port suspended. Returns whether anything was moved.
+    fn drain_rx_fifo(&self) -> bool {
+        critical_section(|| {
+            if self.suspended.lock().is_some() {
+                return false;
+            }
+            let _service = self.service.lock();
+            let lsr = self.hardware().read_lsr();
+            if lsr & (LSR_FIFO_ERROR | LSR_OE) != 0 {
+                self.line_status(lsr);
+            }
+            lsr & LSR_DR != 0 && self.rx_intr_enabled.load(SeqCst) && self.receive(false) > 0
+        })
+    }
+
+    /// Service the device by hand: through `interrupt_handler` before the
+    /// external interrupt is claimed and enabled, from the status
+    /// registers in `DriveMode::Polled`.
+    #[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
+    pub fn pump(&self) {
+        critical_section(|| {
+            if self.suspended.lock().is_some() || self.is_closed() {
+                return;
+            }
+            if self.polled.load(SeqCst) {
+                self.poll_hardware();
+            } else {
+                self.interrupt_handler();
+            }
+        });
+    }
+
+    /// Several tasks can read at once, up to `MAX_WAITERS`. Bytes coming in
+    /// wake them all and go to whichever polls first, the others wait
+    /// again. A read takes its waker out once done or dropped. Fails only
+    /// with `SerialError::Closed`, a re-init is waited through.
+    pub async fn read(self: Arc<Self>, buf: &mut [u8]) -> Result<(), SerialError> {
+        self.read_future(buf, false).await
+    }
+
+    pub async fn write(self: Arc<Self>, buf: &[u8]) -> Result<(), SerialError> {
+        self.write_future(buf, false).await
+    }
+
+    /// Like `read`, but fails if the port is re-initialized or closed
+    /// before `buf` is full. What was read into `buf` so far stays there.
+    pub async fn read_checked(self: Arc<Self>, buf: &mut [u8]) -> Result<(), SerialError> {
+        self.read_future(buf, true).await
+    }
+
+    /// Like `write`, but fails if the port is re-initialized or closed
+    /// before all of `buf` is queued. Queued bytes the re-init did not
+    /// send are lost.
+    pub async fn write_checked(self: Arc<Self>, buf: &[u8]) -> Result<(), SerialError> {
+        self.write_future(buf, true).await
+    }
+
+    fn read_future(
+        self: Arc<Self>,
+        buf: &mut [u8],
+        fail_on_reinit: bool,
+    ) -> SerialReadFuture<'_, R> {
+        self.register_panic();
+        SerialReadFuture {
+            buf,
+            read_len: 0,
+            epoch: self.epoch.load(SeqCst),
+            fail_on_reinit,
+            driver: self,
+            waiter: 0,
+        }
+    }
+
+    fn write_future(self: Arc<Self>, buf: &[u8], fail_on_reinit: bool) -> SerialWriteFuture<'_, R> {
+        self.register_panic();
+        SerialWriteFuture {
+            buf,
+            write_len: 0,
+            epoch: self.epoch.load(SeqCst),
+            fail_on_reinit,
+            driver: self,
+            waiter: 0,
+        }
+    }
+
+    /// Drops the wakers of every waiting read.
+    pub fn remove_read(&self) {
+        self.read_waker.clear();
+    }
+
+    pub fn has_read_waker(&self) -> bool {
+        self.read_waker.is_set()
+    }
+
+    pub fn has_write_waker(&self) -> bool {
+        self.write_waker.is_set()
+    }
+
+    /// Whether a read waits, or was woken and has not polled yet, when
+    /// `has_read_waker` is false for either.
+    pub fn read_waker_state(&self) -> WakerState {
+        self.read_waker.state()
+    }
+
+    pub fn write_waker_state(&self) -> WakerState {
+        self.write_waker.state()
+    }
+
+    /// Drops the wakers of every waiting write.
+    pub fn remove_write(&self) {
+        self.write_waker.clear();
+    }
+
+    /// Resolves once the rx queue holds a byte, at once if it does now.
+    /// Takes nothing out of it, and wakes with the readers of the port.
+    pub fn readable(&self) -> Readiness<'_, R> {
+        Readiness {
+            driver: self,
+            tx: false,
+            waiter: 0,
+        }
+    }
+
+    /// Resolves once the tx queue has room, at once if it has now. Queues
+    /// nothing, and wakes with the writers of the port.
+    pub fn writable(&self) -> Readiness<'_, R> {
+        Readiness {
+            driver: self,
+            tx: true,
+            waiter: 0,
+        }
+    }
+
+    /// Like `read`, but gives up when `token` is cancelled. The waker is
+    /// unregistered in that case.
+    pub async fn read_cancellable(
+        self: Arc<Self>,
+        buf: &mut [u8],
+        token: &CancellationToken,
+    ) -> Result<Result<(), SerialError>, Cancelled> {
+        token
+            .run_until_cancelled(self.read(buf))
+            .await
+            .ok_or(Cancelled)
+    }
+
+    pub async fn write_cancellable(
+        self: Arc<Self>,
+        buf: &[u8],
+        token: &CancellationToken,
+    ) -> Result<Result<(), SerialError>, Cancelled> {
+        token
+            .run_until_cancelled(self.write(buf))
+            .await
+            .ok_or(Cancelled)
+    }
+
+    /// Read until `delim` or until `buf` is full, see `ReadUntil`.
+    pub fn read_until(self: Arc<Self>, delim: u8, buf: &mut [u8]) -> ReadUntil<'_, Arc<Self>> {
+        self.register_panic();
+        ReadUntil::new(self, delim, buf)
+    }
+
+    /// Split the received bytes into lines of at most `N` bytes.
+    pub fn lines<const N: usize>(self: Arc<Self>) -> Lines<Arc<Self>, N> {
+        self.register_panic();
+        Lines::new(self)
+    }
+
+    /// Splits the driver into a reader and a writer half, for one task
+    /// to read while another writes.
+    pub fn split(self: Arc<Self>) -> (SerialReader<R>, SerialWriter<R>) {
+        self.register_panic();
+        (SerialReader::new(self.clone()), SerialWriter::new(self))
+    }
+
+    /// Sends what is queued right away, polling the transmitter with user
+    /// interrupts masked, and waits for it to be idle. Gives up after
+    /// `timeout_us`. Returns whether everything went out, false at once
+    /// while the peer has paused us with XOFF.
+    pub fn flush(&self, timeout_us: usize) -> bool {
+        if self.is_suspended() || self.soft_flow.paused() || self.is_revoked() {
+            return false;
+        }
+        // no waiting for the tx coalescing timer, the drain sends it all
+        self.stop_tx_hold();
+        let (sent, done) = self.tx.drain(timeout_us);
+        self.tx_count.fetch_add(sent, Relaxed);
+        done
+    }
+
+    /// Has the panic handler dump this driver, see `PanicDump`.
+    pub fn register_panic(self: &Arc<Self>) {
+        if !self.panic_registered.swap(true, Relaxed) {
+            register_panic_dump(self);
+        }
+    }
+
+    fn set_read_waker(&self, waker: &Waker, waiter: Option<&mut usize>) {
+        self.read_waker.register(waker, waiter);
+    }
+
+    /// Waits with the readers of this port for bytes to come in, see
+    /// `WakerList::register`.
+    pub(super) fn remove_reader(&self, waiter: &mut usize) {
+        self.read_waker.remove(waiter);
+    }
+
+    fn mark_pending(&self) {
+        let _ = self
+            .pending_since
+            .compare_exchange(0, now_us().max(1), Relaxed, Relaxed);
+    }
+
+    /// Suspects a lost interrupt if a read or write has been pending for
+    /// `threshold_us` with no interrupt handled, and then runs the handler
+    /// by hand. Returns whether it did. Never does in `DriveMode::Polled`.
+    #[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
+    pub fn watchdog(&self, threshold_us: usize) -> bool {
+        let since = self.pending_since.load(Relaxed);
+        if since == 0 || now_us().saturating_sub(since) < threshold_us || self.polled.load(Relaxed)
+        {
+            return false;
+        }
+        self.missed_intr_count.fetch_add(1, Relaxed);
+        push_trace(SERIAL_WATCHDOG | self.port());
+        self.interrupt_handler();
+        true
+    }
+
+    /// Runs `watchdog` every `period_us`, woken by the user timer. Spawn it
+    /// next to the tasks using the serial, it never returns.
+    #[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
+    pub async fn watchdog_task(self: Arc<Self>, period_us: usize, threshold_us: usize) {
+        self.register_panic();
+        loop {
+            sleep_us(period_us).await;
+            self.watchdog(threshold_us);
+        }
+    }
+
+    fn set_write_waker(&self, waker: &Waker, waiter: Option<&mut usize>) {
+        self.write_waker.register(waker, waiter);
+    }
+}
+
+/// Why a checked read or write failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SerialError {
+    /// Bytes went missing to a receiver overrun, reported where in the
+    /// stream it happened.
+    Overrun,
+    /// The port was re-initialized while a `read_checked` or
+    /// `write_checked` was pending.
+    Reinit,
+    /// The port was closed, see `AsyncSerial::close` and `revoke`. `done`
+    /// bytes were read into or queued from the buffer before, 0 for the
+    /// calls that return a count.
+    Closed { done: usize },
+}
+
+/// The urgent lane is full, see `AsyncSerial::write_urgent`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Busy;
+
+/// What the rx hook wants done with a byte, see
+/// `AsyncSerial::set_rx_hook`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HookAction {
+    /// Drops the byte, reads never see it.
+    pub consume: bool,
+    /// Sent ahead of the tx queue, as by `AsyncSerial::write_urgent`.
+    pub reply: Option<u8>,
+}
+
+impl HookAction {
+    /// The byte is queued as if there were no hook.
+    pub const PASS: HookAction = HookAction {
+        consume: false,
+        reply: None,
+    };
+    pub const CONSUME: HookAction = HookAction {
+        consume: true,
+        reply: None,
+    };
+
+    pub const fn with_reply(self, reply: u8) -> Self {
+        HookAction {
+            reply: Some(reply),
+            ..self
+        }
+    }
+}
+
+/// Received bytes lent in place, see `AsyncSerial::read_chunk` and
+/// `fill_buf`. Dropped without `consume`, they stay for the next read.
+pub struct RxChunk<'a, R: UartRegisters = UartMmio> {
+    driver: &'a AsyncSerial<R>,
+    source: ChunkSource<'a>,
+}
+
+enum ChunkSource<'a> {
+    Ring { ring: &'a RxRing, data: &'a [u8] },
+    Stage(MutexGuard<'a, RxStage>),
+}
+
+/// See `AsyncSerial::fill_buf`.
+struct RxStage {
+    buf: [u8; RX_STAGE_LEN],
+    start: usize,
+    end: usize,
+}
+
+impl RxStage {
+    const fn new() -> Self {
+        RxStage {
+            buf: [0; RX_STAGE_LEN],
+            start: 0,
+            end: 0,
+        }
+    }
+}
+
+impl<R: UartRegisters> RxChunk<'_, R> {
+    /// Takes the first `len` bytes, the rest are there for the next
+    /// chunk or read.
+    pub fn consume(mut self, len: usize) {
+        let len = len.min(self.len());
+        let driver = self.driver;
+        match &mut self.source {
+            ChunkSource::Ring { ring, .. } => {
+                ring.advance(len);
+                driver.rx_taken.fetch_add(len, Relaxed);
+                driver.release_peer(driver.rx_len());
+            }
+            // taken from the queue when staged
+            ChunkSource::Stage(stage) => {
+                stage.start += len;
+                driver.rx_staged.fetch_sub(len, Relaxed);
+            }
+        }
+        if !driver.rx_intr_enabled.load(Relaxed) {
+            driver.enable_rdai();
+        }
+    }
+}
+
+impl<R: UartRegisters> core::ops::Deref for RxChunk<'_, R> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.source {
+            ChunkSource::Ring { data, .. } => data,
+            ChunkSource::Stage(stage) => &stage.buf[stage.start..stage.end],
+        }
+    }
+}
+
+impl<R: UartRegisters> Drop for RxChunk<'_, R> {
+    fn drop(&mut self) {
+        if let ChunkSource::Ring { ring, .. } = self.source {
+            ring.return_chunk();
+        }
+    }
+}
+
+/// Resolves to a chunk of at least one byte, or an empty one once the
+/// port is closed, see `AsyncSerial::fill_buf`. Dropping it while it
+/// waits unregisters its read waker.
+pub struct FillBuf<'a, R: UartRegisters = UartMmio> {
+    driver: &'a AsyncSerial<R>,
+    /// Key of our entry in the read waiters, 0 if none.
+    waiter: usize,
+}
+
+impl<'a, R: UartRegisters> Future for FillBuf<'a, R> {
+    type Output = RxChunk<'a, R>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let driver = self.driver;
+        // register first, so a byte arriving after the check below still wakes us
+        driver.set_read_waker(cx.waker(), Some(&mut self.waiter));
+        let chunk = match driver.rx_ring.get() {
+            Some(_) => driver.read_chunk(),
+            None => driver.stage_chunk(),
+        };
+        match chunk {
+            Some(chunk) if !chunk.is_empty() || driver.is_closed() => {
+                driver.read_waker.remove(&mut self.waiter);
+                driver.pending_since.store(0, Relaxed);
+                Poll::Ready(chunk)
+            }
+            Some(_) => {
+                if !driver.rx_intr_enabled.load(Relaxed) {
+                    driver.enable_rdai();
+                }
+                driver.mark_pending();
+                Poll::Pending
+            }
+            None => {
+                // nothing wakes us when the other chunk goes
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<R: UartRegisters> Drop for FillBuf<'_, R> {
+    fn drop(&mut self) {
+        self.driver.read_waker.remove(&mut self.waiter);
+    }
+}
+
+/// Bytes sent before the tx queue, see `AsyncSerial::write_urgent`.
+struct UrgentLane {
+    /// XON or XOFF, ahead of the rest.
+    flow: Option<u8>,
+    bytes: Deque<u8, URGENT_LANE_LEN>,
+    xoff_sent: usize,
+    xon_sent: usize,
+}
+
+impl UrgentLane {
+    const fn new() -> Self {
+        UrgentLane {
+            flow: None,
+            bytes: Deque::new(),
+            xoff_sent: 0,
+            xon_sent: 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.flow.is_none() && self.bytes.is_empty()
+    }
+
+    /// The next byte to send, counting XON and XOFF as they go.
+    fn pop(&mut self) -> Option<u8> {
+        let ch = match self.flow.take() {
+            Some(ch) => ch,
+            None => return self.bytes.pop_front(),
+        };
+        if ch == XOFF {
+            self.xoff_sent += 1;
+        } else {
+            self.xon_sent += 1;
+        }
+        Some(ch)
+    }
+}
+
+/// One drain of the rx FIFO, see `AsyncSerial::set_rx_timing`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RxTiming {
+    /// `timer::cycles` when the drain started.
+    pub cycles: usize,
+    /// Bytes drained.
+    pub len: usize,
+    /// On a character timeout, raised four character times after the last
+    /// byte came in.
+    pub char_timeout: bool,
+}
+
+/// How an `AsyncSerial` is serviced, see `set_mode`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DriveMode {
+    /// By `interrupt_handler`, the default.
+    Interrupt,
+    /// By `pump` only, with the port's interrupts off.
+    Polled,
+}
+
+/// Cycles `interrupt_handler` calls took on the harts' cycle counters.
+/// Calls that find the port closed or polled return at once and are left
+/// out.
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HandlerCycles {
+    pub calls: usize,
+    /// 0 before the first call.
+    pub min: usize,
+    pub max: usize,
+    pub total: usize,
+    /// Calls over the budget, see `AsyncSerial::set_handler_budget`.
+    pub over_budget: usize,
+    /// Calls the budget stopped with the IRQ maybe still raised.
+    pub cut_short: usize,
+}
+
+impl HandlerCycles {
+    /// 0 before the first call.
+    pub fn mean(&self) -> usize {
+        self.total.checked_div(self.calls).unwrap_or(0)
+    }
+}
+
+/// Cycles one `interrupt_handler` call should stay within.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HandlerBudget {
+    pub cycles: usize,
+    /// Stop reading IIR once over it, leaving the rest to the next call.
+    pub cut_short: bool,
+}
+
+/// Counters of one port.
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SerialStats {
+    pub rx_count: usize,
+    pub tx_count: usize,
+    pub intr_count: usize,
+    pub rx_intr_count: usize,
+    pub rx_timeout_count: usize,
+    pub rx_coalesce_count: usize,
+    pub tx_intr_count: usize,
+    /// Tx coalescing timer expiries, see `AsyncSerial::set_tx_coalescing`.
+    pub tx_coalesce_count: usize,
+    pub intr_cycles: usize,
+    /// Wakes from the interrupt handler of a task that registered its waker
+    /// on another hart.
+    pub cross_hart_wakes: usize,
+    /// `interrupt_handler` calls per hart, to check the IRQ affinity.
+    pub intr_harts: [usize; MAX_HART_NUM],
+    /// `interrupt_handler` calls on another hart than the call before,
+    /// each one moves the queue indices between caches.
+    pub intr_hart_switches: usize,
+    /// `interrupt_handler` calls that gave up with the IRQ still raised,
+    /// after its bound of IIR reads or at an IID it does not know.
+    /// The next call goes on, a count that keeps growing points at an
+    /// IIR stuck in hardware.
+    pub irq_loop_bailouts: usize,
+    pub handler: HandlerCycles,
+    /// IIDs read that the driver does not know, see
+    /// `AsyncSerial::unknown_iids` for the values.
+    pub unknown_iids: usize,
+    /// Times a full rx queue turned the rx interrupt off. The bytes after
+    /// wait in the FIFO, where they are lost once it overflows unless flow
+    /// control stops the peer first.
+    pub rx_full_stops: usize,
+    /// Calls of the rx hook, see `AsyncSerial::set_rx_hook`.
+    pub rx_hook_count: usize,
+    /// Hook replies lost to a full urgent lane.
+    pub rx_hook_drops: usize,
+    pub read_waker: WakerStats,
+    pub write_waker: WakerStats,
+    pub missed_intr_count: usize,
+    pub overrun_count: usize,
+    /// Most bytes the rx and tx queues held, 0 for drivers without
+    /// queues of their own.
+    pub rx_high_water: usize,
+    pub tx_high_water: usize,
+    pub fifo_depth: usize,
+    /// Frames received through `FramedSerial` or `Resync`.
+    pub frames: FrameStats,
+    pub soft_flow: SoftFlowStats,
+}
+
+impl SerialStats {
+    /// Rx interrupts and coalescing timer expiries per KiB received, what
+    /// coalescing brings down. 0 before anything was received.
+    pub fn rx_intr_per_kb(&self) -> usize {
+        let intrs = self.rx_intr_count + self.rx_timeout_count + self.rx_coalesce_count;
+        (intrs * 1024).checked_div(self.rx_count).unwrap_or(0)
+    }
+
+    /// THR empty interrupts per KiB sent, what tx coalescing brings down.
+    /// 0 before anything was sent.
+    pub fn tx_intr_per_kb(&self) -> usize {
+        (self.tx_intr_count * 1024)
+            .checked_div(self.tx_count)
+            .unwrap_or(0)
+    }
+}
+
+/// Tasks waiting on one direction of a port. A wake wakes every one of
+/// them and empties the list, each polls again and registers anew if it
+/// still has to wait. No wake gets lost to the wrong task that way, for a
+/// spurious poll of the others.
+pub(super) struct WakerList {
+    /// Only locked with user interrupts masked, so the interrupt handler
+    /// never finds it held by the task it interrupted.
+    waiters: Mutex<Deque<Waiter, MAX_WAITERS>>,
+    next_key: AtomicUsize,
+    /// A `WakerState`, only changed with `waiters` locked.
+    state: AtomicU8,
+    registered: AtomicUsize,
+    woken: AtomicUsize,
+    repolled: AtomicUsize,
+    left: AtomicUsize,
+    idle_wakes: AtomicUsize,
+    redundant_wakes: AtomicUsize,
+}
+
+/// Where the waiters of one direction of a port are, see
+/// `AsyncSerial::read_waker_state`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WakerState {
+    /// Nobody waits.
+    Empty,
+    /// A task waits for the next wake.
+    Registered,
+    /// The wake went out, the task has not polled since. Nothing to wake
+    /// either, but it is on its way.
+    Woken,
+}
+
+impl WakerState {
+    fn from_u8(state: u8) -> Self {
+        match state {
+            1 => WakerState::Registered,
+            2 => WakerState::Woken,
+            _ => WakerState::Empty,
+        }
+    }
+}
+
+/// Transitions of one `WakerState`, see `SerialStats::read_waker`.
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct WakerStats {
+    /// To `Registered`, from `Empty` or by a task the last wake did not
+    /// take out.
+    pub registered: usize,
+    /// From `Registered` to `Woken`.
+    pub woken: usize,
+    /// From `Woken` to `Registered` by a future the wake took out, polled
+    /// and waiting again. Only futures that keep the key of their entry
+    /// are told apart, a registration by waker alone counts as registered.
+    pub repolled: usize,
+    /// To `Empty`, the last waiter got what it waited for or gave up.
+    pub left: usize,
+    /// Wakes with nobody registered.
+    pub idle_wakes: usize,
+    /// Wakes while `Woken`, left out.
+    pub redundant_wakes: usize,
+}
+
+struct Waiter {
+    key: usize,
+    waker: Waker,
+    /// Hart the waker was registered on.
+    hart: usize,
+}
+
+impl WakerList {
+    pub(super) const fn new() -> Self {
+        WakerList {
+            waiters: Mutex::new(Deque::new()),
+            next_key: AtomicUsize::new(1),
+            state: AtomicU8::new(WakerState::Empty as u8),
+            registered: AtomicUsize::new(0),
+            woken: AtomicUsize::new(0),
+            repolled: AtomicUsize::new(0),
+            left: AtomicUsize::new(0),
+            idle_wakes: AtomicUsize::new(0),
+            redundant_wakes: AtomicUsize::new(0),
+        }
+    }
+
+    pub(super) fn state(&self) -> WakerState {
+        WakerState::from_u8(self.state.load(Relaxed))
+    }
+
+    /// Moves to `to` and counts the transition. Only with `waiters` locked.
+    fn enter(&self, to: WakerState) {
+        let from = WakerState::from_u8(self.state.swap(to as u8, Relaxed));
+        let counter = match (from, to) {
+            (WakerState::Registered, WakerState::Woken) => &self.woken,
+            (WakerState::Registered | WakerState::Woken, WakerState::Empty) => &self.left,
+            _ => return,
+        };
+        counter.fetch_add(1, Relaxed);
+    }
+
+    /// `enter` for a new entry. `repoll` is a future that had an entry
+    /// until a wake or an eviction took it out.
+    fn enter_registered(&self, repoll: bool) {
+        let from = WakerState::from_u8(self.state.swap(WakerState::Registered as u8, Relaxed));
+        let counter = match from {
+            WakerState::Registered => return,
+            WakerState::Woken if repoll => &self.repolled,
+            WakerState::Empty | WakerState::Woken => &self.registered,
+        };
+        counter.fetch_add(1, Relaxed);
+    }
+
+    pub(super) fn stats(&self) -> WakerStats {
+        WakerStats {
+            registered: self.registered.load(Relaxed),
+            woken: self.woken.load(Relaxed),
+            repolled: self.repolled.load(Relaxed),
+            left: self.left.load(Relaxed),
+            idle_wakes: self.idle_wakes.load(Relaxed),
+            redundant_wakes: self.redundant_wakes.load(Relaxed),
+        }
+    }
+
+    pub(super) fn reset_stats(&self) {
+        for counter in [
+            &self.registered,
+            &self.woken,
+            &self.repolled,
+            &self.left,
+            &self.idle_wakes,
+            &self.redundant_wakes,
+        ] {
+            counter.store(0, Relaxed);
+        }
+    }
+
+    /// Adds `waker` to the list. A future keeps the key of its entry in
+    /// `waiter`, 0 while it has none, for the next poll and for `remove`.
+    /// Without `waiter` an entry that wakes the same task is taken. Only
+    /// clones `waker` if it differs from the one in the entry.
+    ///
+    /// Past `MAX_WAITERS` the oldest entry is woken and dropped.
+    pub(super) fn register(&self, waker: &Waker, waiter: Option<&mut usize>) {
+        let evicted = critical_section(|| {
+            let mut waiters = self.waiters.lock();
+            let found = match &waiter {
+                Some(key) => waiters.iter_mut().find(|entry| entry.key == **key),
+                None => waiters
+                    .iter_mut()
+                    .find(|entry| entry.waker.will_wake(waker)),
+            };
+            if let Some(entry) = found {
+                if !entry.waker.will_wake(waker) {
+                    entry.waker = waker.clone();
+                }
+                entry.hart = hart_id();
+                return None;
+            }
+            // a key with no entry left, a wake or an eviction took it out
+            let repoll = matches!(&waiter, Some(key) if **key != 0);
+            let evicted = if waiters.is_full() {
+                waiters.pop_front()
+            } else {
+                None
+            };
+            self.enter_registered(repoll);
+            let key = self.next_key.fetch_add(1, Relaxed);
+            if let Some(waiter) = waiter {
+                *waiter = key;
+            }
+            let entry = Waiter {
+                key,
+                waker: waker.clone(),
+                hart: hart_id(),
+            };
+            // there is room, the oldest made way
+            let _ = waiters.push_back(entry);
+            evicted
+        });
+        // wake outside the section, it may signal another hart
+        if let Some(entry) = evicted {
+            entry.waker.wake();
+        }
+    }
+
+    /// Takes out the entry of `waiter` if it is still there, and forgets
+    /// the key.
+    pub(super) fn remove(&self, waiter: &mut usize) {
+        let key = core::mem::replace(waiter, 0);
+        if key == 0 {
+            return;
+        }
+        critical_section(|| {
+            let mut waiters = self.waiters.lock();
+            // `Deque` has no `remove`, go round once and leave it out
+            for _ in 0..waiters.len() {
+                let entry = waiters.pop_front().unwrap();
+                if entry.key != key {
+                    let _ = waiters.push_back(entry);
+                }
+            }
+            if waiters.is_empty() {
+                self.enter(WakerState::Empty);
+            }
+        });
+    }
+
+    pub(super) fn clear(&self) {
+        critical_section(|| {
+            self.waiters.lock().clear();
+            self.enter(WakerState::Empty);
+        });
+    }
+
+    pub(super) fn is_set(&self) -> bool {
+        critical_section(|| !self.waiters.lock().is_empty())
+    }
+
+    /// Wakes and drops every entry. Returns how many of the wakers were
+    /// registered on another hart. Counts a wake with nobody registered,
+    /// or nobody polled since the last wake, and does nothing else.
+    pub(super) fn wake(&self, trace_event: usize) -> usize {
+        let waiters = critical_section(|| {
+            let mut waiters = self.waiters.lock();
+            let redundant = match self.state() {
+                WakerState::Registered => None,
+                WakerState::Empty => Some(&self.idle_wakes),
+                WakerState::Woken => Some(&self.redundant_wakes),
+            };
+            if let Some(counter) = redundant {
+                counter.fetch_add(1, Relaxed);
+                return Deque::new();
+            }
+            self.enter(WakerState::Woken);
+            core::mem::replace(&mut *waiters, Deque::new())
+        });
+        let hart = hart_id();
+        let mut remote = 0;
+        for entry in waiters {
+            push_trace(trace_event);
+            if entry.hart != hart {
+                remote += 1;
+            }
+            entry.waker.wake();
+        }
+        remote
+    }
+}
+
+impl<R: UartRegisters> AsyncRead for AsyncSerial<R> {
+    fn poll_read(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<usize> {
+        self.poll_read_as(cx, buf, None)
+    }
+}
+
+impl<R: UartRegisters> AsyncSerial<R> {
+    /// `poll_read` for a future that keeps its entry in the read waiters,
+    /// see `WakerList::register`. The entry is taken out once it is ready.
+    pub(super) fn poll_read_as(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+        mut waiter: Option<&mut usize>,
+    ) -> Poll<usize> {
+        if buf.is_empty() {
+            return Poll::Ready(0);
+        }
+        // register first, so a byte arriving after the check below still wakes us
+        self.set_read_waker(cx.waker(), waiter.as_deref_mut());
+        let mut len = 0;
+        if self.rx_ring.get().is_some() {
+            // one copy out of the ring instead of a dequeue per byte
+            len = self.read_available(buf);
+        }
+        while len < buf.len() {
+            match self.try_read() {
+                Some(data) => {
+                    buf[len] = data;
+                    len += 1;
+                }
+                None => break,
+            }
+        }
+        if !self.rx_intr_enabled.load(Relaxed) {
+            self.enable_rdai();
+        }
+        push_trace(ASYNC_READ_POLL | len);
+        if len > 0 || self.is_closed() {
+            if let Some(waiter) = waiter {
+                self.read_waker.remove(waiter);
+            }
+            self.pending_since.store(0, Relaxed);
+            Poll::Ready(len)
+        } else {
+            self.mark_pending();
+            Poll::Pending
+        }
+    }
+}
+
+impl<R: UartRegisters> Drop for AsyncSerial<R> {
+    fn drop(&mut self) {
+        // else the caller of `close` got the report
+        if !self.is_closed() {
+            record_drop(self.close());
+        }
+    }
+}
+
+/// Counters, queue fill and interrupt enables. Reads IER only, and only
+/// tries the queue locks, `?` if one is held.
+impl<R: UartRegisters> fmt::Debug for AsyncSerial<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rx_len = self.rx_con.try_lock().map(|con| self.queued_len(&con));
+        let tx_len = self.tx.queue.try_lock().map(|con| con.len());
+        f.debug_struct("AsyncSerial")
+            .field("base_address", &format_args!("{:#x}", self.base_address()))
+            .field("mode", &self.drive_mode())
+            .field("rx_queue", &format_args!("{}", QueueLen(rx_len)))
+            .field("tx_queue", &format_args!("{}", QueueLen(tx_len)))
+            .field("rx_intr_enabled", &self.rx_intr_enabled.load(Relaxed))
+            .field("tx_intr_enabled", &self.tx_intr_enabled.load(Relaxed))
+            .field("read_waker", &self.read_waker.state())
+            .field("write_waker", &self.write_waker.state())
+            .field("ier", &format_args!("{:#x}", self.hardware().read_ier()))
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+/// One line for periodic logging, e.g. `uart1 rx=12345 tx=9876 irq=456
+/// ovf=2`.
+impl<R: UartRegisters> fmt::Display for AsyncSerial<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} rx={} tx={} irq={} ovf={}",
+            serial::PortName(self.base_address()),
+            self.rx_count.load(Relaxed),
+            self.tx_count.load(Relaxed),
+            self.intr_count.load(Relaxed),
+            self.overrun_count.load(Relaxed)
+        )
+    }
+}
+
+impl<R: UartRegisters> PanicDump for AsyncSerial<R> {
+    /// Counters, queue fill and interrupt enables, both as the driver
+    /// believes them and as IER has them. Reads no register with side
+    /// effects.
+    fn panic_dump(&self, out: &mut dyn fmt::Write) -> fmt::Result {
+        let stats = self.stats();
+        writeln!(
+            out,
+            "[panic] serial {:#x}: rx {} tx {} intr {} (rx {} timeout {} tx {}) missed {}",
+            self.regs.base_address(),
+            stats.rx_count,
+            stats.tx_count,
+            stats.intr_count,
+            stats.rx_intr_count,
+            stats.rx_timeout_count,
+            stats.tx_intr_count,
+            stats.missed_intr_count
+        )?;
+        let rx_len = self.rx_con.try_lock().map(|con| self.queued_len(&con));
+        let tx_len = self.tx.queue.try_lock().map(|con| con.len());
+        let ier = self.hardware().read_ier();
+        writeln!(
+            out,
+            "[panic]   rx queue {}/{} tx queue {}/{}, rx intr {} (IER {}), tx intr {} (IER {})",
+            QueueLen(rx_len),
+            self.rx_ring
+                .get()
+                .map_or(DEFAULT_RX_BUFFER_SIZE, RxRing::capacity),
+            QueueLen(tx_len),
+            DEFAULT_TX_BUFFER_SIZE,
+            self.rx_intr_enabled.load(Relaxed),
+            ier & IER_ERBFI != 0,
+            self.tx_intr_enabled.load(Relaxed),
+            ier & IER_ETBEI != 0
+        )?;
+        writeln!(
+            out,
+            "[panic]   read waker {:?} write waker {:?}",
+            self.read_waker.state(),
+            self.write_waker.state()
+        )?;
+        let since = self.pending_since.load(Relaxed);
+        if since != 0 {
+            writeln!(
+                out,
+                "[panic]   pending for {} us with no interrupt",
+                now_us().saturating_sub(since)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The tx side of a driver, shared with the exit hook so it can send what
+/// is still queued in a driver it does not own.
+struct TxDrain<R> {
+    regs: R,
+    queue: Mutex<TxConsumer>,
+    /// Bytes sent per THR empty, see `AsyncSerial::fifo_depth`.
+    fifo_depth: AtomicUsize,
+    /// Only locked with user interrupts masked, after `queue` if both.
+    urgent: Mutex<UrgentLane>,
+}
+
+/// A `TxDrain` whatever registers it runs on.
+trait DrainTx: Send + Sync {
+    /// Sends the queued bytes a FIFO at a time, as the FIFO empties, then
+    /// waits for the transmitter to be idle. Gives up after `timeout_us`.
+    /// Returns the bytes sent and whether everything went out.
+    ///
+    /// User interrupts stay masked throughout, the handler takes the same
+    /// queue. Only for exit and drop, where the latency no longer matters.
+    fn drain(&self, timeout_us: usize) -> (usize, bool);
+}
+
+/// Live drivers for `drain_all`. Dead entries are pruned on registration.
+static TX_DRAINS: Mutex<Vec<Weak<dyn DrainTx>>> = Mutex::new(Vec::new());
+
+fn register_tx_drain(tx: Arc<dyn DrainTx>) {
+    critical_section(|| {
+        let mut drains = TX_DRAINS.lock();
+        drains.retain(|drain| drain.strong_count() > 0);
+        drains.push(Arc::downgrade(&tx));
+    });
+}
+
+impl<R: UartRegisters> DrainTx for TxDrain<R> {
+    fn drain(&self, timeout_us: usize) -> (usize, bool) {
+        let deadline = now_us() + timeout_us;
+        critical_section(|| {
+            // only held here if a panic hit while it was, don't hang the exit
+            let mut queue = match self.queue.try_lock() {
+                Some(queue) => queue,
+                None => return (0, false),
+            };
+            let mut urgent = match self.urgent.try_lock() {
+                Some(urgent) => urgent,
+                None => return (0, false),
+            };
+            let mut sent = 0;
+            loop {
+                let lsr = self.regs.read_lsr();
+                if lsr & LSR_THRE != 0 {
+                    if urgent.is_empty() && queue.peek().is_none() {
+                        if lsr & LSR_TEMT != 0 {
+                            return (sent, true);
+                        }
+                    } else {
+                        let fifo_depth = self.fifo_depth.load(Relaxed);
+                        let next = || urgent.pop().or_else(|| queue.dequeue());
+                        for ch in core::iter::from_fn(next).take(fifo_depth) {
+                            push_trace(SERIAL_TX | ch as usize);
+                            self.regs.write_thr(ch);
+                            sent += 1;
+                        }
+                    }
+                }
+                if now_us() >= deadline {
+                    return (sent, false);
+                }
+            }
+        })
+    }
+}
+
+/// What a port left behind when it was closed, see `AsyncSerial::close`.
+/// Whatever is counted here never went out or was never read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CloseReport {
+    /// Bytes queued and not sent, those the tx FIFO held at the reset
+    /// too if the UART has a level register.
+    pub tx_unsent: usize,
+    /// Bytes received and not read. `AsyncSerial` reads still get them
+    /// after `close`.
+    pub rx_unread: usize,
+    /// The flush gave up: the time ran out, or the peer had paused us.
+    pub flush_timed_out: bool,
+}
+
+/// Only locked with user interrupts masked.
+static LAST_DROP_REPORT: Mutex<Option<CloseReport>> = Mutex::new(None);
+
+/// Keeps the report of a driver that closed its port in `Drop`, and
+/// traces it as `SERIAL_DROP`.
+pub(super) fn record_drop(report: CloseReport) {
+    push_trace(SERIAL_DROP | report.tx_unsent.min(0xfff));
+    critical_section(|| *LAST_DROP_REPORT.lock() = Some(report));
+}
+
+/// The report of the last `AsyncSerial` or `RingBufferedSerial` dropped
+/// without being closed first, `None` before the first.
+pub fn last_drop_report() -> Option<CloseReport> {
+    critical_section(|| *LAST_DROP_REPORT.lock())
+}
+
+/// Upper bound on the time each port gets to drain on exit or drop. A
+/// full tx queue takes longer than this at 115200 baud.
+pub const EXIT_DRAIN_TIMEOUT_US: usize = 200_000;
+
+/// Sends what is still queued in every live driver, each bounded by
+/// `EXIT_DRAIN_TIMEOUT_US`. `exit` calls it before the kernel releases the
+/// claims. Returns false if a port did not drain in time.
+pub fn drain_all() -> bool {
+    let drains: Vec<Arc<dyn DrainTx>> = critical_section(|| {
+        TX_DRAINS
+            .lock()
+            .iter()
+            .filter_map(|drain| drain.upgrade())
+            .collect()
+    });
+    let mut done = true;
+    for drain in drains {
+        done &= drain.drain(EXIT_DRAIN_TIMEOUT_US).1;
+    }
+    done
+}
+
+struct SerialReadFuture<'a, R: UartRegisters> {
+    buf: &'a mut [u8],
+    read_len: usize,
+    /// `AsyncSerial::epoch` the future was made in.
+    epoch: usize,
+    fail_on_reinit: bool,
+    driver: Arc<AsyncSerial<R>>,
+    /// Key of our entry in the read waiters, 0 if none.
+    waiter: usize,
+}
+
+impl<R: UartRegisters> Future for SerialReadFuture<'_, R> {
+    type Output = Result<(), SerialError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        // drop takes the entry out
+        if this.reinit() {
+            return Poll::Ready(Err(SerialError::Reinit));
+        }
+        if this.driver.is_closed() {
+            return Poll::Ready(Err(SerialError::Closed {
+                done: this.read_len,
+            }));
+        }
+        this.driver
+            .set_read_waker(cx.waker(), Some(&mut this.waiter));
+        while self.read_len < self.buf.len() {
+            match self.driver.try_read() {
+                Some(data) => {
+                    let len = self.read_len;
+                    self.buf[len] = data;
+                    self.read_len += 1;
+                }
+                None => break,
+            }
+        }
+        if self.read_len == self.buf.len() {
+            push_trace(ASYNC_READ_POLL);
+            let this = &mut *self;
+            this.driver.read_waker.remove(&mut this.waiter);
+            self.driver.pending_since.store(0, Relaxed);
+            return Poll::Ready(Ok(()));
+        }
+
+        if !self.driver.rx_intr_enabled.load(Relaxed) {
+            self.driver.enable_rdai();
+        }
+        push_trace(ASYNC_READ_POLL | self.read_len);
+        self.driver.mark_pending();
+        Poll::Pending
+    }
+}
+
+impl<R: UartRegisters> SerialReadFuture<'_, R> {
+    /// Whether to fail, catches up with the driver otherwise.
+    fn reinit(&mut self) -> bool {
+        let epoch = self.driver.epoch.load(SeqCst);
+        if epoch == self.epoch {
+            return false;
+        }
+        self.epoch = epoch;
+        self.fail_on_reinit
+    }
+}
+
+/// See `AsyncSerial::readable` and `writable`.
+pub struct Readiness<'a, R: UartRegisters = UartMmio> {
+    driver: &'a AsyncSerial<R>,
+    tx: bool,
+    /// Key of our entry in the waiters, 0 if none.
+    waiter: usize,
+}
+
+impl<'a, R: UartRegisters> Readiness<'a, R> {
+    fn waiters(&self) -> &'a WakerList {
+        if self.tx {
+            &self.driver.write_waker
+        } else {
+            &self.driver.read_waker
+        }
+    }
+}
+
+impl<R: UartRegisters> Future for Readiness<'_, R> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let driver = self.driver;
+        let waiters = self.waiters();
+        // register first, so a change after the check below still wakes us
+        waiters.register(cx.waker(), Some(&mut self.waiter));
+        let ready = if driver.is_closed() {
+            // the read or write that follows finds out
+            true
+        } else if self.tx {
+            driver.tx_space() > 0
+        } else {
+            if !driver.rx_intr_enabled.load(Relaxed) {
+                driver.enable_rdai();
+            }
+            driver.rx_len() > 0
+        };
+        if ready {
+            waiters.remove(&mut self.waiter);
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl<R: UartRegisters> Drop for Readiness<'_, R> {
+    fn drop(&mut self) {
+        self.waiters().remove(&mut self.waiter);
+    }
+}
+
+impl<R: UartRegisters> Drop for SerialReadFuture<'_, R> {
+    fn drop(&mut self) {
+        // cancelled while waiting, don't leave the waker behind
+        self.driver.read_waker.remove(&mut self.waiter);
+    }
+}
+
+struct SerialWriteFuture<'a, R: UartRegisters> {
+    buf: &'a [u8],
+    write_len: usize,
+    epoch: usize,
+    fail_on_reinit: bool,
+    driver: Arc<AsyncSerial<R>>,
+    /// Key of our entry in the write waiters, 0 if none.
+    waiter: usize,
+}
+
+impl<R: UartRegisters> Future for SerialWriteFuture<'_, R> {
+    type Output = Result<(), SerialError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.reinit() {
+            return Poll::Ready(Err(SerialError::Reinit));
+        }
+        if self.driver.is_closed() {
+            return Poll::Ready(Err(SerialError::Closed {
+                done: self.write_len,
+            }));
+        }
+        if self.buf.is_empty() {
+            return Poll::Ready(Ok(()));
+        }
+        let this = &mut *self;
+        this.driver
+            .set_write_waker(cx.waker(), Some(&mut this.waiter));
+        while self.write_len < self.buf.len() {
+            match self.driver.try_write(self.buf[self.write_len]) {
+                Ok(()) => self.write_len += 1,
+                Err(_) => break,
+            }
+        }
+        // Raise THREI only once the bytes are queued, its handler sends
+        // them. Without CTS credit the next CTS edge raises it instead.
+        self.driver.kick_tx();
+        if self.write_len == self.buf.len() {
+            push_trace(ASYNC_WRITE_POLL);
+            let this = &mut *self;
+            this.driver.write_waker.remove(&mut this.waiter);
+            self.driver.pending_since.store(0, Relaxed);
+            return Poll::Ready(Ok(()));
+        }
+
+        push_trace(ASYNC_WRITE_POLL | self.write_len);
+        self.driver.mark_pending();
+        Poll::Pending
+    }
+}
+
+impl<R: UartRegisters> SerialWriteFuture<'_, R> {
+    fn reinit(&mut self) -> bool {
+        let epoch = self.driver.epoch.load(SeqCst);
+        if epoch == self.epoch {
+            return false;
+        }
+        self.epoch = epoch;
+        self.fail_on_reinit
+    }
+}
+
+impl<R: UartRegisters> Drop for SerialWriteFuture<'_, R> {
+    fn drop(&mut self) {
+        self.driver.write_waker.remove(&mut self.waiter);
+    }
+}
+
+fn assert_send<T: Send>() {}
+fn assert_sync<T: Sync>() {}
+fn assert_send_future<F: Future + Send>(_: F) {}
+
+/// Never called, it only has to build: a task holding one of these may be
+/// moved to another hart, see `AsyncSerial`.
+#[allow(dead_code)]
+fn assert_cross_hart<R: UartRegisters>(serial: Arc<AsyncSerial<R>>) {
+    assert_send::<AsyncSerial<R>>();
+    assert_sync::<AsyncSerial<R>>();
+    assert_send::<SerialReadFuture<'static, R>>();
+    assert_send::<SerialWriteFuture<'static, R>>();
+    assert_send::<Readiness<'static, R>>();
+    assert_send::<FillBuf<'static, R>>();
+    assert_send::<RxChunk<'static, R>>();
+    assert_send::<SerialReader<R>>();
+    assert_send::<SerialWriter<R>>();
+    assert_send::<ReadSome<'static, R>>();
+    assert_send_future(serial.clone().read(&mut []));
+    assert_send_future(serial.clone().write(&[]));
+    assert_send_future(serial.clone().read_some_checked(&mut []));
+    assert_send_future(serial.wait_idle(1));
+}
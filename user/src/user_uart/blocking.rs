@@ -0,0 +1,180 @@
+use super::*;
+use crate::wait_ext_int;
+
+/// Sleeps in the kernel on the port's IRQ instead of spinning like
+/// `PollingSerial`, and needs no user interrupt handling or executor like
+/// `AsyncSerial`. Only the claim is needed, and the port must not also
+/// have user external interrupts enabled.
+pub struct BlockingSerial {
+    mmio: UartMmio,
+    irq: u16,
+    pub rx_count: usize,
+    pub tx_count: usize,
+    /// Times the IRQ was waited on, and how many of those timed out.
+    pub wait_count: usize,
+    pub timeout_count: usize,
+}
+
+impl BlockingSerial {
+    /// Only for ports in `RegLayout::BOARD`, panics on others.
+    pub fn from_claim(claim: &SerialClaim) -> Self {
+        BlockingSerial {
+            mmio: claim.registers(),
+            irq: claim.irq(),
+            rx_count: 0,
+            tx_count: 0,
+            wait_count: 0,
+            timeout_count: 0,
+        }
+    }
+
+    fn hardware(&self) -> &UartMmio {
+        &self.mmio
+    }
+
+    fn set_divisor(&self, clock: usize, baud_rate: usize) {
+        let block = self.hardware();
+        let divisor = clock / (16 * baud_rate);
+        block.lcr.modify(|_, w| w.dlab().divisor_latch());
+        #[cfg(feature = "board_lrv")]
+        {
+            block
+                .dll()
+                .write(|w| unsafe { w.bits((divisor & 0b1111_1111) as u32) });
+            block
+                .dlh()
+                .write(|w| unsafe { w.bits(((divisor >> 8) & 0b1111_1111) as u32) });
+        }
+        #[cfg(feature = "board_qemu")]
+        {
+            block
+                .dll()
+                .write(|w| unsafe { w.bits((divisor & 0b1111_1111) as u8) });
+            block
+                .dlh()
+                .write(|w| unsafe { w.bits(((divisor >> 8) & 0b1111_1111) as u8) });
+        }
+
+        block.lcr.modify(|_, w| w.dlab().rx_buffer());
+    }
+
+    pub fn hardware_init(&mut self, baud_rate: usize) {
+        let block = self.hardware();
+        let _unused = block.msr.read().bits();
+        let _unused = block.lsr.read().bits();
+        block.lcr.reset();
+        // No modem control
+        block.mcr.reset();
+        // Interrupts are only enabled while waiting
+        block.ier().reset();
+        block.fcr().reset();
+
+        // Enable DLAB and Set divisor
+        self.set_divisor(100_000_000, baud_rate);
+        // Disable DLAB and set word length 8 bits, no parity, 1 stop bit
+        block
+            .lcr
+            .modify(|_, w| w.dls().eight().pen().disabled().stop().one());
+        // Enable FIFO
+        block.fcr().write(|w| {
+            w.fifoe()
+                .set_bit()
+                .rfifor()
+                .set_bit()
+                .xfifor()
+                .set_bit()
+                .rt()
+                .two_less_than_full()
+        });
+        block.mcr.modify(|_, w| w.rts().asserted());
+    }
+
+    /// Sets the rx trigger level, `Fourteen` after `hardware_init`. Fails
+    /// if the level does not exist with 16 byte FIFOs.
+    pub fn set_fifo_trigger(&mut self, trigger: FifoTrigger) -> Result<(), SerialBuildError> {
+        let bits = trigger
+            .fcr_bits(FIFO_DEPTH)
+            .ok_or(SerialBuildError::UnsupportedTrigger {
+                trigger,
+                fifo_depth: FIFO_DEPTH,
+            })?;
+        // no reset bits, the FIFOs keep what they hold
+        self.hardware().write_fcr(FCR_FIFO_ENABLE | bits);
+        Ok(())
+    }
+
+    /// Returns false if it timed out.
+    fn wait(&mut self, timeout_us: usize) -> bool {
+        self.wait_count += 1;
+        match wait_ext_int(self.irq as usize, timeout_us) {
+            0 => true,
+            -2 => {
+                self.timeout_count += 1;
+                false
+            }
+            err => panic!("[blocking serial] wait irq {} failed: {}", self.irq, err),
+        }
+    }
+
+    fn drain(&mut self, buf: &mut [u8]) -> usize {
+        let block = self.hardware();
+        let mut len = 0;
+        while len < buf.len() && block.lsr.read().dr().is_ready() {
+            let ch = block.rbr().read().rbr().bits();
+            push_trace(SERIAL_RX | ch as usize);
+            buf[len] = ch;
+            len += 1;
+        }
+        self.rx_count += len;
+        len
+    }
+
+    /// Reads what is available, waiting up to `timeout_us` for the first
+    /// byte, 0 waits forever. Returns 0 on timeout.
+    pub fn read(&mut self, buf: &mut [u8], timeout_us: usize) -> usize {
+        loop {
+            let len = self.drain(buf);
+            if len > 0 || buf.is_empty() {
+                return len;
+            }
+            self.hardware().set_rx_interrupt(true);
+            let fired = self.wait(timeout_us);
+            self.hardware().set_rx_interrupt(false);
+            if !fired {
+                return self.drain(buf);
+            }
+        }
+    }
+
+    /// Writes all of `buf`, a FIFO at a time, waiting for the FIFO to empty
+    /// in between.
+    pub fn write(&mut self, buf: &[u8]) {
+        for chunk in buf.chunks(FIFO_DEPTH) {
+            if !self.hardware().lsr.read().thre().is_empty() {
+                self.hardware().set_tx_interrupt(true);
+                self.wait(0);
+                self.hardware().set_tx_interrupt(false);
+            }
+            let block = self.hardware();
+            for &ch in chunk {
+                push_trace(SERIAL_TX | ch as usize);
+                block.thr().write(|w| w.thr().variant(ch));
+            }
+            self.tx_count += chunk.len();
+        }
+    }
+}
+
+impl Drop for BlockingSerial {
+    fn drop(&mut self) {
+        let block = self.hardware();
+        block.ier().reset();
+        let _unused = block.msr.read().bits();
+        let _unused = block.lsr.read().bits();
+        block.mcr.modify(|_, w| w.rts().deasserted());
+        // reset Rx & Tx FIFO, disable FIFO
+        block
+            .fcr()
+            .write(|w| w.fifoe().clear_bit().rfifor().set_bit().xfifor().set_bit());
+    }
+}
@@ -0,0 +1,402 @@
+use super::regs::*;
+use super::*;
+use core::sync::atomic::Ordering::{Acquire, Release};
+use heapless::spsc::Queue;
+
+pub const DEFAULT_BAUD_RATE: usize = 115_200;
+/// An spsc queue holds one byte less than its size.
+pub const MAX_RX_CAPACITY: usize = DEFAULT_RX_BUFFER_SIZE - 1;
+pub const MAX_TX_CAPACITY: usize = DEFAULT_TX_BUFFER_SIZE - 1;
+/// The UART clock `hardware_init` divides down.
+const UART_CLOCK: usize = 100_000_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Polling,
+    Buffered,
+    Async,
+}
+
+/// Bytes in the rx FIFO that raise the rx interrupt. `One` works with
+/// either FIFO depth, the others only with their own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FifoTrigger {
+    One,
+    Four,
+    Eight,
+    Fourteen,
+    /// 64 byte FIFOs only.
+    Sixteen,
+    ThirtyTwo,
+    FiftySix,
+}
+
+impl FifoTrigger {
+    /// The same two FCR bits select other levels with 64 byte FIFOs.
+    pub(super) fn fcr_bits(self, fifo_depth: usize) -> Option<u8> {
+        let deep = fifo_depth == DEEP_FIFO_DEPTH;
+        match self {
+            FifoTrigger::One => Some(FCR_RX_TRIGGER_1),
+            FifoTrigger::Four if !deep => Some(FCR_RX_TRIGGER_4),
+            FifoTrigger::Eight if !deep => Some(FCR_RX_TRIGGER_8),
+            FifoTrigger::Fourteen if !deep => Some(FCR_RX_TRIGGER_14),
+            FifoTrigger::Sixteen if deep => Some(FCR_RX_TRIGGER_4),
+            FifoTrigger::ThirtyTwo if deep => Some(FCR_RX_TRIGGER_8),
+            FifoTrigger::FiftySix if deep => Some(FCR_RX_TRIGGER_14),
+            _ => None,
+        }
+    }
+
+    /// The level `bits` select with FIFOs `fifo_depth` deep.
+    pub(super) fn from_fcr_bits(bits: u8, fifo_depth: usize) -> Self {
+        let levels = if fifo_depth == DEEP_FIFO_DEPTH {
+            [
+                FifoTrigger::One,
+                FifoTrigger::Sixteen,
+                FifoTrigger::ThirtyTwo,
+                FifoTrigger::FiftySix,
+            ]
+        } else {
+            [
+                FifoTrigger::One,
+                FifoTrigger::Four,
+                FifoTrigger::Eight,
+                FifoTrigger::Fourteen,
+            ]
+        };
+        levels[(bits >> 6) as usize & 0b11]
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SerialBuildError {
+    Claim(ClaimError),
+    /// 0, or too fast for the UART clock.
+    InvalidBaudRate(usize),
+    /// 0, or more than the queues hold.
+    InvalidCapacity(usize),
+    /// The option means nothing in this mode, e.g. a FIFO trigger level
+    /// for `Mode::Polling`, which takes no rx interrupt. `"layout"` if the
+    /// mode can't access the port's `RegLayout`.
+    Conflict {
+        mode: Mode,
+        option: &'static str,
+    },
+    /// The port's queues still belong to a live `AsyncSerial`.
+    QueuesInUse,
+    /// Neither `FIFO_DEPTH` nor `DEEP_FIFO_DEPTH`.
+    InvalidFifoDepth(usize),
+    /// The trigger level does not exist with FIFOs this deep.
+    UnsupportedTrigger {
+        trigger: FifoTrigger,
+        fifo_depth: usize,
+    },
+    /// The coalescing delay is 0 or over `Coalescing::max_delay_us`, or
+    /// `MAX_TX_DELAY_US` for tx coalescing.
+    InvalidCoalescing {
+        delay_us: usize,
+        max_delay_us: usize,
+    },
+    /// `low_water` not below `high_water`, or `high_water` over the rx
+    /// queue capacity.
+    InvalidWatermarks {
+        high_water: usize,
+        low_water: usize,
+    },
+}
+
+impl From<ClaimError> for SerialBuildError {
+    fn from(err: ClaimError) -> Self {
+        SerialBuildError::Claim(err)
+    }
+}
+
+/// One of the three drivers, as built by `SerialBuilder`.
+pub enum AnySerial {
+    Polling(PollingSerial),
+    Buffered(BufferedSerial),
+    Async(Arc<AsyncSerial>),
+}
+
+impl AnySerial {
+    pub fn mode(&self) -> Mode {
+        match self {
+            AnySerial::Polling(_) => Mode::Polling,
+            AnySerial::Buffered(_) => Mode::Buffered,
+            AnySerial::Async(_) => Mode::Async,
+        }
+    }
+
+    pub fn into_polling(self) -> Option<PollingSerial> {
+        match self {
+            AnySerial::Polling(serial) => Some(serial),
+            _ => None,
+        }
+    }
+
+    pub fn into_buffered(self) -> Option<BufferedSerial> {
+        match self {
+            AnySerial::Buffered(serial) => Some(serial),
+            _ => None,
+        }
+    }
+
+    pub fn into_async(self) -> Option<Arc<AsyncSerial>> {
+        match self {
+            AnySerial::Async(serial) => Some(serial),
+            _ => None,
+        }
+    }
+}
+
+/// Builds and initializes a driver for a port in one go:
+///
+/// `SerialBuilder::new(port).baud(..).mode(..).build()`
+///
+/// The async driver's queues come from a pool with one pair per port, so
+/// a port can have one `AsyncSerial` built here at a time. The capacities
+/// limit how much of them the driver uses, e.g. to have RTS drop earlier.
+pub struct SerialBuilder {
+    port: usize,
+    baud_rate: usize,
+    mode: Mode,
+    rx_capacity: Option<usize>,
+    tx_capacity: Option<usize>,
+    fifo_trigger: Option<FifoTrigger>,
+    layout: Option<RegLayout>,
+    fifo_depth: Option<usize>,
+    defer_interrupts: bool,
+}
+
+impl SerialBuilder {
+    /// An async driver at `DEFAULT_BAUD_RATE` with full queues.
+    pub fn new(port: usize) -> Self {
+        SerialBuilder {
+            port,
+            baud_rate: DEFAULT_BAUD_RATE,
+            mode: Mode::Async,
+            rx_capacity: None,
+            tx_capacity: None,
+            fifo_trigger: None,
+            layout: None,
+            fifo_depth: None,
+            defer_interrupts: false,
+        }
+    }
+
+    pub fn baud(mut self, baud_rate: usize) -> Self {
+        self.baud_rate = baud_rate;
+        self
+    }
+
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Async only.
+    pub fn rx_capacity(mut self, capacity: usize) -> Self {
+        self.rx_capacity = Some(capacity);
+        self
+    }
+
+    /// Async only.
+    pub fn tx_capacity(mut self, capacity: usize) -> Self {
+        self.tx_capacity = Some(capacity);
+        self
+    }
+
+    /// Buffered and async only, `hardware_init` sets the highest level,
+    /// `Fourteen` or `FiftySix` with 64 byte FIFOs.
+    pub fn fifo_trigger(mut self, trigger: FifoTrigger) -> Self {
+        self.fifo_trigger = Some(trigger);
+        self
+    }
+
+    /// Registers laid out other than the kernel reports for the port.
+    /// Polling and async only, unless it is `RegLayout::BOARD`.
+    pub fn layout(mut self, layout: RegLayout) -> Self {
+        self.layout = Some(layout);
+        self
+    }
+
+    /// FIFO depth to use instead of the one `hardware_init` detects, for a
+    /// UART that has 64 byte FIFOs but does not report them. Polling and
+    /// async only.
+    pub fn fifo_depth(mut self, fifo_depth: usize) -> Self {
+        self.fifo_depth = Some(fifo_depth);
+        self
+    }
+
+    /// Leaves the port's interrupts off, for `AsyncSerial::enable_interrupts`
+    /// once the executor is ready. Async only.
+    pub fn defer_interrupts(mut self) -> Self {
+        self.defer_interrupts = true;
+        self
+    }
+
+    /// Claims the port and builds the driver on it. Keep the claim for as
+    /// long as the driver is used.
+    pub fn build(self) -> Result<(SerialClaim, AnySerial), SerialBuildError> {
+        self.validate()?;
+        let claim = SerialClaim::claim(self.port)?;
+        let serial = self.build_on(&claim)?;
+        Ok((claim, serial))
+    }
+
+    /// Builds the driver on a port claimed already, the port number given
+    /// to `new` is not used.
+    pub fn build_on(self, claim: &SerialClaim) -> Result<AnySerial, SerialBuildError> {
+        self.validate()?;
+        let layout = self.layout.unwrap_or(claim.layout());
+        // the claim's mapping, in the layout asked for
+        let regs = unsafe { UartMmio::with_layout(claim.base_address(), layout) };
+        // the buffered driver only knows the PAC register block
+        if self.mode == Mode::Buffered && layout != RegLayout::BOARD {
+            return Err(SerialBuildError::Conflict {
+                mode: self.mode,
+                option: "layout",
+            });
+        }
+        // kernels without the probe hand out missing ports too
+        if !regs.probe() {
+            return Err(ClaimError::Absent.into());
+        }
+        let serial = match self.mode {
+            Mode::Polling => {
+                let mut serial = PollingSerial::with_registers(regs);
+                serial.hardware_init(self.baud_rate);
+                if let Some(fifo_depth) = self.fifo_depth {
+                    serial.set_fifo_depth(fifo_depth);
+                }
+                AnySerial::Polling(serial)
+            }
+            Mode::Buffered => {
+                let mut serial = BufferedSerial::from_claim(claim);
+                serial.hardware_init(self.baud_rate);
+                AnySerial::Buffered(serial)
+            }
+            Mode::Async => AnySerial::Async(Arc::new(self.build_async(claim, regs)?)),
+        };
+        if let Some(trigger) = self.fifo_trigger {
+            if let AnySerial::Async(serial) = &serial {
+                // it keeps track of the level for `RxTriggerTuner`
+                serial.set_fifo_trigger(trigger)?;
+            } else {
+                let fifo_depth = serial.stats().fifo_depth;
+                let bits =
+                    trigger
+                        .fcr_bits(fifo_depth)
+                        .ok_or(SerialBuildError::UnsupportedTrigger {
+                            trigger,
+                            fifo_depth,
+                        })?;
+                regs.write_fcr(FCR_FIFO_ENABLE | bits);
+            }
+        }
+        Ok(serial)
+    }
+
+    fn build_async(
+        &self,
+        claim: &SerialClaim,
+        regs: UartMmio,
+    ) -> Result<AsyncSerial, SerialBuildError> {
+        let slot = claim.port();
+        if slot >= SERIAL_NUM {
+            return Err(ClaimError::InvalidPort.into());
+        }
+        if QUEUES_IN_USE[slot].swap(true, Acquire) {
+            return Err(SerialBuildError::QueuesInUse);
+        }
+        let queues = QueueSlot(slot);
+        let (rx_pro, rx_con, tx_pro, tx_con) = unsafe {
+            // the last driver may have left bytes behind
+            RX_QUEUES[slot] = RxQueue::new();
+            TX_QUEUES[slot] = TxQueue::new();
+            let (rx_pro, rx_con) = RX_QUEUES[slot].split();
+            let (tx_pro, tx_con) = TX_QUEUES[slot].split();
+            (rx_pro, rx_con, tx_pro, tx_con)
+        };
+        let mut serial = AsyncSerial::with_registers(regs, rx_pro, rx_con, tx_pro, tx_con);
+        serial.own_queues(queues);
+        serial.limit_queues(
+            self.rx_capacity.unwrap_or(MAX_RX_CAPACITY),
+            self.tx_capacity.unwrap_or(MAX_TX_CAPACITY),
+        );
+        serial.init_line(self.baud_rate);
+        if !self.defer_interrupts {
+            serial.enable_interrupts();
+        }
+        if let Some(fifo_depth) = self.fifo_depth {
+            serial.set_fifo_depth(fifo_depth);
+        }
+        Ok(serial)
+    }
+
+    fn validate(&self) -> Result<(), SerialBuildError> {
+        if self.baud_rate == 0 || UART_CLOCK / (16 * self.baud_rate) == 0 {
+            return Err(SerialBuildError::InvalidBaudRate(self.baud_rate));
+        }
+        let conflict = |option| {
+            Err(SerialBuildError::Conflict {
+                mode: self.mode,
+                option,
+            })
+        };
+        if self.mode == Mode::Polling && self.fifo_trigger.is_some() {
+            return conflict("fifo_trigger");
+        }
+        if self.mode == Mode::Buffered && self.fifo_depth.is_some() {
+            return conflict("fifo_depth");
+        }
+        match self.fifo_depth {
+            Some(depth) if depth != FIFO_DEPTH && depth != DEEP_FIFO_DEPTH => {
+                return Err(SerialBuildError::InvalidFifoDepth(depth))
+            }
+            _ => {}
+        }
+        if self.mode != Mode::Async {
+            if self.rx_capacity.is_some() {
+                return conflict("rx_capacity");
+            }
+            if self.tx_capacity.is_some() {
+                return conflict("tx_capacity");
+            }
+            if self.defer_interrupts {
+                return conflict("defer_interrupts");
+            }
+        }
+        for (capacity, max) in [
+            (self.rx_capacity, MAX_RX_CAPACITY),
+            (self.tx_capacity, MAX_TX_CAPACITY),
+        ] {
+            match capacity {
+                Some(capacity) if capacity == 0 || capacity > max => {
+                    return Err(SerialBuildError::InvalidCapacity(capacity))
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+type RxQueue = Queue<u8, DEFAULT_RX_BUFFER_SIZE>;
+type TxQueue = Queue<u8, DEFAULT_TX_BUFFER_SIZE>;
+const EMPTY_RX_QUEUE: RxQueue = RxQueue::new();
+const EMPTY_TX_QUEUE: TxQueue = TxQueue::new();
+// The user heap is too small for these.
+static mut RX_QUEUES: [RxQueue; SERIAL_NUM] = [EMPTY_RX_QUEUE; SERIAL_NUM];
+static mut TX_QUEUES: [TxQueue; SERIAL_NUM] = [EMPTY_TX_QUEUE; SERIAL_NUM];
+const NOT_IN_USE: AtomicBool = AtomicBool::new(false);
+static QUEUES_IN_USE: [AtomicBool; SERIAL_NUM] = [NOT_IN_USE; SERIAL_NUM];
+
+/// The pooled queues of one port, given back when dropped.
+pub(super) struct QueueSlot(usize);
+
+impl Drop for QueueSlot {
+    fn drop(&mut self) {
+        QUEUES_IN_USE[self.0].store(false, Release);
+    }
+}
@@ -0,0 +1,297 @@
+use super::panic_dump::panic_port_released;
+use super::regs::*;
+use super::stdio::stdio_port_released;
+use super::{
+    serial, serial_id_to_irq, AsyncUnbufferedSerial, BufferedSerial, HeapBufferedSerial,
+    PollingSerial, RegLayout, StaticBufferedSerial, UartMmio,
+};
+use crate::{
+    claim_ext_int, release_ext_int, set_ext_int_affinity, set_ext_int_priority,
+    set_ext_int_threshold, transfer_ext_int,
+};
+use alloc::vec::Vec;
+
+/// Highest PLIC priority, both boards implement 3 priority bits.
+pub const MAX_IRQ_PRIORITY: usize = 7;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClaimError {
+    /// `init_user_trap` has not been called.
+    NotInitialized,
+    /// Another claim, from this process or another one, holds the port.
+    AlreadyClaimed,
+    /// No such port, or it is reserved for the kernel console.
+    InvalidPort,
+    /// The port is in the layout but no UART answers there, see
+    /// `probe`.
+    Absent,
+    /// The kernel could not map the serial or PLIC registers.
+    MapFailed,
+    /// No such hart to pin the interrupt to.
+    InvalidHart,
+    /// The PLIC can't route this port's interrupt to the hart.
+    AffinityUnsupported,
+    /// Priority or threshold above `MAX_IRQ_PRIORITY`, or a priority of 0.
+    InvalidPriority,
+    /// Nothing was handed to this process for the port.
+    NotHeld,
+    /// No such process to hand the port to, or it has no user trap.
+    InvalidTarget,
+    /// The process claiming, or the one the port is handed to, holds as
+    /// many sources as its limit allows, see `irq::set_claim_limit`.
+    LimitReached,
+    Unknown(isize),
+}
+
+impl ClaimError {
+    fn from_code(code: isize) -> Self {
+        match code {
+            -1 | -5 => ClaimError::NotInitialized,
+            -2 | -6 => ClaimError::MapFailed,
+            -3 => ClaimError::AlreadyClaimed,
+            -4 => ClaimError::InvalidPort,
+            -7 => ClaimError::Absent,
+            -8 => ClaimError::LimitReached,
+            _ => ClaimError::Unknown(code),
+        }
+    }
+}
+
+/// Ownership of a serial port's external interrupt and MMIO registers.
+/// The claim is released when this is dropped. Panics exit the process,
+/// so on panic the kernel releases it on exit instead and resets the port.
+#[derive(Debug)]
+pub struct SerialClaim {
+    port: usize,
+    irq: u16,
+    base_address: usize,
+    layout: RegLayout,
+    affinity: Option<usize>,
+}
+
+impl SerialClaim {
+    pub fn builder(port: usize) -> ClaimBuilder {
+        ClaimBuilder {
+            port,
+            affinity: None,
+        }
+    }
+
+    pub fn claim(port: usize) -> Result<SerialClaim, ClaimError> {
+        let irq = serial_id_to_irq(port).ok_or(ClaimError::InvalidPort)?;
+        let ret = claim_ext_int(irq as usize);
+        if ret < 0 {
+            return Err(ClaimError::from_code(ret));
+        }
+        let layout = serial::port_info(port).map_or(RegLayout::BOARD, |info| info.layout());
+        Ok(SerialClaim {
+            port,
+            irq,
+            base_address: ret as usize,
+            layout,
+            affinity: None,
+        })
+    }
+
+    pub fn port(&self) -> usize {
+        self.port
+    }
+
+    pub fn irq(&self) -> u16 {
+        self.irq
+    }
+
+    pub fn base_address(&self) -> usize {
+        self.base_address
+    }
+
+    pub fn layout(&self) -> RegLayout {
+        self.layout
+    }
+
+    /// The port's registers, for what the drivers don't cover.
+    pub fn registers(&self) -> UartMmio {
+        // mapped for this process until the claim is dropped
+        unsafe { UartMmio::with_layout(self.base_address, self.layout) }
+    }
+
+    /// The ports this process holds, claimed or handed over to it.
+    pub fn list() -> Vec<usize> {
+        crate::irq::claimed()
+            .into_iter()
+            .filter_map(|irq| serial::port_info_by_irq(irq).map(|info| info.index))
+            .collect()
+    }
+
+    /// Takes over a port another process handed over with `transfer`. The
+    /// port is left running, build the driver with
+    /// `AsyncSerial::from_claimed_running`. Any affinity set by the sender
+    /// still applies, but is not reported by `affinity`.
+    pub fn inherit(port: usize) -> Result<SerialClaim, ClaimError> {
+        let info = serial::enumerate()
+            .into_iter()
+            .find(|info| info.index == port)
+            .ok_or(ClaimError::InvalidPort)?;
+        if info.virt_base == 0 {
+            return Err(ClaimError::NotHeld);
+        }
+        Ok(SerialClaim {
+            port,
+            irq: info.irq(),
+            base_address: info.virt_base,
+            layout: info.layout(),
+            affinity: None,
+        })
+    }
+
+    /// Hands the port to process `pid` as it is, without the reset done on
+    /// drop. Drivers built on it must be forgotten rather than dropped, as
+    /// they reset the port too, but for an `AsyncSerial` registered for
+    /// the IRQ: the kernel has it revoked, see `AsyncSerial::revoke`. On
+    /// error the claim is given back. Stdio and panic output sent to the
+    /// port go back to the kernel console either way.
+    pub fn transfer(self, pid: usize) -> Result<(), (SerialClaim, ClaimError)> {
+        panic_port_released(self.base_address);
+        if let Some(serial) = stdio_port_released(self.base_address) {
+            core::mem::forget(serial);
+        }
+        match transfer_ext_int(self.irq as usize, pid) {
+            0 => {
+                core::mem::forget(self);
+                Ok(())
+            }
+            -2 => Err((self, ClaimError::NotHeld)),
+            -4 => Err((self, ClaimError::InvalidTarget)),
+            code => Err((self, ClaimError::from_code(code))),
+        }
+    }
+
+    /// The hart the interrupt is pinned to, if any.
+    pub fn affinity(&self) -> Option<usize> {
+        self.affinity
+    }
+
+    /// Pins the interrupt to `hart`, or unpins it with `None`. On error the
+    /// routing is left as it was.
+    pub fn set_affinity(&mut self, hart: Option<usize>) -> Result<(), ClaimError> {
+        match set_ext_int_affinity(self.irq as usize, hart.unwrap_or(usize::MAX)) {
+            0 => {
+                self.affinity = hart;
+                Ok(())
+            }
+            -3 => Err(ClaimError::InvalidHart),
+            -4 => Err(ClaimError::AffinityUnsupported),
+            code => Err(ClaimError::from_code(code)),
+        }
+    }
+
+    /// Sets the PLIC priority of the port, 1 to `MAX_IRQ_PRIORITY`. It goes
+    /// back to the default when the claim is dropped.
+    pub fn set_priority(&self, priority: usize) -> Result<(), ClaimError> {
+        match set_ext_int_priority(self.irq as usize, priority) {
+            0 => Ok(()),
+            -3 => Err(ClaimError::InvalidPriority),
+            code => Err(ClaimError::Unknown(code)),
+        }
+    }
+
+    /// Holds back interrupts at or below `threshold` while this process
+    /// runs. This is per process, not per port.
+    pub fn set_threshold(&self, threshold: usize) -> Result<(), ClaimError> {
+        match set_ext_int_threshold(threshold) {
+            0 => Ok(()),
+            -3 => Err(ClaimError::InvalidPriority),
+            code => Err(ClaimError::from_code(code)),
+        }
+    }
+
+    /// Best-effort reset before handing the port back, so the kernel does
+    /// not take over a port with interrupts or RTS still on. The kernel
+    /// resets it again either way.
+    fn quiesce(&self) {
+        let block = self.registers();
+        block.write_ier(0);
+        block.write_mcr(0);
+        let _unused = block.read_msr();
+        let _unused = block.read_lsr();
+        // reset Rx & Tx FIFO, disable FIFO
+        block.reset_fifos(0, FCR_RX_RESET | FCR_TX_RESET);
+    }
+}
+
+impl Drop for SerialClaim {
+    fn drop(&mut self) {
+        drop(stdio_port_released(self.base_address));
+        panic_port_released(self.base_address);
+        self.quiesce();
+        let ret = release_ext_int(self.irq as usize);
+        if ret != 0 {
+            serial_warn!(
+                self.base_address,
+                "[serial claim] release irq {} failed: {}",
+                self.irq,
+                ret
+            );
+        }
+    }
+}
+
+pub struct ClaimBuilder {
+    port: usize,
+    affinity: Option<usize>,
+}
+
+impl ClaimBuilder {
+    /// Take the interrupt on `hart`. If the PLIC can't route it there the
+    /// claim still succeeds, unpinned, check `SerialClaim::affinity`.
+    pub fn affinity(mut self, hart: usize) -> Self {
+        self.affinity = Some(hart);
+        self
+    }
+
+    pub fn claim(self) -> Result<SerialClaim, ClaimError> {
+        let mut claim = SerialClaim::claim(self.port)?;
+        if self.affinity.is_none() {
+            return Ok(claim);
+        }
+        match claim.set_affinity(self.affinity) {
+            Ok(()) | Err(ClaimError::AffinityUnsupported) => Ok(claim),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Drivers that can only be built on a claimed port.
+pub trait FromClaim {
+    fn from_claim(claim: &SerialClaim) -> Self;
+}
+
+impl FromClaim for BufferedSerial {
+    fn from_claim(claim: &SerialClaim) -> Self {
+        BufferedSerial::new(claim.base_address())
+    }
+}
+
+impl<const RX: usize, const TX: usize> FromClaim for StaticBufferedSerial<RX, TX> {
+    fn from_claim(claim: &SerialClaim) -> Self {
+        StaticBufferedSerial::with_registers(claim.registers())
+    }
+}
+
+impl FromClaim for HeapBufferedSerial {
+    fn from_claim(claim: &SerialClaim) -> Self {
+        HeapBufferedSerial::with_registers(claim.registers())
+    }
+}
+
+impl FromClaim for PollingSerial {
+    fn from_claim(claim: &SerialClaim) -> Self {
+        PollingSerial::with_registers(claim.registers())
+    }
+}
+
+impl FromClaim for AsyncUnbufferedSerial {
+    fn from_claim(claim: &SerialClaim) -> Self {
+        unsafe { AsyncUnbufferedSerial::new(claim.base_address()) }
+    }
+}
@@ -0,0 +1,63 @@
+use super::{AsyncSerial, FifoTrigger, UartRegisters};
+use alloc::sync::{Arc, Weak};
+use alloc::task::Wake;
+
+/// Rx interrupt coalescing for an `AsyncSerial`, see `set_coalescing`.
+/// After an rx interrupt the rx interrupts stay off for `delay_us` and a
+/// timer drains the FIFO instead, so a steady stream costs one timer
+/// interrupt per `delay_us` rather than one rx interrupt per `trigger`
+/// bytes.
+///
+/// At 8N1 a byte takes `10_000_000 / baud` us on the line, so an empty
+/// FIFO of `fifo_depth` bytes fills in `fifo_depth * 10_000_000 / baud`
+/// us: 213 us for 64 bytes at 3 Mbaud, 1388 us for 16 bytes at 115200.
+/// `delay_us` must stay below that or bytes are overrun. A byte waits at
+/// most `delay_us` once the rx interrupts are off, and at most four
+/// character times, the character timeout, while they are on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Coalescing {
+    /// Rx trigger level while coalescing.
+    pub trigger: FifoTrigger,
+    pub delay_us: usize,
+    /// Bound on `delay_us`, the latency the application accepts.
+    pub max_latency_us: usize,
+}
+
+impl Coalescing {
+    /// The longest `delay_us` that overruns nothing at `baud_rate` with
+    /// FIFOs `fifo_depth` deep, and that keeps to `max_latency_us`.
+    pub fn max_delay_us(&self, baud_rate: usize, fifo_depth: usize) -> usize {
+        match (fifo_depth * 10_000_000).checked_div(baud_rate) {
+            Some(fill_us) => fill_us.saturating_sub(1).min(self.max_latency_us),
+            // baud rate not known
+            None => 0,
+        }
+    }
+}
+
+/// Longest tx coalescing delay `AsyncSerial::set_tx_coalescing` takes,
+/// the most a byte waits before THREI goes on.
+pub const MAX_TX_DELAY_US: usize = 2_000;
+
+/// Wakes the driver when the coalescing delay is over, from the timer
+/// interrupt.
+pub(super) struct CoalesceTimer<R: UartRegisters>(pub(super) Weak<AsyncSerial<R>>);
+
+impl<R: UartRegisters> Wake for CoalesceTimer<R> {
+    fn wake(self: Arc<Self>) {
+        if let Some(serial) = self.0.upgrade() {
+            serial.coalesce_expired();
+        }
+    }
+}
+
+/// Wakes the driver when the tx coalescing delay is over.
+pub(super) struct TxCoalesceTimer<R: UartRegisters>(pub(super) Weak<AsyncSerial<R>>);
+
+impl<R: UartRegisters> Wake for TxCoalesceTimer<R> {
+    fn wake(self: Arc<Self>) {
+        if let Some(serial) = self.0.upgrade() {
+            serial.tx_coalesce_expired();
+        }
+    }
+}
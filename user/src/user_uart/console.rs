@@ -0,0 +1,169 @@
+use super::*;
+use crate::trace::ASYNC_READ_WAKE;
+use crate::{close_console_ring, open_console_ring, wait_console_ring};
+use core::sync::atomic::{
+    AtomicU32,
+    Ordering::{Acquire, Release},
+};
+
+pub const CONSOLE_RING_SIZE: usize = 2048;
+
+/// Same layout as the kernel's `ConsoleRing`. The kernel only moves `head`,
+/// this side only moves `tail`.
+#[repr(C)]
+struct ConsoleRing {
+    head: AtomicU32,
+    tail: AtomicU32,
+    dropped: AtomicU32,
+    doorbell: AtomicU32,
+    data: [u8; CONSOLE_RING_SIZE],
+}
+
+/// Reads the kernel console without claiming it. The kernel copies its
+/// input into a ring shared with this process, reads drain it with no
+/// syscall. An empty ring is waited on either with the doorbell, a user
+/// external interrupt with the console IRQ that must be passed to
+/// `interrupt_handler`, or with the blocking `read_blocking`.
+///
+/// While open, console input no longer reaches the kernel stdin.
+pub struct ConsoleAsync {
+    ring: &'static ConsoleRing,
+    irq: u16,
+    read_waker: WakerList,
+    pub rx_count: AtomicUsize,
+    pub doorbell_count: AtomicUsize,
+}
+
+impl ConsoleAsync {
+    /// Fails with the kernel's error code, e.g. if this process already has
+    /// the ring open.
+    pub fn open() -> Result<Self, isize> {
+        let ret = open_console_ring();
+        if ret < 0 {
+            return Err(ret);
+        }
+        Ok(ConsoleAsync {
+            ring: unsafe { &*(ret as usize as *const ConsoleRing) },
+            irq: serial::port_info(0).map_or(SERIAL_IRQ_BASE, |port| port.irq()),
+            read_waker: WakerList::new(),
+            rx_count: AtomicUsize::new(0),
+            doorbell_count: AtomicUsize::new(0),
+        })
+    }
+
+    /// The IRQ the doorbell comes with.
+    pub fn irq(&self) -> u16 {
+        self.irq
+    }
+
+    /// Bytes the kernel dropped because the ring was full.
+    pub fn dropped(&self) -> usize {
+        self.ring.dropped.load(Relaxed) as usize
+    }
+
+    /// Moves what is in the ring into `buf` and returns how many bytes.
+    pub fn try_read(&self, buf: &mut [u8]) -> usize {
+        let head = self.ring.head.load(Acquire);
+        let mut tail = self.ring.tail.load(Relaxed);
+        let mut len = 0;
+        while len < buf.len() && tail != head {
+            buf[len] = self.ring.data[tail as usize % CONSOLE_RING_SIZE];
+            tail = tail.wrapping_add(1);
+            len += 1;
+        }
+        self.ring.tail.store(tail, Release);
+        self.rx_count.fetch_add(len, Relaxed);
+        len
+    }
+
+    /// Reads what is available, waiting in the kernel up to `timeout_us`
+    /// for the first byte, 0 waits forever. Returns 0 on timeout.
+    pub fn read_blocking(&self, buf: &mut [u8], timeout_us: usize) -> usize {
+        loop {
+            let len = self.try_read(buf);
+            if len > 0 || buf.is_empty() || wait_console_ring(timeout_us) != 0 {
+                return len;
+            }
+        }
+    }
+
+    /// Waits for at least one byte and returns how many were read.
+    pub async fn read(self: Arc<Self>, buf: &mut [u8]) -> usize {
+        ConsoleReadFuture {
+            buf,
+            console: self,
+            waiting: false,
+        }
+        .await
+    }
+
+    /// Split the console input into lines of at most `N` bytes.
+    pub fn lines<const N: usize>(self: Arc<Self>) -> Lines<Arc<Self>, N> {
+        Lines::new(self)
+    }
+
+    /// Call on the doorbell, a user external interrupt with `irq`.
+    pub fn interrupt_handler(&self) {
+        self.doorbell_count.fetch_add(1, Relaxed);
+        self.read_waker.wake(ASYNC_READ_WAKE);
+    }
+
+    pub fn remove_read(&self) {
+        self.read_waker.clear();
+    }
+}
+
+impl AsyncRead for ConsoleAsync {
+    fn poll_read(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<usize> {
+        if buf.is_empty() {
+            return Poll::Ready(0);
+        }
+        self.read_waker.register(cx.waker(), None);
+        let len = self.try_read(buf);
+        if len > 0 {
+            return Poll::Ready(len);
+        }
+        // arm the doorbell, then look again in case input came in between
+        self.ring.doorbell.store(1, Release);
+        let len = self.try_read(buf);
+        if len > 0 {
+            self.ring.doorbell.store(0, Release);
+            Poll::Ready(len)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for ConsoleAsync {
+    fn drop(&mut self) {
+        close_console_ring();
+    }
+}
+
+struct ConsoleReadFuture<'a> {
+    buf: &'a mut [u8],
+    console: Arc<ConsoleAsync>,
+    /// Returned `Pending` last time, so the read waker is ours.
+    waiting: bool,
+}
+
+impl Future for ConsoleReadFuture<'_> {
+    type Output = usize;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let res = this.console.poll_read(cx, this.buf);
+        this.waiting = res.is_pending();
+        res
+    }
+}
+
+impl Drop for ConsoleReadFuture<'_> {
+    fn drop(&mut self) {
+        // cancelled while waiting, don't leave the waker behind
+        if self.waiting {
+            self.console.remove_read();
+        }
+    }
+}
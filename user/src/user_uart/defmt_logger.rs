@@ -0,0 +1,88 @@
+use super::regs::*;
+use super::*;
+use crate::trace::DEFMT_BYTE;
+use crate::trap::hart_id;
+use crate::uintr::{critical_section, disable, restore};
+use core::sync::atomic::Ordering::{Acquire, Release};
+
+/// Port the defmt frames go out on, see `set_defmt_port`.
+static PORT: Mutex<Option<PollingSerial>> = Mutex::new(None);
+/// Bytes the tx FIFO still takes before THRE has to be waited for.
+static FIFO_ROOM: AtomicUsize = AtomicUsize::new(0);
+/// Hart between `acquire` and `release`, other harts wait for it.
+static OWNER: AtomicUsize = AtomicUsize::new(NO_OWNER);
+const NO_OWNER: usize = usize::MAX;
+static mut UIE: bool = false;
+static mut ENCODER: defmt::Encoder = defmt::Encoder::new();
+
+defmt::timestamp!("{=u64:us}", crate::timer::now_us() as u64);
+
+/// Sends the defmt frames of this process out on `serial`, which must be
+/// initialized. Until this is called, and with `None`, they go to the
+/// trace ring instead, one `DEFMT_BYTE` event per byte, if tracing is on.
+///
+/// The bytes are written straight to THR as the FIFO empties. No flow
+/// control, so `defmt-print` on the other end sees them as they are.
+pub fn set_defmt_port(serial: Option<PollingSerial>) -> Option<PollingSerial> {
+    critical_section(|| {
+        FIFO_ROOM.store(0, Relaxed);
+        core::mem::replace(&mut *PORT.lock(), serial)
+    })
+}
+
+/// Only called between `acquire` and `release`, with user interrupts
+/// masked.
+fn write_bytes(bytes: &[u8]) {
+    let port = PORT.lock();
+    let regs = match port.as_ref() {
+        Some(port) => port.hardware(),
+        None => {
+            for &byte in bytes {
+                push_trace(DEFMT_BYTE | byte as usize);
+            }
+            return;
+        }
+    };
+    for &byte in bytes {
+        if FIFO_ROOM.load(Relaxed) == 0 {
+            while regs.read_lsr() & LSR_THRE == 0 {}
+            FIFO_ROOM.store(FIFO_DEPTH, Relaxed);
+        }
+        regs.write_thr(byte);
+        FIFO_ROOM.fetch_sub(1, Relaxed);
+    }
+}
+
+#[defmt::global_logger]
+struct DefmtLogger;
+
+unsafe impl defmt::Logger for DefmtLogger {
+    fn acquire() {
+        let uie = disable();
+        let hart = hart_id();
+        while let Err(owner) = OWNER.compare_exchange(NO_OWNER, hart, Acquire, Relaxed) {
+            if owner == hart {
+                // only reachable by logging from a `Format` impl
+                panic!("[defmt] logger taken twice");
+            }
+            core::hint::spin_loop();
+        }
+        unsafe {
+            UIE = uie;
+            ENCODER.start_frame(write_bytes);
+        }
+    }
+
+    unsafe fn flush() {}
+
+    unsafe fn release() {
+        ENCODER.end_frame(write_bytes);
+        let uie = UIE;
+        OWNER.store(NO_OWNER, Release);
+        restore(uie);
+    }
+
+    unsafe fn write(bytes: &[u8]) {
+        ENCODER.write(bytes, write_bytes);
+    }
+}
@@ -0,0 +1,167 @@
+// Driver diagnostics. With the `defmt` feature they are defmt frames,
+// see `set_defmt_port`. Else with the `log` feature they go through the
+// `log` facade, `serial_warn!` at warn and `serial_trace!` at trace level.
+// Otherwise warnings go to `intr_println!` and traces are compiled out. The format
+// strings stick to what both defmt and `core::fmt` take.
+//
+// `$base` is the base address of the port a diagnostic is about. If the
+// log goes to that same port it is only counted, see `log_suppressed`:
+// the sites run in its interrupt handler, and sending there could recurse
+// into the handler or spin on a lock it holds.
+
+macro_rules! serial_warn {
+    ($base: expr, $fmt: literal $(, $($arg: tt)+)?) => {
+        #[cfg(feature = "defmt")]
+        {
+            let _ = $base;
+            defmt::warn!($fmt $(, $($arg)+)?);
+        }
+        #[cfg(all(feature = "log", not(feature = "defmt")))]
+        {
+            $crate::user_uart::diag::log_about(
+                $base,
+                log::Level::Warn,
+                format_args!($fmt $(, $($arg)+)?),
+            );
+        }
+        #[cfg(not(any(feature = "log", feature = "defmt")))]
+        {
+            let _ = $base;
+            intr_println!($fmt $(, $($arg)+)?);
+        }
+    };
+}
+
+macro_rules! serial_trace {
+    ($base: expr, $fmt: literal $(, $($arg: tt)+)?) => {
+        #[cfg(feature = "defmt")]
+        {
+            let _ = $base;
+            defmt::trace!($fmt $(, $($arg)+)?);
+        }
+        #[cfg(all(feature = "log", not(feature = "defmt")))]
+        {
+            $crate::user_uart::diag::log_about(
+                $base,
+                log::Level::Trace,
+                format_args!($fmt $(, $($arg)+)?),
+            );
+        }
+    };
+}
+
+#[cfg(feature = "log")]
+pub use logger::*;
+
+#[cfg(feature = "log")]
+mod logger {
+    use super::super::AsyncSerial;
+    use alloc::sync::Arc;
+    use core::fmt::{self, Write};
+    use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+    use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+    use spin::Once;
+
+    /// Longest record `init_serial_logger` sends, longer ones are cut.
+    pub const LOG_RECORD_SIZE: usize = 128;
+
+    struct SerialLogger {
+        serial: Once<Arc<AsyncSerial>>,
+        dropped: AtomicUsize,
+        suppressed: AtomicUsize,
+    }
+
+    static LOGGER: SerialLogger = SerialLogger {
+        serial: Once::new(),
+        dropped: AtomicUsize::new(0),
+        suppressed: AtomicUsize::new(0),
+    };
+
+    /// A record being formatted. What does not fit is cut, leaving room for
+    /// the line end.
+    struct RecordBuf {
+        buf: [u8; LOG_RECORD_SIZE],
+        len: usize,
+    }
+
+    impl Write for RecordBuf {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let room = LOG_RECORD_SIZE - 2 - self.len;
+            let len = s.len().min(room);
+            self.buf[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+            self.len += len;
+            Ok(())
+        }
+    }
+
+    impl Log for SerialLogger {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.level() <= log::max_level()
+        }
+
+        fn log(&self, record: &Record) {
+            let serial = match self.serial.get() {
+                Some(serial) if self.enabled(record.metadata()) => serial,
+                _ => return,
+            };
+            let mut line = RecordBuf {
+                buf: [0; LOG_RECORD_SIZE],
+                len: 0,
+            };
+            let _ = write!(
+                line,
+                "[{} {}] {}",
+                record.level(),
+                record.target(),
+                record.args()
+            );
+            line.buf[line.len..line.len + 2].copy_from_slice(b"\r\n");
+            line.len += 2;
+            // a whole record or nothing, so the log stays readable
+            if serial.tx_space() < line.len
+                || serial.write_available(&line.buf[..line.len]) < line.len
+            {
+                self.dropped.fetch_add(1, Relaxed);
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    /// Sends log records up to `level` to `serial`, an initialized driver.
+    /// Logging never waits: a record that does not fit in the tx queue is
+    /// dropped and counted by `log_dropped`. Fails if a logger is set
+    /// already.
+    pub fn init_serial_logger(
+        serial: Arc<AsyncSerial>,
+        level: LevelFilter,
+    ) -> Result<(), SetLoggerError> {
+        LOGGER.serial.call_once(|| serial);
+        log::set_logger(&LOGGER)?;
+        log::set_max_level(level);
+        Ok(())
+    }
+
+    /// Records dropped because the tx queue was full.
+    pub fn log_dropped() -> usize {
+        LOGGER.dropped.load(Relaxed)
+    }
+
+    /// Driver diagnostics about the log's own port, counted instead of
+    /// logged.
+    pub fn log_suppressed() -> usize {
+        LOGGER.suppressed.load(Relaxed)
+    }
+
+    pub(crate) fn log_about(base_address: usize, level: Level, args: fmt::Arguments) {
+        if level > log::max_level() {
+            return;
+        }
+        match LOGGER.serial.get() {
+            Some(serial) if serial.base_address() == base_address => {
+                LOGGER.suppressed.fetch_add(1, Relaxed);
+            }
+            _ => log::log!(target: "user_uart", level, "{}", args),
+        }
+    }
+}
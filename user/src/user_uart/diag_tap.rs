@@ -0,0 +1,252 @@
+use super::{AsyncSerial, UartMmio, UartRegisters};
+use crate::executor::task_stats;
+use crate::timer::timeout;
+use crate::trace::{last_trace_events, TraceEvent};
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::fmt::Write;
+use core::future::{poll_fn, Future};
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use core::task::{Poll, Waker};
+use heapless::{Deque, Vec};
+use spin::Mutex;
+
+/// Longest escape sequence `DiagnosticTap` takes.
+pub const MAX_ESCAPE_LEN: usize = 8;
+/// What `DiagnosticTap` looks for unless told otherwise. Control bytes
+/// around the text, so neither typing nor most binary data makes it by
+/// chance.
+pub const DEFAULT_ESCAPE: &[u8] = &[0x01, b'D', b'B', b'G', 0xfe, 0x04];
+/// How long the start of an escape sequence is held back waiting for the
+/// rest. After that the bytes go to the application after all.
+pub const ESCAPE_HOLD_US: usize = 50_000;
+/// Trace events in a dump.
+const DUMP_TRACE_EVENTS: usize = 16;
+
+/// Sits between an `AsyncSerial` and the application reading it. An
+/// escape sequence in the received bytes is taken out of them and has
+/// the task of `dumper` write the port's stats, the executor's tasks and
+/// the last trace events back over the port, for a board with nothing
+/// but its console. A sequence split over several reads is found too,
+/// its start is held back until the rest comes or `ESCAPE_HOLD_US` passes.
+pub struct DiagnosticTap<R: UartRegisters = UartMmio> {
+    serial: Arc<AsyncSerial<R>>,
+    /// Empty if the tap is off.
+    escape: Vec<u8, MAX_ESCAPE_LEN>,
+    /// Bytes of `escape` seen so far, held back from the application.
+    matched: usize,
+    /// Bytes let out that did not fit in the caller's buffer.
+    carry: Deque<u8, { 2 * MAX_ESCAPE_LEN }>,
+    trigger: Arc<DumpTrigger>,
+}
+
+struct DumpTrigger {
+    requested: AtomicUsize,
+    served: AtomicUsize,
+    waker: Mutex<Option<Waker>>,
+}
+
+impl<R: UartRegisters> DiagnosticTap<R> {
+    /// Listens for `DEFAULT_ESCAPE`.
+    pub fn new(serial: Arc<AsyncSerial<R>>) -> Self {
+        DiagnosticTap {
+            serial,
+            escape: Vec::from_slice(DEFAULT_ESCAPE).unwrap(),
+            matched: 0,
+            carry: Deque::new(),
+            trigger: Arc::new(DumpTrigger {
+                requested: AtomicUsize::new(0),
+                served: AtomicUsize::new(0),
+                waker: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// Listens for `escape` instead, or for nothing if it is `None`, which
+    /// passes everything through. Longer sequences are less likely to turn
+    /// up in binary data. Fails if `escape` is empty or longer than
+    /// `MAX_ESCAPE_LEN`.
+    pub fn set_escape(&mut self, escape: Option<&[u8]>) -> Result<(), ()> {
+        let escape = match escape {
+            Some(escape) if escape.is_empty() => return Err(()),
+            Some(escape) => Vec::from_slice(escape)?,
+            None => Vec::new(),
+        };
+        self.release_held();
+        self.escape = escape;
+        Ok(())
+    }
+
+    pub fn serial(&self) -> &Arc<AsyncSerial<R>> {
+        &self.serial
+    }
+
+    /// Escape sequences seen.
+    pub fn dumps_requested(&self) -> usize {
+        self.trigger.requested.load(Relaxed)
+    }
+
+    /// Reads at least one byte of application data into `buf`, escape
+    /// sequences taken out, and returns how many.
+    pub async fn read(&mut self, buf: &mut [u8]) -> usize {
+        if buf.is_empty() {
+            return 0;
+        }
+        // the chunks borrow it while the matcher runs
+        let serial = self.serial.clone();
+        loop {
+            let mut len = 0;
+            while len < buf.len() {
+                match self.carry.pop_front() {
+                    Some(byte) => buf[len] = byte,
+                    None => break,
+                }
+                len += 1;
+            }
+            if len > 0 {
+                return len;
+            }
+            let chunk = if self.matched > 0 {
+                match timeout(ESCAPE_HOLD_US, serial.fill_buf()).await {
+                    Ok(chunk) => chunk,
+                    Err(_) => {
+                        // no escape after all
+                        self.release_held();
+                        continue;
+                    }
+                }
+            } else {
+                serial.fill_buf().await
+            };
+            let mut taken = 0;
+            for &byte in chunk.iter() {
+                if !self.carry.is_empty() {
+                    break;
+                }
+                taken += 1;
+                self.feed(byte, buf, &mut len);
+            }
+            chunk.consume(taken);
+            if len > 0 {
+                return len;
+            }
+        }
+    }
+
+    /// Runs `byte` through the escape matcher, letting out what turns out
+    /// not to be part of an escape.
+    fn feed(&mut self, byte: u8, buf: &mut [u8], len: &mut usize) {
+        if self.escape.is_empty() {
+            self.emit(byte, buf, len);
+            return;
+        }
+        loop {
+            if byte == self.escape[self.matched] {
+                self.matched += 1;
+                if self.matched == self.escape.len() {
+                    self.matched = 0;
+                    self.trigger.requested.fetch_add(1, Relaxed);
+                    if let Some(waker) = self.trigger.waker.lock().take() {
+                        waker.wake();
+                    }
+                }
+                return;
+            }
+            if self.matched == 0 {
+                self.emit(byte, buf, len);
+                return;
+            }
+            // the first held byte starts no escape, the ones after it may
+            let held = core::mem::replace(&mut self.matched, 0);
+            self.emit(self.escape[0], buf, len);
+            for i in 1..held {
+                self.feed(self.escape[i], buf, len);
+            }
+        }
+    }
+
+    fn emit(&mut self, byte: u8, buf: &mut [u8], len: &mut usize) {
+        if *len < buf.len() {
+            buf[*len] = byte;
+            *len += 1;
+        } else {
+            // at most the held bytes and one more, which fit
+            let _ = self.carry.push_back(byte);
+        }
+    }
+
+    /// Lets out the bytes held as the start of an escape.
+    fn release_held(&mut self) {
+        let held = core::mem::replace(&mut self.matched, 0);
+        for &byte in &self.escape[..held] {
+            let _ = self.carry.push_back(byte);
+        }
+    }
+
+    /// The task writing a dump for each escape sequence seen. Spawn it at
+    /// `Priority::Low`, so dumps only go out when the application is idle;
+    /// without it escapes are only counted. Escapes that come while a
+    /// dump is written make one more.
+    pub fn dumper(&self) -> impl Future<Output = ()> + Send + 'static
+    where
+        R: Send + Sync + 'static,
+    {
+        let serial = self.serial.clone();
+        let trigger = self.trigger.clone();
+        async move {
+            loop {
+                poll_fn(|cx| {
+                    let served = trigger.served.load(Relaxed);
+                    if trigger.requested.load(Relaxed) != served {
+                        return Poll::Ready(());
+                    }
+                    *trigger.waker.lock() = Some(cx.waker().clone());
+                    // an escape may have come before the waker was in
+                    if trigger.requested.load(Relaxed) != served {
+                        Poll::Ready(())
+                    } else {
+                        Poll::Pending
+                    }
+                })
+                .await;
+                trigger
+                    .served
+                    .store(trigger.requested.load(Relaxed), Relaxed);
+                let dump = format_dump(&serial);
+                if serial.clone().write(dump.as_bytes()).await.is_err() {
+                    // closed, no more dumps
+                    return;
+                }
+            }
+        }
+    }
+}
+
+fn format_dump<R: UartRegisters>(serial: &AsyncSerial<R>) -> String {
+    let mut out = String::new();
+    // writing to a String does not fail
+    let _ = write_dump(serial, &mut out);
+    out
+}
+
+fn write_dump<R: UartRegisters>(serial: &AsyncSerial<R>, out: &mut String) -> core::fmt::Result {
+    writeln!(out, "\r\n[diag] port {:#x}", serial.base_address())?;
+    writeln!(out, "[diag] {:?}", serial.stats())?;
+    for task in task_stats() {
+        writeln!(
+            out,
+            "[diag] task {} {:?}: {} polls, {} wakes, {} cycles",
+            task.id, task.priority, task.polls, task.wakes, task.poll_cycles
+        )?;
+    }
+    let mut events = [TraceEvent::default(); DUMP_TRACE_EVENTS];
+    let len = last_trace_events(&mut events);
+    for event in &events[..len] {
+        writeln!(
+            out,
+            "[diag] trace {:#012x} at {}",
+            event.event_id, event.cycle
+        )?;
+    }
+    writeln!(out, "[diag] end")
+}
@@ -0,0 +1,332 @@
+use super::regs::*;
+use super::serial::MAX_SERIAL_PORTS;
+use super::*;
+use crate::irq::IrqHandler;
+use crate::trace::{push_trace, SERIAL_OVERRUN};
+use crate::uintr::critical_section;
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+/// What the polling, buffered and async drivers have in common, so code
+/// that only moves bytes can take any of them, e.g. an `AnySerial` picked
+/// at runtime. Nothing here waits. The futures stay on `AsyncSerial`.
+///
+/// Object safe: monitoring code can hold a `&dyn SerialDriver` and only
+/// call `mode` and `stats`.
+pub trait SerialDriver {
+    fn mode(&self) -> Mode;
+
+    /// Sets up the UART, see the driver's `hardware_init`.
+    fn init(&mut self, baud_rate: usize);
+
+    /// Queues or sends what fits of `data`, returns how much did.
+    fn write_nonblocking(&mut self, data: &[u8]) -> usize;
+
+    /// Moves the bytes already received into `buf`, returns how many.
+    fn read_nonblocking(&mut self, buf: &mut [u8]) -> usize;
+
+    /// Pushes queued bytes toward the wire. Returns true once everything
+    /// written has left the transmitter.
+    fn flush_nonblocking(&mut self) -> bool;
+
+    /// Counters the driver does not keep stay 0.
+    fn stats(&self) -> SerialStats;
+
+    /// Starts the counters of `stats` over, e.g. after a warm-up.
+    fn reset_stats(&mut self);
+
+    /// Services the port by hand, for drivers whose interrupt is not
+    /// enabled. Does nothing for the polling driver.
+    fn service(&mut self);
+}
+
+impl<R: UartRegisters> SerialDriver for PollingSerial<R> {
+    fn mode(&self) -> Mode {
+        Mode::Polling
+    }
+
+    fn init(&mut self, baud_rate: usize) {
+        self.hardware_init(baud_rate);
+    }
+
+    fn write_nonblocking(&mut self, data: &[u8]) -> usize {
+        data.iter()
+            .take_while(|&&ch| self.try_write(ch).is_ok())
+            .count()
+    }
+
+    fn read_nonblocking(&mut self, buf: &mut [u8]) -> usize {
+        let mut len = 0;
+        while len < buf.len() {
+            match self.try_read() {
+                Ok(ch) => buf[len] = ch,
+                Err(_) => break,
+            }
+            len += 1;
+        }
+        len
+    }
+
+    fn flush_nonblocking(&mut self) -> bool {
+        self.hardware().read_lsr() & LSR_TEMT != 0
+    }
+
+    fn stats(&self) -> SerialStats {
+        SerialStats {
+            rx_count: self.rx_count,
+            tx_count: self.tx_count,
+            fifo_depth: self.fifo_depth(),
+            ..Default::default()
+        }
+    }
+
+    fn reset_stats(&mut self) {
+        self.rx_count = 0;
+        self.tx_count = 0;
+    }
+
+    fn service(&mut self) {
+        self.interrupt_handler();
+    }
+}
+
+impl SerialDriver for BufferedSerial {
+    fn mode(&self) -> Mode {
+        Mode::Buffered
+    }
+
+    fn init(&mut self, baud_rate: usize) {
+        self.hardware_init(baud_rate);
+    }
+
+    fn write_nonblocking(&mut self, data: &[u8]) -> usize {
+        data.iter()
+            .take_while(|&&ch| self.try_write(ch).is_ok())
+            .count()
+    }
+
+    fn read_nonblocking(&mut self, buf: &mut [u8]) -> usize {
+        let mut len = 0;
+        while len < buf.len() {
+            match self.try_read() {
+                Ok(ch) => buf[len] = ch,
+                Err(_) => break,
+            }
+            len += 1;
+        }
+        len
+    }
+
+    fn flush_nonblocking(&mut self) -> bool {
+        self.interrupt_handler();
+        self.try_flush().is_ok()
+    }
+
+    fn stats(&self) -> SerialStats {
+        SerialStats {
+            rx_count: self.rx_count,
+            tx_count: self.tx_count,
+            intr_count: self.intr_count,
+            // character timeouts included, it does not tell them apart
+            rx_intr_count: self.rx_intr_count,
+            tx_intr_count: self.tx_intr_count,
+            // refills 16 bytes at a time, however deep the FIFOs are
+            fifo_depth: FIFO_DEPTH,
+            ..Default::default()
+        }
+    }
+
+    fn reset_stats(&mut self) {
+        self.rx_count = 0;
+        self.tx_count = 0;
+        self.intr_count = 0;
+        self.rx_intr_count = 0;
+        self.tx_intr_count = 0;
+    }
+
+    fn service(&mut self) {
+        self.interrupt_handler();
+    }
+}
+
+impl<RX, TX, R> SerialDriver for RingBufferedSerial<RX, TX, R>
+where
+    RX: AsRef<[u8]> + AsMut<[u8]>,
+    TX: AsRef<[u8]> + AsMut<[u8]>,
+    R: UartRegisters,
+{
+    fn mode(&self) -> Mode {
+        Mode::Buffered
+    }
+
+    fn init(&mut self, baud_rate: usize) {
+        self.hardware_init(baud_rate);
+    }
+
+    fn write_nonblocking(&mut self, data: &[u8]) -> usize {
+        data.iter()
+            .take_while(|&&ch| self.try_write(ch).is_ok())
+            .count()
+    }
+
+    fn read_nonblocking(&mut self, buf: &mut [u8]) -> usize {
+        self.read_available(buf)
+    }
+
+    fn flush_nonblocking(&mut self) -> bool {
+        self.interrupt_handler();
+        self.try_flush().is_ok()
+    }
+
+    fn stats(&self) -> SerialStats {
+        SerialStats {
+            rx_count: self.rx_count,
+            tx_count: self.tx_count,
+            intr_count: self.intr_count,
+            rx_intr_count: self.rx_intr_count,
+            tx_intr_count: self.tx_intr_count,
+            fifo_depth: self.fifo_depth(),
+            ..Default::default()
+        }
+    }
+
+    fn reset_stats(&mut self) {
+        self.rx_count = 0;
+        self.tx_count = 0;
+        self.intr_count = 0;
+        self.rx_intr_count = 0;
+        self.tx_intr_count = 0;
+    }
+
+    fn service(&mut self) {
+        self.interrupt_handler();
+    }
+}
+
+impl<R: UartRegisters> SerialDriver for Arc<AsyncSerial<R>> {
+    fn mode(&self) -> Mode {
+        Mode::Async
+    }
+
+    fn init(&mut self, baud_rate: usize) {
+        self.hardware_init(baud_rate);
+    }
+
+    fn write_nonblocking(&mut self, data: &[u8]) -> usize {
+        self.write_available(data)
+    }
+
+    fn read_nonblocking(&mut self, buf: &mut [u8]) -> usize {
+        self.read_available(buf)
+    }
+
+    fn flush_nonblocking(&mut self) -> bool {
+        // sends at most a FIFO and checks once
+        self.flush(0)
+    }
+
+    fn stats(&self) -> SerialStats {
+        AsyncSerial::stats(self)
+    }
+
+    fn reset_stats(&mut self) {
+        AsyncSerial::reset_stats(self);
+    }
+
+    fn service(&mut self) {
+        AsyncSerial::pump(self);
+    }
+}
+
+impl AnySerial {
+    fn driver(&self) -> &dyn SerialDriver {
+        match self {
+            AnySerial::Polling(serial) => serial,
+            AnySerial::Buffered(serial) => serial,
+            AnySerial::Async(serial) => serial,
+        }
+    }
+
+    fn driver_mut(&mut self) -> &mut dyn SerialDriver {
+        match self {
+            AnySerial::Polling(serial) => serial,
+            AnySerial::Buffered(serial) => serial,
+            AnySerial::Async(serial) => serial,
+        }
+    }
+}
+
+impl SerialDriver for AnySerial {
+    fn mode(&self) -> Mode {
+        self.driver().mode()
+    }
+
+    fn init(&mut self, baud_rate: usize) {
+        self.driver_mut().init(baud_rate);
+    }
+
+    fn write_nonblocking(&mut self, data: &[u8]) -> usize {
+        self.driver_mut().write_nonblocking(data)
+    }
+
+    fn read_nonblocking(&mut self, buf: &mut [u8]) -> usize {
+        self.driver_mut().read_nonblocking(buf)
+    }
+
+    fn flush_nonblocking(&mut self) -> bool {
+        self.driver_mut().flush_nonblocking()
+    }
+
+    fn stats(&self) -> SerialStats {
+        self.driver().stats()
+    }
+
+    fn reset_stats(&mut self) {
+        self.driver_mut().reset_stats();
+    }
+
+    fn service(&mut self) {
+        self.driver_mut().service();
+    }
+}
+
+impl<R: UartRegisters> IrqHandler for AsyncSerial<R> {
+    fn handle(&self, _irq: u16) {
+        self.interrupt_handler();
+    }
+
+    fn revoke(&self, _irq: u16) {
+        AsyncSerial::revoke(self);
+    }
+}
+
+const NO_OVERRUNS: AtomicUsize = AtomicUsize::new(0);
+static BUFFERED_OVERRUNS: [AtomicUsize; MAX_SERIAL_PORTS] = [NO_OVERRUNS; MAX_SERIAL_PORTS];
+
+/// Receiver overruns of port `index` served as a registered
+/// `Mutex<BufferedSerial>`. Only the ones LSR still shows when the
+/// interrupt is taken count, its rx loop reads LSR and drops OE.
+pub fn buffered_overruns(index: usize) -> usize {
+    BUFFERED_OVERRUNS
+        .get(index)
+        .map_or(0, |count| count.load(Relaxed))
+}
+
+/// `BufferedSerial` is serviced through `&mut`, so it is registered behind
+/// a lock.
+impl IrqHandler for Mutex<BufferedSerial> {
+    fn handle(&self, irq: u16) {
+        // an rx interrupt held off until the FIFO overran, looked at before
+        // its handler clears OE
+        // registered for a port this process claimed
+        let regs = unsafe { get_registers_from_irq(irq) };
+        if regs.read_lsr() & LSR_OE != 0 {
+            let index = serial::port_info_by_irq(irq).map_or(0, |port| port.index);
+            let count = BUFFERED_OVERRUNS
+                .get(index)
+                .map_or(0, |count| count.fetch_add(1, Relaxed) + 1);
+            push_trace(SERIAL_OVERRUN | count.min(0xfff));
+            serial_warn!(regs.base_address(), "[uart] lsr.OE!");
+        }
+        critical_section(|| self.lock().interrupt_handler());
+    }
+}
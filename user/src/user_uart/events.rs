@@ -0,0 +1,110 @@
+use crate::uintr::critical_section;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use core::task::{Context, Poll, Waker};
+use heapless::mpmc::Q64;
+use spin::Mutex;
+
+/// Line and modem status changes. CTS is left out, the driver uses its
+/// edges for flow control and they come with every RTS pulse.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SerialEventKind {
+    Break,
+    FramingError,
+    ParityError,
+    Overrun,
+    DsrChanged(bool),
+    CarrierChanged(bool),
+    Ring,
+    /// The kernel took the port back, see `AsyncSerial::revoke`.
+    Revoked,
+}
+
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SerialEvent {
+    pub port: usize,
+    pub kind: SerialEventKind,
+    /// `timer::now_us()` when the interrupt handler saw it.
+    pub timestamp: usize,
+}
+
+/// A queue of up to 64 serial events, filled by the interrupt handlers of
+/// the ports attached to it and drained by one subscriber task. When full,
+/// the oldest event is dropped and counted.
+pub struct SerialEventBus {
+    queue: Q64<SerialEvent>,
+    dropped: AtomicUsize,
+    /// Only locked with user interrupts masked.
+    waker: Mutex<Option<Waker>>,
+}
+
+impl SerialEventBus {
+    pub fn new() -> Self {
+        SerialEventBus {
+            queue: Q64::new(),
+            dropped: AtomicUsize::new(0),
+            waker: Mutex::new(None),
+        }
+    }
+
+    /// Called from interrupt context.
+    pub(crate) fn push(&self, event: SerialEvent) {
+        let mut event = event;
+        while let Err(rejected) = self.queue.enqueue(event) {
+            if self.queue.dequeue().is_some() {
+                self.dropped.fetch_add(1, Relaxed);
+            }
+            event = rejected;
+        }
+        if let Some(waker) = critical_section(|| self.waker.lock().clone()) {
+            waker.wake();
+        }
+    }
+
+    /// Events lost to overflow so far.
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Relaxed)
+    }
+
+    pub fn try_next(&self) -> Option<SerialEvent> {
+        self.queue.dequeue()
+    }
+
+    pub fn next_event(&self) -> NextEvent<'_> {
+        NextEvent { bus: self }
+    }
+}
+
+impl Default for SerialEventBus {
+    fn default() -> Self {
+        SerialEventBus::new()
+    }
+}
+
+pub struct NextEvent<'a> {
+    bus: &'a SerialEventBus,
+}
+
+impl Future for NextEvent<'_> {
+    type Output = SerialEvent;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let bus = self.bus;
+        critical_section(|| {
+            let mut slot = bus.waker.lock();
+            match slot.as_ref() {
+                Some(waker) if waker.will_wake(cx.waker()) => {}
+                _ => {
+                    slot.replace(cx.waker().clone());
+                }
+            }
+        });
+        match bus.try_next() {
+            Some(event) => Poll::Ready(event),
+            None => Poll::Pending,
+        }
+    }
+}
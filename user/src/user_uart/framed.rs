@@ -0,0 +1,413 @@
+use super::regs::UartRegisters;
+use super::*;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::sync::atomic::AtomicUsize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameError {
+    /// Over the maximum frame size, or too long for the buffer it was to
+    /// go in. A received one is dropped up to the next delimiter.
+    TooLong,
+    /// The bytes up to a delimiter are no valid encoding, they are dropped.
+    Corrupt,
+    /// The frame decoded but failed the check set with `set_check`.
+    Check,
+    /// The port was closed, see `AsyncSerial::close`.
+    Closed,
+}
+
+/// Frame counters of a port, see `SerialStats::frames`. Kept by the
+/// `FramedSerial` or `Resync` running on it.
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FrameStats {
+    pub frames_ok: usize,
+    /// Frames that arrived whole but failed their check, usually a CRC.
+    pub crc_failures: usize,
+    /// Times the receiver lost track and looked for the next frame.
+    pub resync_events: usize,
+    /// Bytes dropped while it looked.
+    pub bytes_skipped: usize,
+}
+
+pub(super) struct FrameCounters {
+    frames_ok: AtomicUsize,
+    crc_failures: AtomicUsize,
+    resync_events: AtomicUsize,
+    bytes_skipped: AtomicUsize,
+}
+
+impl FrameCounters {
+    pub(super) const fn new() -> Self {
+        FrameCounters {
+            frames_ok: AtomicUsize::new(0),
+            crc_failures: AtomicUsize::new(0),
+            resync_events: AtomicUsize::new(0),
+            bytes_skipped: AtomicUsize::new(0),
+        }
+    }
+
+    pub(super) fn stats(&self) -> FrameStats {
+        FrameStats {
+            frames_ok: self.frames_ok.load(Relaxed),
+            crc_failures: self.crc_failures.load(Relaxed),
+            resync_events: self.resync_events.load(Relaxed),
+            bytes_skipped: self.bytes_skipped.load(Relaxed),
+        }
+    }
+
+    pub(super) fn reset(&self) {
+        for counter in [
+            &self.frames_ok,
+            &self.crc_failures,
+            &self.resync_events,
+            &self.bytes_skipped,
+        ] {
+            counter.store(0, Relaxed);
+        }
+    }
+
+    /// A frame was lost, `skipped` bytes with it.
+    fn resync(&self, skipped: usize) {
+        self.resync_events.fetch_add(1, Relaxed);
+        self.bytes_skipped.fetch_add(skipped, Relaxed);
+    }
+}
+
+/// A byte stuffing scheme. Each frame is sent encoded, then `DELIMITER`,
+/// which the encoding never contains, so a receiver that lost track finds
+/// the next frame at the next delimiter.
+pub trait FrameCodec {
+    const DELIMITER: u8;
+    /// Longest encoding of a `len` byte frame, without the delimiter.
+    fn max_encoded_len(len: usize) -> usize;
+    /// Encodes `frame` into `out`, which holds `max_encoded_len`. Returns
+    /// the encoded length.
+    fn encode(frame: &[u8], out: &mut [u8]) -> usize;
+    /// Decodes in place what came before a delimiter. Returns the frame
+    /// length, the frame is at the start of `buf`.
+    fn decode(buf: &mut [u8]) -> Result<usize, FrameError>;
+}
+
+/// Consistent Overhead Byte Stuffing: delimited by 0, at most one byte in
+/// 254 of overhead. An empty frame is sent as `[1, 0]`.
+pub struct Cobs;
+
+impl FrameCodec for Cobs {
+    const DELIMITER: u8 = 0;
+
+    fn max_encoded_len(len: usize) -> usize {
+        len + len / 254 + 1
+    }
+
+    fn encode(frame: &[u8], out: &mut [u8]) -> usize {
+        // each block starts with the offset to the next zero
+        let mut code_pos = 0;
+        let mut code = 1u8;
+        let mut pos = 1;
+        for &byte in frame {
+            if byte != 0 {
+                out[pos] = byte;
+                pos += 1;
+                code += 1;
+            }
+            if byte == 0 || code == 0xff {
+                out[code_pos] = code;
+                code_pos = pos;
+                pos += 1;
+                code = 1;
+            }
+        }
+        out[code_pos] = code;
+        pos
+    }
+
+    fn decode(buf: &mut [u8]) -> Result<usize, FrameError> {
+        let (mut read, mut write) = (0, 0);
+        while read < buf.len() {
+            let code = buf[read] as usize;
+            if code == 0 || read + code > buf.len() {
+                return Err(FrameError::Corrupt);
+            }
+            buf.copy_within(read + 1..read + code, write);
+            write += code - 1;
+            read += code;
+            // a full block is not followed by a zero
+            if code != 0xff && read < buf.len() {
+                buf[write] = 0;
+                write += 1;
+            }
+        }
+        Ok(write)
+    }
+}
+
+/// SLIP, RFC 1055: delimited by `0xc0`, which is sent as `0xdb 0xdc`
+/// inside a frame, and `0xdb` as `0xdb 0xdd`.
+#[cfg(feature = "slip")]
+pub struct Slip;
+
+#[cfg(feature = "slip")]
+impl Slip {
+    const ESC: u8 = 0xdb;
+    const ESC_END: u8 = 0xdc;
+    const ESC_ESC: u8 = 0xdd;
+}
+
+#[cfg(feature = "slip")]
+impl FrameCodec for Slip {
+    const DELIMITER: u8 = 0xc0;
+
+    fn max_encoded_len(len: usize) -> usize {
+        2 * len
+    }
+
+    fn encode(frame: &[u8], out: &mut [u8]) -> usize {
+        let mut pos = 0;
+        for &byte in frame {
+            let escaped = match byte {
+                Self::DELIMITER => Self::ESC_END,
+                Self::ESC => Self::ESC_ESC,
+                _ => {
+                    out[pos] = byte;
+                    pos += 1;
+                    continue;
+                }
+            };
+            out[pos] = Self::ESC;
+            out[pos + 1] = escaped;
+            pos += 2;
+        }
+        pos
+    }
+
+    fn decode(buf: &mut [u8]) -> Result<usize, FrameError> {
+        let (mut read, mut write) = (0, 0);
+        while read < buf.len() {
+            buf[write] = match buf[read] {
+                Self::ESC => {
+                    read += 1;
+                    match buf.get(read) {
+                        Some(&Self::ESC_END) => Self::DELIMITER,
+                        Some(&Self::ESC_ESC) => Self::ESC,
+                        _ => return Err(FrameError::Corrupt),
+                    }
+                }
+                byte => byte,
+            };
+            read += 1;
+            write += 1;
+        }
+        Ok(write)
+    }
+}
+
+/// Frames of up to `max_frame` bytes over an `AsyncSerial`, COBS encoded
+/// unless made `with_codec`.
+///
+/// Like `Lines`, the frame being received lives in here, not in the
+/// future of `recv_frame`, so dropping that future mid-frame loses
+/// nothing. The driver is read with `fill_buf`, so nothing past a
+/// delimiter is taken.
+pub struct FramedSerial<R: UartRegisters = UartMmio, C: FrameCodec = Cobs> {
+    serial: Arc<AsyncSerial<R>>,
+    max_frame: usize,
+    /// Encoded bytes of the frame being received, no delimiter among them.
+    rx: Vec<u8>,
+    len: usize,
+    /// Dropping the rest of a frame that was too long.
+    discarding: bool,
+    tx: Vec<u8>,
+    check: Option<fn(&[u8]) -> bool>,
+    codec: PhantomData<C>,
+}
+
+impl<R: UartRegisters> FramedSerial<R, Cobs> {
+    pub fn new(serial: Arc<AsyncSerial<R>>, max_frame: usize) -> Self {
+        Self::with_codec(serial, max_frame)
+    }
+}
+
+impl<R: UartRegisters, C: FrameCodec> FramedSerial<R, C> {
+    pub fn with_codec(serial: Arc<AsyncSerial<R>>, max_frame: usize) -> Self {
+        // the delimiter too
+        let encoded_len = C::max_encoded_len(max_frame) + 1;
+        FramedSerial {
+            serial,
+            max_frame,
+            rx: vec![0; encoded_len],
+            len: 0,
+            discarding: false,
+            tx: vec![0; encoded_len],
+            check: None,
+            codec: PhantomData,
+        }
+    }
+
+    pub fn max_frame(&self) -> usize {
+        self.max_frame
+    }
+
+    pub fn serial(&self) -> &Arc<AsyncSerial<R>> {
+        &self.serial
+    }
+
+    /// Has every decoded frame go through `check`, a CRC check say, and
+    /// those failing it reported as `FrameError::Check`.
+    pub fn set_check(&mut self, check: fn(&[u8]) -> bool) {
+        self.check = Some(check);
+    }
+
+    /// The frame counters of the port.
+    pub fn stats(&self) -> FrameStats {
+        self.serial.frames.stats()
+    }
+
+    /// Encodes `frame` and waits until it is queued, delimiter and all.
+    pub async fn send_frame(&mut self, frame: &[u8]) -> Result<(), FrameError> {
+        if frame.len() > self.max_frame {
+            return Err(FrameError::TooLong);
+        }
+        let len = C::encode(frame, &mut self.tx);
+        self.tx[len] = C::DELIMITER;
+        self.serial
+            .clone()
+            .write(&self.tx[..len + 1])
+            .await
+            .map_err(|_| FrameError::Closed)
+    }
+
+    /// Waits for the next frame and copies it into `buf`, returning its
+    /// length. A frame that fails is dropped and reported, the next call
+    /// goes on from the delimiter after it. Empty encodings, two
+    /// delimiters in a row, are skipped.
+    pub async fn recv_frame(&mut self, buf: &mut [u8]) -> Result<usize, FrameError> {
+        loop {
+            let chunk = self.serial.fill_buf().await;
+            if chunk.is_empty() {
+                return Err(FrameError::Closed);
+            }
+            let frames = &self.serial.frames;
+            let delimiter = chunk.iter().position(|&byte| byte == C::DELIMITER);
+            let (bytes, taken) = match delimiter {
+                Some(pos) => (&chunk[..pos], pos + 1),
+                None => (&chunk[..], chunk.len()),
+            };
+            if self.discarding {
+                frames.bytes_skipped.fetch_add(taken, Relaxed);
+                self.discarding = delimiter.is_none();
+                chunk.consume(taken);
+                continue;
+            }
+            if self.len + bytes.len() > self.rx.len() {
+                frames.resync(self.len + taken);
+                self.len = 0;
+                self.discarding = delimiter.is_none();
+                chunk.consume(taken);
+                return Err(FrameError::TooLong);
+            }
+            self.rx[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+            self.len += bytes.len();
+            chunk.consume(taken);
+            if delimiter.is_some() {
+                let end = core::mem::replace(&mut self.len, 0);
+                if end > 0 {
+                    return self.decode(end, buf);
+                }
+            }
+        }
+    }
+
+    /// Decodes the `end` bytes before a delimiter into `buf`, counting
+    /// them as skipped if that fails.
+    fn decode(&mut self, end: usize, buf: &mut [u8]) -> Result<usize, FrameError> {
+        let frames = &self.serial.frames;
+        let len = match C::decode(&mut self.rx[..end]) {
+            Ok(len) if len > self.max_frame || len > buf.len() => Err(FrameError::TooLong),
+            Ok(len) if !self.check.map_or(true, |check| check(&self.rx[..len])) => {
+                frames.crc_failures.fetch_add(1, Relaxed);
+                Err(FrameError::Check)
+            }
+            result => result,
+        }
+        .map_err(|err| {
+            frames.resync(end + 1);
+            err
+        })?;
+        frames.frames_ok.fetch_add(1, Relaxed);
+        buf[..len].copy_from_slice(&self.rx[..len]);
+        Ok(len)
+    }
+}
+
+/// How `Resync` tells frames apart.
+#[derive(Clone, Copy)]
+pub struct ResyncConfig {
+    /// Ends every frame, though it may turn up inside one too.
+    pub delimiter: u8,
+    /// Whether the bytes before a delimiter are a whole frame, by its CRC
+    /// say. Runs that fail it end at a delimiter that was part of a frame.
+    pub check: fn(&[u8]) -> bool,
+    /// Longer runs without a delimiter are dropped unchecked.
+    pub max_frame: usize,
+}
+
+/// Finds the frame boundaries again after corruption, for framing of
+/// one's own. `FramedSerial` does this by itself.
+pub struct Resync;
+
+impl Resync {
+    /// Drops what `serial` receives until a delimiter closes a frame that
+    /// passes `cfg.check`, and returns the bytes dropped before that frame.
+    /// What comes before the first delimiter is taken for the rest of the
+    /// bad frame and not checked. The frame found is dropped too, it only
+    /// proves the boundary, so the next read starts on the frame after it.
+    ///
+    /// Reads with `fill_buf`, so nothing past that delimiter is taken.
+    pub async fn recover<R: UartRegisters>(
+        serial: &Arc<AsyncSerial<R>>,
+        cfg: &ResyncConfig,
+    ) -> usize {
+        let frames = &serial.frames;
+        frames.resync(0);
+        let mut frame = Vec::with_capacity(cfg.max_frame);
+        let (mut skipped, mut first, mut over) = (0, true, false);
+        loop {
+            let chunk = serial.fill_buf().await;
+            if chunk.is_empty() {
+                // closed
+                return skipped;
+            }
+            let mut taken = 0;
+            for &byte in chunk.iter() {
+                taken += 1;
+                if byte != cfg.delimiter {
+                    if frame.len() < cfg.max_frame {
+                        frame.push(byte);
+                    } else {
+                        over = true;
+                        skipped += 1;
+                        frames.bytes_skipped.fetch_add(1, Relaxed);
+                    }
+                    continue;
+                }
+                if !first && !over && !frame.is_empty() {
+                    if (cfg.check)(&frame) {
+                        frames.frames_ok.fetch_add(1, Relaxed);
+                        chunk.consume(taken);
+                        return skipped;
+                    }
+                    frames.crc_failures.fetch_add(1, Relaxed);
+                }
+                skipped += frame.len() + 1;
+                frames.bytes_skipped.fetch_add(frame.len() + 1, Relaxed);
+                frame.clear();
+                first = false;
+                over = false;
+            }
+            chunk.consume(taken);
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use super::AsyncRead;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use futures::Stream;
+use heapless::{String, Vec};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineError {
+    /// No newline within `N` bytes. The bytes are dropped up to and
+    /// including the next newline.
+    TooLong,
+    /// The line is not valid UTF-8, it is dropped.
+    InvalidUtf8,
+}
+
+/// Splits a byte source into lines ended by `\n` or `\r\n`, the terminator
+/// is not part of the item.
+///
+/// The partial line lives in the adapter, not in the future returned by
+/// `next_line`, so dropping that future mid-line loses nothing. Dropping the
+/// adapter does lose it, use `into_remainder` to get it back.
+pub struct Lines<R, const N: usize> {
+    reader: R,
+    buf: [u8; N],
+    /// The partial line is `buf[start..len]`. Lines are taken from the
+    /// front, so it only moves to the start of `buf` when it reaches the
+    /// end.
+    start: usize,
+    len: usize,
+    /// `buf[start..scanned]` holds no newline.
+    scanned: usize,
+    /// Dropping the rest of a line that was too long.
+    discarding: bool,
+}
+
+impl<R: AsyncRead, const N: usize> Lines<R, N> {
+    pub fn new(reader: R) -> Self {
+        Lines {
+            reader,
+            buf: [0; N],
+            start: 0,
+            len: 0,
+            scanned: 0,
+            discarding: false,
+        }
+    }
+
+    pub fn next_line(&mut self) -> NextLine<'_, R, N> {
+        NextLine { lines: self }
+    }
+
+    pub fn poll_next_line(&mut self, cx: &mut Context<'_>) -> Poll<Result<String<N>, LineError>> {
+        loop {
+            if let Some(pos) = self.buf[self.scanned..self.len]
+                .iter()
+                .position(|&ch| ch == b'\n')
+            {
+                let end = self.scanned + pos;
+                let line = if self.discarding {
+                    self.discarding = false;
+                    None
+                } else {
+                    Some(Self::to_string(&self.buf[self.start..end]))
+                };
+                self.consume(end + 1);
+                match line {
+                    Some(line) => return Poll::Ready(line),
+                    None => continue,
+                }
+            }
+            self.scanned = self.len;
+            if self.len - self.start == N {
+                self.consume(self.len);
+                if !self.discarding {
+                    self.discarding = true;
+                    return Poll::Ready(Err(LineError::TooLong));
+                }
+            } else if self.len == N {
+                self.buf.copy_within(self.start..self.len, 0);
+                self.len -= self.start;
+                self.scanned = self.len;
+                self.start = 0;
+            }
+            match self.reader.poll_read(cx, &mut self.buf[self.len..]) {
+                Poll::Ready(len) => self.len += len,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+
+    /// The reader and the bytes of the unfinished line.
+    pub fn into_remainder(self) -> (R, Vec<u8, N>) {
+        let rest = Vec::from_slice(&self.buf[self.start..self.len]).unwrap();
+        (self.reader, rest)
+    }
+
+    fn to_string(line: &[u8]) -> Result<String<N>, LineError> {
+        let line = match line.split_last() {
+            Some((b'\r', line)) => line,
+            _ => line,
+        };
+        let line = core::str::from_utf8(line).map_err(|_| LineError::InvalidUtf8)?;
+        Ok(String::from(line))
+    }
+
+    /// Takes the bytes up to `end`.
+    fn consume(&mut self, end: usize) {
+        if end == self.len {
+            self.start = 0;
+            self.len = 0;
+        } else {
+            self.start = end;
+        }
+        self.scanned = self.start;
+    }
+}
+
+impl<R: AsyncRead + Unpin, const N: usize> Stream for Lines<R, N> {
+    type Item = Result<String<N>, LineError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_next_line(cx).map(Some)
+    }
+}
+
+pub struct NextLine<'a, R, const N: usize> {
+    lines: &'a mut Lines<R, N>,
+}
+
+impl<R: AsyncRead, const N: usize> Future for NextLine<'_, R, N> {
+    type Output = Result<String<N>, LineError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.lines.poll_next_line(cx)
+    }
+}
+
+/// Reads into `buf` until `delim` (kept) or until `buf` is full, resolving
+/// to the number of bytes read. Bytes are taken one at a time, so nothing
+/// after the delimiter is consumed.
+pub struct ReadUntil<'a, R> {
+    reader: R,
+    delim: u8,
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a, R: AsyncRead> ReadUntil<'a, R> {
+    pub fn new(reader: R, delim: u8, buf: &'a mut [u8]) -> Self {
+        ReadUntil {
+            reader,
+            delim,
+            buf,
+            len: 0,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> Future for ReadUntil<'_, R> {
+    type Output = usize;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        while this.len < this.buf.len() {
+            let len = this.len;
+            match this.reader.poll_read(cx, &mut this.buf[len..len + 1]) {
+                Poll::Ready(_) => {
+                    this.len += 1;
+                    if this.buf[len] == this.delim {
+                        break;
+                    }
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(this.len)
+    }
+}
@@ -0,0 +1,362 @@
+use super::regs::{UartRegisters, LCR_DLAB};
+use core::arch::asm;
+use core::mem::align_of;
+use core::ops::Deref;
+#[cfg(feature = "board_lrv")]
+use lrv_pac::uart;
+#[cfg(feature = "board_qemu")]
+use qemu_pac::uart;
+
+/// Orders every memory and device access before it against every one
+/// after it. Also a compiler barrier.
+#[inline]
+pub fn io_fence() {
+    unsafe {
+        asm!("fence iorw, iorw");
+    }
+}
+
+/// Width of every register access.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessWidth {
+    Byte,
+    Word,
+}
+
+/// Where the 16550 registers sit: register `n` at `n << reg_shift` from
+/// the base, each accessed as `width`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RegLayout {
+    pub reg_shift: u8,
+    pub width: AccessWidth,
+}
+
+impl RegLayout {
+    /// The layout of the PAC register block.
+    #[cfg(feature = "board_qemu")]
+    pub const BOARD: RegLayout = RegLayout {
+        reg_shift: 0,
+        width: AccessWidth::Byte,
+    };
+    /// The layout of the PAC register block.
+    #[cfg(feature = "board_lrv")]
+    pub const BOARD: RegLayout = RegLayout {
+        reg_shift: 2,
+        width: AccessWidth::Word,
+    };
+
+    /// Bytes per access.
+    pub fn reg_width(&self) -> usize {
+        match self.width {
+            AccessWidth::Byte => 1,
+            AccessWidth::Word => 4,
+        }
+    }
+
+    /// As the kernel reports it: `reg_width` in bytes, 0 if unknown.
+    pub fn from_raw(reg_shift: usize, reg_width: usize) -> Option<RegLayout> {
+        let width = match reg_width {
+            0 => return Some(RegLayout::BOARD),
+            1 => AccessWidth::Byte,
+            4 => AccessWidth::Word,
+            _ => return None,
+        };
+        Some(RegLayout {
+            reg_shift: reg_shift as u8,
+            width,
+        })
+    }
+}
+
+// register indices, for layouts other than the PAC's
+const RBR_THR_DLL: usize = 0;
+const IER_DLH: usize = 1;
+const IIR_FCR: usize = 2;
+const LCR: usize = 3;
+const MCR: usize = 4;
+const LSR: usize = 5;
+const MSR: usize = 6;
+const SCR: usize = 7;
+
+/// The registers of a mapped UART. The only place a base address is turned
+/// into a reference to them, and the `UartRegisters` the drivers use on
+/// target.
+///
+/// With `RegLayout::BOARD` accesses go through the PAC register block, and
+/// it dereferences to that. Any other layout is accessed through raw
+/// pointers, and dereferencing panics.
+///
+/// Send and Sync as it keeps an address, not a pointer: a process has its
+/// ports mapped at the same address on every hart, and each access is one
+/// volatile load or store, so any hart may make it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UartMmio {
+    base_address: usize,
+    layout: RegLayout,
+}
+
+impl UartMmio {
+    /// Panics if `base_address` is null or not aligned for the registers.
+    /// `SerialClaim::registers` is the checked way.
+    ///
+    /// # Safety
+    ///
+    /// `base_address` must be the registers of a port this process claimed,
+    /// or memory laid out like them. Nothing checks it. The kernel maps a
+    /// port only for the process that claimed it, so any other port faults
+    /// on the first access.
+    pub unsafe fn new(base_address: usize) -> Self {
+        Self::with_layout(base_address, RegLayout::BOARD)
+    }
+
+    /// Same panics as `new`.
+    ///
+    /// # Safety
+    ///
+    /// As for `new`.
+    pub unsafe fn with_layout(base_address: usize, layout: RegLayout) -> Self {
+        assert!(base_address != 0, "null UART base address");
+        let align = if layout == RegLayout::BOARD {
+            align_of::<uart::RegisterBlock>()
+        } else {
+            layout.reg_width()
+        };
+        assert!(
+            base_address % align == 0,
+            "misaligned UART base address {:#x}",
+            base_address
+        );
+        UartMmio {
+            base_address,
+            layout,
+        }
+    }
+
+    pub fn base_address(&self) -> usize {
+        self.base_address
+    }
+
+    pub fn layout(&self) -> RegLayout {
+        self.layout
+    }
+
+    #[inline]
+    fn is_board(&self) -> bool {
+        self.layout == RegLayout::BOARD
+    }
+
+    #[inline]
+    fn raw_read(&self, index: usize) -> u8 {
+        let addr = self.base_address + (index << self.layout.reg_shift);
+        unsafe {
+            match self.layout.width {
+                AccessWidth::Byte => (addr as *const u8).read_volatile(),
+                AccessWidth::Word => (addr as *const u32).read_volatile() as u8,
+            }
+        }
+    }
+
+    #[inline]
+    fn raw_write(&self, index: usize, value: u8) {
+        let addr = self.base_address + (index << self.layout.reg_shift);
+        unsafe {
+            match self.layout.width {
+                AccessWidth::Byte => (addr as *mut u8).write_volatile(value),
+                AccessWidth::Word => (addr as *mut u32).write_volatile(value as u32),
+            }
+        }
+    }
+
+    /// Whether a UART answers here: its scratch register keeps two
+    /// patterns written to it. Some LRV bitstreams leave instances out,
+    /// and writes to those go nowhere. The old value is put back.
+    pub fn probe(&self) -> bool {
+        let saved = self.read_scr();
+        let present = [0x55, 0xaa].iter().all(|&pattern| {
+            self.write_scr(pattern);
+            self.read_scr() == pattern
+        });
+        self.write_scr(saved);
+        present
+    }
+
+    fn read_scr(&self) -> u8 {
+        if self.is_board() {
+            self.sch.read().bits() as _
+        } else {
+            self.raw_read(SCR)
+        }
+    }
+
+    fn write_scr(&self, scr: u8) {
+        if self.is_board() {
+            self.sch.write(|w| unsafe { w.bits(scr as _) });
+        } else {
+            self.raw_write(SCR, scr);
+        }
+    }
+}
+
+/// `UartMmio::probe` on a claimed port in the board layout. The kernel
+/// probes every port at boot as well, see `SerialPortInfo::is_present`.
+///
+/// # Safety
+///
+/// As for `UartMmio::new`.
+pub unsafe fn probe(base_address: usize) -> bool {
+    UartMmio::new(base_address).probe()
+}
+
+impl Deref for UartMmio {
+    type Target = uart::RegisterBlock;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        assert!(
+            self.is_board(),
+            "no PAC register block for {:?}",
+            self.layout
+        );
+        unsafe { &*(self.base_address as *const _) }
+    }
+}
+
+impl UartRegisters for UartMmio {
+    fn base_address(&self) -> usize {
+        self.base_address
+    }
+
+    #[inline]
+    fn read_iir(&self) -> u8 {
+        if self.is_board() {
+            self.iir().read().bits() as _
+        } else {
+            self.raw_read(IIR_FCR)
+        }
+    }
+
+    #[inline]
+    fn read_lsr(&self) -> u8 {
+        if self.is_board() {
+            self.lsr.read().bits() as _
+        } else {
+            self.raw_read(LSR)
+        }
+    }
+
+    #[inline]
+    fn read_rbr(&self) -> u8 {
+        if self.is_board() {
+            self.rbr().read().rbr().bits()
+        } else {
+            self.raw_read(RBR_THR_DLL)
+        }
+    }
+
+    #[inline]
+    fn write_thr(&self, ch: u8) {
+        if self.is_board() {
+            self.thr().write(|w| w.thr().variant(ch));
+        } else {
+            self.raw_write(RBR_THR_DLL, ch);
+        }
+    }
+
+    #[inline]
+    fn read_msr(&self) -> u8 {
+        if self.is_board() {
+            self.msr.read().bits() as _
+        } else {
+            self.raw_read(MSR)
+        }
+    }
+
+    #[inline]
+    fn read_ier(&self) -> u8 {
+        if self.is_board() {
+            self.ier().read().bits() as _
+        } else {
+            self.raw_read(IER_DLH)
+        }
+    }
+
+    #[inline]
+    fn write_ier(&self, ier: u8) {
+        if self.is_board() {
+            self.ier().write(|w| unsafe { w.bits(ier as _) });
+        } else {
+            self.raw_write(IER_DLH, ier);
+        }
+    }
+
+    #[inline]
+    fn read_lcr(&self) -> u8 {
+        if self.is_board() {
+            self.lcr.read().bits() as _
+        } else {
+            self.raw_read(LCR)
+        }
+    }
+
+    #[inline]
+    fn write_lcr(&self, lcr: u8) {
+        if self.is_board() {
+            self.lcr.write(|w| unsafe { w.bits(lcr as _) });
+        } else {
+            self.raw_write(LCR, lcr);
+        }
+    }
+
+    #[inline]
+    fn read_mcr(&self) -> u8 {
+        if self.is_board() {
+            self.mcr.read().bits() as _
+        } else {
+            self.raw_read(MCR)
+        }
+    }
+
+    #[inline]
+    fn write_mcr(&self, mcr: u8) {
+        if self.is_board() {
+            self.mcr.write(|w| unsafe { w.bits(mcr as _) });
+        } else {
+            self.raw_write(MCR, mcr);
+        }
+    }
+
+    #[inline]
+    fn write_fcr(&self, fcr: u8) {
+        if self.is_board() {
+            self.fcr().write(|w| unsafe { w.bits(fcr as _) });
+        } else {
+            self.raw_write(IIR_FCR, fcr);
+        }
+    }
+
+    fn write_divisor(&self, divisor: u16) {
+        let lcr = self.read_lcr();
+        self.write_lcr(lcr | LCR_DLAB);
+        if self.is_board() {
+            self.dll().write(|w| unsafe { w.bits(divisor as u8 as _) });
+            self.dlh()
+                .write(|w| unsafe { w.bits((divisor >> 8) as u8 as _) });
+        } else {
+            self.raw_write(RBR_THR_DLL, divisor as u8);
+            self.raw_write(IER_DLH, (divisor >> 8) as u8);
+        }
+        self.write_lcr(lcr);
+    }
+
+    fn read_divisor(&self) -> u16 {
+        let lcr = self.read_lcr();
+        self.write_lcr(lcr | LCR_DLAB);
+        let divisor = if self.is_board() {
+            self.dll().read().bits() as u8 as u16 | (self.dlh().read().bits() as u8 as u16) << 8
+        } else {
+            self.raw_read(RBR_THR_DLL) as u16 | (self.raw_read(IER_DLH) as u16) << 8
+        };
+        self.write_lcr(lcr);
+        divisor
+    }
+}
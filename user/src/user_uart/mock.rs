@@ -0,0 +1,465 @@
+use super::regs::*;
+use super::*;
+use crate::uintr::critical_section;
+use alloc::boxed::Box;
+use alloc::task::Wake;
+use alloc::vec::Vec;
+use heapless::spsc::Queue;
+
+/// A UART with scripted traffic, to drive a driver's interrupt handler
+/// without a peer. Build the driver on `&'static MockUart` registers, e.g.
+/// with `AsyncSerial::with_registers`.
+///
+/// Behaves like a 16550 whose transmitter empties at once, unless
+/// `hold_tx` keeps the bytes in the tx FIFO. IIR shows the highest
+/// priority source enabled in IER. Reading RBR takes the next injected
+/// byte. Reading LSR clears its error bits, and reading MSR its delta
+/// bits. Each THR write is logged and, with the transmitter not held,
+/// raises a THR empty interrupt, as does setting IER.ETBEI with the tx
+/// FIFO empty. FIFOs are 16 bytes deep unless `set_deep_fifo` makes it a
+/// 16750. With LCR.DLAB set, RBR/THR and IER are the divisor latches, as
+/// on the real part. `glitch_fifo_reset` makes FIFO resets misbehave the
+/// way the LRV part does.
+pub struct MockUart {
+    state: Mutex<MockState>,
+}
+
+#[derive(Default)]
+struct MockState {
+    ier: u8,
+    lcr: u8,
+    mcr: u8,
+    msr: u8,
+    divisor: u16,
+    fcr: u8,
+    rx: VecDeque<u8>,
+    tx: Vec<u8>,
+    /// Bytes in the tx FIFO while `tx_held`.
+    tx_fifo: usize,
+    tx_held: bool,
+    tx_overruns: usize,
+    tx_level_register: bool,
+    line_errors: u8,
+    thre_pending: bool,
+    rts_edges: usize,
+    /// Has 64 byte FIFOs, and they are on.
+    deep_fifo: bool,
+    fifo64: bool,
+    /// Raw IIR values served before the computed ones.
+    forced_iids: VecDeque<u8>,
+    /// IIR value and reads of it a FIFO reset leaves behind.
+    reset_glitch: Option<(u8, usize)>,
+}
+
+impl MockState {
+    fn fifo_depth(&self) -> usize {
+        if self.fifo64 {
+            DEEP_FIFO_DEPTH
+        } else {
+            FIFO_DEPTH
+        }
+    }
+
+    fn next_iid(&mut self) -> u8 {
+        if let Some(iid) = self.forced_iids.pop_front() {
+            iid
+        } else if self.line_errors != 0 && self.ier & IER_ELSI != 0 {
+            IID_LINE_STATUS
+        } else if !self.rx.is_empty() && self.ier & IER_ERBFI != 0 {
+            IID_RX_DATA
+        } else if self.thre_pending && self.ier & IER_ETBEI != 0 {
+            IID_THR_EMPTY
+        } else if self.msr & MSR_DELTA_BITS != 0 && self.ier & IER_EDSSI != 0 {
+            IID_MODEM_STATUS
+        } else {
+            IID_NO_INTERRUPT
+        }
+    }
+}
+
+impl MockUart {
+    /// The mock lives as long as the program.
+    pub fn new() -> &'static MockUart {
+        Box::leak(Box::new(MockUart {
+            state: Mutex::new(MockState::default()),
+        }))
+    }
+
+    /// Bytes the driver will find in RBR, in order.
+    pub fn inject_rx(&self, bytes: &[u8]) {
+        self.with_state(|state| state.rx.extend(bytes.iter().copied()));
+    }
+
+    /// Sets the `LSR_ERROR_BITS` in `lsr`. They raise a line status
+    /// interrupt until LSR is read.
+    pub fn inject_line_error(&self, lsr: u8) {
+        self.with_state(|state| state.line_errors |= lsr & LSR_ERROR_BITS);
+    }
+
+    /// Sets MSR to `msr`, lines and delta bits. A delta bit raises a modem
+    /// status interrupt until MSR is read.
+    pub fn inject_modem_status(&self, msr: u8) {
+        self.with_state(|state| state.msr = msr);
+    }
+
+    /// Raises a THR empty interrupt, as if the transmitter had just emptied.
+    pub fn inject_thr_empty(&self) {
+        self.with_state(|state| state.thre_pending = true);
+    }
+
+    /// Makes the next IIR read return `iir` as it is, whatever IER says,
+    /// e.g. an ID the driver does not handle.
+    pub fn inject_iid(&self, iir: u8) {
+        self.with_state(|state| state.forced_iids.push_back(iir));
+    }
+
+    /// From now on a FIFO reset raises the interrupt of that FIFO if IER
+    /// has it on, then shows `iir` for the next `reads` IIR reads, whatever
+    /// IER says.
+    pub fn glitch_fifo_reset(&self, iir: u8, reads: usize) {
+        self.with_state(|state| state.reset_glitch = Some((iir, reads)));
+    }
+
+    /// While `held`, bytes written to THR wait in the tx FIFO until
+    /// `shift_tx` sends them, and LSR.THRE is clear while any do. A write
+    /// to a full FIFO is lost, see `tx_overruns`.
+    pub fn hold_tx(&self, held: bool) {
+        self.with_state(|state| {
+            state.tx_held = held;
+            if !held {
+                state.tx_fifo = 0;
+            }
+        })
+    }
+
+    /// Sends up to `len` bytes of the held tx FIFO. Emptying it raises a
+    /// THR empty interrupt.
+    pub fn shift_tx(&self, len: usize) {
+        self.with_state(|state| {
+            if state.tx_fifo != 0 && state.tx_fifo <= len {
+                state.thre_pending = true;
+            }
+            state.tx_fifo = state.tx_fifo.saturating_sub(len);
+        })
+    }
+
+    /// Bytes in the held tx FIFO.
+    pub fn tx_fifo_level(&self) -> usize {
+        self.with_state(|state| state.tx_fifo)
+    }
+
+    /// THR writes lost to a full tx FIFO. They are not in `take_tx`.
+    pub fn tx_overruns(&self) -> usize {
+        self.with_state(|state| state.tx_overruns)
+    }
+
+    /// Gives the mock a tx FIFO level register, for
+    /// `UartRegisters::read_tx_fifo_level`.
+    pub fn set_tx_level_register(&self, present: bool) {
+        self.with_state(|state| state.tx_level_register = present);
+    }
+
+    /// Takes what the driver wrote to THR so far.
+    pub fn take_tx(&self) -> Vec<u8> {
+        self.with_state(|state| core::mem::take(&mut state.tx))
+    }
+
+    /// Moves up to `out.len()` bytes the driver wrote to THR into `out`,
+    /// oldest first, and returns how many. Unlike `take_tx` it keeps the
+    /// room `reserve` made.
+    pub fn drain_tx(&self, out: &mut [u8]) -> usize {
+        self.with_state(|state| {
+            let len = out.len().min(state.tx.len());
+            out[..len].copy_from_slice(&state.tx[..len]);
+            state.tx.drain(..len);
+            len
+        })
+    }
+
+    /// Makes room for `rx` injected and `tx` sent bytes up front, so
+    /// neither allocates while within them, e.g. from an interrupt
+    /// handler that may have interrupted the allocator.
+    pub fn reserve(&self, rx: usize, tx: usize) {
+        self.with_state(|state| {
+            state.rx.reserve(rx);
+            state.tx.reserve(tx);
+        })
+    }
+
+    /// Times the driver changed MCR.RTS, to wire it to a peer's CTS.
+    pub fn rts_edges(&self) -> usize {
+        self.with_state(|state| state.rts_edges)
+    }
+
+    /// Injected bytes the driver has not read yet.
+    pub fn rx_left(&self) -> usize {
+        self.with_state(|state| state.rx.len())
+    }
+
+    pub fn divisor(&self) -> u16 {
+        self.with_state(|state| state.divisor)
+    }
+
+    /// What the driver last wrote to FCR.
+    pub fn fcr(&self) -> u8 {
+        self.with_state(|state| state.fcr)
+    }
+
+    /// Gives the mock 64 byte FIFOs, which `FCR_FIFO64` turns on and IIR
+    /// then reports.
+    pub fn set_deep_fifo(&self, deep: bool) {
+        self.with_state(|state| state.deep_fifo = deep);
+    }
+
+    /// Loses the register contents, as a gated UART clock does. Queued
+    /// traffic and the deep FIFO capability stay.
+    pub fn power_off(&self) {
+        self.with_state(|state| {
+            state.ier = 0;
+            state.lcr = 0;
+            state.mcr = 0;
+            state.divisor = 0;
+            state.fcr = 0;
+            state.fifo64 = false;
+            state.thre_pending = false;
+        })
+    }
+
+    fn with_state<T>(&self, f: impl FnOnce(&mut MockState) -> T) -> T {
+        critical_section(|| f(&mut self.state.lock()))
+    }
+
+    /// A new mock and an `AsyncSerial` on it, after `hardware_init`. The
+    /// driver's queues live as long as the program, like the mock.
+    pub fn async_serial(baud_rate: usize) -> (&'static MockUart, AsyncSerial<&'static MockUart>) {
+        let (mock, serial) = MockUart::async_serial_uninit();
+        serial.hardware_init(baud_rate);
+        (mock, serial)
+    }
+
+    /// `async_serial` before `hardware_init`, to test the init itself.
+    pub fn async_serial_uninit() -> (&'static MockUart, AsyncSerial<&'static MockUart>) {
+        let mock = MockUart::new();
+        let rx = Box::leak(Box::new(Queue::new()));
+        let tx = Box::leak(Box::new(Queue::new()));
+        let (rx_pro, rx_con) = rx.split();
+        let (tx_pro, tx_con) = tx.split();
+        let serial = AsyncSerial::with_registers(mock, rx_pro, rx_con, tx_pro, tx_con);
+        (mock, serial)
+    }
+}
+
+/// The checks of a test bin on a `MockUart`, printed as they are made.
+pub struct MockReport {
+    name: &'static str,
+    passed: bool,
+}
+
+impl MockReport {
+    pub fn new(name: &'static str) -> Self {
+        MockReport { name, passed: true }
+    }
+
+    /// Prints `[name] what: ok`, or `FAILED`, and returns `ok`.
+    pub fn check(&mut self, what: &str, ok: bool) -> bool {
+        println!(
+            "[{}] {}: {}",
+            self.name,
+            what,
+            if ok { "ok" } else { "FAILED" }
+        );
+        self.passed &= ok;
+        ok
+    }
+
+    /// The exit code of the bin, 0 if every check passed.
+    pub fn exit_code(&self) -> i32 {
+        if self.passed {
+            0
+        } else {
+            -1
+        }
+    }
+}
+
+/// A waker that counts its wakes, to poll driver futures by hand.
+#[derive(Default)]
+pub struct CountingWaker(AtomicUsize);
+
+impl CountingWaker {
+    pub fn wakes(&self) -> usize {
+        self.0.load(Relaxed)
+    }
+}
+
+impl Wake for CountingWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.fetch_add(1, Relaxed);
+    }
+}
+
+/// Polls `future` once with `waker`.
+pub fn poll_once<F: Future + ?Sized>(
+    future: Pin<&mut F>,
+    waker: &Arc<CountingWaker>,
+) -> Poll<F::Output> {
+    let waker = Waker::from(waker.clone());
+    future.poll(&mut Context::from_waker(&waker))
+}
+
+impl UartRegisters for &'static MockUart {
+    fn base_address(&self) -> usize {
+        *self as *const MockUart as usize
+    }
+
+    fn read_iir(&self) -> u8 {
+        self.with_state(|state| {
+            let iid = state.next_iid();
+            if iid == IID_THR_EMPTY {
+                state.thre_pending = false;
+            }
+            let fifo64 = if state.fifo64 { IIR_FIFO64 } else { 0 };
+            IIR_FIFO_ENABLED | fifo64 | iid
+        })
+    }
+
+    fn read_lsr(&self) -> u8 {
+        self.with_state(|state| {
+            let dr = if state.rx.is_empty() { 0 } else { LSR_DR };
+            let thre = if state.tx_fifo == 0 {
+                LSR_THRE | LSR_TEMT
+            } else {
+                0
+            };
+            thre | dr | core::mem::take(&mut state.line_errors)
+        })
+    }
+
+    fn read_rbr(&self) -> u8 {
+        self.with_state(|state| {
+            if state.lcr & LCR_DLAB != 0 {
+                state.divisor as u8
+            } else {
+                state.rx.pop_front().unwrap_or(0)
+            }
+        })
+    }
+
+    fn write_thr(&self, ch: u8) {
+        self.with_state(|state| {
+            if state.lcr & LCR_DLAB != 0 {
+                state.divisor = state.divisor & 0xff00 | ch as u16;
+            } else if !state.tx_held {
+                state.tx.push(ch);
+                state.thre_pending = true;
+            } else if state.tx_fifo < state.fifo_depth() {
+                state.tx.push(ch);
+                state.tx_fifo += 1;
+            } else {
+                state.tx_overruns += 1;
+            }
+        })
+    }
+
+    fn read_tx_fifo_level(&self) -> Option<usize> {
+        self.with_state(|state| Some(state.tx_fifo).filter(|_| state.tx_level_register))
+    }
+
+    fn read_msr(&self) -> u8 {
+        self.with_state(|state| {
+            let msr = state.msr;
+            state.msr &= !MSR_DELTA_BITS;
+            msr
+        })
+    }
+
+    fn read_ier(&self) -> u8 {
+        self.with_state(|state| {
+            if state.lcr & LCR_DLAB != 0 {
+                (state.divisor >> 8) as u8
+            } else {
+                state.ier
+            }
+        })
+    }
+
+    fn write_ier(&self, ier: u8) {
+        self.with_state(|state| {
+            if state.lcr & LCR_DLAB != 0 {
+                state.divisor = state.divisor & 0xff | (ier as u16) << 8;
+                return;
+            }
+            if ier & IER_ETBEI != 0 && state.ier & IER_ETBEI == 0 && state.tx_fifo == 0 {
+                state.thre_pending = true;
+            }
+            state.ier = ier;
+        })
+    }
+
+    fn read_lcr(&self) -> u8 {
+        self.with_state(|state| state.lcr)
+    }
+
+    fn write_lcr(&self, lcr: u8) {
+        self.with_state(|state| state.lcr = lcr)
+    }
+
+    fn read_mcr(&self) -> u8 {
+        self.with_state(|state| state.mcr)
+    }
+
+    fn write_mcr(&self, mcr: u8) {
+        self.with_state(|state| {
+            if (state.mcr ^ mcr) & MCR_RTS != 0 {
+                state.rts_edges += 1;
+            }
+            state.mcr = mcr;
+        })
+    }
+
+    fn write_fcr(&self, fcr: u8) {
+        self.with_state(|state| {
+            state.fcr = fcr;
+            if fcr & FCR_RX_RESET != 0 {
+                state.rx.clear();
+            }
+            if fcr & FCR_TX_RESET != 0 {
+                state.tx_fifo = 0;
+            }
+            if state.lcr & LCR_DLAB != 0 {
+                state.fifo64 = state.deep_fifo && fcr & FCR_FIFO64 != 0;
+            }
+            if let Some((iir, reads)) = state.reset_glitch {
+                if fcr & FCR_RX_RESET != 0 && state.ier & IER_ERBFI != 0 {
+                    state.forced_iids.push_back(IID_RX_DATA);
+                }
+                if fcr & FCR_TX_RESET != 0 && state.ier & IER_ETBEI != 0 {
+                    state.forced_iids.push_back(IID_THR_EMPTY);
+                }
+                if fcr & (FCR_RX_RESET | FCR_TX_RESET) != 0 {
+                    state
+                        .forced_iids
+                        .extend(core::iter::repeat(iir).take(reads));
+                }
+            }
+        })
+    }
+
+    // one register access at a time, so a stray access in between lands
+    // where it would on hardware
+    fn write_divisor(&self, divisor: u16) {
+        let lcr = self.read_lcr();
+        self.write_lcr(lcr | LCR_DLAB);
+        self.write_thr(divisor as u8);
+        self.write_ier((divisor >> 8) as u8);
+        self.write_lcr(lcr);
+    }
+
+    fn read_divisor(&self) -> u16 {
+        let lcr = self.read_lcr();
+        self.write_lcr(lcr | LCR_DLAB);
+        let divisor = self.read_rbr() as u16 | (self.read_ier() as u16) << 8;
+        self.write_lcr(lcr);
+        divisor
+    }
+}
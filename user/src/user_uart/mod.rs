@@ -1,7 +1,8 @@
 use crate::future::GetWakerFuture;
 use crate::trace::{
-    push_trace, ASYNC_READ_POLL, ASYNC_WRITE_POLL, ASYNC_WRITE_WAKE, SERIAL_CTS, SERIAL_INTR_ENTER,
-    SERIAL_INTR_EXIT, SERIAL_RTS, SERIAL_RX, SERIAL_TX,
+    push_trace, ASYNC_READ_POLL, ASYNC_WRITE_POLL, ASYNC_WRITE_WAKE, SERIAL_CTS,
+    SERIAL_INTR_BAILOUT, SERIAL_INTR_BAILOUT_UNKNOWN, SERIAL_INTR_ENTER, SERIAL_INTR_EXIT,
+    SERIAL_RTS, SERIAL_RX, SERIAL_TX,
 };
 use alloc::collections::VecDeque;
 use alloc::sync::{Arc, Weak};
@@ -9,10 +10,9 @@ use core::future::Future;
 use core::sync::atomic::Ordering::Relaxed;
 use core::sync::atomic::{AtomicIsize, AtomicUsize};
 use core::task::{Context, Poll, Waker};
-use core::{convert::Infallible, pin::Pin, sync::atomic::AtomicBool};
+use core::{convert::Infallible, fmt, pin::Pin, sync::atomic::AtomicBool};
 use embedded_hal::serial::{Read, Write};
 use futures::{Sink, SinkExt, Stream, StreamExt};
-use heapless::spsc;
 #[cfg(feature = "board_lrv")]
 use lrv_pac::uart;
 #[cfg(feature = "board_qemu")]
@@ -20,6 +20,10 @@ use qemu_pac::uart;
 pub use serial_config::*;
 use spin::Mutex;
 
+// ahead of the drivers, for its macros
+#[macro_use]
+mod diag;
+
 pub const DEFAULT_TX_BUFFER_SIZE: usize = 5256;
 pub const DEFAULT_RX_BUFFER_SIZE: usize = 5256;
 
@@ -28,19 +32,12 @@ mod serial_config {
     pub use uart8250::{uart::LSR, InterruptType, MmioUart8250};
     pub type SerialHardware = MmioUart8250<'static>;
     pub const FIFO_DEPTH: usize = 16;
-    pub const RTS_PULSE_WIDTH: usize = 8;    
+    pub const RTS_PULSE_WIDTH: usize = 8;
+    // Layout defaults, only used when the kernel can't enumerate its ports.
     pub const SERIAL_NUM: usize = 4;
     pub const SERIAL_BASE_ADDRESS: usize = 0x1000_2000;
     pub const SERIAL_ADDRESS_STRIDE: usize = 0x1000;
-    pub fn irq_to_serial_id(irq: u16) -> usize {
-        match irq {
-            12 => 0,
-            13 => 1,
-            14 => 2,
-            15 => 3,
-            _ => 0,
-        }
-    }
+    pub const SERIAL_IRQ_BASE: u16 = 12;
 }
 
 #[cfg(feature = "board_lrv")]
@@ -49,24 +46,41 @@ mod serial_config {
     pub type SerialHardware = MmioUartAxi16550<'static>;
     pub const FIFO_DEPTH: usize = 16;
     pub const RTS_PULSE_WIDTH: usize = 8;
+    // Layout defaults, only used when the kernel can't enumerate its ports.
     pub const SERIAL_NUM: usize = 4;
     pub const SERIAL_BASE_ADDRESS: usize = 0x6000_1000;
     pub const SERIAL_ADDRESS_STRIDE: usize = 0x1000;
-    pub fn irq_to_serial_id(irq: u16) -> usize {
-        match irq {
-            4 => 0,
-            5 => 1,
-            6 => 2,
-            7 => 3,
-            _ => 0,
-        }
-    }
+    pub const SERIAL_IRQ_BASE: u16 = 4;
+}
+
+pub fn irq_to_serial_id(irq: u16) -> usize {
+    serial::port_info_by_irq(irq).map_or(0, |port| port.index)
+}
+
+pub fn serial_id_to_irq(serial_id: usize) -> Option<u16> {
+    serial::port_info(serial_id).map(|port| port.irq())
 }
 
 pub fn get_base_addr_from_irq(irq: u16) -> usize {
-    SERIAL_BASE_ADDRESS + irq_to_serial_id(irq) * SERIAL_ADDRESS_STRIDE
+    serial::port_info_by_irq(irq)
+        .or_else(|| serial::port_info(0))
+        .map_or(SERIAL_BASE_ADDRESS, |port| port.phys_base)
+}
+
+/// The base address from `get_base_addr_from_irq` with the port's register
+/// layout.
+///
+/// # Safety
+///
+/// As for `UartMmio::new`, the port of `irq` must be claimed.
+pub unsafe fn get_registers_from_irq(irq: u16) -> UartMmio {
+    let layout = serial::port_info_by_irq(irq)
+        .or_else(|| serial::port_info(0))
+        .map_or(RegLayout::BOARD, |port| port.layout());
+    UartMmio::with_layout(get_base_addr_from_irq(irq), layout)
 }
 
+// Only used through its public API, so it stays on the crate.
 pub use async_uart_driver::serials::BufferedSerial;
 // pub struct BufferedSerial {
 //     // pub hardware: SerialHardware,
@@ -401,86 +415,81 @@ pub use async_uart_driver::serials::BufferedSerial;
 //     }
 // }
 
-pub struct PollingSerial {
-    base_address: usize,
+pub struct PollingSerial<R: UartRegisters = UartMmio> {
+    regs: R,
     pub rx_count: usize,
     pub tx_count: usize,
     pub tx_fifo_count: isize,
     pub rx_fifo_count: usize,
     prev_cts: bool,
+    fifo_depth: usize,
+    /// The line configuration LCR should hold, DLAB clear.
+    lcr: u8,
+    /// Saved by `suspend`, reads and writes block until `resume`.
+    suspended: Option<SerialConfig>,
 }
 
 impl PollingSerial {
-    pub fn new(base_address: usize) -> Self {
+    /// For bring-up without a claim, `FromClaim` is the checked way.
+    ///
+    /// # Safety
+    ///
+    /// As for `UartMmio::new`.
+    pub unsafe fn new(base_address: usize) -> Self {
+        Self::with_registers(UartMmio::new(base_address))
+    }
+}
+
+impl<R: UartRegisters> PollingSerial<R> {
+    pub fn with_registers(regs: R) -> Self {
         PollingSerial {
-            base_address,
+            regs,
             rx_count: 0,
             tx_count: 0,
             tx_fifo_count: 0,
             rx_fifo_count: 0,
             prev_cts: true,
+            fifo_depth: FIFO_DEPTH,
+            lcr: LCR_8N1,
+            suspended: None,
         }
     }
 
-    fn hardware(&self) -> &uart::RegisterBlock {
-        unsafe { &*(self.base_address as *const _) }
+    fn hardware(&self) -> &R {
+        &self.regs
     }
 
     fn set_divisor(&self, clock: usize, baud_rate: usize) {
-        let block = self.hardware();
         let divisor = clock / (16 * baud_rate);
-        block.lcr.write(|w| w.dlab().divisor_latch());
-        #[cfg(feature = "board_lrv")]
-        {
-            block
-                .dll()
-                .write(|w| unsafe { w.bits((divisor & 0b1111_1111) as u32) });
-            block
-                .dlh()
-                .write(|w| unsafe { w.bits(((divisor >> 8) & 0b1111_1111) as u32) });
-        }
-        #[cfg(feature = "board_qemu")]
-        {
-            block
-                .dll()
-                .write(|w| unsafe { w.bits((divisor & 0b1111_1111) as u8) });
-            block
-                .dlh()
-                .write(|w| unsafe { w.bits(((divisor >> 8) & 0b1111_1111) as u8) });
-        }
-
-        block.lcr.write(|w| w.dlab().rx_buffer());
+        self.hardware().write_divisor(divisor as u16);
     }
 
     #[inline]
     pub fn rts(&self, is_asserted: bool) {
-        self.hardware().mcr.modify(|_, w| w.rts().bit(is_asserted))
+        self.hardware()
+            .modify_mcr(|mcr| with_bits(mcr, MCR_RTS, is_asserted))
     }
 
     #[inline]
     pub fn cts(&self) -> bool {
-        self.hardware().msr.read().cts().bit()
+        self.hardware().read_msr() & MSR_CTS != 0
     }
 
     #[inline]
     pub fn dcts(&self) -> bool {
-        self.hardware().msr.read().dcts().bit()
+        self.hardware().read_msr() & MSR_DCTS != 0
     }
 
     #[inline]
     pub fn iid_rda(&self) -> bool {
-        self.hardware()
-            .iir()
-            .read()
-            .iid()
-            .is_received_data_available()
+        self.hardware().read_iir() & IIR_IID_MASK == IID_RX_DATA
     }
 
     #[inline]
     fn try_recv(&self) -> Option<u8> {
         let block = self.hardware();
-        if block.lsr.read().dr().is_ready() {
-            let ch = block.rbr().read().rbr().bits();
+        if block.read_lsr() & LSR_DR != 0 {
+            let ch = block.read_rbr();
             push_trace(SERIAL_RX | ch as usize);
             Some(ch)
         } else {
@@ -492,36 +501,26 @@ impl PollingSerial {
     fn send(&self, ch: u8) {
         let block = self.hardware();
         push_trace(SERIAL_TX | ch as usize);
-        block.thr().write(|w| w.thr().variant(ch));
+        block.write_thr(ch);
     }
 
     pub fn hardware_init(&mut self, baud_rate: usize) {
-        let block = self.hardware();
-        let _unused = block.msr.read().bits();
-        let _unused = block.lsr.read().bits();
-        block.lcr.reset();
+        let block = self.regs;
+        let _unused = block.read_msr();
+        let _unused = block.read_lsr();
+        block.write_lcr(0);
         // No modem control
-        block.mcr.reset();
-        block.ier().reset();
-        block.fcr().reset();
+        block.write_mcr(0);
+        block.write_ier(0);
+        block.write_fcr(0);
 
-        // Enable DLAB and Set divisor
         self.set_divisor(100_000_000, baud_rate);
-        // Disable DLAB and set word length 8 bits, no parity, 1 stop bit
-        block
-            .lcr
-            .modify(|_, w| w.dls().eight().pen().disabled().stop().one());
-        // Enable FIFO
-        block.fcr().write(|w| {
-            w.fifoe()
-                .set_bit()
-                .rfifor()
-                .set_bit()
-                .xfifor()
-                .set_bit()
-                .rt()
-                .two_less_than_full()
-        });
+        // word length 8 bits, no parity, 1 stop bit
+        block.write_lcr(LCR_8N1);
+        self.lcr = LCR_8N1;
+        // Enable FIFO, 64 bytes deep if it can be
+        self.fifo_depth = block.detect_fifo_depth();
+        block.write_fcr(FCR_FIFO_ENABLE | FCR_RX_TRIGGER_14);
 
         // Loopback
         // block.mcr.modify(|_, w| w.loop_().loop_back());
@@ -530,38 +529,130 @@ impl PollingSerial {
         let _unused = self.dcts();
     }
 
+    /// Bytes written before waiting for CTS credit, as found by
+    /// `hardware_init`.
+    pub fn fifo_depth(&self) -> usize {
+        self.fifo_depth
+    }
+
+    /// Changes the baud rate. Bytes still in the tx FIFO go out at the new
+    /// rate.
+    pub fn set_baud(&mut self, baud_rate: usize) {
+        self.set_divisor(100_000_000, baud_rate);
+    }
+
+    /// LCR holds the line configuration the driver set.
+    pub fn line_config_intact(&self) -> bool {
+        self.hardware().read_lcr() == self.lcr
+    }
+
+    /// Overrides the detected depth, see `AsyncSerial::set_fifo_depth`.
+    pub fn set_fifo_depth(&mut self, fifo_depth: usize) {
+        self.fifo_depth = fifo_depth;
+    }
+
+    /// Waits up to `EXIT_DRAIN_TIMEOUT_US` for the transmitter to be idle
+    /// and saves the configuration, for the UART clock to be gated. Until
+    /// `resume`, reads and writes would block. Returns false if the
+    /// transmitter did not go idle.
+    pub fn suspend(&mut self) -> bool {
+        if self.suspended.is_some() {
+            return true;
+        }
+        let idle = self.regs.wait_tx_idle(EXIT_DRAIN_TIMEOUT_US);
+        let fifo64 = if self.fifo_depth == DEEP_FIFO_DEPTH {
+            FCR_FIFO64
+        } else {
+            0
+        };
+        let config = self
+            .regs
+            .save_config(FCR_FIFO_ENABLE | fifo64 | FCR_RX_TRIGGER_14);
+        self.regs.write_ier(0);
+        self.suspended = Some(config);
+        idle
+    }
+
+    /// Programs the configuration `suspend` saved back.
+    pub fn resume(&mut self) {
+        if let Some(config) = self.suspended.take() {
+            self.regs.restore_config(&config);
+            self.regs.write_ier(config.ier);
+            self.tx_fifo_count = 0;
+            self.rx_fifo_count = 0;
+        }
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        self.suspended.is_some()
+    }
+
     #[inline]
     pub fn interrupt_handler(&mut self) {}
 
     #[inline]
     pub fn error_handler(&self) -> bool {
         let block = self.hardware();
-        let lsr = block.lsr.read();
-        if lsr.fifoerr().is_error() {
-            if lsr.bi().bit_is_set() {
-                println!("[uart] lsr.BI!");
+        let lsr = block.read_lsr();
+        if lsr & LSR_FIFO_ERROR != 0 {
+            if lsr & LSR_BI != 0 {
+                serial_warn!(block.base_address(), "[uart] lsr.BI!");
             }
-            if lsr.fe().bit_is_set() {
-                println!("[uart] lsr.FE!");
+            if lsr & LSR_FE != 0 {
+                serial_warn!(block.base_address(), "[uart] lsr.FE!");
             }
-            if lsr.pe().bit_is_set() {
-                println!("[uart] lsr.PE!");
+            if lsr & LSR_PE != 0 {
+                serial_warn!(block.base_address(), "[uart] lsr.PE!");
             }
         }
-        if lsr.oe().bit_is_set() {
-            block.mcr.modify(|_, w| w.rts().deasserted());
-            println!("[uart] lsr.OE!");
+        if lsr & LSR_OE != 0 {
+            block.modify_mcr(|mcr| mcr & !MCR_RTS);
+            serial_warn!(block.base_address(), "[uart] lsr.OE!");
             return true;
         }
         false
     }
 }
 
-impl Write<u8> for PollingSerial {
+/// Counters and FIFO estimates, no register is read.
+impl<R: UartRegisters> fmt::Debug for PollingSerial<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PollingSerial")
+            .field(
+                "base_address",
+                &format_args!("{:#x}", self.regs.base_address()),
+            )
+            .field("rx_count", &self.rx_count)
+            .field("tx_count", &self.tx_count)
+            .field("rx_fifo_count", &self.rx_fifo_count)
+            .field("tx_fifo_count", &self.tx_fifo_count)
+            .field("prev_cts", &self.prev_cts)
+            .field("fifo_depth", &self.fifo_depth)
+            .finish()
+    }
+}
+
+/// One line for periodic logging, e.g. `uart1 rx=12345 tx=9876`.
+impl<R: UartRegisters> fmt::Display for PollingSerial<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} rx={} tx={}",
+            serial::PortName(self.regs.base_address()),
+            self.rx_count,
+            self.tx_count
+        )
+    }
+}
+
+impl<R: UartRegisters> Write<u8> for PollingSerial<R> {
     type Error = Infallible;
 
     #[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
     fn try_write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+        if self.suspended.is_some() {
+            return Err(nb::Error::WouldBlock);
+        }
         if self.dcts() {
             let cts = self.cts();
             if cts == self.prev_cts {
@@ -580,7 +671,7 @@ impl Write<u8> for PollingSerial {
         // assert!(self.tx_fifo_count >= 0);
         // assert!(self.tx_fifo_count <= FIFO_DEPTH as _);
 
-        if self.tx_fifo_count == FIFO_DEPTH as _ {
+        if self.tx_fifo_count == self.fifo_depth as _ {
             return Err(nb::Error::WouldBlock);
         }
         self.send(word);
@@ -594,11 +685,14 @@ impl Write<u8> for PollingSerial {
     }
 }
 
-impl Read<u8> for PollingSerial {
+impl<R: UartRegisters> Read<u8> for PollingSerial<R> {
     type Error = Infallible;
 
     #[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
     fn try_read(&mut self) -> nb::Result<u8, Self::Error> {
+        if self.suspended.is_some() {
+            return Err(nb::Error::WouldBlock);
+        }
         if let Some(ch) = self.try_recv() {
             self.rx_count += 1;
             self.rx_fifo_count += 1;
@@ -617,505 +711,113 @@ impl Read<u8> for PollingSerial {
     }
 }
 
-impl Drop for PollingSerial {
+impl<R: UartRegisters> Drop for PollingSerial<R> {
     fn drop(&mut self) {
         let block = self.hardware();
-        block.ier().reset();
-        let _unused = block.msr.read().bits();
-        let _unused = block.lsr.read().bits();
+        block.write_ier(0);
+        let _unused = block.read_msr();
+        let _unused = block.read_lsr();
         self.rts(false);
         // reset Rx & Tx FIFO, disable FIFO
-        block
-            .fcr()
-            .write(|w| w.fifoe().clear_bit().rfifor().set_bit().xfifor().set_bit());
+        block.reset_fifos(0, FCR_RX_RESET | FCR_TX_RESET);
         // println!("Polling driver dropped!");
     }
 }
 
-pub use async_uart_driver::serials::AsyncSerial;
-
-// type RxProducer = spsc::Producer<'static, u8, DEFAULT_RX_BUFFER_SIZE>;
-// type RxConsumer = spsc::Consumer<'static, u8, DEFAULT_RX_BUFFER_SIZE>;
-// type TxProducer = spsc::Producer<'static, u8, DEFAULT_TX_BUFFER_SIZE>;
-// type TxConsumer = spsc::Consumer<'static, u8, DEFAULT_TX_BUFFER_SIZE>;
-
-// pub struct AsyncSerial {
-//     base_address: usize,
-//     rx_pro: Mutex<RxProducer>,
-//     rx_con: Mutex<RxConsumer>,
-//     tx_pro: Mutex<TxProducer>,
-//     tx_con: Mutex<TxConsumer>,
-//     pub rx_count: AtomicUsize,
-//     pub tx_count: AtomicUsize,
-//     pub intr_count: AtomicUsize,
-//     pub rx_intr_count: AtomicUsize,
-//     pub tx_intr_count: AtomicUsize,
-//     rx_fifo_count: AtomicUsize,
-//     tx_fifo_count: AtomicIsize,
-//     pub(super) rx_intr_enabled: AtomicBool,
-//     pub(super) tx_intr_enabled: AtomicBool,
-//     prev_cts: AtomicBool,
-//     read_waker: Mutex<Option<Waker>>,
-//     write_waker: Mutex<Option<Waker>>,
-// }
-
-// impl AsyncSerial {
-//     pub fn new(
-//         base_address: usize,
-//         rx_pro: RxProducer,
-//         rx_con: RxConsumer,
-//         tx_pro: TxProducer,
-//         tx_con: TxConsumer,
-//     ) -> Self {
-//         AsyncSerial {
-//             base_address,
-//             rx_pro: Mutex::new(rx_pro),
-//             rx_con: Mutex::new(rx_con),
-//             tx_pro: Mutex::new(tx_pro),
-//             tx_con: Mutex::new(tx_con),
-//             rx_count: AtomicUsize::new(0),
-//             tx_count: AtomicUsize::new(0),
-//             intr_count: AtomicUsize::new(0),
-//             rx_intr_count: AtomicUsize::new(0),
-//             tx_intr_count: AtomicUsize::new(0),
-//             rx_fifo_count: AtomicUsize::new(0),
-//             tx_fifo_count: AtomicIsize::new(0),
-//             rx_intr_enabled: AtomicBool::new(false),
-//             tx_intr_enabled: AtomicBool::new(false),
-//             prev_cts: AtomicBool::new(true),
-//             read_waker: Mutex::new(None),
-//             write_waker: Mutex::new(None),
-//         }
-//     }
-
-//     fn hardware(&self) -> &uart::RegisterBlock {
-//         unsafe { &*(self.base_address as *const _) }
-//     }
-
-//     fn set_divisor(&self, clock: usize, baud_rate: usize) {
-//         let block = self.hardware();
-//         let divisor = clock / (16 * baud_rate);
-//         block.lcr.write(|w| w.dlab().set_bit());
-//         #[cfg(feature = "board_lrv")]
-//         {
-//             block
-//                 .dll()
-//                 .write(|w| unsafe { w.bits((divisor & 0b1111_1111) as u32) });
-//             block
-//                 .dlh()
-//                 .write(|w| unsafe { w.bits(((divisor >> 8) & 0b1111_1111) as u32) });
-//         }
-//         #[cfg(feature = "board_qemu")]
-//         {
-//             block
-//                 .dll()
-//                 .write(|w| unsafe { w.bits((divisor & 0b1111_1111) as u8) });
-//             block
-//                 .dlh()
-//                 .write(|w| unsafe { w.bits(((divisor >> 8) & 0b1111_1111) as u8) });
-//         }
-
-//         block.lcr.write(|w| w.dlab().clear_bit());
-//     }
-
-//     #[inline]
-//     fn addr_no(&self) -> usize {
-//         ((self.base_address >> 12) & 0xFF) + 3
-//     }
-
-//     pub(super) fn enable_rdai(&self) {
-//         self.hardware().ier().modify(|_, w| w.erbfi().set_bit());
-//         self.rx_intr_enabled.store(true, Relaxed);
-//     }
-
-//     fn disable_rdai(&self) {
-//         self.hardware().ier().modify(|_, w| w.erbfi().clear_bit());
-//         self.rx_intr_enabled.store(false, Relaxed);
-//     }
-
-//     pub(super) fn enable_threi(&self) {
-//         self.hardware().ier().modify(|_, w| w.etbei().set_bit());
-//         self.tx_intr_enabled.store(true, Relaxed);
-//     }
-
-//     fn disable_threi(&self) {
-//         self.hardware().ier().modify(|_, w| w.etbei().clear_bit());
-//         self.tx_intr_enabled.store(false, Relaxed);
-//     }
-
-//     #[inline]
-//     pub fn rts(&self, is_asserted: bool) {
-//         // println!("[uart] rts: {}", is_asserted);
-//         self.hardware().mcr.modify(|_, w| w.rts().bit(is_asserted))
-//     }
-
-//     #[inline]
-//     pub fn cts(&self) -> bool {
-//         self.hardware().msr.read().cts().bit()
-//     }
-
-//     #[inline]
-//     pub fn dcts(&self) -> bool {
-//         self.hardware().msr.read().dcts().bit()
-//     }
-
-//     fn try_recv(&self) -> Option<u8> {
-//         let block = self.hardware();
-//         if block.lsr.read().dr().bit_is_set() {
-//             let ch = block.rbr().read().rbr().bits();
-//             push_trace(SERIAL_RX | ch as usize);
-//             Some(ch)
-//         } else {
-//             None
-//         }
-//     }
-
-//     fn send(&self, ch: u8) {
-//         let block = self.hardware();
-//         push_trace(SERIAL_TX | ch as usize);
-//         block.thr().write(|w| w.thr().variant(ch));
-//     }
-
-//     pub(super) fn try_read(&self) -> Option<u8> {
-//         if let Some(mut rx_lock) = self.rx_con.try_lock() {
-//             rx_lock.dequeue()
-//         } else {
-//             println!("[async] cannot lock rx queue!");
-//             None
-//         }
-//     }
-
-//     pub(super) fn try_write(&self, ch: u8) -> Result<(), u8> {
-//         if let Some(mut tx_lock) = self.tx_pro.try_lock() {
-//             tx_lock.enqueue(ch)
-//         } else {
-//             println!("[async] cannot lock tx queue!");
-//             Err(ch)
-//         }
-//     }
-
-//     pub fn hardware_init(&self, baud_rate: usize) {
-//         let block = self.hardware();
-//         let _unused = block.msr.read().bits();
-//         let _unused = block.lsr.read().bits();
-//         block.lcr.reset();
-//         // No modem control
-//         block.mcr.reset();
-//         block.ier().reset();
-//         block.fcr().reset();
-
-//         // Enable DLAB and Set divisor
-//         self.set_divisor(100_000_000, baud_rate);
-//         // Disable DLAB and set word length 8 bits, no parity, 1 stop bit
-//         block
-//             .lcr
-//             .modify(|_, w| w.dls().eight().pen().disabled().stop().one());
-//         // Enable FIFO
-//         block.fcr().write(|w| {
-//             w.fifoe()
-//                 .set_bit()
-//                 .rfifor()
-//                 .set_bit()
-//                 .xfifor()
-//                 .set_bit()
-//                 .rt()
-//                 .two_less_than_full()
-//         });
-//         self.rts(true);
-//         let _unused = self.dcts();
-//         // Enable line status & modem status interrupt
-//         block
-//             .ier()
-//             .modify(|_, w| w.elsi().enable().edssi().enable());
-//         // Enable received_data_available_interrupt
-//         self.enable_rdai();
-//         self.enable_threi();
-//     }
-
-//     #[inline]
-//     fn toggle_threi(&self) {
-//         self.disable_threi();
-//         self.enable_threi();
-//     }
-
-//     #[inline]
-//     fn start_tx(&self) {
-//         let mut tx_count = 0;
-//         let mut tx_fifo_count = self.tx_fifo_count.load(Relaxed);
-//         // assert!(tx_fifo_count >= 0);
-//         assert!(tx_fifo_count <= FIFO_DEPTH as _);
-//         let mut con = self.tx_con.lock();
-
-//         while tx_fifo_count < FIFO_DEPTH as _ {
-//             if let Some(ch) = con.dequeue() {
-//                 self.send(ch);
-//                 tx_count += 1;
-//                 tx_fifo_count += 1;
-//             } else {
-//                 self.disable_threi();
-//                 break;
-//             }
-//         }
-
-//         if tx_fifo_count == FIFO_DEPTH as _ {
-//             self.disable_threi();
-//         }
-
-//         self.tx_count.fetch_add(tx_count, Relaxed);
-//         self.tx_fifo_count.store(tx_fifo_count, Relaxed);
-//     }
-
-//     #[cfg(any(feature = "board_qemu", feature = "board_lrv"))]
-//     pub fn interrupt_handler(&self) {
-//         // println!("[SERIAL] Interrupt!");
-
-//         use crate::trace::{ASYNC_READ_WAKE, ASYNC_WRITE_WAKE};
-//         use core::sync::atomic::Ordering::{Acquire, Release};
-//         use uart::iir::IID_A;
-
-//         let block = self.hardware();
-//         while let Some(int_type) = block.iir().read().iid().variant() {
-//             if int_type == IID_A::NO_INTERRUPT_PENDING {
-//                 break;
-//             }
-//             let intr_id: usize = int_type as u8 as _;
-//             push_trace(SERIAL_INTR_ENTER + intr_id);
-//             self.intr_count.fetch_add(1, Relaxed);
-//             match int_type {
-//                 IID_A::RECEIVED_DATA_AVAILABLE | IID_A::CHARACTER_TIMEOUT => {
-//                     // println!("[SERIAL] Received data available");
-//                     self.rx_intr_count.fetch_add(1, Relaxed);
-//                     let mut rx_count = 0;
-//                     let mut rx_fifo_count = self.rx_fifo_count.load(Acquire);
-//                     let mut pro = self.rx_pro.lock();
-//                     while let Some(ch) = self.try_recv() {
-//                         rx_fifo_count += 1;
-//                         rx_count += 1;
-//                         if rx_fifo_count == RTS_PULSE_WIDTH {
-//                             push_trace(SERIAL_RTS);
-//                             self.rts(false);
-//                         } else if rx_fifo_count == RTS_PULSE_WIDTH * 2 {
-//                             push_trace(SERIAL_RTS | 1);
-//                             self.rts(true);
-//                             rx_fifo_count = 0;
-//                         }
-//                         if let Err(_) = pro.enqueue(ch) {
-//                             println!("[USER UART] Serial rx buffer overflow!");
-//                         }
-//                         if pro.len() >= DEFAULT_RX_BUFFER_SIZE - 1 {
-//                             self.disable_rdai();
-//                             break;
-//                         }
-//                     }
-//                     self.rx_fifo_count.store(rx_fifo_count, Release);
-//                     self.rx_count.fetch_add(rx_count, Relaxed);
-//                     if let Some(waker) = self.read_waker.try_lock() {
-//                         if waker.is_some() {
-//                             // println!("*** [{}] r wake ****", self.addr_no());
-//                             // waker.take().unwrap().wake();
-//                             push_trace(ASYNC_READ_WAKE);
-//                             waker.as_ref().unwrap().wake_by_ref();
-//                         } else {
-//                             // println!("&&& [{}] no r waker &&&&", self.addr_no());
-//                         }
-//                     } else {
-//                         println!("cannot lock reader waker");
-//                     }
-//                 }
-//                 IID_A::THR_EMPTY => {
-//                     // println!("[SERIAL] Transmitter Holding Register Empty");
-//                     self.tx_intr_count.fetch_add(1, Relaxed);
-//                     self.start_tx();
-//                 }
-//                 IID_A::RECEIVER_LINE_STATUS => {
-//                     let block = self.hardware();
-//                     let lsr = block.lsr.read();
-//                     // if lsr.bi().bit_is_set() {
-//                     if lsr.fifoerr().is_error() {
-//                         if lsr.bi().bit_is_set() {
-//                             println!("[uart] lsr.BI!");
-//                         }
-//                         if lsr.fe().bit_is_set() {
-//                             println!("[uart] lsr.FE!");
-//                         }
-//                         if lsr.pe().bit_is_set() {
-//                             println!("[uart] lsr.PE!");
-//                         }
-//                     }
-//                     if lsr.oe().bit_is_set() {
-//                         block.mcr.modify(|_, w| w.rts().deasserted());
-//                         println!("[uart] lsr.OE!");
-//                     }
-//                 }
-//                 IID_A::MODEM_STATUS => {
-//                     if self.dcts() {
-//                         let cts = self.cts();
-//                         if cts == self.prev_cts.load(Relaxed) {
-//                             push_trace(SERIAL_CTS | (RTS_PULSE_WIDTH * 2));
-//                             self.tx_fifo_count
-//                                 .fetch_add(-(RTS_PULSE_WIDTH as isize * 2), Relaxed);
-//                         } else {
-//                             push_trace(SERIAL_CTS | RTS_PULSE_WIDTH);
-//                             self.tx_fifo_count
-//                                 .fetch_add(-(RTS_PULSE_WIDTH as isize), Relaxed);
-//                         }
-//                         self.prev_cts.store(cts, Relaxed);
-//                         self.toggle_threi();
-//                         // println!("dcts && cts");
-//                         if let Some(waker) = self.write_waker.try_lock() {
-//                             if waker.is_some() {
-//                                 // println!("%%% [{}] w wake %%%%", self.addr_no());
-//                                 // waker.take().unwrap().wake();
-//                                 push_trace(ASYNC_WRITE_WAKE);
-//                                 waker.as_ref().unwrap().wake_by_ref();
-//                             } else {
-//                                 // println!("___ [{}] no w waker ____", self.addr_no());
-//                             }
-//                         } else {
-//                             println!("cannot lock writer waker");
-//                         }
-//                     } else {
-//                         let block = self.hardware();
-//                         println!(
-//                             "[USER SERIAL] EDSSI, MSR: {:#x}, LSR: {:#x}, IER: {:#x}",
-//                             block.msr.read().bits(),
-//                             block.lsr.read().bits(),
-//                             block.ier().read().bits()
-//                         );
-//                     }
-//                 }
-//                 _ => {
-//                     println!("[USER SERIAL] {:?} not supported!", int_type);
-//                 }
-//             }
-//             push_trace(SERIAL_INTR_EXIT + intr_id);
-//         }
-//     }
-
-//     async fn register_read(&self) {
-//         let raw_waker = GetWakerFuture.await;
-//         self.read_waker.lock().replace(raw_waker);
-//     }
-
-//     pub async fn read(self: Arc<Self>, buf: &mut [u8]) {
-//         let future = SerialReadFuture {
-//             buf,
-//             read_len: 0,
-//             driver: self.clone(),
-//         };
-//         self.register_read().await;
-//         future.await;
-//     }
-
-//     async fn register_write(&self) {
-//         let raw_waker = GetWakerFuture.await;
-//         self.write_waker.lock().replace(raw_waker);
-//     }
-
-//     pub async fn write(self: Arc<Self>, buf: &[u8]) {
-//         let future = SerialWriteFuture {
-//             buf,
-//             write_len: 0,
-//             driver: self.clone(),
-//         };
-//         self.register_write().await;
-//         future.await;
-//     }
-
-//     pub fn remove_read(&self) {
-//         self.read_waker.lock().take();
-//     }
-
-//     pub fn remove_write(&self) {
-//         self.write_waker.lock().take();
-//     }
-// }
-
-// impl Drop for AsyncSerial {
-//     fn drop(&mut self) {
-//         let block = self.hardware();
-//         block.ier().reset();
-//         let _unused = block.msr.read().bits();
-//         let _unused = block.lsr.read().bits();
-//         self.rts(false);
-//         // reset Rx & Tx FIFO, disable FIFO
-//         block
-//             .fcr()
-//             .write(|w| w.fifoe().clear_bit().rfifor().set_bit().xfifor().set_bit());
-//         // println!("Async driver dropped!");
-//     }
-// }
-
-// struct SerialReadFuture<'a> {
-//     buf: &'a mut [u8],
-//     read_len: usize,
-//     driver: Arc<AsyncSerial>,
-// }
-
-// impl Future for SerialReadFuture<'_> {
-//     type Output = ();
-
-//     fn poll(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
-//         // println!("read poll");
-//         // let driver = self.driver.clone();
-//         while let Some(data) = self.driver.try_read() {
-//             if self.read_len < self.buf.len() {
-//                 let len = self.read_len;
-//                 self.buf[len] = data;
-//                 self.read_len += 1;
-//             } else {
-//                 // println!("### [{:x}] r poll fin ####", self.driver.addr_no());
-//                 push_trace(ASYNC_READ_POLL);
-//                 return Poll::Ready(());
-//             }
-//         }
-
-//         if !self.driver.rx_intr_enabled.load(Relaxed) {
-//             // println!("read intr enabled");
-//             self.driver.enable_rdai();
-//         }
-//         // println!("$$$ [{:x}] r poll pen $$$$", driver.addr_no());
-//         push_trace(ASYNC_READ_POLL | self.read_len);
-//         Poll::Pending
-//     }
-// }
-
-// struct SerialWriteFuture<'a> {
-//     buf: &'a [u8],
-//     write_len: usize,
-//     driver: Arc<AsyncSerial>,
-// }
-
-// impl Future for SerialWriteFuture<'_> {
-//     type Output = ();
-
-//     fn poll(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
-//         // println!("write poll");
-//         // let driver = self.driver.clone();
+/// Non-blocking byte source the stream adapters are built on.
+pub trait AsyncRead {
+    /// Move the bytes already received into `buf` and return how many.
+    /// If there are none, register `cx`'s waker and return `Pending`.
+    fn poll_read(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<usize>;
+}
 
-//         if self.driver.tx_fifo_count.load(Relaxed) < FIFO_DEPTH as _ {
-//             // println!("=== [{:x}] w intr en ====", self.driver.addr_no());
-//             self.driver.toggle_threi();
-//             self.driver.start_tx();
-//         }
-//         while let Ok(()) = self.driver.try_write(self.buf[self.write_len]) {
-//             if self.write_len < self.buf.len() - 1 {
-//                 self.write_len += 1;
-//             } else {
-//                 // println!("--- [{:x}] w poll fin ----", self.driver.addr_no());
-//                 push_trace(ASYNC_WRITE_POLL);
-//                 return Poll::Ready(());
-//             }
-//         }
+impl<T: AsyncRead + ?Sized> AsyncRead for Arc<T> {
+    fn poll_read(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<usize> {
+        (**self).poll_read(cx, buf)
+    }
+}
 
-//         // println!("^^^ [{:x}] w poll pen ^^^^", self.driver.addr_no());
-//         push_trace(ASYNC_WRITE_POLL | self.write_len);
-//         Poll::Pending
-//     }
-// }
+mod async_serial;
+mod blocking;
+mod builder;
+mod claim;
+mod coalesce;
+mod console;
+#[cfg(feature = "defmt")]
+mod defmt_logger;
+mod diag_tap;
+mod driver;
+mod events;
+pub mod framed;
+mod lines;
+mod mmio;
+#[cfg(feature = "mock_uart")]
+mod mock;
+mod nb_io;
+mod panic_dump;
+mod reg_bits;
+pub mod regs;
+mod ring_buffered;
+mod rx_ring;
+mod rx_tuner;
+pub mod serial;
+mod select;
+mod soft_flow;
+mod split;
+mod stats_reporter;
+mod stdio;
+mod terminal;
+mod throttle;
+pub mod xmodem;
+use async_serial::{record_drop, WakerList};
+pub use async_serial::{
+    drain_all, last_drop_report, AsyncSerial, Busy, CloseReport, DriveMode, FillBuf,
+    HandlerBudget, HandlerCycles, HookAction, Readiness, RxChunk, RxTiming, SerialError,
+    SerialStats, WakerState, WakerStats, EXIT_DRAIN_TIMEOUT_US, MAX_WAITERS, RX_STAGE_LEN,
+    RX_TIMING_LEN, UNKNOWN_IID_LOG, URGENT_LANE_LEN,
+};
+pub use blocking::BlockingSerial;
+pub use builder::{
+    AnySerial, FifoTrigger, Mode, SerialBuildError, SerialBuilder, DEFAULT_BAUD_RATE,
+    MAX_RX_CAPACITY, MAX_TX_CAPACITY,
+};
+pub use claim::{ClaimBuilder, ClaimError, FromClaim, SerialClaim, MAX_IRQ_PRIORITY};
+pub use coalesce::{Coalescing, MAX_TX_DELAY_US};
+pub use console::{ConsoleAsync, CONSOLE_RING_SIZE};
+#[cfg(feature = "defmt")]
+pub use defmt_logger::set_defmt_port;
+#[cfg(feature = "log")]
+pub use diag::{init_serial_logger, log_dropped, log_suppressed, LOG_RECORD_SIZE};
+pub use diag_tap::{DiagnosticTap, DEFAULT_ESCAPE, ESCAPE_HOLD_US, MAX_ESCAPE_LEN};
+pub use driver::{buffered_overruns, SerialDriver};
+pub use events::{NextEvent, SerialEvent, SerialEventBus, SerialEventKind};
+pub use lines::{LineError, Lines, NextLine, ReadUntil};
+pub use mmio::{io_fence, probe, AccessWidth, RegLayout, UartMmio};
+#[cfg(feature = "mock_uart")]
+pub use mock::{poll_once, CountingWaker, MockReport, MockUart};
+pub use nb_io::{spin_up_to, wait_up_to_us, ReadExact, SerialBufRead, WriteAll};
+pub(crate) use panic_dump::dump_on_panic;
+pub use panic_dump::{
+    register_panic_dump, set_panic_port, PanicDump, PanicWriter, PANIC_TRACE_EVENTS,
+};
+use regs::*;
+pub use regs::{SerialConfig, UartRegisters, LSR_ERROR_BITS, MSR_DELTA_BITS};
+pub use ring_buffered::{ByteRing, HeapBufferedSerial, RingBufferedSerial, StaticBufferedSerial};
+pub use rx_ring::{RingPages, RxRing};
+pub use rx_tuner::{RxTriggerTuner, RxTuning};
+pub use select::{select_readable, SelectReadable};
+pub use soft_flow::{SoftFlow, SoftFlowStats, XOFF, XON};
+pub use split::{ReadSome, SerialReader, SerialWriter};
+pub use stats_reporter::{StatsRates, StatsReporter};
+pub use stdio::{
+    redirect_stdio, restore_stdio, stdio_dropped, stdio_interrupt, StdioMode, STDIO_LINE_SIZE,
+};
+pub(crate) use stdio::{stdio_read, stdio_write};
+pub use terminal::SerialTerminal;
+pub use throttle::Throttle;
 
 pub struct AsyncUnbufferedSerial {
-    base_address: usize,
+    mmio: UartMmio,
     pub intr_count: AtomicUsize,
     pub rx_intr_count: AtomicUsize,
     pub tx_intr_count: AtomicUsize,
@@ -1132,13 +834,18 @@ pub struct AsyncUnbufferedSerial {
 }
 
 impl AsyncUnbufferedSerial {
-    pub fn new(base_address: usize) -> Self {
+    /// For bring-up without a claim, `FromClaim` is the checked way.
+    ///
+    /// # Safety
+    ///
+    /// As for `UartMmio::new`.
+    pub unsafe fn new(base_address: usize) -> Self {
         let tx_fifo_count = Arc::new(AtomicIsize::new(0));
         let tx_count = Arc::new(AtomicUsize::new(0));
         let rx_count = Arc::new(AtomicUsize::new(0));
         let prev_cts = Arc::new(AtomicBool::new(true));
         AsyncUnbufferedSerial {
-            base_address,
+            mmio: UartMmio::new(base_address),
             intr_count: AtomicUsize::new(0),
             rx_intr_count: AtomicUsize::new(0),
             tx_intr_count: AtomicUsize::new(0),
@@ -1151,27 +858,27 @@ impl AsyncUnbufferedSerial {
             rx_count: rx_count.clone(),
             tx_fifo_count: tx_fifo_count.clone(),
             sender: Mutex::new(UnbufferedSerialSender {
-                base_address,
+                mmio: UartMmio::new(base_address),
                 tx_count: tx_count.clone(),
                 tx_fifo_count: tx_fifo_count.clone(),
                 prev_cts: prev_cts.clone(),
             }),
             receiver: Mutex::new(UnbufferedSerialReceiver {
-                base_address,
+                mmio: UartMmio::new(base_address),
                 rx_count: rx_count.clone(),
                 rx_fifo_count: AtomicUsize::new(0),
             }),
         }
     }
 
-    fn hardware(&self) -> &uart::RegisterBlock {
-        unsafe { &*(self.base_address as *const _) }
+    fn hardware(&self) -> &UartMmio {
+        &self.mmio
     }
 
     fn set_divisor(&self, clock: usize, baud_rate: usize) {
         let block = self.hardware();
         let divisor = clock / (16 * baud_rate);
-        block.lcr.write(|w| w.dlab().set_bit());
+        block.lcr.modify(|_, w| w.dlab().set_bit());
         #[cfg(feature = "board_lrv")]
         {
             block
@@ -1191,31 +898,31 @@ impl AsyncUnbufferedSerial {
                 .write(|w| unsafe { w.bits(((divisor >> 8) & 0b1111_1111) as u8) });
         }
 
-        block.lcr.write(|w| w.dlab().clear_bit());
+        block.lcr.modify(|_, w| w.dlab().clear_bit());
     }
 
     #[inline]
     fn addr_no(&self) -> usize {
-        ((self.base_address >> 12) & 0xFF) + 3
+        ((self.mmio.base_address() >> 12) & 0xFF) + 3
     }
 
     pub(super) fn enable_rdai(&self) {
-        self.hardware().ier().modify(|_, w| w.erbfi().set_bit());
+        self.hardware().set_rx_interrupt(true);
         self.rx_intr_enabled.store(true, Relaxed);
     }
 
     fn disable_rdai(&self) {
-        self.hardware().ier().modify(|_, w| w.erbfi().clear_bit());
+        self.hardware().set_rx_interrupt(false);
         self.rx_intr_enabled.store(false, Relaxed);
     }
 
     pub(super) fn enable_threi(&self) {
-        self.hardware().ier().modify(|_, w| w.etbei().set_bit());
+        self.hardware().set_tx_interrupt(true);
         self.tx_intr_enabled.store(true, Relaxed);
     }
 
     fn disable_threi(&self) {
-        self.hardware().ier().modify(|_, w| w.etbei().clear_bit());
+        self.hardware().set_tx_interrupt(false);
         self.tx_intr_enabled.store(false, Relaxed);
     }
 
@@ -1331,18 +1038,18 @@ impl AsyncUnbufferedSerial {
                     // if lsr.bi().bit_is_set() {
                     if lsr.fifoerr().is_error() {
                         if lsr.bi().bit_is_set() {
-                            println!("[uart] lsr.BI!");
+                            serial_warn!(self.mmio.base_address(), "[uart] lsr.BI!");
                         }
                         if lsr.fe().bit_is_set() {
-                            println!("[uart] lsr.FE!");
+                            serial_warn!(self.mmio.base_address(), "[uart] lsr.FE!");
                         }
                         if lsr.pe().bit_is_set() {
-                            println!("[uart] lsr.PE!");
+                            serial_warn!(self.mmio.base_address(), "[uart] lsr.PE!");
                         }
                     }
                     if lsr.oe().bit_is_set() {
                         block.mcr.modify(|_, w| w.rts().deasserted());
-                        println!("[uart] lsr.OE!");
+                        serial_warn!(self.mmio.base_address(), "[uart] lsr.OE!");
                     }
                 }
                 IID_A::MODEM_STATUS => {
@@ -1362,7 +1069,8 @@ impl AsyncUnbufferedSerial {
                         self.start_tx();
                     } else {
                         let block = self.hardware();
-                        println!(
+                        serial_warn!(
+                            self.mmio.base_address(),
                             "[USER SERIAL] EDSSI, MSR: {:#x}, LSR: {:#x}, IER: {:#x}",
                             block.msr.read().bits(),
                             block.lsr.read().bits(),
@@ -1371,7 +1079,10 @@ impl AsyncUnbufferedSerial {
                     }
                 }
                 _ => {
-                    println!("[USER SERIAL] {:?} not supported!", int_type);
+                    // no print, a stuck IIR would flood the console from here
+                    push_trace(SERIAL_INTR_EXIT + intr_id);
+                    push_trace(SERIAL_INTR_BAILOUT | SERIAL_INTR_BAILOUT_UNKNOWN | intr_id);
+                    return;
                 }
             }
             push_trace(SERIAL_INTR_EXIT + intr_id);
@@ -1380,8 +1091,11 @@ impl AsyncUnbufferedSerial {
 
     #[inline]
     pub async fn register_write(&self) {
-        let raw_waker = GetWakerFuture.await;
-        self.write_waker.lock().replace(raw_waker);
+        let waker = GetWakerFuture.await;
+        let mut slot = self.write_waker.lock();
+        if !matches!(slot.as_ref(), Some(old) if old.will_wake(&waker)) {
+            slot.replace(waker);
+        }
     }
 
     #[inline]
@@ -1391,8 +1105,11 @@ impl AsyncUnbufferedSerial {
 
     #[inline]
     pub async fn register_read(&self) {
-        let raw_waker = GetWakerFuture.await;
-        self.read_waker.lock().replace(raw_waker);
+        let waker = GetWakerFuture.await;
+        let mut slot = self.read_waker.lock();
+        if !matches!(slot.as_ref(), Some(old) if old.will_wake(&waker)) {
+            slot.replace(waker);
+        }
     }
 
     #[inline]
@@ -1434,15 +1151,15 @@ impl Drop for AsyncUnbufferedSerial {
 }
 
 pub struct UnbufferedSerialReceiver {
-    base_address: usize,
+    mmio: UartMmio,
     rx_count: Arc<AtomicUsize>,
     rx_fifo_count: AtomicUsize,
 }
 
 impl UnbufferedSerialReceiver {
     #[inline]
-    fn hardware(&self) -> &uart::RegisterBlock {
-        unsafe { &*(self.base_address as *const _) }
+    fn hardware(&self) -> &UartMmio {
+        &self.mmio
     }
 
     #[inline]
@@ -1459,7 +1176,7 @@ impl UnbufferedSerialReceiver {
 
     #[inline]
     pub(super) fn enable_rdai(&self) {
-        self.hardware().ier().modify(|_, w| w.erbfi().set_bit());
+        self.hardware().set_rx_interrupt(true);
     }
 
     #[inline]
@@ -1497,7 +1214,7 @@ impl Stream for UnbufferedSerialReceiver {
 }
 
 pub struct UnbufferedSerialSender {
-    base_address: usize,
+    mmio: UartMmio,
     tx_count: Arc<AtomicUsize>,
     tx_fifo_count: Arc<AtomicIsize>,
     prev_cts: Arc<AtomicBool>,
@@ -1505,8 +1222,8 @@ pub struct UnbufferedSerialSender {
 
 impl UnbufferedSerialSender {
     #[inline]
-    fn hardware(&self) -> &uart::RegisterBlock {
-        unsafe { &*(self.base_address as *const _) }
+    fn hardware(&self) -> &UartMmio {
+        &self.mmio
     }
 
     #[inline]
@@ -1518,7 +1235,7 @@ impl UnbufferedSerialSender {
 
     #[inline]
     fn disable_threi(&self) {
-        self.hardware().ier().modify(|_, w| w.etbei().clear_bit());
+        self.hardware().set_tx_interrupt(false);
     }
 
     #[inline]
@@ -1574,3 +1291,38 @@ impl Sink<u8> for UnbufferedSerialSender {
         Poll::Ready(Ok(()))
     }
 }
+
+/// The `main` of a test bin on a `MockUart`: `$run` with the `mock_uart`
+/// feature, a note that the test was skipped without it. With `argv`,
+/// `$run` is given the arguments.
+#[macro_export]
+macro_rules! mock_uart_main {
+    ($name:literal, $run:path) => {
+        #[cfg(not(feature = "mock_uart"))]
+        #[no_mangle]
+        pub fn main() -> i32 {
+            $crate::println!("[{}] built without the mock_uart feature, skipped", $name);
+            0
+        }
+
+        #[cfg(feature = "mock_uart")]
+        #[no_mangle]
+        pub fn main() -> i32 {
+            $run()
+        }
+    };
+    ($name:literal, $run:path, argv) => {
+        #[cfg(not(feature = "mock_uart"))]
+        #[no_mangle]
+        pub fn main(_argc: usize, _argv: &[&str]) -> i32 {
+            $crate::println!("[{}] built without the mock_uart feature, skipped", $name);
+            0
+        }
+
+        #[cfg(feature = "mock_uart")]
+        #[no_mangle]
+        pub fn main(_argc: usize, argv: &[&str]) -> i32 {
+            $run(argv)
+        }
+    };
+}
@@ -0,0 +1,110 @@
+use super::{BufferedSerial, RingBufferedSerial, UartRegisters};
+use crate::timer::now_us;
+use embedded_hal::serial::{Read, Write};
+
+/// `write_all` for the `nb` drivers, `BufferedSerial` and
+/// `PollingSerial`, instead of a `WouldBlock` loop at every caller.
+pub trait WriteAll: Write<u8> {
+    /// Writes `buf` a byte at a time. When the driver is full, `on_full`
+    /// is called to yield, poll or time out, and returns whether to try
+    /// again. Stops at the first other error. Returns the bytes accepted,
+    /// `buf.len()` unless `on_full` gave up.
+    fn write_all(&mut self, buf: &[u8], mut on_full: impl FnMut() -> bool) -> usize {
+        let mut len = 0;
+        while len < buf.len() {
+            match self.try_write(buf[len]) {
+                Ok(()) => len += 1,
+                Err(nb::Error::WouldBlock) if on_full() => {}
+                Err(_) => break,
+            }
+        }
+        len
+    }
+}
+
+impl<T: Write<u8> + ?Sized> WriteAll for T {}
+
+/// `read_exact` for the `nb` drivers, see `WriteAll`.
+pub trait ReadExact: Read<u8> {
+    /// Fills `buf` a byte at a time, calling `on_empty` whenever nothing
+    /// was received, like `on_full` of `write_all`. Returns the bytes read.
+    fn read_exact(&mut self, buf: &mut [u8], mut on_empty: impl FnMut() -> bool) -> usize {
+        let mut len = 0;
+        while len < buf.len() {
+            match self.try_read() {
+                Ok(ch) => {
+                    buf[len] = ch;
+                    len += 1;
+                }
+                Err(nb::Error::WouldBlock) if on_empty() => {}
+                Err(_) => break,
+            }
+        }
+        len
+    }
+}
+
+impl<T: Read<u8> + ?Sized> ReadExact for T {}
+
+/// The `fill_buf` and `consume` of `std::io::BufRead` for the `nb`
+/// drivers that keep an rx buffer, so parsers can work on the received
+/// bytes in place. `AsyncSerial::fill_buf` is the async one.
+pub trait SerialBufRead {
+    /// The longest contiguous run of received bytes, empty if there are
+    /// none. Nothing is taken until `consume`.
+    fn buffered_slice(&mut self) -> &[u8];
+
+    /// Takes the first `len` received bytes.
+    fn consume(&mut self, len: usize);
+}
+
+impl SerialBufRead for BufferedSerial {
+    fn buffered_slice(&mut self) -> &[u8] {
+        // where the buffer wraps, the rest comes after a consume
+        self.rx_buffer.as_slices().0
+    }
+
+    fn consume(&mut self, len: usize) {
+        let len = len.min(self.rx_buffer.len());
+        self.rx_buffer.drain(..len);
+        if self.rx_buffer.is_empty() {
+            // turns rx interrupts back on if a full buffer stopped them
+            let _ = self.try_read();
+        }
+    }
+}
+
+impl<RX, TX, R> SerialBufRead for RingBufferedSerial<RX, TX, R>
+where
+    RX: AsRef<[u8]> + AsMut<[u8]>,
+    TX: AsRef<[u8]> + AsMut<[u8]>,
+    R: UartRegisters,
+{
+    fn buffered_slice(&mut self) -> &[u8] {
+        self.rx_slice()
+    }
+
+    fn consume(&mut self, len: usize) {
+        RingBufferedSerial::consume(self, len);
+    }
+}
+
+/// An `on_full` or `on_empty` that spins up to `spins` times over the
+/// whole call, then gives up.
+pub fn spin_up_to(mut spins: usize) -> impl FnMut() -> bool {
+    move || {
+        if spins == 0 {
+            return false;
+        }
+        spins -= 1;
+        core::hint::spin_loop();
+        true
+    }
+}
+
+/// An `on_full` or `on_empty` that keeps waiting for `timeout_us` from
+/// when it is made.
+pub fn wait_up_to_us(timeout_us: usize) -> impl FnMut() -> bool {
+    let start = now_us();
+    move || now_us().wrapping_sub(start) < timeout_us
+}
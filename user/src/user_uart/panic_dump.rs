@@ -0,0 +1,138 @@
+use super::regs::{UartRegisters, LSR_THRE};
+use super::*;
+use crate::syscall::sys_write;
+use crate::timer::now_us;
+use crate::trace::{last_trace_events, TraceEvent};
+use crate::uintr::critical_section;
+use alloc::vec::Vec;
+use core::fmt::{self, Write as FmtWrite};
+
+/// Trace events printed after the driver dumps, only recorded with tracing.
+pub const PANIC_TRACE_EVENTS: usize = 16;
+/// How long the panic writer waits for THRE before it drops a byte.
+const PANIC_TX_TIMEOUT_US: usize = 2_000;
+
+/// State a driver prints when the process panics.
+///
+/// The panic may have hit in the middle of one of the driver's own calls,
+/// or in its interrupt handler. `panic_dump` must not wait on any of its
+/// locks, use `try_lock` and print what it could not get as unknown.
+pub trait PanicDump: Send + Sync {
+    fn panic_dump(&self, out: &mut dyn FmtWrite) -> fmt::Result;
+}
+
+/// Only locked with user interrupts masked. The panic handler only tries.
+static PANIC_DUMPS: Mutex<Vec<Weak<dyn PanicDump>>> = Mutex::new(Vec::new());
+/// Base address of the port the panic output goes to, 0 for the kernel
+/// console.
+static PANIC_PORT: AtomicUsize = AtomicUsize::new(0);
+/// Its register layout, the shift in the low byte and the width above.
+static PANIC_LAYOUT: AtomicUsize = AtomicUsize::new(0);
+
+/// Dumps `driver` on panic for as long as it lives. Registering it twice
+/// is harmless. `AsyncSerial` registers itself on its first read or write.
+pub fn register_panic_dump<D: PanicDump + 'static>(driver: &Arc<D>) {
+    let driver: Weak<dyn PanicDump> = Arc::downgrade(driver) as _;
+    critical_section(|| {
+        let mut dumps = PANIC_DUMPS.lock();
+        dumps.retain(|dump| dump.strong_count() > 0);
+        if !dumps.iter().any(|dump| dump.ptr_eq(&driver)) {
+            dumps.push(driver);
+        }
+    });
+}
+
+/// Sends the panic output over `port` instead of the kernel console, by
+/// polling its registers. The port has to be claimed and set up. Use one
+/// the failing driver does not run on, or its state may be what keeps the
+/// output from getting out. Dropping or transferring the claim switches
+/// back to the console.
+pub fn set_panic_port(port: Option<UartMmio>) {
+    if let Some(mmio) = port {
+        let layout = mmio.layout();
+        PANIC_LAYOUT.store(layout.reg_shift as usize | layout.reg_width() << 8, Relaxed);
+    }
+    PANIC_PORT.store(port.map_or(0, |mmio| mmio.base_address()), Relaxed);
+}
+
+/// Called before the port at `base_address` is unmapped.
+pub(super) fn panic_port_released(base_address: usize) {
+    let _ = PANIC_PORT.compare_exchange(base_address, 0, Relaxed, Relaxed);
+}
+
+/// Writes to the panic port a byte at a time, waiting for THRE, and never
+/// touches IER or a driver queue. Goes to the kernel console through a
+/// plain `write` syscall if no port is set, bypassing redirected stdio.
+pub struct PanicWriter {
+    port: Option<UartMmio>,
+}
+
+impl PanicWriter {
+    pub fn new() -> Self {
+        let base_address = PANIC_PORT.load(Relaxed);
+        let layout = PANIC_LAYOUT.load(Relaxed);
+        let layout = RegLayout::from_raw(layout & 0xff, layout >> 8).unwrap_or(RegLayout::BOARD);
+        PanicWriter {
+            // `set_panic_port` took it from a claim, and the claim going
+            // away clears it
+            port: (base_address != 0)
+                .then(|| unsafe { UartMmio::with_layout(base_address, layout) }),
+        }
+    }
+}
+
+impl FmtWrite for PanicWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mmio = match self.port {
+            Some(mmio) => mmio,
+            None => {
+                sys_write(1, s.as_bytes());
+                return Ok(());
+            }
+        };
+        for &ch in s.as_bytes() {
+            let deadline = now_us() + PANIC_TX_TIMEOUT_US;
+            while mmio.read_lsr() & LSR_THRE == 0 {
+                if now_us() >= deadline {
+                    return Err(fmt::Error);
+                }
+            }
+            mmio.write_thr(ch);
+        }
+        Ok(())
+    }
+}
+
+/// Prints every registered driver and the last trace events to `out`.
+/// Called by the panic handler with user interrupts masked.
+pub(crate) fn dump_on_panic(out: &mut dyn FmtWrite) -> fmt::Result {
+    // a panic while registering holds the lock, dump nothing then
+    let drivers: Vec<Arc<dyn PanicDump>> = match PANIC_DUMPS.try_lock() {
+        Some(dumps) => dumps.iter().filter_map(|dump| dump.upgrade()).collect(),
+        None => Vec::new(),
+    };
+    for driver in drivers {
+        driver.panic_dump(out)?;
+    }
+    let mut events = [TraceEvent::default(); PANIC_TRACE_EVENTS];
+    let len = last_trace_events(&mut events);
+    if len > 0 {
+        writeln!(out, "[panic] last {} trace events:", len)?;
+        for event in &events[..len] {
+            writeln!(out, "[panic]   {:#012x} at {}", event.event_id, event.cycle)?;
+        }
+    }
+    Ok(())
+}
+
+/// Formats a queue length that could not be read as `?`.
+pub(super) struct QueueLen(pub Option<usize>);
+
+impl fmt::Display for QueueLen {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(len) => write!(f, "{}", len),
+            None => write!(f, "?"),
+        }
+    }
+}
@@ -0,0 +1,70 @@
+// 16550 register bits. The kernel driver keeps its own copy of the few
+// it needs in os/src/uart/reg_bits.rs.
+
+pub const IER_ERBFI: u8 = 1 << 0;
+pub const IER_ETBEI: u8 = 1 << 1;
+pub const IER_ELSI: u8 = 1 << 2;
+pub const IER_EDSSI: u8 = 1 << 3;
+
+pub const IIR_IID_MASK: u8 = 0b1111;
+pub const IID_MODEM_STATUS: u8 = 0b0000;
+pub const IID_NO_INTERRUPT: u8 = 0b0001;
+pub const IID_THR_EMPTY: u8 = 0b0010;
+pub const IID_RX_DATA: u8 = 0b0100;
+pub const IID_LINE_STATUS: u8 = 0b0110;
+pub const IID_CHAR_TIMEOUT: u8 = 0b1100;
+pub const IIR_FIFO_ENABLED: u8 = 0b1100_0000;
+/// 16750 FIFOs are 64 bytes deep.
+pub const IIR_FIFO64: u8 = 1 << 5;
+
+pub const FCR_FIFO_ENABLE: u8 = 1 << 0;
+pub const FCR_RX_RESET: u8 = 1 << 1;
+pub const FCR_TX_RESET: u8 = 1 << 2;
+/// 16750 only, and only written with LCR.DLAB set: 64 byte FIFOs. The
+/// rx trigger bits then select 1, 16, 32 or 56 bytes.
+pub const FCR_FIFO64: u8 = 1 << 5;
+pub const FCR_RX_TRIGGER_1: u8 = 0b00 << 6;
+pub const FCR_RX_TRIGGER_4: u8 = 0b01 << 6;
+pub const FCR_RX_TRIGGER_8: u8 = 0b10 << 6;
+/// Rx interrupt when the FIFO is two bytes short of full.
+pub const FCR_RX_TRIGGER_14: u8 = 0b11 << 6;
+
+/// FIFO depth of a 16750 with `FCR_FIFO64` set.
+pub const DEEP_FIFO_DEPTH: usize = 64;
+
+pub const LCR_8N1: u8 = 0b11;
+pub const LCR_DLAB: u8 = 1 << 7;
+
+pub const MCR_DTR: u8 = 1 << 0;
+pub const MCR_RTS: u8 = 1 << 1;
+
+pub const LSR_DR: u8 = 1 << 0;
+pub const LSR_OE: u8 = 1 << 1;
+pub const LSR_PE: u8 = 1 << 2;
+pub const LSR_FE: u8 = 1 << 3;
+pub const LSR_BI: u8 = 1 << 4;
+pub const LSR_THRE: u8 = 1 << 5;
+pub const LSR_TEMT: u8 = 1 << 6;
+pub const LSR_FIFO_ERROR: u8 = 1 << 7;
+/// OE, PE, FE, BI and the rx FIFO error bit.
+pub const LSR_ERROR_BITS: u8 = LSR_OE | LSR_PE | LSR_FE | LSR_BI | LSR_FIFO_ERROR;
+
+pub const MSR_DCTS: u8 = 1 << 0;
+pub const MSR_DDSR: u8 = 1 << 1;
+pub const MSR_TERI: u8 = 1 << 2;
+pub const MSR_DDCD: u8 = 1 << 3;
+pub const MSR_CTS: u8 = 1 << 4;
+pub const MSR_DSR: u8 = 1 << 5;
+pub const MSR_DCD: u8 = 1 << 7;
+/// DCTS, DDSR, TERI and DDCD.
+pub const MSR_DELTA_BITS: u8 = MSR_DCTS | MSR_DDSR | MSR_TERI | MSR_DDCD;
+
+/// `value` with `bits` set if `set`, cleared otherwise.
+#[inline]
+pub fn with_bits(value: u8, bits: u8, set: bool) -> u8 {
+    if set {
+        value | bits
+    } else {
+        value & !bits
+    }
+}
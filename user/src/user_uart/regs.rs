@@ -0,0 +1,181 @@
+use super::mmio::io_fence;
+use super::FIFO_DEPTH;
+use crate::timer::now_us;
+
+pub use super::reg_bits::*;
+
+/// IIR reads `UartRegisters::reset_fifos` spends on what a FIFO reset
+/// left there.
+const STALE_IIR_READS: usize = 4;
+
+/// The register accesses a 16550 driver makes, as raw register values.
+/// `UartMmio` implements it with the PAC register block, `MockUart` with
+/// scripted traffic, so a driver generic over it runs on either. Copied
+/// into every part of a driver that touches the port.
+pub trait UartRegisters: Copy + Send + Sync + 'static {
+    /// Identifies the port, the physical base of a real one.
+    fn base_address(&self) -> usize;
+    /// Reads IIR, which clears a pending THR empty interrupt.
+    fn read_iir(&self) -> u8;
+    /// Reads LSR, which clears its error bits.
+    fn read_lsr(&self) -> u8;
+    /// Takes the received byte in RBR.
+    fn read_rbr(&self) -> u8;
+    fn write_thr(&self, ch: u8);
+    /// Reads MSR, which clears its delta bits.
+    fn read_msr(&self) -> u8;
+    fn read_ier(&self) -> u8;
+    fn write_ier(&self, ier: u8);
+    fn read_lcr(&self) -> u8;
+    fn write_lcr(&self, lcr: u8);
+    fn read_mcr(&self) -> u8;
+    fn write_mcr(&self, mcr: u8);
+    /// FCR can't be read back, IIR is at its offset.
+    fn write_fcr(&self, fcr: u8);
+    /// Sets LCR.DLAB, writes the divisor latch and restores LCR.
+    fn write_divisor(&self, divisor: u16);
+    /// Sets LCR.DLAB, reads the divisor latch and restores LCR.
+    fn read_divisor(&self) -> u16;
+
+    /// Bytes waiting in the tx FIFO, for a UART with a level register. The
+    /// 16550 has none.
+    fn read_tx_fifo_level(&self) -> Option<usize> {
+        None
+    }
+
+    /// How many bytes THR takes now without overrunning a `fifo_depth`
+    /// tx FIFO, `lsr` read just before. Without a level register, all of
+    /// it only once LSR.THRE says the FIFO is empty, else a single byte,
+    /// which only a completely full FIFO drops: a THR empty handler also
+    /// runs on other interrupts and when polled, with the FIFO part full.
+    fn tx_fifo_room(&self, lsr: u8, fifo_depth: usize) -> usize {
+        match self.read_tx_fifo_level() {
+            Some(level) => fifo_depth.saturating_sub(level),
+            None if lsr & LSR_THRE != 0 => fifo_depth,
+            None => 1,
+        }
+    }
+
+    /// Enables and resets the FIFOs, 64 bytes deep if the UART has them,
+    /// and returns the depth IIR reports: `DEEP_FIFO_DEPTH` or
+    /// `FIFO_DEPTH`. Reading IIR clears a pending THR empty interrupt.
+    fn detect_fifo_depth(&self) -> usize {
+        let lcr = self.read_lcr();
+        self.write_lcr(lcr | LCR_DLAB);
+        self.write_fcr(FCR_FIFO_ENABLE | FCR_FIFO64);
+        self.write_lcr(lcr);
+        // FCR_FIFO64 is only written with DLAB set, the reset keeps it
+        self.reset_fifos(FCR_FIFO_ENABLE, FCR_RX_RESET | FCR_TX_RESET);
+        let iir = self.read_iir();
+        if iir & IIR_FIFO_ENABLED == IIR_FIFO_ENABLED && iir & IIR_FIFO64 != 0 {
+            DEEP_FIFO_DEPTH
+        } else {
+            FIFO_DEPTH
+        }
+    }
+
+    /// What clock gating loses. `fcr` is what the driver last wrote, with
+    /// `FCR_FIFO64` for 64 byte FIFOs, since FCR can't be read back.
+    fn save_config(&self, fcr: u8) -> SerialConfig {
+        SerialConfig {
+            divisor: self.read_divisor(),
+            lcr: self.read_lcr() & !LCR_DLAB,
+            ier: self.read_ier(),
+            fcr: fcr & !(FCR_RX_RESET | FCR_TX_RESET),
+            mcr: self.read_mcr(),
+        }
+    }
+
+    /// Programs `config` back, all but IER, which stays 0 for the driver
+    /// to set. The FIFOs start empty.
+    fn restore_config(&self, config: &SerialConfig) {
+        self.write_lcr(config.lcr);
+        self.write_ier(0);
+        self.write_divisor(config.divisor);
+        // FCR_FIFO64 only sticks with DLAB set
+        self.write_lcr(config.lcr | LCR_DLAB);
+        self.write_fcr(config.fcr);
+        self.write_lcr(config.lcr);
+        self.reset_fifos(config.fcr, FCR_RX_RESET | FCR_TX_RESET);
+        self.write_mcr(config.mcr);
+    }
+
+    /// Empties the rx FIFO, see `reset_fifos`.
+    fn reset_rx_fifo(&self, fcr: u8) {
+        self.reset_fifos(fcr, FCR_RX_RESET);
+    }
+
+    /// Empties the tx FIFO, see `reset_fifos`.
+    fn reset_tx_fifo(&self, fcr: u8) {
+        self.reset_fifos(fcr, FCR_TX_RESET);
+    }
+
+    /// Pulses the FCR reset bits in `reset` with IER off, reads IIR until
+    /// what the pulse left there is gone, and puts IER back. A reset with
+    /// RDAI or THREI armed raises a spurious interrupt, and the LRV part
+    /// shows bogus IIDs for a few reads after one. All of IER goes off, so
+    /// the reads can't take a real interrupt of the other sources; those
+    /// raise again once IER is back. `fcr` is what the driver last wrote,
+    /// FCR can't be read back.
+    fn reset_fifos(&self, fcr: u8, reset: u8) {
+        let ier = self.read_ier();
+        io_fence();
+        self.write_ier(0);
+        self.write_fcr(fcr & !(FCR_RX_RESET | FCR_TX_RESET) | reset);
+        for _ in 0..STALE_IIR_READS {
+            if self.read_iir() & IIR_IID_MASK == IID_NO_INTERRUPT {
+                break;
+            }
+        }
+        self.write_ier(ier);
+        io_fence();
+    }
+
+    /// Waits up to `timeout_us` for the transmitter to be idle, LSR.TEMT.
+    fn wait_tx_idle(&self, timeout_us: usize) -> bool {
+        let start = now_us();
+        while self.read_lsr() & LSR_TEMT == 0 {
+            if now_us().saturating_sub(start) >= timeout_us {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn modify_ier(&self, f: impl FnOnce(u8) -> u8) {
+        self.write_ier(f(self.read_ier()));
+    }
+
+    fn modify_mcr(&self, f: impl FnOnce(u8) -> u8) {
+        self.write_mcr(f(self.read_mcr()));
+    }
+
+    /// Sets or clears IER.ERBFI between two fences. Queue and waker updates
+    /// made before are seen by the handler the write may trigger, and the
+    /// queue is only checked again after it.
+    #[inline]
+    fn set_rx_interrupt(&self, enable: bool) {
+        io_fence();
+        self.modify_ier(|ier| with_bits(ier, IER_ERBFI, enable));
+        io_fence();
+    }
+
+    /// Sets or clears IER.ETBEI, fenced like `set_rx_interrupt`.
+    #[inline]
+    fn set_tx_interrupt(&self, enable: bool) {
+        io_fence();
+        self.modify_ier(|ier| with_bits(ier, IER_ETBEI, enable));
+        io_fence();
+    }
+}
+
+/// Registers of a port as `UartRegisters::save_config` found them, for
+/// `restore_config` after the UART lost its state, e.g. to clock gating.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SerialConfig {
+    pub divisor: u16,
+    pub lcr: u8,
+    pub ier: u8,
+    pub fcr: u8,
+    pub mcr: u8,
+}
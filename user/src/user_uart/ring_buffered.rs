@@ -0,0 +1,351 @@
+use super::regs::*;
+use super::{
+    record_drop, CloseReport, UartMmio, UartRegisters, DEFAULT_RX_BUFFER_SIZE,
+    DEFAULT_TX_BUFFER_SIZE, FIFO_DEPTH,
+};
+use crate::trace::{push_trace, SERIAL_RX, SERIAL_TX};
+use alloc::boxed::Box;
+use alloc::vec;
+use core::convert::Infallible;
+use embedded_hal::serial::{Read, Write};
+
+/// IIR reads per `interrupt_handler` call, as for `AsyncSerial`.
+const MAX_INTR_ROUNDS: usize = 8;
+
+/// A byte FIFO on fixed storage: an inline array, a caller's
+/// `&'static mut [u8]` or a boxed slice. Never allocates or grows.
+pub struct ByteRing<S> {
+    storage: S,
+    start: usize,
+    len: usize,
+}
+
+impl<S: AsRef<[u8]> + AsMut<[u8]>> ByteRing<S> {
+    pub const fn new(storage: S) -> Self {
+        ByteRing {
+            storage,
+            start: 0,
+            len: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.storage.as_ref().len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == self.capacity()
+    }
+
+    /// False if the ring is full.
+    pub fn push_back(&mut self, ch: u8) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        let at = (self.start + self.len) % self.capacity();
+        self.storage.as_mut()[at] = ch;
+        self.len += 1;
+        true
+    }
+
+    pub fn pop_front(&mut self) -> Option<u8> {
+        let ch = *self.front_slice().first()?;
+        self.consume(1);
+        Some(ch)
+    }
+
+    /// The bytes from the front up to where the storage wraps.
+    pub fn front_slice(&self) -> &[u8] {
+        let end = (self.start + self.len).min(self.capacity());
+        &self.storage.as_ref()[self.start..end]
+    }
+
+    /// Drops the first `len` bytes, or all there are.
+    pub fn consume(&mut self, len: usize) {
+        let len = len.min(self.len);
+        self.len -= len;
+        self.start = if self.len == 0 {
+            0
+        } else {
+            (self.start + len) % self.capacity()
+        };
+    }
+}
+
+/// The interrupt driven `BufferedSerial` on storage of the caller's
+/// choice, see `ByteRing`. `StaticBufferedSerial` keeps its buffers
+/// inline, for a process without a heap; `HeapBufferedSerial` boxes them.
+/// Both run the same code.
+///
+/// A full rx buffer turns the rx interrupt off instead of dropping bytes,
+/// the next `try_read` that finds it empty turns it back on.
+///
+/// Drop flushes nothing, what is still queued is reported lost, see
+/// `last_drop_report`.
+pub struct RingBufferedSerial<RX, TX, R: UartRegisters = UartMmio> {
+    regs: R,
+    rx_buffer: ByteRing<RX>,
+    tx_buffer: ByteRing<TX>,
+    pub rx_count: usize,
+    pub tx_count: usize,
+    pub intr_count: usize,
+    pub rx_intr_count: usize,
+    pub tx_intr_count: usize,
+    fifo_depth: usize,
+    rx_intr_enabled: bool,
+    tx_intr_enabled: bool,
+}
+
+/// Buffers of `RX` and `TX` bytes inside the driver.
+pub type StaticBufferedSerial<const RX: usize, const TX: usize, R = UartMmio> =
+    RingBufferedSerial<[u8; RX], [u8; TX], R>;
+
+/// Buffers of the default sizes on the heap.
+pub type HeapBufferedSerial<R = UartMmio> = RingBufferedSerial<Box<[u8]>, Box<[u8]>, R>;
+
+impl<RX, TX, R> RingBufferedSerial<RX, TX, R>
+where
+    RX: AsRef<[u8]> + AsMut<[u8]>,
+    TX: AsRef<[u8]> + AsMut<[u8]>,
+    R: UartRegisters,
+{
+    pub fn with_storage(regs: R, rx: RX, tx: TX) -> Self {
+        RingBufferedSerial {
+            regs,
+            rx_buffer: ByteRing::new(rx),
+            tx_buffer: ByteRing::new(tx),
+            rx_count: 0,
+            tx_count: 0,
+            intr_count: 0,
+            rx_intr_count: 0,
+            tx_intr_count: 0,
+            fifo_depth: FIFO_DEPTH,
+            rx_intr_enabled: false,
+            tx_intr_enabled: false,
+        }
+    }
+
+    fn hardware(&self) -> &R {
+        &self.regs
+    }
+
+    pub fn hardware_init(&mut self, baud_rate: usize) {
+        let block = self.hardware();
+        let _unused = block.read_msr();
+        let _unused = block.read_lsr();
+        block.write_lcr(0);
+        block.write_mcr(0);
+        block.write_ier(0);
+        block.write_fcr(0);
+        block.write_divisor((100_000_000 / (16 * baud_rate)) as u16);
+        // word length 8 bits, no parity, 1 stop bit
+        block.write_lcr(LCR_8N1);
+        self.fifo_depth = block.detect_fifo_depth();
+        let block = self.hardware();
+        block.write_fcr(FCR_FIFO_ENABLE | FCR_RX_TRIGGER_14);
+        block.modify_mcr(|mcr| mcr | MCR_RTS);
+        block.set_rx_interrupt(true);
+        self.rx_intr_enabled = true;
+        self.tx_intr_enabled = false;
+    }
+
+    pub fn rx_capacity(&self) -> usize {
+        self.rx_buffer.capacity()
+    }
+
+    pub fn tx_capacity(&self) -> usize {
+        self.tx_buffer.capacity()
+    }
+
+    /// Bytes received and not read yet.
+    pub fn rx_len(&self) -> usize {
+        self.rx_buffer.len()
+    }
+
+    /// Bytes queued and not sent yet.
+    pub fn tx_len(&self) -> usize {
+        self.tx_buffer.len()
+    }
+
+    pub fn fifo_depth(&self) -> usize {
+        self.fifo_depth
+    }
+
+    /// Moves received bytes into the rx buffer and queued ones into the
+    /// tx FIFO. Stops at an IID it does not know, the next call goes on.
+    pub fn interrupt_handler(&mut self) {
+        for _ in 0..MAX_INTR_ROUNDS {
+            let int_type = self.hardware().read_iir() & IIR_IID_MASK;
+            if int_type == IID_NO_INTERRUPT {
+                break;
+            }
+            self.intr_count += 1;
+            match int_type {
+                IID_RX_DATA | IID_CHAR_TIMEOUT => {
+                    self.rx_intr_count += 1;
+                    self.receive();
+                }
+                IID_THR_EMPTY => {
+                    self.tx_intr_count += 1;
+                    self.start_tx();
+                }
+                IID_LINE_STATUS => {
+                    let _unused = self.hardware().read_lsr();
+                }
+                IID_MODEM_STATUS => {
+                    let _unused = self.hardware().read_msr();
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn receive(&mut self) {
+        while !self.rx_buffer.is_full() {
+            let block = self.hardware();
+            if block.read_lsr() & LSR_DR == 0 {
+                return;
+            }
+            let ch = block.read_rbr();
+            push_trace(SERIAL_RX | ch as usize);
+            self.rx_buffer.push_back(ch);
+            self.rx_count += 1;
+        }
+        // the rest waits in the FIFO until there is room
+        self.hardware().set_rx_interrupt(false);
+        self.rx_intr_enabled = false;
+    }
+
+    fn start_tx(&mut self) {
+        for _ in 0..self.fifo_depth {
+            let ch = match self.tx_buffer.pop_front() {
+                Some(ch) => ch,
+                None => {
+                    self.hardware().set_tx_interrupt(false);
+                    self.tx_intr_enabled = false;
+                    return;
+                }
+            };
+            push_trace(SERIAL_TX | ch as usize);
+            self.hardware().write_thr(ch);
+            self.tx_count += 1;
+        }
+    }
+
+    /// Moves what was received into `buf`, returns how many bytes.
+    pub fn read_available(&mut self, buf: &mut [u8]) -> usize {
+        let mut len = 0;
+        while len < buf.len() && !self.rx_buffer.is_empty() {
+            let slice = self.rx_buffer.front_slice();
+            let take = slice.len().min(buf.len() - len);
+            buf[len..len + take].copy_from_slice(&slice[..take]);
+            self.rx_buffer.consume(take);
+            len += take;
+        }
+        self.resume_rx();
+        len
+    }
+
+    /// The received bytes from the front up to where the buffer wraps,
+    /// see `SerialBufRead`.
+    pub fn rx_slice(&self) -> &[u8] {
+        self.rx_buffer.front_slice()
+    }
+
+    /// Drops the first `len` received bytes. Turns the rx interrupt back
+    /// on if a full buffer stopped it.
+    pub fn consume(&mut self, len: usize) {
+        self.rx_buffer.consume(len);
+        self.resume_rx();
+    }
+
+    fn resume_rx(&mut self) {
+        if !self.rx_intr_enabled && !self.rx_buffer.is_full() {
+            self.hardware().set_rx_interrupt(true);
+            self.rx_intr_enabled = true;
+        }
+    }
+}
+
+impl<const RX: usize, const TX: usize, R: UartRegisters> StaticBufferedSerial<RX, TX, R> {
+    pub fn with_registers(regs: R) -> Self {
+        Self::with_storage(regs, [0; RX], [0; TX])
+    }
+}
+
+impl<R: UartRegisters> HeapBufferedSerial<R> {
+    pub fn with_registers(regs: R) -> Self {
+        Self::with_storage(
+            regs,
+            vec![0; DEFAULT_RX_BUFFER_SIZE].into_boxed_slice(),
+            vec![0; DEFAULT_TX_BUFFER_SIZE].into_boxed_slice(),
+        )
+    }
+}
+
+impl<RX, TX, R> Write<u8> for RingBufferedSerial<RX, TX, R>
+where
+    RX: AsRef<[u8]> + AsMut<[u8]>,
+    TX: AsRef<[u8]> + AsMut<[u8]>,
+    R: UartRegisters,
+{
+    type Error = Infallible;
+
+    fn try_write(&mut self, word: u8) -> nb::Result<(), Self::Error> {
+        if !self.tx_buffer.push_back(word) {
+            return Err(nb::Error::WouldBlock);
+        }
+        if !self.tx_intr_enabled {
+            self.hardware().set_tx_interrupt(true);
+            self.tx_intr_enabled = true;
+        }
+        Ok(())
+    }
+
+    /// Done once the buffer is empty and the transmitter idle.
+    fn try_flush(&mut self) -> nb::Result<(), Self::Error> {
+        if self.tx_buffer.is_empty() && self.hardware().read_lsr() & LSR_TEMT != 0 {
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
+
+impl<RX, TX, R> Read<u8> for RingBufferedSerial<RX, TX, R>
+where
+    RX: AsRef<[u8]> + AsMut<[u8]>,
+    TX: AsRef<[u8]> + AsMut<[u8]>,
+    R: UartRegisters,
+{
+    type Error = Infallible;
+
+    fn try_read(&mut self) -> nb::Result<u8, Self::Error> {
+        let ch = self.rx_buffer.pop_front();
+        self.resume_rx();
+        ch.ok_or(nb::Error::WouldBlock)
+    }
+}
+
+impl<RX, TX, R: UartRegisters> Drop for RingBufferedSerial<RX, TX, R> {
+    fn drop(&mut self) {
+        let block = &self.regs;
+        record_drop(CloseReport {
+            tx_unsent: self.tx_buffer.len + block.read_tx_fifo_level().unwrap_or(0),
+            rx_unread: self.rx_buffer.len,
+            flush_timed_out: false,
+        });
+        block.write_ier(0);
+        let _unused = block.read_msr();
+        let _unused = block.read_lsr();
+        block.reset_fifos(0, FCR_RX_RESET | FCR_TX_RESET);
+    }
+}
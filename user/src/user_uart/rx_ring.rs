@@ -0,0 +1,171 @@
+use crate::trap::PAGE_SIZE;
+use core::sync::atomic::{
+    AtomicBool, AtomicUsize,
+    Ordering::{Acquire, Relaxed, Release},
+};
+
+/// `N` whole pages for an `RxRing`.
+#[repr(C, align(4096))]
+pub struct RingPages<const N: usize>([[u8; PAGE_SIZE]; N]);
+
+impl<const N: usize> RingPages<N> {
+    pub const fn new() -> Self {
+        RingPages([[0; PAGE_SIZE]; N])
+    }
+}
+
+impl<const N: usize> Default for RingPages<N> {
+    fn default() -> Self {
+        RingPages::new()
+    }
+}
+
+/// Rx storage of an `AsyncSerial` on whole pages, taken with
+/// `AsyncSerial::attach_rx_ring`. The interrupt handler writes bytes in
+/// place and moves `head` once per drain, the reader moves `tail`. Both
+/// run freely and wrap.
+pub struct RxRing {
+    data: *mut u8,
+    capacity: usize,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    /// Set while an `RxChunk` borrows the filled region.
+    chunk_out: AtomicBool,
+}
+
+// the pages are only reached through the indices above
+unsafe impl Send for RxRing {}
+unsafe impl Sync for RxRing {}
+
+impl RxRing {
+    pub fn new<const N: usize>(pages: &'static mut RingPages<N>) -> Self {
+        RxRing {
+            data: pages.0.as_mut_ptr() as *mut u8,
+            capacity: N * PAGE_SIZE,
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            chunk_out: AtomicBool::new(false),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Bytes written and not consumed yet.
+    pub fn len(&self) -> usize {
+        self.head
+            .load(Acquire)
+            .wrapping_sub(self.tail.load(Acquire))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// For the one producer, the interrupt handler.
+    pub(super) fn writer(&self) -> RingWriter<'_> {
+        RingWriter {
+            ring: self,
+            head: self.head.load(Relaxed),
+            tail: self.tail.load(Acquire),
+        }
+    }
+
+    /// The filled bytes from `tail` on, up to the end of the pages. The
+    /// producer leaves them alone until `advance` moves past them.
+    ///
+    /// # Safety
+    ///
+    /// Only one reader may use the region at a time.
+    pub(super) unsafe fn filled(&self) -> &[u8] {
+        let tail = self.tail.load(Relaxed);
+        let start = tail % self.capacity;
+        let len = self
+            .head
+            .load(Acquire)
+            .wrapping_sub(tail)
+            .min(self.capacity - start);
+        core::slice::from_raw_parts(self.data.add(start), len)
+    }
+
+    pub(super) fn advance(&self, len: usize) {
+        let tail = self.tail.load(Relaxed);
+        self.tail.store(tail.wrapping_add(len), Release);
+    }
+
+    /// Takes the filled region for an `RxChunk`, false if one has it.
+    pub(super) fn take_chunk(&self) -> bool {
+        !self.chunk_out.swap(true, Acquire)
+    }
+
+    pub(super) fn return_chunk(&self) {
+        self.chunk_out.store(false, Release);
+    }
+
+    pub(super) fn chunk_out(&self) -> bool {
+        self.chunk_out.load(Acquire)
+    }
+
+    /// Copies out what fits in `buf`, across the wrap. The caller keeps
+    /// other readers out.
+    pub(super) fn read_into(&self, buf: &mut [u8]) -> usize {
+        let mut len = 0;
+        while len < buf.len() {
+            let filled = unsafe { self.filled() };
+            if filled.is_empty() {
+                break;
+            }
+            let take = filled.len().min(buf.len() - len);
+            buf[len..len + take].copy_from_slice(&filled[..take]);
+            self.advance(take);
+            len += take;
+        }
+        len
+    }
+
+    /// Drops what was not consumed, unless a chunk has it. Returns how
+    /// many bytes.
+    pub(super) fn discard(&self) -> usize {
+        if !self.take_chunk() {
+            return 0;
+        }
+        let len = self.len();
+        self.advance(len);
+        self.return_chunk();
+        len
+    }
+}
+
+/// Writes at the head of an `RxRing`, published when dropped.
+pub(super) struct RingWriter<'a> {
+    ring: &'a RxRing,
+    head: usize,
+    /// As the reader had it when the writer was made, the room only grows.
+    tail: usize,
+}
+
+impl RingWriter<'_> {
+    /// The ring holds, counting what is not published yet.
+    pub fn len(&self) -> usize {
+        self.head.wrapping_sub(self.tail)
+    }
+
+    /// False if the ring is full.
+    pub fn push(&mut self, ch: u8) -> bool {
+        if self.len() >= self.ring.capacity {
+            return false;
+        }
+        unsafe {
+            self.ring.data.add(self.head % self.ring.capacity).write(ch);
+        }
+        self.head = self.head.wrapping_add(1);
+        true
+    }
+}
+
+impl Drop for RingWriter<'_> {
+    fn drop(&mut self) {
+        self.ring.head.store(self.head, Release);
+    }
+}
@@ -0,0 +1,98 @@
+use super::{AsyncSerial, FifoTrigger, UartMmio, UartRegisters};
+use crate::timer::now_us;
+use alloc::sync::Arc;
+use core::sync::atomic::Ordering::Relaxed;
+
+/// When `RxTriggerTuner` moves the rx trigger level.
+#[derive(Clone, Copy, Debug)]
+pub struct RxTuning {
+    pub window_us: usize,
+    /// Lower the level when more than this share of the rx interrupts of a
+    /// window, in percent, were character timeouts.
+    pub max_timeout_percent: usize,
+    /// Raise it when there were more rx interrupts per second than this.
+    pub max_intr_rate: usize,
+    /// Windows with fewer rx interrupts are left alone, too few to tell.
+    pub min_intr_count: usize,
+}
+
+impl Default for RxTuning {
+    fn default() -> Self {
+        RxTuning {
+            window_us: 100_000,
+            max_timeout_percent: 50,
+            max_intr_rate: 5_000,
+            min_intr_count: 16,
+        }
+    }
+}
+
+/// Moves the rx trigger level of an `AsyncSerial` to the traffic, one step
+/// per sampling window. Many character timeouts mean bytes come in bursts
+/// shorter than the level and wait for the timeout, so it goes down. Too
+/// many rx interrupts mean the level is low for the rate, so it goes up.
+///
+/// Nothing is tuned unless `tune` is called, e.g. from a task that sleeps
+/// `window_us` between calls.
+pub struct RxTriggerTuner<R: UartRegisters = UartMmio> {
+    serial: Arc<AsyncSerial<R>>,
+    tuning: RxTuning,
+    window_start: usize,
+    data_count: usize,
+    timeout_count: usize,
+}
+
+impl<R: UartRegisters> RxTriggerTuner<R> {
+    pub fn new(serial: Arc<AsyncSerial<R>>, tuning: RxTuning) -> Self {
+        assert!(tuning.window_us > 0 && tuning.max_timeout_percent <= 100);
+        RxTriggerTuner {
+            window_start: now_us(),
+            data_count: serial.rx_intr_count.load(Relaxed),
+            timeout_count: serial.rx_timeout_count.load(Relaxed),
+            serial,
+            tuning,
+        }
+    }
+
+    pub fn serial(&self) -> &Arc<AsyncSerial<R>> {
+        &self.serial
+    }
+
+    /// Closes the sampling window if it is over. Returns the new level if
+    /// it was moved, `None` before the window is over and if it stays.
+    pub fn tune(&mut self) -> Option<FifoTrigger> {
+        let now = now_us();
+        let elapsed = now.saturating_sub(self.window_start);
+        if elapsed < self.tuning.window_us {
+            return None;
+        }
+        let data_count = self.serial.rx_intr_count.load(Relaxed);
+        let timeout_count = self.serial.rx_timeout_count.load(Relaxed);
+        let data = data_count.wrapping_sub(self.data_count);
+        let timeouts = timeout_count.wrapping_sub(self.timeout_count);
+        self.window_start = now;
+        self.data_count = data_count;
+        self.timeout_count = timeout_count;
+
+        let intrs = data + timeouts;
+        if intrs < self.tuning.min_intr_count.max(1) {
+            return None;
+        }
+        let step = if timeouts * 100 > intrs * self.tuning.max_timeout_percent {
+            -1
+        } else if intrs * 1_000_000 / elapsed > self.tuning.max_intr_rate {
+            1
+        } else {
+            return None;
+        };
+        let fifo_depth = self.serial.fifo_depth();
+        let level = self.serial.fifo_trigger().fcr_bits(fifo_depth)? >> 6;
+        let next = level as isize + step;
+        if !(0..4).contains(&next) {
+            return None;
+        }
+        let trigger = FifoTrigger::from_fcr_bits((next as u8) << 6, fifo_depth);
+        self.serial.set_fifo_trigger(trigger).ok()?;
+        Some(trigger)
+    }
+}
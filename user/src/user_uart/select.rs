@@ -0,0 +1,50 @@
+use super::{AsyncSerial, Readiness, UartMmio, UartRegisters};
+use alloc::{sync::Arc, vec::Vec};
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use core::task::{Context, Poll};
+
+/// Where the next `select_readable` starts looking, one past the port the
+/// last one picked.
+static NEXT_START: AtomicUsize = AtomicUsize::new(0);
+
+/// Waits until one of `ports` has bytes in its rx queue and resolves with
+/// its index, at once if one has them already. Ports are checked from one
+/// past the last pick, so a busy port does not starve the others even if
+/// the set changes between calls. Take the bytes with `read_available`.
+///
+/// It waits with `readable` on every port, next to their readers and
+/// other selects.
+pub fn select_readable<R: UartRegisters>(ports: &[Arc<AsyncSerial<R>>]) -> SelectReadable<'_, R> {
+    assert!(!ports.is_empty());
+    SelectReadable {
+        start: NEXT_START.load(Relaxed) % ports.len(),
+        readable: ports.iter().map(|port| port.readable()).collect(),
+    }
+}
+
+pub struct SelectReadable<'a, R: UartRegisters = UartMmio> {
+    start: usize,
+    /// One per port, dropped once one is ready.
+    readable: Vec<Readiness<'a, R>>,
+}
+
+impl<R: UartRegisters> Future for SelectReadable<'_, R> {
+    type Output = usize;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let len = this.readable.len();
+        for offset in 0..len {
+            let index = (this.start + offset) % len;
+            if Pin::new(&mut this.readable[index]).poll(cx).is_ready() {
+                // takes our wakers out of the other ports
+                this.readable.clear();
+                NEXT_START.store(index + 1, Relaxed);
+                return Poll::Ready(index);
+            }
+        }
+        Poll::Pending
+    }
+}
@@ -0,0 +1,371 @@
+use super::serial_config::{
+    SERIAL_ADDRESS_STRIDE, SERIAL_BASE_ADDRESS, SERIAL_IRQ_BASE, SERIAL_NUM,
+};
+use super::{AsyncSerial, RegLayout};
+use crate::{dump_serial_regs, enumerate_serial};
+use alloc::sync::Arc;
+use core::fmt::{self, Display, Formatter};
+use core::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use heapless::Vec;
+use spin::Once;
+
+pub const MAX_SERIAL_PORTS: usize = 8;
+
+const CLAIMABLE: usize = 1 << 0;
+const CLAIMED: usize = 1 << 1;
+const ABSENT: usize = 1 << 2;
+
+/// A serial port as reported by the kernel, same layout as its
+/// `SerialPortInfo`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SerialPortInfo {
+    pub index: usize,
+    pub irq: usize,
+    pub phys_base: usize,
+    /// Where the registers are mapped for this process, 0 if it has not
+    /// claimed the port.
+    pub virt_base: usize,
+    pub flags: usize,
+    /// Register spacing and access width in bytes, see `layout`.
+    pub reg_shift: usize,
+    pub reg_width: usize,
+}
+
+impl SerialPortInfo {
+    /// Not the kernel console, so it may be claimed.
+    pub fn is_claimable(&self) -> bool {
+        self.flags & CLAIMABLE != 0
+    }
+
+    pub fn is_claimed(&self) -> bool {
+        self.flags & CLAIMED != 0
+    }
+
+    /// The kernel's probe found a UART at the port's address. Ports that
+    /// are not present are not claimable either.
+    pub fn is_present(&self) -> bool {
+        self.flags & ABSENT == 0
+    }
+
+    pub fn irq(&self) -> u16 {
+        self.irq as u16
+    }
+
+    /// How the registers are laid out, the board's if the kernel reports
+    /// one this library can't access.
+    pub fn layout(&self) -> RegLayout {
+        RegLayout::from_raw(self.reg_shift, self.reg_width).unwrap_or(RegLayout::BOARD)
+    }
+}
+
+/// Every serial port the kernel knows about. Falls back to the board
+/// defaults in `serial_config` if the kernel can't tell.
+pub fn enumerate() -> Vec<SerialPortInfo, MAX_SERIAL_PORTS> {
+    let mut ports = [SerialPortInfo::default(); MAX_SERIAL_PORTS];
+    let port_num = enumerate_serial(&mut ports);
+    if port_num < 0 {
+        return default_ports();
+    }
+    Vec::from_slice(&ports[..(port_num as usize).min(MAX_SERIAL_PORTS)]).unwrap()
+}
+
+fn default_ports() -> Vec<SerialPortInfo, MAX_SERIAL_PORTS> {
+    (0..SERIAL_NUM.min(MAX_SERIAL_PORTS))
+        .map(|index| SerialPortInfo {
+            index,
+            irq: SERIAL_IRQ_BASE as usize + index,
+            phys_base: SERIAL_BASE_ADDRESS + index * SERIAL_ADDRESS_STRIDE,
+            virt_base: 0,
+            // serial 0 is the kernel console
+            flags: if index == 0 { 0 } else { CLAIMABLE },
+            // the board layout
+            reg_shift: 0,
+            reg_width: 0,
+        })
+        .collect()
+}
+
+/// The port layout, enumerated once. Claim state in here goes stale, call
+/// `enumerate` for that.
+fn layout() -> &'static [SerialPortInfo] {
+    static LAYOUT: Once<Vec<SerialPortInfo, MAX_SERIAL_PORTS>> = Once::new();
+    LAYOUT.call_once(enumerate)
+}
+
+pub fn port_info(index: usize) -> Option<SerialPortInfo> {
+    layout().iter().find(|port| port.index == index).copied()
+}
+
+pub fn port_info_by_irq(irq: u16) -> Option<SerialPortInfo> {
+    layout().iter().find(|port| port.irq() == irq).copied()
+}
+
+pub fn port_info_by_base(phys_base: usize) -> Option<SerialPortInfo> {
+    layout()
+        .iter()
+        .find(|port| port.phys_base == phys_base)
+        .copied()
+}
+
+/// `uart<index>` for a port in the layout, else `uart@<base address>`.
+pub(super) struct PortName(pub usize);
+
+impl Display for PortName {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match port_info_by_base(self.0) {
+            Some(port) => write!(f, "uart{}", port.index),
+            None => write!(f, "uart@{:#x}", self.0),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegisterError {
+    /// No such port in the layout, or its index is not below
+    /// `MAX_SERIAL_PORTS`.
+    InvalidPort,
+    /// A driver is registered for the port already.
+    AlreadyRegistered,
+}
+
+struct Registered {
+    irq: u16,
+    serial: Arc<AsyncSerial>,
+}
+
+const UNREGISTERED: Once<Registered> = Once::new();
+/// Each slot is set once and never cleared, so looking a port up takes no
+/// lock and is fine in an interrupt handler.
+static REGISTRY: [Once<Registered>; MAX_SERIAL_PORTS] = [UNREGISTERED; MAX_SERIAL_PORTS];
+
+/// Shares `serial` as the driver of port `index` for the rest of the
+/// program, for `get` and `dispatch`. A port is registered only once.
+pub fn register(index: usize, serial: Arc<AsyncSerial>) -> Result<(), RegisterError> {
+    let irq = port_info(index).ok_or(RegisterError::InvalidPort)?.irq();
+    let slot = REGISTRY.get(index).ok_or(RegisterError::InvalidPort)?;
+    let mut serial = Some(serial);
+    slot.call_once(|| Registered {
+        irq,
+        serial: serial.take().unwrap(),
+    });
+    match serial {
+        Some(_) => Err(RegisterError::AlreadyRegistered),
+        None => Ok(()),
+    }
+}
+
+/// The driver registered for port `index`.
+pub fn get(index: usize) -> Option<Arc<AsyncSerial>> {
+    REGISTRY
+        .get(index)?
+        .get()
+        .map(|registered| registered.serial.clone())
+}
+
+/// Runs the interrupt handler of the registered port with IRQ `irq`, for
+/// `ext_intr_handler`. Returns false if there is none.
+pub fn dispatch(irq: u16) -> bool {
+    match REGISTRY
+        .iter()
+        .filter_map(Once::get)
+        .find(|registered| registered.irq == irq)
+    {
+        Some(registered) => {
+            registered.serial.interrupt_handler();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Revokes the registered port with IRQ `irq`, see `AsyncSerial::revoke`.
+/// It stays registered, closed.
+pub fn revoke(irq: u16) {
+    if let Some(registered) = REGISTRY
+        .iter()
+        .filter_map(Once::get)
+        .find(|registered| registered.irq == irq)
+    {
+        registered.serial.revoke();
+    }
+}
+
+/// How long the interrupts of a port waited from the PLIC claim to the
+/// handler, in cycles. Only for interrupts taken directly, not forwarded
+/// by the kernel.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ServiceLatency {
+    pub count: usize,
+    pub total_cycles: usize,
+    pub max_cycles: usize,
+}
+
+impl ServiceLatency {
+    pub fn mean_cycles(&self) -> usize {
+        self.total_cycles.checked_div(self.count).unwrap_or(0)
+    }
+}
+
+const ZERO: AtomicUsize = AtomicUsize::new(0);
+static LATENCY_COUNT: [AtomicUsize; MAX_SERIAL_PORTS] = [ZERO; MAX_SERIAL_PORTS];
+static LATENCY_TOTAL: [AtomicUsize; MAX_SERIAL_PORTS] = [ZERO; MAX_SERIAL_PORTS];
+static LATENCY_MAX: [AtomicUsize; MAX_SERIAL_PORTS] = [ZERO; MAX_SERIAL_PORTS];
+
+/// For the user trap handler, IRQs of other devices are ignored.
+pub(crate) fn record_service_latency(irq: u16, cycles: usize) {
+    let index = match port_info_by_irq(irq) {
+        Some(port) if port.index < MAX_SERIAL_PORTS => port.index,
+        _ => return,
+    };
+    LATENCY_COUNT[index].fetch_add(1, Relaxed);
+    LATENCY_TOTAL[index].fetch_add(cycles, Relaxed);
+    LATENCY_MAX[index].fetch_max(cycles, Relaxed);
+}
+
+/// Claim to handler latency of port `index` since the last
+/// `reset_service_latency`.
+pub fn service_latency(index: usize) -> ServiceLatency {
+    if index >= MAX_SERIAL_PORTS {
+        return ServiceLatency::default();
+    }
+    ServiceLatency {
+        count: LATENCY_COUNT[index].load(Relaxed),
+        total_cycles: LATENCY_TOTAL[index].load(Relaxed),
+        max_cycles: LATENCY_MAX[index].load(Relaxed),
+    }
+}
+
+pub fn reset_service_latency() {
+    for stats in [&LATENCY_COUNT, &LATENCY_TOTAL, &LATENCY_MAX] {
+        stats.iter().for_each(|stat| stat.store(0, Relaxed));
+    }
+}
+
+const SNAPSHOT_IER_SKIPPED: usize = 1 << 0;
+const SNAPSHOT_LSR_CLEARED: usize = 1 << 1;
+const SNAPSHOT_MSR_CLEARED: usize = 1 << 2;
+const SNAPSHOT_CLAIMED: usize = 1 << 3;
+
+const IER_BITS: &[(usize, &str)] = &[(0, "ERBFI"), (1, "ETBEI"), (2, "ELSI"), (3, "EDSSI")];
+const LCR_BITS: &[(usize, &str)] = &[
+    (2, "STB"),
+    (3, "PEN"),
+    (4, "EPS"),
+    (5, "SP"),
+    (6, "BC"),
+    (7, "DLAB"),
+];
+const MCR_BITS: &[(usize, &str)] = &[
+    (0, "DTR"),
+    (1, "RTS"),
+    (2, "OUT1"),
+    (3, "OUT2"),
+    (4, "LOOP"),
+    (5, "AFE"),
+];
+const LSR_BITS: &[(usize, &str)] = &[
+    (0, "DR"),
+    (1, "OE"),
+    (2, "PE"),
+    (3, "FE"),
+    (4, "BI"),
+    (5, "THRE"),
+    (6, "TEMT"),
+    (7, "RXFE"),
+];
+const MSR_BITS: &[(usize, &str)] = &[
+    (0, "DCTS"),
+    (1, "DDSR"),
+    (2, "TERI"),
+    (3, "DDCD"),
+    (4, "CTS"),
+    (5, "DSR"),
+    (6, "RI"),
+    (7, "DCD"),
+];
+
+/// The registers of a port as `debug_dump` read them, same layout as the
+/// kernel's `UartRegSnapshot`. Displays with the set bits named.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UartRegSnapshot {
+    pub index: usize,
+    pub ier: usize,
+    pub lcr: usize,
+    pub mcr: usize,
+    pub lsr: usize,
+    pub msr: usize,
+    pub flags: usize,
+}
+
+impl UartRegSnapshot {
+    /// LCR.DLAB was set, so `ier` was not read.
+    pub fn ier_skipped(&self) -> bool {
+        self.flags & SNAPSHOT_IER_SKIPPED != 0
+    }
+
+    /// LSR had error bits set, and reading it cleared them.
+    pub fn lsr_cleared(&self) -> bool {
+        self.flags & SNAPSHOT_LSR_CLEARED != 0
+    }
+
+    /// MSR had delta bits set, and reading it cleared them.
+    pub fn msr_cleared(&self) -> bool {
+        self.flags & SNAPSHOT_MSR_CLEARED != 0
+    }
+
+    pub fn is_claimed(&self) -> bool {
+        self.flags & SNAPSHOT_CLAIMED != 0
+    }
+}
+
+fn write_reg(f: &mut Formatter, name: &str, value: usize, bits: &[(usize, &str)]) -> fmt::Result {
+    write!(f, " {} {:#04x} [", name, value)?;
+    let mut first = true;
+    for &(bit, bit_name) in bits {
+        if value & (1 << bit) != 0 {
+            write!(f, "{}{}", if first { "" } else { " " }, bit_name)?;
+            first = false;
+        }
+    }
+    write!(f, "]")
+}
+
+impl Display for UartRegSnapshot {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "serial {}", self.index)?;
+        if self.is_claimed() {
+            write!(f, " (claimed)")?;
+        }
+        if self.ier_skipped() {
+            write!(f, " IER ? [DLAB set]")?;
+        } else {
+            write_reg(f, "IER", self.ier, IER_BITS)?;
+        }
+        write_reg(f, "LCR", self.lcr, LCR_BITS)?;
+        write!(f, " {} data bits,", 5 + (self.lcr & 0b11))?;
+        write_reg(f, "MCR", self.mcr, MCR_BITS)?;
+        write_reg(f, "LSR", self.lsr, LSR_BITS)?;
+        if self.lsr_cleared() {
+            write!(f, " (errors cleared)")?;
+        }
+        write_reg(f, "MSR", self.msr, MSR_BITS)?;
+        if self.msr_cleared() {
+            write!(f, " (deltas cleared)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the registers of `index` through the kernel, whoever holds the
+/// port. Needs the serial debug capability, see `crate::drop_caps`. The
+/// read clears LSR error and MSR delta bits a driver of the port may be
+/// waiting on. Returns the kernel's error code on failure.
+pub fn debug_dump(index: usize) -> Result<UartRegSnapshot, isize> {
+    let mut snapshot = UartRegSnapshot::default();
+    match dump_serial_regs(index, &mut snapshot) {
+        0 => Ok(snapshot),
+        err => Err(err),
+    }
+}
@@ -0,0 +1,130 @@
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed};
+
+/// Asks the peer to resume sending.
+pub const XON: u8 = 0x11;
+/// Asks the peer to stop sending.
+pub const XOFF: u8 = 0x13;
+
+/// XON/XOFF flow control for an `AsyncSerial`, see `set_soft_flow`. XOFF
+/// goes out in the urgent lane, ahead of queued data, once the rx queue
+/// holds `high_water` bytes, XON once a reader has taken it down to
+/// `low_water`. An XOFF from the peer pauses the transmitter until its XON.
+///
+/// XON and XOFF received are taken out of the data, so there is no
+/// transparency: binary data that holds 0x11 or 0x13 is cut up. Such
+/// peers need RTS/CTS, or an encoding that leaves those bytes out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SoftFlow {
+    pub high_water: usize,
+    pub low_water: usize,
+}
+
+/// XON and XOFF counters of a port, see `SerialStats::soft_flow`. The
+/// sent ones count what left the urgent lane.
+#[derive(Clone, Copy, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SoftFlowStats {
+    pub xoff_sent: usize,
+    pub xon_sent: usize,
+    pub xoff_received: usize,
+    pub xon_received: usize,
+}
+
+pub(super) struct SoftFlowState {
+    enabled: AtomicBool,
+    high_water: AtomicUsize,
+    low_water: AtomicUsize,
+    /// The peer sent XOFF, no data goes out.
+    paused: AtomicBool,
+    /// We sent XOFF, or are about to, and owe the peer an XON.
+    stopped: AtomicBool,
+    xoff_received: AtomicUsize,
+    xon_received: AtomicUsize,
+}
+
+impl SoftFlowState {
+    pub(super) const fn new() -> Self {
+        SoftFlowState {
+            enabled: AtomicBool::new(false),
+            high_water: AtomicUsize::new(0),
+            low_water: AtomicUsize::new(0),
+            paused: AtomicBool::new(false),
+            stopped: AtomicBool::new(false),
+            xoff_received: AtomicUsize::new(0),
+            xon_received: AtomicUsize::new(0),
+        }
+    }
+
+    /// Starts over with neither side paused. Returns true if the peer was
+    /// stopped and is owed an XON.
+    pub(super) fn set(&self, flow: Option<SoftFlow>) -> bool {
+        if let Some(flow) = flow {
+            self.high_water.store(flow.high_water, Relaxed);
+            self.low_water.store(flow.low_water, Relaxed);
+        }
+        self.paused.store(false, Relaxed);
+        self.enabled.store(flow.is_some(), Relaxed);
+        self.stopped.swap(false, Relaxed)
+    }
+
+    pub(super) fn get(&self) -> Option<SoftFlow> {
+        self.enabled.load(Relaxed).then(|| SoftFlow {
+            high_water: self.high_water.load(Relaxed),
+            low_water: self.low_water.load(Relaxed),
+        })
+    }
+
+    pub(super) fn paused(&self) -> bool {
+        self.paused.load(Relaxed)
+    }
+
+    /// Takes a received XON or XOFF out of the data. Returns true for
+    /// those, and an XON wants the transmitter started again.
+    pub(super) fn filter(&self, ch: u8) -> bool {
+        if !self.enabled.load(Relaxed) {
+            return false;
+        }
+        match ch {
+            XOFF => {
+                self.xoff_received.fetch_add(1, Relaxed);
+                self.paused.store(true, Relaxed);
+            }
+            XON => {
+                self.xon_received.fetch_add(1, Relaxed);
+                self.paused.store(false, Relaxed);
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// The rx queue holds `len` bytes after the rx path filled it. Returns
+    /// true if XOFF is to be sent.
+    pub(super) fn filled(&self, len: usize) -> bool {
+        self.enabled.load(Relaxed)
+            && len >= self.high_water.load(Relaxed)
+            && !self.stopped.swap(true, Relaxed)
+    }
+
+    /// The rx queue holds `len` bytes after a reader took some. Returns
+    /// true if XON is to be sent.
+    pub(super) fn drained(&self, len: usize) -> bool {
+        self.stopped.load(Relaxed)
+            && len <= self.low_water.load(Relaxed)
+            && self.stopped.swap(false, Relaxed)
+    }
+
+    /// The received counters, the sent ones are kept by the urgent lane.
+    pub(super) fn stats(&self) -> SoftFlowStats {
+        SoftFlowStats {
+            xoff_received: self.xoff_received.load(Relaxed),
+            xon_received: self.xon_received.load(Relaxed),
+            ..SoftFlowStats::default()
+        }
+    }
+
+    pub(super) fn reset_stats(&self) {
+        self.xoff_received.store(0, Relaxed);
+        self.xon_received.store(0, Relaxed);
+    }
+}
@@ -0,0 +1,101 @@
+use super::regs::UartRegisters;
+use super::*;
+
+/// The receiving half of an `AsyncSerial`, see `AsyncSerial::split`.
+pub struct SerialReader<R: UartRegisters = UartMmio> {
+    serial: Arc<AsyncSerial<R>>,
+}
+
+/// The sending half of an `AsyncSerial`, see `AsyncSerial::split`.
+pub struct SerialWriter<R: UartRegisters = UartMmio> {
+    serial: Arc<AsyncSerial<R>>,
+}
+
+impl<R: UartRegisters> SerialReader<R> {
+    pub(super) fn new(serial: Arc<AsyncSerial<R>>) -> Self {
+        SerialReader { serial }
+    }
+
+    /// Fills `buf`, see `AsyncSerial::read`.
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<(), SerialError> {
+        self.serial.clone().read(buf).await
+    }
+
+    /// Waits for at least one byte, then takes what has arrived, up to
+    /// `buf.len()`.
+    pub fn read_some<'a>(&'a mut self, buf: &'a mut [u8]) -> ReadSome<'a, R> {
+        ReadSome {
+            serial: &self.serial,
+            buf,
+            waiter: 0,
+        }
+    }
+
+    pub fn read_available(&mut self, buf: &mut [u8]) -> usize {
+        self.serial.read_available(buf)
+    }
+
+    pub fn stats(&self) -> SerialStats {
+        self.serial.stats()
+    }
+}
+
+impl<R: UartRegisters> AsyncRead for SerialReader<R> {
+    fn poll_read(&self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<usize> {
+        self.serial.poll_read(cx, buf)
+    }
+}
+
+impl<R: UartRegisters> SerialWriter<R> {
+    pub(super) fn new(serial: Arc<AsyncSerial<R>>) -> Self {
+        SerialWriter { serial }
+    }
+
+    /// Queues all of `buf`, see `AsyncSerial::write`.
+    pub async fn write(&mut self, buf: &[u8]) -> Result<(), SerialError> {
+        self.serial.clone().write(buf).await
+    }
+
+    pub fn write_available(&mut self, buf: &[u8]) -> usize {
+        self.serial.write_available(buf)
+    }
+
+    pub fn space(&self) -> usize {
+        self.serial.tx_space()
+    }
+
+    /// See `AsyncSerial::flush`.
+    pub fn flush(&mut self, timeout_us: usize) -> bool {
+        self.serial.flush(timeout_us)
+    }
+
+    pub fn stats(&self) -> SerialStats {
+        self.serial.stats()
+    }
+}
+
+/// Resolves to the number of bytes read, at least one unless `buf` is
+/// empty. Dropping it while it waits unregisters its read waker, other
+/// readers of the port keep theirs.
+pub struct ReadSome<'a, R: UartRegisters> {
+    serial: &'a Arc<AsyncSerial<R>>,
+    buf: &'a mut [u8],
+    /// Key of our entry in the read waiters, 0 if none.
+    waiter: usize,
+}
+
+impl<R: UartRegisters> Future for ReadSome<'_, R> {
+    type Output = usize;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        this.serial
+            .poll_read_as(cx, this.buf, Some(&mut this.waiter))
+    }
+}
+
+impl<R: UartRegisters> Drop for ReadSome<'_, R> {
+    fn drop(&mut self) {
+        self.serial.remove_reader(&mut self.waiter);
+    }
+}
@@ -0,0 +1,132 @@
+use super::{AsyncSerial, SerialStats};
+use crate::executor::{Executor, JoinHandle};
+use crate::timer::{now_us, Sleep};
+use alloc::sync::Arc;
+use core::fmt;
+
+/// What a port did over one interval of a `StatsReporter`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StatsRates {
+    pub elapsed_us: usize,
+    /// Bytes per second.
+    pub rx_rate: usize,
+    pub tx_rate: usize,
+    /// Share of what the line carries at the baud rate, in percent. 0 if
+    /// the baud rate is not known.
+    pub rx_utilization: usize,
+    pub tx_utilization: usize,
+    /// Interrupts per second.
+    pub intr_rate: usize,
+    /// Overruns in the interval.
+    pub overruns: usize,
+}
+
+impl StatsRates {
+    /// The counters may wrap in between, but not go back: an interval a
+    /// `reset_stats` falls into comes out wrong.
+    pub fn between(
+        prev: &SerialStats,
+        now: &SerialStats,
+        elapsed_us: usize,
+        baud_rate: usize,
+    ) -> Self {
+        let per_sec = |prev: usize, now: usize| {
+            (now.wrapping_sub(prev) * 1_000_000)
+                .checked_div(elapsed_us)
+                .unwrap_or(0)
+        };
+        // 10 bits a byte at 8N1
+        let utilization = |rate: usize| (rate * 10 * 100).checked_div(baud_rate).unwrap_or(0);
+        let rx_rate = per_sec(prev.rx_count, now.rx_count);
+        let tx_rate = per_sec(prev.tx_count, now.tx_count);
+        StatsRates {
+            elapsed_us,
+            rx_rate,
+            tx_rate,
+            rx_utilization: utilization(rx_rate),
+            tx_utilization: utilization(tx_rate),
+            intr_rate: per_sec(prev.intr_count, now.intr_count),
+            overruns: now.overrun_count.wrapping_sub(prev.overrun_count),
+        }
+    }
+}
+
+/// Writes the rates of an `AsyncSerial` to `sink` once per interval, one
+/// line each, e.g. to `console::print` or a `SerialTerminal` of another
+/// port. `spawn` runs it as a task, cancelled through its `JoinHandle`.
+pub struct StatsReporter<F> {
+    serial: Arc<AsyncSerial>,
+    interval_us: usize,
+    sink: F,
+    prev: SerialStats,
+    prev_us: usize,
+}
+
+impl<F: FnMut(fmt::Arguments) + Send + 'static> StatsReporter<F> {
+    /// The first interval starts now.
+    pub fn new(serial: Arc<AsyncSerial>, interval_us: usize, sink: F) -> Self {
+        assert!(interval_us > 0);
+        StatsReporter {
+            prev: serial.stats(),
+            prev_us: now_us(),
+            serial,
+            interval_us,
+            sink,
+        }
+    }
+
+    pub fn spawn(
+        exec: &Executor,
+        serial: Arc<AsyncSerial>,
+        interval_us: usize,
+        sink: F,
+    ) -> JoinHandle {
+        exec.spawn(Self::new(serial, interval_us, sink).run())
+    }
+
+    /// Rates since the last sample, or `new`, and starts the next interval.
+    pub fn sample(&mut self) -> StatsRates {
+        let now = self.serial.stats();
+        let now_us = now_us();
+        let rates = StatsRates::between(
+            &self.prev,
+            &now,
+            now_us - self.prev_us,
+            self.serial.baud_rate(),
+        );
+        self.prev = now;
+        self.prev_us = now_us;
+        rates
+    }
+
+    /// Samples and writes the line.
+    pub fn report(&mut self) -> StatsRates {
+        let rates = self.sample();
+        (self.sink)(format_args!(
+            "[serial {}] rx {} B/s ({}%) tx {} B/s ({}%) intr {}/s overruns {}\r\n",
+            self.serial.port(),
+            rates.rx_rate,
+            rates.rx_utilization,
+            rates.tx_rate,
+            rates.tx_utilization,
+            rates.intr_rate,
+            rates.overruns
+        ));
+        rates
+    }
+
+    /// Reports every interval until dropped. A late wakeup shortens the
+    /// next interval rather than shifting all that follow, unless it was
+    /// a whole interval late.
+    pub async fn run(mut self) {
+        let mut deadline = self.prev_us + self.interval_us;
+        loop {
+            Sleep::until(deadline).await;
+            self.report();
+            deadline += self.interval_us;
+            if deadline <= self.prev_us {
+                deadline = self.prev_us + self.interval_us;
+            }
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use super::*;
+use crate::syscall::sys_write;
+use crate::uintr::{critical_section, is_ext_enabled};
+use crate::yield_;
+use heapless::Vec;
+
+/// Longest line buffered in either direction in `StdioMode::Line`.
+pub const STDIO_LINE_SIZE: usize = 256;
+
+pub(super) const BACKSPACE: u8 = 0x08;
+pub(super) const DELETE: u8 = 0x7f;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StdioMode {
+    /// Output is sent once a line ends, the buffer fills or input is read.
+    /// Input is echoed and handed out a line at a time, ended by `\n`, with
+    /// backspace handled.
+    Line,
+    /// Bytes go through both ways as they come, with no echo.
+    Raw,
+}
+
+struct Stdio {
+    serial: Arc<AsyncSerial>,
+    irq: u16,
+    mode: StdioMode,
+    out_line: Vec<u8, STDIO_LINE_SIZE>,
+    in_line: Vec<u8, STDIO_LINE_SIZE>,
+    /// `in_line[..in_ready]` is a finished line not read out yet.
+    in_ready: usize,
+    /// Skip the `\n` of a `\r\n`.
+    last_cr: bool,
+}
+
+/// Only locked with user interrupts masked: printing from an interrupt
+/// handler takes it too.
+static STDIO: Mutex<Option<Stdio>> = Mutex::new(None);
+static STDIO_DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+impl Stdio {
+    /// Returns how many bytes did not fit in the tx queue.
+    fn write(&mut self, buf: &[u8]) -> usize {
+        if self.mode == StdioMode::Raw {
+            return buf.len() - self.serial.write_available(buf);
+        }
+        let mut dropped = 0;
+        for &ch in buf {
+            if self.out_line.is_full() {
+                dropped += self.flush();
+            }
+            let _ = self.out_line.push(ch);
+            if ch == b'\n' {
+                dropped += self.flush();
+            }
+        }
+        dropped
+    }
+
+    fn flush(&mut self) -> usize {
+        let len = self.serial.write_available(&self.out_line);
+        let dropped = self.out_line.len() - len;
+        self.out_line.clear();
+        dropped
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> usize {
+        if self.mode == StdioMode::Raw {
+            return self.serial.read_available(buf);
+        }
+        // a prompt without a newline has to show before the input
+        if !self.out_line.is_empty() {
+            STDIO_DROPPED.fetch_add(self.flush(), Relaxed);
+        }
+        if self.in_ready == 0 {
+            self.edit_line();
+        }
+        let len = buf.len().min(self.in_ready);
+        buf[..len].copy_from_slice(&self.in_line[..len]);
+        self.in_line.copy_within(len.., 0);
+        self.in_line.truncate(self.in_line.len() - len);
+        self.in_ready -= len;
+        len
+    }
+
+    /// Takes the received bytes into the line being edited until it ends.
+    fn edit_line(&mut self) {
+        let mut ch = [0u8];
+        while self.in_ready == 0 && self.serial.read_available(&mut ch) == 1 {
+            let last_cr = core::mem::replace(&mut self.last_cr, ch[0] == b'\r');
+            match ch[0] {
+                b'\n' if last_cr => {}
+                b'\r' | b'\n' => {
+                    if self.in_line.is_full() {
+                        self.in_line.pop();
+                    }
+                    let _ = self.in_line.push(b'\n');
+                    self.serial.write_available(b"\r\n");
+                    self.in_ready = self.in_line.len();
+                }
+                BACKSPACE | DELETE => {
+                    if self.in_line.pop().is_some() {
+                        self.serial.write_available(b"\x08 \x08");
+                    }
+                }
+                ch => {
+                    let _ = self.in_line.push(ch);
+                    self.serial.write_available(&[ch]);
+                    if self.in_line.is_full() {
+                        self.in_ready = self.in_line.len();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Makes fd 0, 1 and 2 of `read` and `write`, and so `print!` and
+/// `getchar`, go over `serial` instead of the kernel console. `serial` must
+/// be initialized and have its rx interrupt enabled. The default
+/// `ext_intr_handler` serves its interrupts, a program with its own handler
+/// must call `stdio_interrupt` from it.
+///
+/// Output never waits: what does not fit in the tx queue is dropped and
+/// counted by `stdio_dropped`, so printing from an interrupt handler is
+/// safe. Reads wait for input, with `yield_` between polls.
+///
+/// Dropping or transferring the claim of the port switches back to the
+/// kernel console.
+pub fn redirect_stdio(serial: Arc<AsyncSerial>, mode: StdioMode) {
+    let irq = serial::port_info_by_base(serial.base_address()).map_or(0, |port| port.irq());
+    let old = critical_section(|| {
+        STDIO.lock().replace(Stdio {
+            serial,
+            irq,
+            mode,
+            out_line: Vec::new(),
+            in_line: Vec::new(),
+            in_ready: 0,
+            last_cr: false,
+        })
+    });
+    drop(old);
+}
+
+/// Switches back to the kernel console, sending the buffered output first.
+/// Returns the port that was used.
+pub fn restore_stdio() -> Option<Arc<AsyncSerial>> {
+    let mut stdio = critical_section(|| STDIO.lock().take())?;
+    STDIO_DROPPED.fetch_add(stdio.flush(), Relaxed);
+    Some(stdio.serial)
+}
+
+/// Output bytes dropped because the tx queue was full.
+pub fn stdio_dropped() -> usize {
+    STDIO_DROPPED.load(Relaxed)
+}
+
+/// Runs the interrupt handler of the stdio port if `irq` is its IRQ.
+/// Returns false otherwise.
+pub fn stdio_interrupt(irq: u16) -> bool {
+    let serial = critical_section(|| {
+        STDIO
+            .lock()
+            .as_ref()
+            .filter(|stdio| stdio.irq == irq)
+            .map(|stdio| stdio.serial.clone())
+    });
+    match serial {
+        Some(serial) => {
+            serial.interrupt_handler();
+            true
+        }
+        None => false,
+    }
+}
+
+/// `None` if stdio is not redirected.
+pub(crate) fn stdio_write(buf: &[u8]) -> Option<isize> {
+    let dropped = critical_section(|| STDIO.lock().as_mut().map(|stdio| stdio.write(buf)))?;
+    STDIO_DROPPED.fetch_add(dropped, Relaxed);
+    Some(buf.len() as isize)
+}
+
+/// `None` if stdio is not redirected, also if the port is lost while
+/// waiting.
+pub(crate) fn stdio_read(buf: &mut [u8]) -> Option<isize> {
+    loop {
+        let (len, serial) = critical_section(|| {
+            let mut stdio = STDIO.lock();
+            let stdio = stdio.as_mut()?;
+            Some((stdio.read(buf), stdio.serial.clone()))
+        })?;
+        if len > 0 || buf.is_empty() {
+            return Some(len as isize);
+        }
+        if is_ext_enabled() {
+            yield_();
+        } else {
+            // no handler can run on this hart, serve the port from here
+            serial.pump();
+        }
+    }
+}
+
+/// Called before the port at `base_address` is unmapped. The line still
+/// buffered goes to the kernel console. Returns the driver stdio used.
+pub(super) fn stdio_port_released(base_address: usize) -> Option<Arc<AsyncSerial>> {
+    let stdio = critical_section(|| {
+        let mut stdio = STDIO.lock();
+        match stdio.as_ref() {
+            Some(used) if used.serial.base_address() == base_address => stdio.take(),
+            _ => None,
+        }
+    })?;
+    sys_write(1, &stdio.out_line);
+    Some(stdio.serial)
+}
@@ -0,0 +1,98 @@
+use super::stdio::{BACKSPACE, DELETE};
+use super::SerialDriver;
+use core::fmt;
+use heapless::Vec;
+
+/// A line-editing console on any `SerialDriver`, served by polling: call
+/// `poll_line` from the main loop. Typed bytes are echoed and backspace
+/// is handled, the same as `StdioMode::Line`.
+pub struct SerialTerminal<D, const N: usize> {
+    driver: D,
+    line: Vec<u8, N>,
+    /// Skip the `\n` of a `\r\n`.
+    last_cr: bool,
+}
+
+impl<D: SerialDriver, const N: usize> SerialTerminal<D, N> {
+    pub fn new(driver: D) -> Self {
+        SerialTerminal {
+            driver,
+            line: Vec::new(),
+            last_cr: false,
+        }
+    }
+
+    pub fn driver(&self) -> &D {
+        &self.driver
+    }
+
+    pub fn driver_mut(&mut self) -> &mut D {
+        &mut self.driver
+    }
+
+    pub fn into_driver(self) -> D {
+        self.driver
+    }
+
+    /// Sends all of `data`, pumping the driver while it has no room.
+    pub fn write(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let len = self.driver.write_nonblocking(data);
+            data = &data[len..];
+            if len == 0 {
+                self.driver.service();
+            }
+        }
+    }
+
+    /// Takes what was received into the line being edited. Returns the
+    /// line, without its terminator, once `\r` or `\n` ends it or it fills
+    /// up.
+    pub fn poll_line(&mut self) -> Option<Vec<u8, N>> {
+        self.driver.service();
+        let mut ch = [0u8];
+        while self.driver.read_nonblocking(&mut ch) == 1 {
+            let last_cr = core::mem::replace(&mut self.last_cr, ch[0] == b'\r');
+            match ch[0] {
+                b'\n' if last_cr => {}
+                b'\r' | b'\n' => {
+                    self.write(b"\r\n");
+                    return Some(core::mem::take(&mut self.line));
+                }
+                BACKSPACE | DELETE => {
+                    if self.line.pop().is_some() {
+                        self.write(b"\x08 \x08");
+                    }
+                }
+                ch => {
+                    let _ = self.line.push(ch);
+                    self.write(&[ch]);
+                    if self.line.is_full() {
+                        return Some(core::mem::take(&mut self.line));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Pumps the driver until everything written has gone out.
+    pub fn flush(&mut self) {
+        while !self.driver.flush_nonblocking() {
+            self.driver.service();
+        }
+    }
+}
+
+/// `\n` goes out as `\r\n`.
+impl<D: SerialDriver, const N: usize> fmt::Write for SerialTerminal<D, N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for (i, part) in s.split('\n').enumerate() {
+            if i > 0 {
+                self.write(b"\r\n");
+            }
+            self.write(part.as_bytes());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,99 @@
+use super::{AsyncSerial, SerialError};
+use crate::timer::{now_us, Sleep};
+use alloc::sync::Arc;
+use spin::Mutex;
+
+/// Paces writes to an `AsyncSerial` with a token bucket, for devices that
+/// cannot take bytes at the full baud rate. Every `tick_us` the bucket gains
+/// `bytes_per_tick` tokens, up to `capacity`. A write larger than the bucket
+/// is sent in chunks as tokens come in.
+pub struct Throttle {
+    serial: Arc<AsyncSerial>,
+    bytes_per_tick: usize,
+    tick_us: usize,
+    capacity: usize,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    tokens: usize,
+    last_refill: usize,
+}
+
+impl Throttle {
+    /// The bucket starts full.
+    pub fn new(
+        serial: Arc<AsyncSerial>,
+        bytes_per_tick: usize,
+        tick_us: usize,
+        capacity: usize,
+    ) -> Self {
+        assert!(bytes_per_tick > 0 && tick_us > 0 && capacity > 0);
+        Throttle {
+            serial,
+            bytes_per_tick,
+            tick_us,
+            capacity,
+            bucket: Mutex::new(Bucket {
+                tokens: capacity,
+                last_refill: now_us(),
+            }),
+        }
+    }
+
+    /// Tokens currently in the bucket.
+    pub fn level(&self) -> usize {
+        let mut bucket = self.bucket.lock();
+        self.refill(&mut bucket);
+        bucket.tokens
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn serial(&self) -> &Arc<AsyncSerial> {
+        &self.serial
+    }
+
+    /// Fails only once the port is closed, `done` counting from the start
+    /// of `buf`.
+    pub async fn write(&self, buf: &[u8]) -> Result<(), SerialError> {
+        let mut written = 0;
+        while written < buf.len() {
+            let (len, next_tick) = {
+                let mut bucket = self.bucket.lock();
+                self.refill(&mut bucket);
+                let len = bucket.tokens.min(buf.len() - written);
+                bucket.tokens -= len;
+                (len, bucket.last_refill + self.tick_us)
+            };
+            if len == 0 {
+                Sleep::until(next_tick).await;
+                continue;
+            }
+            self.serial
+                .clone()
+                .write(&buf[written..written + len])
+                .await
+                .map_err(|err| match err {
+                    SerialError::Closed { done } => SerialError::Closed {
+                        done: written + done,
+                    },
+                    err => err,
+                })?;
+            written += len;
+        }
+        Ok(())
+    }
+
+    fn refill(&self, bucket: &mut Bucket) {
+        let ticks = now_us().saturating_sub(bucket.last_refill) / self.tick_us;
+        if ticks > 0 {
+            bucket.tokens = self
+                .capacity
+                .min(bucket.tokens + ticks * self.bytes_per_tick);
+            bucket.last_refill += ticks * self.tick_us;
+        }
+    }
+}
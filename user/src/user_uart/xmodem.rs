@@ -0,0 +1,331 @@
+use super::regs::UartRegisters;
+use super::*;
+use crate::sync::CancellationToken;
+use crate::timer::timeout;
+use core::convert::TryInto;
+
+// XMODEM-CRC with 1K blocks. Block 0 carries the image length and its
+// CRC-16 so the receiver can drop the padding of the last block and check
+// the whole image, the data follows from block 1 on.
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+/// Sent by the receiver to ask for CRC mode.
+const CRC_MODE: u8 = b'C';
+/// Fills the rest of the last block.
+const PAD: u8 = 0x1a;
+
+pub const SMALL_BLOCK: usize = 128;
+pub const LARGE_BLOCK: usize = 1024;
+/// Times a block, EOT or the start request is sent before giving up.
+pub const MAX_RETRIES: usize = 10;
+/// How long the receiver waits for the first block after each `C`.
+const START_TIMEOUT_US: usize = 3_000_000;
+/// How long the sender waits for `C` at the start and for the answer to
+/// a block.
+const ANSWER_TIMEOUT_US: usize = 10_000_000;
+/// Gap allowed within a block and between blocks.
+const BLOCK_TIMEOUT_US: usize = 1_000_000;
+/// The line has to stay quiet this long before a NAK, so the NAK does not
+/// cross the rest of a bad block.
+const PURGE_QUIET_US: usize = 100_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum XmodemError {
+    /// No answer after `MAX_RETRIES` tries.
+    TooManyRetries,
+    /// The token passed in was cancelled.
+    Cancelled,
+    /// The other side sent CAN CAN.
+    RemoteCancelled,
+    /// The port was closed under the transfer, see `AsyncSerial::close`.
+    Closed,
+    /// The receiver's buffer cannot hold the image.
+    TooLarge { len: usize },
+    /// A block number that is neither the next one nor a repeat.
+    Sequence { expected: u8, received: u8 },
+    /// The blocks end before the length block 0 announced.
+    Length { expected: usize, received: usize },
+    /// The image CRC does not match the one in block 0.
+    Checksum { expected: u16, actual: u16 },
+}
+
+/// CRC-16/XMODEM, polynomial 0x1021, initial value 0.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Sends `data` to a receiver in `LARGE_BLOCK` or `SMALL_BLOCK` blocks.
+/// Returns when the receiver acknowledged the end and the tx queue is
+/// flushed. On an error or when `token` is cancelled, the receiver is
+/// sent CAN CAN.
+pub async fn send<R: UartRegisters>(
+    serial: &Arc<AsyncSerial<R>>,
+    data: &[u8],
+    block_size: usize,
+    token: &CancellationToken,
+) -> Result<(), XmodemError> {
+    assert!(block_size == SMALL_BLOCK || block_size == LARGE_BLOCK);
+    let result = token
+        .run_until_cancelled(send_inner(serial, data, block_size))
+        .await
+        .unwrap_or(Err(XmodemError::Cancelled));
+    finish(serial, result).await
+}
+
+/// Receives an image into `buf` and returns its length. The length and
+/// CRC are checked against block 0 once the sender ends. On an error or
+/// when `token` is cancelled, the sender is sent CAN CAN.
+pub async fn receive<R: UartRegisters>(
+    serial: &Arc<AsyncSerial<R>>,
+    buf: &mut [u8],
+    token: &CancellationToken,
+) -> Result<usize, XmodemError> {
+    let result = token
+        .run_until_cancelled(receive_inner(serial, buf))
+        .await
+        .unwrap_or(Err(XmodemError::Cancelled));
+    finish(serial, result).await
+}
+
+async fn finish<R: UartRegisters, T>(
+    serial: &Arc<AsyncSerial<R>>,
+    result: Result<T, XmodemError>,
+) -> Result<T, XmodemError> {
+    match result {
+        Ok(_) | Err(XmodemError::RemoteCancelled) | Err(XmodemError::Closed) => {}
+        Err(_) => {
+            // the future that was cancelled may have been mid-block,
+            // CAN CAN ends the block as well as the transfer
+            let _ = timeout(BLOCK_TIMEOUT_US, serial.clone().write(&[CAN; 2])).await;
+        }
+    }
+    serial.flush(EXIT_DRAIN_TIMEOUT_US);
+    result
+}
+
+/// `None` on a timeout.
+async fn read_byte<R: UartRegisters>(
+    serial: &Arc<AsyncSerial<R>>,
+    timeout_us: usize,
+) -> Result<Option<u8>, XmodemError> {
+    let mut byte = [0u8];
+    match timeout(timeout_us, serial.clone().read(&mut byte)).await {
+        Ok(Ok(())) => Ok(Some(byte[0])),
+        Ok(Err(_)) => Err(XmodemError::Closed),
+        Err(_) => Ok(None),
+    }
+}
+
+async fn write_all<R: UartRegisters>(
+    serial: &Arc<AsyncSerial<R>>,
+    buf: &[u8],
+) -> Result<(), XmodemError> {
+    serial
+        .clone()
+        .write(buf)
+        .await
+        .map_err(|_| XmodemError::Closed)
+}
+
+/// Drops what arrives until the line is quiet.
+async fn purge<R: UartRegisters>(serial: &Arc<AsyncSerial<R>>) -> Result<(), XmodemError> {
+    let mut buf = [0u8; 64];
+    while read_byte(serial, PURGE_QUIET_US).await?.is_some() {
+        while serial.read_available(&mut buf) > 0 {}
+    }
+    Ok(())
+}
+
+/// Checks the second byte of a CAN CAN.
+async fn remote_cancel<R: UartRegisters>(serial: &Arc<AsyncSerial<R>>) -> bool {
+    read_byte(serial, BLOCK_TIMEOUT_US).await == Ok(Some(CAN))
+}
+
+/// Waits for ACK, NAK or CAN CAN. Anything else, like a `C` sent before
+/// the receiver saw the block, counts as NAK.
+async fn wait_answer<R: UartRegisters>(serial: &Arc<AsyncSerial<R>>) -> Result<bool, XmodemError> {
+    match read_byte(serial, ANSWER_TIMEOUT_US).await? {
+        Some(ACK) => Ok(true),
+        Some(CAN) if remote_cancel(serial).await => Err(XmodemError::RemoteCancelled),
+        _ => Ok(false),
+    }
+}
+
+async fn send_block<R: UartRegisters>(
+    serial: &Arc<AsyncSerial<R>>,
+    number: u8,
+    payload: &[u8],
+    block_size: usize,
+) -> Result<(), XmodemError> {
+    let mut frame = [PAD; 3 + LARGE_BLOCK + 2];
+    let frame = &mut frame[..3 + block_size + 2];
+    frame[0] = if block_size == LARGE_BLOCK { STX } else { SOH };
+    frame[1] = number;
+    frame[2] = !number;
+    frame[3..3 + payload.len()].copy_from_slice(payload);
+    let crc = crc16(&frame[3..3 + block_size]);
+    frame[3 + block_size..].copy_from_slice(&crc.to_be_bytes());
+    for _ in 0..MAX_RETRIES {
+        write_all(serial, frame).await?;
+        if wait_answer(serial).await? {
+            return Ok(());
+        }
+    }
+    Err(XmodemError::TooManyRetries)
+}
+
+async fn send_inner<R: UartRegisters>(
+    serial: &Arc<AsyncSerial<R>>,
+    data: &[u8],
+    block_size: usize,
+) -> Result<(), XmodemError> {
+    let mut started = false;
+    for _ in 0..MAX_RETRIES {
+        match read_byte(serial, ANSWER_TIMEOUT_US).await? {
+            Some(CRC_MODE) => {
+                started = true;
+                break;
+            }
+            Some(CAN) if remote_cancel(serial).await => return Err(XmodemError::RemoteCancelled),
+            _ => {}
+        }
+    }
+    if !started {
+        return Err(XmodemError::TooManyRetries);
+    }
+
+    let mut header = [0u8; 6];
+    header[..4].copy_from_slice(&(data.len() as u32).to_le_bytes());
+    header[4..].copy_from_slice(&crc16(data).to_le_bytes());
+    send_block(serial, 0, &header, SMALL_BLOCK).await?;
+    for (i, payload) in data.chunks(block_size).enumerate() {
+        send_block(serial, (i + 1) as u8, payload, block_size).await?;
+    }
+    for _ in 0..MAX_RETRIES {
+        write_all(serial, &[EOT]).await?;
+        if wait_answer(serial).await? {
+            return Ok(());
+        }
+    }
+    Err(XmodemError::TooManyRetries)
+}
+
+async fn receive_inner<R: UartRegisters>(
+    serial: &Arc<AsyncSerial<R>>,
+    buf: &mut [u8],
+) -> Result<usize, XmodemError> {
+    // the first block answers one of the `C`s
+    let mut first = None;
+    for _ in 0..MAX_RETRIES {
+        write_all(serial, &[CRC_MODE]).await?;
+        first = read_byte(serial, START_TIMEOUT_US).await?;
+        if first.is_some() {
+            break;
+        }
+    }
+    if first.is_none() {
+        return Err(XmodemError::TooManyRetries);
+    }
+    let mut header = first;
+
+    let mut frame = [0u8; 2 + LARGE_BLOCK + 2];
+    let mut expected = 0u8;
+    let mut image: Option<(usize, u16)> = None;
+    let mut received = 0;
+    let mut errors = 0;
+    loop {
+        let block_size = match header {
+            Some(SOH) => SMALL_BLOCK,
+            Some(STX) => LARGE_BLOCK,
+            Some(EOT) if image.is_some() => {
+                write_all(serial, &[ACK]).await?;
+                break;
+            }
+            Some(CAN) if remote_cancel(serial).await => return Err(XmodemError::RemoteCancelled),
+            // a timeout or noise
+            _ => 0,
+        };
+        let frame = &mut frame[..2 + block_size + 2];
+        let read = block_size > 0
+            && match timeout(BLOCK_TIMEOUT_US, serial.clone().read(frame)).await {
+                Ok(Ok(())) => true,
+                Ok(Err(_)) => return Err(XmodemError::Closed),
+                Err(_) => false,
+            };
+        let good = read
+            && frame[0] == !frame[1]
+            && crc16(&frame[2..2 + block_size])
+                == u16::from_be_bytes(frame[2 + block_size..].try_into().unwrap());
+        if !good {
+            errors += 1;
+            if errors >= MAX_RETRIES {
+                return Err(XmodemError::TooManyRetries);
+            }
+            purge(serial).await?;
+            // until block 0 is in, the sender only listens for `C`
+            let retry = if image.is_some() { NAK } else { CRC_MODE };
+            write_all(serial, &[retry]).await?;
+        } else if frame[0] == expected.wrapping_sub(1) && image.is_some() {
+            // our ACK got lost, the sender repeats the block
+            write_all(serial, &[ACK]).await?;
+        } else if frame[0] != expected {
+            return Err(XmodemError::Sequence {
+                expected,
+                received: frame[0],
+            });
+        } else {
+            let data = &frame[2..2 + block_size];
+            match image {
+                None => {
+                    let len = u32::from_le_bytes(data[..4].try_into().unwrap()) as usize;
+                    if len > buf.len() {
+                        return Err(XmodemError::TooLarge { len });
+                    }
+                    image = Some((len, u16::from_le_bytes(data[4..6].try_into().unwrap())));
+                }
+                Some((len, _)) => {
+                    // the padding of the last block goes nowhere
+                    let take = block_size.min(len.saturating_sub(received));
+                    buf[received..received + take].copy_from_slice(&data[..take]);
+                    received += block_size;
+                }
+            }
+            errors = 0;
+            expected = expected.wrapping_add(1);
+            write_all(serial, &[ACK]).await?;
+        }
+        header = read_byte(serial, ANSWER_TIMEOUT_US).await?;
+    }
+
+    let (len, crc) = image.unwrap();
+    if received < len {
+        return Err(XmodemError::Length {
+            expected: len,
+            received,
+        });
+    }
+    let actual = crc16(&buf[..len]);
+    if actual != crc {
+        return Err(XmodemError::Checksum {
+            expected: crc,
+            actual,
+        });
+    }
+    Ok(len)
+}